@@ -1,6 +1,10 @@
-use bitcoin::{consensus::Decodable, Block};
-use criterion::{black_box, criterion_group, criterion_main, Criterion};
+use bitcoin::opcodes::OP_TRUE;
+use bitcoin::script::Builder;
+use bitcoin::{consensus::Decodable, Block, Transaction};
+use criterion::{black_box, criterion_group, criterion_main, BatchSize, Criterion, Throughput};
+use electrs::config::Config;
 use electrs::new_index::schema::bench::*;
+use electrs::rest::bench::txs_json;
 
 fn criterion_benchmark(c: &mut Criterion) {
     c.bench_function("add_blocks", |b| {
@@ -11,6 +15,44 @@ fn criterion_benchmark(c: &mut Criterion) {
 
         b.iter(move || black_box(add_blocks(&data)))
     });
+
+    let config = Config::from_arg_list(&["electrs", "--network", "regtest"]);
+    let script = Builder::new().push_opcode(OP_TRUE).into_script();
+
+    // 10 blocks of 200 transactions, all paying to the same script
+    let chain = Chain::new(10, 200, &script);
+    let tx_count = chain.txs().count() as u64;
+
+    let mut group = c.benchmark_group("index");
+    group.throughput(Throughput::Elements(tx_count));
+    group.bench_function("index_blocks", |b| {
+        b.iter(|| black_box(index_blocks(&chain)))
+    });
+    group.finish();
+
+    let history = History::new(&chain, &script, &config);
+    let mut group = c.benchmark_group("history");
+    group.throughput(Throughput::Elements(tx_count));
+    group.bench_function("history_txids", |b| {
+        b.iter(|| black_box(history_txids(&history)))
+    });
+    group.bench_function("utxos", |b| {
+        b.iter_batched(
+            || history.clear_utxo_cache(),
+            |()| black_box(utxos(&history)),
+            BatchSize::SmallInput,
+        )
+    });
+    group.bench_function("utxos_cached", |b| b.iter(|| black_box(utxos(&history))));
+    group.finish();
+
+    let txs: Vec<Transaction> = chain.txs().cloned().collect();
+    let mut group = c.benchmark_group("rest");
+    group.throughput(Throughput::Elements(tx_count));
+    group.bench_function("txs_json", |b| {
+        b.iter(|| black_box(txs_json(&txs, &chain.txos, &config)))
+    });
+    group.finish();
 }
 
 criterion_group!(benches, criterion_benchmark);
@@ -0,0 +1,331 @@
+//! The amounts of the responses, in satoshis unless another unit is requested with `?unit=`
+//! (and as strings with `--json-amounts-as-strings`).
+
+use serde::de::{self, Deserializer, Unexpected, Visitor};
+use serde::{Deserialize, Serializer};
+use serde_json::Value;
+use std::cell::Cell;
+use std::convert::TryFrom;
+use std::fmt;
+use std::str::FromStr;
+
+const SATS_PER_COIN: u64 = 100_000_000;
+
+thread_local! {
+    // the format the amount fields are serialized in on this thread, see `with_format()`
+    static FORMAT: Cell<AmountFormat> = Cell::new(AmountFormat::RAW);
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AmountUnit {
+    Sat,
+    Flc,
+}
+
+impl FromStr for AmountUnit {
+    type Err = String;
+
+    fn from_str(unit: &str) -> Result<Self, Self::Err> {
+        match unit {
+            "sat" => Ok(AmountUnit::Sat),
+            "flc" => Ok(AmountUnit::Flc),
+            _ => Err(format!("Invalid unit {:?} (expected sat or flc)", unit)),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AmountFormat {
+    pub unit: AmountUnit,
+    // render satoshi amounts as strings, for clients that cannot represent integers above 2^53
+    pub as_strings: bool,
+}
+
+impl AmountFormat {
+    pub const RAW: AmountFormat = AmountFormat {
+        unit: AmountUnit::Sat,
+        as_strings: false,
+    };
+
+    // amounts are rendered as plain satoshi numbers, no conversion needed
+    pub fn is_raw(&self) -> bool {
+        self.unit == AmountUnit::Sat && !self.as_strings
+    }
+
+    pub fn format(&self, sats: i64) -> Value {
+        match self.unit {
+            AmountUnit::Flc => Value::String(sat_to_flc_string(sats)),
+            AmountUnit::Sat if self.as_strings => Value::String(sats.to_string()),
+            AmountUnit::Sat => Value::from(sats),
+        }
+    }
+}
+
+// Formats an amount of satoshis as a fixed-point FLC decimal string
+pub fn sat_to_flc_string(sats: i64) -> String {
+    let sign = if sats < 0 { "-" } else { "" };
+    let sats = sats.unsigned_abs();
+    format!(
+        "{}{}.{:08}",
+        sign,
+        sats / SATS_PER_COIN,
+        sats % SATS_PER_COIN
+    )
+}
+
+/// Runs `f` with the amount fields serialized in `format` on the current thread.
+///
+/// The amount fields are the ones annotated with `#[serde(serialize_with = ...)]` using the
+/// serializers below. They are plain satoshi numbers outside of `with_format()`, and always are
+/// with the binary serializers (like bincode, for the values written to a database).
+pub fn with_format<T>(format: AmountFormat, f: impl FnOnce() -> T) -> T {
+    // restores the previous format, even if `f` panics
+    struct Restore(AmountFormat);
+    impl Drop for Restore {
+        fn drop(&mut self) {
+            FORMAT.with(|current| current.set(self.0));
+        }
+    }
+    let _restore = Restore(FORMAT.with(|current| current.replace(format)));
+    f()
+}
+
+// The format to serialize the amounts in with `serializer`
+fn current_format<S: Serializer>(serializer: &S) -> AmountFormat {
+    if serializer.is_human_readable() {
+        FORMAT.with(Cell::get)
+    } else {
+        AmountFormat::RAW
+    }
+}
+
+fn serialize_formatted<S: Serializer>(
+    sats: i64,
+    format: AmountFormat,
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    match format.unit {
+        AmountUnit::Flc => serializer.serialize_str(&sat_to_flc_string(sats)),
+        AmountUnit::Sat if format.as_strings => serializer.collect_str(&sats),
+        AmountUnit::Sat => serializer.serialize_i64(sats),
+    }
+}
+
+/// Serializes an amount of satoshis in the format set with `with_format()`
+pub fn serialize<S: Serializer>(sats: &u64, serializer: S) -> Result<S::Ok, S::Error> {
+    match current_format(&serializer) {
+        format if format.is_raw() => serializer.serialize_u64(*sats),
+        format => serialize_formatted(*sats as i64, format, serializer),
+    }
+}
+
+/// Like `serialize()`, for amounts that may be missing (e.g. confidential values)
+pub fn serialize_opt<S: Serializer>(sats: &Option<u64>, serializer: S) -> Result<S::Ok, S::Error> {
+    match sats {
+        Some(sats) => serialize(sats, serializer),
+        None => serializer.serialize_none(),
+    }
+}
+
+/// Like `serialize()`, for amounts that may be negative (e.g. balance changes)
+pub fn serialize_signed<S: Serializer>(sats: &i64, serializer: S) -> Result<S::Ok, S::Error> {
+    match current_format(&serializer) {
+        format if format.is_raw() => serializer.serialize_i64(*sats),
+        format => serialize_formatted(*sats, format, serializer),
+    }
+}
+
+// Parses the amounts in any of the formats above, back into satoshis
+struct SatsVisitor;
+
+impl<'de> Visitor<'de> for SatsVisitor {
+    type Value = i64;
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("an amount in satoshis, or a decimal FLC string")
+    }
+
+    fn visit_i64<E: de::Error>(self, sats: i64) -> Result<i64, E> {
+        Ok(sats)
+    }
+
+    fn visit_u64<E: de::Error>(self, sats: u64) -> Result<i64, E> {
+        i64::try_from(sats).map_err(|_| E::invalid_value(Unexpected::Unsigned(sats), &self))
+    }
+
+    fn visit_str<E: de::Error>(self, amount: &str) -> Result<i64, E> {
+        parse_amount(amount).ok_or_else(|| E::invalid_value(Unexpected::Str(amount), &self))
+    }
+}
+
+// FLC amounts always have a decimal point, satoshi amounts never do
+fn parse_amount(amount: &str) -> Option<i64> {
+    let (negative, unsigned) = match amount.strip_prefix('-') {
+        Some(unsigned) => (true, unsigned),
+        None => (false, amount),
+    };
+    let sats = match unsigned.split_once('.') {
+        Some((coins, fraction)) => {
+            if fraction.len() > 8 || !fraction.bytes().all(|b| b.is_ascii_digit()) {
+                return None;
+            }
+            let fraction: u64 = format!("{:0<8}", fraction).parse().ok()?;
+            coins
+                .parse::<u64>()
+                .ok()?
+                .checked_mul(SATS_PER_COIN)?
+                .checked_add(fraction)?
+        }
+        None => unsigned.parse::<u64>().ok()?,
+    };
+    let sats = i64::try_from(sats).ok()?;
+    Some(if negative { -sats } else { sats })
+}
+
+/// Deserializes an amount serialized by `serialize()`, in any format
+pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<u64, D::Error> {
+    let sats = deserializer.deserialize_any(SatsVisitor)?;
+    u64::try_from(sats)
+        .map_err(|_| de::Error::invalid_value(Unexpected::Signed(sats), &SatsVisitor))
+}
+
+/// Deserializes an amount serialized by `serialize_opt()`, in any format
+pub fn deserialize_opt<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Option<u64>, D::Error> {
+    #[derive(Deserialize)]
+    struct Amount(#[serde(deserialize_with = "deserialize")] u64);
+
+    Ok(Option::<Amount>::deserialize(deserializer)?.map(|amount| amount.0))
+}
+
+/// Deserializes an amount serialized by `serialize_signed()`, in any format
+pub fn deserialize_signed<'de, D: Deserializer<'de>>(deserializer: D) -> Result<i64, D::Error> {
+    deserializer.deserialize_any(SatsVisitor)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde::Serialize;
+
+    #[test]
+    fn test_sat_to_flc_string() {
+        assert_eq!(sat_to_flc_string(0), "0.00000000");
+        assert_eq!(sat_to_flc_string(1), "0.00000001");
+        assert_eq!(sat_to_flc_string(123_456_789), "1.23456789");
+        assert_eq!(sat_to_flc_string(-50_000_000), "-0.50000000");
+    }
+
+    #[derive(Serialize)]
+    struct Amounts {
+        txid: &'static str,
+        #[serde(serialize_with = "serialize")]
+        value: u64,
+        #[serde(serialize_with = "serialize_opt")]
+        fee: Option<u64>,
+        #[serde(serialize_with = "serialize_signed")]
+        delta: i64,
+        // not an amount, left as is
+        weight: u64,
+    }
+
+    fn amounts() -> Amounts {
+        Amounts {
+            txid: "00",
+            value: 9_007_199_254_740_993,
+            fee: Some(1000),
+            delta: -50_000_000,
+            weight: 400,
+        }
+    }
+
+    #[test]
+    fn test_serialize_raw() {
+        assert_eq!(
+            serde_json::to_value(amounts()).unwrap(),
+            json!({
+                "txid": "00",
+                "value": 9_007_199_254_740_993u64,
+                "fee": 1000,
+                "delta": -50_000_000,
+                "weight": 400,
+            })
+        );
+    }
+
+    #[test]
+    fn test_serialize_flc() {
+        let flc = AmountFormat {
+            unit: AmountUnit::Flc,
+            as_strings: false,
+        };
+        let value = with_format(flc, || serde_json::to_value(amounts()).unwrap());
+        assert_eq!(
+            value,
+            json!({
+                "txid": "00",
+                "value": "90071992.54740993",
+                "fee": "0.00001000",
+                "delta": "-0.50000000",
+                "weight": 400,
+            })
+        );
+        // back to the raw format once done
+        assert_eq!(serde_json::to_value(amounts()).unwrap()["fee"], 1000);
+    }
+
+    #[test]
+    fn test_serialize_as_strings() {
+        let sat_strings = AmountFormat {
+            unit: AmountUnit::Sat,
+            as_strings: true,
+        };
+        let value = with_format(sat_strings, || serde_json::to_value(amounts()).unwrap());
+        assert_eq!(value["value"], "9007199254740993");
+        assert_eq!(value["fee"], "1000");
+        assert_eq!(value["delta"], "-50000000");
+        assert_eq!(value["weight"], 400);
+    }
+
+    #[test]
+    fn test_deserialize() {
+        #[derive(Deserialize)]
+        struct Amounts {
+            #[serde(deserialize_with = "deserialize")]
+            value: u64,
+            #[serde(default, deserialize_with = "deserialize_opt")]
+            fee: Option<u64>,
+            #[serde(deserialize_with = "deserialize_signed")]
+            delta: i64,
+        }
+
+        for value in [
+            json!({ "value": 123_456_789, "fee": 1000, "delta": -50_000_000 }),
+            json!({ "value": "123456789", "fee": "1000", "delta": "-50000000" }),
+            json!({ "value": "1.23456789", "fee": "0.00001", "delta": "-0.50000000" }),
+        ] {
+            let amounts: Amounts = serde_json::from_value(value).unwrap();
+            assert_eq!(amounts.value, 123_456_789);
+            assert_eq!(amounts.fee, Some(1000));
+            assert_eq!(amounts.delta, -50_000_000);
+        }
+        let amounts: Amounts = serde_json::from_value(json!({ "value": 1, "delta": 0 })).unwrap();
+        assert_eq!(amounts.fee, None);
+
+        assert_eq!(parse_amount("1.123456789"), None);
+        assert_eq!(parse_amount("1.5e3"), None);
+        assert!(serde_json::from_value::<Amounts>(json!({ "value": -1, "delta": 0 })).is_err());
+    }
+
+    #[test]
+    fn test_serialize_binary() {
+        let flc = AmountFormat {
+            unit: AmountUnit::Flc,
+            as_strings: false,
+        };
+        let raw = bincode::serialize(&amounts()).unwrap();
+        let formatted = with_format(flc, || bincode::serialize(&amounts()).unwrap());
+        assert_eq!(raw, formatted);
+    }
+}
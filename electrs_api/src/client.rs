@@ -0,0 +1,173 @@
+use std::collections::HashMap;
+
+use flokicoin::{BlockHash, Txid};
+use reqwest::Response;
+use serde::de::DeserializeOwned;
+
+use crate::errors::*;
+use crate::types::*;
+
+/// A client of the REST API of a flokicoin-electrs server
+#[derive(Clone, Debug)]
+pub struct Client {
+    base_url: String,
+    http: reqwest::Client,
+}
+
+impl Client {
+    /// `base_url` is the root of the REST API, like `http://localhost:3000`
+    pub fn new(base_url: &str) -> Self {
+        Client::with_http_client(base_url, reqwest::Client::new())
+    }
+
+    /// Uses a preconfigured `reqwest::Client`, for setting timeouts, proxies or headers
+    pub fn with_http_client(base_url: &str, http: reqwest::Client) -> Self {
+        Client {
+            base_url: base_url.trim_end_matches('/').to_string(),
+            http,
+        }
+    }
+
+    pub async fn tx(&self, txid: &Txid) -> Result<Transaction> {
+        self.get_json(&format!("/tx/{}", txid)).await
+    }
+
+    pub async fn tx_status(&self, txid: &Txid) -> Result<TxStatus> {
+        self.get_json(&format!("/tx/{}/status", txid)).await
+    }
+
+    pub async fn tx_hex(&self, txid: &Txid) -> Result<String> {
+        self.get_text(&format!("/tx/{}/hex", txid)).await
+    }
+
+    pub async fn tx_outspends(&self, txid: &Txid) -> Result<Vec<Outspend>> {
+        self.get_json(&format!("/tx/{}/outspends", txid)).await
+    }
+
+    /// Broadcasts a hex-encoded raw transaction, returning its txid
+    pub async fn broadcast(&self, tx_hex: &str) -> Result<Txid> {
+        let resp = self
+            .http
+            .post(format!("{}/tx", self.base_url))
+            .body(tx_hex.to_string())
+            .send()
+            .await?;
+        parse_text(check_status(resp).await?.text().await?)
+    }
+
+    pub async fn block(&self, hash: &BlockHash) -> Result<Block> {
+        self.get_json(&format!("/block/{}", hash)).await
+    }
+
+    pub async fn block_status(&self, hash: &BlockHash) -> Result<BlockStatus> {
+        self.get_json(&format!("/block/{}/status", hash)).await
+    }
+
+    pub async fn block_hash(&self, height: u32) -> Result<BlockHash> {
+        parse_text(self.get_text(&format!("/block-height/{}", height)).await?)
+    }
+
+    /// The 10 blocks at and below `start_height`, or below the tip if not given
+    pub async fn blocks(&self, start_height: Option<u32>) -> Result<Vec<Block>> {
+        match start_height {
+            Some(height) => self.get_json(&format!("/blocks/{}", height)).await,
+            None => self.get_json("/blocks").await,
+        }
+    }
+
+    pub async fn tip_hash(&self) -> Result<BlockHash> {
+        parse_text(self.get_text("/blocks/tip/hash").await?)
+    }
+
+    pub async fn tip_height(&self) -> Result<u32> {
+        parse_text(self.get_text("/blocks/tip/height").await?)
+    }
+
+    pub async fn address(&self, address: &str) -> Result<AddressInfo> {
+        self.get_json(&format!("/address/{}", address)).await
+    }
+
+    /// The confirmed transactions of an address, newest first, following `last_seen_txid`
+    pub async fn address_txs_chain(
+        &self,
+        address: &str,
+        last_seen_txid: Option<&Txid>,
+    ) -> Result<Vec<Transaction>> {
+        match last_seen_txid {
+            Some(txid) => {
+                self.get_json(&format!("/address/{}/txs/chain/{}", address, txid))
+                    .await
+            }
+            None => {
+                self.get_json(&format!("/address/{}/txs/chain", address))
+                    .await
+            }
+        }
+    }
+
+    pub async fn address_txs_mempool(&self, address: &str) -> Result<Vec<Transaction>> {
+        self.get_json(&format!("/address/{}/txs/mempool", address))
+            .await
+    }
+
+    pub async fn address_utxos(&self, address: &str) -> Result<Vec<Utxo>> {
+        self.get_json(&format!("/address/{}/utxo", address)).await
+    }
+
+    pub async fn mempool(&self) -> Result<MempoolStats> {
+        self.get_json("/mempool").await
+    }
+
+    /// The feerate estimates by confirmation target, in sat/vB
+    pub async fn fee_estimates(&self) -> Result<HashMap<u16, f64>> {
+        let estimates: HashMap<String, f64> = self.get_json("/fee-estimates").await?;
+        estimates
+            .into_iter()
+            .map(|(target, feerate)| Ok((parse_text(target)?, feerate)))
+            .collect()
+    }
+
+    pub async fn get_json<T: DeserializeOwned>(&self, path: &str) -> Result<T> {
+        Ok(self.get(path).await?.json().await?)
+    }
+
+    pub async fn get_text(&self, path: &str) -> Result<String> {
+        Ok(self.get(path).await?.text().await?)
+    }
+
+    async fn get(&self, path: &str) -> Result<Response> {
+        let resp = self
+            .http
+            .get(format!("{}{}", self.base_url, path))
+            .send()
+            .await?;
+        check_status(resp).await
+    }
+}
+
+// Turn the error responses into `ErrorKind::Api`, keeping the stable error code when the body
+// has one
+async fn check_status(resp: Response) -> Result<Response> {
+    let status = resp.status();
+    if status.is_success() {
+        return Ok(resp);
+    }
+    let body = resp.text().await?;
+    let error = serde_json::from_str::<ApiError>(&body).unwrap_or_else(|_| ApiError {
+        code: "UNKNOWN".to_string(),
+        message: body,
+        details: None,
+        request_id: None,
+    });
+    bail!(ErrorKind::Api(status.as_u16(), error))
+}
+
+fn parse_text<T>(text: String) -> Result<T>
+where
+    T: std::str::FromStr,
+    T::Err: std::fmt::Display,
+{
+    text.trim()
+        .parse()
+        .map_err(|e: T::Err| ErrorKind::InvalidResponse(format!("{}: {}", text, e)).into())
+}
@@ -0,0 +1,23 @@
+use crate::types::ApiError;
+
+error_chain! {
+    types {
+        Error, ErrorKind, ResultExt, Result;
+    }
+
+    foreign_links {
+        Http(reqwest::Error);
+    }
+
+    errors {
+        Api(status: u16, error: ApiError) {
+            description("API error")
+            display("API error {} ({}): {}", status, error.code, error.message)
+        }
+
+        InvalidResponse(msg: String) {
+            description("Invalid response")
+            display("Invalid response: {}", msg)
+        }
+    }
+}
@@ -0,0 +1,35 @@
+//! Typed responses of the REST API of flokicoin-electrs, and an async client for it.
+//!
+//! ```no_run
+//! # async fn example() -> flokicoin_electrs_api::Result<()> {
+//! let client = flokicoin_electrs_api::Client::new("http://localhost:3000");
+//! let tip = client.tip_hash().await?;
+//! let block = client.block(&tip).await?;
+//! println!("block {} has {} transactions", block.height, block.tx_count);
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! The server serializes the transactions, blocks, utxos and address stats with these types, so
+//! they can't drift apart. Its tests check that the other responses (the statuses, outspends and
+//! mempool stats) parse as them.
+
+#[cfg(feature = "client")]
+#[macro_use]
+extern crate error_chain;
+#[cfg(test)]
+#[macro_use]
+extern crate serde_json;
+
+pub mod amount;
+#[cfg(feature = "client")]
+mod client;
+#[cfg(feature = "client")]
+pub mod errors;
+pub mod types;
+
+#[cfg(feature = "client")]
+pub use crate::client::Client;
+#[cfg(feature = "client")]
+pub use crate::errors::{Error, ErrorKind, Result};
+pub use crate::types::*;
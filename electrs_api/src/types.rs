@@ -0,0 +1,341 @@
+//! The JSON responses of the REST API, as served without the `liquid` feature
+
+use flokicoin::{BlockHash, ScriptBuf, TxMerkleNode, Txid};
+use serde::{Deserialize, Serialize};
+
+#[cfg(feature = "schema")]
+use utoipa::ToSchema;
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "schema", derive(ToSchema))]
+pub struct Transaction {
+    #[cfg_attr(feature = "schema", schema(value_type = String))]
+    pub txid: Txid,
+    pub version: u32,
+    pub locktime: u32,
+    /// Whether the locktime is a block height or a timestamp, missing when it is 0
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub locktime_type: Option<LocktimeType>,
+    /// Whether the locktime is enforced, which takes an input with a non-final sequence
+    pub locktime_active: bool,
+    /// Whether the locktime allows including the transaction in the next block
+    pub is_final: bool,
+    pub vin: Vec<TxIn>,
+    pub vout: Vec<TxOut>,
+    pub size: u32,
+    pub weight: u64,
+    pub vsize: u64,
+    pub stripped_size: u32,
+    /// Missing when the lookup of the prevouts is skipped (with `?prevouts=no`)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sigops: Option<usize>,
+    /// Missing when the lookup of the prevouts is skipped (with `?prevouts=no`)
+    #[serde(
+        default,
+        skip_serializing_if = "Option::is_none",
+        serialize_with = "crate::amount::serialize_opt",
+        deserialize_with = "crate::amount::deserialize_opt"
+    )]
+    pub fee: Option<u64>,
+    /// The value of the outputs and fee in the `--price-currency`, missing unless enabled
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub fiat: Option<FiatValue>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub status: Option<TxStatus>,
+    /// Set when only a page of the inputs and outputs is included
+    #[serde(flatten)]
+    pub paging: Option<TxIoPaging>,
+    /// Whether the transaction pays to or spends from a blocked script
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub blocked: bool,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "schema", derive(ToSchema))]
+pub struct TxIn {
+    #[cfg_attr(feature = "schema", schema(value_type = String))]
+    pub txid: Txid,
+    pub vout: u32,
+    pub prevout: Option<TxOut>,
+    #[cfg_attr(feature = "schema", schema(value_type = String))]
+    pub scriptsig: ScriptBuf,
+    pub scriptsig_asm: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub witness: Option<Vec<String>>,
+    pub is_coinbase: bool,
+    pub sequence: u32,
+    /// The BIP68 lock relative to the confirmation of the spent output
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub relative_timelock: Option<RelativeTimelock>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub inner_redeemscript_asm: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub inner_witnessscript_asm: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "schema", derive(ToSchema))]
+pub struct TxOut {
+    #[cfg_attr(feature = "schema", schema(value_type = String))]
+    pub scriptpubkey: ScriptBuf,
+    pub scriptpubkey_asm: String,
+    pub scriptpubkey_type: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub scriptpubkey_address: Option<String>,
+    #[serde(
+        serialize_with = "crate::amount::serialize",
+        deserialize_with = "crate::amount::deserialize"
+    )]
+    pub value: u64,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "schema", derive(ToSchema))]
+pub struct TxIoPaging {
+    pub vin_count: usize,
+    pub vin_offset: usize,
+    pub vout_count: usize,
+    pub vout_offset: usize,
+    pub page_size: usize,
+    /// Whether inputs or outputs remain past this page
+    #[serde(default)]
+    pub truncated: bool,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "schema", derive(ToSchema))]
+#[serde(rename_all = "lowercase")]
+pub enum LocktimeType {
+    Height,
+    Time,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "schema", derive(ToSchema))]
+#[serde(tag = "type", content = "value", rename_all = "lowercase")]
+pub enum RelativeTimelock {
+    /// A number of blocks since the spent output was confirmed
+    Blocks(u32),
+    /// A number of seconds since the spent output was confirmed
+    Time(u32),
+}
+
+/// The value of an amount in the `--price-currency`, at the price fetched from the
+/// `--price-source`
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "schema", derive(ToSchema))]
+pub struct FiatValue {
+    pub currency: String,
+    /// The price of 1 FLC in the currency
+    pub rate: f64,
+    /// The unix time the price was fetched at
+    pub rate_time: u64,
+    /// The value of the outputs of a transaction, or of the balance of an address
+    pub value: f64,
+    /// The value of the fee of a transaction, when known
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub fee: Option<f64>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "schema", derive(ToSchema))]
+pub struct TxStatus {
+    pub confirmed: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub block_height: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    #[cfg_attr(feature = "schema", schema(value_type = Option<String>))]
+    pub block_hash: Option<BlockHash>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub block_time: Option<u32>,
+    /// Set for the unconfirmed transactions that left the mempool
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub evicted: Option<Eviction>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "schema", derive(ToSchema))]
+pub struct Eviction {
+    pub reason: EvictionReason,
+    /// The transaction spending the same input, for replaced and conflicted transactions
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    #[cfg_attr(feature = "schema", schema(value_type = Option<String>))]
+    pub replaced_by: Option<Txid>,
+    /// The unix timestamp the transaction left the mempool at
+    pub time: u64,
+}
+
+/// Why an unconfirmed transaction disappeared from lokid's mempool
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "schema", derive(ToSchema))]
+#[serde(rename_all = "lowercase")]
+pub enum EvictionReason {
+    /// Another mempool transaction spends one of its inputs
+    Replaced,
+    /// A confirmed transaction spends one of its inputs
+    Conflicted,
+    /// It stayed unconfirmed for longer than lokid's mempool expiry
+    Expired,
+    /// Any other reason, most likely a feerate too low for a full mempool
+    Evicted,
+}
+
+impl EvictionReason {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            EvictionReason::Replaced => "replaced",
+            EvictionReason::Conflicted => "conflicted",
+            EvictionReason::Expired => "expired",
+            EvictionReason::Evicted => "evicted",
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct Outspend {
+    pub spent: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub txid: Option<Txid>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub vin: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub status: Option<TxStatus>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "schema", derive(ToSchema))]
+pub struct Block {
+    #[cfg_attr(feature = "schema", schema(value_type = String))]
+    pub id: BlockHash,
+    pub height: u32,
+    pub version: u32,
+    pub timestamp: u32,
+    pub tx_count: u32,
+    pub size: u32,
+    pub weight: u64,
+    #[cfg_attr(feature = "schema", schema(value_type = String))]
+    pub merkle_root: TxMerkleNode,
+    #[cfg_attr(feature = "schema", schema(value_type = Option<String>))]
+    pub previousblockhash: Option<BlockHash>,
+    pub mediantime: u32,
+    pub nonce: u32,
+    pub bits: u32,
+    pub difficulty: f64,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct BlockStatus {
+    pub in_best_chain: bool,
+    pub height: Option<u32>,
+    pub next_best: Option<BlockHash>,
+    pub confirmations: Option<u32>,
+    /// The block the confirmations are counted from
+    #[serde(default)]
+    pub block_hash: Option<BlockHash>,
+    /// The cumulative work of the chain up to the block, as a hex string
+    pub chainwork: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "schema", derive(ToSchema))]
+pub struct Utxo {
+    #[cfg_attr(feature = "schema", schema(value_type = String))]
+    pub txid: Txid,
+    pub vout: u32,
+    pub status: TxStatus,
+    #[serde(
+        serialize_with = "crate::amount::serialize",
+        deserialize_with = "crate::amount::deserialize"
+    )]
+    pub value: u64,
+}
+
+/// The response of `GET /address/:address`, with the address (or hash) it was looked up by
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "schema", derive(ToSchema))]
+pub struct AddressInfo {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub address: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub scripthash: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub pkh: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sh: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub taproot_key: Option<String>,
+    /// The number of confirmed and unconfirmed transactions
+    pub tx_count: usize,
+    pub chain_stats: ScriptStats,
+    pub mempool_stats: ScriptStats,
+    /// The first and last blocks with a transaction of the script, if any
+    #[serde(default)]
+    pub chain_activity: Option<ScriptActivity>,
+    /// The value of the balance (including the mempool) in the `--price-currency`, missing unless
+    /// enabled
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub fiat: Option<FiatValue>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "schema", derive(ToSchema))]
+pub struct ScriptStats {
+    pub tx_count: usize,
+    pub funded_txo_count: usize,
+    pub spent_txo_count: usize,
+    #[serde(
+        serialize_with = "crate::amount::serialize",
+        deserialize_with = "crate::amount::deserialize"
+    )]
+    pub funded_txo_sum: u64,
+    #[serde(
+        serialize_with = "crate::amount::serialize",
+        deserialize_with = "crate::amount::deserialize"
+    )]
+    pub spent_txo_sum: u64,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "schema", derive(ToSchema))]
+pub struct ScriptActivity {
+    pub first_seen: BlockId,
+    pub last_seen: BlockId,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "schema", derive(ToSchema))]
+pub struct BlockId {
+    pub height: u32,
+    #[cfg_attr(feature = "schema", schema(value_type = String))]
+    pub hash: BlockHash,
+    pub time: u32,
+}
+
+/// The response of `GET /mempool`
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct MempoolStats {
+    pub count: u32,
+    /// In virtual bytes
+    pub vsize: u64,
+    #[serde(
+        serialize_with = "crate::amount::serialize",
+        deserialize_with = "crate::amount::deserialize"
+    )]
+    pub total_fee: u64,
+    /// `(feerate, vsize)` pairs, with the feerate in sat/vB
+    pub fee_histogram: Vec<(f64, u64)>,
+}
+
+/// The JSON body of the error responses
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "schema", derive(ToSchema))]
+pub struct ApiError {
+    /// A stable machine-readable code, like `TX_NOT_FOUND`
+    pub code: String,
+    pub message: String,
+    #[serde(default)]
+    #[cfg_attr(feature = "schema", schema(value_type = Option<Object>))]
+    pub details: Option<serde_json::Value>,
+    #[serde(default)]
+    pub request_id: Option<String>,
+}
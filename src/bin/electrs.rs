@@ -7,14 +7,22 @@ extern crate electrs;
 use bitcoin::hex::DisplayHex;
 use crossbeam_channel::{self as channel};
 use electrs::{
-    config::Config,
+    config::{Config, RunMode},
     daemon::Daemon,
     electrum::RPC as ElectrumRPC,
     errors::*,
     metrics::Metrics,
-    new_index::{precache, zmq, ChainQuery, FetchFrom, Indexer, Mempool, Query, Store},
+    new_index::{
+        describe_prefix, precache, snapshot, zmq, BlockLatency, ChainQuery, FetchFrom,
+        HistoryAuditor, Indexer, Mempool, Query, Store,
+    },
     rest,
     signal::Waiter,
+    util::{
+        pool::ThreadPools,
+        supervisor::{spawn_critical, spawn_supervised},
+        systemd, FullHash,
+    },
 };
 use error_chain::ChainedError;
 use rand::{rng, RngCore};
@@ -32,59 +40,143 @@ use electrs::metrics::MetricOpts;
 /// Default salt rotation interval in seconds (24 hours)
 const DEFAULT_SALT_ROTATION_INTERVAL_SECS: u64 = 24 * 3600;
 
-fn fetch_from(config: &Config, store: &Store) -> FetchFrom {
+/// How often the refreshes of the cached utxo sets put off by the load are retried
+const UTXO_CACHES_RETRY_INTERVAL: Duration = Duration::from_secs(5);
+
+fn fetch_from(config: &Config, store: &Store) -> Result<FetchFrom> {
     let mut jsonrpc_import = config.jsonrpc_import;
     if !jsonrpc_import {
         // switch over to jsonrpc after the initial sync is done
-        jsonrpc_import = store.done_initial_sync();
+        jsonrpc_import = store.done_initial_sync()?;
     }
 
-    if jsonrpc_import {
+    Ok(if jsonrpc_import {
         // slower, uses JSONRPC (good for incremental updates)
         FetchFrom::Bitcoind
     } else {
         // faster, uses blk*.dat files (good for initial indexing)
         FetchFrom::BlkFiles
+    })
+}
+
+// Refreshes the cached utxo sets of the scripthashes touched by the new blocks on a thread of its
+// own, so that the main loop goes on with the mempool and the subscriptions in the meantime
+fn start_utxo_caches_refresh(query: Arc<Query>) -> channel::Sender<Vec<FullHash>> {
+    let (sender, receiver) = channel::unbounded::<Vec<FullHash>>();
+    spawn_supervised("utxo-caches", move || loop {
+        match receiver.recv_timeout(UTXO_CACHES_RETRY_INTERVAL) {
+            Ok(scripthashes) => query.update_utxo_caches(scripthashes),
+            // retry the refreshes put off by the load, if any
+            Err(channel::RecvTimeoutError::Timeout) => query.update_utxo_caches(vec![]),
+            Err(channel::RecvTimeoutError::Disconnected) => break,
+        }
+    });
+    sender
+}
+
+// Refreshes the block template served by the next-block endpoint whenever notified, so that the
+// requests and the main loop don't wait for lokid's getblocktemplate
+#[cfg(not(feature = "liquid"))]
+fn start_block_template_refresh(query: Arc<Query>) -> channel::Sender<()> {
+    // notifications sent while refreshing are coalesced into one
+    let (sender, receiver) = channel::bounded::<()>(1);
+    spawn_supervised("block-template", move || {
+        for () in receiver.iter() {
+            query.update_block_template();
+        }
+    });
+    sender
+}
+
+fn print_db_stats(config: &Config, store: &Store) -> Result<()> {
+    const MB: f64 = 1024.0 * 1024.0;
+    for (db_name, usage) in store.key_space_usage()? {
+        let (rows, bytes) = usage
+            .values()
+            .fold((0, 0), |(rows, bytes), u| (rows + u.rows, bytes + u.bytes));
+        println!(
+            "{}/{}: {} rows, {:.1} MB",
+            config.network_name,
+            db_name,
+            rows,
+            bytes as f64 / MB
+        );
+        for (prefix, u) in usage {
+            println!(
+                "  {:<6} {:<28} {:>14} rows {:>12.1} MB {:>6.1}%",
+                format!("{:?}", prefix as char),
+                describe_prefix(db_name, prefix),
+                u.rows,
+                u.bytes as f64 / MB,
+                100.0 * u.bytes as f64 / bytes.max(1) as f64
+            );
+        }
     }
+    Ok(())
 }
 
-fn run_server(config: Arc<Config>, salt_rwlock: Arc<RwLock<String>>) -> Result<()> {
+fn run_server(
+    config: Arc<Config>,
+    metrics: Metrics,
+    salt_rwlock: Arc<RwLock<String>>,
+    ready: channel::Sender<()>,
+) -> Result<()> {
+    if let RunMode::DbStats = config.run_mode {
+        // no need for lokid to look into the databases
+        let store = Store::open(&config.db_path, &config, &metrics)?;
+        return print_db_stats(&config, &store);
+    }
+
     let (block_hash_notify, block_hash_receive) = channel::bounded(1);
     let signal = Waiter::start(block_hash_receive);
-    let metrics = Metrics::new(config.monitoring_addr);
-    metrics.start();
 
-    if let Some(zmq_addr) = config.zmq_addr.as_ref() {
-        zmq::start(&format!("tcp://{zmq_addr}"), block_hash_notify);
-    }
+    let latency = Arc::new(BlockLatency::new(&metrics));
+    let mempool_sequence = config.zmq_addr.as_ref().map(|zmq_addr| {
+        let feed = Arc::new(zmq::MempoolSequenceFeed::default());
+        zmq::start(
+            &format!("tcp://{zmq_addr}"),
+            block_hash_notify,
+            Arc::clone(&latency),
+            Arc::clone(&feed),
+        );
+        feed
+    });
 
     let daemon = Arc::new(Daemon::new(
         &config.daemon_dir,
         &config.blocks_dir,
-        config.daemon_rpc_addr,
+        config.daemon_rpc_addr.clone(),
         config.daemon_parallelism,
+        config.daemon_rpc_batch_size,
         config.cookie_getter(),
         config.network_type,
         signal.clone(),
         &metrics,
     )?);
-    let store = Arc::new(Store::open(
-        &config.db_path.join("newindex"),
-        &config,
-        &metrics,
-    ));
+    let store = Arc::new(Store::open(&config.db_path, &config, &metrics)?);
+    let pools = ThreadPools::new(&config, &metrics);
+    if let RunMode::DumpUtxos { height, ref out } = config.run_mode {
+        // from the index as it is, without syncing it first
+        let chain = ChainQuery::new(store, daemon, &config, &pools, &metrics);
+        return snapshot::dump_utxos(&chain, &pools.query, height, out).map(|_| ());
+    }
     let mut indexer = Indexer::open(
         Arc::clone(&store),
-        fetch_from(&config, &store),
+        fetch_from(&config, &store)?,
         &config,
+        &pools,
         &metrics,
     );
+    if let RunMode::ReindexRange { from, to } = config.run_mode {
+        return indexer.reindex_range(&daemon, from, to);
+    }
     let mut tip = indexer.update(&daemon)?;
 
     let chain = Arc::new(ChainQuery::new(
         Arc::clone(&store),
         Arc::clone(&daemon),
         &config,
+        &pools,
         &metrics,
     ));
 
@@ -99,8 +191,16 @@ fn run_server(config: Arc<Config>, salt_rwlock: Arc<RwLock<String>>) -> Result<(
         &metrics,
         Arc::clone(&config),
     )));
+    if let Some(feed) = mempool_sequence {
+        mempool.write().unwrap().follow_zmq_sequence(feed);
+    }
 
     while !Mempool::update(&mempool, &daemon, &tip)? {
+        if store.is_read_only() {
+            // the new blocks can't be indexed until disk space is freed up
+            warn!("starting with a partially synced mempool, indexing is paused");
+            break;
+        }
         // Mempool syncing was aborted because the chain tip moved;
         // Index the new block(s) and try again.
         tip = indexer.update(&daemon)?;
@@ -118,19 +218,34 @@ fn run_server(config: Arc<Config>, salt_rwlock: Arc<RwLock<String>>) -> Result<(
         Arc::clone(&mempool),
         Arc::clone(&daemon),
         Arc::clone(&config),
+        &metrics,
         #[cfg(feature = "liquid")]
         asset_db,
-    ));
+    )?);
 
     // TODO: configuration for which servers to start
-    let rest_server = rest::start(Arc::clone(&config), Arc::clone(&query));
+    let rest_server = rest::start(Arc::clone(&config), Arc::clone(&query), &metrics);
     let electrum_server = ElectrumRPC::start(
         Arc::clone(&config),
         Arc::clone(&query),
+        &pools,
         &metrics,
         Arc::clone(&salt_rwlock),
     );
 
+    // the index and mempool caught up and the servers are listening
+    systemd::notify(&format!(
+        "STATUS=Indexed {} up to height {}",
+        config.network_name,
+        chain.best_height()
+    ));
+    ready.send(()).ok();
+
+    let utxo_caches_refresh = start_utxo_caches_refresh(Arc::clone(&query));
+    #[cfg(not(feature = "liquid"))]
+    let block_template_refresh = start_block_template_refresh(Arc::clone(&query));
+    HistoryAuditor::new(Arc::clone(&chain), &config, &metrics).start(pools.load.clone());
+
     let main_loop_count = metrics.gauge(MetricOpts::new(
         "electrs_main_loop_count",
         "count of iterations of electrs main loop each 5 seconds or after interrupts",
@@ -141,24 +256,72 @@ fn run_server(config: Arc<Config>, salt_rwlock: Arc<RwLock<String>>) -> Result<(
 
         if let Err(err) = signal.wait(Duration::from_millis(config.main_loop_delay), true) {
             info!("stopping server: {}", err);
+            systemd::notify("STOPPING=1");
             rest_server.stop();
+            if let Err(err) = query.clients().save() {
+                warn!(
+                    "failed saving the electrum client stats: {}",
+                    err.display_chain()
+                );
+            }
+            if let Err(err) = store.save_totals() {
+                warn!("failed saving the lifetime totals: {}", err.display_chain());
+            }
             // the electrum server is stopped when dropped
             break;
         }
 
+        if signal.take_reload_request() {
+            if let Err(err) = config.reload_runtime() {
+                warn!(
+                    "failed reloading the runtime config: {}",
+                    err.display_chain()
+                );
+            }
+            if let Err(err) = query.reload_blocklist() {
+                warn!("failed reloading the blocklist: {}", err.display_chain());
+            }
+        }
+
         // Index new blocks
         let current_tip = daemon.getbestblockhash()?;
         if current_tip != tip {
+            latency.announced(current_tip);
             tip = indexer.update(&daemon)?;
+            let best = chain.best_header();
+            latency.indexed(best.hash(), best.header().time);
+            systemd::notify(&format!(
+                "STATUS=Indexed {} up to height {}",
+                config.network_name,
+                chain.best_height()
+            ));
         };
+        let touched = indexer.take_touched();
+        if !touched.is_empty() {
+            utxo_caches_refresh.send(touched).ok();
+        }
 
         // Update mempool
         if !Mempool::update(&mempool, &daemon, &tip)? {
             warn!("skipped failed mempool update, trying again in 5 seconds");
         }
 
+        #[cfg(not(feature = "liquid"))]
+        block_template_refresh.try_send(()).ok();
+
         // Update subscribed clients
         electrum_server.notify();
+
+        if let Err(err) = query.clients().save_if_due(&pools.load) {
+            warn!(
+                "failed saving the electrum client stats: {}",
+                err.display_chain()
+            );
+        }
+
+        if let Err(err) = store.save_totals_if_due(&pools.load) {
+            warn!("failed saving the lifetime totals: {}", err.display_chain());
+        }
     }
     info!("server stopped");
     Ok(())
@@ -206,10 +369,40 @@ fn spawn_salt_rotation_thread() -> Arc<RwLock<String>> {
 fn main_() {
     let salt_rwlock = spawn_salt_rotation_thread();
 
-    let config = Arc::new(Config::from_args());
-    if let Err(e) = run_server(config, Arc::clone(&salt_rwlock)) {
-        error!("server failed: {}", e.display_chain());
-        process::exit(1);
+    let configs = Config::from_args_multi();
+    // the metrics of all the networks are served together, on the first network's address
+    let metrics = Metrics::new(configs[0].monitoring_addr);
+    metrics.start();
+
+    let (ready_sender, ready_receiver) = channel::unbounded();
+    let multi_network = configs.len() > 1;
+    let servers = configs
+        .into_iter()
+        .map(|config| {
+            let metrics = if multi_network {
+                metrics.with_label("network", &config.network_name)
+            } else {
+                metrics.clone()
+            };
+            let salt_rwlock = Arc::clone(&salt_rwlock);
+            let ready = ready_sender.clone();
+            spawn_critical(&format!("server-{}", config.network_name), move || {
+                let network_name = config.network_name.clone();
+                if let Err(e) = run_server(Arc::new(config), metrics, salt_rwlock, ready) {
+                    error!("{} server failed: {}", network_name, e.display_chain());
+                    process::exit(1);
+                }
+            })
+        })
+        .collect::<Vec<_>>();
+    drop(ready_sender);
+
+    // ready once all the networks caught up with their chain tip
+    if ready_receiver.iter().take(servers.len()).count() == servers.len() {
+        systemd::notify("READY=1");
+    }
+    for server in servers {
+        server.join().expect("server thread panicked");
     }
 }
 
@@ -18,17 +18,13 @@ fn main() {
         metrics::Metrics,
         new_index::{ChainQuery, FetchFrom, Indexer, Store},
         signal::Waiter,
-        util::has_prevout,
+        util::{has_prevout, pool::ThreadPools},
     };
 
     let signal = Waiter::start(crossbeam_channel::never());
     let config = Config::from_args();
     let metrics = Metrics::new(config.monitoring_addr);
-    let store = Arc::new(Store::open(
-        &config.db_path.join("newindex"),
-        &config,
-        &metrics,
-    ));
+    let store = Arc::new(Store::open(&config.db_path, &config, &metrics).unwrap());
 
     let metrics = Metrics::new(config.monitoring_addr);
     metrics.start();
@@ -37,8 +33,9 @@ fn main() {
         Daemon::new(
             &config.daemon_dir,
             &config.blocks_dir,
-            config.daemon_rpc_addr,
+            config.daemon_rpc_addr.clone(),
             config.daemon_parallelism,
+            config.daemon_rpc_batch_size,
             config.cookie_getter(),
             config.network_type,
             signal,
@@ -47,9 +44,22 @@ fn main() {
         .unwrap(),
     );
 
-    let chain = ChainQuery::new(Arc::clone(&store), Arc::clone(&daemon), &config, &metrics);
+    let pools = ThreadPools::new(&config, &metrics);
+    let chain = ChainQuery::new(
+        Arc::clone(&store),
+        Arc::clone(&daemon),
+        &config,
+        &pools,
+        &metrics,
+    );
 
-    let mut indexer = Indexer::open(Arc::clone(&store), FetchFrom::Bitcoind, &config, &metrics);
+    let mut indexer = Indexer::open(
+        Arc::clone(&store),
+        FetchFrom::Bitcoind,
+        &config,
+        &pools,
+        &metrics,
+    );
     indexer.update(&daemon).unwrap();
 
     let mut iter = store.txstore_db().raw_iterator();
@@ -66,6 +76,10 @@ fn main() {
             break;
         }
 
+        let value = store
+            .checksums()
+            .verify("transaction", key, value.to_vec())
+            .expect("corrupt transaction row");
         let tx: Transaction = deserialize(&value).expect("failed to parse Transaction");
         let txid = tx.compute_txid();
 
@@ -81,7 +95,7 @@ fn main() {
         }
 
         // skip orphaned transactions
-        let blockid = match chain.tx_confirming_block(&txid) {
+        let blockid = match chain.tx_confirming_block(&txid).unwrap() {
             Some(blockid) => blockid,
             None => continue,
         };
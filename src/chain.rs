@@ -2,7 +2,7 @@
 pub use bitcoin::{
     address, blockdata::block::Header as BlockHeader, blockdata::script, consensus::deserialize,
     hash_types::TxMerkleNode, Address, Block, BlockHash, OutPoint, ScriptBuf as Script, Sequence,
-    Transaction, TxIn, TxOut, Txid,
+    Transaction, TxIn, TxOut, Txid, Wtxid,
 };
 
 #[cfg(feature = "liquid")]
@@ -22,6 +22,40 @@ pub type Value = u64;
 #[cfg(feature = "liquid")]
 pub use confidential::Value;
 
+/// Static description of the chain family electrs is built for.
+///
+/// Code paths that only differ in *whether* asset-aware behaviour applies (as opposed to
+/// the concrete types they operate on) should branch on these constants instead of adding
+/// new `cfg(feature = "liquid")` blocks. The branches are resolved at compile time.
+pub trait ChainParams {
+    /// Issued assets, pegs and burns are exposed under the `/asset(s)` REST endpoints.
+    const HAS_ASSET_ENDPOINTS: bool;
+}
+
+/// The regular Flokicoin chain.
+pub enum Flokicoin {}
+
+impl ChainParams for Flokicoin {
+    const HAS_ASSET_ENDPOINTS: bool = false;
+}
+
+/// Elements-based sidechains (Liquid, or a future Flokicoin sidechain).
+pub enum Elements {}
+
+impl ChainParams for Elements {
+    const HAS_ASSET_ENDPOINTS: bool = true;
+}
+
+#[cfg(not(feature = "liquid"))]
+pub type ActiveChain = Flokicoin;
+#[cfg(feature = "liquid")]
+pub type ActiveChain = Elements;
+
+// The asset endpoints (and the `AssetId` and `I` history rows they serve) only exist with the
+// `liquid` feature, so make sure the active chain params can never claim otherwise.
+const _: () =
+    assert!(<ActiveChain as ChainParams>::HAS_ASSET_ENDPOINTS == cfg!(feature = "liquid"));
+
 #[derive(Debug, Copy, Clone, PartialEq, Hash, Serialize, Ord, PartialOrd, Eq)]
 pub enum Network {
     #[cfg(not(feature = "liquid"))]
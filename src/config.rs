@@ -1,15 +1,25 @@
-use clap::{App, Arg};
+use clap::{App, Arg, ArgMatches};
 use dirs::home_dir;
+use std::collections::{BTreeMap, HashSet};
+use std::env;
+use std::ffi::OsString;
 use std::fs;
 use std::net::SocketAddr;
 use std::net::ToSocketAddrs;
 use std::path::{Path, PathBuf};
-use std::sync::Arc;
+use std::str::FromStr;
+use std::sync::{Arc, Once, RwLock};
+use std::time::Duration;
 use stderrlog;
 
 use crate::chain::Network;
-use crate::daemon::CookieGetter;
+use crate::daemon::{CookieGetter, DaemonAddr};
 use crate::errors::*;
+use crate::new_index::checksum::ChecksumMode;
+use crate::new_index::db::WalMode;
+use crate::util::address_to_script;
+use crate::util::amount::AmountUnit;
+use crate::util::memory::{MemoryAllocation, MemoryRatios};
 
 #[cfg(feature = "liquid")]
 use bitcoin::Network as BNetwork;
@@ -18,6 +28,16 @@ pub(crate) const APP_NAME: &str = "flokicoin-electrs";
 pub(crate) const ELECTRS_VERSION: &str = env!("CARGO_PKG_VERSION");
 pub(crate) const GIT_HASH: Option<&str> = option_env!("GIT_HASH");
 
+// the optional cargo features the binary was built with
+pub(crate) const FEATURES: &[(&str, bool)] = &[
+    ("liquid", cfg!(feature = "liquid")),
+    ("electrum-discovery", cfg!(feature = "electrum-discovery")),
+    ("tls", cfg!(feature = "tls")),
+    ("otlp-tracing", cfg!(feature = "otlp-tracing")),
+    ("jemalloc", cfg!(feature = "jemalloc")),
+    ("mimalloc", cfg!(feature = "mimalloc")),
+];
+
 lazy_static! {
     pub(crate) static ref VERSION_STRING: String = {
         if let Some(hash) = GIT_HASH {
@@ -33,14 +53,23 @@ pub struct Config {
     // See below for the documentation of each field:
     pub log: stderrlog::StdErrLog,
     pub network_type: Network,
+    pub network_name: String,
     pub db_path: PathBuf,
     pub daemon_dir: PathBuf,
     pub blocks_dir: PathBuf,
-    pub daemon_rpc_addr: SocketAddr,
+    pub daemon_rpc_addr: DaemonAddr,
     pub daemon_parallelism: usize,
+    pub daemon_rpc_batch_size: usize,
     pub cookie: Option<String>,
     pub electrum_rpc_addr: SocketAddr,
+    /// An additional IPv6-only Electrum RPC listener, for dual-stack setups
+    pub electrum_rpc_addr_v6: Option<SocketAddr>,
     pub http_addr: SocketAddr,
+    /// An additional IPv6-only HTTP listener, for dual-stack setups
+    pub http_addr_v6: Option<SocketAddr>,
+    /// An additional HTTP listener for the internal services, whose requests aren't queued
+    /// behind the public ones
+    pub http_internal_addr: Option<SocketAddr>,
     pub http_socket_file: Option<PathBuf>,
     pub rpc_socket_file: Option<PathBuf>,
     pub monitoring_addr: SocketAddr,
@@ -49,21 +78,75 @@ pub struct Config {
     pub main_loop_delay: u64,
     pub address_search: bool,
     pub index_unspendables: bool,
+    pub utxo_age_stats: bool,
+    pub reorg_check_depth: usize,
+    pub index_dedup_window: usize,
+    pub history_audit_rows: usize,
+    pub assume_valid_height: Option<usize>,
+    pub aggregate_cache_mb: usize,
+    pub ledger_cache_mb: usize,
+    pub existence_filter_mb: usize,
+    pub burn_addresses: Vec<String>,
     pub cors: Option<String>,
     pub precache_scripts: Option<String>,
     pub precache_threads: usize,
+    /// The sizes of the thread pools of `util::pool`
+    pub query_threads: usize,
+    pub index_threads: usize,
+    pub notify_threads: usize,
     pub utxos_limit: usize,
     pub electrum_txs_limit: usize,
+    pub electrum_utxos_limit: usize,
+    pub electrum_history_page_size: usize,
+    pub electrum_max_block_size: usize,
     pub electrum_banner: String,
     pub mempool_backlog_stats_ttl: u64,
     pub mempool_recent_txs_size: usize,
+    /// How long lokid keeps unconfirmed transactions for (its -mempoolexpiry)
+    pub mempool_expiry: Duration,
+    pub mempool_event_retention: Duration,
     pub rest_default_block_limit: usize,
     pub rest_default_chain_txs_per_page: usize,
     pub rest_default_max_mempool_txs: usize,
     pub rest_default_max_address_summary_txs: usize,
     pub rest_max_mempool_page_size: usize,
     pub rest_max_mempool_txid_page_size: usize,
+    pub rest_max_export_blocks: usize,
+    pub rest_default_unit: AmountUnit,
+    pub json_amounts_as_strings: bool,
+    pub rest_access_log: bool,
+    pub rest_proxy_protocol: bool,
+    pub rest_disabled_endpoints: Vec<EndpointGroup>,
+    pub electrum_proxy_protocol: bool,
+    pub electrum_idle_timeout: Option<Duration>,
+    pub electrum_keepalive: Option<Duration>,
+    pub electrum_max_lifetime: Option<Duration>,
+    pub electrum_max_connections: usize,
+    pub rate_limit: f64,
+    pub rate_limit_burst: u32,
+    pub electrum_ban_threshold: u32,
+    pub electrum_ban_window: Duration,
+    pub electrum_ban_duration: Duration,
+    pub electrum_client_stats: Option<PathBuf>,
+    pub slow_query_threshold: Option<Duration>,
+    pub debug_token: Option<String>,
+    pub internal_api_key: Option<String>,
+    pub rest_public_concurrency: usize,
+    /// Serve the read-only lokid RPCs of `daemon::PASSTHROUGH_METHODS` under `/daemon/:method`
+    pub daemon_passthrough: bool,
+    /// The URL template of the fiat price source, see `util::price`
+    pub price_source: Option<String>,
+    pub price_currency: String,
+    pub price_refresh: Duration,
+    /// The SOCKS5 proxy the outbound connections go through, see `util::socks`
+    pub socks_proxy: Option<SocketAddr>,
+    pub blocklist: Option<PathBuf>,
     pub rpc_logging: RpcLogging,
+    pub log_level: log::LevelFilter,
+    pub fee_estimates_ttl: u64,
+    pub runtime_conf: Option<PathBuf>,
+    /// The current values of the options that can be reloaded at runtime (see `RuntimeConfig`)
+    pub runtime: Arc<RwLock<Arc<RuntimeConfig>>>,
     pub zmq_addr: Option<SocketAddr>,
 
     /// Enable compaction during initial sync
@@ -89,6 +172,12 @@ pub struct Config {
     /// Total RAM usage = write_buffer_size * max_write_buffer_number * 3_databases
     /// Larger buffers = fewer flushes (less CPU) but more RAM usage
     pub db_write_buffer_size_mb: usize,
+    /// The `--memory-budget-mb`, which also sets the number of write buffers it was split for
+    pub memory_budget_mb: Option<usize>,
+    pub min_free_disk_mb: u64,
+    pub txstore_checksums: ChecksumMode,
+    pub wal_mode: WalMode,
+    pub run_mode: RunMode,
 
     #[cfg(feature = "liquid")]
     pub parent_network: BNetwork,
@@ -97,10 +186,218 @@ pub struct Config {
 
     #[cfg(feature = "electrum-discovery")]
     pub electrum_public_hosts: Option<crate::electrum::ServerHosts>,
+    /// The onion service hostname and port the Electrum server is reachable at, advertised
+    /// in `server.features` next to the public hosts
+    #[cfg(feature = "electrum-discovery")]
+    pub electrum_onion_host: Option<(String, u16)>,
     #[cfg(feature = "electrum-discovery")]
     pub electrum_announce: bool,
     #[cfg(feature = "electrum-discovery")]
+    pub electrum_seed_peers: Vec<String>,
+    #[cfg(feature = "electrum-discovery")]
     pub tor_proxy: Option<std::net::SocketAddr>,
+
+    #[cfg(feature = "tls")]
+    pub rest_tls_cert: Option<PathBuf>,
+    #[cfg(feature = "tls")]
+    pub rest_tls_key: Option<PathBuf>,
+    #[cfg(feature = "tls")]
+    pub rest_acme_domains: Vec<String>,
+    #[cfg(feature = "tls")]
+    pub rest_acme_email: Option<String>,
+    #[cfg(feature = "tls")]
+    pub rest_acme_directory: String,
+    #[cfg(feature = "tls")]
+    pub rest_acme_dir: PathBuf,
+    #[cfg(feature = "tls")]
+    pub rest_acme_challenge_addr: std::net::SocketAddr,
+}
+
+const ENV_PREFIX: &str = "FLOKICOIN_ELECTRS_";
+
+static INIT_LOG: Once = Once::new();
+
+/// The options of a TOML configuration file as strings, keyed by the long option name
+struct ConfFile {
+    options: BTreeMap<String, String>,
+    // the options of each `[networks.<name>]` table, served by the same process
+    networks: BTreeMap<String, BTreeMap<String, String>>,
+}
+
+/// Parses the command line arguments, merged with `options` (set via environment variables
+/// and the `--conf` file, keyed by the long option name). The options are added as if they
+/// were given on the command line, unless they already were, so they go through the same
+/// validation and defaults.
+fn get_matches<'a, 'b>(
+    app: App<'a, 'b>,
+    cli_matches: &ArgMatches,
+    mut args: Vec<OsString>,
+    options: BTreeMap<String, String>,
+) -> ArgMatches<'a> {
+    for (option, value) in options {
+        let given = match option.as_str() {
+            "verbosity" => cli_matches.occurrences_of("verbosity") > 0,
+            long => given_on_cli(&args, long),
+        };
+        if given {
+            continue;
+        }
+        match (option.as_str(), value.as_str()) {
+            ("verbosity", count) => {
+                let count: usize = count.parse().expect("invalid verbosity");
+                args.extend((0..count).map(|_| OsString::from("-v")));
+            }
+            (_, "true") => args.push(format!("--{}", option).into()),
+            (_, "false") => (),
+            (_, value) => args.push(format!("--{}={}", option, value).into()),
+        }
+    }
+    app.get_matches_from(args)
+}
+
+// Whether the option was given on the command line, by its long name (as `--name value` or
+// `--name=value`)
+fn given_on_cli(args: &[OsString], long: &str) -> bool {
+    let flag = format!("--{}", long);
+    args.iter().filter_map(|arg| arg.to_str()).any(|arg| {
+        arg == flag
+            || arg
+                .strip_prefix(&flag)
+                .map_or(false, |rest| rest.starts_with('='))
+    })
+}
+
+// Drops the option from the command line arguments, for the value set in a network table to
+// take precedence. `-v` counts as the `verbosity` option.
+fn remove_from_cli(args: &mut Vec<OsString>, long: &str, takes_value: bool) {
+    if long == "verbosity" {
+        args.retain(|arg| {
+            arg.to_str().map_or(true, |arg| {
+                !(arg.len() > 1 && arg.starts_with('-') && arg[1..].bytes().all(|b| b == b'v'))
+            })
+        });
+        return;
+    }
+    let flag = format!("--{}", long);
+    let mut i = 1; // the program name
+    while i < args.len() {
+        let len = match args[i].to_str() {
+            Some(arg) if arg == flag && takes_value => 2,
+            Some(arg) if arg == flag => 1,
+            Some(arg) if arg.starts_with(&format!("{}=", flag)) => 1,
+            _ => 0,
+        };
+        if len == 0 {
+            i += 1;
+        } else {
+            args.drain(i..(i + len).min(args.len()));
+        }
+    }
+}
+
+// The logging options can't differ between the network tables, as there is a single logger
+fn check_log_options(networks: &BTreeMap<String, BTreeMap<String, String>>) {
+    for option in &["verbosity", "timestamp"] {
+        let values: HashSet<Option<&String>> = networks
+            .values()
+            .map(|options| options.get(*option))
+            .collect();
+        if values.len() > 1 {
+            clap::Error::with_description(
+                &format!(
+                    "the {} option differs between the network tables, it must be the same for all the networks",
+                    option
+                ),
+                clap::ErrorKind::ArgumentConflict,
+            )
+            .exit()
+        }
+    }
+}
+
+// Whether `long` is the long name of one of the options of the app
+fn is_option(app: &App, long: &str) -> bool {
+    if long == "verbosity" {
+        return true;
+    }
+    let probe = vec![
+        OsString::from(APP_NAME),
+        OsString::from(format!("--{}", long)),
+    ];
+    match app.clone().get_matches_from_safe(probe) {
+        Err(e) => e.kind != clap::ErrorKind::UnknownArgument,
+        Ok(_) => true,
+    }
+}
+
+// The options set via FLOKICOIN_ELECTRS_<OPTION> environment variables, along with the
+// variables that don't name an option, which are ignored
+fn env_options(app: &App) -> (BTreeMap<String, String>, Vec<String>) {
+    let mut unknown = vec![];
+    let options = env::vars_os()
+        .filter_map(|(key, value)| {
+            let key = key.into_string().ok()?;
+            let option = key.strip_prefix(ENV_PREFIX)?;
+            if option == "CONF" {
+                return None;
+            }
+            let option = option.to_lowercase().replace('_', "-");
+            if !is_option(app, &option) {
+                unknown.push(key);
+                return None;
+            }
+            Some((option, value.into_string().ok()?))
+        })
+        .collect();
+    (options, unknown)
+}
+
+fn read_conf_file(path: &Path) -> ConfFile {
+    let contents = fs::read_to_string(path)
+        .unwrap_or_else(|e| panic!("failed reading {}: {}", path.display(), e));
+    let mut table: toml::Table = toml::from_str(&contents)
+        .unwrap_or_else(|e| panic!("invalid configuration file {}: {}", path.display(), e));
+
+    let networks = match table.remove("networks") {
+        Some(toml::Value::Table(networks)) => networks
+            .iter()
+            .map(|(name, options)| match options {
+                toml::Value::Table(options) => (name.clone(), table_options(options, path)),
+                _ => panic!("networks.{} in {} is not a table", name, path.display()),
+            })
+            .collect(),
+        Some(_) => panic!("networks in {} is not a table", path.display()),
+        None => BTreeMap::new(),
+    };
+    ConfFile {
+        options: table_options(&table, path),
+        networks,
+    }
+}
+
+fn table_options(table: &toml::Table, path: &Path) -> BTreeMap<String, String> {
+    let to_string = |key: &str, value: &toml::Value| match value {
+        toml::Value::String(s) => s.clone(),
+        toml::Value::Integer(i) => i.to_string(),
+        toml::Value::Float(f) => f.to_string(),
+        toml::Value::Boolean(b) => b.to_string(),
+        _ => panic!("unsupported value for {} in {}", key, path.display()),
+    };
+    table
+        .iter()
+        .map(|(key, value)| {
+            let value = match value {
+                // lists are passed as comma-separated values (e.g. burn-addresses)
+                toml::Value::Array(items) => items
+                    .iter()
+                    .map(|item| to_string(key, item))
+                    .collect::<Vec<_>>()
+                    .join(","),
+                value => to_string(key, value),
+            };
+            (key.replace('_', "-"), value)
+        })
+        .collect()
 }
 
 fn str_to_socketaddr(address: &str, what: &str) -> SocketAddr {
@@ -112,8 +409,62 @@ fn str_to_socketaddr(address: &str, what: &str) -> SocketAddr {
         .unwrap()
 }
 
+fn str_to_socketaddr_v6(address: &str, what: &str) -> SocketAddr {
+    let addr = str_to_socketaddr(address, what);
+    if !addr.is_ipv6() {
+        panic!("{} address {} is not an IPv6 address", what, addr);
+    }
+    addr
+}
+
+// The Electrum client stats file set for several networks is split into one per network, named
+// after it (`stats.json` becoming `stats-mainnet.json`), as their clients are counted separately
+fn separate_client_stats(configs: &mut [Config]) {
+    let paths: Vec<Option<PathBuf>> = configs
+        .iter()
+        .map(|config| config.electrum_client_stats.clone())
+        .collect();
+    for config in configs.iter_mut() {
+        let path = match config.electrum_client_stats {
+            Some(ref path) if paths.iter().flatten().filter(|p| *p == path).count() > 1 => path,
+            _ => continue,
+        };
+        let stem = path
+            .file_stem()
+            .map_or_else(String::new, |stem| stem.to_string_lossy().into_owned());
+        let file_name = match path.extension() {
+            Some(ext) => format!("{}-{}.{}", stem, config.network_name, ext.to_string_lossy()),
+            None => format!("{}-{}", stem, config.network_name),
+        };
+        config.electrum_client_stats = Some(path.with_file_name(file_name));
+    }
+}
+
+// A number of seconds, with 0 standing for disabled
+fn secs_or_disabled(secs: u64) -> Option<Duration> {
+    (secs > 0).then(|| Duration::from_secs(secs))
+}
+
 impl Config {
+    /// Parses the configuration of the (first) network to serve
     pub fn from_args() -> Config {
+        Config::from_args_multi().remove(0)
+    }
+
+    /// Parses the configuration of every network to serve: one for each `[networks.<name>]`
+    /// table of the `--conf` file, or a single one when there are none.
+    pub fn from_args_multi() -> Vec<Config> {
+        Config::from_arg_list_multi(env::args_os().collect())
+    }
+
+    /// Parses the configuration from the given arguments instead of the command line, for the
+    /// benchmarks (the first argument is the program name)
+    #[cfg(feature = "bench")]
+    pub fn from_arg_list(args: &[&str]) -> Config {
+        Config::from_arg_list_multi(args.iter().map(OsString::from).collect()).remove(0)
+    }
+
+    fn from_arg_list_multi(cli_args: Vec<OsString>) -> Vec<Config> {
         let network_help = format!("Select network type ({})", Network::names().join(", "));
 
         let args = App::new("Electrum Rust Server")
@@ -129,6 +480,12 @@ impl Config {
                     .long("timestamp")
                     .help("Prepend log lines with a timestamp"),
             )
+            .arg(
+                Arg::with_name("conf")
+                    .long("conf")
+                    .help("Path to a TOML configuration file, with keys named after the command line options. Overridden by FLOKICOIN_ELECTRS_<OPTION> environment variables, which are overridden by command line arguments.")
+                    .takes_value(true),
+            )
             .arg(
                 Arg::with_name("db_dir")
                     .long("db-dir")
@@ -171,10 +528,28 @@ impl Config {
                     .help("HTTP server 'addr:port' to listen on (default: '127.0.0.1:3000' for mainnet, '127.0.0.1:3001' for testnet3 and '127.0.0.1:3004' for testnet4 and '127.0.0.1:3002' for regtest)")
                     .takes_value(true),
             )
+            .arg(
+                Arg::with_name("electrum_rpc_addr_v6")
+                    .long("electrum-rpc-addr-v6")
+                    .help("IPv6 '[addr]:port' to also listen on for Electrum RPC, IPv6 only (so that it can share its port with an IPv4 --electrum-rpc-addr)")
+                    .takes_value(true),
+            )
+            .arg(
+                Arg::with_name("http_addr_v6")
+                    .long("http-addr-v6")
+                    .help("IPv6 '[addr]:port' to also listen on for HTTP, IPv6 only (so that it can share its port with an IPv4 --http-addr)")
+                    .takes_value(true),
+            )
+            .arg(
+                Arg::with_name("http_internal_addr")
+                    .long("http-internal-addr")
+                    .help("HTTP 'addr:port' to also listen on for the internal services, whose requests are never queued behind the public ones nor rate limited (not available with --http-socket-file)")
+                    .takes_value(true),
+            )
             .arg(
                 Arg::with_name("daemon_rpc_addr")
                     .long("daemon-rpc-addr")
-                    .help("Flokicoin daemon JSONRPC 'addr:port' to connect (default: 127.0.0.1:8332 for mainnet, 127.0.0.1:18332 for testnet3, 127.0.0.1:48332 for testnet4, and 127.0.0.1:18443 for regtest)")
+                    .help("Flokicoin daemon JSONRPC 'addr:port' to connect, or 'host:port' to fail over between the addresses of the host, or 'srv:<name>' to fail over between the targets of a DNS SRV record, resolved again every minute (default: 127.0.0.1:8332 for mainnet, 127.0.0.1:18332 for testnet3, 127.0.0.1:48332 for testnet4, and 127.0.0.1:18443 for regtest)")
                     .takes_value(true),
             )
             .arg(
@@ -183,6 +558,12 @@ impl Config {
                     .help("Number of JSONRPC requests to send in parallel")
                     .default_value("4")
             )
+            .arg(
+                Arg::with_name("daemon_rpc_batch_size")
+                    .long("daemon-rpc-batch-size")
+                    .help("Number of JSONRPC requests sent together as one batch over each of the --daemon-parallelism connections (1 disables batching)")
+                    .default_value("50")
+            )
             .arg(
                 Arg::with_name("monitoring_addr")
                     .long("monitoring-addr")
@@ -215,6 +596,59 @@ impl Config {
                     .long("index-unspendables")
                     .help("Enable indexing of provably unspendable outputs")
             )
+            .arg(
+                Arg::with_name("utxo_age_stats")
+                    .long("utxo-age-stats")
+                    .help("Track the creation height of spent outputs while indexing, needed for the utxo age distribution (slower indexing)")
+            )
+            .arg(
+                Arg::with_name("reorg_check_depth")
+                    .long("reorg-check-depth")
+                    .help("Run an index consistency check (removing history rows left by orphaned blocks) after reorgs deeper than this number of blocks")
+                    .default_value("6")
+            )
+            .arg(
+                Arg::with_name("index_dedup_window")
+                    .long("index-dedup-window")
+                    .help("Number of the last rows written by the indexer to each db that aren't written again at the same value (0 to disable)")
+                    .default_value("100000")
+            )
+            .arg(
+                Arg::with_name("history_audit_rows")
+                    .long("history-audit-rows")
+                    .help("Number of random history rows verified against their stored transaction every minute, while the queries are idle. Set to 0 to disable")
+                    .default_value("20")
+            )
+            .arg(
+                Arg::with_name("assume_valid_height")
+                    .long("assume-valid-height")
+                    .help("Validate the block headers (proof-of-work, difficulty and timestamps) from this height on, trusting lokid for the ones below it (0 validates them all). Disabled by default")
+                    .takes_value(true)
+            )
+            .arg(
+                Arg::with_name("aggregate_cache_mb")
+                    .long("aggregate-cache-mb")
+                    .help("Maximum size in MB of the cached chain-wide aggregates (the supply, burns and /stats endpoints) and address summaries, kept until the next block. Set to 0 to disable")
+                    .default_value("32")
+            )
+            .arg(
+                Arg::with_name("ledger_cache_mb")
+                    .long("ledger-cache-mb")
+                    .help("Maximum size in MB of the address ledgers kept in the database, beyond which the least recently used ones are dropped (and rebuilt with new sequence numbers on their next request). Set to 0 for no limit")
+                    .default_value("256")
+            )
+            .arg(
+                Arg::with_name("existence_filter_mb")
+                    .long("existence-filter-mb")
+                    .help("Size in MB of the in-memory filter of the scripthashes with confirmed history, answering the lookups of unused addresses without touching the database (about 1% of false positives with 1MB per 800k scripthashes). Set to 0 to disable")
+                    .default_value("0")
+            )
+            .arg(
+                Arg::with_name("burn_addresses")
+                    .long("burn-addresses")
+                    .help("Comma-separated list of burn addresses to index alongside provably unspendable outputs (changing it requires a reindex)")
+                    .takes_value(true)
+            )
             .arg(
                 Arg::with_name("cors")
                     .long("cors")
@@ -233,6 +667,24 @@ impl Config {
                     .help("Non-zero number of threads to use for precache threadpool. [default: 4 * CORE_COUNT]")
                     .takes_value(true)
             )
+            .arg(
+                Arg::with_name("query_threads")
+                    .long("query-threads")
+                    .help("Non-zero number of threads running the parallel lookups of the REST and Electrum requests. [default: CORE_COUNT]")
+                    .takes_value(true)
+            )
+            .arg(
+                Arg::with_name("index_threads")
+                    .long("index-threads")
+                    .help("Non-zero number of threads parsing and indexing the new blocks. [default: CORE_COUNT]")
+                    .takes_value(true)
+            )
+            .arg(
+                Arg::with_name("notify_threads")
+                    .long("notify-threads")
+                    .help("Non-zero number of threads refreshing the Electrum subscriptions after the chain or the mempool changed. [default: CORE_COUNT]")
+                    .takes_value(true)
+            )
             .arg(
                 Arg::with_name("utxos_limit")
                     .long("utxos-limit")
@@ -251,6 +703,18 @@ impl Config {
                     .help("The number of transactions that mempool will keep in its recents queue. This is returned by mempool/recent endpoint.")
                     .default_value("10")
             )
+            .arg(
+                Arg::with_name("mempool_expiry")
+                    .long("mempool-expiry")
+                    .help("The number of hours lokid keeps unconfirmed transactions for (its -mempoolexpiry), used to tell expired transactions from evicted ones")
+                    .default_value("336")
+            )
+            .arg(
+                Arg::with_name("mempool_event_retention")
+                    .long("mempool-event-retention")
+                    .help("The number of seconds the mempool transactions added and removed are remembered for, per scripthash, for the clients catching up with /address/:address/events (0 to disable)")
+                    .default_value("300")
+            )
             .arg(
                 Arg::with_name("rest_default_block_limit")
                     .long("rest-default-block-limit")
@@ -269,6 +733,160 @@ impl Config {
                     .help("The default number of mempool transactions returned by the txs endpoints.")
                     .default_value("50")
             )
+            .arg(
+                Arg::with_name("rest_default_unit")
+                    .long("rest-default-unit")
+                    .help("The default unit of the amounts returned by the REST API, can be overridden per request with ?unit=")
+                    .possible_values(&["sat", "flc"])
+                    .default_value("sat")
+            )
+            .arg(
+                Arg::with_name("json_amounts_as_strings")
+                    .long("json-amounts-as-strings")
+                    .help("Render the amounts returned by the REST API as strings, for lossless handling in JavaScript clients")
+            )
+            .arg(
+                Arg::with_name("rest_access_log")
+                    .long("rest-access-log")
+                    .help("Print a structured (JSON) access log line to stdout for every REST request")
+            )
+            .arg(
+                Arg::with_name("rest_proxy_protocol")
+                    .long("rest-proxy-protocol")
+                    .help("Expect a PROXY protocol (v1 or v2) header on REST connections, and use the client address it holds")
+            )
+            .arg(
+                Arg::with_name("rest_disable_endpoints")
+                    .long("rest-disable-endpoints")
+                    .help("Comma-separated groups of expensive REST endpoints to disable, responding with a 403: export (the address history and block exports), bulk (the multi-address and multi-transaction lookups), ledger (the address ledgers), stats (the chain, supply and mining statistics) and search (the search and address prefix lookups)")
+                    .takes_value(true)
+                    .use_delimiter(true)
+                    .possible_values(&["export", "bulk", "ledger", "stats", "search"])
+            )
+            .arg(
+                Arg::with_name("electrum_proxy_protocol")
+                    .long("electrum-proxy-protocol")
+                    .help("Expect a PROXY protocol (v1 or v2) header on Electrum RPC connections, and use the client address it holds")
+            )
+            .arg(
+                Arg::with_name("electrum_idle_timeout")
+                    .long("electrum-idle-timeout")
+                    .help("Close Electrum RPC connections that sent no request for this many seconds. 0 to disable")
+                    .default_value("0")
+            )
+            .arg(
+                Arg::with_name("electrum_keepalive")
+                    .long("electrum-keepalive")
+                    .help("Send TCP keepalive probes on Electrum RPC connections idle for this many seconds, to detect and close half-open connections. 0 to disable")
+                    .default_value("300")
+            )
+            .arg(
+                Arg::with_name("electrum_max_lifetime")
+                    .long("electrum-max-lifetime")
+                    .help("Close Electrum RPC connections once they have been open for this many seconds. 0 to disable")
+                    .default_value("0")
+            )
+            .arg(
+                Arg::with_name("electrum_max_connections")
+                    .long("electrum-max-connections")
+                    .help("Maximum number of concurrent Electrum RPC connections, each served by its own threads. The connections beyond it are closed right away")
+                    .default_value("2000")
+            )
+            .arg(
+                Arg::with_name("rate_limit")
+                    .long("rate-limit")
+                    .help("Maximum sustained number of REST and Electrum RPC requests per second per client (IPv4 address, IPv6 /64 or Tor circuit). 0 to disable")
+                    .default_value("0")
+            )
+            .arg(
+                Arg::with_name("rate_limit_burst")
+                    .long("rate-limit-burst")
+                    .help("Number of requests a client can make at once before --rate-limit applies")
+                    .default_value("50")
+            )
+            .arg(
+                Arg::with_name("electrum_ban_threshold")
+                    .long("electrum-ban-threshold")
+                    .help("Temporarily ban the Electrum RPC clients that made this many invalid requests (invalid JSON, malformed JSON-RPC requests, unknown methods and invalid params) within --electrum-ban-window. 0 to disable")
+                    .default_value("0")
+            )
+            .arg(
+                Arg::with_name("electrum_ban_window")
+                    .long("electrum-ban-window")
+                    .help("The number of seconds the invalid requests of Electrum RPC clients are counted over for --electrum-ban-threshold")
+                    .default_value("60")
+            )
+            .arg(
+                Arg::with_name("electrum_ban_duration")
+                    .long("electrum-ban-duration")
+                    .help("The number of seconds Electrum RPC clients are banned for once they reach --electrum-ban-threshold")
+                    .default_value("3600")
+            )
+            .arg(
+                Arg::with_name("electrum_client_stats")
+                    .long("electrum-client-stats-file")
+                    .help("Path to a JSON file to persist the per-client Electrum RPC counters and bans to, so that they survive restarts. Set for several networks, one file per network is used, named after it")
+                    .takes_value(true)
+            )
+            .arg(
+                Arg::with_name("slow_query_threshold_ms")
+                    .long("slow-query-threshold-ms")
+                    .help("Log REST and Electrum RPC requests that take longer than this many milliseconds to handle, along with the database reads they made")
+                    .takes_value(true)
+            )
+            .arg(
+                Arg::with_name("debug_token")
+                    .long("debug-token")
+                    .help("Enable the /internal/debug REST endpoints, for requests authenticated with an 'Authorization: Bearer <token>' header")
+                    .takes_value(true)
+            )
+            .arg(
+                Arg::with_name("internal_api_key")
+                    .long("internal-api-key")
+                    .help("Treat the REST requests with an 'X-Api-Key: <key>' header like the ones received on --http-internal-addr")
+                    .takes_value(true)
+            )
+            .arg(
+                Arg::with_name("rest_public_concurrency")
+                    .long("rest-public-concurrency")
+                    .help("Non-zero number of public REST requests to handle at once, the others waiting for their turn. The internal requests don't count towards it. [default: CORE_COUNT]")
+                    .takes_value(true)
+            )
+            .arg(
+                Arg::with_name("daemon_passthrough")
+                    .long("daemon-passthrough")
+                    .help("Enable the /daemon/:method REST endpoints, returning the (cached) result of a few read-only lokid RPCs like getblockchaininfo")
+            )
+            .arg(
+                Arg::with_name("price_source")
+                    .long("price-source")
+                    .help("The http:// URL of a price source answering with the price of 1 FLC (as a JSON number or a `price` field), whose {currency} is replaced with --price-currency. Adds fiat values to the transaction and address REST responses (default: disabled)")
+                    .takes_value(true)
+            )
+            .arg(
+                Arg::with_name("price_currency")
+                    .long("price-currency")
+                    .help("The currency of the fiat values")
+                    .default_value("USD")
+            )
+            .arg(
+                Arg::with_name("price_refresh_secs")
+                    .long("price-refresh-secs")
+                    .help("The number of seconds between the fetches of the fiat price")
+                    .default_value("300")
+            )
+            .arg(
+                Arg::with_name("socks_proxy")
+                    .long("socks-proxy")
+                    .help("ip:port of a SOCKS5 proxy (like Tor) to route the outbound connections through: the price source and, with the electrum-discovery feature, the Electrum peers")
+                    .takes_value(true)
+            )
+            .arg(
+                Arg::with_name("blocklist")
+                    .long("blocklist")
+                    .help("Path to a file of scripthashes (one per line) that are refused by the address endpoints and whose transactions are tagged as blocked. Reloaded on SIGHUP.")
+                    .takes_value(true)
+            )
             .arg(
                 Arg::with_name("rest_default_max_address_summary_txs")
                     .long("rest-default-max-address-summary-txs")
@@ -287,16 +905,47 @@ impl Config {
                     .help("The maximum number of transactions returned by the paginated /mempool/txids/page endpoint.")
                     .default_value("10000")
             )
+            .arg(
+                Arg::with_name("rest_max_export_blocks")
+                    .long("rest-max-export-blocks")
+                    .help("The maximum number of blocks exported by a single /v1/export/blocks request.")
+                    .default_value("1000")
+            )
             .arg(
                 Arg::with_name("electrum_txs_limit")
                     .long("electrum-txs-limit")
-                    .help("Maximum number of transactions returned by Electrum history queries. Lookups with more results will fail.")
+                    .help("Maximum number of transactions returned by Electrum history queries. Lookups with more results will fail with the \"history too large\" error.")
                     .default_value("500")
+            ).arg(
+                Arg::with_name("electrum_utxos_limit")
+                    .long("electrum-utxos-limit")
+                    .help("Maximum number of utxos returned by Electrum blockchain.scripthash.listunspent queries. Lookups with more results will fail. [default: --utxos-limit]")
+                    .takes_value(true)
+            ).arg(
+                Arg::with_name("electrum_history_page_size")
+                    .long("electrum-history-page-size")
+                    .help("Number of transactions returned per page by the non-standard Electrum blockchain.scripthash.get_history_page method, and by default by blockchain.scripthash.get_history_paginated.")
+                    .default_value("100")
+            ).arg(
+                Arg::with_name("electrum_max_block_size")
+                    .long("electrum-max-block-size")
+                    .help("Enables the non-standard Electrum blockchain.block.get method, returning the raw blocks of up to this many bytes (0 to disable).")
+                    .default_value("0")
             ).arg(
                 Arg::with_name("electrum_banner")
                     .long("electrum-banner")
                     .help("Welcome banner for the Electrum server, shown in the console to clients.")
                     .takes_value(true)
+            ).arg(
+                Arg::with_name("fee_estimates_ttl")
+                    .long("fee-estimates-ttl")
+                    .help("Number of seconds to cache the fee estimates fetched from lokid for")
+                    .default_value("60")
+            ).arg(
+                Arg::with_name("runtime_conf")
+                    .long("runtime-conf")
+                    .help("Path to a JSON file with options to reload on SIGHUP or POST /internal/reload (cors, electrum_banner, log_level, fee_estimates_ttl, rate_limit, rate_limit_burst)")
+                    .takes_value(true)
             ).arg(
                 Arg::with_name("enable_json_rpc_logging")
                     .long("enable-json-rpc-logging")
@@ -334,10 +983,69 @@ impl Config {
                     .help("RocksDB write buffer size in MB per database. RAM usage = size * max_write_buffers(2) * 3_databases")
                     .takes_value(true)
                     .default_value("256")
+             ).arg(
+                Arg::with_name("memory_budget_mb")
+                    .long("memory-budget-mb")
+                    .help("Total memory in MB for the in-memory caches, taking out --existence-filter-mb and splitting the rest between the RocksDB block caches and write buffers of the 3 databases, setting --db-block-cache-mb and --db-write-buffer-size-mb (unless given explicitly)")
+                    .takes_value(true)
+             ).arg(
+                Arg::with_name("memory_budget_ratios")
+                    .long("memory-budget-ratios")
+                    .help("How to split --memory-budget-mb, as <block-cache>:<write-buffers> weights")
+                    .default_value("60:40")
+             ).arg(
+                Arg::with_name("min_free_disk_mb")
+                    .long("min-free-disk-mb")
+                    .help("Pause indexing and serve read-only while the database volume has less free space than this many MB. 0 to disable")
+                    .default_value("0")
+             ).arg(
+                Arg::with_name("txstore_checksums")
+                    .long("txstore-checksums")
+                    .help("What to do with the transaction and block rows failing checksum verification on read: off (don't verify), log (log and count them) or fail (refuse to serve them)")
+                    .possible_values(&["off", "log", "fail"])
+                    .default_value("log")
+             ).arg(
+                Arg::with_name("wal_mode")
+                    .long("wal-mode")
+                    .help("Which indexed blocks to write through the RocksDB write-ahead log: off (none, fastest), tip-only (the blocks within 100 of lokid's tip, skipping it for the bulk historical writes) or always (all of them, safest). The blocks written without it are flushed to disk at the end of each index update")
+                    .possible_values(&["off", "tip-only", "always"])
+                    .default_value("tip-only")
+             ).arg(
+                Arg::with_name("run_mode")
+                    .long("run-mode")
+                    .help("What to run: 'serve' to keep the index in sync and serve clients, 'reindex-range' to regenerate the index rows of the blocks from --from to --to (inclusive) and exit, 'db-stats' to report the rows and bytes used by each key prefix of the databases and exit, or 'dump-utxos' to write the utxo set as of --height to --out and exit")
+                    .possible_values(&["serve", "reindex-range", "db-stats", "dump-utxos"])
+                    .default_value("serve")
+                    .requires_ifs(&[
+                        ("reindex-range", "reindex_from"),
+                        ("reindex-range", "reindex_to"),
+                        ("dump-utxos", "dump_height"),
+                        ("dump-utxos", "dump_out"),
+                    ])
+             ).arg(
+                Arg::with_name("reindex_from")
+                    .long("from")
+                    .help("First height to reindex with --run-mode reindex-range")
+                    .takes_value(true)
+             ).arg(
+                Arg::with_name("reindex_to")
+                    .long("to")
+                    .help("Last height to reindex with --run-mode reindex-range")
+                    .takes_value(true)
+             ).arg(
+                Arg::with_name("dump_height")
+                    .long("height")
+                    .help("Height of the utxo set written with --run-mode dump-utxos")
+                    .takes_value(true)
+             ).arg(
+                Arg::with_name("dump_out")
+                    .long("out")
+                    .help("File the utxo set is written to with --run-mode dump-utxos")
+                    .takes_value(true)
              ).arg(
                 Arg::with_name("zmq_addr")
                     .long("zmq-addr")
-                    .help("Optional zmq socket address of the Lokid daemon")
+                    .help("Optional zmq socket address of the Lokid daemon, publishing hashblock (and sequence, which spares fetching an unchanged mempool)")
                     .takes_value(true),
             );
 
@@ -377,10 +1085,20 @@ impl Config {
                     .long("electrum-public-hosts")
                     .help("A dictionary of hosts where the Electrum server can be reached at. Required to enable server discovery. See https://electrumx.readthedocs.io/en/latest/protocol-methods.html#server-features")
                     .takes_value(true)
+            ).arg(
+                Arg::with_name("electrum_onion_host")
+                    .long("electrum-onion-host")
+                    .help("Onion service <hostname>[:<port>] the Electrum server can be reached at, advertised in server.features (port defaults to the one of --electrum-rpc-addr)")
+                    .takes_value(true)
             ).arg(
                 Arg::with_name("electrum_announce")
                     .long("electrum-announce")
                     .help("Announce the Electrum server to other servers")
+            ).arg(
+                Arg::with_name("electrum_seed_peers")
+                    .long("electrum-seed-peers")
+                    .help("Comma-separated Electrum servers to discover peers from, as <hostname>:<service>[:<service>] (e.g. electrum.example.com:t50001:s50002)")
+                    .takes_value(true)
             ).arg(
             Arg::with_name("tor_proxy")
                 .long("tor-proxy")
@@ -388,8 +1106,100 @@ impl Config {
                 .takes_value(true),
         );
 
-        let m = args.get_matches();
+        #[cfg(feature = "tls")]
+        let args = args
+            .arg(
+                Arg::with_name("rest_tls_cert")
+                    .long("rest-tls-cert")
+                    .help("PEM certificate chain to serve the REST API over TLS with (requires --rest-tls-key)")
+                    .takes_value(true),
+            )
+            .arg(
+                Arg::with_name("rest_tls_key")
+                    .long("rest-tls-key")
+                    .help("PEM private key of the --rest-tls-cert certificate")
+                    .takes_value(true),
+            )
+            .arg(
+                Arg::with_name("rest_acme_domains")
+                    .long("rest-acme-domains")
+                    .help("Comma-separated domains to provision a TLS certificate for with ACME, and serve the REST API over TLS with (instead of --rest-tls-cert)")
+                    .takes_value(true),
+            )
+            .arg(
+                Arg::with_name("rest_acme_email")
+                    .long("rest-acme-email")
+                    .help("Contact email of the ACME account, for expiration notices")
+                    .takes_value(true),
+            )
+            .arg(
+                Arg::with_name("rest_acme_directory")
+                    .long("rest-acme-directory")
+                    .help("ACME directory URL (use https://acme-staging-v02.api.letsencrypt.org/directory for testing)")
+                    .default_value("https://acme-v02.api.letsencrypt.org/directory"),
+            )
+            .arg(
+                Arg::with_name("rest_acme_dir")
+                    .long("rest-acme-dir")
+                    .help("Directory to store the ACME account and certificates in (default: <db-dir>/<network>/acme)")
+                    .takes_value(true),
+            )
+            .arg(
+                Arg::with_name("rest_acme_challenge_addr")
+                    .long("rest-acme-challenge-addr")
+                    .help("HTTP server address to answer the ACME HTTP-01 challenges on while ordering a certificate, must be reachable on port 80 of the domains")
+                    .default_value("0.0.0.0:80"),
+            );
+
+        let cli_matches = args.clone().get_matches_from(cli_args.clone());
+        let conf = cli_matches
+            .value_of("conf")
+            .map(PathBuf::from)
+            .or_else(|| env::var_os(format!("{}CONF", ENV_PREFIX)).map(PathBuf::from))
+            .map(|path| read_conf_file(&path));
+        let (options, networks) = match conf {
+            Some(conf) => (conf.options, conf.networks),
+            None => (BTreeMap::new(), BTreeMap::new()),
+        };
 
+        // environment variables override the configuration file, which are overridden by the
+        // command line arguments. The options of the network tables override all of them.
+        let (env_options, unknown_env) = env_options(&args);
+        let configs = if networks.is_empty() {
+            let mut options = options;
+            options.extend(env_options);
+            let m = get_matches(args, &cli_matches, cli_args, options);
+            vec![Config::from_matches(&m)]
+        } else {
+            check_log_options(&networks);
+            let mut configs: Vec<Config> = networks
+                .into_iter()
+                .map(|(name, mut network_options)| {
+                    network_options.insert("network".to_string(), name);
+                    let mut cli_args = cli_args.clone();
+                    for (option, value) in &network_options {
+                        let takes_value = value != "true" && value != "false";
+                        remove_from_cli(&mut cli_args, option, takes_value);
+                    }
+                    let cli_matches = args.clone().get_matches_from(cli_args.clone());
+                    let mut options = options.clone();
+                    options.extend(env_options.clone());
+                    options.extend(network_options);
+                    let m = get_matches(args.clone(), &cli_matches, cli_args, options);
+                    Config::from_matches(&m)
+                })
+                .collect();
+            separate_client_stats(&mut configs);
+            configs
+        };
+        // reported once the logger is set up
+        for key in unknown_env {
+            warn!("ignoring the {} environment variable: not an option", key);
+        }
+        configs
+    }
+
+    fn from_matches(m: &ArgMatches) -> Config {
         let network_name = m.value_of("network").unwrap_or("mainnet");
         let network_type = Network::from(network_name);
         let db_dir = Path::new(m.value_of("db_dir").unwrap_or("./db"));
@@ -483,11 +1293,11 @@ impl Config {
             Network::LiquidRegtest => 44224,
         };
 
-        let daemon_rpc_addr: SocketAddr = str_to_socketaddr(
-            m.value_of("daemon_rpc_addr")
-                .unwrap_or(&format!("127.0.0.1:{}", default_daemon_port)),
-            "Lokid RPC",
-        );
+        let daemon_rpc_addr = if m.is_present("daemon_rpc_addr") {
+            value_t_or_exit!(m, "daemon_rpc_addr", DaemonAddr)
+        } else {
+            DaemonAddr::Socket(([127, 0, 0, 1], default_daemon_port).into())
+        };
         let electrum_rpc_addr: SocketAddr = str_to_socketaddr(
             m.value_of("electrum_rpc_addr")
                 .unwrap_or(&format!("127.0.0.1:{}", default_electrum_port)),
@@ -498,6 +1308,15 @@ impl Config {
                 .unwrap_or(&format!("127.0.0.1:{}", default_http_port)),
             "HTTP Server",
         );
+        let electrum_rpc_addr_v6 = m
+            .value_of("electrum_rpc_addr_v6")
+            .map(|addr| str_to_socketaddr_v6(addr, "Electrum RPC IPv6"));
+        let http_addr_v6 = m
+            .value_of("http_addr_v6")
+            .map(|addr| str_to_socketaddr_v6(addr, "HTTP Server IPv6"));
+        let http_internal_addr = m
+            .value_of("http_internal_addr")
+            .map(|addr| str_to_socketaddr(addr, "Internal HTTP Server"));
         let zmq_addr: Option<SocketAddr> = m
             .value_of("zmq_addr")
             .map(|e| str_to_socketaddr(e, "ZMQ addr"));
@@ -530,6 +1349,27 @@ impl Config {
 
         let main_loop_delay = value_t_or_exit!(m, "main_loop_delay", u64);
 
+        // a non-zero count, defaulting to the number of cores
+        let count_or_cores = |name: &str| {
+            m.value_of(name).map_or_else(
+                || {
+                    std::thread::available_parallelism()
+                        .expect("Can't get core count")
+                        .get()
+                },
+                |s| match s.parse::<usize>() {
+                    Ok(v) if v > 0 => v,
+                    _ => clap::Error::value_validation_auto(format!(
+                        "The argument '{}' isn't a valid value",
+                        s
+                    ))
+                    .exit(),
+                },
+            )
+        };
+        let rest_public_concurrency = count_or_cores("rest_public_concurrency");
+        let utxos_limit = value_t_or_exit!(m, "utxos_limit", usize);
+
         let precache_threads = m.value_of("precache_threads").map_or_else(
             || {
                 std::thread::available_parallelism()
@@ -559,46 +1399,205 @@ impl Config {
         let rest_max_mempool_page_size = value_t_or_exit!(m, "rest_max_mempool_page_size", usize);
         let rest_max_mempool_txid_page_size =
             value_t_or_exit!(m, "rest_max_mempool_txid_page_size", usize);
+        let rest_default_unit = value_t_or_exit!(m, "rest_default_unit", AmountUnit);
 
         let electrum_banner = m
             .value_of("electrum_banner")
             .map_or_else(|| format!("Welcome to {}", *VERSION_STRING), |s| s.into());
 
+        let burn_addresses: Vec<String> = m.value_of("burn_addresses").map_or_else(Vec::new, |s| {
+            s.split(',').map(|addr| addr.trim().to_string()).collect()
+        });
+        for addr in &burn_addresses {
+            if let Err(e) = address_to_script(addr, network_type) {
+                clap::Error::value_validation_auto(format!("invalid burn address {}: {}", addr, e))
+                    .exit()
+            }
+        }
+
         #[cfg(feature = "electrum-discovery")]
         let electrum_public_hosts = m
             .value_of("electrum_public_hosts")
             .map(|s| serde_json::from_str(s).expect("invalid --electrum-public-hosts"));
 
+        #[cfg(feature = "tls")]
+        let rest_acme_dir = m
+            .value_of("rest_acme_dir")
+            .map_or_else(|| db_path.join("acme"), PathBuf::from);
+
+        #[cfg(feature = "electrum-discovery")]
+        let electrum_onion_host = m.value_of("electrum_onion_host").map(|s| {
+            let (host, port) = match s.rsplit_once(':') {
+                Some((host, port)) => (
+                    host,
+                    port.parse().expect("invalid --electrum-onion-host port"),
+                ),
+                None => (s, electrum_rpc_addr.port()),
+            };
+            if !host.ends_with(".onion") {
+                clap::Error::value_validation_auto(format!("{} is not an onion hostname", host))
+                    .exit()
+            }
+            (host.to_string(), port)
+        });
+
+        // the logger lets everything through, the actual level is set with `log::set_max_level`
+        // so that it can be changed at runtime
+        let log_level = match m.occurrences_of("verbosity") {
+            0 => log::LevelFilter::Error,
+            1 => log::LevelFilter::Warn,
+            2 => log::LevelFilter::Info,
+            3 => log::LevelFilter::Debug,
+            _ => log::LevelFilter::Trace,
+        };
         let mut log = stderrlog::new();
-        log.verbosity(m.occurrences_of("verbosity") as usize);
+        log.verbosity(4); // trace
         log.timestamp(if m.is_present("timestamp") {
             stderrlog::Timestamp::Millisecond
         } else {
             stderrlog::Timestamp::Off
         });
-        log.init().expect("logging initialization failed");
+        // the logger is shared by all the networks, and set up by the first one
+        INIT_LOG.call_once(|| {
+            log.init().expect("logging initialization failed");
+            log::set_max_level(log_level);
+        });
+
+        // the memory budget sets the sizes of the caches and buffers not given explicitly
+        let existence_filter_mb = value_t_or_exit!(m, "existence_filter_mb", usize);
+        let memory_budget_mb = m
+            .value_of("memory_budget_mb")
+            .map(|_| value_t_or_exit!(m, "memory_budget_mb", usize));
+        let memory_allocation = memory_budget_mb.map(|budget_mb| {
+            let ratios = value_t_or_exit!(m, "memory_budget_ratios", MemoryRatios);
+            MemoryAllocation::new(budget_mb, existence_filter_mb, &ratios)
+        });
+        let memory_option =
+            |name: &str, budgeted: fn(&MemoryAllocation) -> usize| match memory_allocation {
+                Some(ref allocation) if m.occurrences_of(name) == 0 => budgeted(allocation),
+                _ => value_t_or_exit!(m, name, usize),
+            };
+        let memory = MemoryAllocation {
+            app_caches_mb: existence_filter_mb,
+            db_block_cache_mb: memory_option("db_block_cache_mb", |a| a.db_block_cache_mb),
+            db_write_buffer_size_mb: memory_option("db_write_buffer_size_mb", |a| {
+                a.db_write_buffer_size_mb
+            }),
+        };
+        if memory_allocation.is_some() {
+            info!(
+                "memory budget: {} MB of application caches, {} MB of block cache and {} MB of write buffers per database ({} MB in total)",
+                memory.app_caches_mb,
+                memory.db_block_cache_mb,
+                memory.db_write_buffer_size_mb,
+                memory.total_mb()
+            );
+        }
+
+        let cors = m.value_of("cors").map(|s| s.to_string());
+        let fee_estimates_ttl = value_t_or_exit!(m, "fee_estimates_ttl", u64);
+        let rate_limit = value_t_or_exit!(m, "rate_limit", f64);
+        let rate_limit_burst = value_t_or_exit!(m, "rate_limit_burst", u32);
+        let runtime = RuntimeConfig {
+            cors: cors.clone(),
+            electrum_banner: electrum_banner.clone(),
+            log_level,
+            fee_estimates_ttl,
+            rate_limit,
+            rate_limit_burst,
+        };
 
         let config = Config {
             log,
             network_type,
+            network_name: network_name.to_string(),
             db_path,
             daemon_dir,
             blocks_dir,
             daemon_rpc_addr,
             daemon_parallelism: value_t_or_exit!(m, "daemon_parallelism", usize),
+            daemon_rpc_batch_size: value_t_or_exit!(m, "daemon_rpc_batch_size", usize).max(1),
             cookie,
-            utxos_limit: value_t_or_exit!(m, "utxos_limit", usize),
+            utxos_limit,
             electrum_rpc_addr,
+            electrum_rpc_addr_v6,
             electrum_txs_limit: value_t_or_exit!(m, "electrum_txs_limit", usize),
+            electrum_utxos_limit: if m.is_present("electrum_utxos_limit") {
+                value_t_or_exit!(m, "electrum_utxos_limit", usize)
+            } else {
+                utxos_limit
+            },
+            electrum_history_page_size: value_t_or_exit!(m, "electrum_history_page_size", usize)
+                .max(1),
+            electrum_max_block_size: value_t_or_exit!(m, "electrum_max_block_size", usize),
             electrum_banner,
             mempool_backlog_stats_ttl,
             mempool_recent_txs_size,
+            mempool_expiry: Duration::from_secs(value_t_or_exit!(m, "mempool_expiry", u64) * 3600),
+            mempool_event_retention: Duration::from_secs(value_t_or_exit!(
+                m,
+                "mempool_event_retention",
+                u64
+            )),
             rest_default_block_limit,
             rest_default_chain_txs_per_page,
             rest_default_max_mempool_txs,
             rest_default_max_address_summary_txs,
             rest_max_mempool_page_size,
             rest_max_mempool_txid_page_size,
+            rest_max_export_blocks: value_t_or_exit!(m, "rest_max_export_blocks", usize),
+            rest_default_unit,
+            json_amounts_as_strings: m.is_present("json_amounts_as_strings"),
+            rest_access_log: m.is_present("rest_access_log"),
+            rest_proxy_protocol: m.is_present("rest_proxy_protocol"),
+            rest_disabled_endpoints: if m.is_present("rest_disable_endpoints") {
+                values_t_or_exit!(m, "rest_disable_endpoints", EndpointGroup)
+            } else {
+                vec![]
+            },
+            electrum_proxy_protocol: m.is_present("electrum_proxy_protocol"),
+            electrum_idle_timeout: secs_or_disabled(value_t_or_exit!(
+                m,
+                "electrum_idle_timeout",
+                u64
+            )),
+            electrum_keepalive: secs_or_disabled(value_t_or_exit!(m, "electrum_keepalive", u64)),
+            electrum_max_lifetime: secs_or_disabled(value_t_or_exit!(
+                m,
+                "electrum_max_lifetime",
+                u64
+            )),
+            electrum_max_connections: value_t_or_exit!(m, "electrum_max_connections", usize),
+            rate_limit,
+            rate_limit_burst,
+            electrum_ban_threshold: value_t_or_exit!(m, "electrum_ban_threshold", u32),
+            electrum_ban_window: Duration::from_secs(value_t_or_exit!(
+                m,
+                "electrum_ban_window",
+                u64
+            )),
+            electrum_ban_duration: Duration::from_secs(value_t_or_exit!(
+                m,
+                "electrum_ban_duration",
+                u64
+            )),
+            electrum_client_stats: m.value_of("electrum_client_stats").map(PathBuf::from),
+            slow_query_threshold: m
+                .value_of("slow_query_threshold_ms")
+                .map(|ms| Duration::from_millis(ms.parse().expect("invalid slow query threshold"))),
+            debug_token: m.value_of("debug_token").map(str::to_string),
+            internal_api_key: m.value_of("internal_api_key").map(str::to_string),
+            rest_public_concurrency,
+            daemon_passthrough: m.is_present("daemon_passthrough"),
+            price_source: m.value_of("price_source").map(String::from),
+            price_currency: value_t_or_exit!(m, "price_currency", String),
+            price_refresh: Duration::from_secs(
+                value_t_or_exit!(m, "price_refresh_secs", u64).max(1),
+            ),
+            socks_proxy: m
+                .value_of("socks_proxy")
+                .map(|addr| str_to_socketaddr(addr, "SOCKS proxy")),
+            blocklist: m.value_of("blocklist").map(PathBuf::from),
             rpc_logging: {
                 let params = RpcLogging {
                     enabled: m.is_present("enable_json_rpc_logging"),
@@ -608,7 +1607,13 @@ impl Config {
                 params.validate();
                 params
             },
+            log_level,
+            fee_estimates_ttl,
+            runtime_conf: m.value_of("runtime_conf").map(PathBuf::from),
+            runtime: Arc::new(RwLock::new(Arc::new(runtime))),
             http_addr,
+            http_addr_v6,
+            http_internal_addr,
             http_socket_file,
             rpc_socket_file,
             monitoring_addr,
@@ -617,13 +1622,43 @@ impl Config {
             main_loop_delay,
             address_search: m.is_present("address_search"),
             index_unspendables: m.is_present("index_unspendables"),
-            cors: m.value_of("cors").map(|s| s.to_string()),
+            utxo_age_stats: m.is_present("utxo_age_stats"),
+            reorg_check_depth: value_t_or_exit!(m, "reorg_check_depth", usize),
+            index_dedup_window: value_t_or_exit!(m, "index_dedup_window", usize),
+            history_audit_rows: value_t_or_exit!(m, "history_audit_rows", usize),
+            assume_valid_height: m
+                .is_present("assume_valid_height")
+                .then(|| value_t_or_exit!(m, "assume_valid_height", usize)),
+            aggregate_cache_mb: value_t_or_exit!(m, "aggregate_cache_mb", usize),
+            ledger_cache_mb: value_t_or_exit!(m, "ledger_cache_mb", usize),
+            existence_filter_mb,
+            burn_addresses,
+            cors,
             precache_scripts: m.value_of("precache_scripts").map(|s| s.to_string()),
             precache_threads,
+            query_threads: count_or_cores("query_threads"),
+            index_threads: count_or_cores("index_threads"),
+            notify_threads: count_or_cores("notify_threads"),
             initial_sync_compaction: m.is_present("initial_sync_compaction"),
-            db_block_cache_mb: value_t_or_exit!(m, "db_block_cache_mb", usize),
+            db_block_cache_mb: memory.db_block_cache_mb,
             db_parallelism: value_t_or_exit!(m, "db_parallelism", usize),
-            db_write_buffer_size_mb: value_t_or_exit!(m, "db_write_buffer_size_mb", usize),
+            db_write_buffer_size_mb: memory.db_write_buffer_size_mb,
+            memory_budget_mb,
+            min_free_disk_mb: value_t_or_exit!(m, "min_free_disk_mb", u64),
+            txstore_checksums: value_t_or_exit!(m, "txstore_checksums", ChecksumMode),
+            wal_mode: value_t_or_exit!(m, "wal_mode", WalMode),
+            run_mode: match m.value_of("run_mode") {
+                Some("reindex-range") => RunMode::ReindexRange {
+                    from: value_t_or_exit!(m, "reindex_from", usize),
+                    to: value_t_or_exit!(m, "reindex_to", usize),
+                },
+                Some("db-stats") => RunMode::DbStats,
+                Some("dump-utxos") => RunMode::DumpUtxos {
+                    height: value_t_or_exit!(m, "dump_height", usize),
+                    out: PathBuf::from(m.value_of("dump_out").unwrap()),
+                },
+                _ => RunMode::Serve,
+            },
             zmq_addr,
 
             #[cfg(feature = "liquid")]
@@ -634,13 +1669,85 @@ impl Config {
             #[cfg(feature = "electrum-discovery")]
             electrum_public_hosts,
             #[cfg(feature = "electrum-discovery")]
+            electrum_onion_host,
+            #[cfg(feature = "electrum-discovery")]
             electrum_announce: m.is_present("electrum_announce"),
             #[cfg(feature = "electrum-discovery")]
+            electrum_seed_peers: m
+                .value_of("electrum_seed_peers")
+                .map_or_else(Vec::new, |s| {
+                    s.split(',').map(|peer| peer.trim().to_string()).collect()
+                }),
+            #[cfg(feature = "electrum-discovery")]
             tor_proxy: m.value_of("tor_proxy").map(|s| s.parse().unwrap()),
+
+            #[cfg(feature = "tls")]
+            rest_tls_cert: m.value_of("rest_tls_cert").map(PathBuf::from),
+            #[cfg(feature = "tls")]
+            rest_tls_key: m.value_of("rest_tls_key").map(PathBuf::from),
+            #[cfg(feature = "tls")]
+            rest_acme_domains: m.value_of("rest_acme_domains").map_or_else(Vec::new, |s| {
+                s.split(',')
+                    .map(|domain| domain.trim().to_string())
+                    .collect()
+            }),
+            #[cfg(feature = "tls")]
+            rest_acme_email: m.value_of("rest_acme_email").map(|s| s.to_string()),
+            #[cfg(feature = "tls")]
+            rest_acme_directory: m.value_of("rest_acme_directory").unwrap().to_string(),
+            #[cfg(feature = "tls")]
+            rest_acme_dir,
+            #[cfg(feature = "tls")]
+            rest_acme_challenge_addr: value_t_or_exit!(
+                m,
+                "rest_acme_challenge_addr",
+                std::net::SocketAddr
+            ),
         };
+        if config.runtime_conf.is_some() {
+            config.reload_runtime().expect("invalid --runtime-conf");
+        }
         config
     }
 
+    /// The current values of the runtime-reloadable options, swapped as a whole on reload
+    pub fn runtime(&self) -> Arc<RuntimeConfig> {
+        Arc::clone(&self.runtime.read().unwrap())
+    }
+
+    /// Reload the runtime options from the `--runtime-conf` file. Options missing from the
+    /// file keep the value they were given on the command line.
+    pub fn reload_runtime(&self) -> Result<()> {
+        let path = match self.runtime_conf {
+            Some(ref path) => path,
+            None => bail!("no --runtime-conf file to reload from"),
+        };
+        let contents =
+            fs::read_to_string(path).chain_err(|| format!("failed reading {}", path.display()))?;
+        let file: RuntimeConfigFile = serde_json::from_str(&contents)
+            .chain_err(|| format!("invalid runtime config file {}", path.display()))?;
+
+        let runtime = RuntimeConfig {
+            cors: file.cors.or_else(|| self.cors.clone()),
+            electrum_banner: file
+                .electrum_banner
+                .unwrap_or_else(|| self.electrum_banner.clone()),
+            log_level: match file.log_level {
+                Some(level) => level
+                    .parse()
+                    .chain_err(|| format!("invalid log level {}", level))?,
+                None => self.log_level,
+            },
+            fee_estimates_ttl: file.fee_estimates_ttl.unwrap_or(self.fee_estimates_ttl),
+            rate_limit: file.rate_limit.unwrap_or(self.rate_limit),
+            rate_limit_burst: file.rate_limit_burst.unwrap_or(self.rate_limit_burst),
+        };
+        log::set_max_level(runtime.log_level);
+        info!("reloaded runtime config: {:?}", runtime);
+        *self.runtime.write().unwrap() = Arc::new(runtime);
+        Ok(())
+    }
+
     pub fn cookie_getter(&self) -> Arc<dyn CookieGetter> {
         if let Some(ref value) = self.cookie {
             Arc::new(StaticCookie {
@@ -654,6 +1761,84 @@ impl Config {
     }
 }
 
+/// The subset of the configuration that can be reloaded without restarting (which would drop
+/// the Electrum subscriptions and cold the caches)
+#[derive(Debug, Clone)]
+pub struct RuntimeConfig {
+    pub cors: Option<String>,
+    pub electrum_banner: String,
+    pub log_level: log::LevelFilter,
+    pub fee_estimates_ttl: u64,
+    pub rate_limit: f64,
+    pub rate_limit_burst: u32,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RuntimeConfigFile {
+    cors: Option<String>,
+    electrum_banner: Option<String>,
+    log_level: Option<String>,
+    fee_estimates_ttl: Option<u64>,
+    rate_limit: Option<f64>,
+    rate_limit_burst: Option<u32>,
+}
+
+/// What electrs does once started, see `--run-mode`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RunMode {
+    /// Keep the index in sync with lokid and serve the REST and Electrum clients
+    Serve,
+    /// Regenerate the index rows of the blocks at heights `from..=to`, then exit
+    ReindexRange { from: usize, to: usize },
+    /// Report the rows and bytes used by each key prefix of the databases, then exit
+    DbStats,
+    /// Write the utxo set as of the block at `height` to the `out` file, then exit
+    DumpUtxos { height: usize, out: PathBuf },
+}
+
+/// A group of expensive REST endpoints, see `--rest-disable-endpoints`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EndpointGroup {
+    /// The address history and block exports, and the chainstate diffs
+    Export,
+    /// The lookups of many addresses or transactions at once
+    Bulk,
+    /// The address ledgers
+    Ledger,
+    /// The chain, supply and mining statistics
+    Stats,
+    /// The search and address prefix lookups
+    Search,
+}
+
+impl EndpointGroup {
+    pub fn name(&self) -> &'static str {
+        match self {
+            EndpointGroup::Export => "export",
+            EndpointGroup::Bulk => "bulk",
+            EndpointGroup::Ledger => "ledger",
+            EndpointGroup::Stats => "stats",
+            EndpointGroup::Search => "search",
+        }
+    }
+}
+
+impl FromStr for EndpointGroup {
+    type Err = String;
+
+    fn from_str(group: &str) -> std::result::Result<Self, Self::Err> {
+        match group {
+            "export" => Ok(EndpointGroup::Export),
+            "bulk" => Ok(EndpointGroup::Bulk),
+            "ledger" => Ok(EndpointGroup::Ledger),
+            "stats" => Ok(EndpointGroup::Stats),
+            "search" => Ok(EndpointGroup::Search),
+            _ => Err(format!("Invalid endpoint group {:?}", group)),
+        }
+    }
+}
+
 #[derive(Debug, Default, Clone)]
 pub struct RpcLogging {
     pub enabled: bool,
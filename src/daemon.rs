@@ -1,12 +1,14 @@
 use std::cell::OnceCell;
 use std::collections::{HashMap, HashSet};
 use std::convert::TryFrom;
+use std::fmt;
 use std::io::{BufRead, BufReader, Lines, Write};
-use std::net::{SocketAddr, TcpStream};
+use std::net::{SocketAddr, TcpStream, ToSocketAddrs};
 use std::path::PathBuf;
 use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
 use std::sync::{Arc, Mutex};
-use std::time::Duration;
+use std::time::{Duration, Instant};
 use std::{env, fs, io};
 
 use base64::prelude::{Engine, BASE64_STANDARD};
@@ -14,6 +16,7 @@ use bitcoin::hex::FromHex;
 use error_chain::ChainedError;
 use rayon::iter::{IndexedParallelIterator, IntoParallelIterator, ParallelIterator};
 use serde_json::{from_str, from_value, Value};
+use utoipa::ToSchema;
 
 #[cfg(not(feature = "liquid"))]
 use bitcoin::consensus::encode::{deserialize, serialize_hex};
@@ -23,9 +26,11 @@ use elements::encode::{deserialize, serialize_hex};
 use electrs_macros::trace;
 
 use crate::chain::{Block, BlockHash, BlockHeader, Network, Transaction, Txid};
-use crate::metrics::{HistogramOpts, HistogramVec, Metrics};
+#[cfg(feature = "liquid")]
+use crate::elements::ebcompact::TxidCompat;
+use crate::metrics::{Gauge, HistogramOpts, HistogramVec, MetricOpts, Metrics};
 use crate::signal::Waiter;
-use crate::util::{HeaderList, DEFAULT_BLOCKHASH};
+use crate::util::{dns, HeaderList, DEFAULT_BLOCKHASH};
 
 use crate::errors::*;
 
@@ -45,6 +50,25 @@ lazy_static! {
     );
 }
 
+// how often the addresses of the `--daemon-rpc-addr` are resolved again, when (re)connecting
+const RESOLVE_INTERVAL: Duration = Duration::from_secs(60);
+
+/// The lokid RPCs taking no parameters that can be exposed over REST, as they don't reveal or change
+/// anything sensitive
+pub const PASSTHROUGH_METHODS: &[&str] = &[
+    "getbestblockhash",
+    "getblockchaininfo",
+    "getblockcount",
+    "getchaintips",
+    "getconnectioncount",
+    "getdifficulty",
+    "getmempoolinfo",
+    "getmininginfo",
+    "getnettotals",
+    "getnetworkinfo",
+    "uptime",
+];
+
 const MAX_ATTEMPTS: u32 = 5;
 const RETRY_WAIT_DURATION: Duration = Duration::from_secs(1);
 
@@ -154,7 +178,39 @@ struct NetworkInfo {
 }
 
 #[derive(Serialize, Deserialize, Debug)]
-struct MempoolFees {
+struct MempoolInfo {
+    mempoolminfee: f64, // in BTC/kB
+}
+
+/// The parts of lokid's `getblocktemplate` result describing the block contents
+#[derive(Deserialize, Debug, Clone)]
+pub struct BlockTemplate {
+    pub height: u32,
+    pub previousblockhash: BlockHash,
+    pub coinbasevalue: u64, // in satoshis, including the fees
+    pub sigoplimit: Option<u64>,
+    pub weightlimit: Option<u64>,
+    pub transactions: Vec<BlockTemplateTx>,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct BlockTemplateTx {
+    pub txid: Txid,
+    pub fee: u64,    // in satoshis
+    pub sigops: u64, // sigop cost
+    pub weight: u64,
+}
+
+/// lokid's mempool sequence number, along with the failovers of the connection it was read over,
+/// as the numbers of different lokid instances can't be compared
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct MempoolSequence {
+    pub failovers: u64,
+    pub sequence: u64,
+}
+
+#[derive(Serialize, Deserialize, Debug, ToSchema)]
+pub struct MempoolFees {
     base: f64,
     #[serde(rename = "effective-feerate")]
     effective_feerate: f64,
@@ -162,7 +218,7 @@ struct MempoolFees {
     effective_includes: Vec<String>,
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, ToSchema)]
 pub struct MempoolAcceptResult {
     txid: String,
     wtxid: String,
@@ -173,8 +229,8 @@ pub struct MempoolAcceptResult {
     reject_reason: Option<String>,
 }
 
-#[derive(Serialize, Deserialize, Debug)]
-struct MempoolFeesSubmitPackage {
+#[derive(Serialize, Deserialize, Debug, ToSchema)]
+pub struct MempoolFeesSubmitPackage {
     base: f64,
     #[serde(rename = "effective-feerate")]
     effective_feerate: Option<f64>,
@@ -182,7 +238,7 @@ struct MempoolFeesSubmitPackage {
     effective_includes: Option<Vec<String>>,
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, ToSchema)]
 pub struct SubmitPackageResult {
     package_msg: String,
     #[serde(rename = "tx-results")]
@@ -191,7 +247,7 @@ pub struct SubmitPackageResult {
     replaced_transactions: Option<Vec<String>>,
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, ToSchema)]
 pub struct TxResult {
     txid: String,
     #[serde(rename = "other-wtxid")]
@@ -205,44 +261,204 @@ pub trait CookieGetter: Send + Sync {
     fn get(&self) -> Result<Vec<u8>>;
 }
 
+/// The lokid RPC endpoint (`--daemon-rpc-addr`): an `ip:port`, a `host:port` whose resolved
+/// addresses are all failover candidates, or an `srv:<name>` DNS SRV record listing them (like
+/// `srv:_flokicoin-rpc._tcp.example.com`)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DaemonAddr {
+    Socket(SocketAddr),
+    Host(String, u16),
+    Srv(String),
+}
+
+impl DaemonAddr {
+    /// The addresses to connect to, by order of preference
+    pub fn resolve(&self) -> Result<Vec<SocketAddr>> {
+        let mut addrs = vec![];
+        match self {
+            DaemonAddr::Socket(addr) => addrs.push(*addr),
+            DaemonAddr::Host(host, port) => addrs.extend(
+                (host.as_str(), *port)
+                    .to_socket_addrs()
+                    .chain_err(|| format!("failed resolving {}", host))?,
+            ),
+            DaemonAddr::Srv(name) => {
+                for target in dns::lookup_srv(name)? {
+                    match (target.host.as_str(), target.port).to_socket_addrs() {
+                        Ok(resolved) => addrs.extend(resolved),
+                        Err(e) => warn!("failed resolving {} (from {}): {}", target.host, name, e),
+                    }
+                }
+            }
+        }
+        let mut seen = HashSet::new();
+        addrs.retain(|addr| seen.insert(*addr));
+        if addrs.is_empty() {
+            bail!("no address for {}", self);
+        }
+        Ok(addrs)
+    }
+}
+
+impl FromStr for DaemonAddr {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        if let Some(name) = s.strip_prefix("srv:") {
+            if name.is_empty() {
+                return Err("missing SRV record name".to_string());
+            }
+            return Ok(DaemonAddr::Srv(name.to_string()));
+        }
+        if let Ok(addr) = s.parse() {
+            return Ok(DaemonAddr::Socket(addr));
+        }
+        match s.rsplit_once(':') {
+            Some((host, port)) if !host.is_empty() => port
+                .parse()
+                .map(|port| DaemonAddr::Host(host.to_string(), port))
+                .map_err(|_| format!("invalid port in {:?}", s)),
+            _ => Err(format!(
+                "expected ip:port, host:port or srv:<name>, not {:?}",
+                s
+            )),
+        }
+    }
+}
+
+impl From<SocketAddr> for DaemonAddr {
+    fn from(addr: SocketAddr) -> Self {
+        DaemonAddr::Socket(addr)
+    }
+}
+
+impl fmt::Display for DaemonAddr {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            DaemonAddr::Socket(addr) => write!(f, "{}", addr),
+            DaemonAddr::Host(host, port) => write!(f, "{}:{}", host, port),
+            DaemonAddr::Srv(name) => write!(f, "srv:{}", name),
+        }
+    }
+}
+
+// The addresses of the `--daemon-rpc-addr`, shared by the connections to the daemon. The address
+// connected to last is tried first, and the ones failing are moved last. They are resolved again
+// when connecting every `RESOLVE_INTERVAL`, and once all of them failed.
+struct DaemonEndpoint {
+    addr: DaemonAddr,
+    state: Mutex<EndpointState>,
+}
+
+#[derive(Default)]
+struct EndpointState {
+    candidates: Vec<SocketAddr>,
+    resolved_at: Option<Instant>,
+}
+
+impl DaemonEndpoint {
+    fn new(addr: DaemonAddr) -> Self {
+        DaemonEndpoint {
+            addr,
+            state: Mutex::new(EndpointState::default()),
+        }
+    }
+
+    fn candidates(&self) -> Vec<SocketAddr> {
+        let mut state = self.state.lock().unwrap();
+        let due = state.resolved_at.map_or(true, |resolved_at| {
+            resolved_at.elapsed() >= RESOLVE_INTERVAL
+        });
+        if due {
+            state.resolved_at = Some(Instant::now());
+            match self.addr.resolve() {
+                Ok(mut resolved) => {
+                    // keep the address in use first, as long as it is listed
+                    if let Some(pos) = state
+                        .candidates
+                        .first()
+                        .and_then(|current| resolved.iter().position(|addr| addr == current))
+                    {
+                        resolved[..=pos].rotate_right(1);
+                    }
+                    let known: HashSet<_> = state.candidates.iter().collect();
+                    if resolved.len() != known.len() || !resolved.iter().all(|a| known.contains(a))
+                    {
+                        info!("daemon {} resolved to {:?}", self.addr, resolved);
+                    }
+                    state.candidates = resolved;
+                }
+                Err(e) => warn!(
+                    "failed resolving daemon {}: {} (keeping {:?})",
+                    self.addr,
+                    e.display_chain(),
+                    state.candidates
+                ),
+            }
+        }
+        state.candidates.clone()
+    }
+
+    fn connected(&self, addr: SocketAddr) {
+        let mut state = self.state.lock().unwrap();
+        if let Some(pos) = state.candidates.iter().position(|a| *a == addr) {
+            state.candidates[..=pos].rotate_right(1);
+        }
+    }
+
+    fn failed(&self, addr: SocketAddr) {
+        let mut state = self.state.lock().unwrap();
+        if let Some(pos) = state.candidates.iter().position(|a| *a == addr) {
+            state.candidates[pos..].rotate_left(1);
+        }
+    }
+
+    fn expire(&self) {
+        self.state.lock().unwrap().resolved_at = None;
+    }
+}
+
 struct Connection {
     tx: TcpStream,
     rx: Lines<BufReader<TcpStream>>,
     cookie_getter: Arc<dyn CookieGetter>,
+    endpoint: Arc<DaemonEndpoint>,
     addr: SocketAddr,
     signal: Waiter,
 }
 
 #[trace]
-fn tcp_connect(addr: SocketAddr, signal: &Waiter) -> Result<TcpStream> {
+fn tcp_connect(endpoint: &DaemonEndpoint, signal: &Waiter) -> Result<(TcpStream, SocketAddr)> {
     loop {
-        match TcpStream::connect_timeout(&addr, *DAEMON_CONNECTION_TIMEOUT) {
-            Ok(conn) => {
-                // can only fail if DAEMON_TIMEOUT is 0
-                conn.set_read_timeout(Some(*DAEMON_READ_TIMEOUT)).unwrap();
-                conn.set_write_timeout(Some(*DAEMON_WRITE_TIMEOUT)).unwrap();
-                return Ok(conn);
-            }
-            Err(err) => {
-                warn!(
-                    "failed to connect daemon at {}: {} (backoff 3 seconds)",
-                    addr, err
-                );
-                signal.wait(Duration::from_secs(3), false)?;
-                continue;
+        for addr in endpoint.candidates() {
+            match TcpStream::connect_timeout(&addr, *DAEMON_CONNECTION_TIMEOUT) {
+                Ok(conn) => {
+                    // can only fail if DAEMON_TIMEOUT is 0
+                    conn.set_read_timeout(Some(*DAEMON_READ_TIMEOUT)).unwrap();
+                    conn.set_write_timeout(Some(*DAEMON_WRITE_TIMEOUT)).unwrap();
+                    endpoint.connected(addr);
+                    return Ok((conn, addr));
+                }
+                Err(err) => warn!("failed to connect daemon at {}: {}", addr, err),
             }
         }
+        warn!(
+            "failed to connect daemon {} (backoff 3 seconds)",
+            endpoint.addr
+        );
+        endpoint.expire();
+        signal.wait(Duration::from_secs(3), false)?;
     }
 }
 
 impl Connection {
     #[trace]
     fn new(
-        addr: SocketAddr,
+        endpoint: Arc<DaemonEndpoint>,
         cookie_getter: Arc<dyn CookieGetter>,
         signal: Waiter,
     ) -> Result<Connection> {
-        let conn = tcp_connect(addr, &signal)?;
+        let (conn, addr) = tcp_connect(&endpoint, &signal)?;
         let reader = BufReader::new(
             conn.try_clone()
                 .chain_err(|| format!("failed to clone {:?}", conn))?,
@@ -251,6 +467,7 @@ impl Connection {
             tx: conn,
             rx: reader.lines(),
             cookie_getter,
+            endpoint,
             addr,
             signal,
         })
@@ -258,7 +475,18 @@ impl Connection {
 
     #[trace]
     fn reconnect(&self) -> Result<Connection> {
-        Connection::new(self.addr, self.cookie_getter.clone(), self.signal.clone())
+        Connection::new(
+            self.endpoint.clone(),
+            self.cookie_getter.clone(),
+            self.signal.clone(),
+        )
+    }
+
+    // Reconnects after a failure, preferring the other addresses of the daemon
+    #[trace]
+    fn failover(&self) -> Result<Connection> {
+        self.endpoint.failed(self.addr);
+        self.reconnect()
     }
 
     #[trace]
@@ -383,52 +611,64 @@ pub struct Daemon {
     conn: Mutex<Connection>,
     message_id: Counter, // for monotonic JSONRPC 'id'
     signal: Waiter,
+    batch_size: usize, // # of requests sent per JSONRPC batch by requests()
+    // whether getrawmempool reports the mempool sequence (cleared once lokid rejected the
+    // parameter, until the next failover)
+    mempool_sequence: AtomicBool,
+    // # of failovers to another address of the daemon, whose mempool sequence is unrelated
+    failovers: AtomicU64,
 
     rpc_threads: Arc<rayon::ThreadPool>,
 
     // monitoring
     latency: HistogramVec,
     size: HistogramVec,
+    inflight: Gauge,
+}
+
+// Counts requests in the `daemon_rpc_inflight` gauge for as long as it is alive, so that they
+// are no longer counted once done, even if they panicked
+struct Inflight<'a> {
+    gauge: &'a Gauge,
+    count: i64,
+}
+
+impl<'a> Inflight<'a> {
+    fn new(gauge: &'a Gauge, count: i64) -> Self {
+        gauge.add(count);
+        Inflight { gauge, count }
+    }
+}
+
+impl Drop for Inflight<'_> {
+    fn drop(&mut self) {
+        self.gauge.sub(self.count);
+    }
 }
 
 impl Daemon {
     pub fn new(
         daemon_dir: &PathBuf,
         blocks_dir: &PathBuf,
-        daemon_rpc_addr: SocketAddr,
+        daemon_rpc_addr: DaemonAddr,
         daemon_parallelism: usize,
+        batch_size: usize,
         cookie_getter: Arc<dyn CookieGetter>,
         network: Network,
         signal: Waiter,
         metrics: &Metrics,
     ) -> Result<Daemon> {
-        let daemon = Daemon {
-            daemon_dir: daemon_dir.clone(),
-            blocks_dir: blocks_dir.clone(),
+        let daemon = Daemon::connect(
+            daemon_dir,
+            blocks_dir,
+            daemon_rpc_addr,
+            daemon_parallelism,
+            batch_size,
+            cookie_getter,
             network,
-            conn: Mutex::new(Connection::new(
-                daemon_rpc_addr,
-                cookie_getter,
-                signal.clone(),
-            )?),
-            message_id: Counter::new(),
-            signal: signal.clone(),
-            rpc_threads: Arc::new(
-                rayon::ThreadPoolBuilder::new()
-                    .num_threads(daemon_parallelism)
-                    .thread_name(|i| format!("rpc-requests-{}", i))
-                    .build()
-                    .unwrap(),
-            ),
-            latency: metrics.histogram_vec(
-                HistogramOpts::new("daemon_rpc", "Lokid RPC latency (in seconds)"),
-                &["method"],
-            ),
-            size: metrics.histogram_vec(
-                HistogramOpts::new("daemon_bytes", "Lokid RPC size (in bytes)"),
-                &["method", "dir"],
-            ),
-        };
+            signal.clone(),
+            metrics,
+        )?;
         let network_info = daemon.getnetworkinfo()?;
         info!("{:?}", network_info);
         if network_info.version < 16_00_00 {
@@ -460,6 +700,86 @@ impl Daemon {
         Ok(daemon)
     }
 
+    /// A daemon that isn't backed by a node, failing all its requests, to benchmark the queries
+    /// that don't use it
+    #[cfg(feature = "bench")]
+    pub fn unreachable(network: Network, metrics: &Metrics) -> Result<Daemon> {
+        struct NoCookie;
+        impl CookieGetter for NoCookie {
+            fn get(&self) -> Result<Vec<u8>> {
+                Ok(vec![])
+            }
+        }
+
+        // connected to a listener that is closed right away
+        let listener = std::net::TcpListener::bind("127.0.0.1:0")
+            .chain_err(|| "failed binding the unreachable daemon")?;
+        let addr = listener
+            .local_addr()
+            .chain_err(|| "failed binding the unreachable daemon")?;
+        let daemon = Daemon::connect(
+            &PathBuf::new(),
+            &PathBuf::new(),
+            DaemonAddr::Socket(addr),
+            1,
+            1,
+            Arc::new(NoCookie),
+            network,
+            Waiter::start(crossbeam_channel::never()),
+            metrics,
+        )?;
+        drop(listener);
+        Ok(daemon)
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    fn connect(
+        daemon_dir: &PathBuf,
+        blocks_dir: &PathBuf,
+        daemon_rpc_addr: DaemonAddr,
+        daemon_parallelism: usize,
+        batch_size: usize,
+        cookie_getter: Arc<dyn CookieGetter>,
+        network: Network,
+        signal: Waiter,
+        metrics: &Metrics,
+    ) -> Result<Daemon> {
+        Ok(Daemon {
+            daemon_dir: daemon_dir.clone(),
+            blocks_dir: blocks_dir.clone(),
+            network,
+            conn: Mutex::new(Connection::new(
+                Arc::new(DaemonEndpoint::new(daemon_rpc_addr)),
+                cookie_getter,
+                signal.clone(),
+            )?),
+            message_id: Counter::new(),
+            signal: signal.clone(),
+            batch_size: batch_size.max(1),
+            mempool_sequence: AtomicBool::new(true),
+            failovers: AtomicU64::new(0),
+            rpc_threads: Arc::new(
+                rayon::ThreadPoolBuilder::new()
+                    .num_threads(daemon_parallelism)
+                    .thread_name(|i| format!("rpc-requests-{}", i))
+                    .build()
+                    .unwrap(),
+            ),
+            latency: metrics.histogram_vec(
+                HistogramOpts::new("daemon_rpc", "Lokid RPC latency (in seconds)"),
+                &["method"],
+            ),
+            size: metrics.histogram_vec(
+                HistogramOpts::new("daemon_bytes", "Lokid RPC size (in bytes)"),
+                &["method", "dir"],
+            ),
+            inflight: metrics.gauge(MetricOpts::new(
+                "daemon_rpc_inflight",
+                "# of Lokid RPC requests being handled by the rpc-requests thread pool",
+            )),
+        })
+    }
+
     #[trace]
     pub fn reconnect(&self) -> Result<Daemon> {
         Ok(Daemon {
@@ -469,9 +789,13 @@ impl Daemon {
             conn: Mutex::new(self.conn.lock().unwrap().reconnect()?),
             message_id: Counter::new(),
             signal: self.signal.clone(),
+            batch_size: self.batch_size,
+            mempool_sequence: AtomicBool::new(self.mempool_sequence.load(Ordering::Relaxed)),
+            failovers: AtomicU64::new(self.failovers.load(Ordering::Relaxed)),
             rpc_threads: self.rpc_threads.clone(),
             latency: self.latency.clone(),
             size: self.size.clone(),
+            inflight: self.inflight.clone(),
         })
     }
 
@@ -542,14 +866,77 @@ impl Daemon {
         parse_jsonrpc_reply(reply, method, id)
     }
 
+    // Sends the requests as one JSONRPC batch, returning the result of each of them
+    #[trace(method = %method)]
+    fn handle_batch(&self, method: &str, params_list: &[Value]) -> Result<Vec<Result<Value>>> {
+        let ids: Vec<u64> = params_list.iter().map(|_| self.message_id.next()).collect();
+        let batch: Vec<Value> = params_list
+            .iter()
+            .zip(&ids)
+            .map(|(params, id)| {
+                json!({
+                    "jsonrpc": "1.0",
+                    "method": method,
+                    "params": params,
+                    "id": id,
+                })
+            })
+            .collect();
+        let mut replies: HashMap<u64, Value> = match self.call_jsonrpc(method, &json!(batch))? {
+            Value::Array(replies) => replies
+                .into_iter()
+                .filter_map(|reply| Some((reply.get("id")?.as_u64()?, reply)))
+                .collect(),
+            reply => bail!("non-array reply to {} batch: {:?}", method, reply),
+        };
+        Ok(ids
+            .into_iter()
+            .map(|id| {
+                let reply = replies
+                    .remove(&id)
+                    .chain_err(|| format!("no reply to {} request {}", method, id))?;
+                parse_jsonrpc_reply(reply, method, id)
+            })
+            .collect())
+    }
+
+    fn retry_batch(&self, method: &str, params_list: &[Value]) -> Result<Vec<Result<Value>>> {
+        if let [params] = params_list {
+            return Ok(vec![self.retry_request(method, params)]);
+        }
+        loop {
+            let result = self.handle_batch(method, params_list);
+            // lokid fails every request of the batch while warming up, retry it as a whole
+            let disconnected = match result {
+                Err(ref e @ Error(ErrorKind::Connection(_), _)) => {
+                    Some(e.display_chain().to_string())
+                }
+                Ok(ref replies) => replies.iter().find_map(|reply| match reply {
+                    Err(e @ Error(ErrorKind::Connection(_), _)) => {
+                        Some(e.display_chain().to_string())
+                    }
+                    _ => None,
+                }),
+                Err(_) => None,
+            };
+            match disconnected {
+                Some(err) => {
+                    warn!("reconnecting to Lokid: {}", err);
+                    self.signal.wait(Duration::from_secs(3), false)?;
+                    self.failover()?;
+                }
+                None => return result,
+            }
+        }
+    }
+
     fn retry_request(&self, method: &str, params: &Value) -> Result<Value> {
         loop {
             match self.handle_request(method, &params) {
                 Err(e @ Error(ErrorKind::Connection(_), _)) => {
                     warn!("reconnecting to Lokid: {}", e.display_chain());
                     self.signal.wait(Duration::from_secs(3), false)?;
-                    let mut conn = self.conn.lock().unwrap();
-                    *conn = conn.reconnect()?;
+                    self.failover()?;
                     continue;
                 }
                 result => return result,
@@ -557,6 +944,15 @@ impl Daemon {
         }
     }
 
+    fn failover(&self) -> Result<()> {
+        let mut conn = self.conn.lock().unwrap();
+        *conn = conn.failover()?;
+        // the other address may be another lokid instance, which may report the mempool sequence
+        self.failovers.fetch_add(1, Ordering::Relaxed);
+        self.mempool_sequence.store(true, Ordering::Relaxed);
+        Ok(())
+    }
+
     #[trace]
     fn request(&self, method: &str, params: Value) -> Result<Value> {
         self.retry_request(method, &params)
@@ -575,33 +971,40 @@ impl Daemon {
         }
     }
 
-    // Send requests in parallel over multiple RPC connections as individual JSON-RPC requests (with no JSON-RPC batching),
+    // Send requests in parallel over multiple RPC connections as JSON-RPC batches of `batch_size` requests,
     // buffering the replies into a vector. If any of the requests fail, processing is terminated and an Err is returned.
     #[trace]
     fn requests(&self, method: &str, params_list: Vec<Value>) -> Result<Vec<Value>> {
         self.requests_iter(method, params_list).collect()
     }
 
-    // Send requests in parallel over multiple RPC connections, iterating over the results without buffering them.
-    // Errors are included in the iterator and do not terminate other pending requests.
+    // Send requests in parallel over multiple RPC connections, iterating over the results (in order) without
+    // buffering more than a batch of them. Errors are included in the iterator and do not terminate other pending
+    // requests, except for the failures of whole batches.
     #[trace]
     fn requests_iter<'a>(
         &'a self,
         method: &'a str,
         params_list: Vec<Value>,
-    ) -> impl ParallelIterator<Item = Result<Value>> + IndexedParallelIterator + 'a {
+    ) -> impl ParallelIterator<Item = Result<Value>> + 'a {
+        let batch_size = self.batch_size;
         self.rpc_threads.install(move || {
-            params_list.into_par_iter().map(move |params| {
-                // Store a local per-thread Daemon, each with its own TCP connection. These will
-                // get initialized as necessary for the `rpc_threads` pool thread managed by rayon.
-                thread_local!(static DAEMON_INSTANCE: OnceCell<Daemon> = OnceCell::new());
-
-                DAEMON_INSTANCE.with(|daemon| {
-                    daemon
-                        .get_or_init(|| self.retry_reconnect())
-                        .retry_request(&method, &params)
+            params_list
+                .into_par_iter()
+                .chunks(batch_size)
+                .flat_map_iter(move |batch| {
+                    // Store a local per-thread Daemon, each with its own TCP connection. These will
+                    // get initialized as necessary for the `rpc_threads` pool thread managed by rayon.
+                    thread_local!(static DAEMON_INSTANCE: OnceCell<Daemon> = OnceCell::new());
+
+                    let _inflight = Inflight::new(&self.inflight, batch.len() as i64);
+                    let result = DAEMON_INSTANCE.with(|daemon| {
+                        daemon
+                            .get_or_init(|| self.retry_reconnect())
+                            .retry_batch(&method, &batch)
+                    });
+                    result.unwrap_or_else(|e| vec![Err(e)])
                 })
-            })
         })
     }
 
@@ -619,6 +1022,15 @@ impl Daemon {
         Ok(from_value(info).chain_err(|| "invalid network info")?)
     }
 
+    /// Calls one of the `PASSTHROUGH_METHODS`
+    #[trace]
+    pub fn passthrough(&self, method: &str) -> Result<Value> {
+        if !PASSTHROUGH_METHODS.contains(&method) {
+            bail!("{} is not a passthrough method", method);
+        }
+        self.request(method, json!([]))
+    }
+
     #[trace]
     pub fn getbestblockhash(&self) -> Result<BlockHash> {
         parse_hash(&self.request("getbestblockhash", json!([]))?)
@@ -741,9 +1153,8 @@ impl Daemon {
             .collect();
 
         self.requests_iter("getrawtransaction", params_list)
-            .zip(txids)
-            .filter_map(|(res, txid)| match res {
-                Ok(val) => Some(tx_from_value(val).map(|tx| (**txid, tx))),
+            .filter_map(|res| match res {
+                Ok(val) => Some(tx_from_value(val).map(|tx| (tx.compute_txid(), tx))),
                 // Ignore 'tx not found' errors
                 Err(Error(ErrorKind::RpcError(code, _, _), _))
                     if code == RPC_INVALID_ADDRESS_OR_KEY =>
@@ -778,6 +1189,58 @@ impl Daemon {
         Ok(serde_json::from_value(res).chain_err(|| "invalid getrawmempool reply")?)
     }
 
+    /// The # of failovers to another address of the daemon so far
+    pub fn failovers(&self) -> u64 {
+        self.failovers.load(Ordering::Relaxed)
+    }
+
+    /// The mempool txids, with the mempool sequence number when lokid reports it (0.21+), which
+    /// changes whenever a transaction enters or leaves the mempool
+    #[trace]
+    pub fn getmempooltxids_sequence(&self) -> Result<(HashSet<Txid>, Option<MempoolSequence>)> {
+        // the errors of the versions without the parameter (help text or invalid parameter)
+        const RPC_MISC_ERROR: i64 = -1;
+        const RPC_TYPE_ERROR: i64 = -3;
+        const RPC_INVALID_PARAMETER: i64 = -8;
+
+        #[derive(Deserialize)]
+        struct Reply {
+            txids: HashSet<Txid>,
+            mempool_sequence: u64,
+        }
+
+        if self.mempool_sequence.load(Ordering::Relaxed) {
+            // read first, so that a failover during the request doesn't get attributed to the
+            // sequence of the previous instance
+            let failovers = self.failovers();
+            let params = json!([/*verbose=*/ false, /*mempool_sequence=*/ true]);
+            match self.request("getrawmempool", params) {
+                Ok(res) => {
+                    let res: Reply =
+                        serde_json::from_value(res).chain_err(|| "invalid getrawmempool reply")?;
+                    let sequence = MempoolSequence {
+                        failovers,
+                        sequence: res.mempool_sequence,
+                    };
+                    return Ok((res.txids, Some(sequence)));
+                }
+                Err(Error(ErrorKind::RpcError(code, msg, _), _))
+                    if code == RPC_MISC_ERROR
+                        || code == RPC_TYPE_ERROR
+                        || code == RPC_INVALID_PARAMETER =>
+                {
+                    info!(
+                        "lokid doesn't report the mempool sequence ({}: {}), diffing the full mempool on every update",
+                        code, msg
+                    );
+                    self.mempool_sequence.store(false, Ordering::Relaxed);
+                }
+                Err(e) => return Err(e),
+            }
+        }
+        Ok((self.getmempooltxids()?, None))
+    }
+
     #[trace]
     pub fn broadcast(&self, tx: &Transaction) -> Result<Txid> {
         self.broadcast_raw(&serialize_hex(tx))
@@ -932,4 +1395,68 @@ impl Daemon {
         // from BTC/kB to sat/b
         Ok(relayfee * 100_000f64)
     }
+
+    /// The block lokid would currently mine on top of its tip
+    #[trace]
+    pub fn getblocktemplate(&self) -> Result<BlockTemplate> {
+        let template = self.request("getblocktemplate", json!([{ "rules": ["segwit"] }]))?;
+        from_value(template).chain_err(|| "invalid block template")
+    }
+
+    /// The feerate under which transactions are evicted from lokid's mempool, in sat/b
+    #[trace]
+    pub fn get_mempool_min_fee(&self) -> Result<f64> {
+        let info: Value = self.request("getmempoolinfo", json!([]))?;
+        let info: MempoolInfo = from_value(info).chain_err(|| "invalid mempool info")?;
+
+        // from BTC/kB to sat/b
+        Ok(info.mempoolminfee * 100_000f64)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_daemon_addr() {
+        let addr: DaemonAddr = "127.0.0.1:8332".parse().unwrap();
+        assert_eq!(addr, DaemonAddr::Socket(([127, 0, 0, 1], 8332).into()));
+        assert_eq!(
+            "[::1]:8332"
+                .parse::<DaemonAddr>()
+                .unwrap()
+                .resolve()
+                .unwrap(),
+            vec!["[::1]:8332".parse().unwrap()]
+        );
+        let addr: DaemonAddr = "lokid.example.com:8332".parse().unwrap();
+        assert_eq!(
+            addr,
+            DaemonAddr::Host("lokid.example.com".to_string(), 8332)
+        );
+        let addr: DaemonAddr = "srv:_flokicoin-rpc._tcp.example.com".parse().unwrap();
+        assert_eq!(addr.to_string(), "srv:_flokicoin-rpc._tcp.example.com");
+
+        assert!("lokid.example.com".parse::<DaemonAddr>().is_err());
+        assert!("lokid:port".parse::<DaemonAddr>().is_err());
+        assert!("srv:".parse::<DaemonAddr>().is_err());
+    }
+
+    #[test]
+    fn test_endpoint_failover() {
+        let endpoint = DaemonEndpoint::new(DaemonAddr::Socket(([127, 0, 0, 1], 1).into()));
+        let addrs: Vec<SocketAddr> = (1..=3).map(|port| ([127, 0, 0, 1], port).into()).collect();
+        endpoint.state.lock().unwrap().candidates = addrs.clone();
+        endpoint.state.lock().unwrap().resolved_at = Some(Instant::now());
+
+        endpoint.failed(addrs[0]);
+        assert_eq!(endpoint.candidates(), vec![addrs[1], addrs[2], addrs[0]]);
+        endpoint.connected(addrs[2]);
+        assert_eq!(endpoint.candidates(), vec![addrs[2], addrs[1], addrs[0]]);
+
+        // resolved again once expired
+        endpoint.expire();
+        assert_eq!(endpoint.candidates(), vec![addrs[0]]);
+    }
 }
@@ -9,11 +9,12 @@ use std::thread;
 use std::time::{Duration, Instant};
 
 use electrum_client::ElectrumApi;
+use serde_json::Value;
 
 use crate::chain::Network;
 use crate::electrum::{Client, Hostname, Port, ProtocolVersion, ServerFeatures};
 use crate::errors::{Result, ResultExt};
-use crate::util::spawn_thread;
+use crate::util::supervisor::spawn_supervised;
 
 mod default_servers;
 use default_servers::add_default_servers;
@@ -24,6 +25,9 @@ const MAX_CONSECUTIVE_FAILURES: usize = 24; // drop servers after 24 consecutive
 const MAX_QUEUE_SIZE: usize = 500; // refuse accepting new servers if we have that many health check jobs
 const MAX_SERVERS_PER_REQUEST: usize = 3; // maximum number of server hosts added per server.add_peer call
 const MAX_SERVICES_PER_REQUEST: usize = 6; // maximum number of services added per server.add_peer call
+const MAX_SERVERS_PER_PEER_LIST: usize = 10; // maximum number of server hosts added from a peer's server.peers.subscribe
+const DEFAULT_TCP_PORT: Port = 50001;
+const DEFAULT_SSL_PORT: Port = 50002;
 
 #[derive(Debug)]
 pub struct DiscoveryManager {
@@ -45,8 +49,11 @@ pub struct DiscoveryManager {
     /// Whether we should announce ourselves to the servers we're connecting to
     announce: bool,
 
-    /// Optional, will not support onion hosts without this
+    /// Optional, will not support onion hosts without this (or the `socks_proxy`)
     tor_proxy: Option<SocketAddr>,
+
+    /// Optional, routes the connections to all the servers
+    socks_proxy: Option<SocketAddr>,
 }
 
 /// A Server corresponds to a single IP address or onion hostname, with one or more services
@@ -64,6 +71,9 @@ struct Server {
 enum ServerAddr {
     Clearnet(IpAddr),
     Onion(Hostname),
+    // a clearnet hostname, left for the `socks_proxy` to resolve so that it doesn't leak through
+    // the local DNS resolver
+    Proxied(Hostname),
 }
 
 #[derive(Eq, PartialEq, Hash, Copy, Clone, Debug)]
@@ -97,12 +107,13 @@ impl DiscoveryManager {
         our_version: ProtocolVersion,
         announce: bool,
         tor_proxy: Option<SocketAddr>,
+        socks_proxy: Option<SocketAddr>,
     ) -> Self {
         let our_addrs = our_features
             .hosts
             .keys()
             .filter_map(|hostname| {
-                ServerAddr::resolve(hostname)
+                ServerAddr::resolve(hostname, socks_proxy.is_some())
                     .map_err(|e| warn!("failed resolving own hostname {}: {:?}", hostname, e))
                     .ok()
             })
@@ -113,6 +124,7 @@ impl DiscoveryManager {
             our_features,
             announce,
             tor_proxy,
+            socks_proxy,
             healthy: Default::default(),
             queue: Default::default(),
         };
@@ -148,7 +160,7 @@ impl DiscoveryManager {
                     warn!("skipping invalid hostname");
                     return None;
                 }
-                let addr = match ServerAddr::resolve(&hostname) {
+                let addr = match ServerAddr::resolve(&hostname, self.socks_proxy.is_some()) {
                     Ok(addr) => addr,
                     Err(e) => {
                         warn!("failed resolving {}: {:?}", hostname, e);
@@ -160,15 +172,21 @@ impl DiscoveryManager {
                     return None;
                 }
                 // ensure the server address matches the ip that advertised it to us.
-                // onion hosts are exempt.
-                if let ServerAddr::Clearnet(ip) = addr {
-                    if ip != added_by {
+                // onion hosts are exempt, the proxied hostnames can't be checked without
+                // resolving them locally.
+                match addr {
+                    ServerAddr::Clearnet(ip) if ip != added_by => {
                         warn!(
                             "server ip does not match source ip ({}, {} != {})",
                             hostname, ip, added_by
                         );
                         return None;
                     }
+                    ServerAddr::Proxied(_) => {
+                        warn!("skipping unverifiable proxied hostname {}", hostname);
+                        return None;
+                    }
+                    _ => (),
                 }
                 Some((addr, hostname, ports))
             })
@@ -185,7 +203,8 @@ impl DiscoveryManager {
                             .map_or(true, |s| !s.contains(service))
                     })
                     .map(|service| {
-                        HealthCheck::new(addr.clone(), hostname.clone(), service, Some(added_by))
+                        HealthCheck::new(addr.clone(), hostname.clone(), service, false)
+                            .added_by(added_by)
                     })
                     .collect::<Vec<_>>()
             })
@@ -204,16 +223,74 @@ impl DiscoveryManager {
     /// Add a default server. Default servers are exempt from limits and given more leniency
     /// before being removed due to unavailability.
     pub fn add_default_server(&self, hostname: Hostname, services: Vec<Service>) -> Result<()> {
-        let addr = ServerAddr::resolve(&hostname)?;
+        let addr = ServerAddr::resolve(&hostname, self.socks_proxy.is_some())?;
         let mut queue = self.queue.write().unwrap();
         queue.extend(
             services
                 .into_iter()
-                .map(|service| HealthCheck::new(addr.clone(), hostname.clone(), service, None)),
+                .map(|service| HealthCheck::new(addr.clone(), hostname.clone(), service, true)),
         );
         Ok(())
     }
 
+    /// Add a seed peer given as `<hostname>:<service>[:<service>...]`, where services are
+    /// formatted like in `server.peers.subscribe` (e.g. `electrum.example.com:t50001:s50002`).
+    /// Seed peers are treated like the default servers.
+    pub fn add_seed_peer(&self, peer: &str) -> Result<()> {
+        let mut parts = peer.split(':');
+        let hostname = parts.next().unwrap_or_default().to_lowercase();
+        let services = parts.map(Service::from_str).collect::<Result<Vec<_>>>()?;
+        ensure!(
+            !hostname.is_empty() && !services.is_empty(),
+            "expected <hostname>:<service>"
+        );
+        self.add_default_server(hostname, services)
+    }
+
+    /// Queue the servers listed in the `server.peers.subscribe` response of a healthy server
+    /// for health checking. Like servers added via `server.add_peer`, they are dropped if the
+    /// first check fails.
+    fn add_peer_list(&self, peers: &Value) {
+        let candidates = peers
+            .as_array()
+            .map_or(&[][..], |peers| &peers[..])
+            .iter()
+            .filter_map(parse_peer_entry)
+            .take(MAX_SERVERS_PER_PEER_LIST)
+            .filter_map(|(hostname, services)| {
+                let addr = ServerAddr::resolve(&hostname, self.socks_proxy.is_some())
+                    .map_err(|e| debug!("failed resolving {}: {:?}", hostname, e))
+                    .ok()?;
+                if !is_remote_addr(&addr) || self.our_addrs.contains(&addr) {
+                    return None;
+                }
+                Some((addr, hostname, services))
+            })
+            .collect::<Vec<_>>();
+
+        let mut queue = self.queue.write().unwrap();
+        let mut existing_services = queue
+            .iter()
+            .map(|job| (job.addr.clone(), job.service))
+            .collect::<HashSet<_>>();
+        for (addr, hostname, services) in candidates {
+            for service in services {
+                if queue.len() >= MAX_QUEUE_SIZE {
+                    return;
+                }
+                if existing_services.insert((addr.clone(), service)) {
+                    debug!("discovered {} {:?}", hostname, service);
+                    queue.push(HealthCheck::new(
+                        addr.clone(),
+                        hostname.clone(),
+                        service,
+                        false,
+                    ));
+                }
+            }
+        }
+    }
+
     /// Get the list of healthy servers formatted for `servers.peers.subscribe`
     pub fn get_servers(&self) -> Vec<ServerEntry> {
         // XXX return a random sample instead of everything?
@@ -323,20 +400,23 @@ impl DiscoveryManager {
         let server_url = match (addr, service) {
             (ServerAddr::Clearnet(ip), Service::Tcp(port)) => format!("tcp://{}:{}", ip, port),
             (ServerAddr::Clearnet(_), Service::Ssl(port)) => format!("ssl://{}:{}", hostname, port),
-            (ServerAddr::Onion(onion_host), Service::Tcp(port)) => {
-                format!("tcp://{}:{}", onion_host, port)
-            }
-            (ServerAddr::Onion(onion_host), Service::Ssl(port)) => {
-                format!("ssl://{}:{}", onion_host, port)
-            }
+            (ServerAddr::Onion(host), Service::Tcp(port))
+            | (ServerAddr::Proxied(host), Service::Tcp(port)) => format!("tcp://{}:{}", host, port),
+            (ServerAddr::Onion(host), Service::Ssl(port))
+            | (ServerAddr::Proxied(host), Service::Ssl(port)) => format!("ssl://{}:{}", host, port),
         };
 
-        let mut config = electrum_client::ConfigBuilder::new();
-        if let ServerAddr::Onion(_) = addr {
-            let socks = electrum_client::Socks5Config::new(
+        let proxy = match addr {
+            ServerAddr::Onion(_) => Some(
                 self.tor_proxy
+                    .or(self.socks_proxy)
                     .chain_err(|| "no tor proxy configured, onion hosts are unsupported")?,
-            );
+            ),
+            ServerAddr::Clearnet(_) | ServerAddr::Proxied(_) => self.socks_proxy,
+        };
+        let mut config = electrum_client::ConfigBuilder::new();
+        if let Some(proxy) = proxy {
+            let socks = electrum_client::Socks5Config::new(proxy);
             config = config.socks5(Some(socks)).unwrap()
         }
 
@@ -353,6 +433,12 @@ impl DiscoveryManager {
             );
         }
 
+        // learn about more servers from the peers of healthy ones
+        match client.raw_call("server.peers.subscribe", vec![]) {
+            Ok(peers) => self.add_peer_list(&peers),
+            Err(e) => debug!("failed fetching the peers of {}: {:?}", hostname, e),
+        }
+
         Ok(features)
     }
 
@@ -376,7 +462,7 @@ impl DiscoveryManager {
     }
 
     pub fn spawn_jobs_thread(manager: Arc<DiscoveryManager>) {
-        spawn_thread("discovery-jobs", move || loop {
+        spawn_supervised("discovery-jobs", move || loop {
             if let Err(e) = manager.run_health_check() {
                 debug!("health check failed: {:?}", e);
             }
@@ -408,11 +494,14 @@ impl Server {
 }
 
 impl ServerAddr {
-    fn resolve(host: &str) -> Result<Self> {
+    // With `proxied`, the hostnames are left for the `--socks-proxy` to resolve
+    fn resolve(host: &str, proxied: bool) -> Result<Self> {
         Ok(if host.ends_with(".onion") {
             ServerAddr::Onion(host.into())
         } else if let Ok(ip) = IpAddr::from_str(host) {
             ServerAddr::Clearnet(ip)
+        } else if proxied {
+            ServerAddr::Proxied(host.into())
         } else {
             let ip = format!("{}:1", host)
                 .to_socket_addrs()
@@ -429,7 +518,9 @@ impl fmt::Display for ServerAddr {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
             ServerAddr::Clearnet(ip) => write!(f, "{}", ip),
-            ServerAddr::Onion(hostname) => write!(f, "{}", hostname),
+            ServerAddr::Onion(hostname) | ServerAddr::Proxied(hostname) => {
+                write!(f, "{}", hostname)
+            }
         }
     }
 }
@@ -444,24 +535,24 @@ impl serde::Serialize for ServerAddr {
 }
 
 impl HealthCheck {
-    fn new(
-        addr: ServerAddr,
-        hostname: Hostname,
-        service: Service,
-        added_by: Option<IpAddr>,
-    ) -> Self {
+    fn new(addr: ServerAddr, hostname: Hostname, service: Service, is_default: bool) -> Self {
         HealthCheck {
             addr,
             hostname,
             service,
-            is_default: added_by.is_none(),
-            added_by,
+            is_default,
+            added_by: None,
             last_check: None,
             last_healthy: None,
             consecutive_failures: 0,
         }
     }
 
+    fn added_by(mut self, added_by: IpAddr) -> Self {
+        self.added_by = Some(added_by);
+        self
+    }
+
     fn is_healthy(&self) -> bool {
         match (self.last_check, self.last_healthy) {
             (Some(last_check), Some(last_healthy)) => last_check == last_healthy,
@@ -504,9 +595,44 @@ impl fmt::Display for Service {
     }
 }
 
+impl FromStr for Service {
+    type Err = crate::errors::Error;
+
+    // "t50001" or "s50002", with the port defaulting to 50001 and 50002 respectively
+    fn from_str(s: &str) -> Result<Self> {
+        let port = |port: &str, default: Port| -> Result<Port> {
+            match port {
+                "" => Ok(default),
+                port => port.parse().chain_err(|| format!("invalid port {}", port)),
+            }
+        };
+        if let Some(p) = s.strip_prefix('t') {
+            Ok(Service::Tcp(port(p, DEFAULT_TCP_PORT)?))
+        } else if let Some(p) = s.strip_prefix('s') {
+            Ok(Service::Ssl(port(p, DEFAULT_SSL_PORT)?))
+        } else {
+            bail!("invalid service {}", s)
+        }
+    }
+}
+
+// parse a `[ip, hostname, [features...]]` entry of a `server.peers.subscribe` response
+fn parse_peer_entry(entry: &Value) -> Option<(Hostname, Vec<Service>)> {
+    let entry = entry.as_array()?;
+    let hostname = entry.get(1)?.as_str()?.to_lowercase();
+    let services = entry
+        .get(2)?
+        .as_array()?
+        .iter()
+        .filter_map(|feature| feature.as_str()?.parse().ok())
+        .collect::<Vec<Service>>();
+    (hostname.len() <= 100 && !services.is_empty()).then(|| (hostname, services))
+}
+
 fn is_remote_addr(addr: &ServerAddr) -> bool {
     match addr {
-        ServerAddr::Onion(_) => true,
+        // the proxy decides which addresses it connects to
+        ServerAddr::Onion(_) | ServerAddr::Proxied(_) => true,
         ServerAddr::Clearnet(ip) => {
             !ip.is_loopback()
                 && !ip.is_unspecified()
@@ -528,6 +654,44 @@ mod tests {
 
     const PROTOCOL_VERSION: ProtocolVersion = ProtocolVersion::new(1, 4);
 
+    #[test]
+    fn test_parse_peer_entry() {
+        let entry = json!([
+            "1.2.3.4",
+            "Electrum.Example.com",
+            ["v1.4", "s", "t50011", "p10000"]
+        ]);
+        assert_eq!(
+            parse_peer_entry(&entry),
+            Some((
+                "electrum.example.com".to_string(),
+                vec![Service::Ssl(50002), Service::Tcp(50011)]
+            ))
+        );
+        assert_eq!(
+            parse_peer_entry(&json!(["1.2.3.4", "example.com", ["v1.4"]])),
+            None
+        );
+        assert_eq!(parse_peer_entry(&json!("example.com")), None);
+        assert!("x50001".parse::<Service>().is_err());
+    }
+
+    #[test]
+    fn test_resolve_proxied() {
+        assert_eq!(
+            ServerAddr::resolve("electrum.example.com", true).unwrap(),
+            ServerAddr::Proxied("electrum.example.com".into())
+        );
+        assert_eq!(
+            ServerAddr::resolve("1.2.3.4", true).unwrap(),
+            ServerAddr::Clearnet("1.2.3.4".parse().unwrap())
+        );
+        assert_eq!(
+            ServerAddr::resolve("abc.onion", true).unwrap(),
+            ServerAddr::Onion("abc.onion".into())
+        );
+    }
+
     #[test]
     fn test() -> Result<()> {
         stderrlog::new().verbosity(4).init().unwrap();
@@ -547,6 +711,7 @@ mod tests {
             PROTOCOL_VERSION,
             false,
             None,
+            None,
         ));
         discovery
             .add_default_server(
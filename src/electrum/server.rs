@@ -1,30 +1,40 @@
 use std::collections::HashMap;
-use std::io::{BufRead, BufReader, Write};
+use std::io::{self, BufRead, BufReader, Write};
+use std::iter;
 use std::net::{Shutdown, SocketAddr, TcpListener, TcpStream};
 use std::sync::mpsc::{self, Receiver, Sender, SyncSender, TrySendError};
 use std::sync::{Arc, Mutex, RwLock};
 use std::thread;
-use std::time::Instant;
+use std::time::{Duration, Instant};
 
 use bitcoin::hashes::sha256d::Hash as Sha256dHash;
-use bitcoin::hex::DisplayHex;
+use bitcoin::hashes::Hash;
+use bitcoin::hex::{DisplayHex, FromHex};
 use crypto::digest::Digest;
 use crypto::sha2::Sha256;
 use error_chain::ChainedError;
 use serde_json::{from_str, Value};
+use socket2::{SockRef, TcpKeepalive};
 
 use electrs_macros::trace;
 
-use crate::chain::{deserialize, Script, Transaction, TxIn, TxOut, Txid};
+use crate::chain::{deserialize, BlockHash, Script, Transaction, TxIn, TxOut, Txid};
 use crate::config::{Config, RpcLogging};
 use crate::electrum::{get_electrum_height, ProtocolVersion};
 use crate::errors::*;
-use crate::metrics::{Gauge, HistogramOpts, HistogramVec, MetricOpts, Metrics};
-use crate::new_index::{Query, Utxo};
+use crate::metrics::{
+    Counter, Gauge, HistogramOpts, HistogramVec, MetricOpts, Metrics, SlowQueryLog,
+};
+use crate::new_index::db::ReadStats;
+use crate::new_index::{compute_script_hash, HistoryCursor, Query, Utxo};
 use crate::util::electrum_merkle::{get_header_merkle_proof, get_id_from_pos, get_tx_merkle_proof};
+use crate::util::pool::{ThreadPool, ThreadPools};
+use crate::util::proxy_protocol;
+use crate::util::ratelimit::RateLimiter;
+use crate::util::supervisor::{spawn_critical, spawn_supervised};
 use crate::util::{
-    create_socket, is_coinbase, spawn_thread, BlockId, BoolThen, Channel, FullHash, HeaderEntry,
-    ScriptToAddr, ScriptToAsm,
+    create_socket, is_coinbase, new_request_id, spawn_thread, systemd, BlockId, BoolThen, Channel,
+    FullHash, HeaderEntry, ScriptToAddr, ScriptToAsm,
 };
 #[cfg(not(feature = "liquid"))]
 use bitcoin::consensus::encode::serialize_hex;
@@ -35,21 +45,47 @@ const ELECTRS_VERSION: &str = env!("CARGO_PKG_VERSION");
 const PROTOCOL_VERSION: ProtocolVersion = ProtocolVersion::new(1, 4);
 const MAX_HEADERS: usize = 2016;
 const MAX_ARRAY_BATCH: usize = 20;
+const PROXY_HEADER_TIMEOUT: Duration = Duration::from_secs(10);
+const KEEPALIVE_INTERVAL: Duration = Duration::from_secs(30);
+
+// The code of the errors caused by the request, like the lookups of histories over the limits
+// (as returned by ElectrumX)
+const BAD_REQUEST: i64 = 1;
 
 #[cfg(feature = "electrum-discovery")]
-use crate::electrum::{DiscoveryManager, ServerFeatures};
+use crate::electrum::{DiscoveryManager, ServerFeatures, ServerPorts};
 
 // TODO: Sha256dHash should be a generic hash-container (since script hash is single SHA256)
 fn hash_from_value(val: Option<&Value>) -> Result<Sha256dHash> {
-    let script_hash = val.chain_err(|| "missing hash")?;
-    let script_hash = script_hash.as_str().chain_err(|| "non-string hash")?;
-    let script_hash = script_hash.parse().chain_err(|| "non-hex hash")?;
+    let script_hash = val.chain_err(|| bad_param("missing hash"))?;
+    let script_hash = script_hash
+        .as_str()
+        .chain_err(|| bad_param("non-string hash"))?;
+    let script_hash = script_hash
+        .parse()
+        .chain_err(|| bad_param("non-hex hash"))?;
     Ok(script_hash)
 }
 
+// The error of malformed request params, which counts towards banning the client
+fn bad_param(msg: &str) -> ErrorKind {
+    ErrorKind::InvalidRequest(msg.to_string())
+}
+
+fn script_from_value(val: Option<&Value>) -> Result<Script> {
+    let script = val.chain_err(|| bad_param("missing script"))?;
+    let script = script
+        .as_str()
+        .chain_err(|| bad_param("non-string script"))?;
+    let script = Vec::<u8>::from_hex(script).chain_err(|| bad_param("non-hex script"))?;
+    Ok(Script::from(script))
+}
+
 fn usize_from_value(val: Option<&Value>, name: &str) -> Result<usize> {
-    let val = val.chain_err(|| format!("missing {}", name))?;
-    let val = val.as_u64().chain_err(|| format!("non-integer {}", name))?;
+    let val = val.chain_err(|| bad_param(&format!("missing {}", name)))?;
+    let val = val
+        .as_u64()
+        .chain_err(|| bad_param(&format!("non-integer {}", name)))?;
     Ok(val as usize)
 }
 
@@ -61,8 +97,10 @@ fn usize_from_value_or(val: Option<&Value>, name: &str, default: usize) -> Resul
 }
 
 fn bool_from_value(val: Option<&Value>, name: &str) -> Result<bool> {
-    let val = val.chain_err(|| format!("missing {}", name))?;
-    let val = val.as_bool().chain_err(|| format!("not a bool {}", name))?;
+    let val = val.chain_err(|| bad_param(&format!("missing {}", name)))?;
+    let val = val
+        .as_bool()
+        .chain_err(|| bad_param(&format!("not a bool {}", name)))?;
     Ok(val)
 }
 
@@ -253,6 +291,13 @@ fn get_status_hash(txs: Vec<(Txid, Option<BlockId>)>, query: &Query) -> Option<F
     }
 }
 
+// Get the status of a scripthash, as sent to its subscribers
+fn get_status(query: &Query, script_hash: &Sha256dHash, txs_limit: usize) -> Result<Value> {
+    let history_txids = get_history(query, &script_hash[..], txs_limit)?;
+    Ok(get_status_hash(history_txids, query)
+        .map_or(Value::Null, |h| json!(h.to_lower_hex_string())))
+}
+
 macro_rules! conditionally_log_rpc_event {
     ($self:ident, $event:expr) => {
         if $self.rpc_logging.enabled {
@@ -265,15 +310,20 @@ struct Connection {
     query: Arc<Query>,
     last_header_entry: Option<HeaderEntry>,
     status_hashes: HashMap<Sha256dHash, Value>, // ScriptHash -> StatusHash
+    script_status_hashes: HashMap<Sha256dHash, (Value, Value)>, // ScriptHash -> (Script, Status)
     stream: TcpStream,
     addr: SocketAddr,
     sender: SyncSender<Message>,
     stats: Arc<Stats>,
-    txs_limit: usize,
+    limits: ResultLimits,
     #[cfg(feature = "electrum-discovery")]
     discovery: Option<Arc<DiscoveryManager>>,
     rpc_logging: RpcLogging,
     salt: String,
+    rate_limiter: Arc<RateLimiter>,
+    connected_at: Instant,
+    max_lifetime: Option<Duration>,
+    notify_pool: ThreadPool,
 }
 
 impl Connection {
@@ -283,24 +333,32 @@ impl Connection {
         addr: SocketAddr,
         sender: SyncSender<Message>,
         stats: Arc<Stats>,
-        txs_limit: usize,
+        limits: ResultLimits,
         #[cfg(feature = "electrum-discovery")] discovery: Option<Arc<DiscoveryManager>>,
         rpc_logging: RpcLogging,
         salt: String,
+        rate_limiter: Arc<RateLimiter>,
+        max_lifetime: Option<Duration>,
+        notify_pool: ThreadPool,
     ) -> Connection {
         Connection {
             query,
             last_header_entry: None, // disable header subscription for now
             status_hashes: HashMap::new(),
+            script_status_hashes: HashMap::new(),
             stream,
             addr,
             sender,
             stats,
-            txs_limit,
+            limits,
             #[cfg(feature = "electrum-discovery")]
             discovery,
             rpc_logging,
             salt,
+            rate_limiter,
+            connected_at: Instant::now(),
+            max_lifetime,
+            notify_pool,
         }
     }
 
@@ -320,7 +378,7 @@ impl Connection {
     }
 
     fn server_banner(&self) -> Result<Value> {
-        Ok(json!(self.query.config().electrum_banner.clone()))
+        Ok(json!(self.query.config().runtime().electrum_banner))
     }
 
     #[cfg(feature = "electrum-discovery")]
@@ -358,9 +416,10 @@ impl Connection {
 
         let features = params
             .get(0)
-            .chain_err(|| "missing features param")?
+            .chain_err(|| bad_param("missing features param"))?
             .clone();
-        let features = serde_json::from_value(features).chain_err(|| "invalid features")?;
+        let features =
+            serde_json::from_value(features).chain_err(|| bad_param("invalid features"))?;
 
         discovery.add_server_request(self.addr.ip(), features)?;
         Ok(json!(true))
@@ -393,6 +452,39 @@ impl Connection {
         }))
     }
 
+    // A non-standard extension returning the raw block by hash or height (of the best chain), for
+    // the blocks of up to `--electrum-max-block-size` bytes (disabled if 0)
+    fn blockchain_block_get(&self, params: &[Value]) -> Result<Value> {
+        ensure!(
+            self.limits.block_size > 0,
+            "blockchain.block.get is disabled"
+        );
+        let chain = self.query.chain();
+        let block = params
+            .get(0)
+            .chain_err(|| bad_param("missing block hash or height"))?;
+        let hash = match block.as_u64() {
+            Some(height) => *chain
+                .header_by_height(height as usize)
+                .chain_err(|| "missing block")?
+                .hash(),
+            None => BlockHash::from(
+                hash_from_value(Some(block)).chain_err(|| bad_param("bad block hash"))?,
+            ),
+        };
+
+        let meta = chain.get_block_meta(&hash)?.chain_err(|| "missing block")?;
+        ensure!(
+            meta.size as usize <= self.limits.block_size,
+            "block {} of {} bytes is over the limit of {} bytes",
+            hash,
+            meta.size,
+            self.limits.block_size
+        );
+        let raw = chain.get_block_raw(&hash)?.chain_err(|| "missing block")?;
+        Ok(json!(raw.to_lower_hex_string()))
+    }
+
     fn blockchain_block_headers(&self, params: &[Value]) -> Result<Value> {
         let start_height = usize_from_value(params.get(0), "start_height")?;
         let count = MAX_HEADERS.min(usize_from_value(params.get(1), "count")?);
@@ -445,21 +537,28 @@ impl Connection {
         Ok(json!(relayfee / 100_000f64))
     }
 
-    fn blockchain_scripthash_subscribe(&mut self, params: &[Value]) -> Result<Value> {
-        let script_hash = hash_from_value(params.get(0)).chain_err(|| "bad script_hash")?;
+    // The scripthash param, refusing the ones of the `--blocklist`
+    fn script_hash_param(&self, params: &[Value]) -> Result<Sha256dHash> {
+        let script_hash =
+            hash_from_value(params.get(0)).chain_err(|| bad_param("bad script_hash"))?;
+        self.query.check_scripthash(&script_hash[..])?;
+        Ok(script_hash)
+    }
 
-        let history_txids = get_history(&self.query, &script_hash[..], self.txs_limit)?;
-        let status_hash = get_status_hash(history_txids, &self.query)
-            .map_or(Value::Null, |h| json!(h.to_lower_hex_string()));
+    fn blockchain_scripthash_subscribe(&mut self, params: &[Value]) -> Result<Value> {
+        let script_hash = self.script_hash_param(params)?;
+        let status_hash = get_status(&self.query, &script_hash, self.limits.txs)?;
 
         if let None = self.status_hashes.insert(script_hash, status_hash.clone()) {
             self.stats.subscriptions.inc();
+            self.query.clients().record_subscription(self.addr.ip());
         }
         Ok(status_hash)
     }
 
     fn blockchain_scripthash_unsubscribe(&mut self, params: &[Value]) -> Result<Value> {
-        let script_hash = hash_from_value(params.get(0)).chain_err(|| "bad script_hash")?;
+        let script_hash =
+            hash_from_value(params.get(0)).chain_err(|| bad_param("bad script_hash"))?;
 
         match self.status_hashes.remove(&script_hash) {
             None => Ok(json!(false)),
@@ -470,10 +569,51 @@ impl Connection {
         }
     }
 
+    // Subscribe to the exact scriptPubKey bytes rather than to its hash, notably for watching
+    // OP_RETURN outputs (which have no address). Notifications are sent as
+    // `blockchain.script.subscribe` with the script hex and the status hash.
+    fn blockchain_script_subscribe(&mut self, params: &[Value]) -> Result<Value> {
+        let script = script_from_value(params.get(0)).chain_err(|| bad_param("bad script"))?;
+        #[cfg(not(feature = "liquid"))]
+        let unspendable = script.is_op_return();
+        #[cfg(feature = "liquid")]
+        let unspendable = script.is_provably_unspendable();
+        if unspendable && !self.query.config().index_unspendables {
+            bail!("unspendable scripts are not indexed (requires --index-unspendables)");
+        }
+
+        let script_hash = Sha256dHash::from_byte_array(compute_script_hash(&script));
+        self.query.check_scripthash(&script_hash[..])?;
+        let status_hash = get_status(&self.query, &script_hash, self.limits.txs)?;
+        let script = json!(script.as_bytes().to_lower_hex_string());
+
+        if let None = self
+            .script_status_hashes
+            .insert(script_hash, (script, status_hash.clone()))
+        {
+            self.stats.subscriptions.inc();
+            self.query.clients().record_subscription(self.addr.ip());
+        }
+        Ok(status_hash)
+    }
+
+    fn blockchain_script_unsubscribe(&mut self, params: &[Value]) -> Result<Value> {
+        let script = script_from_value(params.get(0)).chain_err(|| bad_param("bad script"))?;
+        let script_hash = Sha256dHash::from_byte_array(compute_script_hash(&script));
+
+        match self.script_status_hashes.remove(&script_hash) {
+            None => Ok(json!(false)),
+            Some(_) => {
+                self.stats.subscriptions.dec();
+                Ok(json!(true))
+            }
+        }
+    }
+
     #[cfg(not(feature = "liquid"))]
     fn blockchain_scripthash_get_balance(&self, params: &[Value]) -> Result<Value> {
-        let script_hash = hash_from_value(params.get(0)).chain_err(|| "bad script_hash")?;
-        let (chain_stats, mempool_stats) = self.query.stats(&script_hash[..]);
+        let script_hash = self.script_hash_param(params)?;
+        let (chain_stats, mempool_stats) = self.query.stats(&script_hash[..])?;
 
         Ok(json!({
             "confirmed": chain_stats.funded_txo_sum - chain_stats.spent_txo_sum,
@@ -482,10 +622,92 @@ impl Connection {
     }
 
     fn blockchain_scripthash_get_history(&self, params: &[Value]) -> Result<Value> {
-        let script_hash = hash_from_value(params.get(0)).chain_err(|| "bad script_hash")?;
-        let history_txids = get_history(&self.query, &script_hash[..], self.txs_limit)?;
+        let script_hash = self.script_hash_param(params)?;
+        let history_txids = get_history(&self.query, &script_hash[..], self.limits.txs)?;
+
+        Ok(json!(self.history_results(history_txids)))
+    }
+
+    // A non-standard extension of `blockchain.scripthash.get_history`, for the clients of the
+    // histories over the limit. Returns the history by pages of `--electrum-history-page-size`,
+    // each following the `tx_hash` ending the previous one (passed as `next`, null for the last
+    // page).
+    fn blockchain_scripthash_get_history_page(&self, params: &[Value]) -> Result<Value> {
+        let script_hash = self.script_hash_param(params)?;
+        let (history, next) =
+            self.history_page(&script_hash, params.get(1), 0, self.limits.history_page)?;
+        let next = next.map(|cursor| match cursor {
+            HistoryCursor::AfterConfirmed(_, txid) | HistoryCursor::AfterMempool(txid) => txid,
+            HistoryCursor::FromHeight(_) => unreachable!("pages end after a transaction"),
+        });
+
+        Ok(json!({
+            "history": history,
+            "next": next,
+        }))
+    }
+
+    // A non-standard extension of `blockchain.scripthash.get_history`, paginated by height for the
+    // wallet backends reading huge histories. Returns the history from `from_height` on (0 by
+    // default), or following the `after` transaction, by pages of up to `limit` transactions (at
+    // most `--electrum-txs-limit`). The `next_from_height` and `next_after` of the next page are
+    // the height (past the tip for the unconfirmed transactions) and the `tx_hash` ending this
+    // one, both null for the last page.
+    fn blockchain_scripthash_get_history_paginated(&self, params: &[Value]) -> Result<Value> {
+        let script_hash = self.script_hash_param(params)?;
+        let from_height = usize_from_value_or(params.get(1), "from_height", 0)?;
+        let limit = usize_from_value_or(params.get(2), "limit", self.limits.history_page)?;
+        ensure!(
+            limit > 0 && limit <= self.limits.txs,
+            "limit must be between 1 and {}",
+            self.limits.txs
+        );
+        let (history, next) = self.history_page(&script_hash, params.get(3), from_height, limit)?;
+        let (next_from_height, next_after) = match next {
+            Some(HistoryCursor::AfterConfirmed(height, txid)) => (Some(height), Some(txid)),
+            Some(HistoryCursor::AfterMempool(txid)) => {
+                (Some(self.query.chain().best_height() + 1), Some(txid))
+            }
+            Some(HistoryCursor::FromHeight(_)) => unreachable!("pages end after a transaction"),
+            None => (None, None),
+        };
+
+        Ok(json!({
+            "history": history,
+            "next_from_height": next_from_height,
+            "next_after": next_after,
+        }))
+    }
+
+    // The page of up to `limit` transactions of the history of both paging methods, following the
+    // `after` transaction if any or starting at `from_height` otherwise, along with the cursor of
+    // the next page
+    fn history_page(
+        &self,
+        script_hash: &Sha256dHash,
+        after: Option<&Value>,
+        from_height: usize,
+        limit: usize,
+    ) -> Result<(Vec<GetHistoryResult>, Option<HistoryCursor>)> {
+        let cursor = match after {
+            None | Some(Value::Null) => HistoryCursor::FromHeight(from_height),
+            after => {
+                let txid =
+                    Txid::from(hash_from_value(after).chain_err(|| bad_param("bad cursor"))?);
+                self.query.history_cursor(&txid)?
+            }
+        };
+        let (history_txids, next) =
+            self.query
+                .history_txids_page(&script_hash[..], &cursor, limit)?;
+        Ok((self.history_results(history_txids), next))
+    }
 
-        Ok(json!(history_txids
+    fn history_results(
+        &self,
+        history_txids: Vec<(Txid, Option<BlockId>)>,
+    ) -> Vec<GetHistoryResult> {
+        history_txids
             .into_iter()
             .map(|(txid, blockid)| {
                 let is_mempool = blockid.is_none();
@@ -496,12 +718,14 @@ impl Connection {
                 let height = get_electrum_height(blockid, has_unconfirmed_parents);
                 GetHistoryResult { txid, height, fee }
             })
-            .collect::<Vec<_>>()))
+            .collect()
     }
 
     fn blockchain_scripthash_listunspent(&self, params: &[Value]) -> Result<Value> {
-        let script_hash = hash_from_value(params.get(0)).chain_err(|| "bad script_hash")?;
-        let utxos = self.query.utxo(&script_hash[..])?;
+        let script_hash = self.script_hash_param(params)?;
+        let utxos = self
+            .query
+            .utxo_limited(&script_hash[..], self.limits.utxos)?;
 
         let to_json = |utxo: Utxo| {
             let json = json!({
@@ -528,8 +752,11 @@ impl Connection {
     }
 
     fn blockchain_transaction_broadcast(&self, params: &[Value]) -> Result<Value> {
-        let tx = params.get(0).chain_err(|| "missing tx")?;
-        let tx = tx.as_str().chain_err(|| "non-string tx")?.to_string();
+        let tx = params.get(0).chain_err(|| bad_param("missing tx"))?;
+        let tx = tx
+            .as_str()
+            .chain_err(|| bad_param("non-string tx"))?
+            .to_string();
         let txid = self.query.broadcast_raw(&tx)?;
         if let Err(e) = self.sender.try_send(Message::PeriodicUpdate) {
             warn!("failed to issue PeriodicUpdate after broadcast: {}", e);
@@ -538,22 +765,25 @@ impl Connection {
     }
 
     fn blockchain_transaction_get(&self, params: &[Value]) -> Result<Value> {
-        let tx_hash = Txid::from(hash_from_value(params.get(0)).chain_err(|| "bad tx_hash")?);
+        let tx_hash =
+            Txid::from(hash_from_value(params.get(0)).chain_err(|| bad_param("bad tx_hash"))?);
         let verbose = match params.get(1) {
-            Some(value) => value.as_bool().chain_err(|| "non-bool verbose value")?,
+            Some(value) => value
+                .as_bool()
+                .chain_err(|| bad_param("non-bool verbose value"))?,
             None => false,
         };
 
         let rawtx = self
             .query
-            .lookup_raw_txn(&tx_hash)
+            .lookup_raw_txn(&tx_hash)?
             .chain_err(|| "missing transaction")?;
         let raw_hex = rawtx.to_lower_hex_string();
 
         if verbose {
             let tx: Transaction =
                 deserialize(&rawtx).chain_err(|| "failed to parse transaction")?;
-            let blockid = self.query.chain().tx_confirming_block(&tx_hash);
+            let blockid = self.query.chain().tx_confirming_block(&tx_hash)?;
             return Ok(build_verbose_transaction(
                 &self.query,
                 &tx,
@@ -567,12 +797,13 @@ impl Connection {
 
     #[trace]
     fn blockchain_transaction_get_merkle(&self, params: &[Value]) -> Result<Value> {
-        let txid = Txid::from(hash_from_value(params.get(0)).chain_err(|| "bad tx_hash")?);
+        let txid =
+            Txid::from(hash_from_value(params.get(0)).chain_err(|| bad_param("bad tx_hash"))?);
         let height = usize_from_value(params.get(1), "height")?;
         let blockid = self
             .query
             .chain()
-            .tx_confirming_block(&txid)
+            .tx_confirming_block(&txid)?
             .ok_or_else(|| "tx not found or is unconfirmed")?;
         if blockid.height != height {
             bail!("invalid confirmation height provided");
@@ -603,8 +834,14 @@ impl Connection {
         }))
     }
 
-    #[trace(method = %method)]
-    fn handle_command(&mut self, method: &str, params: &[Value], id: &Value) -> Result<Value> {
+    #[trace(method = %method, request_id = %request_id)]
+    fn handle_command(
+        &mut self,
+        method: &str,
+        params: &[Value],
+        id: &Value,
+        request_id: &str,
+    ) -> Result<Value> {
         let timer = self
             .stats
             .latency
@@ -612,6 +849,7 @@ impl Connection {
             .start_timer();
 
         let result = match method {
+            "blockchain.block.get" => self.blockchain_block_get(&params),
             "blockchain.block.header" => self.blockchain_block_header(&params),
             "blockchain.block.headers" => self.blockchain_block_headers(&params),
             "blockchain.estimatefee" => self.blockchain_estimatefee(&params),
@@ -620,9 +858,17 @@ impl Connection {
             #[cfg(not(feature = "liquid"))]
             "blockchain.scripthash.get_balance" => self.blockchain_scripthash_get_balance(&params),
             "blockchain.scripthash.get_history" => self.blockchain_scripthash_get_history(&params),
+            "blockchain.scripthash.get_history_page" => {
+                self.blockchain_scripthash_get_history_page(&params)
+            }
+            "blockchain.scripthash.get_history_paginated" => {
+                self.blockchain_scripthash_get_history_paginated(&params)
+            }
             "blockchain.scripthash.listunspent" => self.blockchain_scripthash_listunspent(&params),
             "blockchain.scripthash.subscribe" => self.blockchain_scripthash_subscribe(&params),
             "blockchain.scripthash.unsubscribe" => self.blockchain_scripthash_unsubscribe(&params),
+            "blockchain.script.subscribe" => self.blockchain_script_subscribe(&params),
+            "blockchain.script.unsubscribe" => self.blockchain_script_unsubscribe(&params),
             "blockchain.transaction.broadcast" => self.blockchain_transaction_broadcast(&params),
             "blockchain.transaction.get" => self.blockchain_transaction_get(&params),
             "blockchain.transaction.get_merkle" => self.blockchain_transaction_get_merkle(&params),
@@ -641,7 +887,10 @@ impl Connection {
             #[cfg(feature = "electrum-discovery")]
             "server.add_peer" => self.server_add_peer(&params),
 
-            &_ => bail!("unknown method {} {:?}", method, params),
+            &_ => bail!(ErrorKind::InvalidRequest(format!(
+                "unknown method {} {:?}",
+                method, params
+            ))),
         };
         timer.observe_duration();
         // TODO: return application errors should be sent to the client
@@ -649,13 +898,19 @@ impl Connection {
             Ok(result) => json!({"jsonrpc": "2.0", "id": id, "result": result}),
             Err(e) => {
                 warn!(
-                    "rpc #{} {} {:?} failed: {}",
+                    "[{}] rpc #{} {} {:?} failed: {}",
+                    request_id,
                     id,
                     method,
                     params,
                     e.display_chain()
                 );
-                json!({"jsonrpc": "2.0", "id": id, "error": format!("{}", e)})
+                // only protocol abuse counts, rather than the requests failing for other reasons
+                // (like unknown transactions or rejected broadcasts)
+                if let ErrorKind::InvalidRequest(_) = e.kind() {
+                    self.record_error()?;
+                }
+                json!({"jsonrpc": "2.0", "id": id, "error": rpc_error(method, &e)})
             }
         })
     }
@@ -681,9 +936,7 @@ impl Connection {
             }
         }
         for (script_hash, status_hash) in self.status_hashes.iter_mut() {
-            let history_txids = get_history(&self.query, &script_hash[..], self.txs_limit)?;
-            let new_status_hash = get_status_hash(history_txids, &self.query)
-                .map_or(Value::Null, |h| json!(h.to_lower_hex_string()));
+            let new_status_hash = get_status(&self.query, script_hash, self.limits.txs)?;
             if new_status_hash == *status_hash {
                 continue;
             }
@@ -693,6 +946,17 @@ impl Connection {
                 "params": [script_hash, new_status_hash]}));
             *status_hash = new_status_hash;
         }
+        for (script_hash, (script, status_hash)) in self.script_status_hashes.iter_mut() {
+            let new_status_hash = get_status(&self.query, script_hash, self.limits.txs)?;
+            if new_status_hash == *status_hash {
+                continue;
+            }
+            result.push(json!({
+                "jsonrpc": "2.0",
+                "method": "blockchain.script.subscribe",
+                "params": [script, new_status_hash]}));
+            *status_hash = new_status_hash;
+        }
         timer.observe_duration();
         Ok(result)
     }
@@ -722,6 +986,16 @@ impl Connection {
         println!("{}", log);
     }
 
+    // Count an invalid request, closing the connection if the client got banned for it
+    fn record_error(&self) -> Result<()> {
+        if self.query.clients().record_error(self.addr.ip()) {
+            self.stats.bans.inc();
+            warn!("[{}] banned after too many invalid requests", self.addr);
+            bail!("client banned after too many invalid requests");
+        }
+        Ok(())
+    }
+
     fn send_values(&mut self, values: &[Value]) -> Result<()> {
         for value in values {
             let line = value.to_string() + "\n";
@@ -738,9 +1012,21 @@ impl Connection {
         loop {
             let msg = receiver.recv().chain_err(|| "channel closed")?;
             trace!("RPC {:?}", msg);
+            if self.max_lifetime.map_or(false, |max_lifetime| {
+                self.connected_at.elapsed() >= max_lifetime
+            }) {
+                debug!("[{}] closing connection at its max lifetime", self.addr);
+                return Ok(());
+            }
             match msg {
                 Message::Request(line) => {
-                    let cmd: Value = from_str(&line).chain_err(|| "invalid JSON format")?;
+                    let cmd: Value = match from_str(&line) {
+                        Ok(cmd) => cmd,
+                        Err(e) => {
+                            self.record_error()?;
+                            return Err(e).chain_err(|| "invalid JSON format");
+                        }
+                    };
                     if let Value::Array(arr) = cmd {
                         if arr.len() > MAX_ARRAY_BATCH {
                             bail!(
@@ -761,8 +1047,10 @@ impl Connection {
                     }
                 }
                 Message::PeriodicUpdate => {
-                    let values = self
-                        .update_subscriptions()
+                    // all the clients are notified at once, their updates take turns on the pool
+                    let notify_pool = self.notify_pool.clone();
+                    let values = notify_pool
+                        .install(|| self.update_subscriptions())
                         .chain_err(|| "failed to update subscriptions")?;
                     self.send_values(&values)?
                 }
@@ -773,6 +1061,7 @@ impl Connection {
 
     fn handle_value(&mut self, cmd: Value, empty_params: &Value) -> Result<Value> {
         let start_time = Instant::now();
+        let request_id = new_request_id();
         Ok(
             match (
                 cmd.get("method"),
@@ -780,7 +1069,33 @@ impl Connection {
                 cmd.get("id"),
             ) {
                 (Some(&Value::String(ref method)), &Value::Array(ref params), Some(ref id)) => {
-                    let reply = self.handle_command(method, params, id)?;
+                    self.query.clients().record_request(self.addr.ip());
+                    let runtime = self.query.config().runtime();
+                    let (rate, burst) = (runtime.rate_limit, runtime.rate_limit_burst);
+                    if !self.rate_limiter.check(self.addr.ip(), rate, burst) {
+                        self.stats.rate_limited.inc();
+                        return Ok(
+                            json!({"jsonrpc": "2.0", "id": id, "error": "rate limit exceeded"}),
+                        );
+                    }
+                    let load = self.query.chain().load().electrum_request();
+                    let (reply, reads) =
+                        ReadStats::measure(|| self.handle_command(method, params, id, &request_id));
+                    drop(load);
+                    self.stats.slow_queries.observe(
+                        method,
+                        &request_id,
+                        start_time.elapsed(),
+                        reads,
+                        || {
+                            if self.rpc_logging.hide_params {
+                                method.to_string()
+                            } else {
+                                format!("{} {}", method, params)
+                            }
+                        },
+                    );
+                    let reply = reply?;
 
                     conditionally_log_rpc_event!(
                         self,
@@ -796,12 +1111,14 @@ impl Connection {
                             "response_size": reply.to_string().as_bytes().len(),
                             "duration_micros": start_time.elapsed().as_micros(),
                             "id": id,
+                            "request_id": request_id,
                         })
                     );
 
                     reply
                 }
                 _ => {
+                    self.record_error()?;
                     bail!("invalid command: {}", cmd)
                 }
             },
@@ -812,9 +1129,14 @@ impl Connection {
     fn parse_requests(mut reader: BufReader<TcpStream>, tx: &SyncSender<Message>) -> Result<()> {
         loop {
             let mut line = Vec::<u8>::new();
-            reader
-                .read_until(b'\n', &mut line)
-                .chain_err(|| "failed to read a request")?;
+            if let Err(e) = reader.read_until(b'\n', &mut line) {
+                // the read timeout is only set with --electrum-idle-timeout
+                if let io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut = e.kind() {
+                    debug!("closing idle connection");
+                    return Ok(());
+                }
+                return Err(e).chain_err(|| "failed to read a request");
+            }
             if line.is_empty() {
                 return Ok(());
             } else {
@@ -859,7 +1181,7 @@ impl Connection {
         self.stats.clients.dec();
         self.stats
             .subscriptions
-            .sub(self.status_hashes.len() as i64);
+            .sub((self.status_hashes.len() + self.script_status_hashes.len()) as i64);
 
         debug!("[{}] shutting down connection", self.addr);
         conditionally_log_rpc_event!(self, json!({ "event": "connection_closed" }));
@@ -871,6 +1193,43 @@ impl Connection {
     }
 }
 
+/// The limits of the results of the Electrum methods, which fail past them rather than
+/// returning truncated results
+#[derive(Clone, Copy)]
+struct ResultLimits {
+    // the transactions of the histories and statuses
+    txs: usize,
+    // the utxos of `listunspent`
+    utxos: usize,
+    // the transactions of a `get_history_page` page
+    history_page: usize,
+    // the bytes of the blocks returned by `blockchain.block.get`, 0 if disabled
+    block_size: usize,
+}
+
+impl ResultLimits {
+    fn new(config: &Config) -> Self {
+        ResultLimits {
+            txs: config.electrum_txs_limit,
+            utxos: config.electrum_utxos_limit,
+            history_page: config.electrum_history_page_size,
+            block_size: config.electrum_max_block_size,
+        }
+    }
+}
+
+// The error of a failed request: the results over the limits get the standard code and message
+// that the wallets recognize, the other errors are reported as a plain message
+fn rpc_error(method: &str, e: &Error) -> Value {
+    match e.kind() {
+        ErrorKind::TooPopular if method == "blockchain.scripthash.listunspent" => {
+            json!({"code": BAD_REQUEST, "message": "too many unspent outputs"})
+        }
+        ErrorKind::TooPopular => json!({"code": BAD_REQUEST, "message": "history too large"}),
+        _ => json!(format!("{}", e)),
+    }
+}
+
 #[trace]
 fn get_history(
     query: &Query,
@@ -878,7 +1237,7 @@ fn get_history(
     txs_limit: usize,
 ) -> Result<Vec<(Txid, Option<BlockId>)>> {
     // to avoid silently trunacting history entries, ask for one extra more than the limit and fail if it exists
-    let history_txids = query.history_txids(scripthash, txs_limit + 1);
+    let history_txids = query.history_txids(scripthash, txs_limit + 1)?;
     ensure!(history_txids.len() <= txs_limit, ErrorKind::TooPopular);
     Ok(history_txids)
 }
@@ -913,6 +1272,50 @@ struct Stats {
     latency: HistogramVec,
     clients: Gauge,
     subscriptions: Gauge,
+    rate_limited: Counter,
+    bans: Counter,
+    rejected_connections: Counter,
+    slow_queries: SlowQueryLog,
+}
+
+// The address of the client, as passed by the proxy in front of us when using the PROXY protocol
+fn read_client_addr(
+    stream: &mut TcpStream,
+    addr: SocketAddr,
+    proxy_protocol: bool,
+) -> Result<SocketAddr> {
+    if !proxy_protocol {
+        return Ok(addr);
+    }
+    stream
+        .set_read_timeout(Some(PROXY_HEADER_TIMEOUT))
+        .chain_err(|| "failed setting read timeout")?;
+    let client_addr = proxy_protocol::read_header(stream)?;
+    stream
+        .set_read_timeout(None)
+        .chain_err(|| "failed clearing read timeout")?;
+    Ok(client_addr.unwrap_or(addr))
+}
+
+// Close the sessions that stay idle, and detect the half-open connections of the clients that
+// went away without closing them (like mobile clients switching networks)
+fn set_session_timeouts(
+    stream: &TcpStream,
+    idle_timeout: Option<Duration>,
+    keepalive: Option<Duration>,
+) -> Result<()> {
+    stream
+        .set_read_timeout(idle_timeout)
+        .chain_err(|| "failed setting idle timeout")?;
+    if let Some(keepalive) = keepalive {
+        let params = TcpKeepalive::new()
+            .with_time(keepalive)
+            .with_interval(KEEPALIVE_INTERVAL);
+        SockRef::from(stream)
+            .set_tcp_keepalive(&params)
+            .chain_err(|| "failed enabling TCP keepalive")?;
+    }
+    Ok(())
 }
 
 impl RPC {
@@ -921,7 +1324,7 @@ impl RPC {
         senders: Arc<Mutex<Vec<SyncSender<Message>>>>,
         acceptor: Sender<Option<(TcpStream, SocketAddr)>>,
     ) {
-        spawn_thread("notification", move || {
+        spawn_supervised("notification", move || {
             for msg in notification.receiver().iter() {
                 let mut senders = senders.lock().unwrap();
                 match msg {
@@ -942,32 +1345,47 @@ impl RPC {
         });
     }
 
-    fn start_acceptor(addr: SocketAddr) -> Channel<Option<(TcpStream, SocketAddr)>> {
+    // Accept connections on `addr`, and on the IPv6-only `addr_v6` if set
+    fn start_acceptor(
+        addr: SocketAddr,
+        addr_v6: Option<SocketAddr>,
+    ) -> Channel<Option<(TcpStream, SocketAddr)>> {
         let chan = Channel::unbounded();
-        let acceptor = chan.sender();
-        spawn_thread("acceptor", move || {
-            let socket = create_socket(&addr);
-            socket.listen(511).expect("setting backlog failed");
-            socket
-                .set_nonblocking(false)
-                .expect("cannot set nonblocking to false");
-            let listener = TcpListener::from(socket);
-
-            info!("Electrum RPC server running on {}", addr);
-            loop {
-                let (stream, addr) = listener.accept().expect("accept failed");
-                stream
+        // (thread name, systemd socket name, address, IPv6 only)
+        let listeners = iter::once(("acceptor", "electrum", addr, false))
+            .chain(addr_v6.map(|addr| ("acceptor-v6", "electrum-v6", addr, true)));
+        for (thread_name, socket_name, addr, only_v6) in listeners {
+            let acceptor = chan.sender();
+            spawn_supervised(thread_name, move || {
+                let listener = systemd::take_listener(socket_name).unwrap_or_else(|| {
+                    let socket = create_socket(&addr, only_v6);
+                    socket.listen(511).expect("setting backlog failed");
+                    TcpListener::from(socket)
+                });
+                listener
                     .set_nonblocking(false)
-                    .expect("failed to set connection as blocking");
-                acceptor.send(Some((stream, addr))).expect("send failed");
-            }
-        });
+                    .expect("cannot set nonblocking to false");
+
+                info!(
+                    "Electrum RPC server running on {}",
+                    listener.local_addr().unwrap()
+                );
+                loop {
+                    let (stream, addr) = listener.accept().expect("accept failed");
+                    stream
+                        .set_nonblocking(false)
+                        .expect("failed to set connection as blocking");
+                    acceptor.send(Some((stream, addr))).expect("send failed");
+                }
+            });
+        }
         chan
     }
 
     pub fn start(
         config: Arc<Config>,
         query: Arc<Query>,
+        pools: &ThreadPools,
         metrics: &Metrics,
         salt_rwlock: Arc<RwLock<String>>,
     ) -> RPC {
@@ -981,15 +1399,43 @@ impl RPC {
                 "electrum_subscriptions",
                 "# of Electrum subscriptions",
             )),
+            rate_limited: metrics.counter(MetricOpts::new(
+                "electrum_rate_limited",
+                "# of Electrum RPC requests rejected by the rate limiter",
+            )),
+            bans: metrics.counter(MetricOpts::new(
+                "electrum_client_bans",
+                "# of Electrum clients banned for making too many invalid requests",
+            )),
+            rejected_connections: metrics.counter(MetricOpts::new(
+                "electrum_rejected_connections",
+                "# of Electrum connections closed for exceeding --electrum-max-connections",
+            )),
+            slow_queries: SlowQueryLog::new(metrics, "electrum", config.slow_query_threshold),
         });
         stats.clients.set(0);
         stats.subscriptions.set(0);
 
         let notification = Channel::unbounded();
 
-        // Discovery is enabled when electrum-public-hosts is set
+        // Discovery is enabled when electrum-public-hosts or electrum-onion-host is set
         #[cfg(feature = "electrum-discovery")]
-        let discovery = config.electrum_public_hosts.clone().map(|hosts| {
+        let hosts = match (&config.electrum_public_hosts, &config.electrum_onion_host) {
+            (None, None) => None,
+            (public_hosts, onion_host) => {
+                let mut hosts = public_hosts.clone().unwrap_or_default();
+                if let Some((hostname, port)) = onion_host {
+                    let ports = ServerPorts {
+                        tcp_port: Some(*port),
+                        ssl_port: None,
+                    };
+                    hosts.insert(hostname.clone(), ports);
+                }
+                Some(hosts)
+            }
+        };
+        #[cfg(feature = "electrum-discovery")]
+        let discovery = hosts.map(|hosts| {
             use crate::chain::genesis_hash;
             let features = ServerFeatures {
                 hosts,
@@ -1006,26 +1452,57 @@ impl RPC {
                 PROTOCOL_VERSION,
                 config.electrum_announce,
                 config.tor_proxy,
+                config.socks_proxy,
             ));
+            for peer in &config.electrum_seed_peers {
+                if let Err(e) = discovery.add_seed_peer(peer) {
+                    warn!("failed adding seed peer {}: {}", peer, e.display_chain());
+                }
+            }
             DiscoveryManager::spawn_jobs_thread(Arc::clone(&discovery));
             discovery
         });
 
-        let rpc_addr = config.electrum_rpc_addr;
-        let txs_limit = config.electrum_txs_limit;
+        let (rpc_addr, rpc_addr_v6) = (config.electrum_rpc_addr, config.electrum_rpc_addr_v6);
+        let limits = ResultLimits::new(&config);
+        let proxy_protocol = config.electrum_proxy_protocol;
+        let (idle_timeout, keepalive) = (config.electrum_idle_timeout, config.electrum_keepalive);
+        let max_lifetime = config.electrum_max_lifetime;
+        let max_connections = config.electrum_max_connections;
+        let notify_pool = pools.notify.clone();
+        let rate_limiter = Arc::new(RateLimiter::default());
 
         RPC {
             notification: notification.sender(),
-            server: Some(spawn_thread("rpc", move || {
+            server: Some(spawn_critical("rpc", move || {
                 let senders = Arc::new(Mutex::new(Vec::<SyncSender<Message>>::new()));
 
-                let acceptor = RPC::start_acceptor(rpc_addr);
+                let acceptor = RPC::start_acceptor(rpc_addr, rpc_addr_v6);
                 RPC::start_notifier(notification, senders.clone(), acceptor.sender());
 
                 let mut threads = HashMap::new();
                 let (garbage_sender, garbage_receiver) = crossbeam_channel::unbounded();
 
                 while let Some((stream, addr)) = acceptor.receiver().recv().unwrap() {
+                    while let Ok(id) = garbage_receiver.try_recv() {
+                        if let Some(thread) = threads.remove(&id) {
+                            trace!("[{}] joining {:?}", addr, id);
+                            if let Err(error) = thread.join() {
+                                error!("failed to join {:?}: {:?}", id, error);
+                            }
+                        }
+                    }
+                    // every connection takes a peer and a reader thread
+                    if threads.len() >= max_connections {
+                        warn!(
+                            "[{}] rejected connection: {} connections open already",
+                            addr,
+                            threads.len()
+                        );
+                        stats.rejected_connections.inc();
+                        continue;
+                    }
+
                     // explicitly scope the shadowed variables for the new thread
                     let query = Arc::clone(&query);
                     let stats = Arc::clone(&stats);
@@ -1033,6 +1510,8 @@ impl RPC {
                     let rpc_logging = config.rpc_logging.clone();
                     #[cfg(feature = "electrum-discovery")]
                     let discovery = discovery.clone();
+                    let rate_limiter = Arc::clone(&rate_limiter);
+                    let notify_pool = notify_pool.clone();
 
                     let (sender, receiver) = mpsc::sync_channel(10);
                     senders.lock().unwrap().push(sender.clone());
@@ -1040,34 +1519,45 @@ impl RPC {
                     let salt = salt_rwlock.read().unwrap().clone();
 
                     let spawned = spawn_thread("peer", move || {
-                        info!("[{}] connected peer", addr);
-                        let conn = Connection::new(
-                            query,
-                            stream,
-                            addr,
-                            sender,
-                            stats,
-                            txs_limit,
-                            #[cfg(feature = "electrum-discovery")]
-                            discovery,
-                            rpc_logging,
-                            salt,
-                        );
-                        conn.run(receiver);
-                        info!("[{}] disconnected peer", addr);
+                        let mut stream = stream;
+                        let client_addr = read_client_addr(&mut stream, addr, proxy_protocol)
+                            .and_then(|addr| {
+                                set_session_timeouts(&stream, idle_timeout, keepalive)?;
+                                Ok(addr)
+                            });
+                        match client_addr {
+                            Ok(addr) if query.clients().is_banned(addr.ip()) => {
+                                debug!("[{}] rejected banned client", addr)
+                            }
+                            Ok(addr) => {
+                                info!("[{}] connected peer", addr);
+                                let conn = Connection::new(
+                                    query,
+                                    stream,
+                                    addr,
+                                    sender,
+                                    stats,
+                                    limits,
+                                    #[cfg(feature = "electrum-discovery")]
+                                    discovery,
+                                    rpc_logging,
+                                    salt,
+                                    rate_limiter,
+                                    max_lifetime,
+                                    notify_pool,
+                                );
+                                conn.run(receiver);
+                                info!("[{}] disconnected peer", addr);
+                            }
+                            Err(e) => {
+                                warn!("[{}] rejected connection: {}", addr, e.display_chain())
+                            }
+                        }
                         let _ = garbage_sender.send(std::thread::current().id());
                     });
 
                     trace!("[{}] spawned {:?}", addr, spawned.thread().id());
                     threads.insert(spawned.thread().id(), spawned);
-                    while let Ok(id) = garbage_receiver.try_recv() {
-                        if let Some(thread) = threads.remove(&id) {
-                            trace!("[{}] joining {:?}", addr, id);
-                            if let Err(error) = thread.join() {
-                                error!("failed to join {:?}: {:?}", id, error);
-                            }
-                        }
-                    }
                 }
 
                 trace!("closing {} RPC connections", senders.lock().unwrap().len());
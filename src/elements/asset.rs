@@ -356,7 +356,7 @@ pub fn lookup_asset(
     meta: Option<&AssetMeta>, // may optionally be provided if already known
 ) -> Result<Option<LiquidAsset>> {
     if query.network().pegged_asset() == Some(asset_id) {
-        let (chain_stats, mempool_stats) = pegged_asset_stats(query, asset_id);
+        let (chain_stats, mempool_stats) = pegged_asset_stats(query, asset_id)?;
 
         return Ok(Some(LiquidAsset::Native(PeggedAsset {
             asset_id: *asset_id,
@@ -370,7 +370,7 @@ pub fn lookup_asset(
     let mempool_issuances = &mempool.asset_issuance;
 
     let chain_row = history_db
-        .get(&[b"i", &asset_id.into_inner()[..]].concat())
+        .get(&[b"i", &asset_id.into_inner()[..]].concat())?
         .map(|row| bincode::deserialize_little::<AssetRow>(&row).expect("failed parsing AssetRow"));
 
     let row = chain_row
@@ -383,8 +383,8 @@ pub fn lookup_asset(
         let meta = meta
             .cloned()
             .or_else(|| registry.and_then(|r| r.read().unwrap().get(asset_id).cloned()));
-        let stats = issued_asset_stats(query.chain(), &mempool, asset_id, &reissuance_token);
-        let status = query.get_tx_status(&deserialize(&row.issuance_txid).unwrap());
+        let stats = issued_asset_stats(query.chain(), &mempool, asset_id, &reissuance_token)?;
+        let status = query.get_tx_status(&deserialize(&row.issuance_txid).unwrap())?;
 
         let asset = IssuedAsset::new(asset_id, row, stats, meta, status);
 
@@ -454,11 +454,14 @@ where
 }
 
 // Get stats for the network's pegged asset
-fn pegged_asset_stats(query: &Query, asset_id: &AssetId) -> (PeggedAssetStats, PeggedAssetStats) {
-    (
-        chain_asset_stats(query.chain(), asset_id, apply_pegged_asset_stats),
+fn pegged_asset_stats(
+    query: &Query,
+    asset_id: &AssetId,
+) -> Result<(PeggedAssetStats, PeggedAssetStats)> {
+    Ok((
+        chain_asset_stats(query.chain(), asset_id, apply_pegged_asset_stats)?,
         mempool_asset_stats(&query.mempool(), asset_id, apply_pegged_asset_stats),
-    )
+    ))
 }
 
 // Get stats for issued assets
@@ -467,22 +470,26 @@ fn issued_asset_stats(
     mempool: &Mempool,
     asset_id: &AssetId,
     reissuance_token: &AssetId,
-) -> (IssuedAssetStats, IssuedAssetStats) {
+) -> Result<(IssuedAssetStats, IssuedAssetStats)> {
     let afn = apply_issued_asset_stats;
 
-    let mut chain_stats = chain_asset_stats(chain, asset_id, afn);
+    let mut chain_stats = chain_asset_stats(chain, asset_id, afn)?;
     chain_stats.burned_reissuance_tokens =
-        chain_asset_stats(chain, reissuance_token, afn).burned_amount;
+        chain_asset_stats(chain, reissuance_token, afn)?.burned_amount;
 
     let mut mempool_stats = mempool_asset_stats(&mempool, &asset_id, afn);
     mempool_stats.burned_reissuance_tokens =
         mempool_asset_stats(mempool, &reissuance_token, afn).burned_amount;
 
-    (chain_stats, mempool_stats)
+    Ok((chain_stats, mempool_stats))
 }
 
 // Get on-chain confirmed asset stats (issued or the pegged asset)
-fn chain_asset_stats<T>(chain: &ChainQuery, asset_id: &AssetId, apply_fn: AssetStatApplyFn<T>) -> T
+fn chain_asset_stats<T>(
+    chain: &ChainQuery,
+    asset_id: &AssetId,
+    apply_fn: AssetStatApplyFn<T>,
+) -> Result<T>
 where
     T: Default + serde::Serialize + serde::de::DeserializeOwned,
 {
@@ -491,7 +498,7 @@ where
     let cache: Option<(T, usize)> = chain
         .store()
         .cache_db()
-        .get(&asset_cache_key(asset_id))
+        .get(&asset_cache_key(asset_id))?
         .map(|c| bincode::deserialize_little(&c).unwrap())
         .and_then(|(stats, blockhash)| {
             chain
@@ -505,17 +512,18 @@ where
         |(oldstats, blockheight)| {
             chain_asset_stats_delta(chain, asset_id, oldstats, blockheight + 1, apply_fn)
         },
-    );
+    )?;
 
     // save updated stats to cache
     if let Some(lastblock) = lastblock {
-        chain.store().cache_db().write(
-            vec![asset_cache_row(asset_id, &newstats, &lastblock)],
-            DBFlush::Enable,
-        );
+        let row = asset_cache_row(asset_id, &newstats, &lastblock);
+        match chain.store().cache_db().write(vec![row], DBFlush::Enable) {
+            Err(e) if !e.is_read_only() => warn!("failed caching the asset stats: {}", e),
+            _ => (),
+        }
     }
 
-    newstats
+    Ok(newstats)
 }
 
 // Update the asset stats with the delta of confirmed txs since start_height
@@ -525,21 +533,19 @@ fn chain_asset_stats_delta<T>(
     init_stats: T,
     start_height: usize,
     apply_fn: AssetStatApplyFn<T>,
-) -> (T, Option<BlockHash>) {
-    let history_iter = chain
-        .history_iter_scan(b'I', &asset_id.into_inner()[..], start_height)
-        .map(TxHistoryRow::from_row)
-        .filter_map(|history| {
-            chain
-                .tx_confirming_block(&history.get_txid())
-                .map(|blockid| (history, blockid))
-        });
+) -> Result<(T, Option<BlockHash>)> {
+    let history_iter = chain.history_iter_scan(b'I', &asset_id.into_inner()[..], start_height);
 
     let mut stats = init_stats;
     let mut seen_txids = HashSet::new();
     let mut lastblock = None;
 
-    for (row, blockid) in history_iter {
+    for row in history_iter {
+        let row = TxHistoryRow::from_row(row?);
+        let blockid = match chain.tx_confirming_block(&row.get_txid())? {
+            Some(blockid) => blockid,
+            None => continue,
+        };
         if lastblock != Some(blockid.hash) {
             seen_txids.clear();
         }
@@ -547,7 +553,7 @@ fn chain_asset_stats_delta<T>(
         lastblock = Some(blockid.hash);
     }
 
-    (stats, lastblock)
+    Ok((stats, lastblock))
 }
 
 // Get mempool asset stats (issued or the pegged asset)
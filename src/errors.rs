@@ -24,6 +24,26 @@ error_chain! {
             display("Too many history entries")
         }
 
+        Blocked {
+            description("Blocked script")
+            display("This script is blocked")
+        }
+
+        InvalidRequest(msg: String) {
+            description("Invalid request")
+            display("{}", msg)
+        }
+
+        ReadOnly {
+            description("Read-only database")
+            display("low disk space, the database is read-only")
+        }
+
+        DbError(e: rocksdb::Error) {
+            description("Database error")
+            display("Database error: {}", e)
+        }
+
         #[cfg(feature = "electrum-discovery")]
         ElectrumClient(e: electrum_client::Error) {
             description("Electrum client error")
@@ -33,6 +53,19 @@ error_chain! {
     }
 }
 
+impl Error {
+    /// Whether a write was refused by a database in read-only mode, see `--min-free-disk-mb`
+    pub fn is_read_only(&self) -> bool {
+        matches!(self.kind(), ErrorKind::ReadOnly)
+    }
+}
+
+impl From<rocksdb::Error> for Error {
+    fn from(e: rocksdb::Error) -> Self {
+        Error::from(ErrorKind::DbError(e))
+    }
+}
+
 #[cfg(feature = "electrum-discovery")]
 impl From<electrum_client::Error> for Error {
     fn from(e: electrum_client::Error) -> Self {
@@ -31,3 +31,9 @@ pub mod elements;
 
 #[cfg(feature = "otlp-tracing")]
 pub mod otlp_trace;
+
+#[cfg(feature = "tls")]
+pub mod tls;
+
+#[cfg(feature = "testing")]
+pub mod testing;
@@ -1,5 +1,7 @@
 use page_size;
+use prometheus::proto::MetricType;
 use prometheus::{self, Encoder};
+use std::collections::HashMap;
 use std::fs;
 use std::io;
 use std::net::SocketAddr;
@@ -13,13 +15,18 @@ pub use prometheus::{
     IntCounterVec as CounterVec, IntGauge as Gauge, Opts as MetricOpts,
 };
 
-use crate::util::spawn_thread;
+use crate::new_index::db::ReadStats;
+use crate::util::allocator;
+use crate::util::supervisor::{spawn_supervised, THREAD_PANICS};
 
 use crate::errors::*;
 
+#[derive(Clone)]
 pub struct Metrics {
     reg: prometheus::Registry,
     addr: SocketAddr,
+    // added to all the registered metrics, to tell apart the networks sharing the registry
+    labels: HashMap<String, String>,
 }
 
 impl Metrics {
@@ -27,51 +34,106 @@ impl Metrics {
         Metrics {
             reg: prometheus::Registry::new(),
             addr,
+            labels: HashMap::new(),
+        }
+    }
+
+    /// Returns a handle registering metrics into the same registry, with an additional
+    /// constant label
+    pub fn with_label(&self, name: &str, value: &str) -> Metrics {
+        let mut labels = self.labels.clone();
+        labels.insert(name.to_string(), value.to_string());
+        Metrics {
+            reg: self.reg.clone(),
+            addr: self.addr,
+            labels,
         }
     }
 
     pub fn counter(&self, opts: prometheus::Opts) -> Counter {
-        let c = Counter::with_opts(opts).unwrap();
+        let c = Counter::with_opts(opts.const_labels(self.labels.clone())).unwrap();
         self.reg.register(Box::new(c.clone())).unwrap();
         c
     }
 
     pub fn counter_vec(&self, opts: prometheus::Opts, labels: &[&str]) -> CounterVec {
-        let c = CounterVec::new(opts, labels).unwrap();
+        let c = CounterVec::new(opts.const_labels(self.labels.clone()), labels).unwrap();
         self.reg.register(Box::new(c.clone())).unwrap();
         c
     }
 
     pub fn gauge(&self, opts: prometheus::Opts) -> Gauge {
-        let g = Gauge::with_opts(opts).unwrap();
+        let g = Gauge::with_opts(opts.const_labels(self.labels.clone())).unwrap();
         self.reg.register(Box::new(g.clone())).unwrap();
         g
     }
 
     pub fn gauge_vec(&self, opts: prometheus::Opts, labels: &[&str]) -> GaugeVec {
-        let g = GaugeVec::new(opts, labels).unwrap();
+        let g = GaugeVec::new(opts.const_labels(self.labels.clone()), labels).unwrap();
         self.reg.register(Box::new(g.clone())).unwrap();
         g
     }
 
     pub fn histogram(&self, opts: prometheus::HistogramOpts) -> Histogram {
-        let h = Histogram::with_opts(opts).unwrap();
+        let h = Histogram::with_opts(opts.const_labels(self.labels.clone())).unwrap();
         self.reg.register(Box::new(h.clone())).unwrap();
         h
     }
 
     pub fn histogram_vec(&self, opts: prometheus::HistogramOpts, labels: &[&str]) -> HistogramVec {
-        let h = HistogramVec::new(opts, labels).unwrap();
+        let h = HistogramVec::new(opts.const_labels(self.labels.clone()), labels).unwrap();
         self.reg.register(Box::new(h.clone())).unwrap();
         h
     }
 
+    /// The current values of the metrics starting with `prefix`, keeping to the metrics of this
+    /// handle's network when the registry is shared. Histograms are summed up by their count
+    /// and sum.
+    pub fn snapshot(&self, prefix: &str) -> serde_json::Value {
+        let mut snapshot = serde_json::Map::new();
+        for family in self.reg.gather() {
+            if !family.get_name().starts_with(prefix) {
+                continue;
+            }
+            let metrics: Vec<serde_json::Value> = family
+                .get_metric()
+                .iter()
+                .filter_map(|metric| {
+                    let labels: HashMap<&str, &str> = metric
+                        .get_label()
+                        .iter()
+                        .map(|label| (label.get_name(), label.get_value()))
+                        .collect();
+                    let own = self
+                        .labels
+                        .iter()
+                        .all(|(name, value)| labels.get(name.as_str()) == Some(&value.as_str()));
+                    let value = match family.get_field_type() {
+                        MetricType::COUNTER => json!(metric.get_counter().get_value()),
+                        MetricType::GAUGE => json!(metric.get_gauge().get_value()),
+                        MetricType::HISTOGRAM => json!({
+                            "count": metric.get_histogram().get_sample_count(),
+                            "sum": metric.get_histogram().get_sample_sum(),
+                        }),
+                        _ => return None,
+                    };
+                    own.then(|| json!({ "labels": labels, "value": value }))
+                })
+                .collect();
+            if !metrics.is_empty() {
+                snapshot.insert(family.get_name().to_string(), json!(metrics));
+            }
+        }
+        serde_json::Value::Object(snapshot)
+    }
+
     pub fn start(&self) {
         let server = tiny_http::Server::http(self.addr)
             .unwrap_or_else(|_| panic!("failed to start monitoring HTTP server at {}", self.addr));
+        self.reg.register(Box::new(THREAD_PANICS.clone())).unwrap();
         start_process_exporter(&self);
         let reg = self.reg.clone();
-        spawn_thread("metrics", move || loop {
+        spawn_supervised("metrics", move || loop {
             if let Err(e) = handle_request(&reg, server.recv()) {
                 error!("http error: {}", e);
             }
@@ -79,6 +141,113 @@ impl Metrics {
     }
 }
 
+/// The metrics of a cache, exported under the common `electrs_cache_*` family with a `cache`
+/// label. The entries and size are only reported for the caches that keep track of them.
+#[derive(Clone)]
+pub struct CacheMetrics {
+    hits: Counter,
+    misses: Counter,
+    evictions: Counter,
+    entries: Gauge,
+    size: Gauge,
+}
+
+impl CacheMetrics {
+    pub fn new(metrics: &Metrics, cache: &str) -> Self {
+        let metrics = metrics.with_label("cache", cache);
+        CacheMetrics {
+            hits: metrics.counter(MetricOpts::new(
+                "electrs_cache_hits",
+                "# of cache lookups that found a valid entry",
+            )),
+            misses: metrics.counter(MetricOpts::new(
+                "electrs_cache_misses",
+                "# of cache lookups that found no valid entry",
+            )),
+            evictions: metrics.counter(MetricOpts::new(
+                "electrs_cache_evictions",
+                "# of cache entries dropped or invalidated",
+            )),
+            entries: metrics.gauge(MetricOpts::new(
+                "electrs_cache_entries",
+                "# of entries in the cache",
+            )),
+            size: metrics.gauge(MetricOpts::new(
+                "electrs_cache_size_bytes",
+                "Size of the cache entries (in bytes)",
+            )),
+        }
+    }
+
+    pub fn hit(&self) {
+        self.hits.inc();
+    }
+
+    pub fn miss(&self) {
+        self.misses.inc();
+    }
+
+    pub fn evicted(&self, count: usize) {
+        self.evictions.inc_by(count as u64);
+    }
+
+    pub fn set_usage(&self, entries: usize, size: usize) {
+        self.entries.set(entries as i64);
+        self.size.set(size as i64);
+    }
+}
+
+/// Logs the requests of a server that took longer than the configured threshold to handle,
+/// and counts them by endpoint under `electrs_slow_requests`.
+#[derive(Clone)]
+pub struct SlowQueryLog {
+    server: &'static str,
+    threshold: Option<Duration>,
+    count: CounterVec,
+}
+
+impl SlowQueryLog {
+    pub fn new(metrics: &Metrics, server: &'static str, threshold: Option<Duration>) -> Self {
+        SlowQueryLog {
+            server,
+            threshold,
+            count: metrics.with_label("server", server).counter_vec(
+                MetricOpts::new(
+                    "electrs_slow_requests",
+                    "# of requests that exceeded the slow query threshold",
+                ),
+                &["endpoint"],
+            ),
+        }
+    }
+
+    /// Logs the request if it is slow, `describe` gives the request details to log
+    pub fn observe(
+        &self,
+        endpoint: &str,
+        request_id: &str,
+        elapsed: Duration,
+        reads: ReadStats,
+        describe: impl FnOnce() -> String,
+    ) {
+        match self.threshold {
+            Some(threshold) if elapsed >= threshold => (),
+            _ => return,
+        }
+        self.count.with_label_values(&[endpoint]).inc();
+        warn!(
+            "[{}] slow {} request {} took {}ms: {} rows scanned, {} point reads, {} bytes read",
+            request_id,
+            self.server,
+            describe(),
+            elapsed.as_millis(),
+            reads.rows_scanned,
+            reads.point_reads,
+            reads.bytes_read,
+        );
+    }
+}
+
 fn handle_request(
     reg: &prometheus::Registry,
     request: io::Result<tiny_http::Request>,
@@ -139,7 +308,25 @@ fn start_process_exporter(metrics: &Metrics) {
         &["type"],
     );
     let fds = metrics.gauge(MetricOpts::new("process_fs_fds", "# of file descriptors"));
-    spawn_thread("exporter", move || loop {
+    let allocator_gauges = allocator::NAME.map(|name| {
+        info!("using the {} allocator", name);
+        let memory = metrics.gauge_vec(
+            MetricOpts::new(
+                "process_allocator_memory",
+                "Memory reported by the allocator [bytes]",
+            ),
+            &["type"],
+        );
+        let fragmentation = metrics.gauge_vec(
+            MetricOpts::new(
+                "process_allocator_fragmentation",
+                "Share of the allocator's resident memory that isn't allocated",
+            ),
+            &[],
+        );
+        (memory, fragmentation)
+    });
+    spawn_supervised("exporter", move || loop {
         match parse_stats() {
             Ok(stats) => {
                 cpu.with_label_values(&["utime"]).set(stats.utime as f64);
@@ -148,6 +335,27 @@ fn start_process_exporter(metrics: &Metrics) {
             }
             Err(e) => warn!("failed to export stats: {}", e),
         }
+        if let Some((ref memory, ref fragmentation)) = allocator_gauges {
+            match allocator::stats() {
+                Ok(stats) => {
+                    if let Some(allocated) = stats.allocated {
+                        memory
+                            .with_label_values(&["allocated"])
+                            .set(allocated as f64);
+                    }
+                    memory
+                        .with_label_values(&["active"])
+                        .set(stats.active as f64);
+                    memory
+                        .with_label_values(&["resident"])
+                        .set(stats.resident as f64);
+                    if let Some(ratio) = stats.fragmentation() {
+                        fragmentation.with_label_values(&[]).set(ratio);
+                    }
+                }
+                Err(e) => warn!("failed to export allocator stats: {}", e),
+            }
+        }
         thread::sleep(Duration::from_secs(5));
     });
 }
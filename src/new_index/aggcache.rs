@@ -0,0 +1,151 @@
+use serde::{de::DeserializeOwned, Serialize};
+
+use std::sync::Mutex;
+
+use crate::chain::BlockHash;
+use crate::errors::*;
+use crate::metrics::{CacheMetrics, Metrics};
+use crate::new_index::db::{DBFlush, DBRow, DB};
+use crate::util::Bytes;
+
+/// A cache for aggregates computed over the best chain, persisted under the `Q` prefix of the
+/// cache db.
+///
+/// Entries are keyed by the chain generation and the tip they were computed for, and are dropped
+/// as soon as a query is made for a different one. Once `max_size` bytes are used, new entries
+/// are no longer saved until the next invalidation.
+pub struct AggregateCache {
+    max_size: usize,
+    state: Mutex<CacheState>,
+    metrics: CacheMetrics,
+}
+
+struct CacheState {
+    tip: Option<(u64, BlockHash)>,
+    entries: usize,
+    size: usize,
+}
+
+impl AggregateCache {
+    pub fn new(max_size: usize, metrics: &Metrics) -> Self {
+        AggregateCache {
+            max_size,
+            state: Mutex::new(CacheState {
+                tip: None,
+                entries: 0,
+                size: 0,
+            }),
+            metrics: CacheMetrics::new(metrics, "aggregate"),
+        }
+    }
+
+    /// Returns the cached result of the `query` with the given `params` for `tip` of the chain
+    /// `generation`, or runs `compute` and caches its result.
+    pub fn get_or_compute<T, F>(
+        &self,
+        db: &DB,
+        generation: u64,
+        tip: &BlockHash,
+        query: &str,
+        params: &str,
+        compute: F,
+    ) -> Result<T>
+    where
+        T: Serialize + DeserializeOwned,
+        F: FnOnce() -> Result<T>,
+    {
+        if self.max_size == 0 {
+            return compute();
+        }
+        self.invalidate(db, generation, tip)?;
+
+        let key = cache_key(generation, tip, query, params);
+        if let Some(value) = db.get(&key)? {
+            match serde_json::from_slice(&value) {
+                Ok(result) => {
+                    self.metrics.hit();
+                    return Ok(result);
+                }
+                Err(e) => warn!("ignoring invalid {} aggregate cache entry: {}", query, e),
+            }
+        }
+        self.metrics.miss();
+
+        let result = compute()?;
+        let value = serde_json::to_vec(&result).expect("failed to serialize aggregate");
+        let row_size = key.len() + value.len();
+
+        let mut state = self.state.lock().unwrap();
+        if state.tip != Some((generation, *tip)) {
+            // the tip changed while computing, the result is already stale
+            return Ok(result);
+        }
+        if state.size + row_size > self.max_size {
+            debug!("aggregate cache is full, not caching {} {}", query, params);
+            return Ok(result);
+        }
+        if let Err(e) = db.write(vec![DBRow { key, value }], DBFlush::Disable) {
+            if !e.is_read_only() {
+                warn!("failed caching {} {}: {}", query, params, e);
+            }
+            return Ok(result);
+        }
+        state.entries += 1;
+        state.size += row_size;
+        self.metrics.set_usage(state.entries, state.size);
+        Ok(result)
+    }
+
+    // drop the entries computed for any generation and tip other than `generation` and `tip`
+    fn invalidate(&self, db: &DB, generation: u64, tip: &BlockHash) -> Result<()> {
+        let mut state = self.state.lock().unwrap();
+        if state.tip == Some((generation, *tip)) {
+            return Ok(());
+        }
+
+        let current = tip_prefix(generation, tip);
+        let mut stale_keys = vec![];
+        let (mut entries, mut size) = (0, 0);
+        for row in db.iter_scan(b"Q") {
+            let row = row?;
+            if row.key.starts_with(&current) {
+                entries += 1;
+                size += row.key.len() + row.value.len();
+            } else {
+                stale_keys.push(row.key);
+            }
+        }
+        if !stale_keys.is_empty() {
+            debug!(
+                "dropping {} stale aggregate cache entries",
+                stale_keys.len()
+            );
+            // the stale entries are never served, failing to drop them only wastes space
+            match db.delete(&stale_keys) {
+                Ok(()) => self.metrics.evicted(stale_keys.len()),
+                Err(e) if e.is_read_only() => (),
+                Err(e) => warn!("failed dropping the stale aggregate cache entries: {}", e),
+            }
+        }
+        state.tip = Some((generation, *tip));
+        state.entries = entries;
+        state.size = size;
+        self.metrics.set_usage(entries, size);
+        Ok(())
+    }
+}
+
+fn tip_prefix(generation: u64, tip: &BlockHash) -> Bytes {
+    [&b"Q"[..], &generation.to_be_bytes(), &tip[..]].concat()
+}
+
+// "Q{generation}{tip-blockhash}{query}\0{params}"
+fn cache_key(generation: u64, tip: &BlockHash, query: &str, params: &str) -> Bytes {
+    [
+        &tip_prefix(generation, tip)[..],
+        query.as_bytes(),
+        b"\0",
+        params.as_bytes(),
+    ]
+    .concat()
+}
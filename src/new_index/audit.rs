@@ -0,0 +1,199 @@
+use std::collections::{BTreeSet, HashMap};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use bitcoin::hex::DisplayHex;
+use rand::RngCore;
+
+use crate::config::Config;
+use crate::errors::*;
+use crate::metrics::{CounterVec, MetricOpts, Metrics};
+use crate::new_index::db::DBRow;
+use crate::new_index::schema::{index_transaction, IndexerConfig};
+use crate::new_index::{ChainQuery, TxHistoryRow};
+use crate::util::pool::LoadMonitor;
+use crate::util::supervisor::spawn_supervised;
+use crate::util::{has_prevout, Bytes, FullHash};
+
+// how often a sample of the history rows is audited, while the queries leave room for it
+const AUDIT_INTERVAL: Duration = Duration::from_secs(60);
+// how often the audit thread checks whether an audit is due
+const CHECK_INTERVAL: Duration = Duration::from_secs(5);
+// the niceness of the audit thread, the lowest scheduling priority
+#[cfg(target_os = "linux")]
+const AUDIT_NICENESS: libc::c_int = 19;
+
+/// Verifies a random sample of the history rows in the background, by deriving the rows of
+/// their transaction again from the stored transaction and previous outputs. A sampled row that
+/// isn't derived again, or a derived row missing from the index, is logged and counted as a
+/// mismatch by the `history_audit_rows` metric, long before it shows up as a wrong balance.
+///
+/// The rows left by orphaned blocks (which the queries skip) are counted as stale.
+pub struct HistoryAuditor {
+    chain: Arc<ChainQuery>,
+    iconfig: IndexerConfig,
+    rows_per_run: usize,
+    last_run: Instant,
+    rows: CounterVec,
+}
+
+/// The result of auditing a history row
+#[derive(Debug, PartialEq, Eq)]
+pub enum Audit {
+    Ok,
+    Stale,
+    Mismatch,
+}
+
+impl HistoryAuditor {
+    pub fn new(chain: Arc<ChainQuery>, config: &Config, metrics: &Metrics) -> Self {
+        HistoryAuditor {
+            chain,
+            iconfig: IndexerConfig::from(config),
+            rows_per_run: config.history_audit_rows,
+            last_run: Instant::now(),
+            rows: metrics.counter_vec(
+                MetricOpts::new(
+                    "history_audit_rows",
+                    "# of history rows verified by the background audit, by result",
+                ),
+                &["result"],
+            ),
+        }
+    }
+
+    /// Runs the audits on a thread of its own, at the lowest scheduling priority, so that the
+    /// main loop and the queries never wait for them
+    pub fn start(mut self, load: LoadMonitor) {
+        if self.rows_per_run == 0 {
+            return;
+        }
+        spawn_supervised("history-audit", move || {
+            lower_thread_priority();
+            loop {
+                thread::sleep(CHECK_INTERVAL);
+                self.audit_if_due(&load);
+            }
+        });
+    }
+
+    // Audits `--history-audit-rows` random history rows, unless audited less than a minute ago
+    // (or longer, while the queries are overloaded)
+    fn audit_if_due(&mut self, load: &LoadMonitor) {
+        if self.rows_per_run == 0
+            || !load.is_due("history_audit", self.last_run.elapsed(), AUDIT_INTERVAL)
+        {
+            return;
+        }
+        self.last_run = Instant::now();
+
+        let mut rng = rand::rng();
+        for _ in 0..self.rows_per_run {
+            let mut seed = FullHash::default();
+            rng.fill_bytes(&mut seed);
+            let result = match self.audit_from(&seed) {
+                Ok(Some(Audit::Ok)) => "ok",
+                Ok(Some(Audit::Stale)) => "stale",
+                Ok(Some(Audit::Mismatch)) => "mismatch",
+                Ok(None) => return, // nothing indexed yet
+                Err(e) => {
+                    error!("history audit failed: {}", e.display_chain());
+                    "error"
+                }
+            };
+            self.rows.with_label_values(&[result]).inc();
+        }
+    }
+
+    /// Audits the first history row following `scripthash` (wrapping around to the first one),
+    /// or returns `None` when there are no history rows
+    pub fn audit_from(&self, scripthash: &FullHash) -> Result<Option<Audit>> {
+        match self.sample(scripthash)? {
+            Some(row) => self.audit(row).map(Some),
+            None => Ok(None),
+        }
+    }
+
+    // the first history row following a random scripthash, wrapping around to the first one
+    fn sample(&self, seed: &FullHash) -> Result<Option<DBRow>> {
+        let db = self.chain.store().history_db();
+        match db.iter_scan_from(b"H", &[b"H", &seed[..]].concat()).next() {
+            Some(row) => row.map(Some),
+            None => db.iter_scan(b"H").next().transpose(),
+        }
+    }
+
+    fn audit(&self, row: DBRow) -> Result<Audit> {
+        let key = row.key.clone();
+        let history = TxHistoryRow::from_row(row);
+        let txid = history.get_txid();
+        let height = history.key.confirmed_height;
+        let blockid = match self.chain.tx_confirming_block(&txid)? {
+            Some(blockid) if blockid.height == height as usize => blockid,
+            _ => return Ok(Audit::Stale),
+        };
+        let tx = self
+            .chain
+            .lookup_txn(&txid, Some(&blockid.hash))?
+            .ok_or_else(|| format!("missing transaction {}", txid))?;
+        let outpoints: BTreeSet<_> = tx
+            .input
+            .iter()
+            .filter(|txin| has_prevout(txin))
+            .map(|txin| txin.previous_output)
+            .collect();
+        let prevouts = self.chain.lookup_txos(outpoints)?;
+
+        let mut rows = vec![];
+        index_transaction(
+            &tx,
+            height,
+            &prevouts,
+            &mut rows,
+            &mut HashMap::new(),
+            &self.iconfig,
+        );
+        let derived: Vec<Bytes> = rows
+            .into_iter()
+            .map(|row| row.key)
+            .filter(|key| key[0] == b'H')
+            .collect();
+        let mut missing = 0;
+        for res in self.chain.store().history_db().multi_get(&derived) {
+            if res?.is_none() {
+                missing += 1;
+            }
+        }
+        let rederived = derived.contains(&key);
+        if rederived && missing == 0 {
+            return Ok(Audit::Ok);
+        }
+        error!(
+            "history audit mismatch in transaction {} at height {}: the row {} is{} derived from \
+             it, and {} of its {} derived rows are missing, the database should be reindexed",
+            txid,
+            height,
+            key.to_lower_hex_string(),
+            if rederived { "" } else { " not" },
+            missing,
+            derived.len()
+        );
+        Ok(Audit::Mismatch)
+    }
+}
+
+// the audits are best-effort, they yield the cpu to everything else
+fn lower_thread_priority() {
+    #[cfg(target_os = "linux")]
+    unsafe {
+        // the niceness of a linux thread is set by its thread id
+        let tid = libc::syscall(libc::SYS_gettid) as libc::id_t;
+        if libc::setpriority(libc::PRIO_PROCESS, tid, AUDIT_NICENESS) != 0 {
+            warn!(
+                "failed lowering the priority of the history audit thread: {}",
+                std::io::Error::last_os_error()
+            );
+        }
+    }
+}
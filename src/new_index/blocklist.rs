@@ -0,0 +1,80 @@
+//! The scripthashes that must not be served (like sanctioned addresses), loaded from the
+//! `--blocklist` file. Requests for them are refused, and the transactions paying to or spending
+//! from them are tagged as blocked.
+
+use std::collections::HashSet;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::RwLock;
+
+use bitcoin::hex::FromHex;
+
+use crate::errors::*;
+use crate::util::FullHash;
+
+pub struct Blocklist {
+    path: Option<PathBuf>,
+    scripthashes: RwLock<HashSet<FullHash>>,
+}
+
+impl Blocklist {
+    /// Loads the blocklist from `path`, or an empty one that blocks nothing
+    pub fn open(path: Option<&Path>) -> Result<Self> {
+        let blocklist = Blocklist {
+            path: path.map(Path::to_path_buf),
+            scripthashes: RwLock::new(HashSet::new()),
+        };
+        blocklist.reload()?;
+        Ok(blocklist)
+    }
+
+    /// Reloads the blocklist file, keeping the previous entries if it can't be read
+    pub fn reload(&self) -> Result<()> {
+        let path = match self.path {
+            Some(ref path) => path,
+            None => return Ok(()),
+        };
+        let contents =
+            fs::read_to_string(path).chain_err(|| format!("failed reading {}", path.display()))?;
+        let scripthashes =
+            parse(&contents).chain_err(|| format!("invalid blocklist file {}", path.display()))?;
+        info!("loaded {} blocklisted scripthashes", scripthashes.len());
+        *self.scripthashes.write().unwrap() = scripthashes;
+        Ok(())
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.path.is_some()
+    }
+
+    pub fn contains(&self, scripthash: &[u8]) -> bool {
+        self.is_enabled() && self.scripthashes.read().unwrap().contains(scripthash)
+    }
+}
+
+// One hex scripthash per line, with blank lines and `#` comments ignored
+fn parse(contents: &str) -> Result<HashSet<FullHash>> {
+    contents
+        .lines()
+        .map(|line| line.split('#').next().unwrap().trim())
+        .filter(|line| !line.is_empty())
+        .map(|line| FullHash::from_hex(line).chain_err(|| format!("invalid scripthash {}", line)))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        let scripthash = "8b01df4e368ea28f8dc0423bcf7a4923e3a12d307c875e47a0cfbf90b5c39161";
+        let contents = format!("# sanctioned\n\n{}  # some label\n", scripthash);
+        let scripthashes = parse(&contents).unwrap();
+        assert_eq!(scripthashes.len(), 1);
+        assert!(scripthashes.contains(&FullHash::from_hex(scripthash).unwrap()));
+
+        assert!(parse("not a scripthash\n").is_err());
+        assert!(parse("").unwrap().is_empty());
+    }
+}
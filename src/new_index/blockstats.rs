@@ -0,0 +1,233 @@
+use std::collections::BTreeMap;
+
+use utoipa::ToSchema;
+
+use crate::util::{MultisigKind, MultisigType};
+
+/// Per-block aggregates, persisted under the `G` prefix of the history db.
+#[derive(Serialize, Deserialize, Default, Debug)]
+pub struct BlockStats {
+    // total value of the spendable outputs created in the block
+    pub created_value: u64,
+    // newly issued value (the block subsidy actually claimed by the coinbase)
+    pub issued_value: u64,
+    // value sent to provably unspendable outputs
+    pub burned_value: u64,
+    // value spent by the block inputs, grouped by the height the spent outputs were created at.
+    // only collected when `--utxo-age-stats` is enabled.
+    pub spent_by_height: Vec<(u32, u64)>,
+    // number of inputs spending each multisig pattern
+    pub multisig_spends: Vec<(MultisigType, u32)>,
+    // sum of the virtual sizes of the block transactions
+    pub tx_vsize: u64,
+    // sum of the sigop costs of the block transactions
+    #[cfg(not(feature = "liquid"))]
+    pub sigops: u64,
+}
+
+/// Aggregates of the `BlockStats` along the best chain, built by connecting its blocks in order.
+/// The results derived from them are cached per tip by the aggregate cache.
+pub struct ChainStats {
+    unspent_by_height: Vec<u64>,
+    supply: Vec<Supply>, // cumulative, by height
+}
+
+#[derive(Serialize, Deserialize, Default, Debug, Clone, Copy, PartialEq)]
+pub struct Supply {
+    pub total: u64,
+    pub burned: u64,
+}
+
+impl Supply {
+    pub fn circulating(&self) -> u64 {
+        self.total.saturating_sub(self.burned)
+    }
+}
+
+impl ChainStats {
+    pub fn new() -> Self {
+        ChainStats {
+            unspent_by_height: vec![],
+            supply: vec![],
+        }
+    }
+
+    pub fn connect(&mut self, stats: &BlockStats) {
+        // pushed first, as the block may spend the outputs it creates
+        self.unspent_by_height.push(stats.created_value);
+        for (height, value) in &stats.spent_by_height {
+            if let Some(unspent) = self.unspent_by_height.get_mut(*height as usize) {
+                *unspent = unspent.saturating_sub(*value);
+            }
+        }
+        let supply = self.supply.last().copied().unwrap_or_default();
+        self.supply.push(Supply {
+            total: supply.total + stats.issued_value,
+            burned: supply.burned + stats.burned_value,
+        });
+    }
+
+    /// The cumulative supply as of the given height
+    pub fn supply_at(&self, height: usize) -> Option<Supply> {
+        self.supply.get(height).copied()
+    }
+
+    /// The value still unspent, indexed by the height it was created at
+    pub fn unspent_by_height(&self) -> &[u64] {
+        &self.unspent_by_height
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, ToSchema)]
+pub struct MultisigUsage {
+    #[serde(rename = "type")]
+    pub kind: MultisigKind,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub m: Option<u8>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub n: Option<u8>,
+    pub count: u64,
+}
+
+/// Sums up the multisig spends of the given blocks, most used patterns first
+pub fn multisig_usage<'a>(blocks: impl Iterator<Item = &'a BlockStats>) -> Vec<MultisigUsage> {
+    let mut counts = BTreeMap::<MultisigType, u64>::new();
+    for stats in blocks {
+        for (multisig_type, count) in &stats.multisig_spends {
+            *counts.entry(*multisig_type).or_default() += *count as u64;
+        }
+    }
+    let mut usage = counts
+        .into_iter()
+        .map(|(multisig_type, count)| MultisigUsage {
+            kind: multisig_type.kind,
+            m: multisig_type.threshold.map(|(m, _)| m),
+            n: multisig_type.threshold.map(|(_, n)| n),
+            count,
+        })
+        .collect::<Vec<_>>();
+    usage.sort_by(|a, b| b.count.cmp(&a.count));
+    usage
+}
+
+#[derive(Serialize, Deserialize, Debug, ToSchema)]
+pub struct UtxoAgeBucket {
+    pub max_age: Option<u32>, // in seconds, None for the last (unbounded) bucket
+    #[serde(serialize_with = "crate::util::amount::serialize")]
+    pub value: u64,
+    pub share: f64,
+}
+
+// upper bounds (in seconds) of the utxo age buckets
+pub const UTXO_AGE_BUCKETS: &[u32] = &[
+    86_400,      // 1 day
+    604_800,     // 1 week
+    2_592_000,   // 1 month
+    7_776_000,   // 3 months
+    15_552_000,  // 6 months
+    31_536_000,  // 1 year
+    63_072_000,  // 2 years
+    94_608_000,  // 3 years
+    157_680_000, // 5 years
+];
+
+/// Groups the unspent value by its age relative to `tip_time`, using the block time of
+/// the height it was created at (as returned by `time_at`).
+pub fn utxo_age_buckets(
+    unspent_by_height: &[u64],
+    tip_time: u32,
+    time_at: impl Fn(usize) -> u32,
+) -> Vec<UtxoAgeBucket> {
+    let mut values = vec![0u64; UTXO_AGE_BUCKETS.len() + 1];
+    for (height, value) in unspent_by_height.iter().enumerate() {
+        if *value == 0 {
+            continue;
+        }
+        let age = tip_time.saturating_sub(time_at(height));
+        let bucket = UTXO_AGE_BUCKETS
+            .iter()
+            .position(|max_age| age < *max_age)
+            .unwrap_or(UTXO_AGE_BUCKETS.len());
+        values[bucket] += value;
+    }
+
+    let total: u64 = values.iter().sum();
+    values
+        .into_iter()
+        .enumerate()
+        .map(|(i, value)| UtxoAgeBucket {
+            max_age: UTXO_AGE_BUCKETS.get(i).copied(),
+            value,
+            share: if total > 0 {
+                value as f64 / total as f64
+            } else {
+                0.0
+            },
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_connect() {
+        let mut chain = ChainStats::new();
+        chain.connect(&BlockStats {
+            created_value: 50,
+            issued_value: 50,
+            ..Default::default()
+        });
+        assert_eq!(chain.unspent_by_height(), &[50]);
+        assert_eq!(
+            chain.supply_at(0),
+            Some(Supply {
+                total: 50,
+                burned: 0
+            })
+        );
+        assert_eq!(chain.supply_at(1), None);
+
+        chain.connect(&BlockStats {
+            created_value: 30,
+            issued_value: 10,
+            burned_value: 5,
+            spent_by_height: vec![(0, 20)],
+            multisig_spends: vec![],
+            ..Default::default()
+        });
+        assert_eq!(chain.unspent_by_height(), &[30, 30]);
+        assert_eq!(chain.supply_at(1).unwrap().circulating(), 55);
+    }
+
+    #[test]
+    fn test_spent_in_same_block() {
+        let mut chain = ChainStats::new();
+        let coinbase = BlockStats {
+            created_value: 50,
+            issued_value: 50,
+            ..Default::default()
+        };
+        chain.connect(&coinbase);
+        // spends 20 of the first block and 15 of the 40 it creates
+        chain.connect(&BlockStats {
+            created_value: 40,
+            spent_by_height: vec![(0, 20), (1, 15)],
+            ..Default::default()
+        });
+        assert_eq!(chain.unspent_by_height(), &[30, 25]);
+    }
+
+    #[test]
+    fn test_utxo_age_buckets() {
+        let buckets = utxo_age_buckets(&[10, 0, 30], 200_000_000, |height| {
+            [0, 100_000_000, 199_999_000][height]
+        });
+        assert_eq!(buckets.len(), UTXO_AGE_BUCKETS.len() + 1);
+        assert_eq!(buckets[0].value, 30);
+        assert_eq!(buckets[0].share, 0.75);
+        assert_eq!(buckets.last().unwrap().value, 10);
+        assert_eq!(buckets.last().unwrap().max_age, None);
+    }
+}
@@ -0,0 +1,162 @@
+use bitcoin::hex::DisplayHex;
+
+use std::str::FromStr;
+
+use crate::errors::*;
+use crate::metrics::{CounterVec, MetricOpts, Metrics};
+use crate::util::Bytes;
+
+const CHECKSUM_LEN: usize = 4;
+
+/// What to do with the txstore rows failing checksum verification
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum ChecksumMode {
+    /// Don't verify the checksums
+    Off,
+    /// Log the mismatches, and serve the rows anyway
+    Log,
+    /// Refuse to serve the rows, failing the query with an error
+    Fail,
+}
+
+impl FromStr for ChecksumMode {
+    type Err = String;
+
+    fn from_str(mode: &str) -> std::result::Result<Self, Self::Err> {
+        match mode {
+            "off" => Ok(ChecksumMode::Off),
+            "log" => Ok(ChecksumMode::Log),
+            "fail" => Ok(ChecksumMode::Fail),
+            _ => Err(format!(
+                "Invalid checksum mode {:?} (expected off, log or fail)",
+                mode
+            )),
+        }
+    }
+}
+
+/// Appends the checksum (CRC-32) of a row's value
+pub fn append_checksum(mut value: Bytes) -> Bytes {
+    let checksum = crc32fast::hash(&value);
+    value.extend_from_slice(&checksum.to_le_bytes());
+    value
+}
+
+/// Strips the checksums from the values of the transaction and block rows of the txstore,
+/// verifying them on the way, so that data corrupted on disk isn't silently served.
+pub struct ChecksumVerifier {
+    enabled: bool,
+    mode: ChecksumMode,
+    failures: CounterVec,
+    refused: CounterVec,
+}
+
+impl ChecksumVerifier {
+    /// `enabled` is whether the txstore rows carry checksums
+    pub fn new(enabled: bool, mode: ChecksumMode, metrics: &Metrics) -> Self {
+        ChecksumVerifier {
+            enabled,
+            mode,
+            failures: metrics.counter_vec(
+                MetricOpts::new(
+                    "txstore_checksum_failures",
+                    "# of txstore rows read with an invalid checksum, by row type",
+                ),
+                &["row"],
+            ),
+            refused: metrics.counter_vec(
+                MetricOpts::new(
+                    "txstore_checksum_refused",
+                    "# of txstore rows refused in the fail mode for an invalid checksum, by row type",
+                ),
+                &["row"],
+            ),
+        }
+    }
+
+    pub fn enabled(&self) -> bool {
+        self.enabled
+    }
+
+    /// The value of the `what` row at `key`, without its checksum. Fails on mismatches in the
+    /// `fail` mode.
+    pub fn verify(&self, what: &str, key: &[u8], mut value: Bytes) -> Result<Bytes> {
+        if !self.enabled {
+            return Ok(value);
+        }
+        let checksum = value.split_off(value.len().saturating_sub(CHECKSUM_LEN));
+        if self.mode == ChecksumMode::Off
+            || checksum[..] == crc32fast::hash(&value).to_le_bytes()[..]
+        {
+            return Ok(value);
+        }
+
+        self.failures.with_label_values(&[what]).inc();
+        let message = format!(
+            "corrupt {} row {} in the txstore (checksum mismatch), the database should be \
+             restored or reindexed",
+            what,
+            key.to_lower_hex_string()
+        );
+        error!("{}", message);
+        if self.mode == ChecksumMode::Fail {
+            self.refused.with_label_values(&[what]).inc();
+            bail!(message);
+        }
+        Ok(value)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn verifier(mode: ChecksumMode) -> ChecksumVerifier {
+        let metrics = Metrics::new("127.0.0.1:0".parse().unwrap());
+        ChecksumVerifier::new(true, mode, &metrics)
+    }
+
+    #[test]
+    fn test_verify() {
+        let value = b"raw transaction".to_vec();
+        let row = append_checksum(value.clone());
+        assert_eq!(row.len(), value.len() + CHECKSUM_LEN);
+        assert_eq!(
+            verifier(ChecksumMode::Fail)
+                .verify("tx", b"T", row)
+                .unwrap(),
+            value
+        );
+
+        // the corrupt rows are still served in the log mode
+        let mut corrupt = append_checksum(value.clone());
+        corrupt[0] ^= 1;
+        let verifier = verifier(ChecksumMode::Log);
+        assert_eq!(
+            verifier.verify("tx", b"T", corrupt).unwrap()[1..],
+            value[1..]
+        );
+        assert_eq!(verifier.failures.with_label_values(&["tx"]).get(), 1);
+        assert_eq!(verifier.verify("tx", b"T", vec![1]).unwrap(), vec![]);
+        assert_eq!(verifier.failures.with_label_values(&["tx"]).get(), 2);
+        assert_eq!(verifier.refused.with_label_values(&["tx"]).get(), 0);
+    }
+
+    #[test]
+    fn test_verify_fail() {
+        let mut corrupt = append_checksum(b"raw transaction".to_vec());
+        corrupt[0] ^= 1;
+        let verifier = verifier(ChecksumMode::Fail);
+        let err = verifier.verify("tx", b"T", corrupt).unwrap_err();
+        assert!(err.to_string().contains("checksum mismatch"));
+        assert_eq!(verifier.refused.with_label_values(&["tx"]).get(), 1);
+    }
+
+    #[test]
+    fn test_verify_disabled() {
+        let metrics = Metrics::new("127.0.0.1:0".parse().unwrap());
+        let value = b"raw transaction".to_vec();
+        let verifier = ChecksumVerifier::new(false, ChecksumMode::Fail, &metrics);
+        assert_eq!(verifier.verify("tx", b"T", value.clone()).unwrap(), value);
+    }
+}
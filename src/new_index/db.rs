@@ -1,17 +1,71 @@
 use prometheus::GaugeVec;
 use rocksdb;
 
+use std::cell::Cell;
+use std::collections::BTreeMap;
 use std::convert::TryInto;
+use std::fs;
+use std::io;
+use std::os::unix::io::AsRawFd;
 use std::path::Path;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::Arc;
 use std::thread;
 use std::time::Duration;
 
 use crate::config::Config;
+use crate::errors::*;
 use crate::new_index::db_metrics::RocksDbMetrics;
-use crate::util::{bincode, spawn_thread, Bytes};
+use crate::util::memory::WRITE_BUFFERS_PER_DB;
+use crate::util::supervisor::spawn_supervised;
+use crate::util::{bincode, Bytes};
 
-static DB_VERSION: u32 = 1;
+/// The version of the database layout, bumped when it changes incompatibly
+pub static DB_VERSION: u32 = 2;
+
+// transient failures (like a busy or timed out write) are retried this many times, waiting for
+// twice as long before each attempt
+const MAX_RETRIES: u32 = 5;
+const RETRY_DELAY: Duration = Duration::from_millis(100);
+
+thread_local! {
+    static READ_STATS: Cell<ReadStats> = Cell::new(ReadStats::default());
+}
+
+/// Database reads made by a thread, used to attribute reads to the request it is handling.
+/// Reads made by parallel lookups on the rayon thread pool are not included.
+#[derive(Copy, Clone, Debug, Default)]
+pub struct ReadStats {
+    pub rows_scanned: u64,
+    pub point_reads: u64,
+    pub bytes_read: u64,
+}
+
+impl ReadStats {
+    /// Runs `f`, returning its result along with the reads it made on the current thread
+    pub fn measure<T>(f: impl FnOnce() -> T) -> (T, ReadStats) {
+        let before = READ_STATS.with(Cell::get);
+        let result = f();
+        let after = READ_STATS.with(Cell::get);
+        let reads = ReadStats {
+            rows_scanned: after.rows_scanned - before.rows_scanned,
+            point_reads: after.point_reads - before.point_reads,
+            bytes_read: after.bytes_read - before.bytes_read,
+        };
+        (result, reads)
+    }
+
+    fn record(rows_scanned: u64, point_reads: u64, bytes_read: usize) {
+        READ_STATS.with(|stats| {
+            let mut current = stats.get();
+            current.rows_scanned += rows_scanned;
+            current.point_reads += point_reads;
+            current.bytes_read += bytes_read as u64;
+            stats.set(current);
+        })
+    }
+}
 
 #[derive(Debug, Eq, PartialEq)]
 pub struct DBRow {
@@ -26,21 +80,28 @@ pub struct ScanIterator<'a> {
 }
 
 impl<'a> Iterator for ScanIterator<'a> {
-    type Item = DBRow;
+    type Item = Result<DBRow>;
 
-    fn next(&mut self) -> Option<DBRow> {
+    fn next(&mut self) -> Option<Result<DBRow>> {
         if self.done {
             return None;
         }
-        let (key, value) = self.iter.next()?.expect("valid iterator");
+        let (key, value) = match self.iter.next()? {
+            Ok(item) => item,
+            Err(e) => {
+                self.done = true;
+                return Some(Err(e).chain_err(|| "failed scanning the database"));
+            }
+        };
         if !key.starts_with(&self.prefix) {
             self.done = true;
             return None;
         }
-        Some(DBRow {
+        ReadStats::record(1, 0, key.len() + value.len());
+        Some(Ok(DBRow {
             key: key.to_vec(),
             value: value.to_vec(),
-        })
+        }))
     }
 }
 
@@ -51,12 +112,19 @@ pub struct ReverseScanIterator<'a> {
 }
 
 impl<'a> Iterator for ReverseScanIterator<'a> {
-    type Item = DBRow;
+    type Item = Result<DBRow>;
 
-    fn next(&mut self) -> Option<DBRow> {
-        if self.done || !self.iter.valid() {
+    fn next(&mut self) -> Option<Result<DBRow>> {
+        if self.done {
             return None;
         }
+        if !self.iter.valid() {
+            self.done = true;
+            return match self.iter.status() {
+                Ok(()) => None,
+                Err(e) => Some(Err(e).chain_err(|| "failed scanning the database")),
+            };
+        }
 
         let key = self.iter.key().unwrap();
         if !key.starts_with(&self.prefix) {
@@ -68,16 +136,26 @@ impl<'a> Iterator for ReverseScanIterator<'a> {
             key: key.into(),
             value: self.iter.value().unwrap().into(),
         };
+        ReadStats::record(1, 0, row.key.len() + row.value.len());
 
         self.iter.prev();
 
-        Some(row)
+        Some(Ok(row))
     }
 }
 
 #[derive(Debug)]
 pub struct DB {
     db: Arc<rocksdb::DB>,
+    // set when running low on disk space, refusing the writes made in the meantime
+    read_only: AtomicBool,
+}
+
+/// The number of rows under a key prefix, and their size in bytes (uncompressed)
+#[derive(Copy, Clone, Debug, Default)]
+pub struct PrefixUsage {
+    pub rows: u64,
+    pub bytes: u64,
 }
 
 #[derive(Copy, Clone, Debug)]
@@ -86,9 +164,39 @@ pub enum DBFlush {
     Enable,
 }
 
+/// Which of the indexed blocks are written through the write-ahead log, see `--wal-mode`.
+/// The blocks written without it are only durable once the databases are flushed, at the end of
+/// each index update.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum WalMode {
+    /// None of them, for the fastest sync
+    Off,
+    /// Only the blocks near the daemon's tip, skipping it for the bulk historical writes
+    TipOnly,
+    /// All of them
+    Always,
+}
+
+impl FromStr for WalMode {
+    type Err = String;
+
+    fn from_str(mode: &str) -> std::result::Result<Self, Self::Err> {
+        match mode {
+            "off" => Ok(WalMode::Off),
+            "tip-only" => Ok(WalMode::TipOnly),
+            "always" => Ok(WalMode::Always),
+            _ => Err(format!(
+                "Invalid WAL mode {:?} (expected off, tip-only or always)",
+                mode
+            )),
+        }
+    }
+}
+
 impl DB {
-    pub fn open(path: &Path, config: &Config) -> DB {
+    pub fn open(path: &Path, config: &Config) -> Result<DB> {
         debug!("opening DB at {:?}", path);
+        check_lock(path)?;
         let mut db_opts = rocksdb::Options::default();
         db_opts.create_if_missing(true);
         db_opts.set_max_open_files(100_000); // TODO: make sure to `ulimit -n` this process correctly
@@ -107,6 +215,10 @@ impl DB {
 
         // Configure write buffer size (not set by increase_parallelism)
         db_opts.set_write_buffer_size(config.db_write_buffer_size_mb * 1024 * 1024);
+        if config.memory_budget_mb.is_some() {
+            // pinned to what the budget was split for
+            db_opts.set_max_write_buffer_number(WRITE_BUFFERS_PER_DB as i32);
+        }
 
         // db_opts.set_advise_random_on_open(???);
         db_opts.set_compaction_readahead_size(1 << 20);
@@ -117,11 +229,13 @@ impl DB {
         block_opts.set_block_cache(&rocksdb::Cache::new_lru_cache(cache_size_bytes));
         db_opts.set_block_based_table_factory(&block_opts);
 
+        let db = rocksdb::DB::open(&db_opts, path).map_err(|e| open_error(path, e))?;
         let db = DB {
-            db: Arc::new(rocksdb::DB::open(&db_opts, path).expect("failed to open RocksDB")),
+            db: Arc::new(db),
+            read_only: AtomicBool::new(false),
         };
-        db.verify_compatibility(config);
-        db
+        db.verify_compatibility(path, config)?;
+        Ok(db)
     }
 
     pub fn full_compaction(&self) {
@@ -131,9 +245,9 @@ impl DB {
         debug!("finished full compaction on {:?}", self.db);
     }
 
-    pub fn enable_auto_compaction(&self) {
+    pub fn enable_auto_compaction(&self) -> Result<()> {
         let opts = [("disable_auto_compactions", "false")];
-        self.db.set_options(&opts).unwrap();
+        retry(|| self.db.set_options(&opts))
     }
 
     pub fn raw_iterator(&self) -> rocksdb::DBRawIterator {
@@ -171,7 +285,18 @@ impl DB {
         }
     }
 
-    pub fn write(&self, mut rows: Vec<DBRow>, flush: DBFlush) {
+    pub fn set_read_only(&self, read_only: bool) {
+        self.read_only.store(read_only, Ordering::Relaxed);
+    }
+
+    pub fn is_read_only(&self) -> bool {
+        self.read_only.load(Ordering::Relaxed)
+    }
+
+    pub fn write(&self, mut rows: Vec<DBRow>, flush: DBFlush) -> Result<()> {
+        if self.is_read_only() {
+            bail!(ErrorKind::ReadOnly);
+        }
         log::trace!(
             "writing {} rows to {:?}, flush={:?}",
             rows.len(),
@@ -179,10 +304,6 @@ impl DB {
             flush
         );
         rows.sort_unstable_by(|a, b| a.key.cmp(&b.key));
-        let mut batch = rocksdb::WriteBatch::default();
-        for row in rows {
-            batch.put(&row.key, &row.value);
-        }
         let do_flush = match flush {
             DBFlush::Enable => true,
             DBFlush::Disable => false,
@@ -190,53 +311,113 @@ impl DB {
         let mut opts = rocksdb::WriteOptions::new();
         opts.set_sync(do_flush);
         opts.disable_wal(!do_flush);
-        self.db.write_opt(batch, &opts).unwrap();
+        retry(|| {
+            let mut batch = rocksdb::WriteBatch::default();
+            for row in &rows {
+                batch.put(&row.key, &row.value);
+            }
+            self.db.write_opt(batch, &opts)
+        })
+    }
+
+    pub fn delete(&self, keys: &[Vec<u8>]) -> Result<()> {
+        if self.is_read_only() {
+            bail!(ErrorKind::ReadOnly);
+        }
+        log::trace!("deleting {} rows from {:?}", keys.len(), self.db);
+        let mut opts = rocksdb::WriteOptions::new();
+        opts.set_sync(true);
+        retry(|| {
+            let mut batch = rocksdb::WriteBatch::default();
+            for key in keys {
+                batch.delete(key);
+            }
+            self.db.write_opt(batch, &opts)
+        })
     }
 
-    pub fn flush(&self) {
-        self.db.flush().unwrap();
+    pub fn flush(&self) -> Result<()> {
+        retry(|| self.db.flush())
     }
 
-    pub fn put(&self, key: &[u8], value: &[u8]) {
-        self.db.put(key, value).unwrap();
+    pub fn put(&self, key: &[u8], value: &[u8]) -> Result<()> {
+        retry(|| self.db.put(key, value))
     }
 
-    pub fn put_sync(&self, key: &[u8], value: &[u8]) {
+    pub fn put_sync(&self, key: &[u8], value: &[u8]) -> Result<()> {
         let mut opts = rocksdb::WriteOptions::new();
         opts.set_sync(true);
-        self.db.put_opt(key, value, &opts).unwrap();
+        retry(|| self.db.put_opt(key, value, &opts))
     }
 
-    pub fn get(&self, key: &[u8]) -> Option<Bytes> {
-        self.db.get(key).unwrap().map(|v| v.to_vec())
+    /// Reads are retried like the writes
+    pub fn get(&self, key: &[u8]) -> Result<Option<Bytes>> {
+        let value = retry(|| self.db.get(key))
+            .chain_err(|| format!("failed reading from {:?}", self.db))?;
+        ReadStats::record(0, 1, value.as_ref().map_or(0, Vec::len));
+        Ok(value)
     }
 
-    pub fn multi_get<K, I>(&self, keys: I) -> Vec<Result<Option<Vec<u8>>, rocksdb::Error>>
+    pub fn multi_get<K, I>(
+        &self,
+        keys: I,
+    ) -> Vec<std::result::Result<Option<Vec<u8>>, rocksdb::Error>>
     where
         K: AsRef<[u8]>,
         I: IntoIterator<Item = K>,
     {
-        self.db.multi_get(keys)
+        let values = self.db.multi_get(keys);
+        let bytes_read = values
+            .iter()
+            .map(|value| {
+                value
+                    .as_ref()
+                    .ok()
+                    .and_then(Option::as_ref)
+                    .map_or(0, Vec::len)
+            })
+            .sum();
+        ReadStats::record(0, values.len() as u64, bytes_read);
+        values
     }
 
-    fn verify_compatibility(&self, config: &Config) {
-        let mut compatibility_bytes = bincode::serialize_little(&DB_VERSION).unwrap();
-
-        if config.light_mode {
-            // append a byte to indicate light_mode is enabled.
-            // we're not letting bincode serialize this so that the compatiblity bytes won't change
-            // (and require a reindex) when light_mode is disabled. this should be chagned the next
-            // time we bump DB_VERSION and require a re-index anyway.
-            compatibility_bytes.push(1);
+    /// Scans the whole database, tallying its rows by the first byte of their key
+    pub fn key_space_usage(&self) -> Result<BTreeMap<u8, PrefixUsage>> {
+        let mut opts = rocksdb::ReadOptions::default();
+        // don't evict the hot blocks for a one-off scan
+        opts.fill_cache(false);
+        let mut iter = self.db.raw_iterator_opt(opts);
+        iter.seek_to_first();
+
+        let mut usage: BTreeMap<u8, PrefixUsage> = BTreeMap::new();
+        while let Some((key, value)) = iter.item() {
+            let prefix = usage.entry(key.first().copied().unwrap_or(0)).or_default();
+            prefix.rows += 1;
+            prefix.bytes += (key.len() + value.len()) as u64;
+            iter.next();
         }
+        iter.status()?;
+        Ok(usage)
+    }
 
-        match self.get(b"V") {
-            None => self.put(b"V", &compatibility_bytes),
-            Some(ref x) if x != &compatibility_bytes => {
-                panic!("Incompatible database found. Please reindex.")
-            }
+    fn verify_compatibility(&self, path: &Path, config: &Config) -> Result<()> {
+        let compatibility_bytes =
+            bincode::serialize_little(&(DB_VERSION, config.light_mode)).unwrap();
+
+        match self.get(b"V")? {
+            None => self
+                .put(b"V", &compatibility_bytes)
+                .chain_err(|| "failed writing the database version")?,
+            Some(ref x) if x != &compatibility_bytes => bail!(
+                "the database at {} was created by an incompatible version, or with{} \
+                 --lightmode: remove it to reindex, or run with{} --lightmode",
+                path.display(),
+                if config.light_mode { "out" } else { "" },
+                if config.light_mode { "out" } else { "" },
+            ),
             Some(_) => (),
         }
+        Ok(())
     }
 
     pub fn start_stats_exporter(&self, db_metrics: Arc<RocksDbMetrics>, db_name: &str) {
@@ -251,7 +432,7 @@ impl DB {
             }
         };
 
-        spawn_thread("db_stats_exporter", move || loop {
+        spawn_supervised("db_stats_exporter", move || loop {
             update_gauge(
                 &db_metrics.num_immutable_mem_table,
                 "rocksdb.num-immutable-mem-table",
@@ -364,3 +545,87 @@ impl DB {
         });
     }
 }
+
+fn retry<T>(mut op: impl FnMut() -> std::result::Result<T, rocksdb::Error>) -> Result<T> {
+    let mut delay = RETRY_DELAY;
+    let mut retries = 0;
+    loop {
+        match op() {
+            Err(e) if is_transient(&e) && retries < MAX_RETRIES => {
+                warn!("database operation failed ({}), retrying in {:?}", e, delay);
+                thread::sleep(delay);
+                delay *= 2;
+                retries += 1;
+            }
+            result => return Ok(result?),
+        }
+    }
+}
+
+fn is_transient(e: &rocksdb::Error) -> bool {
+    use rocksdb::ErrorKind::*;
+    matches!(e.kind(), Busy | TryAgain | TimedOut | Incomplete | Aborted)
+}
+
+// RocksDB holds a lock on the `LOCK` file of the databases it opens. The file is left behind by
+// every shutdown and harmless, but a lock still held means that another process is using the
+// database, which RocksDB only reports as a generic I/O error.
+fn check_lock(path: &Path) -> Result<()> {
+    let lock_path = path.join("LOCK");
+    let file = match fs::OpenOptions::new()
+        .read(true)
+        .write(true)
+        .open(&lock_path)
+    {
+        Ok(file) => file,
+        Err(_) => return Ok(()), // a new database
+    };
+    let mut lock: libc::flock = unsafe { std::mem::zeroed() };
+    lock.l_type = libc::F_WRLCK as libc::c_short;
+    lock.l_whence = libc::SEEK_SET as libc::c_short;
+    if unsafe { libc::fcntl(file.as_raw_fd(), libc::F_GETLK, &mut lock) } != 0 {
+        warn!(
+            "failed checking the lock of {}: {}",
+            lock_path.display(),
+            io::Error::last_os_error()
+        );
+    } else if lock.l_type != libc::F_UNLCK as libc::c_short {
+        bail!(
+            "the database at {} is in use by process {} (another electrs instance?): stop it, or \
+             use another --db-dir",
+            path.display(),
+            lock.l_pid
+        );
+    } else {
+        debug!(
+            "{} is not locked, left by a previous run",
+            lock_path.display()
+        );
+    }
+    Ok(())
+}
+
+// Explain how to recover from the errors opening a database fails with
+fn open_error(path: &Path, e: rocksdb::Error) -> Error {
+    let message = e.to_string();
+    let guidance = match e.kind() {
+        rocksdb::ErrorKind::Corruption => {
+            "it is corrupted: restore it from a backup, or remove it to reindex"
+        }
+        _ if message.contains("lock") => {
+            "it could not be locked although no other process holds the lock. On network \
+             filesystems, locks can outlive an unclean shutdown: make sure that no other electrs \
+             instance uses it, and remove its LOCK file"
+        }
+        _ if message.contains("No space left") => "the disk is full",
+        _ if message.contains("Permission denied") => {
+            "it must be writable by the user running electrs"
+        }
+        _ => "",
+    };
+    let mut description = format!("failed opening the database at {}", path.display());
+    if !guidance.is_empty() {
+        description += &format!(": {}", guidance);
+    }
+    Error::from(ErrorKind::DbError(e)).chain_err(|| description)
+}
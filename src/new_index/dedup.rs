@@ -0,0 +1,125 @@
+use std::collections::hash_map::DefaultHasher;
+use std::collections::{HashMap, VecDeque};
+use std::hash::{Hash, Hasher};
+
+use crate::new_index::db::DBRow;
+
+/// A window of the last `--index-dedup-window` rows written by the indexer to a db, so that the
+/// rows produced again at the same value (like the transactions of the blocks orphaned and
+/// replaced during a reorg-heavy catch-up) aren't rewritten, which would only add compaction work.
+///
+/// The rows are remembered by the hashes of their key and value, and only once written, so that a
+/// failed write is retried in full. The window has to be cleared when rows are deleted from the
+/// db, as they would otherwise be skipped when written again.
+pub(super) struct WriteDedup {
+    capacity: usize,
+    // hash(key) -> hash(value)
+    written: HashMap<u64, u64>,
+    order: VecDeque<u64>,
+}
+
+/// The hashes of the rows about to be written, to remember once they are
+pub(super) struct PendingRows(Vec<(u64, u64)>);
+
+impl WriteDedup {
+    pub fn new(capacity: usize) -> Self {
+        WriteDedup {
+            capacity,
+            written: HashMap::new(),
+            order: VecDeque::new(),
+        }
+    }
+
+    /// Drops the rows already written at the same value within the window, returning the rows
+    /// left to write and the number of rows dropped
+    pub fn filter(&self, rows: Vec<DBRow>) -> (Vec<DBRow>, PendingRows, usize) {
+        if self.capacity == 0 {
+            return (rows, PendingRows(vec![]), 0);
+        }
+        let count = rows.len();
+        let mut pending = Vec::with_capacity(count);
+        let rows: Vec<DBRow> = rows
+            .into_iter()
+            .filter(|row| {
+                let hashes = (hash(&row.key), hash(&row.value));
+                if self.written.get(&hashes.0) == Some(&hashes.1) {
+                    return false;
+                }
+                pending.push(hashes);
+                true
+            })
+            .collect();
+        let skipped = count - rows.len();
+        (rows, PendingRows(pending), skipped)
+    }
+
+    pub fn remember(&mut self, pending: PendingRows) {
+        for (key_hash, value_hash) in pending.0 {
+            if self.written.insert(key_hash, value_hash).is_none() {
+                self.order.push_back(key_hash);
+            }
+        }
+        while self.order.len() > self.capacity {
+            let oldest = self.order.pop_front().expect("non-empty");
+            self.written.remove(&oldest);
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.written.clear();
+        self.order.clear();
+    }
+}
+
+fn hash(bytes: &[u8]) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    bytes.hash(&mut hasher);
+    hasher.finish()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn row(key: &[u8], value: &[u8]) -> DBRow {
+        DBRow {
+            key: key.to_vec(),
+            value: value.to_vec(),
+        }
+    }
+
+    #[test]
+    fn test_write_dedup() {
+        let mut dedup = WriteDedup::new(2);
+        let (rows, pending, skipped) = dedup.filter(vec![row(b"a", b"1"), row(b"b", b"1")]);
+        assert_eq!((rows.len(), skipped), (2, 0));
+        dedup.remember(pending);
+
+        // the same value is skipped, a new value is written
+        let (rows, pending, skipped) = dedup.filter(vec![row(b"a", b"1"), row(b"b", b"2")]);
+        assert_eq!(rows, vec![row(b"b", b"2")]);
+        assert_eq!(skipped, 1);
+        dedup.remember(pending);
+
+        // the oldest row leaves the window
+        let (_, pending, _) = dedup.filter(vec![row(b"c", b"1")]);
+        dedup.remember(pending);
+        assert_eq!(dedup.filter(vec![row(b"a", b"1")]).2, 0);
+        assert_eq!(dedup.filter(vec![row(b"c", b"1")]).2, 1);
+
+        // rows that weren't written aren't remembered
+        let _ = dedup.filter(vec![row(b"d", b"1")]);
+        assert_eq!(dedup.filter(vec![row(b"d", b"1")]).2, 0);
+
+        dedup.clear();
+        assert_eq!(dedup.filter(vec![row(b"c", b"1")]).2, 0);
+    }
+
+    #[test]
+    fn test_write_dedup_disabled() {
+        let mut dedup = WriteDedup::new(0);
+        let (_, pending, _) = dedup.filter(vec![row(b"a", b"1")]);
+        dedup.remember(pending);
+        assert_eq!(dedup.filter(vec![row(b"a", b"1")]).2, 0);
+    }
+}
@@ -0,0 +1,184 @@
+use std::collections::{BTreeMap, HashMap};
+
+use utoipa::ToSchema;
+
+use crate::chain::{OutPoint, Transaction, Txid};
+use crate::util::{has_prevout, Eviction};
+
+// A transaction that left the mempool without being confirmed
+#[derive(Serialize, ToSchema)]
+pub struct EvictedTx {
+    #[schema(value_type = String)]
+    pub txid: Txid,
+    #[serde(flatten)]
+    pub eviction: Eviction,
+    #[serde(skip)]
+    pub tx: Transaction,
+}
+
+/// The most recent transactions to leave the mempool unconfirmed, indexed by txid and by the
+/// outputs they spend.
+///
+/// A transaction evicted again after re-entering the mempool only keeps its latest eviction.
+pub struct EvictedTxs {
+    capacity: usize,
+    next_seq: u64,
+    entries: BTreeMap<u64, EvictedTx>, // insertion sequence -> entry, oldest first
+    by_txid: HashMap<Txid, u64>,       // Txid -> insertion sequence
+    // OutPoint -> the evicted txs spending it
+    by_outpoint: HashMap<OutPoint, Vec<Txid>>,
+}
+
+impl EvictedTxs {
+    pub fn new(capacity: usize) -> Self {
+        EvictedTxs {
+            capacity: capacity.max(1),
+            next_seq: 0,
+            entries: BTreeMap::new(),
+            by_txid: HashMap::new(),
+            by_outpoint: HashMap::new(),
+        }
+    }
+
+    pub fn push(&mut self, evicted: EvictedTx) {
+        self.remove(&evicted.txid);
+        let seq = self.next_seq;
+        self.next_seq += 1;
+        for outpoint in spent_outpoints(&evicted.tx) {
+            self.by_outpoint
+                .entry(outpoint)
+                .or_default()
+                .push(evicted.txid);
+        }
+        self.by_txid.insert(evicted.txid, seq);
+        self.entries.insert(seq, evicted);
+
+        if self.entries.len() > self.capacity {
+            let oldest = self.entries.values().next().expect("not empty").txid;
+            self.remove(&oldest);
+        }
+    }
+
+    fn remove(&mut self, txid: &Txid) {
+        let evicted = match self.by_txid.remove(txid) {
+            Some(seq) => self.entries.remove(&seq).expect("indexed entry"),
+            None => return,
+        };
+        for outpoint in spent_outpoints(&evicted.tx) {
+            if let Some(txids) = self.by_outpoint.get_mut(&outpoint) {
+                txids.retain(|spending_txid| spending_txid != txid);
+                if txids.is_empty() {
+                    self.by_outpoint.remove(&outpoint);
+                }
+            }
+        }
+    }
+
+    pub fn get(&self, txid: &Txid) -> Option<&EvictedTx> {
+        self.by_txid.get(txid).map(|seq| &self.entries[seq])
+    }
+
+    /// The evicted transactions spending `outpoint`
+    pub fn spending(&self, outpoint: &OutPoint) -> impl Iterator<Item = &EvictedTx> {
+        self.by_outpoint
+            .get(outpoint)
+            .into_iter()
+            .flatten()
+            .filter_map(move |txid| self.get(txid))
+    }
+
+    /// Most recent first
+    pub fn iter(&self) -> impl Iterator<Item = &EvictedTx> {
+        self.entries.values().rev()
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+}
+
+fn spent_outpoints(tx: &Transaction) -> impl Iterator<Item = OutPoint> + '_ {
+    tx.input
+        .iter()
+        .filter(|txin| has_prevout(txin))
+        .map(|txin| txin.previous_output)
+}
+
+#[cfg(all(test, not(feature = "liquid")))]
+mod tests {
+    use super::*;
+    use crate::util::EvictionReason;
+    use bitcoin::absolute::LockTime;
+    use bitcoin::hashes::Hash;
+    use bitcoin::transaction::Version;
+    use bitcoin::{ScriptBuf, Sequence, TxIn, Witness};
+
+    fn outpoint(vout: u32) -> OutPoint {
+        OutPoint::new(Txid::from_byte_array([1; 32]), vout)
+    }
+
+    fn evicted(lock_time: u32, time: u64) -> EvictedTx {
+        let tx = Transaction {
+            version: Version::TWO,
+            lock_time: LockTime::from_consensus(lock_time),
+            input: vec![TxIn {
+                previous_output: outpoint(lock_time % 2),
+                script_sig: ScriptBuf::new(),
+                sequence: Sequence::MAX,
+                witness: Witness::new(),
+            }],
+            output: vec![],
+        };
+        EvictedTx {
+            txid: tx.compute_txid(),
+            eviction: Eviction {
+                reason: EvictionReason::Evicted,
+                replaced_by: None,
+                time,
+            },
+            tx,
+        }
+    }
+
+    #[test]
+    fn test_evicted_txs() {
+        let mut evicted_txs = EvictedTxs::new(2);
+        let (first, second, third) = (evicted(1, 10), evicted(2, 20), evicted(3, 30));
+        let (first_txid, second_txid, third_txid) = (first.txid, second.txid, third.txid);
+        evicted_txs.push(first);
+        evicted_txs.push(second);
+        assert_eq!(evicted_txs.get(&first_txid).unwrap().eviction.time, 10);
+
+        // the oldest entry makes room for the new one
+        evicted_txs.push(third);
+        assert_eq!(evicted_txs.len(), 2);
+        assert!(evicted_txs.get(&first_txid).is_none());
+        let txids: Vec<_> = evicted_txs.iter().map(|e| e.txid).collect();
+        assert_eq!(txids, vec![third_txid, second_txid]);
+
+        // evicted again, only the latest eviction is kept
+        evicted_txs.push(evicted(2, 40));
+        assert_eq!(evicted_txs.len(), 2);
+        assert_eq!(evicted_txs.get(&second_txid).unwrap().eviction.time, 40);
+        let txids: Vec<_> = evicted_txs.iter().map(|e| e.txid).collect();
+        assert_eq!(txids, vec![second_txid, third_txid]);
+    }
+
+    #[test]
+    fn test_evicted_spending() {
+        let mut evicted_txs = EvictedTxs::new(2);
+        let (first, second, third) = (evicted(1, 10), evicted(2, 20), evicted(3, 30));
+        let (first_txid, second_txid, third_txid) = (first.txid, second.txid, third.txid);
+        evicted_txs.push(first);
+        evicted_txs.push(second);
+        evicted_txs.push(third);
+
+        // the first one, spending the same output as the third, was dropped
+        let spending: Vec<_> = evicted_txs.spending(&outpoint(1)).map(|e| e.txid).collect();
+        assert_eq!(spending, vec![third_txid]);
+        let spending: Vec<_> = evicted_txs.spending(&outpoint(0)).map(|e| e.txid).collect();
+        assert_eq!(spending, vec![second_txid]);
+        assert_eq!(evicted_txs.spending(&outpoint(2)).count(), 0);
+        assert!(evicted_txs.get(&first_txid).is_none());
+    }
+}
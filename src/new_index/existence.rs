@@ -0,0 +1,134 @@
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::time::Instant;
+
+use crate::errors::*;
+use crate::metrics::{CounterVec, MetricOpts, Metrics};
+use crate::new_index::db::DBRow;
+
+// the number of bits set per scripthash, giving ~1% of false positives at 10 bits per scripthash
+const HASHES: u64 = 7;
+
+/// An in-memory Bloom filter over the scripthashes with confirmed history, loaded from their
+/// existence markers (`E{scripthash}` rows) and kept up to date by the indexer. It is consulted
+/// before the scripthash lookups, so that the lookups of unused addresses (the bulk of a wallet
+/// restore) don't touch the database.
+///
+/// The filter has no false negatives once loaded, and answers "maybe" for all the scripthashes
+/// while loading. Unlike the markers, it isn't updated on reorgs: the scripthashes only used by
+/// orphaned blocks keep answering "maybe", and are then looked up in the database.
+pub struct ExistenceFilter {
+    bits: Vec<AtomicU64>,
+    ready: AtomicBool,
+    lookups: CounterVec,
+}
+
+impl ExistenceFilter {
+    pub fn new(size_mb: usize, metrics: &Metrics) -> Self {
+        let words = (size_mb << 20) / 8;
+        ExistenceFilter {
+            bits: (0..words.max(1)).map(|_| AtomicU64::new(0)).collect(),
+            ready: AtomicBool::new(false),
+            lookups: metrics.counter_vec(
+                MetricOpts::new(
+                    "existence_filter_lookups",
+                    "# of scripthash lookups checked against the existence filter, by result",
+                ),
+                &["result"],
+            ),
+        }
+    }
+
+    // the bits of a scripthash, from its first 16 bytes (which are uniformly distributed already)
+    fn positions(&self, scripthash: &[u8]) -> impl Iterator<Item = usize> {
+        let mut word = [0u8; 8];
+        word.copy_from_slice(&scripthash[..8]);
+        let h1 = u64::from_le_bytes(word);
+        word.copy_from_slice(&scripthash[8..16]);
+        let h2 = u64::from_le_bytes(word) | 1;
+        let len = self.bits.len() as u64 * 64;
+        (0..HASHES).map(move |i| (h1.wrapping_add(i.wrapping_mul(h2)) % len) as usize)
+    }
+
+    pub fn insert(&self, scripthash: &[u8]) {
+        for pos in self.positions(scripthash) {
+            self.bits[pos / 64].fetch_or(1 << (pos % 64), Ordering::Relaxed);
+        }
+    }
+
+    /// Whether the scripthash may have confirmed history, `false` meaning that it has none
+    pub fn may_exist(&self, scripthash: &[u8]) -> bool {
+        if !self.ready.load(Ordering::Acquire) {
+            self.lookups.with_label_values(&["loading"]).inc();
+            return true;
+        }
+        let exists = self
+            .positions(scripthash)
+            .all(|pos| self.bits[pos / 64].load(Ordering::Relaxed) & (1 << (pos % 64)) != 0);
+        let result = if exists { "maybe" } else { "absent" };
+        self.lookups.with_label_values(&[result]).inc();
+        exists
+    }
+
+    /// Inserts the scripthashes of the existence markers `rows`, then starts answering lookups.
+    /// The markers written meanwhile must be inserted too.
+    pub fn load(&self, rows: impl Iterator<Item = Result<DBRow>>) -> Result<()> {
+        let started = Instant::now();
+        let mut count = 0;
+        for row in rows {
+            self.insert(&row?.key[1..]);
+            count += 1;
+        }
+        self.ready.store(true, Ordering::Release);
+        let bits = self.bits.len() * 64;
+        info!(
+            "loaded the existence filter of {} scripthashes in {:?} ({:.1} bits per scripthash)",
+            count,
+            started.elapsed(),
+            bits as f64 / count.max(1) as f64
+        );
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crypto::digest::Digest;
+    use crypto::sha2::Sha256;
+
+    fn scripthash(i: u32) -> Vec<u8> {
+        let mut hash = [0u8; 32];
+        let mut sha2 = Sha256::new();
+        sha2.input(&i.to_le_bytes());
+        sha2.result(&mut hash);
+        hash.to_vec()
+    }
+
+    fn marker(scripthash: &[u8]) -> DBRow {
+        DBRow {
+            key: [b"E", scripthash].concat(),
+            value: vec![],
+        }
+    }
+
+    #[test]
+    fn test_existence_filter() {
+        let metrics = Metrics::new("127.0.0.1:0".parse().unwrap());
+        let filter = ExistenceFilter::new(1, &metrics);
+        // everything may exist until loaded
+        assert!(filter.may_exist(&scripthash(0)));
+
+        filter.insert(&scripthash(0));
+        filter
+            .load((1..1000).map(|i| Ok(marker(&scripthash(i)))))
+            .unwrap();
+        filter.insert(&scripthash(1000));
+        assert!((0..=1000).all(|i| filter.may_exist(&scripthash(i))));
+
+        let false_positives = (1001..11001)
+            .filter(|i| filter.may_exist(&scripthash(*i)))
+            .count();
+        assert!(false_positives < 10, "{}", false_positives);
+        assert_eq!(filter.lookups.with_label_values(&["loading"]).get(), 1);
+    }
+}
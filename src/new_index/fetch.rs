@@ -19,7 +19,9 @@ use electrs_macros::trace;
 use crate::chain::{Block, BlockHash};
 use crate::daemon::Daemon;
 use crate::errors::*;
-use crate::util::{spawn_thread, HeaderEntry, SyncChannel};
+use crate::util::pool::ThreadPool;
+use crate::util::supervisor::spawn_critical;
+use crate::util::{HeaderEntry, SyncChannel};
 
 #[derive(Clone, Copy, Debug)]
 pub enum FetchFrom {
@@ -32,12 +34,13 @@ pub fn start_fetcher(
     from: FetchFrom,
     daemon: &Daemon,
     new_headers: Vec<HeaderEntry>,
+    pool: &ThreadPool,
 ) -> Result<Fetcher<Vec<BlockEntry>>> {
-    let fetcher = match from {
-        FetchFrom::Bitcoind => bitcoind_fetcher,
-        FetchFrom::BlkFiles => blkfiles_fetcher,
-    };
-    fetcher(daemon, new_headers)
+    match from {
+        FetchFrom::Bitcoind => bitcoind_fetcher(daemon, new_headers),
+        // the blk*.dat files are parsed on the index pool
+        FetchFrom::BlkFiles => blkfiles_fetcher(daemon, new_headers, pool.clone()),
+    }
 }
 
 #[derive(Clone)]
@@ -59,14 +62,15 @@ impl<T> Fetcher<T> {
         Fetcher { receiver, thread }
     }
 
-    pub fn map<F>(self, mut func: F)
+    /// Runs `func` over the fetched items, stopping at the first error. The fetcher thread
+    /// notices the receiver is gone and stops too.
+    pub fn map<F>(self, func: F) -> Result<()>
     where
-        F: FnMut(T) -> (),
+        F: FnMut(T) -> Result<()>,
     {
-        for item in self.receiver {
-            func(item);
-        }
-        self.thread.join().expect("fetcher thread panicked")
+        let result = self.receiver.into_iter().try_for_each(func);
+        self.thread.join().expect("fetcher thread panicked");
+        result
     }
 }
 
@@ -83,7 +87,7 @@ fn bitcoind_fetcher(
     let sender = chan.sender();
     Ok(Fetcher::from(
         chan.into_receiver(),
-        spawn_thread("lokid_fetcher", move || {
+        spawn_critical("lokid_fetcher", move || {
             for entries in new_headers.chunks(100) {
                 let blockhashes: Vec<BlockHash> = entries.iter().map(|e| *e.hash()).collect();
                 let blocks = daemon
@@ -100,9 +104,10 @@ fn bitcoind_fetcher(
                     })
                     .collect();
                 assert_eq!(block_entries.len(), entries.len());
-                sender
-                    .send(block_entries)
-                    .expect("failed to send fetched blocks");
+                if sender.send(block_entries).is_err() {
+                    debug!("stopped fetching blocks, the indexer is gone");
+                    return;
+                }
                 log::debug!("last fetch {:?}", entries.last());
             }
         }),
@@ -113,6 +118,7 @@ fn bitcoind_fetcher(
 fn blkfiles_fetcher(
     daemon: &Daemon,
     new_headers: Vec<HeaderEntry>,
+    pool: ThreadPool,
 ) -> Result<Fetcher<Vec<BlockEntry>>> {
     let magic = daemon.magic();
     let blk_files = daemon.list_blk_files()?;
@@ -124,11 +130,11 @@ fn blkfiles_fetcher(
     let mut entry_map: HashMap<BlockHash, HeaderEntry> =
         new_headers.into_iter().map(|h| (*h.hash(), h)).collect();
 
-    let parser = blkfiles_parser(blkfiles_reader(blk_files, xor_key), magic);
+    let parser = blkfiles_parser(blkfiles_reader(blk_files, xor_key), magic, pool);
     Ok(Fetcher::from(
         chan.into_receiver(),
-        spawn_thread("blkfiles_fetcher", move || {
-            parser.map(|sizedblocks| {
+        spawn_critical("blkfiles_fetcher", move || {
+            let sent = parser.map(|sizedblocks| {
                 let block_entries: Vec<BlockEntry> = sizedblocks
                     .into_iter()
                     .filter_map(|(block, size)| {
@@ -145,8 +151,12 @@ fn blkfiles_fetcher(
                 trace!("fetched {} blocks", block_entries.len());
                 sender
                     .send(block_entries)
-                    .expect("failed to send blocks entries from blk*.dat files");
+                    .chain_err(|| "failed to send blocks entries from blk*.dat files")
             });
+            if sent.is_err() {
+                debug!("stopped fetching blocks, the indexer is gone");
+                return;
+            }
             if !entry_map.is_empty() {
                 panic!(
                     "failed to index {} blocks from blk*.dat files",
@@ -164,7 +174,7 @@ fn blkfiles_reader(blk_files: Vec<PathBuf>, xor_key: Option<[u8; 8]>) -> Fetcher
 
     Fetcher::from(
         chan.into_receiver(),
-        spawn_thread("blkfiles_reader", move || {
+        spawn_critical("blkfiles_reader", move || {
             for path in blk_files {
                 trace!("reading {:?}", path);
                 let mut blob = fs::read(&path)
@@ -172,9 +182,10 @@ fn blkfiles_reader(blk_files: Vec<PathBuf>, xor_key: Option<[u8; 8]>) -> Fetcher
                 if let Some(xor_key) = xor_key {
                     blkfile_apply_xor_key(xor_key, &mut blob);
                 }
-                sender
-                    .send(blob)
-                    .unwrap_or_else(|_| panic!("failed to send {:?} contents", path));
+                if sender.send(blob).is_err() {
+                    debug!("stopped reading blk*.dat files, the parser is gone");
+                    return;
+                }
             }
         }),
     )
@@ -189,20 +200,28 @@ fn blkfile_apply_xor_key(xor_key: [u8; 8], blob: &mut [u8]) {
 }
 
 #[trace]
-fn blkfiles_parser(blobs: Fetcher<Vec<u8>>, magic: u32) -> Fetcher<Vec<SizedBlock>> {
+fn blkfiles_parser(
+    blobs: Fetcher<Vec<u8>>,
+    magic: u32,
+    pool: ThreadPool,
+) -> Fetcher<Vec<SizedBlock>> {
     let chan = SyncChannel::new(1);
     let sender = chan.sender();
 
     Fetcher::from(
         chan.into_receiver(),
-        spawn_thread("blkfiles_parser", move || {
-            blobs.map(|blob| {
+        spawn_critical("blkfiles_parser", move || {
+            let sent = blobs.map(|blob| {
                 trace!("parsing {} bytes", blob.len());
-                let blocks = parse_blocks(blob, magic).expect("failed to parse blk*.dat file");
+                let blocks =
+                    parse_blocks(blob, magic, &pool).expect("failed to parse blk*.dat file");
                 sender
                     .send(blocks)
-                    .expect("failed to send blocks from blk*.dat file");
+                    .chain_err(|| "failed to send blocks from blk*.dat file")
             });
+            if sent.is_err() {
+                debug!("stopped parsing blk*.dat files, the fetcher is gone");
+            }
         }),
     )
 }
@@ -217,7 +236,7 @@ impl<T: AsRef<[u8]>> bitcoin::io::Read for StdCursorCompat<T> {
 }
 
 #[trace]
-fn parse_blocks(blob: Vec<u8>, magic: u32) -> Result<Vec<SizedBlock>> {
+fn parse_blocks(blob: Vec<u8>, magic: u32, pool: &ThreadPool) -> Result<Vec<SizedBlock>> {
     let mut cursor = StdCursorCompat(Cursor::new(&blob));
     let mut slices = vec![];
     let max_pos = blob.len() as u64;
@@ -254,11 +273,6 @@ fn parse_blocks(blob: Vec<u8>, magic: u32) -> Result<Vec<SizedBlock>> {
         cursor.0.set_position(end as u64);
     }
 
-    let pool = rayon::ThreadPoolBuilder::new()
-        .num_threads(0) // CPU-bound
-        .thread_name(|i| format!("parse-blocks-{}", i))
-        .build()
-        .unwrap();
     Ok(pool.install(|| {
         slices
             .into_par_iter()
@@ -0,0 +1,111 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+use crate::chain::BlockHash;
+use crate::metrics::{Histogram, HistogramOpts, Metrics};
+use crate::util::unix_time;
+
+// the announced blocks waiting to be indexed, beyond which the oldest are forgotten (like the
+// blocks orphaned before being indexed)
+const MAX_PENDING: usize = 100;
+
+/// The latency of the new tips, split between the daemon and the indexer, for telling one from the
+/// other when the tip looks stale: the time from the header timestamp to the daemon announcing the
+/// block (over ZMQ, or else when the main loop polls the new tip), and from the announcement to the
+/// block being indexed.
+pub struct BlockLatency {
+    announced: Mutex<HashMap<BlockHash, f64>>, // unix time, in seconds
+    announce_delay: Histogram,
+    index_delay: Histogram,
+    tip_delay: Histogram,
+}
+
+impl BlockLatency {
+    pub fn new(metrics: &Metrics) -> Self {
+        let histogram = |name: &str, help: &str| {
+            metrics.histogram(HistogramOpts::new(name, help).buckets(vec![
+                0.1, 0.5, 1.0, 2.0, 5.0, 10.0, 30.0, 60.0, 120.0, 300.0, 600.0, 1800.0, 3600.0,
+            ]))
+        };
+        BlockLatency {
+            announced: Mutex::new(HashMap::new()),
+            announce_delay: histogram(
+                "block_announce_delay",
+                "Time from the header timestamp of the new tips to the daemon announcing them (in seconds)",
+            ),
+            index_delay: histogram(
+                "block_index_delay",
+                "Time from the daemon announcing the new tips to them being indexed (in seconds)",
+            ),
+            tip_delay: histogram(
+                "block_tip_delay",
+                "Time from the header timestamp of the new tips to them being indexed (in seconds)",
+            ),
+        }
+    }
+
+    /// Records the time the daemon announced `blockhash`, unless it already did
+    pub fn announced(&self, blockhash: BlockHash) {
+        let mut announced = self.announced.lock().unwrap();
+        if announced.len() >= MAX_PENDING && !announced.contains_key(&blockhash) {
+            let oldest = announced
+                .iter()
+                .min_by(|a, b| a.1.total_cmp(b.1))
+                .map(|(hash, _)| *hash);
+            if let Some(oldest) = oldest {
+                announced.remove(&oldest);
+            }
+        }
+        announced
+            .entry(blockhash)
+            .or_insert_with(|| unix_time().as_secs_f64());
+    }
+
+    /// Observes the latency of the new tip `blockhash`, with the `time` of its header, once indexed
+    pub fn indexed(&self, blockhash: &BlockHash, time: u32) {
+        let now = unix_time().as_secs_f64();
+        let time = time as f64;
+        // the header timestamps may be ahead of the local clock
+        self.tip_delay.observe((now - time).max(0.0));
+        if let Some(announced_at) = self.announced.lock().unwrap().remove(blockhash) {
+            self.announce_delay.observe((announced_at - time).max(0.0));
+            self.index_delay.observe((now - announced_at).max(0.0));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bitcoin::hashes::Hash;
+
+    #[test]
+    fn test_block_latency() {
+        let metrics = Metrics::new("127.0.0.1:0".parse().unwrap());
+        let latency = BlockLatency::new(&metrics);
+        let blockhash = BlockHash::from_byte_array([1; 32]);
+        latency.announced(blockhash);
+        let announced_at = latency.announced.lock().unwrap()[&blockhash];
+        // announced again by the polling after ZMQ, the first announcement is kept
+        latency.announced(blockhash);
+        assert_eq!(latency.announced.lock().unwrap()[&blockhash], announced_at);
+
+        let time = announced_at as u32 - 30;
+        latency.indexed(&blockhash, time);
+        assert_eq!(latency.announce_delay.get_sample_count(), 1);
+        assert!(latency.announce_delay.get_sample_sum() >= 29.0);
+        assert_eq!(latency.index_delay.get_sample_count(), 1);
+        assert_eq!(latency.tip_delay.get_sample_count(), 1);
+        assert!(latency.announced.lock().unwrap().is_empty());
+
+        // a tip that wasn't announced only has its tip delay observed
+        latency.indexed(&BlockHash::from_byte_array([2; 32]), time);
+        assert_eq!(latency.index_delay.get_sample_count(), 1);
+        assert_eq!(latency.tip_delay.get_sample_count(), 2);
+
+        for i in 0..=MAX_PENDING {
+            latency.announced(BlockHash::from_byte_array([i as u8; 32]));
+        }
+        assert_eq!(latency.announced.lock().unwrap().len(), MAX_PENDING);
+    }
+}
@@ -0,0 +1,172 @@
+use itertools::Itertools;
+
+use std::collections::HashMap;
+use std::sync::{Mutex, MutexGuard};
+
+use crate::errors::*;
+use crate::metrics::{CacheMetrics, Metrics};
+use crate::new_index::db::DB;
+use crate::util::{full_hash, FullHash};
+
+// the number of locks the scripthashes are spread over
+const LOCK_SHARDS: usize = 64;
+
+/// Keeps track of the scripthash ledgers persisted under the `L` prefix of the cache db.
+///
+/// The updates of a ledger append to its existing entries, so they are serialized per
+/// scripthash (spread over a fixed set of locks). Once `max_size` bytes are used, the least
+/// recently used ledgers are dropped; a dropped ledger is rebuilt from the history on its next
+/// request, with new sequence numbers.
+pub struct LedgerCache {
+    max_size: usize,
+    locks: Vec<Mutex<()>>,
+    state: Mutex<CacheState>,
+    metrics: CacheMetrics,
+}
+
+struct CacheState {
+    // the ledgers already in the db are only accounted for on first use
+    loaded: bool,
+    size: usize,
+    clock: u64,
+    ledgers: HashMap<FullHash, LedgerUsage>,
+}
+
+struct LedgerUsage {
+    size: usize,
+    last_used: u64,
+}
+
+impl LedgerCache {
+    /// A `max_size` of 0 keeps all the ledgers
+    pub fn new(max_size: usize, metrics: &Metrics) -> Self {
+        LedgerCache {
+            max_size,
+            locks: (0..LOCK_SHARDS).map(|_| Mutex::new(())).collect(),
+            state: Mutex::new(CacheState {
+                loaded: false,
+                size: 0,
+                clock: 0,
+                ledgers: HashMap::new(),
+            }),
+            metrics: CacheMetrics::new(metrics, "ledger"),
+        }
+    }
+
+    /// Serializes the updates of the ledger of `scripthash`
+    pub fn lock(&self, scripthash: &[u8]) -> MutexGuard<()> {
+        self.locks[shard(scripthash)].lock().unwrap()
+    }
+
+    /// Accounts for a use of the ledger of `scripthash`, which grew by `added` bytes. Must be
+    /// called with the ledger locked.
+    pub fn used(&self, db: &DB, scripthash: &[u8], added: usize) -> Result<()> {
+        let mut state = self.state.lock().unwrap();
+        state.load(db)?;
+        if added == 0 {
+            self.metrics.hit();
+        } else {
+            self.metrics.miss();
+        }
+        state.clock += 1;
+        let clock = state.clock;
+        let usage = state
+            .ledgers
+            .entry(full_hash(scripthash))
+            .or_insert(LedgerUsage {
+                size: 0,
+                last_used: 0,
+            });
+        usage.size += added;
+        usage.last_used = clock;
+        state.size += added;
+        self.metrics.set_usage(state.ledgers.len(), state.size);
+        Ok(())
+    }
+
+    /// Drops the least recently used ledgers (other than the one of `scripthash`) beyond the
+    /// `max_size`. Must be called with no ledger locked, the ones being updated are skipped.
+    pub fn evict(&self, db: &DB, scripthash: &[u8]) {
+        if self.max_size == 0 {
+            return;
+        }
+        let current = full_hash(scripthash);
+        let victims = {
+            let state = self.state.lock().unwrap();
+            if state.size <= self.max_size {
+                return;
+            }
+            let mut ledgers: Vec<(&FullHash, &LedgerUsage)> = state
+                .ledgers
+                .iter()
+                .filter(|(hash, _)| **hash != current)
+                .collect();
+            ledgers.sort_unstable_by_key(|(_, usage)| usage.last_used);
+            let mut excess = state.size - self.max_size;
+            ledgers
+                .into_iter()
+                .take_while(|(_, usage)| {
+                    let take = excess > 0;
+                    excess = excess.saturating_sub(usage.size);
+                    take
+                })
+                .map(|(hash, _)| *hash)
+                .collect::<Vec<FullHash>>()
+        };
+
+        for victim in victims {
+            let _lock = match self.locks[shard(&victim)].try_lock() {
+                Ok(lock) => lock,
+                Err(_) => continue, // being updated, so not the least recently used anymore
+            };
+            let keys: Result<Vec<Vec<u8>>> = db
+                .iter_scan(&[&b"L"[..], &victim[..]].concat())
+                .map_ok(|row| row.key)
+                .collect();
+            if let Err(e) = keys.and_then(|keys| db.delete(&keys)) {
+                if !e.is_read_only() {
+                    warn!("failed dropping a ledger from the cache: {}", e);
+                }
+                continue;
+            }
+            let mut state = self.state.lock().unwrap();
+            if let Some(usage) = state.ledgers.remove(&victim) {
+                state.size -= usage.size;
+            }
+            self.metrics.evicted(1);
+            self.metrics.set_usage(state.ledgers.len(), state.size);
+        }
+    }
+}
+
+impl CacheState {
+    fn load(&mut self, db: &DB) -> Result<()> {
+        if self.loaded {
+            return Ok(());
+        }
+        let mut ledgers: HashMap<FullHash, LedgerUsage> = HashMap::new();
+        let mut total = 0;
+        for row in db.iter_scan(b"L") {
+            // "L{scripthash}{seq}"
+            let row = row?;
+            let size = row.key.len() + row.value.len();
+            let usage = ledgers
+                .entry(full_hash(&row.key[1..33]))
+                .or_insert(LedgerUsage {
+                    size: 0,
+                    last_used: 0,
+                });
+            usage.size += size;
+            total += size;
+        }
+        self.ledgers = ledgers;
+        self.size = total;
+        self.loaded = true;
+        Ok(())
+    }
+}
+
+fn shard(scripthash: &[u8]) -> usize {
+    // the scripthashes are uniformly distributed
+    scripthash[0] as usize % LOCK_SHARDS
+}
@@ -1,5 +1,6 @@
 use bounded_vec_deque::BoundedVecDeque;
 use itertools::{Either, Itertools};
+use utoipa::ToSchema;
 
 #[cfg(not(feature = "liquid"))]
 use bitcoin::consensus::encode::serialize;
@@ -13,20 +14,40 @@ use std::ops::Bound::{Excluded, Unbounded};
 use std::sync::{Arc, RwLock};
 use std::time::{Duration, Instant};
 
+#[cfg(not(feature = "liquid"))]
+use crate::chain::Wtxid;
 use crate::chain::{deserialize, BlockHash, Network, OutPoint, Transaction, TxOut, Txid};
 use crate::config::Config;
-use crate::daemon::Daemon;
+use crate::daemon::{Daemon, MempoolSequence};
 use crate::errors::*;
-use crate::metrics::{GaugeVec, HistogramOpts, HistogramVec, MetricOpts, Metrics};
+use crate::metrics::{
+    CounterVec, Gauge, GaugeVec, HistogramOpts, HistogramVec, MetricOpts, Metrics,
+};
+use crate::new_index::evicted::{EvictedTx, EvictedTxs};
+use crate::new_index::mempool_events::{MempoolEventKind, MempoolEventLog, MempoolEvents};
+use crate::new_index::zmq::MempoolSequenceFeed;
 use crate::new_index::{
     compute_script_hash, schema::FullHash, ChainQuery, FundingInfo, GetAmountVal, ScriptStats,
     SpendingInfo, SpendingInput, TxHistoryInfo, Utxo,
 };
-use crate::util::fees::{make_fee_histogram, TxFeeInfo};
-use crate::util::{extract_tx_prevouts, full_hash, get_prev_outpoints, is_spendable, Bytes};
+use crate::util::fees::{make_fee_histogram, FeeFloor, TxFeeInfo};
+use crate::util::{
+    extract_tx_prevouts, full_hash, get_prev_outpoints, has_prevout, is_spendable, unix_now, Bytes,
+    Eviction, EvictionReason,
+};
+
+#[cfg(not(feature = "liquid"))]
+use crate::new_index::schema::net_deltas;
+use crate::new_index::schema::HistoryFilter;
 
 #[cfg(feature = "liquid")]
-use crate::elements::asset;
+use crate::elements::{asset, ebcompact::TxidCompat};
+
+// The number of transactions that left the mempool unconfirmed to remember
+const EVICTED_TXS_SIZE: usize = 10_000;
+// The interval at which the mempool is fetched even though the ZMQ notifications report no
+// change, in case some got lost
+const ZMQ_POLL_INTERVAL: Duration = Duration::from_secs(60);
 
 pub struct Mempool {
     chain: Arc<ChainQuery>,
@@ -36,12 +57,25 @@ pub struct Mempool {
     history: HashMap<FullHash, Vec<TxHistoryInfo>>, // ScriptHash -> {history_entries}
     edges: HashMap<OutPoint, (Txid, u32)>,          // OutPoint -> (spending_txid, spending_vin)
     recent: BoundedVecDeque<TxOverview>,            // The N most recent txs to enter the mempool
+    entry_time: HashMap<Txid, u64>,                 // Txid -> unix time it was first seen at
+    evicted: EvictedTxs, // The most recent txs to leave the mempool unconfirmed
+    events: MempoolEventLog, // ScriptHash -> the recent txs added/removed
+    #[cfg(not(feature = "liquid"))]
+    wtxids: HashMap<Wtxid, Txid>, // Wtxid -> Txid, for the segwit txs
     backlog_stats: (BacklogStats, Instant),
+    fee_floor: FeeFloor,
+    // lokid's mempool sequence as of the last update when reported, and the one notified via ZMQ
+    // when subscribed, which spares fetching the mempool txids while they match
+    sequence: Option<MempoolSequence>,
+    zmq_sequence: Option<Arc<MempoolSequenceFeed>>,
+    polled_at: Instant, // when the mempool txids were last fetched from lokid
 
     // monitoring
     latency: HistogramVec, // mempool requests latency
     delta: HistogramVec,   // # of added/removed txs
     count: GaugeVec,       // current state of the mempool
+    min_fee: Gauge,        // lokid's mempool min fee
+    evictions: CounterVec, // # of txs that left the mempool unconfirmed, by reason
 
     // elements only
     #[cfg(feature = "liquid")]
@@ -51,21 +85,62 @@ pub struct Mempool {
 }
 
 // A simplified transaction view used for the list of most recent transactions
-#[derive(Serialize)]
+#[derive(Serialize, ToSchema)]
 pub struct TxOverview {
+    #[schema(value_type = String)]
     txid: Txid,
+    #[serde(serialize_with = "crate::util::amount::serialize")]
     fee: u64,
     vsize: u64,
     #[cfg(not(feature = "liquid"))]
+    #[serde(serialize_with = "crate::util::amount::serialize")]
     value: u64,
     #[cfg(feature = "liquid")]
     discount_vsize: u64,
 }
 
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum TxState {
+    Mempool,
+    Confirmed,
+    Evicted,
+}
+
+// A transaction spending some of the same outputs as another one
+#[derive(Serialize, ToSchema)]
+pub struct ConflictingTx {
+    #[schema(value_type = String)]
+    pub txid: Txid,
+    pub state: TxState,
+    /// The inputs of the other transaction whose outputs this one spends too
+    pub vin: Vec<u32>,
+    /// The feerate of the transaction along with its unconfirmed ancestors (in sat/vB), for
+    /// the unconfirmed transactions whose fee is known
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub package_feerate: Option<f64>,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct TxConflicts {
+    #[schema(value_type = String)]
+    pub txid: Txid,
+    pub state: TxState,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub package_feerate: Option<f64>,
+    pub conflicts: Vec<ConflictingTx>,
+    /// The unconfirmed transaction with the highest package feerate among this one and its
+    /// conflicts, which lokid would keep
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[schema(value_type = Option<String>)]
+    pub highest_feerate_txid: Option<Txid>,
+}
+
 impl Mempool {
     pub fn new(chain: Arc<ChainQuery>, metrics: &Metrics, config: Arc<Config>) -> Self {
         let recent_capacity = config.mempool_recent_txs_size;
         let backlog_ttl = config.mempool_backlog_stats_ttl;
+        let event_retention = config.mempool_event_retention;
         Mempool {
             chain,
             config,
@@ -74,10 +149,19 @@ impl Mempool {
             history: HashMap::new(),
             edges: HashMap::new(),
             recent: BoundedVecDeque::new(recent_capacity.max(1)),
+            entry_time: HashMap::new(),
+            evicted: EvictedTxs::new(EVICTED_TXS_SIZE),
+            events: MempoolEventLog::new(event_retention, unix_now()),
+            #[cfg(not(feature = "liquid"))]
+            wtxids: HashMap::new(),
             backlog_stats: (
                 BacklogStats::default(),
                 Instant::now() - Duration::from_secs(backlog_ttl),
             ),
+            fee_floor: FeeFloor::default(),
+            sequence: None,
+            zmq_sequence: None,
+            polled_at: Instant::now(),
             latency: metrics.histogram_vec(
                 HistogramOpts::new("mempool_latency", "Mempool requests latency (in seconds)"),
                 &["part"],
@@ -90,6 +174,17 @@ impl Mempool {
                 MetricOpts::new("mempool_count", "# of elements currently at the mempool"),
                 &["type"],
             ),
+            min_fee: metrics.gauge(MetricOpts::new(
+                "mempool_min_fee",
+                "The feerate under which lokid evicts mempool transactions (in sat/kvB)",
+            )),
+            evictions: metrics.counter_vec(
+                MetricOpts::new(
+                    "mempool_evictions",
+                    "# of transactions that left the mempool without confirming",
+                ),
+                &["reason"],
+            ),
 
             #[cfg(feature = "liquid")]
             asset_history: HashMap::new(),
@@ -106,6 +201,11 @@ impl Mempool {
         self.txstore.get(txid).cloned()
     }
 
+    #[cfg(not(feature = "liquid"))]
+    pub fn lookup_wtxid(&self, wtxid: &Wtxid) -> Option<Txid> {
+        self.wtxids.get(wtxid).copied()
+    }
+
     pub fn lookup_raw_txn(&self, txid: &Txid) -> Option<Bytes> {
         self.txstore.get(txid).map(serialize)
     }
@@ -119,14 +219,38 @@ impl Mempool {
         })
     }
 
+    pub fn has_txn(&self, txid: &Txid) -> bool {
+        self.txstore.contains_key(txid)
+    }
+
     pub fn has_spend(&self, outpoint: &OutPoint) -> bool {
         self.edges.contains_key(outpoint)
     }
 
+    pub fn has_history(&self, scripthash: &[u8]) -> bool {
+        self.history.contains_key(scripthash)
+    }
+
     pub fn get_tx_fee(&self, txid: &Txid) -> Option<u64> {
         Some(self.feeinfo.get(txid)?.fee)
     }
 
+    /// The highest-feerate transactions that fit within `max_vsize`, as a rough projection of
+    /// the next block (ignoring the dependencies between transactions)
+    #[trace]
+    pub fn projected_block(&self, max_vsize: u64) -> Vec<(&Txid, &TxFeeInfo)> {
+        let mut entries: Vec<_> = self.feeinfo.iter().collect();
+        entries.sort_unstable_by(|(_, a), (_, b)| b.fee_per_vbyte.total_cmp(&a.fee_per_vbyte));
+        let mut vsize = 0;
+        entries
+            .into_iter()
+            .take_while(|(_, feeinfo)| {
+                vsize += feeinfo.vsize;
+                vsize <= max_vsize
+            })
+            .collect()
+    }
+
     #[trace]
     pub fn has_unconfirmed_parents(&self, txid: &Txid) -> bool {
         let tx = match self.txstore.get(txid) {
@@ -146,6 +270,39 @@ impl Mempool {
             .map_or_else(|| vec![], |entries| self._history(entries, limit))
     }
 
+    /// Like `history()`, keeping the transactions matching the `filter`
+    #[trace]
+    pub fn history_filtered(
+        &self,
+        scripthash: &[u8],
+        limit: usize,
+        filter: &HistoryFilter,
+    ) -> Vec<Transaction> {
+        // the unconfirmed transactions are out of any height range
+        if filter.by_height() {
+            return vec![];
+        }
+        #[cfg(not(feature = "liquid"))]
+        if filter.by_value() {
+            let _timer = self
+                .latency
+                .with_label_values(&["history_filtered"])
+                .start_timer();
+            let entries = match self.history.get(scripthash) {
+                Some(entries) => entries,
+                None => return vec![],
+            };
+            return net_deltas(entries.iter())
+                .into_iter()
+                .filter(|(_, delta)| filter.matches(*delta))
+                .take(limit)
+                .map(|(txid, _)| self.txstore.get(&txid).expect("missing mempool tx"))
+                .cloned()
+                .collect();
+        }
+        self.history(scripthash, limit)
+    }
+
     #[trace]
     pub fn history_group(
         &self,
@@ -366,11 +523,166 @@ impl Mempool {
         self.recent.iter().collect()
     }
 
+    #[trace]
+    // The transactions that left the mempool without confirming, most recent first
+    pub fn evicted_txs(&self) -> impl Iterator<Item = &EvictedTx> {
+        self.evicted.iter()
+    }
+
+    /// The transactions of a scripthash added to or removed from the mempool since a unix time,
+    /// within the `--mempool-event-retention` period
+    pub fn events(&self, scripthash: &[u8], since: u64) -> MempoolEvents {
+        self.events
+            .events(&full_hash(scripthash), since, unix_now())
+    }
+
+    /// Why the transaction left the mempool, if it did recently and isn't back in it
+    pub fn lookup_evicted(&self, txid: &Txid) -> Option<&EvictedTx> {
+        if self.txstore.contains_key(txid) {
+            return None;
+        }
+        self.evicted.get(txid)
+    }
+
+    /// The known transactions spending any of the outputs `tx` spends: the one in the mempool,
+    /// the confirmed one and the ones that recently left the mempool
+    #[trace]
+    pub fn conflicts(&self, tx: &Transaction) -> Result<TxConflicts> {
+        let txid = tx.compute_txid();
+        let mut conflicts: Vec<ConflictingTx> = vec![];
+        let mut add_conflict = |conflict_txid: Txid, state: TxState, vin: u32| {
+            if conflict_txid == txid {
+                return;
+            }
+            match conflicts.iter_mut().find(|c| c.txid == conflict_txid) {
+                Some(conflict) => {
+                    if !conflict.vin.contains(&vin) {
+                        conflict.vin.push(vin);
+                    }
+                }
+                None => conflicts.push(ConflictingTx {
+                    txid: conflict_txid,
+                    state,
+                    vin: vec![vin],
+                    package_feerate: None,
+                }),
+            }
+        };
+        for (vin, txin) in tx.input.iter().enumerate() {
+            if !has_prevout(txin) {
+                continue;
+            }
+            let (outpoint, vin) = (txin.previous_output, vin as u32);
+            if let Some(spend) = self.chain.lookup_spend(&outpoint)? {
+                add_conflict(spend.txid, TxState::Confirmed, vin);
+            }
+            if let Some((spending_txid, _)) = self.edges.get(&outpoint) {
+                add_conflict(*spending_txid, TxState::Mempool, vin);
+            }
+            for evicted in self.evicted.spending(&outpoint) {
+                add_conflict(evicted.txid, TxState::Evicted, vin);
+            }
+        }
+
+        for conflict in &mut conflicts {
+            conflict.package_feerate = match conflict.state {
+                TxState::Confirmed => None,
+                TxState::Mempool => self.txstore.get(&conflict.txid),
+                TxState::Evicted => self.lookup_evicted(&conflict.txid).map(|e| &e.tx),
+            }
+            .and_then(|tx| self.package_feerate(tx));
+        }
+
+        let state = if self.txstore.contains_key(&txid) {
+            TxState::Mempool
+        } else if self.chain.tx_confirming_block(&txid)?.is_some() {
+            TxState::Confirmed
+        } else {
+            TxState::Evicted
+        };
+        let package_feerate = match state {
+            TxState::Confirmed => None,
+            _ => self.package_feerate(tx),
+        };
+        let highest_feerate_txid = conflicts
+            .iter()
+            .filter_map(|c| Some((c.txid, c.package_feerate?)))
+            .chain(package_feerate.map(|feerate| (txid, feerate)))
+            .max_by(|(_, a), (_, b)| a.total_cmp(b))
+            .map(|(txid, _)| txid);
+
+        Ok(TxConflicts {
+            txid,
+            state,
+            package_feerate,
+            conflicts,
+            highest_feerate_txid,
+        })
+    }
+
+    // The feerate of the transaction along with its unconfirmed ancestors (in sat/vB), if the
+    // outputs it spends are known
+    fn package_feerate(&self, tx: &Transaction) -> Option<f64> {
+        let (mut fee, mut vsize) = match self.feeinfo.get(&tx.compute_txid()) {
+            Some(feeinfo) => (feeinfo.fee, feeinfo.vsize),
+            None => {
+                let txos = self
+                    .lookup_txos(get_prev_outpoints(std::iter::once(tx)))
+                    .ok()?;
+                let prevouts = extract_tx_prevouts(tx, &txos, false);
+                let feeinfo = TxFeeInfo::new(tx, &prevouts, self.config.network_type);
+                (feeinfo.fee, feeinfo.vsize)
+            }
+        };
+        let parents = |tx: &Transaction| -> Vec<Txid> {
+            tx.input
+                .iter()
+                .filter(|txin| has_prevout(txin))
+                .map(|txin| txin.previous_output.txid)
+                .collect()
+        };
+        let mut visited = HashSet::new();
+        let mut queue = parents(tx);
+        while let Some(parent_txid) = queue.pop() {
+            if !visited.insert(parent_txid) {
+                continue;
+            }
+            if let (Some(parent), Some(feeinfo)) = (
+                self.txstore.get(&parent_txid),
+                self.feeinfo.get(&parent_txid),
+            ) {
+                fee += feeinfo.fee;
+                vsize += feeinfo.vsize;
+                queue.extend(parents(parent));
+            }
+        }
+        Some(fee as f64 / vsize.max(1) as f64)
+    }
+
     #[trace]
     pub fn backlog_stats(&self) -> &BacklogStats {
         &self.backlog_stats.0
     }
 
+    /// The size of the mempool tracker's indexes, for debugging
+    pub fn tracker_stats(&self) -> TrackerStats {
+        TrackerStats {
+            txs: self.txstore.len(),
+            fee_infos: self.feeinfo.len(),
+            scripthashes: self.history.len(),
+            history_entries: self.history.values().map(Vec::len).sum(),
+            spent_outpoints: self.edges.len(),
+            recent: self.recent.len(),
+            event_scripthashes: self.events.scripthashes(),
+            backlog_age_secs: self.backlog_stats.1.elapsed().as_secs(),
+        }
+    }
+
+    /// The feerate (in sat/vb) under which transactions risk being evicted from lokid's mempool
+    pub fn fee_floor(&self) -> f64 {
+        self.fee_floor.get()
+    }
+
     #[trace]
     pub fn txids_set(&self) -> HashSet<Txid> {
         return HashSet::from_iter(self.txstore.keys().cloned());
@@ -430,9 +742,18 @@ impl Mempool {
         txos.extend(self.lookup_txos(remain_prevouts)?);
 
         // Add to txstore and indexes
+        let now = unix_now();
         for (txid, tx) in txs_map {
             self.txstore.insert(txid, tx);
+            self.entry_time.entry(txid).or_insert(now);
             let tx = self.txstore.get(&txid).expect("was just added");
+            #[cfg(not(feature = "liquid"))]
+            {
+                let wtxid = tx.compute_wtxid();
+                if wtxid[..] != txid[..] {
+                    self.wtxids.insert(wtxid, txid);
+                }
+            }
 
             let prevouts = extract_tx_prevouts(&tx, &txos, false);
             let txid_bytes = full_hash(&txid[..]);
@@ -492,6 +813,8 @@ impl Mempool {
 
             // Index funding/spending history entries and spend edges
             for (scripthash, entry) in funding.chain(spending) {
+                self.events
+                    .record(&scripthash, MempoolEventKind::Added, txid, now);
                 self.history
                     .entry(scripthash)
                     .or_insert_with(Vec::new)
@@ -515,7 +838,7 @@ impl Mempool {
         Ok(())
     }
 
-    fn lookup_txo(&self, outpoint: &OutPoint) -> Option<TxOut> {
+    pub fn lookup_txo(&self, outpoint: &OutPoint) -> Option<TxOut> {
         self.txstore
             .get(&outpoint.txid)
             .and_then(|tx| tx.output.get(outpoint.vout as usize).cloned())
@@ -543,17 +866,23 @@ impl Mempool {
         Ok(txos)
     }
 
+    // Returns the removed transactions, with the time they were first seen at
     #[trace]
-    fn remove(&mut self, to_remove: HashSet<&Txid>) {
+    fn remove(&mut self, to_remove: HashSet<&Txid>) -> Vec<(Transaction, Option<u64>)> {
         self.delta
             .with_label_values(&["remove"])
             .observe(to_remove.len() as f64);
         let _timer = self.latency.with_label_values(&["remove"]).start_timer();
 
+        let mut removed = Vec::with_capacity(to_remove.len());
         for txid in &to_remove {
-            self.txstore
+            let tx = self
+                .txstore
                 .remove(*txid)
                 .unwrap_or_else(|| panic!("missing mempool tx {}", txid));
+            #[cfg(not(feature = "liquid"))]
+            self.wtxids.remove(&tx.compute_wtxid());
+            removed.push((tx, self.entry_time.remove(*txid)));
 
             self.feeinfo.remove(*txid).or_else(|| {
                 warn!("missing mempool tx feeinfo {}", txid);
@@ -562,8 +891,17 @@ impl Mempool {
         }
 
         // TODO: make it more efficient (currently it takes O(|mempool|) time)
-        self.history.retain(|_scripthash, entries| {
-            entries.retain(|entry| !to_remove.contains(&entry.get_txid()));
+        let events = &mut self.events;
+        let now = unix_now();
+        self.history.retain(|scripthash, entries| {
+            entries.retain(|entry| {
+                let txid = entry.get_txid();
+                if !to_remove.contains(&txid) {
+                    return true;
+                }
+                events.record(scripthash, MempoolEventKind::Removed, txid, now);
+                false
+            });
             !entries.is_empty()
         });
 
@@ -576,6 +914,81 @@ impl Mempool {
 
         self.edges
             .retain(|_outpoint, (txid, _vin)| !to_remove.contains(txid));
+
+        removed
+    }
+
+    // Remember why the removed transactions that didn't get confirmed left the mempool. Must be
+    // called once the transactions that replaced them were added.
+    fn record_evictions(&mut self, removed: Vec<(Transaction, Option<u64>)>) {
+        let now = unix_now();
+        for (tx, entry_time) in removed {
+            let txid = tx.compute_txid();
+            let eviction = match self.eviction_of(&tx, entry_time, now) {
+                Ok(Some(eviction)) => eviction,
+                Ok(None) => continue, // confirmed
+                Err(e) => {
+                    warn!("failed looking up the eviction of {}: {}", txid, e);
+                    continue;
+                }
+            };
+            debug!("mempool tx {} left unconfirmed: {:?}", txid, eviction);
+            self.evictions
+                .with_label_values(&[eviction.reason.as_str()])
+                .inc();
+            self.evicted.push(EvictedTx { txid, eviction, tx });
+        }
+    }
+
+    fn eviction_of(
+        &self,
+        tx: &Transaction,
+        entry_time: Option<u64>,
+        now: u64,
+    ) -> Result<Option<Eviction>> {
+        let txid = tx.compute_txid();
+        if self.chain.tx_confirming_block(&txid)?.is_some() {
+            return Ok(None);
+        }
+        let spent = || {
+            tx.input
+                .iter()
+                .filter(|txin| has_prevout(txin))
+                .map(|txin| txin.previous_output)
+        };
+        let replaced_by = spent().find_map(|outpoint| {
+            self.edges
+                .get(&outpoint)
+                .map(|(spending_txid, _vin)| *spending_txid)
+                .filter(|spending_txid| *spending_txid != txid)
+        });
+        let conflicted_by = || -> Result<Option<Txid>> {
+            for outpoint in spent() {
+                if let Some(spend) = self.chain.lookup_spend(&outpoint)? {
+                    return Ok(Some(spend.txid));
+                }
+            }
+            Ok(None)
+        };
+        // lokid doesn't tell why, so this is only inferred from what we know of the other txs
+        let (reason, replaced_by) = match replaced_by {
+            Some(replaced_by) => (EvictionReason::Replaced, Some(replaced_by)),
+            None => match conflicted_by()? {
+                Some(conflicted_by) => (EvictionReason::Conflicted, Some(conflicted_by)),
+                None if entry_time.map_or(false, |entry_time| {
+                    now.saturating_sub(entry_time) >= self.config.mempool_expiry.as_secs()
+                }) =>
+                {
+                    (EvictionReason::Expired, None)
+                }
+                None => (EvictionReason::Evicted, None),
+            },
+        };
+        Ok(Some(Eviction {
+            reason,
+            replaced_by,
+            time: now,
+        }))
     }
 
     #[cfg(feature = "liquid")]
@@ -590,6 +1003,24 @@ impl Mempool {
             .map_or_else(|| vec![], |entries| self._history(entries, limit))
     }
 
+    /// Skip fetching the mempool txids while lokid's ZMQ `sequence` notifications report no change
+    pub fn follow_zmq_sequence(&mut self, feed: Arc<MempoolSequenceFeed>) {
+        self.zmq_sequence = Some(feed);
+    }
+
+    // The mempool sequence as of the last update, when the ZMQ notifications tell that it didn't
+    // change since. They're assumed to come from the lokid first connected to, so a failover to
+    // another address stops relying on them.
+    fn notified_unchanged(&self, daemon: &Daemon) -> Option<MempoolSequence> {
+        let sequence = self.sequence?;
+        let notified = self.zmq_sequence.as_ref()?.sequence()?;
+        (notified == sequence.sequence
+            && sequence.failovers == 0
+            && daemon.failovers() == 0
+            && self.polled_at.elapsed() < ZMQ_POLL_INTERVAL)
+            .then_some(sequence)
+    }
+
     /// Sync our local view of the mempool with the lokid Daemon RPC. If the chain tip moves before
     /// the mempool is fetched in full, syncing is aborted and an Ok(false) is returned.
     #[trace]
@@ -607,18 +1038,41 @@ impl Mempool {
 
         // Continuously attempt to fetch mempool transactions until we're able to get them in full
         let mut fetched_txs = BTreeMap::<Txid, Transaction>::new();
-        let mut indexed_txids = mempool.read().unwrap().txids_set();
+        let mut indexed_txids = None;
+        let mut removed = vec![];
+        let mut sequence;
+        let mut polled = false;
         loop {
+            if !polled {
+                if let Some(unchanged) = mempool.read().unwrap().notified_unchanged(daemon) {
+                    trace!("mempool sequence {:?} unchanged per ZMQ", unchanged);
+                    sequence = Some(unchanged);
+                    break;
+                }
+            }
+            polled = true;
+
             // Get lokid's current list of mempool txids
-            let all_txids = daemon
-                .getmempooltxids()
+            let (all_txids, mempool_sequence) = daemon
+                .getmempooltxids_sequence()
                 .chain_err(|| "failed to update mempool from daemon")?;
+            sequence = mempool_sequence;
+            if sequence.is_some() && sequence == mempool.read().unwrap().sequence {
+                // nothing entered or left lokid's mempool since the last update, which spares
+                // diffing it with ours
+                trace!("mempool sequence {:?} unchanged", sequence);
+                break;
+            }
+            let indexed_txids =
+                indexed_txids.get_or_insert_with(|| mempool.read().unwrap().txids_set());
 
             // Remove evicted mempool transactions
-            mempool
-                .write()
-                .unwrap()
-                .remove(indexed_txids.difference(&all_txids).collect());
+            removed.extend(
+                mempool
+                    .write()
+                    .unwrap()
+                    .remove(indexed_txids.difference(&all_txids).collect()),
+            );
 
             indexed_txids.retain(|txid| all_txids.contains(txid));
             fetched_txs.retain(|txid, _| all_txids.contains(txid));
@@ -657,7 +1111,8 @@ impl Mempool {
 
             let new_txs = daemon.gettransactions_available(&new_txids)?;
 
-            // Abort if the chain tip moved while fetching transactions
+            // Abort if the chain tip moved while fetching transactions. The transactions removed
+            // so far aren't recorded as evicted, they were most likely confirmed by the new tip.
             if daemon.getbestblockhash()? != *tip {
                 warn!("chain tip moved while updating mempool");
                 return Ok(false);
@@ -683,21 +1138,49 @@ impl Mempool {
             }
         }
 
+        // Transactions confirmed by a block that isn't indexed yet would be taken for evicted ones
+        if !removed.is_empty() && daemon.getbestblockhash()? != *tip {
+            warn!("chain tip moved while updating mempool");
+            return Ok(false);
+        }
+
+        // Track the eviction feerate, to keep the fee estimates above it during congestion
+        let min_fee = match daemon.get_mempool_min_fee() {
+            Ok(min_fee) => Some(min_fee),
+            Err(err) => {
+                warn!("failed getting the mempool min fee: {}", err);
+                None
+            }
+        };
+
         // Add fetched transactions to our view of the mempool
         {
             let mut mempool = mempool.write().unwrap();
 
             mempool.add(fetched_txs)?;
+            mempool.record_evictions(removed);
+            mempool.sequence = sequence;
+            if polled {
+                mempool.polled_at = Instant::now();
+            }
+            mempool.events.prune(unix_now());
+
+            if let Some(min_fee) = min_fee {
+                mempool.fee_floor.record(min_fee);
+                mempool.min_fee.set((min_fee * 1000f64) as i64);
+            }
 
             mempool
                 .count
                 .with_label_values(&["txs"])
                 .set(mempool.txstore.len() as f64);
 
-            // Update cached backlog stats (if expired)
-            if mempool.backlog_stats.1.elapsed()
-                > Duration::from_secs(mempool.config.mempool_backlog_stats_ttl)
-            {
+            // Update cached backlog stats (if expired, and later while the queries are overloaded)
+            if mempool.chain.load().is_due(
+                "backlog_stats",
+                mempool.backlog_stats.1.elapsed(),
+                Duration::from_secs(mempool.config.mempool_backlog_stats_ttl),
+            ) {
                 mempool.update_backlog_stats();
             }
         }
@@ -709,10 +1192,25 @@ impl Mempool {
 }
 
 #[derive(Serialize)]
+pub struct TrackerStats {
+    pub txs: usize,
+    pub fee_infos: usize,
+    pub scripthashes: usize,
+    pub history_entries: usize,
+    pub spent_outpoints: usize,
+    pub recent: usize,
+    pub event_scripthashes: usize,
+    pub backlog_age_secs: u64,
+}
+
+#[derive(Serialize, ToSchema)]
 pub struct BacklogStats {
     pub count: u32,
-    pub vsize: u64,     // in virtual bytes (= weight/4)
+    pub vsize: u64, // in virtual bytes (= weight/4)
+    #[serde(serialize_with = "crate::util::amount::serialize")]
     pub total_fee: u64, // in satoshis
+    /// `(feerate, vsize)` pairs, with the feerate in sat/vB
+    #[schema(value_type = Vec<Vec<f64>>)]
     pub fee_histogram: Vec<(f64, u64)>,
 }
 
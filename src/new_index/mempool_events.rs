@@ -0,0 +1,249 @@
+use std::collections::{HashMap, VecDeque};
+use std::time::Duration;
+
+use utoipa::ToSchema;
+
+use crate::chain::Txid;
+use crate::util::FullHash;
+
+// The number of events remembered per scripthash within the retention period
+const SCRIPTHASH_EVENTS_SIZE: usize = 1_000;
+// The number of events remembered overall, across all the scripthashes
+const EVENTS_SIZE: usize = 500_000;
+
+/// A transaction of a scripthash entering or leaving the mempool
+#[derive(Serialize, ToSchema, Debug, Clone, PartialEq, Eq)]
+pub struct MempoolEvent {
+    /// The unix time the event was seen at
+    pub time: u64,
+    #[serde(rename = "type")]
+    pub kind: MempoolEventKind,
+    #[schema(value_type = String)]
+    pub txid: Txid,
+}
+
+#[derive(Serialize, ToSchema, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum MempoolEventKind {
+    Added,
+    /// Confirmed or evicted, which the client has to look up
+    Removed,
+}
+
+/// The mempool events of a scripthash since a given time
+#[derive(Serialize, ToSchema)]
+pub struct MempoolEvents {
+    /// Whether all the events since the given time are listed. When they aren't (because the
+    /// time is older than the retention period), the mempool history has to be fetched again.
+    pub complete: bool,
+    pub events: Vec<MempoolEvent>,
+}
+
+#[derive(Default)]
+struct ScriptEvents {
+    events: VecDeque<MempoolEvent>,
+    // the time of the newest event dropped for lack of room, if any
+    truncated_at: Option<u64>,
+    // the number of this scripthash's oldest entries in the log order whose event was dropped
+    stale: usize,
+}
+
+/// A rolling log of the recent mempool events of every scripthash, kept for
+/// `--mempool-event-retention` seconds, so that a wallet reconnecting shortly after losing its
+/// connection catches up with the events it missed instead of fetching its full mempool history.
+pub struct MempoolEventLog {
+    retention: u64,
+    // the time the log started at, nothing is known of the events before it
+    started_at: u64,
+    scripthashes: HashMap<FullHash, ScriptEvents>,
+    // (time, scripthash) of every recorded event, oldest first, to enforce the overall limit
+    order: VecDeque<(u64, FullHash)>,
+    // the time of the newest event dropped for lack of overall room, if any
+    truncated_at: Option<u64>,
+}
+
+impl MempoolEventLog {
+    pub fn new(retention: Duration, now: u64) -> Self {
+        MempoolEventLog {
+            retention: retention.as_secs(),
+            started_at: now,
+            scripthashes: HashMap::new(),
+            order: VecDeque::new(),
+            truncated_at: None,
+        }
+    }
+
+    pub fn record(&mut self, scripthash: &FullHash, kind: MempoolEventKind, txid: Txid, now: u64) {
+        if self.retention == 0 {
+            return;
+        }
+        let entry = self.scripthashes.entry(*scripthash).or_default();
+        let event = MempoolEvent {
+            time: now,
+            kind,
+            txid,
+        };
+        // a transaction funding or spending several outputs of the scripthash is a single event
+        if entry.events.back() == Some(&event) {
+            return;
+        }
+        if entry.events.len() == SCRIPTHASH_EVENTS_SIZE {
+            let dropped = entry.events.pop_front().expect("full");
+            entry.truncated_at = Some(dropped.time);
+            entry.stale += 1;
+        }
+        entry.events.push_back(event);
+        self.order.push_back((now, *scripthash));
+
+        while self.order.len() > EVENTS_SIZE {
+            let (time, scripthash) = self.order.pop_front().expect("not empty");
+            let entry = match self.scripthashes.get_mut(&scripthash) {
+                Some(entry) => entry,
+                None => continue,
+            };
+            if entry.stale > 0 {
+                entry.stale -= 1;
+                continue;
+            }
+            entry.events.pop_front();
+            self.truncated_at = Some(time);
+            if entry.events.is_empty() {
+                self.scripthashes.remove(&scripthash);
+            }
+        }
+    }
+
+    /// Forgets the events older than the retention period
+    pub fn prune(&mut self, now: u64) {
+        let oldest = now.saturating_sub(self.retention);
+        while self.order.front().map_or(false, |(time, _)| *time < oldest) {
+            let (_, scripthash) = self.order.pop_front().expect("not empty");
+            if let Some(entry) = self.scripthashes.get_mut(&scripthash) {
+                entry.stale = entry.stale.saturating_sub(1);
+            }
+        }
+        self.scripthashes.retain(|_, entry| {
+            while entry
+                .events
+                .front()
+                .map_or(false, |event| event.time < oldest)
+            {
+                entry.events.pop_front();
+            }
+            !entry.events.is_empty()
+        });
+    }
+
+    /// The events of `scripthash` seen at `since` or later
+    pub fn events(&self, scripthash: &FullHash, since: u64, now: u64) -> MempoolEvents {
+        let entry = self.scripthashes.get(scripthash);
+        let truncated = entry
+            .and_then(|entry| entry.truncated_at)
+            .into_iter()
+            .chain(self.truncated_at)
+            .any(|truncated_at| since <= truncated_at);
+        MempoolEvents {
+            complete: self.retention > 0
+                && since >= self.started_at
+                && since >= now.saturating_sub(self.retention)
+                && !truncated,
+            events: entry.map_or_else(Vec::new, |entry| {
+                entry
+                    .events
+                    .iter()
+                    .filter(|event| event.time >= since)
+                    .cloned()
+                    .collect()
+            }),
+        }
+    }
+
+    pub fn scripthashes(&self) -> usize {
+        self.scripthashes.len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bitcoin::hashes::Hash;
+
+    fn txid(i: u8) -> Txid {
+        Txid::from_byte_array([i; 32])
+    }
+
+    #[test]
+    fn test_event_log() {
+        let scripthash = [1u8; 32];
+        let mut log = MempoolEventLog::new(Duration::from_secs(60), 1000);
+        log.record(&scripthash, MempoolEventKind::Added, txid(1), 1010);
+        log.record(&scripthash, MempoolEventKind::Added, txid(1), 1010);
+        log.record(&scripthash, MempoolEventKind::Removed, txid(1), 1020);
+
+        let res = log.events(&scripthash, 1010, 1030);
+        assert!(res.complete);
+        assert_eq!(res.events.len(), 2);
+        assert_eq!(res.events[1].kind, MempoolEventKind::Removed);
+        let res = log.events(&scripthash, 1015, 1030);
+        assert_eq!(res.events.len(), 1);
+
+        // before the log started, or older than the retention
+        assert!(!log.events(&scripthash, 999, 1030).complete);
+        assert!(!log.events(&scripthash, 1010, 1080).complete);
+        assert!(log.events(&[2u8; 32], 1030, 1030).complete);
+
+        log.prune(1075);
+        assert_eq!(log.events(&scripthash, 1015, 1075).events.len(), 1);
+        log.prune(1081);
+        assert_eq!(log.scripthashes(), 0);
+    }
+
+    #[test]
+    fn test_event_log_truncated() {
+        let scripthash = [1u8; 32];
+        let mut log = MempoolEventLog::new(Duration::from_secs(60), 1000);
+        for i in 0..=SCRIPTHASH_EVENTS_SIZE {
+            let kind = if i % 2 == 0 {
+                MempoolEventKind::Added
+            } else {
+                MempoolEventKind::Removed
+            };
+            log.record(&scripthash, kind, txid(1), 1000 + (i / 100) as u64);
+        }
+        // the first event was dropped
+        let res = log.events(&scripthash, 1000, 1010);
+        assert!(!res.complete);
+        assert_eq!(res.events.len(), SCRIPTHASH_EVENTS_SIZE);
+        assert!(log.events(&scripthash, 1001, 1010).complete);
+    }
+
+    #[test]
+    fn test_event_log_overall_limit() {
+        let mut log = MempoolEventLog::new(Duration::from_secs(60), 1000);
+        let scripthash = |i: usize| {
+            let mut scripthash = [1u8; 32];
+            scripthash[..8].copy_from_slice(&(i as u64).to_le_bytes());
+            scripthash
+        };
+        for i in 0..=EVENTS_SIZE {
+            log.record(&scripthash(i), MempoolEventKind::Added, txid(1), 1000);
+        }
+        // the very first event was dropped
+        assert_eq!(log.order.len(), EVENTS_SIZE);
+        assert_eq!(log.scripthashes(), EVENTS_SIZE);
+        let first = scripthash(0);
+        let res = log.events(&first, 1000, 1010);
+        assert!(!res.complete);
+        assert!(res.events.is_empty());
+        assert!(log.events(&first, 1001, 1010).complete);
+    }
+
+    #[test]
+    fn test_event_log_disabled() {
+        let mut log = MempoolEventLog::new(Duration::from_secs(0), 1000);
+        log.record(&[1u8; 32], MempoolEventKind::Added, txid(1), 1000);
+        let res = log.events(&[1u8; 32], 1000, 1000);
+        assert!(!res.complete);
+        assert!(res.events.is_empty());
+    }
+}
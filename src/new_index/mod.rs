@@ -1,17 +1,44 @@
+mod aggcache;
+mod audit;
+mod blocklist;
+pub mod blockstats;
+pub mod checksum;
 pub mod db;
 pub mod db_metrics;
+mod dedup;
+mod evicted;
+mod existence;
 mod fetch;
+mod latency;
+#[cfg(not(feature = "liquid"))]
+mod ledgercache;
 mod mempool;
+mod mempool_events;
 pub mod precache;
 mod query;
 pub mod schema;
+pub mod snapshot;
+mod totals;
+pub mod watchdog;
 pub mod zmq;
 
+pub use self::audit::{Audit, HistoryAuditor};
 pub use self::db::{DBRow, DB};
+pub use self::evicted::EvictedTx;
 pub use self::fetch::{BlockEntry, FetchFrom};
-pub use self::mempool::Mempool;
-pub use self::query::Query;
+pub use self::latency::BlockLatency;
+pub use self::mempool::{
+    BacklogStats, ConflictingTx, Mempool, TrackerStats, TxConflicts, TxOverview, TxState,
+};
+pub use self::mempool_events::{MempoolEvent, MempoolEventKind, MempoolEvents};
+pub use self::query::{HistoryCursor, Query};
+#[cfg(not(feature = "liquid"))]
+pub use self::query::{NextBlock, ProjectionStats, TemplateStats};
 pub use self::schema::{
-    compute_script_hash, parse_hash, ChainQuery, FundingInfo, GetAmountVal, Indexer, ScriptStats,
-    SpendingInfo, SpendingInput, Store, TxHistoryInfo, TxHistoryKey, TxHistoryRow, Utxo,
+    compute_script_hash, describe_prefix, parse_hash, Burn, ChainQuery, FundingInfo, GetAmountVal,
+    HistoryFilter, Indexer, ScriptActivity, ScriptStats, SpendingInfo, SpendingInput, Store,
+    TxHistoryInfo, TxHistoryKey, TxHistoryRow, Utxo,
 };
+#[cfg(not(feature = "liquid"))]
+pub use self::schema::{HistoryDelta, HistoryDirection, LedgerEntry, LedgerEntryKind};
+pub use self::totals::{LifetimeTotals, Total};
@@ -37,7 +37,9 @@ pub fn precache(chain: &ChainQuery, scripthashes: Vec<FullHash>, threads: usize)
                 if i % 5 == 0 {
                     info!("running pre-cache for scripthash {}/{}", i + 1, total);
                 }
-                chain.stats(&scripthash[..]);
+                if let Err(e) = chain.stats(&scripthash[..]) {
+                    warn!("failed pre-caching the stats: {}", e);
+                }
                 //chain.utxo(&scripthash[..]);
             })
     });
@@ -1,15 +1,29 @@
 use rayon::prelude::*;
+use serde_json::Value;
+use utoipa::ToSchema;
 
-use std::collections::{BTreeSet, HashMap};
-use std::sync::{Arc, RwLock, RwLockReadGuard};
+use std::collections::{BTreeSet, HashMap, HashSet};
+use std::mem;
+use std::sync::{Arc, Mutex, RwLock, RwLockReadGuard};
 use std::time::{Duration, Instant};
 
+#[cfg(not(feature = "liquid"))]
+use crate::chain::{BlockHash, Wtxid};
 use crate::chain::{Network, OutPoint, Transaction, TxOut, Txid};
 use crate::config::Config;
+#[cfg(not(feature = "liquid"))]
+use crate::daemon::BlockTemplate;
 use crate::daemon::{Daemon, MempoolAcceptResult, SubmitPackageResult};
 use crate::errors::*;
-use crate::new_index::{ChainQuery, Mempool, ScriptStats, SpendingInput, Utxo};
-use crate::util::{is_spendable, BlockId, Bytes, TransactionStatus};
+use crate::metrics::{CacheMetrics, Metrics};
+use crate::new_index::blocklist::Blocklist;
+use crate::new_index::{
+    compute_script_hash, ChainQuery, Mempool, ScriptStats, SpendingInput, Total, TxConflicts, Utxo,
+};
+use crate::util::clients::ClientTracker;
+#[cfg(not(feature = "liquid"))]
+use crate::util::price::{FiatValue, PriceFeed};
+use crate::util::{is_spendable, BlockId, Bytes, FullHash, TransactionStatus};
 
 use electrs_macros::trace;
 
@@ -19,22 +33,64 @@ use crate::{
     elements::{ebcompact::TxidCompat, lookup_asset, AssetRegistry, AssetSorting, LiquidAsset},
 };
 
-const FEE_ESTIMATES_TTL: u64 = 60; // seconds
-
 const CONF_TARGETS: [u16; 28] = [
     1u16, 2u16, 3u16, 4u16, 5u16, 6u16, 7u16, 8u16, 9u16, 10u16, 11u16, 12u16, 13u16, 14u16, 15u16,
     16u16, 17u16, 18u16, 19u16, 20u16, 21u16, 22u16, 23u16, 24u16, 25u16, 144u16, 504u16, 1008u16,
 ];
 
+// how long the results of the lokid passthrough RPCs are cached for
+const DAEMON_PASSTHROUGH_TTL: Duration = Duration::from_secs(10);
+
+// how long the refresh of the cached utxo sets is put off for at most, while the queries are
+// overloaded
+const UTXO_CACHES_MAX_DELAY: Duration = Duration::from_secs(60);
+
+// how often lokid's block template is refreshed, unless a new block arrives
+#[cfg(not(feature = "liquid"))]
+const BLOCK_TEMPLATE_INTERVAL: Duration = Duration::from_secs(30);
+// the block weight limit, for templates that don't specify one
+#[cfg(not(feature = "liquid"))]
+const MAX_BLOCK_WEIGHT: u64 = 4_000_000;
+
+// the number of confirmed transactions to keep the sigop cost of
+#[cfg(not(feature = "liquid"))]
+const SIGOPS_CACHE_SIZE: usize = 100_000;
+
+/// Where a page of the history of a scripthash starts
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum HistoryCursor {
+    /// At the first transaction confirmed from the height on
+    FromHeight(usize),
+    /// Following the transaction confirmed at the height, which ended the previous page
+    AfterConfirmed(usize, Txid),
+    /// Following the unconfirmed transaction, which ended the previous page
+    AfterMempool(Txid),
+}
+
 pub struct Query {
     chain: Arc<ChainQuery>, // TODO: should be used as read-only
     mempool: Arc<RwLock<Mempool>>,
     daemon: Arc<Daemon>,
     config: Arc<Config>,
     cached_estimates: RwLock<(HashMap<u16, f64>, Option<Instant>)>,
+    estimates_cache: CacheMetrics,
     cached_relayfee: RwLock<Option<f64>>,
+    cached_passthrough: RwLock<HashMap<String, (Value, Instant)>>,
+    blocklist: Blocklist,
+    clients: ClientTracker,
+    // the scripthashes whose cached utxo set is to be refreshed, and since when
+    stale_utxo_caches: Mutex<(HashSet<FullHash>, Option<Instant>)>,
+    #[cfg(not(feature = "liquid"))]
+    sigops_cache: RwLock<HashMap<Txid, usize>>,
+    #[cfg(not(feature = "liquid"))]
+    sigops_cache_metrics: CacheMetrics,
+    #[cfg(not(feature = "liquid"))]
+    block_template: RwLock<(Option<BlockTemplate>, Option<Instant>)>,
+    #[cfg(not(feature = "liquid"))]
+    prices: Option<Arc<PriceFeed>>,
     #[cfg(feature = "liquid")]
     asset_db: Option<Arc<RwLock<AssetRegistry>>>,
+    started: Instant,
 }
 
 impl Query {
@@ -44,15 +100,29 @@ impl Query {
         mempool: Arc<RwLock<Mempool>>,
         daemon: Arc<Daemon>,
         config: Arc<Config>,
-    ) -> Self {
-        Query {
+        metrics: &Metrics,
+    ) -> Result<Self> {
+        let blocklist =
+            Blocklist::open(config.blocklist.as_deref()).chain_err(|| "invalid --blocklist")?;
+        let clients = open_client_tracker(&config)?;
+        Ok(Query {
             chain,
             mempool,
             daemon,
             config,
             cached_estimates: RwLock::new((HashMap::new(), None)),
+            estimates_cache: CacheMetrics::new(metrics, "fee_estimates"),
             cached_relayfee: RwLock::new(None),
-        }
+            cached_passthrough: RwLock::new(HashMap::new()),
+            blocklist,
+            clients,
+            stale_utxo_caches: Mutex::new((HashSet::new(), None)),
+            sigops_cache: RwLock::new(HashMap::new()),
+            sigops_cache_metrics: CacheMetrics::new(metrics, "sigops"),
+            block_template: RwLock::new((None, None)),
+            prices: PriceFeed::start(&config, metrics),
+            started: Instant::now(),
+        })
     }
 
     pub fn chain(&self) -> &ChainQuery {
@@ -71,9 +141,26 @@ impl Query {
         self.mempool.read().unwrap()
     }
 
+    /// How long the server has been up
+    pub fn uptime(&self) -> Duration {
+        self.started.elapsed()
+    }
+
+    /// The per-client counters and bans of the Electrum RPC server
+    pub fn clients(&self) -> &ClientTracker {
+        &self.clients
+    }
+
+    /// The value of an amount (and of a fee) in the `--price-currency`, when a price is known
+    #[cfg(not(feature = "liquid"))]
+    pub fn fiat_value(&self, sats: u64, fee: Option<u64>) -> Option<FiatValue> {
+        self.prices.as_ref()?.value(sats, fee)
+    }
+
     #[trace]
     pub fn broadcast_raw(&self, txhex: &str) -> Result<Txid> {
         let txid = self.daemon.broadcast_raw(txhex)?;
+        self.chain.store().totals().inc(Total::TxsBroadcast, 1);
         let _ = self
             .mempool
             .write()
@@ -101,18 +188,131 @@ impl Query {
         self.daemon.test_mempool_accept(txhex, maxfeerate)
     }
 
+    /// Fails with `ErrorKind::Blocked` for the scripthashes of the `--blocklist`
+    pub fn check_scripthash(&self, scripthash: &[u8]) -> Result<()> {
+        if self.is_blocked(scripthash) {
+            bail!(ErrorKind::Blocked)
+        }
+        Ok(())
+    }
+
+    /// Whether the scripthash is one of the `--blocklist`
+    pub fn is_blocked(&self, scripthash: &[u8]) -> bool {
+        self.blocklist.contains(scripthash)
+    }
+
+    /// Whether the transaction pays to or spends from a script of the `--blocklist`
+    pub fn is_tx_blocked(&self, tx: &Transaction, prevouts: &HashMap<OutPoint, TxOut>) -> bool {
+        if !self.blocklist.is_enabled() {
+            return false;
+        }
+        tx.output
+            .iter()
+            .chain(
+                tx.input
+                    .iter()
+                    .filter_map(|txin| prevouts.get(&txin.previous_output)),
+            )
+            .any(|txout| {
+                self.blocklist
+                    .contains(&compute_script_hash(&txout.script_pubkey))
+            })
+    }
+
+    /// The sigop cost of the transaction, cached for the confirmed ones
+    #[cfg(not(feature = "liquid"))]
+    pub fn sigop_cost(
+        &self,
+        tx: &Transaction,
+        prevouts: &HashMap<OutPoint, TxOut>,
+        confirmed: bool,
+    ) -> usize {
+        let compute = || tx.total_sigop_cost(|outpoint| prevouts.get(outpoint).cloned());
+        if !confirmed {
+            return compute();
+        }
+        let txid = tx.compute_txid();
+        if let Some(sigops) = self.sigops_cache.read().unwrap().get(&txid) {
+            self.sigops_cache_metrics.hit();
+            return *sigops;
+        }
+        self.sigops_cache_metrics.miss();
+
+        let sigops = compute();
+        let mut cache = self.sigops_cache.write().unwrap();
+        if cache.len() >= SIGOPS_CACHE_SIZE {
+            self.sigops_cache_metrics.evicted(cache.len());
+            cache.clear();
+        }
+        cache.insert(txid, sigops);
+        self.sigops_cache_metrics
+            .set_usage(cache.len(), cache.len() * mem::size_of::<(Txid, usize)>());
+        sigops
+    }
+
+    pub fn reload_blocklist(&self) -> Result<()> {
+        self.blocklist.reload()
+    }
+
     #[trace]
     pub fn utxo(&self, scripthash: &[u8]) -> Result<Vec<Utxo>> {
-        let mut utxos = self.chain.utxo(scripthash, self.config.utxos_limit)?;
+        self.utxo_limited(scripthash, self.config.utxos_limit)
+    }
+
+    /// The utxos of a scripthash, failing with `TooPopular` if it has more than `limit` confirmed
+    #[trace]
+    pub fn utxo_limited(&self, scripthash: &[u8], limit: usize) -> Result<Vec<Utxo>> {
+        let mut utxos = self.chain.utxo(scripthash, limit)?;
         let mempool = self.mempool();
         utxos.retain(|utxo| !mempool.has_spend(&OutPoint::from(utxo)));
         utxos.extend(mempool.utxo(scripthash));
         Ok(utxos)
     }
 
+    /// Brings the cached utxo sets of the `scripthashes` up to date with the new blocks, so that
+    /// the next lookups of the heavy scripthashes only replay the blocks since. The refresh is put
+    /// off while the queries are overloaded, and done along with the next call.
+    #[trace]
+    pub fn update_utxo_caches(&self, scripthashes: Vec<FullHash>) {
+        let scripthashes: Vec<FullHash> = {
+            let mut stale = self.stale_utxo_caches.lock().unwrap();
+            stale.0.extend(scripthashes);
+            if stale.0.is_empty() {
+                return;
+            }
+            let since = *stale.1.get_or_insert_with(Instant::now);
+            let load = self.chain.load();
+            if !load.may_run("utxo_caches", since.elapsed(), UTXO_CACHES_MAX_DELAY) {
+                return;
+            }
+            stale.1 = None;
+            stale.0.drain().collect()
+        };
+        self.chain.pool().install(|| {
+            scripthashes.par_iter().for_each(|scripthash| {
+                let updated = self
+                    .chain
+                    .has_cached_utxos(&scripthash[..])
+                    .and_then(|cached| {
+                        if cached {
+                            self.chain.utxo(&scripthash[..], self.config.utxos_limit)?;
+                        }
+                        Ok(())
+                    });
+                if let Err(e) = updated {
+                    debug!("failed updating the cached utxo set: {}", e);
+                }
+            })
+        })
+    }
+
     #[trace]
-    pub fn history_txids(&self, scripthash: &[u8], limit: usize) -> Vec<(Txid, Option<BlockId>)> {
-        let confirmed_txids = self.chain.history_txids(scripthash, limit);
+    pub fn history_txids(
+        &self,
+        scripthash: &[u8],
+        limit: usize,
+    ) -> Result<Vec<(Txid, Option<BlockId>)>> {
+        let confirmed_txids = self.chain.history_txids(scripthash, limit)?;
         let confirmed_len = confirmed_txids.len();
         let confirmed_txids = confirmed_txids.into_iter().map(|(tx, b)| (tx, Some(b)));
 
@@ -122,68 +322,193 @@ impl Query {
             .into_iter()
             .map(|tx| (tx, None));
 
-        confirmed_txids.chain(mempool_txids).collect()
+        Ok(confirmed_txids.chain(mempool_txids).collect())
+    }
+
+    /// The position of the transaction in the history of a scripthash, for the page following it
+    pub fn history_cursor(&self, txid: &Txid) -> Result<HistoryCursor> {
+        Ok(match self.chain.tx_confirming_block(txid)? {
+            Some(blockid) => HistoryCursor::AfterConfirmed(blockid.height, *txid),
+            None => HistoryCursor::AfterMempool(*txid),
+        })
+    }
+
+    /// A page of up to `limit` transactions of the history of a scripthash, in the order of
+    /// `history_txids()` and starting at the `cursor`, along with the cursor of the next page
+    /// (`None` for the last one). The pages can end within a height, however many transactions
+    /// it has.
+    #[trace]
+    pub fn history_txids_page(
+        &self,
+        scripthash: &[u8],
+        cursor: &HistoryCursor,
+        limit: usize,
+    ) -> Result<(Vec<(Txid, Option<BlockId>)>, Option<HistoryCursor>)> {
+        // one more to tell whether there's a next page
+        let confirmed_txids = match *cursor {
+            HistoryCursor::FromHeight(height) => self
+                .chain
+                .history_txids_from(scripthash, height, None, limit + 1)?
+                .unwrap_or_default(),
+            HistoryCursor::AfterConfirmed(height, ref after) => self
+                .chain
+                .history_txids_from(scripthash, height, Some(after), limit + 1)?
+                .chain_err(|| "unknown history page cursor")?,
+            HistoryCursor::AfterMempool(_) => vec![],
+        };
+        let mut txids: Vec<(Txid, Option<BlockId>)> = confirmed_txids
+            .into_iter()
+            .map(|(txid, blockid)| (txid, Some(blockid)))
+            .collect();
+
+        if txids.len() <= limit {
+            let mempool = self.mempool();
+            let mut mempool_txids = mempool.history_txids(scripthash, usize::MAX).into_iter();
+            if let HistoryCursor::AfterMempool(ref after) = *cursor {
+                ensure!(
+                    mempool_txids.any(|txid| txid == *after),
+                    "unknown history page cursor"
+                );
+            }
+            let remaining = limit + 1 - txids.len();
+            txids.extend(mempool_txids.take(remaining).map(|txid| (txid, None)));
+        }
+
+        let next = if txids.len() > limit {
+            txids.truncate(limit);
+            txids.last().map(|(txid, blockid)| match blockid {
+                Some(blockid) => HistoryCursor::AfterConfirmed(blockid.height, *txid),
+                None => HistoryCursor::AfterMempool(*txid),
+            })
+        } else {
+            None
+        };
+        Ok((txids, next))
     }
 
-    pub fn stats(&self, scripthash: &[u8]) -> (ScriptStats, ScriptStats) {
-        (
-            self.chain.stats(scripthash),
+    pub fn stats(&self, scripthash: &[u8]) -> Result<(ScriptStats, ScriptStats)> {
+        Ok((
+            self.chain.stats(scripthash)?,
             self.mempool().stats(scripthash),
-        )
+        ))
+    }
+
+    pub fn scripthashes_used(&self, scripthashes: &[FullHash]) -> Result<Vec<bool>> {
+        let mempool = self.mempool();
+        Ok(self
+            .chain
+            .scripthashes_used(scripthashes)?
+            .into_iter()
+            .zip(scripthashes)
+            .map(|(used, scripthash)| used || mempool.has_history(scripthash))
+            .collect())
     }
 
     #[trace]
-    pub fn lookup_txn(&self, txid: &Txid) -> Option<Transaction> {
-        self.chain
-            .lookup_txn(txid, None)
-            .or_else(|| self.mempool().lookup_txn(txid))
+    pub fn lookup_txn(&self, txid: &Txid) -> Result<Option<Transaction>> {
+        Ok(self
+            .chain
+            .lookup_txn(txid, None)?
+            .or_else(|| self.mempool().lookup_txn(txid)))
+    }
+
+    /// The txid of the transaction identified by `id`, which may be the wtxid of a segwit
+    /// transaction
+    #[trace]
+    pub fn resolve_txid(&self, id: Txid) -> Result<Txid> {
+        #[cfg(not(feature = "liquid"))]
+        {
+            // most requests are for txids, which spares the wtxid lookups
+            if self.mempool().has_txn(&id) || self.chain.tx_confirming_block(&id)?.is_some() {
+                return Ok(id);
+            }
+            let wtxid = Wtxid::from_raw_hash(id.to_raw_hash());
+            let txid = match self.mempool().lookup_wtxid(&wtxid) {
+                Some(txid) => Some(txid),
+                None => self.chain.lookup_wtxid(&wtxid)?,
+            };
+            if let Some(txid) = txid {
+                return Ok(txid);
+            }
+        }
+        Ok(id)
     }
 
     #[trace]
-    pub fn lookup_raw_txn(&self, txid: &Txid) -> Option<Bytes> {
-        self.chain
-            .lookup_raw_txn(txid, None)
-            .or_else(|| self.mempool().lookup_raw_txn(txid))
+    pub fn lookup_raw_txn(&self, txid: &Txid) -> Result<Option<Bytes>> {
+        Ok(self
+            .chain
+            .lookup_raw_txn(txid, None)?
+            .or_else(|| self.mempool().lookup_raw_txn(txid)))
     }
 
     #[trace]
-    pub fn lookup_txos(&self, outpoints: BTreeSet<OutPoint>) -> HashMap<OutPoint, TxOut> {
+    pub fn lookup_txo(&self, outpoint: &OutPoint) -> Result<Option<TxOut>> {
+        Ok(self
+            .chain
+            .lookup_txo(outpoint)?
+            .or_else(|| self.mempool().lookup_txo(outpoint)))
+    }
+
+    #[trace]
+    pub fn lookup_txos(&self, outpoints: BTreeSet<OutPoint>) -> Result<HashMap<OutPoint, TxOut>> {
         // the mempool lookup_txos() internally looks up confirmed txos as well
-        self.mempool()
-            .lookup_txos(outpoints)
-            .expect("failed loading txos")
+        self.mempool().lookup_txos(outpoints)
     }
 
     #[trace]
-    pub fn lookup_spend(&self, outpoint: &OutPoint) -> Option<SpendingInput> {
-        self.chain
-            .lookup_spend(outpoint)
-            .or_else(|| self.mempool().lookup_spend(outpoint))
+    pub fn lookup_spend(&self, outpoint: &OutPoint) -> Result<Option<SpendingInput>> {
+        Ok(self
+            .chain
+            .lookup_spend(outpoint)?
+            .or_else(|| self.mempool().lookup_spend(outpoint)))
     }
 
     #[trace]
-    pub fn lookup_tx_spends(&self, tx: Transaction) -> Vec<Option<SpendingInput>> {
+    pub fn lookup_tx_spends(&self, tx: Transaction) -> Result<Vec<Option<SpendingInput>>> {
         let txid = tx.compute_txid();
 
-        tx.output
-            .par_iter()
-            .enumerate()
-            .map(|(vout, txout)| {
-                if is_spendable(txout) {
-                    self.lookup_spend(&OutPoint {
-                        txid,
-                        vout: vout as u32,
-                    })
-                } else {
-                    None
-                }
-            })
-            .collect()
+        self.chain.pool().install(|| {
+            tx.output
+                .par_iter()
+                .enumerate()
+                .map(|(vout, txout)| {
+                    if is_spendable(txout) {
+                        self.lookup_spend(&OutPoint {
+                            txid,
+                            vout: vout as u32,
+                        })
+                    } else {
+                        Ok(None)
+                    }
+                })
+                .collect()
+        })
     }
 
+    /// An unconfirmed transaction that recently left the mempool
     #[trace]
-    pub fn get_tx_status(&self, txid: &Txid) -> TransactionStatus {
-        TransactionStatus::from(self.chain.tx_confirming_block(txid))
+    pub fn lookup_evicted_txn(&self, txid: &Txid) -> Option<Transaction> {
+        self.mempool()
+            .lookup_evicted(txid)
+            .map(|evicted| evicted.tx.clone())
+    }
+
+    #[trace]
+    pub fn tx_conflicts(&self, tx: &Transaction) -> Result<TxConflicts> {
+        self.mempool().conflicts(tx)
+    }
+
+    #[trace]
+    pub fn get_tx_status(&self, txid: &Txid) -> Result<TransactionStatus> {
+        let mut status = TransactionStatus::from(self.chain.tx_confirming_block(txid)?);
+        if !status.confirmed {
+            status.evicted = self
+                .mempool()
+                .lookup_evicted(txid)
+                .map(|evicted| evicted.eviction.clone());
+        }
+        Ok(status)
     }
 
     #[trace]
@@ -202,37 +527,72 @@ impl Query {
             return self.get_relayfee().ok();
         }
         if let (ref cache, Some(cache_time)) = *self.cached_estimates.read().unwrap() {
-            if cache_time.elapsed() < Duration::from_secs(FEE_ESTIMATES_TTL) {
-                return cache.get(&conf_target).copied();
+            if cache_time.elapsed() < self.fee_estimates_ttl() {
+                self.estimates_cache.hit();
+                return cache
+                    .get(&conf_target)
+                    .map(|fee| self.above_fee_floor(*fee));
             }
         }
 
+        self.estimates_cache.miss();
         self.update_fee_estimates();
-        self.cached_estimates
+        let estimate = self
+            .cached_estimates
             .read()
             .unwrap()
             .0
             .get(&conf_target)
-            .copied()
+            .copied();
+        estimate.map(|fee| self.above_fee_floor(fee))
+    }
+
+    // Raise the feerate to lokid's current eviction floor, since transactions paying less would
+    // be purged from its mempool (or not accepted) until the congestion clears
+    fn above_fee_floor(&self, fee: f64) -> f64 {
+        fee.max(self.mempool().fee_floor())
+    }
+
+    fn fee_estimates_ttl(&self) -> Duration {
+        Duration::from_secs(self.config.runtime().fee_estimates_ttl)
     }
 
     #[trace]
     pub fn estimate_fee_map(&self) -> HashMap<u16, f64> {
         if let (ref cache, Some(cache_time)) = *self.cached_estimates.read().unwrap() {
-            if cache_time.elapsed() < Duration::from_secs(FEE_ESTIMATES_TTL) {
-                return cache.clone();
+            if cache_time.elapsed() < self.fee_estimates_ttl() {
+                self.estimates_cache.hit();
+                return self.with_fee_floor(cache);
             }
         }
 
+        self.estimates_cache.miss();
         self.update_fee_estimates();
-        self.cached_estimates.read().unwrap().0.clone()
+        self.with_fee_floor(&self.cached_estimates.read().unwrap().0)
+    }
+
+    fn with_fee_floor(&self, estimates: &HashMap<u16, f64>) -> HashMap<u16, f64> {
+        let fee_floor = self.mempool().fee_floor();
+        estimates
+            .iter()
+            .map(|(target, fee)| (*target, fee.max(fee_floor)))
+            .collect()
     }
 
     #[trace]
     fn update_fee_estimates(&self) {
         match self.daemon.estimatesmartfee_batch(&CONF_TARGETS) {
             Ok(estimates) => {
-                *self.cached_estimates.write().unwrap() = (estimates, Some(Instant::now()));
+                self.estimates_cache.set_usage(
+                    estimates.len(),
+                    estimates.len() * mem::size_of::<(u16, f64)>(),
+                );
+                let mut cached = self.cached_estimates.write().unwrap();
+                if cached.1.is_some() {
+                    // the expired estimates are replaced
+                    self.estimates_cache.evicted(cached.0.len());
+                }
+                *cached = (estimates, Some(Instant::now()));
             }
             Err(err) => {
                 warn!("failed estimating feerates: {:?}", err);
@@ -243,12 +603,111 @@ impl Query {
     #[trace]
     pub fn get_relayfee(&self) -> Result<f64> {
         if let Some(cached) = *self.cached_relayfee.read().unwrap() {
-            return Ok(cached);
+            return Ok(self.above_fee_floor(cached));
         }
 
         let relayfee = self.daemon.get_relayfee()?;
         self.cached_relayfee.write().unwrap().replace(relayfee);
-        Ok(relayfee)
+        Ok(self.above_fee_floor(relayfee))
+    }
+
+    /// The result of one of the read-only `daemon::PASSTHROUGH_METHODS`, cached for a few seconds
+    #[trace]
+    pub fn daemon_passthrough(&self, method: &str) -> Result<Value> {
+        if let Some((result, time)) = self.cached_passthrough.read().unwrap().get(method) {
+            if time.elapsed() < DAEMON_PASSTHROUGH_TTL {
+                return Ok(result.clone());
+            }
+        }
+        let result = self.daemon.passthrough(method)?;
+        self.cached_passthrough
+            .write()
+            .unwrap()
+            .insert(method.to_string(), (result.clone(), Instant::now()));
+        Ok(result)
+    }
+
+    /// Fetches a new block template from lokid if the current one is outdated, either because
+    /// a new block arrived or because it was fetched more than `BLOCK_TEMPLATE_INTERVAL` ago
+    /// (or longer, while the queries are overloaded)
+    #[cfg(not(feature = "liquid"))]
+    #[trace]
+    pub fn update_block_template(&self) {
+        let tip = self.chain.best_hash();
+        if let (ref template, Some(time)) = *self.block_template.read().unwrap() {
+            let same_tip = template
+                .as_ref()
+                .map_or(true, |template| template.previousblockhash == tip);
+            if same_tip
+                && !self.chain.load().is_due(
+                    "block_template",
+                    time.elapsed(),
+                    BLOCK_TEMPLATE_INTERVAL,
+                )
+            {
+                return;
+            }
+        }
+        let template = match self.daemon.getblocktemplate() {
+            Ok(template) => Some(template),
+            Err(err) => {
+                warn!("failed getting the block template: {:?}", err);
+                None
+            }
+        };
+        *self.block_template.write().unwrap() = (template, Some(Instant::now()));
+    }
+
+    /// Compares lokid's block template, as last fetched by `update_block_template()`, with the
+    /// next block projected from the mempool
+    #[cfg(not(feature = "liquid"))]
+    #[trace]
+    pub fn next_block(&self) -> Option<NextBlock> {
+        let cached = self.block_template.read().unwrap();
+        let (template, time) = match *cached {
+            (Some(ref template), Some(time)) => (template, time),
+            _ => return None,
+        };
+
+        let mempool = self.mempool();
+        let max_weight = template.weightlimit.unwrap_or(MAX_BLOCK_WEIGHT);
+        let projected = mempool.projected_block(max_weight / 4);
+        let projected_txids: HashSet<&Txid> = projected.iter().map(|(txid, _)| *txid).collect();
+
+        let shared_tx_count = template
+            .transactions
+            .iter()
+            .filter(|tx| projected_txids.contains(&tx.txid))
+            .count();
+        let unknown_tx_count = template
+            .transactions
+            .iter()
+            .filter(|tx| !mempool.has_txn(&tx.txid))
+            .count();
+
+        Some(NextBlock {
+            template: TemplateStats {
+                height: template.height,
+                previous_block_hash: template.previousblockhash,
+                tx_count: template.transactions.len(),
+                total_fee: template.transactions.iter().map(|tx| tx.fee).sum(),
+                sigops: template.transactions.iter().map(|tx| tx.sigops).sum(),
+                sigop_limit: template.sigoplimit,
+                weight: template.transactions.iter().map(|tx| tx.weight).sum(),
+                weight_limit: max_weight,
+                coinbase_value: template.coinbasevalue,
+                age: time.elapsed().as_secs(),
+            },
+            projection: ProjectionStats {
+                tx_count: projected.len(),
+                total_fee: projected.iter().map(|(_, feeinfo)| feeinfo.fee).sum(),
+                vsize: projected.iter().map(|(_, feeinfo)| feeinfo.vsize).sum(),
+            },
+            shared_tx_count,
+            template_only_tx_count: template.transactions.len() - shared_tx_count,
+            projection_only_tx_count: projected.len() - shared_tx_count,
+            unknown_tx_count,
+        })
     }
 
     #[cfg(feature = "liquid")]
@@ -257,17 +716,27 @@ impl Query {
         mempool: Arc<RwLock<Mempool>>,
         daemon: Arc<Daemon>,
         config: Arc<Config>,
+        metrics: &Metrics,
         asset_db: Option<Arc<RwLock<AssetRegistry>>>,
-    ) -> Self {
-        Query {
+    ) -> Result<Self> {
+        let blocklist =
+            Blocklist::open(config.blocklist.as_deref()).chain_err(|| "invalid --blocklist")?;
+        let clients = open_client_tracker(&config)?;
+        Ok(Query {
             chain,
             mempool,
             daemon,
             config,
             asset_db,
+            started: Instant::now(),
             cached_estimates: RwLock::new((HashMap::new(), None)),
+            estimates_cache: CacheMetrics::new(metrics, "fee_estimates"),
             cached_relayfee: RwLock::new(None),
-        }
+            cached_passthrough: RwLock::new(HashMap::new()),
+            blocklist,
+            clients,
+            stale_utxo_caches: Mutex::new((HashSet::new(), None)),
+        })
     }
 
     #[cfg(feature = "liquid")]
@@ -300,3 +769,54 @@ impl Query {
         Ok((total_num, results))
     }
 }
+
+#[cfg(not(feature = "liquid"))]
+#[derive(Serialize, Debug, ToSchema)]
+pub struct NextBlock {
+    pub template: TemplateStats,
+    pub projection: ProjectionStats,
+    /// The transactions both in the template and in the projection
+    pub shared_tx_count: usize,
+    pub template_only_tx_count: usize,
+    pub projection_only_tx_count: usize,
+    /// The template transactions that electrs' mempool doesn't know about yet
+    pub unknown_tx_count: usize,
+}
+
+#[cfg(not(feature = "liquid"))]
+#[derive(Serialize, Debug, ToSchema)]
+pub struct TemplateStats {
+    pub height: u32,
+    #[schema(value_type = String)]
+    pub previous_block_hash: BlockHash,
+    pub tx_count: usize,
+    #[serde(serialize_with = "crate::util::amount::serialize")]
+    pub total_fee: u64,
+    pub sigops: u64,
+    pub sigop_limit: Option<u64>,
+    pub weight: u64,
+    pub weight_limit: u64,
+    #[serde(serialize_with = "crate::util::amount::serialize")]
+    pub coinbase_value: u64,
+    /// The seconds since the template was fetched
+    pub age: u64,
+}
+
+#[cfg(not(feature = "liquid"))]
+#[derive(Serialize, Debug, ToSchema)]
+pub struct ProjectionStats {
+    pub tx_count: usize,
+    #[serde(serialize_with = "crate::util::amount::serialize")]
+    pub total_fee: u64,
+    pub vsize: u64,
+}
+
+fn open_client_tracker(config: &Config) -> Result<ClientTracker> {
+    ClientTracker::open(
+        config.electrum_client_stats.as_deref(),
+        config.electrum_ban_threshold,
+        config.electrum_ban_window,
+        config.electrum_ban_duration,
+    )
+    .chain_err(|| "invalid --electrum-client-stats-file")
+}
@@ -1,12 +1,13 @@
 use bitcoin::hashes::sha256d::Hash as Sha256dHash;
-use bitcoin::hex::FromHex;
+use bitcoin::hex::{DisplayHex, FromHex};
 #[cfg(not(feature = "liquid"))]
 use bitcoin::merkle_tree::MerkleBlock;
 
 use crypto::digest::Digest;
 use crypto::sha2::Sha256;
-use itertools::Itertools;
+use itertools::{process_results, Itertools};
 use rayon::prelude::*;
+use utoipa::ToSchema;
 
 #[cfg(not(feature = "liquid"))]
 use bitcoin::consensus::encode::{deserialize, serialize};
@@ -17,28 +18,56 @@ use elements::{
     AssetId,
 };
 
-use std::collections::{BTreeSet, HashMap, HashSet};
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use std::fs;
 use std::path::Path;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
 use std::sync::{Arc, RwLock};
+use std::time::{Duration, Instant};
 
+#[cfg(not(feature = "liquid"))]
+use crate::chain::Wtxid;
 use crate::config::Config;
 use crate::daemon::Daemon;
 use crate::errors::*;
-use crate::metrics::{Gauge, HistogramOpts, HistogramTimer, HistogramVec, MetricOpts, Metrics};
+use crate::metrics::{
+    CacheMetrics, CounterVec, Gauge, GaugeVec, HistogramOpts, HistogramTimer, HistogramVec,
+    MetricOpts, Metrics,
+};
 use crate::util::{
-    bincode, full_hash, has_prevout, is_spendable, BlockHeaderMeta, BlockId, BlockMeta,
-    BlockStatus, Bytes, HeaderEntry, HeaderList, ScriptToAddr,
+    address_to_script, bincode, explicit_value, full_hash, get_multisig_type, has_prevout,
+    is_spendable, spawn_thread, tx_vsize, BlockHeaderMeta, BlockId, BlockMeta, BlockStatus, Bytes,
+    HeaderEntry, HeaderList, MultisigType, ScriptToAddr, DEFAULT_BLOCKHASH,
 };
 use crate::{
     chain::{BlockHash, BlockHeader, Network, OutPoint, Script, Transaction, TxOut, Txid, Value},
     new_index::db_metrics::RocksDbMetrics,
 };
 
-use crate::new_index::db::{DBFlush, DBRow, ReverseScanIterator, ScanIterator, DB};
+use crate::new_index::aggcache::AggregateCache;
+use crate::new_index::blockstats::{
+    multisig_usage, utxo_age_buckets, BlockStats, ChainStats, MultisigUsage, Supply, UtxoAgeBucket,
+};
+use crate::new_index::checksum::{append_checksum, ChecksumVerifier};
+use crate::new_index::db::{
+    DBFlush, DBRow, PrefixUsage, ReverseScanIterator, ScanIterator, WalMode, DB,
+};
+use crate::new_index::dedup::WriteDedup;
+use crate::new_index::existence::ExistenceFilter;
 use crate::new_index::fetch::{start_fetcher, BlockEntry, FetchFrom};
+#[cfg(not(feature = "liquid"))]
+use crate::new_index::ledgercache::LedgerCache;
+use crate::new_index::totals::{LifetimeTotals, Total};
+use crate::new_index::watchdog::DiskWatchdog;
+use crate::util::pool::{LoadMonitor, ThreadPool, ThreadPools};
 
 #[cfg(feature = "liquid")]
-use crate::elements::{asset, ebcompact::TxidCompat, peg};
+use crate::elements::{
+    asset,
+    ebcompact::{SizeMethod, TxidCompat},
+    peg,
+};
 
 #[cfg(feature = "liquid")]
 use elements::encode::VarInt;
@@ -48,6 +77,27 @@ use bitcoin::VarInt;
 
 const MIN_HISTORY_ITEMS_TO_CACHE: usize = 100;
 
+// the cached utxo sets of a scripthash kept below its latest one, for this many blocks (plus the
+// newest one past them), to fall back to after a reorg rather than replaying the full history
+const UTXO_CACHE_REORG_DEPTH: u32 = 6;
+
+// history rows that are not tied to the blockhash they were indexed from, and have to be removed
+// once orphaned (history, spending edges, burns and asset history)
+const REORG_CHECKED_ROWS: &[u8] = b"HSbI";
+
+// marks a history db as maintaining the per-scripthash stats (`c{scripthash}` rows)
+const SCRIPT_STATS_MARKER: &[u8] = b"c";
+
+// marks a txstore db whose transaction and block rows end with a checksum (see checksum.rs)
+const CHECKSUMS_MARKER: &[u8] = b"k";
+
+// the blocks written through the WAL in the tip-only `--wal-mode`, counting down from the tip
+const WAL_TIP_DEPTH: usize = 100;
+
+// how long the full compaction following the initial sync is put off for at most, while the
+// queries are overloaded
+const FULL_COMPACTION_MAX_DELAY: Duration = Duration::from_secs(600);
+
 pub struct Store {
     // TODO: should be column families
     txstore_db: DB,
@@ -56,52 +106,149 @@ pub struct Store {
     added_blockhashes: RwLock<HashSet<BlockHash>>,
     indexed_blockhashes: RwLock<HashSet<BlockHash>>,
     indexed_headers: RwLock<HeaderList>,
+    script_stats: bool,
+    checksums: ChecksumVerifier,
+    existence_filter: Option<ExistenceFilter>,
+    // bumped on each reorg, see `chain_generation()`
+    generation: AtomicU64,
+    totals: LifetimeTotals,
 }
 
 impl Store {
-    pub fn open(path: &Path, config: &Config, metrics: &Metrics) -> Self {
-        let txstore_db = DB::open(&path.join("txstore"), config);
-        let added_blockhashes = load_blockhashes(&txstore_db, &BlockRow::done_filter());
+    /// Opens the databases of the network at `path` (`<db-dir>/<network>`)
+    pub fn open(path: &Path, config: &Config, metrics: &Metrics) -> Result<Self> {
+        migrate_legacy_layout(path);
+
+        let txstore_db = DB::open(&path.join("txstore"), config)?;
+        // refuse to mix up the index of another network
+        match txstore_db.get(b"N")? {
+            None => txstore_db
+                .put(b"N", config.network_name.as_bytes())
+                .chain_err(|| "failed writing the network name")?,
+            Some(ref network) if network != config.network_name.as_bytes() => bail!(
+                "the database at {} was indexed for the {} network, not {}: check --network and \
+                 --db-dir",
+                path.display(),
+                String::from_utf8_lossy(network),
+                config.network_name
+            ),
+            Some(_) => (),
+        }
+        let added_blockhashes = load_blockhashes(&txstore_db, &BlockRow::done_filter())?;
         debug!("{} blocks were added", added_blockhashes.len());
+        // the rows can only be checksummed if they were since the first block
+        if added_blockhashes.is_empty() && txstore_db.get(CHECKSUMS_MARKER)?.is_none() {
+            txstore_db
+                .put(CHECKSUMS_MARKER, b"")
+                .expect("failed writing the checksums marker");
+        }
+        let checksums = ChecksumVerifier::new(
+            txstore_db.get(CHECKSUMS_MARKER)?.is_some(),
+            config.txstore_checksums,
+            metrics,
+        );
+        if !checksums.enabled() {
+            info!("the txstore rows have no checksums, reindex to enable them");
+        }
 
-        let history_db = DB::open(&path.join("history"), config);
-        let indexed_blockhashes = load_blockhashes(&history_db, &BlockRow::done_filter());
+        let history_db = DB::open(&path.join("history"), config)?;
+        let indexed_blockhashes = load_blockhashes(&history_db, &BlockRow::done_filter())?;
         debug!("{} blocks were indexed", indexed_blockhashes.len());
+        // the scripthash stats can only be maintained if they were since the first block
+        if indexed_blockhashes.is_empty() && history_db.get(SCRIPT_STATS_MARKER)?.is_none() {
+            history_db
+                .put(SCRIPT_STATS_MARKER, b"")
+                .expect("failed writing the scripthash stats marker");
+        }
+        let script_stats = history_db.get(SCRIPT_STATS_MARKER)?.is_some();
+        if !script_stats {
+            info!("scripthash stats are not indexed, reindex to enable them");
+        }
 
-        let cache_db = DB::open(&path.join("cache"), config);
+        // the existence markers are complete in the databases indexed with the scripthash stats,
+        // which were introduced after them
+        let existence_filter = match config.existence_filter_mb {
+            0 => None,
+            _ if !script_stats => {
+                info!("the existence markers are incomplete, reindex to use the existence filter");
+                None
+            }
+            size_mb => Some(ExistenceFilter::new(size_mb, metrics)),
+        };
+
+        let cache_db = DB::open(&path.join("cache"), config)?;
+        let generation = cache_db.get(b"g")?.map_or(0, |value| {
+            let mut bytes = [0u8; 8];
+            bytes.copy_from_slice(&value);
+            u64::from_be_bytes(bytes)
+        });
+        let totals = LifetimeTotals::load(&cache_db, metrics)?;
 
         let db_metrics = Arc::new(RocksDbMetrics::new(&metrics));
         txstore_db.start_stats_exporter(Arc::clone(&db_metrics), "txstore_db");
         history_db.start_stats_exporter(Arc::clone(&db_metrics), "history_db");
         cache_db.start_stats_exporter(Arc::clone(&db_metrics), "cache_db");
 
-        let headers = if let Some(tip_hash) = txstore_db.get(b"t") {
-            let tip_hash = deserialize(&tip_hash).expect("invalid chain tip in `t`");
-            let headers_map = load_blockheaders(&txstore_db);
+        let headers = if let Some(tip) = txstore_db.get(b"t")? {
+            let tip_hash = deserialize::<BlockHash>(&tip).ok();
+            let headers_map = load_blockheaders(&txstore_db, &checksums)?;
             debug!(
                 "{} headers were loaded, tip at {:?}",
                 headers_map.len(),
                 tip_hash
             );
-            HeaderList::new(headers_map, tip_hash)
+            let consistent_tip = tip_hash.and_then(|tip_hash| {
+                consistent_tip(
+                    tip_hash,
+                    &headers_map,
+                    &added_blockhashes,
+                    &indexed_blockhashes,
+                )
+            });
+            if consistent_tip != tip_hash {
+                // the blocks above are fetched and indexed again (or only marked as done)
+                warn!(
+                    "the synced tip {:?} is inconsistent with the stored blocks (after an unclean \
+                     shutdown?), rolling back to {:?}",
+                    tip_hash, consistent_tip
+                );
+                match consistent_tip {
+                    Some(ref hash) => txstore_db.put_sync(b"t", &serialize(hash))?,
+                    None => txstore_db.delete(&[b"t".to_vec()])?,
+                }
+            }
+            match consistent_tip {
+                Some(tip_hash) => HeaderList::new(headers_map, tip_hash),
+                None => HeaderList::empty(),
+            }
         } else {
             HeaderList::empty()
         };
 
-        Store {
+        Ok(Store {
             txstore_db,
             history_db,
             cache_db,
             added_blockhashes: RwLock::new(added_blockhashes),
             indexed_blockhashes: RwLock::new(indexed_blockhashes),
             indexed_headers: RwLock::new(headers),
-        }
+            script_stats,
+            checksums,
+            existence_filter,
+            generation: AtomicU64::new(generation),
+            totals,
+        })
     }
 
     pub fn txstore_db(&self) -> &DB {
         &self.txstore_db
     }
 
+    /// Verifies the checksums of the transaction and block rows read from the txstore
+    pub fn checksums(&self) -> &ChecksumVerifier {
+        &self.checksums
+    }
+
     pub fn history_db(&self) -> &DB {
         &self.history_db
     }
@@ -110,11 +257,130 @@ impl Store {
         &self.cache_db
     }
 
-    pub fn done_initial_sync(&self) -> bool {
-        self.txstore_db.get(b"t").is_some()
+    /// Loads the existence filter (if enabled) from the existence markers
+    pub fn load_existence_filter(&self) -> Result<()> {
+        if let Some(ref filter) = self.existence_filter {
+            filter.load(self.history_db.iter_scan(&UsedRow::filter()))?;
+        }
+        Ok(())
+    }
+
+    // Inserts the scripthashes of the existence markers into the filter, before they are written
+    fn mark_used(&self, rows: &[DBRow]) {
+        if let Some(ref filter) = self.existence_filter {
+            for row in rows.iter().filter(|row| row.key[0] == b'E') {
+                filter.insert(&row.key[1..]);
+            }
+        }
+    }
+
+    /// The generation of the indexed chain, bumped each time a reorg orphans some of its blocks
+    /// and never reused. Only the chain-wide aggregates (the `Q` rows) embed it in their keys, so
+    /// that the data cached for an orphaned chain is never served for the chain replacing it. The
+    /// per-scripthash cache rows don't use it: they keep the blockhash they are up to date for,
+    /// and are checked against the best chain when read instead.
+    pub fn chain_generation(&self) -> u64 {
+        self.generation.load(Ordering::Acquire)
+    }
+
+    // Starts a new chain generation, persisted before it is used for caching
+    fn bump_generation(&self) -> Result<u64> {
+        let generation = self.chain_generation() + 1;
+        self.cache_db
+            .put_sync(b"g", &generation.to_be_bytes())
+            .chain_err(|| "failed persisting the chain generation")?;
+        self.generation.store(generation, Ordering::Release);
+        Ok(generation)
+    }
+
+    /// The counters kept across restarts, see `LifetimeTotals`
+    pub fn totals(&self) -> &LifetimeTotals {
+        &self.totals
+    }
+
+    pub fn save_totals(&self) -> Result<()> {
+        self.totals.save(&self.cache_db)
+    }
+
+    pub fn save_totals_if_due(&self, load: &LoadMonitor) -> Result<()> {
+        self.totals.save_if_due(&self.cache_db, load)
+    }
+
+    pub fn done_initial_sync(&self) -> Result<bool> {
+        Ok(self.txstore_db.get(b"t")?.is_some())
+    }
+
+    /// Stops (or resumes) writing to the databases, see `DiskWatchdog`
+    pub fn set_read_only(&self, read_only: bool) {
+        self.txstore_db.set_read_only(read_only);
+        self.history_db.set_read_only(read_only);
+        self.cache_db.set_read_only(read_only);
+    }
+
+    pub fn is_read_only(&self) -> bool {
+        self.history_db.is_read_only()
+    }
+
+    /// The rows and bytes used by each key prefix of the txstore, history and cache databases
+    pub fn key_space_usage(&self) -> Result<Vec<(&'static str, BTreeMap<u8, PrefixUsage>)>> {
+        let dbs = [
+            ("txstore", &self.txstore_db),
+            ("history", &self.history_db),
+            ("cache", &self.cache_db),
+        ];
+        dbs.iter()
+            .map(|(name, db)| {
+                info!("scanning the {} db", name);
+                Ok((*name, db.key_space_usage()?))
+            })
+            .collect()
     }
 }
 
+// The key prefixes of the rows in each database (or in all of them, with no database), along with
+// what the rows hold. Kept in sync with doc/schema.md, which is checked by the tests.
+const KEY_PREFIXES: &[(Option<&str>, u8, &str)] = &[
+    (None, b'V', "database version"),
+    (None, b'F', "full compaction marker"),
+    (Some("txstore"), b'B', "block headers"),
+    (Some("txstore"), b'X', "block txids"),
+    (Some("txstore"), b'M', "block metadata"),
+    (Some("txstore"), b'D', "done blocks"),
+    (Some("txstore"), b'T', "transactions"),
+    (Some("txstore"), b'C', "transaction confirmations"),
+    (Some("txstore"), b'W', "wtxids"),
+    (Some("txstore"), b'O', "transaction outputs"),
+    (Some("txstore"), b'k', "checksums marker"),
+    (Some("txstore"), b'N', "network name"),
+    (Some("txstore"), b't', "synced tip"),
+    (Some("history"), b'D', "done blocks"),
+    (Some("history"), b'H', "scripthash history"),
+    (Some("history"), b'a', "address search"),
+    (Some("history"), b'S', "spending edges"),
+    (Some("history"), b'b', "burns"),
+    (Some("history"), b'L', "scripthash activity"),
+    (Some("history"), b'E', "used scripthashes"),
+    (Some("history"), b'c', "scripthash stats"),
+    (Some("history"), b'G', "block stats"),
+    (Some("history"), b'i', "asset issuances"),
+    (Some("history"), b'I', "asset history"),
+    (Some("cache"), b'A', "scripthash stats"),
+    (Some("cache"), b'U', "scripthash utxos"),
+    (Some("cache"), b'L', "scripthash ledgers"),
+    (Some("cache"), b'Q', "aggregates"),
+    (Some("cache"), b'g', "chain generation"),
+    (Some("cache"), b'T', "lifetime totals"),
+    (Some("cache"), b'z', "asset stats"),
+];
+
+/// What the rows under a key prefix of the `db` database hold (see doc/schema.md)
+pub fn describe_prefix(db: &str, prefix: u8) -> &'static str {
+    KEY_PREFIXES
+        .iter()
+        .find(|(prefix_db, p, _)| *p == prefix && prefix_db.map_or(true, |d| d == db))
+        .map_or("unknown", |(_, _, description)| description)
+}
+
 type UtxoMap = HashMap<OutPoint, (BlockId, Value)>;
 
 #[derive(Debug)]
@@ -148,14 +414,16 @@ pub struct SpendingInput {
     pub confirmed: Option<BlockId>,
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, Clone, ToSchema)]
 pub struct ScriptStats {
     pub tx_count: usize,
     pub funded_txo_count: usize,
     pub spent_txo_count: usize,
     #[cfg(not(feature = "liquid"))]
+    #[serde(serialize_with = "crate::util::amount::serialize")]
     pub funded_txo_sum: u64,
     #[cfg(not(feature = "liquid"))]
+    #[serde(serialize_with = "crate::util::amount::serialize")]
     pub spent_txo_sum: u64,
 }
 
@@ -171,33 +439,259 @@ impl ScriptStats {
             spent_txo_sum: 0,
         }
     }
+
+    // Account for a funding or spending history entry, whose transaction is counted separately
+    fn add_txo(&mut self, txinfo: &TxHistoryInfo) {
+        match txinfo {
+            #[cfg(not(feature = "liquid"))]
+            TxHistoryInfo::Funding(info) => {
+                self.funded_txo_count += 1;
+                self.funded_txo_sum += info.value;
+            }
+
+            #[cfg(not(feature = "liquid"))]
+            TxHistoryInfo::Spending(info) => {
+                self.spent_txo_count += 1;
+                self.spent_txo_sum += info.value;
+            }
+
+            #[cfg(feature = "liquid")]
+            TxHistoryInfo::Funding(_) => {
+                self.funded_txo_count += 1;
+            }
+
+            #[cfg(feature = "liquid")]
+            TxHistoryInfo::Spending(_) => {
+                self.spent_txo_count += 1;
+            }
+
+            #[cfg(feature = "liquid")]
+            TxHistoryInfo::Issuing(_)
+            | TxHistoryInfo::Burning(_)
+            | TxHistoryInfo::Pegin(_)
+            | TxHistoryInfo::Pegout(_) => unreachable!(),
+        }
+    }
+
+    fn add(&mut self, other: &ScriptStats) {
+        self.tx_count += other.tx_count;
+        self.funded_txo_count += other.funded_txo_count;
+        self.spent_txo_count += other.spent_txo_count;
+        #[cfg(not(feature = "liquid"))]
+        {
+            self.funded_txo_sum += other.funded_txo_sum;
+            self.spent_txo_sum += other.spent_txo_sum;
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, ToSchema)]
+pub struct ScriptActivity {
+    pub first_seen: BlockId,
+    pub last_seen: BlockId,
+}
+
+/// A confirmed transaction of a scripthash, with the net value it moved into (when positive) or
+/// out of (when negative) the scripthash
+#[cfg(not(feature = "liquid"))]
+pub struct HistoryDelta {
+    pub txid: Txid,
+    pub blockid: BlockId,
+    pub delta: i64,
+}
+
+/// Which transactions of a scripthash history to keep, by the height they were confirmed at
+/// (excluding the unconfirmed ones when bounded), and by the direction and the amount of the net
+/// value they moved (see `HistoryDelta`)
+#[derive(Debug, Default, Clone)]
+pub struct HistoryFilter {
+    pub from_height: Option<usize>,
+    pub to_height: Option<usize>,
+    #[cfg(not(feature = "liquid"))]
+    pub direction: Option<HistoryDirection>,
+    #[cfg(not(feature = "liquid"))]
+    pub min_amount: Option<u64>,
+    #[cfg(not(feature = "liquid"))]
+    pub max_amount: Option<u64>,
+}
+
+#[cfg(not(feature = "liquid"))]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HistoryDirection {
+    /// Moving value out of the scripthash
+    Sent,
+    /// Moving value into the scripthash
+    Received,
+}
+
+#[cfg(not(feature = "liquid"))]
+impl std::str::FromStr for HistoryDirection {
+    type Err = String;
+
+    fn from_str(direction: &str) -> std::result::Result<Self, Self::Err> {
+        match direction {
+            "sent" => Ok(HistoryDirection::Sent),
+            "received" => Ok(HistoryDirection::Received),
+            _ => Err(format!(
+                "Invalid direction {:?} (expected sent or received)",
+                direction
+            )),
+        }
+    }
+}
+
+impl HistoryFilter {
+    /// Whether the transactions are filtered by the height they were confirmed at, leaving out the
+    /// unconfirmed ones
+    pub fn by_height(&self) -> bool {
+        self.from_height.is_some() || self.to_height.is_some()
+    }
+
+    /// Whether the transactions are filtered by the net value they moved
+    #[cfg(not(feature = "liquid"))]
+    pub fn by_value(&self) -> bool {
+        self.direction.is_some() || self.min_amount.is_some() || self.max_amount.is_some()
+    }
+
+    #[cfg(feature = "liquid")]
+    pub fn by_value(&self) -> bool {
+        false
+    }
+
+    /// Whether a transaction moving the net value `delta` into the scripthash is kept
+    #[cfg(not(feature = "liquid"))]
+    pub fn matches(&self, delta: i64) -> bool {
+        let direction = match self.direction {
+            None => true,
+            Some(HistoryDirection::Sent) => delta < 0,
+            Some(HistoryDirection::Received) => delta > 0,
+        };
+        let amount = delta.unsigned_abs();
+        direction
+            && self.min_amount.map_or(true, |min| amount >= min)
+            && self.max_amount.map_or(true, |max| amount <= max)
+    }
+}
+
+/// The net value moved into the scripthash by each transaction of its history `rows`, in the
+/// order of their first row
+#[cfg(not(feature = "liquid"))]
+pub fn net_deltas<'a>(rows: impl Iterator<Item = &'a TxHistoryInfo>) -> Vec<(Txid, i64)> {
+    let mut deltas: Vec<(Txid, i64)> = vec![];
+    let mut positions: HashMap<Txid, usize> = HashMap::new();
+    for info in rows {
+        let txid = info.get_txid();
+        match positions.get(&txid) {
+            Some(&pos) => deltas[pos].1 += info.value_delta(),
+            None => {
+                positions.insert(txid, deltas.len());
+                deltas.push((txid, info.value_delta()));
+            }
+        }
+    }
+    deltas
+}
+
+/// An entry of the append-only balance ledger of a scripthash. Entries are never modified once
+/// written: the transactions of blocks orphaned by a reorg are undone by `Reversal` entries.
+#[cfg(not(feature = "liquid"))]
+#[derive(Serialize, Deserialize, Debug, Clone, ToSchema)]
+pub struct LedgerEntry {
+    pub seq: u64,
+    pub kind: LedgerEntryKind,
+    #[schema(value_type = String)]
+    pub txid: Txid,
+    pub height: u32,
+    #[schema(value_type = String)]
+    pub block_hash: BlockHash,
+    pub time: u32,
+    /// The net value moved into (when positive) or out of (when negative) the scripthash
+    #[serde(serialize_with = "crate::util::amount::serialize_signed")]
+    pub amount: i64,
+    /// The balance of the scripthash once the entry is applied
+    #[serde(serialize_with = "crate::util::amount::serialize_signed")]
+    pub balance: i64,
+    /// The sequence number of the entry undone by a `Reversal` entry
+    pub reverses: Option<u64>,
+}
+
+#[cfg(not(feature = "liquid"))]
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum LedgerEntryKind {
+    Funding,
+    Spending,
+    Reversal,
 }
 
 pub struct Indexer {
     store: Arc<Store>,
-    flush: DBFlush,
+    wal_mode: WalMode,
+    // the height of the daemon's tip in the current update
+    tip_height: usize,
     from: FetchFrom,
     iconfig: IndexerConfig,
     duration: HistogramVec,
     tip_metric: Gauge,
+    rows_written: CounterVec,
+    rows_deduplicated: CounterVec,
+    batch_bytes: HistogramVec,
+    blocks: CounterVec,
+    blocks_rate: GaugeVec,
+    // heights of the headers being added in the current update, which are not yet
+    // available in the indexed headers list
+    pending_heights: HashMap<BlockHash, u32>,
+    disk_watchdog: DiskWatchdog,
+    pool: ThreadPool,
+    load: LoadMonitor,
+    // since when the full compaction is due
+    compaction_due: Mutex<Option<Instant>>,
+    // the scripthashes with history in the blocks indexed since the initial sync, whose cached
+    // utxo sets are brought up to date by the query side
+    touched: Mutex<HashSet<FullHash>>,
+    txstore_dedup: Mutex<WriteDedup>,
+    history_dedup: Mutex<WriteDedup>,
 }
 
-struct IndexerConfig {
+pub(super) struct IndexerConfig {
     light_mode: bool,
     address_search: bool,
     index_unspendables: bool,
+    utxo_age_stats: bool,
+    burn_scripts: HashSet<Script>,
+    reorg_check_depth: usize,
+    assume_valid_height: Option<usize>,
     network: Network,
+    // whether the transaction and block rows are written with checksums
+    checksums: bool,
     #[cfg(feature = "liquid")]
     parent_network: crate::chain::BNetwork,
 }
 
+impl IndexerConfig {
+    // Provably unspendable outputs and outputs to the burn addresses
+    fn is_burn(&self, txo: &TxOut) -> bool {
+        !is_spendable(txo) || self.burn_scripts.contains(&txo.script_pubkey)
+    }
+}
+
 impl From<&Config> for IndexerConfig {
     fn from(config: &Config) -> Self {
         IndexerConfig {
             light_mode: config.light_mode,
             address_search: config.address_search,
             index_unspendables: config.index_unspendables,
+            utxo_age_stats: config.utxo_age_stats,
+            reorg_check_depth: config.reorg_check_depth,
+            assume_valid_height: config.assume_valid_height,
+            burn_scripts: config
+                .burn_addresses
+                .iter()
+                // validated by the config
+                .filter_map(|addr| address_to_script(addr, config.network_type).ok())
+                .collect(),
             network: config.network_type,
+            checksums: false, // set by the indexer, following the txstore
             #[cfg(feature = "liquid")]
             parent_network: config.parent_network,
         }
@@ -210,21 +704,76 @@ pub struct ChainQuery {
     light_mode: bool,
     duration: HistogramVec,
     network: Network,
+    aggregate_cache: AggregateCache,
+    stats_cache: CacheMetrics,
+    utxo_cache: CacheMetrics,
+    #[cfg(not(feature = "liquid"))]
+    ledger_cache: LedgerCache,
+    pool: ThreadPool,
+    load: LoadMonitor,
 }
 
 // TODO: &[Block] should be an iterator / a queue.
 impl Indexer {
-    pub fn open(store: Arc<Store>, from: FetchFrom, config: &Config, metrics: &Metrics) -> Self {
+    pub fn open(
+        store: Arc<Store>,
+        from: FetchFrom,
+        config: &Config,
+        pools: &ThreadPools,
+        metrics: &Metrics,
+    ) -> Self {
         Indexer {
-            store,
-            flush: DBFlush::Disable,
+            pool: pools.index.clone(),
+            wal_mode: config.wal_mode,
+            tip_height: 0,
             from,
-            iconfig: IndexerConfig::from(config),
+            iconfig: IndexerConfig {
+                checksums: store.checksums.enabled(),
+                ..IndexerConfig::from(config)
+            },
             duration: metrics.histogram_vec(
                 HistogramOpts::new("index_duration", "Index update duration (in seconds)"),
                 &["step"],
             ),
             tip_metric: metrics.gauge(MetricOpts::new("tip_height", "Current chain tip height")),
+            rows_written: metrics.counter_vec(
+                MetricOpts::new(
+                    "index_rows_written",
+                    "# of rows written, by db and key prefix",
+                ),
+                &["db", "prefix"],
+            ),
+            rows_deduplicated: metrics.counter_vec(
+                MetricOpts::new(
+                    "index_rows_deduplicated",
+                    "# of rows not written as they were written at the same value recently, by db",
+                ),
+                &["db"],
+            ),
+            batch_bytes: metrics.histogram_vec(
+                HistogramOpts::new("index_batch_bytes", "Index write batch size (in bytes)")
+                    .buckets(prometheus::exponential_buckets(1024.0, 4.0, 10).unwrap()),
+                &["db"],
+            ),
+            blocks: metrics.counter_vec(
+                MetricOpts::new("index_blocks", "# of blocks processed, by step"),
+                &["step"],
+            ),
+            blocks_rate: metrics.gauge_vec(
+                MetricOpts::new(
+                    "index_blocks_per_second",
+                    "Blocks processed per second in the last batch, by step",
+                ),
+                &["step"],
+            ),
+            pending_heights: HashMap::new(),
+            load: pools.load.clone(),
+            compaction_due: Mutex::new(None),
+            touched: Mutex::new(HashSet::new()),
+            txstore_dedup: Mutex::new(WriteDedup::new(config.index_dedup_window)),
+            history_dedup: Mutex::new(WriteDedup::new(config.index_dedup_window)),
+            disk_watchdog: DiskWatchdog::new(Arc::clone(&store), config, metrics),
+            store,
         }
     }
 
@@ -232,6 +781,55 @@ impl Indexer {
         self.duration.with_label_values(&[name]).start_timer()
     }
 
+    // Record the number of rows written for each key prefix and the size of the write batch
+    fn observe_write(&self, db_name: &str, rows: &[DBRow]) {
+        let mut prefix_rows: HashMap<u8, u64> = HashMap::new();
+        let mut batch_bytes = 0;
+        for row in rows {
+            *prefix_rows.entry(row.key[0]).or_default() += 1;
+            batch_bytes += row.key.len() + row.value.len();
+        }
+        for (prefix, count) in prefix_rows {
+            let prefix = (prefix as char).to_string();
+            self.rows_written
+                .with_label_values(&[db_name, &prefix])
+                .inc_by(count);
+        }
+        self.batch_bytes
+            .with_label_values(&[db_name])
+            .observe(batch_bytes as f64);
+    }
+
+    // Write the rows to the db, except the ones written at the same value recently
+    fn write_dedup(
+        &self,
+        db: &DB,
+        db_name: &str,
+        dedup: &Mutex<WriteDedup>,
+        rows: Vec<DBRow>,
+        flush: DBFlush,
+    ) -> Result<()> {
+        let mut dedup = dedup.lock().unwrap();
+        let (rows, pending, skipped) = dedup.filter(rows);
+        self.rows_deduplicated
+            .with_label_values(&[db_name])
+            .inc_by(skipped as u64);
+        self.observe_write(db_name, &rows);
+        db.write(rows, flush)?;
+        dedup.remember(pending);
+        Ok(())
+    }
+
+    fn observe_blocks(&self, step: &str, count: usize, started: Instant) {
+        self.blocks.with_label_values(&[step]).inc_by(count as u64);
+        let elapsed = started.elapsed().as_secs_f64();
+        if elapsed > 0.0 {
+            self.blocks_rate
+                .with_label_values(&[step])
+                .set(count as f64 / elapsed);
+        }
+    }
+
     fn headers_to_add(&self, new_headers: &[HeaderEntry]) -> Vec<HeaderEntry> {
         let added_blockhashes = self.store.added_blockhashes.read().unwrap();
         new_headers
@@ -250,20 +848,37 @@ impl Indexer {
             .collect()
     }
 
-    fn start_auto_compactions(&self, db: &DB) {
+    fn start_auto_compactions(&self, db: &DB) -> Result<()> {
         let key = b"F".to_vec();
-        if db.get(&key).is_none() {
+        if db.get(&key)?.is_none() {
+            let due = *self
+                .compaction_due
+                .lock()
+                .unwrap()
+                .get_or_insert_with(Instant::now);
+            if !self
+                .load
+                .may_run("full_compaction", due.elapsed(), FULL_COMPACTION_MAX_DELAY)
+            {
+                // the auto compactions are started along with the full compaction
+                return Ok(());
+            }
             db.full_compaction();
-            db.put_sync(&key, b"");
-            assert!(db.get(&key).is_some());
+            db.put_sync(&key, b"")?;
+            assert!(db.get(&key)?.is_some());
         }
-        db.enable_auto_compaction();
+        db.enable_auto_compaction()
     }
 
     fn get_new_headers(&self, daemon: &Daemon, tip: &BlockHash) -> Result<Vec<HeaderEntry>> {
         let headers = self.store.indexed_headers.read().unwrap();
         let new_headers = daemon.get_new_headers(&headers, &tip)?;
         let result = headers.order(new_headers);
+        if let Some(assume_valid_height) = self.iconfig.assume_valid_height {
+            // the proof-of-work is checked in parallel, on the index pool
+            self.pool
+                .install(|| headers.validate(&result, self.iconfig.network, assume_valid_height))?;
+        }
 
         if let Some(tip) = result.last() {
             info!("{:?} ({} left to index)", tip, result.len());
@@ -272,76 +887,288 @@ impl Indexer {
     }
 
     pub fn update(&mut self, daemon: &Daemon) -> Result<BlockHash> {
+        if !self.disk_watchdog.check() {
+            // keep serving the blocks indexed so far
+            return Ok(*self.store.indexed_headers.read().unwrap().tip());
+        }
         let daemon = daemon.reconnect()?;
         let tip = daemon.getbestblockhash()?;
         let new_headers = self.get_new_headers(&daemon, &tip)?;
+        self.pending_heights = new_headers
+            .iter()
+            .map(|e| (*e.hash(), e.height() as u32))
+            .collect();
+
+        self.tip_height = new_headers.last().map_or(0, |e| e.height());
 
         let to_add = self.headers_to_add(&new_headers);
+        // whether some of the blocks are written without the WAL, and need flushing
+        let mut unlogged = to_add.iter().any(|e| !self.use_wal(e.height()));
         debug!(
             "adding transactions from {} blocks using {:?}",
             to_add.len(),
             self.from
         );
-        start_fetcher(self.from, &daemon, to_add)?.map(|blocks| self.add(&blocks));
-        self.start_auto_compactions(&self.store.txstore_db);
+        start_fetcher(self.from, &daemon, to_add, &self.pool)?.map(|blocks| self.add(&blocks))?;
+        self.start_auto_compactions(&self.store.txstore_db)?;
 
         let to_index = self.headers_to_index(&new_headers);
+        unlogged |= to_index.iter().any(|e| !self.use_wal(e.height()));
         debug!(
             "indexing history from {} blocks using {:?}",
             to_index.len(),
             self.from
         );
-        start_fetcher(self.from, &daemon, to_index)?.map(|blocks| self.index(&blocks));
-        self.start_auto_compactions(&self.store.history_db);
+        start_fetcher(self.from, &daemon, to_index, &self.pool)?
+            .map(|blocks| self.index(&blocks))?;
+        self.start_auto_compactions(&self.store.history_db)?;
 
-        if let DBFlush::Disable = self.flush {
+        if unlogged {
             debug!("flushing to disk");
-            self.store.txstore_db.flush();
-            self.store.history_db.flush();
-            self.flush = DBFlush::Enable;
+            self.store.txstore_db.flush()?;
+            self.store.history_db.flush()?;
         }
 
         // update the synced tip *after* the new data is flushed to disk
         debug!("updating synced tip to {:?}", tip);
-        self.store.txstore_db.put_sync(b"t", &serialize(&tip));
+        self.store.txstore_db.put_sync(b"t", &serialize(&tip))?;
 
         let mut headers = self.store.indexed_headers.write().unwrap();
-        headers.apply(new_headers);
+        let reorg_depth = new_headers
+            .first()
+            .map_or(0, |first| headers.len().saturating_sub(first.height()));
+        let replacing = if reorg_depth > self.iconfig.reorg_check_depth {
+            new_headers.clone()
+        } else {
+            vec![]
+        };
+        let orphaned = headers.apply(new_headers);
         assert_eq!(tip, *headers.tip());
+        if !orphaned.is_empty() {
+            // bumped under the headers lock, so that the tip and generation are read consistently
+            let generation = self.store.bump_generation()?;
+            self.store.totals.inc(Total::Reorgs, 1);
+            info!(
+                "{} blocks orphaned, starting chain generation {}",
+                orphaned.len(),
+                generation
+            );
+        }
+        self.pending_heights.clear();
 
         if let FetchFrom::BlkFiles = self.from {
             self.from = FetchFrom::Bitcoind;
         }
 
         self.tip_metric.set(headers.len() as i64 - 1);
+        drop(headers);
+
+        if !replacing.is_empty() {
+            info!(
+                "checking the index after a reorg of {} blocks",
+                orphaned.len()
+            );
+            if let Err(e) = self.check_reorg(&daemon, &orphaned, &replacing) {
+                warn!("index consistency check failed: {}", e.display_chain());
+            }
+        }
+        if !orphaned.is_empty() {
+            if let Err(e) = self.unmark_orphaned_used(&daemon, &orphaned) {
+                warn!(
+                    "failed removing the existence markers of orphaned blocks: {}",
+                    e.display_chain()
+                );
+            }
+        }
 
         Ok(tip)
     }
 
-    fn add(&self, blocks: &[BlockEntry]) {
+    // Re-derive the history rows of the blocks orphaned by a reorg and of the blocks replacing
+    // them, then remove the rows that were only produced by the orphaned blocks and restore the
+    // rows of the new blocks that are missing.
+    fn check_reorg(
+        &self,
+        daemon: &Daemon,
+        orphaned: &[HeaderEntry],
+        replacing: &[HeaderEntry],
+    ) -> Result<()> {
+        let _timer = self.start_timer("reorg_check");
+        let replacing_rows = self.derive_history_rows(daemon, replacing)?;
+        let replacing_keys: HashSet<&[u8]> =
+            replacing_rows.iter().map(|row| &row.key[..]).collect();
+        let stale_keys: Vec<Vec<u8>> = self
+            .derive_history_rows(daemon, orphaned)?
+            .into_iter()
+            .map(|row| row.key)
+            .filter(|key| !replacing_keys.contains(&key[..]))
+            .collect();
+
+        let db = &self.store.history_db;
+        let stale_keys: Vec<Vec<u8>> = db
+            .multi_get(&stale_keys)
+            .into_iter()
+            .zip(stale_keys)
+            .map(|(res, key)| Ok(res?.map(|_| key)))
+            .filter_map(Result::transpose)
+            .collect::<Result<_>>()?;
+        let missing_rows: Vec<DBRow> = db
+            .multi_get(replacing_rows.iter().map(|row| &row.key))
+            .into_iter()
+            .zip(replacing_rows)
+            .map(|(res, row)| Ok(res?.is_none().then(|| row)))
+            .filter_map(Result::transpose)
+            .collect::<Result<_>>()?;
+
+        if stale_keys.is_empty() && missing_rows.is_empty() {
+            info!("index is consistent after the reorg");
+            return Ok(());
+        }
+        warn!(
+            "found {} stale and {} missing history rows after the reorg, repairing",
+            stale_keys.len(),
+            missing_rows.len()
+        );
+        db.delete(&stale_keys)?;
+        self.history_dedup.lock().unwrap().clear();
+        db.write(missing_rows, DBFlush::Enable)?;
+
+        let mut remaining = 0;
+        for res in db.multi_get(&stale_keys) {
+            if res?.is_some() {
+                remaining += 1;
+            }
+        }
+        if remaining > 0 {
+            bail!("{} stale history rows remain after the repair", remaining);
+        }
+        Ok(())
+    }
+
+    // Remove the existence markers of the scripthashes touched by the orphaned blocks that have
+    // no activity left in the best chain
+    fn unmark_orphaned_used(&self, daemon: &Daemon, orphaned: &[HeaderEntry]) -> Result<()> {
+        let _timer = self.start_timer("reorg_unmark_used");
+        let markers = self.derive_rows(daemon, orphaned, b"E")?;
+        let db = &self.store.history_db;
+        let headers = self.store.indexed_headers.read().unwrap();
+        let mut unused_keys: Vec<Bytes> = vec![];
+        for marker in markers {
+            let activity = db.iter_scan(&ActivityRow::filter(&marker.key[1..]));
+            let used = process_results(activity, |mut rows| {
+                rows.any(|row| {
+                    let row = ActivityRow::from_row(row);
+                    let blockhash: BlockHash =
+                        deserialize(&row.blockhash).expect("invalid blockhash");
+                    headers.header_by_blockhash(&blockhash).is_some()
+                })
+            })?;
+            if !used {
+                unused_keys.push(marker.key);
+            }
+        }
+        drop(headers);
+        if !unused_keys.is_empty() {
+            info!(
+                "removing the existence markers of {} scripthashes only used by orphaned blocks",
+                unused_keys.len()
+            );
+            db.delete(&unused_keys)?;
+        }
+        Ok(())
+    }
+
+    // Re-derive the history rows produced by the given blocks that are not tied to their
+    // blockhash (and are therefore not filtered out at query time once orphaned)
+    fn derive_history_rows(&self, daemon: &Daemon, entries: &[HeaderEntry]) -> Result<Vec<DBRow>> {
+        self.derive_rows(daemon, entries, REORG_CHECKED_ROWS)
+    }
+
+    // Re-derive the history rows of the given `prefixes` produced by the given blocks
+    fn derive_rows(
+        &self,
+        daemon: &Daemon,
+        entries: &[HeaderEntry],
+        prefixes: &[u8],
+    ) -> Result<Vec<DBRow>> {
+        let blockhashes: Vec<BlockHash> = entries.iter().map(|e| *e.hash()).collect();
+        let blocks: Vec<BlockEntry> = daemon
+            .getblocks(&blockhashes)?
+            .into_iter()
+            .zip(entries)
+            .map(|(block, entry)| BlockEntry {
+                entry: entry.clone(),
+                size: block.total_size() as u32,
+                block,
+            })
+            .collect();
+        let previous_txos_map = lookup_txos(&self.store.txstore_db, get_previous_txos(&blocks))?;
+        let (rows, _) = self
+            .pool
+            .install(|| index_blocks(&blocks, &previous_txos_map, &HashMap::new(), &self.iconfig));
+        Ok(rows
+            .into_iter()
+            .filter(|row| prefixes.contains(&row.key[0]))
+            .collect())
+    }
+
+    fn use_wal(&self, height: usize) -> bool {
+        match self.wal_mode {
+            WalMode::Off => false,
+            WalMode::TipOnly => height + WAL_TIP_DEPTH > self.tip_height,
+            WalMode::Always => true,
+        }
+    }
+
+    // a batch reaching the blocks near the tip is written through the WAL as a whole
+    fn flush_for(&self, blocks: &[BlockEntry]) -> DBFlush {
+        if blocks.iter().any(|b| self.use_wal(b.entry.height())) {
+            DBFlush::Enable
+        } else {
+            DBFlush::Disable
+        }
+    }
+
+    fn add(&self, blocks: &[BlockEntry]) -> Result<()> {
+        let started = Instant::now();
         // TODO: skip orphaned blocks?
         let rows = {
             let _timer = self.start_timer("add_process");
-            add_blocks(blocks, &self.iconfig)
+            self.pool.install(|| add_blocks(blocks, &self.iconfig))
         };
         {
             let _timer = self.start_timer("add_write");
-            self.store.txstore_db.write(rows, self.flush);
+            self.write_dedup(
+                &self.store.txstore_db,
+                "txstore",
+                &self.txstore_dedup,
+                rows,
+                self.flush_for(blocks),
+            )?;
         }
+        self.observe_blocks("add", blocks.len(), started);
 
         self.store
             .added_blockhashes
             .write()
             .unwrap()
             .extend(blocks.iter().map(|b| b.entry.hash()));
+        Ok(())
     }
 
-    fn index(&self, blocks: &[BlockEntry]) {
+    fn index(&self, blocks: &[BlockEntry]) -> Result<()> {
+        let started = Instant::now();
         let previous_txos_map = {
             let _timer = self.start_timer("index_lookup");
-            lookup_txos(&self.store.txstore_db, get_previous_txos(blocks)).unwrap()
+            lookup_txos(&self.store.txstore_db, get_previous_txos(blocks))?
         };
-        let rows = {
+        let funding_heights = if self.iconfig.utxo_age_stats {
+            let _timer = self.start_timer("index_lookup_heights");
+            self.lookup_funding_heights(&previous_txos_map)?
+        } else {
+            HashMap::new()
+        };
+        let (mut rows, script_stats) = {
             let _timer = self.start_timer("index_process");
             let added_blockhashes = self.store.added_blockhashes.read().unwrap();
             for b in blocks {
@@ -351,9 +1178,380 @@ impl Indexer {
                     panic!("cannot index block {} (missing from store)", blockhash);
                 }
             }
-            index_blocks(blocks, &previous_txos_map, &self.iconfig)
+            self.pool.install(|| {
+                index_blocks(blocks, &previous_txos_map, &funding_heights, &self.iconfig)
+            })
+        };
+        if self.store.script_stats {
+            let _timer = self.start_timer("index_script_stats");
+            rows.extend(self.script_stats_rows(&script_stats)?);
+        }
+        self.store.mark_used(&rows);
+        if self.store.done_initial_sync()? {
+            let mut touched = self.touched.lock().unwrap();
+            for row in rows.iter().filter(|row| row.key[0] == b'H') {
+                // H{scripthash}...
+                touched.insert(full_hash(&row.key[1..33]));
+            }
+        }
+        {
+            let _timer = self.start_timer("index_write");
+            self.write_dedup(
+                &self.store.history_db,
+                "history",
+                &self.history_dedup,
+                rows,
+                self.flush_for(blocks),
+            )?;
+        }
+        self.observe_blocks("index", blocks.len(), started);
+        self.store
+            .totals
+            .inc(Total::BlocksIndexed, blocks.len() as u64);
+        Ok(())
+    }
+
+    /// The scripthashes with history in the blocks indexed since the last call
+    pub fn take_touched(&self) -> Vec<FullHash> {
+        self.touched.lock().unwrap().drain().collect()
+    }
+
+    /// Regenerates the rows derived from the blocks at heights `from..=to` of the indexed chain,
+    /// fetched again from the daemon, to repair a corrupted range without a full reindex.
+    ///
+    /// The history rows of the range (the history and activity rows, the spending edges of the
+    /// history, the block stats and burns) are deleted before being regenerated, so that none of
+    /// the rows left by the blocks previously indexed there survive. Finding them scans the whole
+    /// history and activity index. The existence markers and the stats of the scripthashes with
+    /// history in the range are then rebuilt from their history in the best chain.
+    pub fn reindex_range(&self, daemon: &Daemon, from: usize, to: usize) -> Result<()> {
+        ensure!(
+            self.disk_watchdog.check(),
+            "not enough free disk space to reindex"
+        );
+        let entries: Vec<HeaderEntry> = {
+            let headers = self.store.indexed_headers.read().unwrap();
+            ensure!(
+                from <= to && to < headers.len(),
+                "invalid height range {}..={}, the index is at height {}",
+                from,
+                to,
+                headers.len() as isize - 1
+            );
+            (from..=to)
+                .map(|height| headers.header_by_height(height).unwrap().clone())
+                .collect()
         };
-        self.store.history_db.write(rows, self.flush);
+        info!(
+            "reindexing {} blocks, from height {} to {}",
+            entries.len(),
+            from,
+            to
+        );
+
+        let daemon = daemon.reconnect()?;
+        let mut scripthashes = self.delete_range_rows(from as u32, to as u32)?;
+        // the regenerated rows are written whatever the window holds, to repair the corrupted ones
+        self.txstore_dedup.lock().unwrap().clear();
+        self.history_dedup.lock().unwrap().clear();
+        start_fetcher(FetchFrom::Bitcoind, &daemon, entries, &self.pool)?
+            .map(|blocks| self.reindex(&blocks, &mut scripthashes))?;
+        self.rebuild_scripthash_rows(&scripthashes)?;
+
+        // the aggregates are keyed by the tip, which didn't change
+        let aggregate_keys: Vec<Vec<u8>> = self
+            .store
+            .cache_db
+            .iter_scan(b"Q")
+            .map_ok(|row| row.key)
+            .collect::<Result<_>>()?;
+        self.store.cache_db.delete(&aggregate_keys)?;
+
+        self.store.txstore_db.flush()?;
+        self.store.history_db.flush()?;
+        info!("reindexed heights {} to {}", from, to);
+        Ok(())
+    }
+
+    // Rewrite the txstore and history rows of the blocks, keeping track of the scripthashes
+    // they touched
+    fn reindex(&self, blocks: &[BlockEntry], scripthashes: &mut HashSet<FullHash>) -> Result<()> {
+        let started = Instant::now();
+        let rows = self.pool.install(|| add_blocks(blocks, &self.iconfig));
+        self.observe_write("txstore", &rows);
+        self.store.txstore_db.write(rows, DBFlush::Enable)?;
+
+        let previous_txos_map = lookup_txos(&self.store.txstore_db, get_previous_txos(blocks))?;
+        let funding_heights = if self.iconfig.utxo_age_stats {
+            self.lookup_funding_heights(&previous_txos_map)?
+        } else {
+            HashMap::new()
+        };
+        let (rows, _) = self
+            .pool
+            .install(|| index_blocks(blocks, &previous_txos_map, &funding_heights, &self.iconfig));
+
+        // H{scripthash}...
+        scripthashes.extend(
+            rows.iter()
+                .filter(|row| row.key[0] == b'H')
+                .map(|row| full_hash(&row.key[1..33])),
+        );
+        self.observe_write("history", &rows);
+        self.store.mark_used(&rows);
+        self.store.history_db.write(rows, DBFlush::Enable)?;
+        self.observe_blocks("reindex", blocks.len(), started);
+        Ok(())
+    }
+
+    // Delete the history rows of the blocks at heights `from..=to`, returning the scripthashes
+    // they touched. The history and activity rows aren't prefixed by their height, so their whole
+    // index is scanned, and the spending edges are found through the spending history rows.
+    fn delete_range_rows(&self, from: u32, to: u32) -> Result<HashSet<FullHash>> {
+        let db = &self.store.history_db;
+        let mut keys = self.height_range_keys(from, to)?;
+        let mut scripthashes = HashSet::new();
+        for row in db.iter_scan(&TxHistoryRow::filter(b'H', &[])) {
+            let history = TxHistoryRow::from_row(row?);
+            if history.key.confirmed_height < from || history.key.confirmed_height > to {
+                continue;
+            }
+            if let TxHistoryInfo::Spending(ref info) = history.key.txinfo {
+                let edge = TxEdgeRow::new(info.prev_txid, info.prev_vout, info.txid, info.vin);
+                keys.push(edge.into_row().key);
+            }
+            scripthashes.insert(history.key.hash);
+            keys.push(history.into_row().key);
+        }
+        for row in db.iter_scan(&ActivityRow::filter(&[])) {
+            let activity = ActivityRow::from_row(row?);
+            if activity.key.height < from || activity.key.height > to {
+                continue;
+            }
+            scripthashes.insert(activity.key.scripthash);
+            keys.push(activity.into_row().key);
+        }
+        info!(
+            "deleting {} history rows of heights {} to {}",
+            keys.len(),
+            from,
+            to
+        );
+        db.delete(&keys)?;
+        Ok(scripthashes)
+    }
+
+    // Rebuild the existence markers and the stats of the scripthashes from their history in the
+    // best chain, once their history was reindexed, and drop their cached stats and utxos
+    fn rebuild_scripthash_rows(&self, scripthashes: &HashSet<FullHash>) -> Result<()> {
+        let db = &self.store.history_db;
+        let headers = self.store.indexed_headers.read().unwrap();
+        let tip = full_hash(&headers.tip()[..]);
+        let mut rows = vec![];
+        let mut unused_keys = vec![];
+        let mut cache_keys = vec![];
+        for scripthash in scripthashes {
+            let activity = db.iter_scan(&ActivityRow::filter(&scripthash[..]));
+            let used = process_results(activity, |mut activity| {
+                activity.any(|row| {
+                    let row = ActivityRow::from_row(row);
+                    let blockhash: BlockHash =
+                        deserialize(&row.blockhash).expect("invalid blockhash");
+                    headers.header_by_blockhash(&blockhash).is_some()
+                })
+            })?;
+            if used {
+                rows.push(UsedRow::new(*scripthash).into_row());
+            } else {
+                unused_keys.push(UsedRow::key(scripthash));
+            }
+            if self.store.script_stats {
+                let stats = self.recompute_stats(scripthash, u32::MAX, &headers)?;
+                rows.push(ScriptStatsRow::new(*scripthash, stats, tip).into_row());
+            }
+
+            cache_keys.push(StatsCacheRow::key(&scripthash[..]));
+            for cached in self
+                .store
+                .cache_db
+                .iter_scan(&UtxoCacheRow::filter(&scripthash[..]))
+            {
+                cache_keys.push(cached?.key);
+            }
+        }
+        drop(headers);
+        info!(
+            "rebuilding the markers and stats of {} scripthashes",
+            scripthashes.len()
+        );
+        db.delete(&unused_keys)?;
+        self.store.mark_used(&rows);
+        db.write(rows, DBFlush::Enable)?;
+        self.store.cache_db.delete(&cache_keys)?;
+        Ok(())
+    }
+
+    // The keys of the history rows prefixed by a height in `from..=to`
+    fn height_range_keys(&self, from: u32, to: u32) -> Result<Vec<Bytes>> {
+        let db = &self.store.history_db;
+        let ranges = [
+            (
+                BlockStatsRow::filter(),
+                BlockStatsRow::prefix_height(from),
+                BlockStatsRow::prefix_height(to + 1),
+            ),
+            (
+                BurnRow::filter(),
+                BurnRow::prefix_height(from),
+                BurnRow::prefix_height(to + 1),
+            ),
+        ];
+        ranges
+            .iter()
+            .flat_map(|(filter, start, end)| {
+                db.iter_scan_from(filter, start)
+                    .map_ok(|row| row.key)
+                    .take_while(move |key| key.as_ref().map_or(true, |key| key < end))
+            })
+            .collect()
+    }
+
+    // Add the transactions and txos of the indexed blocks to the running stats of every
+    // scripthash they touched. Stats last updated by a block that has since been orphaned are
+    // recomputed from the history rows of the new best chain.
+    fn script_stats_rows(&self, blocks: &[BlockScriptStats]) -> Result<Vec<DBRow>> {
+        let mut batch: HashMap<FullHash, (ScriptStats, FullHash)> = HashMap::new();
+        for block in blocks {
+            for (scripthash, stats) in &block.stats {
+                let entry = batch
+                    .entry(*scripthash)
+                    .or_insert_with(|| (ScriptStats::default(), block.blockhash));
+                entry.0.add(stats);
+                entry.1 = block.blockhash;
+            }
+        }
+
+        let headers = self.store.indexed_headers.read().unwrap();
+        // blocks above the first pending height are being replaced when reorging
+        let fork_height = self.pending_heights.values().min().copied();
+        let in_best_chain = |blockhash: &BlockHash| {
+            self.pending_heights.contains_key(blockhash)
+                || headers
+                    .header_by_blockhash(blockhash)
+                    .map_or(false, |header| {
+                        fork_height.map_or(true, |fork| (header.height() as u32) < fork)
+                    })
+        };
+
+        let db = &self.store.history_db;
+        let scripthashes: Vec<FullHash> = batch.keys().copied().collect();
+        db.multi_get(scripthashes.iter().map(|sh| ScriptStatsRow::key(sh)))
+            .into_iter()
+            .zip(scripthashes)
+            .map(|(res, scripthash)| -> Result<DBRow> {
+                let mut stats = match res? {
+                    None => ScriptStats::default(),
+                    Some(value) => {
+                        let prev = ScriptStatsRow::from_value(scripthash, &value)?;
+                        if in_best_chain(&prev.blockhash()?) {
+                            prev.stats
+                        } else {
+                            self.recompute_stats(&scripthash, fork_height.unwrap_or(0), &headers)?
+                        }
+                    }
+                };
+                let (block_stats, blockhash) = &batch[&scripthash];
+                stats.add(block_stats);
+                Ok(ScriptStatsRow::new(scripthash, stats, *blockhash).into_row())
+            })
+            .collect()
+    }
+
+    // Sum up the transactions and txos of the scripthash confirmed in the best chain below
+    // `height`, ignoring history entries of orphaned blocks
+    fn recompute_stats(
+        &self,
+        scripthash: &FullHash,
+        height: u32,
+        headers: &HeaderList,
+    ) -> Result<ScriptStats> {
+        let mut stats = ScriptStats::default();
+        let mut seen_txids = HashSet::new();
+        let mut last_height = None;
+        let history = self
+            .store
+            .history_db
+            .iter_scan(&TxHistoryRow::filter(b'H', &scripthash[..]))
+            .map_ok(TxHistoryRow::from_row)
+            .take_while(|history| {
+                history
+                    .as_ref()
+                    .map_or(true, |history| history.key.confirmed_height < height)
+            });
+        for history in history {
+            let history = history?;
+            let txid = history.get_txid();
+            let mut confirmed = false;
+            for conf in self
+                .store
+                .txstore_db
+                .iter_scan(&TxConfRow::filter(&txid[..]))
+            {
+                let conf = TxConfRow::from_row(conf?);
+                let blockhash: BlockHash = deserialize(&conf.key.blockhash)
+                    .chain_err(|| "invalid blockhash in TxConfRow")?;
+                let conf_height = headers.header_by_blockhash(&blockhash).map(|h| h.height());
+                if conf_height == Some(history.key.confirmed_height as usize) {
+                    confirmed = true;
+                    break;
+                }
+            }
+            if !confirmed {
+                continue;
+            }
+            if last_height != Some(history.key.confirmed_height) {
+                seen_txids.clear();
+                last_height = Some(history.key.confirmed_height);
+            }
+            if seen_txids.insert(txid) {
+                stats.tx_count += 1;
+            }
+            stats.add_txo(&history.key.txinfo);
+        }
+        Ok(stats)
+    }
+
+    // Get the confirmation height of the transactions funding the given outputs
+    fn lookup_funding_heights(
+        &self,
+        previous_txos_map: &HashMap<OutPoint, TxOut>,
+    ) -> Result<HashMap<Txid, u32>> {
+        let txids: HashSet<Txid> = previous_txos_map.keys().map(|o| o.txid).collect();
+        let headers = self.store.indexed_headers.read().unwrap();
+        self.pool.install(|| {
+            txids
+                .into_par_iter()
+                .map(|txid| -> Result<Option<(Txid, u32)>> {
+                    let confs = self
+                        .store
+                        .txstore_db
+                        .iter_scan(&TxConfRow::filter(&txid[..]));
+                    let height = process_results(confs, |mut confs| {
+                        confs.find_map(|conf| {
+                            let conf = TxConfRow::from_row(conf);
+                            let blockhash: BlockHash = deserialize(&conf.key.blockhash).unwrap();
+                            self.pending_heights.get(&blockhash).copied().or_else(|| {
+                                headers
+                                    .header_by_blockhash(&blockhash)
+                                    .map(|h| h.height() as u32)
+                            })
+                        })
+                    })?;
+                    Ok(height.map(|height| (txid, height)))
+                })
+                .filter_map(Result::transpose)
+                .collect()
+        })
     }
 
     pub fn fetch_from(&mut self, from: FetchFrom) {
@@ -362,7 +1560,21 @@ impl Indexer {
 }
 
 impl ChainQuery {
-    pub fn new(store: Arc<Store>, daemon: Arc<Daemon>, config: &Config, metrics: &Metrics) -> Self {
+    pub fn new(
+        store: Arc<Store>,
+        daemon: Arc<Daemon>,
+        config: &Config,
+        pools: &ThreadPools,
+        metrics: &Metrics,
+    ) -> Self {
+        if store.existence_filter.is_some() {
+            let store = Arc::clone(&store);
+            spawn_thread("existence_filter", move || {
+                if let Err(e) = store.load_existence_filter() {
+                    warn!("failed loading the existence filter: {}", e.display_chain());
+                }
+            });
+        }
         ChainQuery {
             store,
             daemon,
@@ -372,6 +1584,13 @@ impl ChainQuery {
                 HistogramOpts::new("query_duration", "Index query duration (in seconds)"),
                 &["name"],
             ),
+            aggregate_cache: AggregateCache::new(config.aggregate_cache_mb << 20, metrics),
+            stats_cache: CacheMetrics::new(metrics, "stats"),
+            utxo_cache: CacheMetrics::new(metrics, "utxo"),
+            #[cfg(not(feature = "liquid"))]
+            ledger_cache: LedgerCache::new(config.ledger_cache_mb << 20, metrics),
+            pool: pools.query.clone(),
+            load: pools.load.clone(),
         }
     }
 
@@ -383,51 +1602,95 @@ impl ChainQuery {
         &self.store
     }
 
+    // Whether the scripthash may have confirmed history, or certainly has none according to the
+    // existence filter
+    fn may_be_used(&self, scripthash: &[u8]) -> bool {
+        self.store
+            .existence_filter
+            .as_ref()
+            .map_or(true, |filter| filter.may_exist(scripthash))
+    }
+
+    /// The pool running the parallel lookups of the queries
+    pub fn pool(&self) -> &ThreadPool {
+        &self.pool
+    }
+
+    pub fn load(&self) -> &LoadMonitor {
+        &self.load
+    }
+
     fn start_timer(&self, name: &str) -> HistogramTimer {
         self.duration.with_label_values(&[name]).start_timer()
     }
 
-    pub fn get_block_txids(&self, hash: &BlockHash) -> Option<Vec<Txid>> {
+    pub fn get_block_txids(&self, hash: &BlockHash) -> Result<Option<Vec<Txid>>> {
         let _timer = self.start_timer("get_block_txids");
         if self.light_mode {
             // TODO fetch block as binary from REST API instead of as hex
-            let mut blockinfo = self.daemon.getblock_raw(hash, 1).ok()?;
-            Some(serde_json::from_value(blockinfo["tx"].take()).unwrap())
+            let mut blockinfo = match self.daemon.getblock_raw(hash, 1) {
+                Ok(blockinfo) => blockinfo,
+                Err(_) => return Ok(None),
+            };
+            Ok(Some(
+                serde_json::from_value(blockinfo["tx"].take()).unwrap(),
+            ))
         } else {
-            self.store
+            let key = BlockRow::txids_key(full_hash(&hash[..]));
+            Ok(self
+                .store
                 .txstore_db
-                .get(&BlockRow::txids_key(full_hash(&hash[..])))
-                .map(|val| bincode::deserialize_little(&val).expect("failed to parse block txids"))
+                .get(&key)?
+                .map(|val| self.store.checksums.verify("block txids", &key, val))
+                .transpose()?
+                .map(|val| bincode::deserialize_little(&val).expect("failed to parse block txids")))
         }
     }
 
-    pub fn get_block_meta(&self, hash: &BlockHash) -> Option<BlockMeta> {
+    pub fn get_block_meta(&self, hash: &BlockHash) -> Result<Option<BlockMeta>> {
         let _timer = self.start_timer("get_block_meta");
 
         if self.light_mode {
-            let blockinfo = self.daemon.getblock_raw(hash, 1).ok()?;
-            Some(serde_json::from_value(blockinfo).unwrap())
+            let blockinfo = match self.daemon.getblock_raw(hash, 1) {
+                Ok(blockinfo) => blockinfo,
+                Err(_) => return Ok(None),
+            };
+            Ok(Some(serde_json::from_value(blockinfo).unwrap()))
         } else {
-            self.store
+            let key = BlockRow::meta_key(full_hash(&hash[..]));
+            Ok(self
+                .store
                 .txstore_db
-                .get(&BlockRow::meta_key(full_hash(&hash[..])))
-                .map(|val| bincode::deserialize_little(&val).expect("failed to parse BlockMeta"))
+                .get(&key)?
+                .map(|val| self.store.checksums.verify("block metadata", &key, val))
+                .transpose()?
+                .map(|val| bincode::deserialize_little(&val).expect("failed to parse BlockMeta")))
         }
     }
 
-    pub fn get_block_raw(&self, hash: &BlockHash) -> Option<Vec<u8>> {
+    pub fn get_block_raw(&self, hash: &BlockHash) -> Result<Option<Vec<u8>>> {
         let _timer = self.start_timer("get_block_raw");
 
         if self.light_mode {
-            let blockval = self.daemon.getblock_raw(hash, 0).ok()?;
+            let blockval = match self.daemon.getblock_raw(hash, 0) {
+                Ok(blockval) => blockval,
+                Err(_) => return Ok(None),
+            };
             let blockhex = blockval
                 .as_str()
                 .expect("valid block from Flokicoin daemon");
-            Some(Vec::from_hex(blockhex).expect("valid block from Flokicoin daemon"))
+            Ok(Some(
+                Vec::from_hex(blockhex).expect("valid block from Flokicoin daemon"),
+            ))
         } else {
-            let entry = self.header_by_hash(hash)?;
-            let meta = self.get_block_meta(hash)?;
-            let txids = self.get_block_txids(hash)?;
+            let entry = match self.header_by_hash(hash) {
+                Some(entry) => entry,
+                None => return Ok(None),
+            };
+            let (meta, txids) = match (self.get_block_meta(hash)?, self.get_block_txids(hash)?) {
+                (Some(meta), Some(txids)) => (meta, txids),
+                _ => return Ok(None),
+            };
 
             // Reconstruct the raw block using the header and txids,
             // as <raw header><tx count varint><raw txs>
@@ -438,10 +1701,13 @@ impl ChainQuery {
 
             for txid in txids {
                 // we don't need to provide the blockhash because we know we're not in light mode
-                raw.append(&mut self.lookup_raw_txn(&txid, None)?);
+                match self.lookup_raw_txn(&txid, None)? {
+                    Some(mut rawtx) => raw.append(&mut rawtx),
+                    None => return Ok(None),
+                }
             }
 
-            Some(raw)
+            Ok(Some(raw))
         }
     }
 
@@ -455,14 +1721,17 @@ impl ChainQuery {
         self.store.indexed_headers.read().unwrap().get_mtp(height)
     }
 
-    pub fn get_block_with_meta(&self, hash: &BlockHash) -> Option<BlockHeaderMeta> {
+    pub fn get_block_with_meta(&self, hash: &BlockHash) -> Result<Option<BlockHeaderMeta>> {
         let _timer = self.start_timer("get_block_with_meta");
-        let header_entry = self.header_by_hash(hash)?;
-        Some(BlockHeaderMeta {
-            meta: self.get_block_meta(hash)?,
+        let header_entry = match self.header_by_hash(hash) {
+            Some(header_entry) => header_entry,
+            None => return Ok(None),
+        };
+        Ok(self.get_block_meta(hash)?.map(|meta| BlockHeaderMeta {
+            meta,
             mtp: self.get_mtp(header_entry.height()),
             header_entry,
-        })
+        }))
     }
 
     pub fn history_iter_scan(&self, code: u8, hash: &[u8], start_height: usize) -> ScanIterator {
@@ -484,7 +1753,10 @@ impl ChainQuery {
         scripthash: &[u8],
         last_seen_txid: Option<&Txid>,
         limit: usize,
-    ) -> Vec<(Transaction, BlockId)> {
+    ) -> Result<Vec<(Transaction, BlockId)>> {
+        if !self.may_be_used(scripthash) {
+            return Ok(vec![]);
+        }
         // scripthash lookup
         self._history(b'H', scripthash, last_seen_txid, limit)
     }
@@ -495,66 +1767,378 @@ impl ChainQuery {
         hash: &[u8],
         last_seen_txid: Option<&Txid>,
         limit: usize,
-    ) -> Vec<(Transaction, BlockId)> {
+    ) -> Result<Vec<(Transaction, BlockId)>> {
         let _timer_scan = self.start_timer("history");
-        let txs_conf = self
+        let txids = self
             .history_iter_scan_reverse(code, hash)
-            .map(|row| TxHistoryRow::from_row(row).get_txid())
-            // XXX: unique() requires keeping an in-memory list of all txids, can we avoid that?
-            .unique()
-            // TODO seek directly to last seen tx without reading earlier rows
-            .skip_while(|txid| {
-                // skip until we reach the last_seen_txid
-                last_seen_txid.map_or(false, |last_seen_txid| last_seen_txid != txid)
-            })
-            .skip(match last_seen_txid {
-                Some(_) => 1, // skip the last_seen_txid itself
-                None => 0,
+            .map_ok(|row| TxHistoryRow::from_row(row).get_txid());
+        self.history_page(txids, last_seen_txid, limit)
+    }
+
+    /// Like `history()`, keeping the transactions matching the `filter`. The scan of the history
+    /// rows is bounded by the heights, and the net value moved by each transaction is computed
+    /// from its funding and spending rows while scanning, before looking up the transactions.
+    pub fn history_filtered(
+        &self,
+        scripthash: &[u8],
+        last_seen_txid: Option<&Txid>,
+        limit: usize,
+        filter: &HistoryFilter,
+    ) -> Result<Vec<(Transaction, BlockId)>> {
+        if !filter.by_height() && !filter.by_value() {
+            return self.history(scripthash, last_seen_txid, limit);
+        }
+        if !self.may_be_used(scripthash) {
+            return Ok(vec![]);
+        }
+        let _timer_scan = self.start_timer("history_filtered");
+        let from_height = filter.from_height.unwrap_or(0);
+        // the reverse scan starts from the last row below the height following `to_height`
+        let scan_end = match filter.to_height {
+            Some(to_height) if to_height < std::u32::MAX as usize => {
+                TxHistoryRow::prefix_height(b'H', scripthash, to_height as u32 + 1)
+            }
+            _ => TxHistoryRow::prefix_end(b'H', scripthash),
+        };
+        let rows = self
+            .store
+            .history_db
+            .iter_scan_reverse(&TxHistoryRow::filter(b'H', scripthash), &scan_end)
+            .map_ok(TxHistoryRow::from_row)
+            .take_while(move |row| {
+                row.as_ref()
+                    .map_or(true, |row| row.key.confirmed_height as usize >= from_height)
+            });
+
+        #[cfg(not(feature = "liquid"))]
+        if filter.by_value() {
+            let mut rows = rows.peekable();
+            // the rows of a transaction aren't next to each other, but all have the same height
+            let txids = std::iter::from_fn(move || {
+                let first = match rows.next()? {
+                    Ok(row) => row,
+                    Err(e) => return Some(vec![Err(e)]),
+                };
+                let height = first.key.confirmed_height;
+                let mut infos = vec![first.key.txinfo];
+                while let Some(Ok(row)) =
+                    rows.next_if(|row| matches!(row, Ok(row) if row.key.confirmed_height == height))
+                {
+                    infos.push(row.key.txinfo);
+                }
+                Some(
+                    net_deltas(infos.iter())
+                        .into_iter()
+                        .map(Ok)
+                        .collect::<Vec<_>>(),
+                )
             })
-            .filter_map(|txid| self.tx_confirming_block(&txid).map(|b| (txid, b)))
-            .take(limit)
-            .collect::<Vec<(Txid, BlockId)>>();
+            .flatten()
+            .filter_ok(|(_, delta)| filter.matches(*delta))
+            .map_ok(|(txid, _)| txid);
+            return self.history_page(txids, last_seen_txid, limit);
+        }
+
+        self.history_page(rows.map_ok(|row| row.get_txid()), last_seen_txid, limit)
+    }
 
-        self.lookup_txns(&txs_conf)
-            .expect("failed looking up txs in history index")
+    // The confirmed transactions among `txids` following `last_seen_txid`, up to `limit` of them
+    fn history_page(
+        &self,
+        txids: impl Iterator<Item = Result<Txid>>,
+        last_seen_txid: Option<&Txid>,
+        limit: usize,
+    ) -> Result<Vec<(Transaction, BlockId)>> {
+        let txs_conf = process_results(txids, |txids| {
+            let txids = txids
+                // XXX: unique() requires keeping an in-memory list of all txids, can we avoid that?
+                .unique()
+                // TODO seek directly to last seen tx without reading earlier rows
+                .skip_while(|txid| {
+                    // skip until we reach the last_seen_txid
+                    last_seen_txid.map_or(false, |last_seen_txid| last_seen_txid != txid)
+                })
+                .skip(match last_seen_txid {
+                    Some(_) => 1, // skip the last_seen_txid itself
+                    None => 0,
+                });
+            self.confirmed(txids, limit)
+        })??;
+
+        Ok(self
+            .lookup_txns(&txs_conf)
+            .chain_err(|| "failed looking up txs in history index")?
             .into_iter()
             .zip(txs_conf)
             .map(|(tx, (_, blockid))| (tx, blockid))
-            .collect()
+            .collect())
     }
 
-    pub fn history_txids(&self, scripthash: &[u8], limit: usize) -> Vec<(Txid, BlockId)> {
+    /// The confirmed history of a scripthash within `from_height..=to_height`, oldest first and in
+    /// block order
+    #[cfg(not(feature = "liquid"))]
+    pub fn history_deltas(
+        &self,
+        scripthash: &[u8],
+        from_height: usize,
+        to_height: usize,
+    ) -> impl Iterator<Item = Result<HistoryDelta>> + '_ {
+        let mut rows = self
+            .history_iter_scan(b'H', scripthash, from_height)
+            .map_ok(TxHistoryRow::from_row)
+            .take_while(move |row| {
+                row.as_ref()
+                    .map_or(true, |row| row.key.confirmed_height as usize <= to_height)
+            })
+            .peekable();
+        std::iter::from_fn(move || {
+            let first = match rows.next()? {
+                Ok(row) => row,
+                Err(e) => return Some(vec![Err(e)]),
+            };
+            let height = first.key.confirmed_height;
+            let mut block_rows = vec![first];
+            while let Some(Ok(row)) =
+                rows.next_if(|row| matches!(row, Ok(row) if row.key.confirmed_height == height))
+            {
+                block_rows.push(row);
+            }
+            Some(match self.block_deltas(height, block_rows) {
+                Ok(deltas) => deltas.into_iter().map(Ok).collect(),
+                Err(e) => vec![Err(e)],
+            })
+        })
+        .flatten()
+    }
+
+    // The net value moved by each transaction of the history rows of the block at `height`
+    #[cfg(not(feature = "liquid"))]
+    fn block_deltas(&self, height: u32, rows: Vec<TxHistoryRow>) -> Result<Vec<HistoryDelta>> {
+        let _timer = self.start_timer("history_deltas");
+        let mut deltas: Vec<HistoryDelta> = vec![];
+        let mut positions: HashMap<Txid, usize> = HashMap::new();
+        for row in rows {
+            let txid = row.get_txid();
+            let delta = row.key.txinfo.value_delta();
+            if let Some(&pos) = positions.get(&txid) {
+                deltas[pos].delta += delta;
+            } else if let Some(blockid) = self
+                .tx_confirming_block(&txid)?
+                // drop the rows of transactions that were confirmed again at a different
+                // height after a reorg
+                .filter(|blockid| blockid.height == height as usize)
+            {
+                positions.insert(txid, deltas.len());
+                deltas.push(HistoryDelta {
+                    txid,
+                    blockid,
+                    delta,
+                });
+            }
+        }
+        // the history rows of a block are ordered by txid, not by their position in the block
+        if deltas.len() > 1 {
+            if let Some(txids) = self.get_block_txids(&deltas[0].blockid.hash)? {
+                let order: HashMap<Txid, usize> = txids
+                    .into_iter()
+                    .enumerate()
+                    .map(|(i, txid)| (txid, i))
+                    .collect();
+                deltas.sort_by_key(|d| order.get(&d.txid).copied());
+            }
+        }
+        Ok(deltas)
+    }
+
+    /// The ledger entries of a scripthash following the `after` sequence number, once the ledger
+    /// is brought up to date with the best chain
+    #[cfg(not(feature = "liquid"))]
+    pub fn ledger(&self, scripthash: &[u8], after: u64, limit: usize) -> Result<Vec<LedgerEntry>> {
+        let entries = {
+            let _lock = self.ledger_cache.lock(scripthash);
+            let added = self.update_ledger(scripthash)?;
+            self.ledger_cache
+                .used(&self.store.cache_db, scripthash, added)?;
+            let _timer = self.start_timer("ledger");
+            self.store
+                .cache_db
+                .iter_scan_from(
+                    &LedgerRow::filter(scripthash),
+                    &LedgerRow::key(scripthash, after.saturating_add(1)),
+                )
+                .map_ok(|row| LedgerRow::entry(&row.value))
+                .take(limit)
+                .collect::<Result<Vec<LedgerEntry>>>()?
+        };
+        self.ledger_cache.evict(&self.store.cache_db, scripthash);
+        Ok(entries)
+    }
+
+    // Append the entries of the history confirmed since the last update, preceded by the
+    // reversals of the entries of the blocks orphaned since then. The live (not reversed) entries
+    // are ordered by height, so the orphaned ones are found by scanning the ledger backwards up to
+    // the first live entry still in the best chain. Returns the size of the rows written, the
+    // ledger must be locked.
+    #[cfg(not(feature = "liquid"))]
+    fn update_ledger(&self, scripthash: &[u8]) -> Result<usize> {
+        let _timer = self.start_timer("ledger_update");
+        let tip_height = self.best_height();
+
+        let mut last: Option<(u64, i64)> = None; // the sequence number and balance of the last entry
+        let mut reversed: HashSet<u64> = HashSet::new();
+        let mut orphaned: Vec<LedgerEntry> = vec![];
+        let mut synced_height: Option<u32> = None;
+        let scan = self.store.cache_db.iter_scan_reverse(
+            &LedgerRow::filter(scripthash),
+            &LedgerRow::key(scripthash, u64::MAX),
+        );
+        for row in scan {
+            let entry = LedgerRow::entry(&row?.value);
+            last.get_or_insert((entry.seq, entry.balance));
+            if let Some(seq) = entry.reverses {
+                reversed.insert(seq);
+            } else if reversed.contains(&entry.seq) {
+                continue;
+            } else if self.height_by_hash(&entry.block_hash).is_some() {
+                synced_height = Some(entry.height);
+                break;
+            } else {
+                orphaned.push(entry);
+            }
+        }
+
+        let (mut seq, mut balance) = last.unwrap_or((0, 0));
+        let mut entries = vec![];
+        for entry in orphaned.into_iter().rev() {
+            seq += 1;
+            balance -= entry.amount;
+            entries.push(LedgerEntry {
+                seq,
+                kind: LedgerEntryKind::Reversal,
+                amount: -entry.amount,
+                balance,
+                reverses: Some(entry.seq),
+                ..entry
+            });
+        }
+        let from_height = synced_height.map_or(0, |height| height as usize + 1);
+        for delta in self.history_deltas(scripthash, from_height, tip_height) {
+            let delta = delta?;
+            if delta.delta == 0 {
+                continue;
+            }
+            seq += 1;
+            balance += delta.delta;
+            entries.push(LedgerEntry {
+                seq,
+                kind: if delta.delta > 0 {
+                    LedgerEntryKind::Funding
+                } else {
+                    LedgerEntryKind::Spending
+                },
+                txid: delta.txid,
+                height: delta.blockid.height as u32,
+                block_hash: delta.blockid.hash,
+                time: delta.blockid.time,
+                amount: delta.delta,
+                balance,
+                reverses: None,
+            });
+        }
+
+        let rows: Vec<DBRow> = entries
+            .iter()
+            .map(|entry| LedgerRow::new(scripthash, entry).into_row())
+            .collect();
+        let size = rows.iter().map(|row| row.key.len() + row.value.len()).sum();
+        if !rows.is_empty() {
+            self.store.cache_db.write(rows, DBFlush::Enable)?;
+        }
+        Ok(size)
+    }
+
+    pub fn history_txids(&self, scripthash: &[u8], limit: usize) -> Result<Vec<(Txid, BlockId)>> {
+        if !self.may_be_used(scripthash) {
+            return Ok(vec![]);
+        }
         // scripthash lookup
         self._history_txids(b'H', scripthash, limit)
     }
 
-    fn _history_txids(&self, code: u8, hash: &[u8], limit: usize) -> Vec<(Txid, BlockId)> {
+    fn _history_txids(&self, code: u8, hash: &[u8], limit: usize) -> Result<Vec<(Txid, BlockId)>> {
         let _timer = self.start_timer("history_txids");
-        self.history_iter_scan(code, hash, 0)
-            .map(|row| TxHistoryRow::from_row(row).get_txid())
-            .unique()
-            .filter_map(|txid| self.tx_confirming_block(&txid).map(|b| (txid, b)))
+        let txids = self
+            .history_iter_scan(code, hash, 0)
+            .map_ok(|row| TxHistoryRow::from_row(row).get_txid());
+        process_results(txids, |txids| self.confirmed(txids.unique(), limit))?
+    }
+
+    // Up to `limit` of the `txids` confirmed in the best chain, along with their block
+    fn confirmed(
+        &self,
+        txids: impl Iterator<Item = Txid>,
+        limit: usize,
+    ) -> Result<Vec<(Txid, BlockId)>> {
+        txids
+            .map(|txid| -> Result<Option<(Txid, BlockId)>> {
+                Ok(self.tx_confirming_block(&txid)?.map(|b| (txid, b)))
+            })
+            .filter_map(Result::transpose)
             .take(limit)
             .collect()
     }
 
+    /// Up to `limit` transactions of the history of a scripthash confirmed from `from_height`
+    /// on, in the order of `history_txids()`. With `after`, a transaction confirmed at
+    /// `from_height`, they follow it instead (or are `None` if it isn't part of the history).
+    pub fn history_txids_from(
+        &self,
+        scripthash: &[u8],
+        from_height: usize,
+        after: Option<&Txid>,
+        limit: usize,
+    ) -> Result<Option<Vec<(Txid, BlockId)>>> {
+        if !self.may_be_used(scripthash) {
+            return Ok(after.map_or(Some(vec![]), |_| None));
+        }
+        let _timer = self.start_timer("history_txids_from");
+        let rows = self
+            .history_iter_scan(b'H', scripthash, from_height)
+            .map_ok(TxHistoryRow::from_row)
+            .map_ok(|row| (row.key.confirmed_height as usize, row.get_txid()));
+        process_results(rows, |rows| {
+            let mut txids = rows.unique_by(|(_, txid)| *txid);
+            if let Some(after) = after {
+                // the rows of a height are ordered by txid, the previous page ended within them
+                let found = txids
+                    .by_ref()
+                    .take_while(|(height, _)| *height == from_height)
+                    .any(|(_, txid)| txid == *after);
+                if !found {
+                    return Ok(None);
+                }
+            }
+            self.confirmed(txids.map(|(_, txid)| txid), limit).map(Some)
+        })?
+    }
+
     // TODO: avoid duplication with stats/stats_delta?
     pub fn utxo(&self, scripthash: &[u8], limit: usize) -> Result<Vec<Utxo>> {
+        if !self.may_be_used(scripthash) {
+            return Ok(vec![]);
+        }
         let _timer = self.start_timer("utxo");
 
-        // get the last known utxo set and the blockhash it was updated for.
-        // invalidates the cache if the block was orphaned.
+        // get the last known utxo set of the best chain and the height it was updated for.
         let cache: Option<(UtxoMap, usize)> = self
-            .store
-            .cache_db
-            .get(&UtxoCacheRow::key(scripthash))
-            .map(|c| bincode::deserialize_little(&c).unwrap())
-            .and_then(|(utxos_cache, blockhash)| {
-                self.height_by_hash(&blockhash)
-                    .map(|height| (utxos_cache, height))
-            })
+            .cached_utxos(scripthash)?
             .map(|(utxos_cache, height)| (from_utxo_cache(utxos_cache, self), height));
         let had_cache = cache.is_some();
+        if had_cache {
+            self.utxo_cache.hit();
+        } else {
+            self.utxo_cache.miss();
+        }
 
         // update utxo set with new transactions since
         let (newutxos, lastblock, processed_items) = cache.map_or_else(
@@ -565,24 +2149,24 @@ impl ChainQuery {
         // save updated utxo set to cache
         if let Some(lastblock) = lastblock {
             if had_cache || processed_items > MIN_HISTORY_ITEMS_TO_CACHE {
-                self.store.cache_db.write(
-                    vec![UtxoCacheRow::new(scripthash, &newutxos, &lastblock).into_row()],
-                    DBFlush::Enable,
-                );
+                match self.cache_utxos(scripthash, &newutxos, &lastblock) {
+                    Err(e) if !e.is_read_only() => warn!("failed caching the utxo set: {}", e),
+                    _ => (),
+                }
             }
         }
 
         // format as Utxo objects
-        Ok(newutxos
+        newutxos
             .into_iter()
             .map(|(outpoint, (blockid, value))| {
                 // in elements/liquid chains, we have to lookup the txo in order to get its
                 // associated asset. the asset information could be kept in the db history rows
                 // alongside the value to avoid this.
                 #[cfg(feature = "liquid")]
-                let txo = self.lookup_txo(&outpoint).expect("missing utxo");
+                let txo = self.lookup_txo(&outpoint)?.chain_err(|| "missing utxo")?;
 
-                Utxo {
+                Ok(Utxo {
                     txid: outpoint.txid,
                     vout: outpoint.vout,
                     value,
@@ -594,9 +2178,83 @@ impl ChainQuery {
                     nonce: txo.nonce,
                     #[cfg(feature = "liquid")]
                     witness: txo.witness,
+                })
+            })
+            .collect()
+    }
+
+    // The latest cached utxo set of the scripthash updated for a block of the best chain, and the
+    // height of that block. The sets of orphaned blocks are removed on the way.
+    fn cached_utxos(&self, scripthash: &[u8]) -> Result<Option<(CachedUtxoMap, usize)>> {
+        let mut stale_keys = vec![];
+        let rows = self.store.cache_db.iter_scan_reverse(
+            &UtxoCacheRow::filter(scripthash),
+            &UtxoCacheRow::prefix_end(scripthash),
+        );
+        let cached = process_results(rows, |mut rows| {
+            rows.find_map(|row| {
+                // the rows of the former layout, without a height, are removed too
+                if row.key.len() == UtxoCacheRow::KEY_LEN {
+                    let (utxos_cache, blockhash): (CachedUtxoMap, BlockHash) =
+                        bincode::deserialize_little(&row.value).unwrap();
+                    if let Some(height) = self.height_by_hash(&blockhash) {
+                        return Some((utxos_cache, height));
+                    }
                 }
+                stale_keys.push(row.key);
+                None
             })
-            .collect())
+        })?;
+        if !stale_keys.is_empty() {
+            self.utxo_cache.evicted(stale_keys.len());
+            match self.store.cache_db.delete(&stale_keys) {
+                Err(e) if !e.is_read_only() => {
+                    warn!("failed removing the orphaned utxo sets: {}", e)
+                }
+                _ => (),
+            }
+        }
+        Ok(cached)
+    }
+
+    // Caches the utxo set of the scripthash up to `lastblock`, keeping the previous sets of the
+    // last `UTXO_CACHE_REORG_DEPTH` blocks and the newest one past them
+    fn cache_utxos(&self, scripthash: &[u8], utxos: &UtxoMap, lastblock: &BlockId) -> Result<()> {
+        let height = lastblock.height as u32;
+        let mut past_depth = false;
+        let dropped_keys: Vec<Bytes> = self
+            .store
+            .cache_db
+            .iter_scan_reverse(
+                &UtxoCacheRow::filter(scripthash),
+                &UtxoCacheRow::key(scripthash, height.saturating_sub(1)),
+            )
+            .map_ok(|row| row.key)
+            .filter_ok(|key| match UtxoCacheRow::height(key) {
+                Some(cached_height) if cached_height + UTXO_CACHE_REORG_DEPTH > height => false,
+                Some(_) if !past_depth => {
+                    past_depth = true;
+                    false
+                }
+                _ => true,
+            })
+            .collect::<Result<_>>()?;
+
+        let row = UtxoCacheRow::new(scripthash, utxos, lastblock).into_row();
+        self.store.cache_db.write(vec![row], DBFlush::Enable)?;
+        self.store.cache_db.delete(&dropped_keys)
+    }
+
+    /// Whether the utxo set of the scripthash is cached, to be kept up to date as new blocks
+    /// touch it
+    pub fn has_cached_utxos(&self, scripthash: &[u8]) -> Result<bool> {
+        Ok(self
+            .store
+            .cache_db
+            .iter_scan(&UtxoCacheRow::filter(scripthash))
+            .next()
+            .transpose()?
+            .is_some())
     }
 
     fn utxo_delta(
@@ -605,23 +2263,26 @@ impl ChainQuery {
         init_utxos: UtxoMap,
         start_height: usize,
         limit: usize,
-    ) -> Result<(UtxoMap, Option<BlockHash>, usize)> {
+    ) -> Result<(UtxoMap, Option<BlockId>, usize)> {
         let _timer = self.start_timer("utxo_delta");
         let history_iter = self
             .history_iter_scan(b'H', scripthash, start_height)
-            .map(TxHistoryRow::from_row)
-            .filter_map(|history| {
-                self.tx_confirming_block(&history.get_txid())
-                    .map(|b| (history, b))
-            });
+            .map(|row| -> Result<_> {
+                let history = TxHistoryRow::from_row(row?);
+                Ok(self
+                    .tx_confirming_block(&history.get_txid())?
+                    .map(|b| (history, b)))
+            })
+            .filter_map(Result::transpose);
 
         let mut utxos = init_utxos;
         let mut processed_items = 0;
         let mut lastblock = None;
 
-        for (history, blockid) in history_iter {
+        for history in history_iter {
+            let (history, blockid) = history?;
             processed_items += 1;
-            lastblock = Some(blockid.hash);
+            lastblock = Some(blockid.clone());
 
             match history.key.txinfo {
                 TxHistoryInfo::Funding(ref info) => {
@@ -644,38 +2305,57 @@ impl ChainQuery {
         Ok((utxos, lastblock, processed_items))
     }
 
-    pub fn stats(&self, scripthash: &[u8]) -> ScriptStats {
+    pub fn stats(&self, scripthash: &[u8]) -> Result<ScriptStats> {
+        if !self.may_be_used(scripthash) {
+            return Ok(ScriptStats::default());
+        }
         let _timer = self.start_timer("stats");
 
+        // served from the stats maintained at index time when they are up-to-date
+        match self.indexed_stats(scripthash) {
+            Ok(Some(stats)) => return Ok(stats),
+            Ok(None) => (),
+            Err(e) => warn!("failed reading the indexed stats: {}", e),
+        }
+
         // get the last known stats and the blockhash they are updated for.
         // invalidates the cache if the block was orphaned.
         let cache: Option<(ScriptStats, usize)> = self
             .store
             .cache_db
-            .get(&StatsCacheRow::key(scripthash))
+            .get(&StatsCacheRow::key(scripthash))?
             .map(|c| bincode::deserialize_little(&c).unwrap())
             .and_then(|(stats, blockhash)| {
-                self.height_by_hash(&blockhash)
-                    .map(|height| (stats, height))
+                let height = self.height_by_hash(&blockhash);
+                if height.is_none() {
+                    self.stats_cache.evicted(1);
+                }
+                height.map(|height| (stats, height))
             });
+        if cache.is_some() {
+            self.stats_cache.hit();
+        } else {
+            self.stats_cache.miss();
+        }
 
         // update stats with new transactions since
         let (newstats, lastblock) = cache.map_or_else(
             || self.stats_delta(scripthash, ScriptStats::default(), 0),
             |(oldstats, blockheight)| self.stats_delta(scripthash, oldstats, blockheight + 1),
-        );
+        )?;
 
         // save updated stats to cache
         if let Some(lastblock) = lastblock {
             if newstats.funded_txo_count + newstats.spent_txo_count > MIN_HISTORY_ITEMS_TO_CACHE {
-                self.store.cache_db.write(
-                    vec![StatsCacheRow::new(scripthash, &newstats, &lastblock).into_row()],
-                    DBFlush::Enable,
-                );
+                let row = StatsCacheRow::new(scripthash, &newstats, &lastblock).into_row();
+                match self.store.cache_db.write(vec![row], DBFlush::Enable) {
+                    Err(e) if !e.is_read_only() => warn!("failed caching the stats: {}", e),
+                    _ => (),
+                }
             }
         }
 
-        newstats
+        Ok(newstats)
     }
 
     fn stats_delta(
@@ -683,24 +2363,27 @@ impl ChainQuery {
         scripthash: &[u8],
         init_stats: ScriptStats,
         start_height: usize,
-    ) -> (ScriptStats, Option<BlockHash>) {
+    ) -> Result<(ScriptStats, Option<BlockHash>)> {
         let _timer = self.start_timer("stats_delta"); // TODO: measure also the number of txns processed.
         let history_iter = self
             .history_iter_scan(b'H', scripthash, start_height)
-            .map(TxHistoryRow::from_row)
-            .filter_map(|history| {
-                self.tx_confirming_block(&history.get_txid())
+            .map(|row| -> Result<_> {
+                let history = TxHistoryRow::from_row(row?);
+                Ok(self
+                    .tx_confirming_block(&history.get_txid())?
                     // drop history entries that were previously confirmed in a re-orged block and later
                     // confirmed again at a different height
                     .filter(|blockid| blockid.height == history.key.confirmed_height as usize)
-                    .map(|blockid| (history, blockid))
-            });
+                    .map(|blockid| (history, blockid)))
+            })
+            .filter_map(Result::transpose);
 
         let mut stats = init_stats;
         let mut seen_txids = HashSet::new();
         let mut lastblock = None;
 
-        for (history, blockid) in history_iter {
+        for history in history_iter {
+            let (history, blockid) = history?;
             if lastblock != Some(blockid.hash) {
                 seen_txids.clear();
             }
@@ -708,50 +2391,293 @@ impl ChainQuery {
             if seen_txids.insert(history.get_txid()) {
                 stats.tx_count += 1;
             }
+            stats.add_txo(&history.key.txinfo);
 
-            match history.key.txinfo {
-                #[cfg(not(feature = "liquid"))]
-                TxHistoryInfo::Funding(ref info) => {
-                    stats.funded_txo_count += 1;
-                    stats.funded_txo_sum += info.value;
-                }
+            lastblock = Some(blockid.hash);
+        }
 
-                #[cfg(not(feature = "liquid"))]
-                TxHistoryInfo::Spending(ref info) => {
-                    stats.spent_txo_count += 1;
-                    stats.spent_txo_sum += info.value;
-                }
+        Ok((stats, lastblock))
+    }
 
-                #[cfg(feature = "liquid")]
-                TxHistoryInfo::Funding(_) => {
-                    stats.funded_txo_count += 1;
-                }
+    /// The confirmed stats and activity of a scripthash, as served by the address endpoints
+    pub fn address_summary(
+        &self,
+        scripthash: &[u8],
+    ) -> Result<(ScriptStats, Option<ScriptActivity>)> {
+        if !self.may_be_used(scripthash) {
+            return Ok((ScriptStats::default(), None));
+        }
+        let (generation, tip) = self.best_hash_generation();
+        let cache_db = &self.store.cache_db;
+        let params = scripthash.to_lower_hex_string();
+        self.aggregate_cache.get_or_compute(
+            cache_db,
+            generation,
+            &tip,
+            "address_summary",
+            &params,
+            || Ok((self.stats(scripthash)?, self.activity(scripthash)?)),
+        )
+    }
 
-                #[cfg(feature = "liquid")]
-                TxHistoryInfo::Spending(_) => {
-                    stats.spent_txo_count += 1;
-                }
+    /// Returns the blocks where the scripthash was first and last seen active (funded or spent),
+    /// ignoring activity recorded in blocks that have since been orphaned.
+    pub fn activity(&self, scripthash: &[u8]) -> Result<Option<ScriptActivity>> {
+        if !self.may_be_used(scripthash) {
+            return Ok(None);
+        }
+        let _timer = self.start_timer("activity");
+        let to_blockid = |row: DBRow| {
+            let row = ActivityRow::from_row(row);
+            let blockhash: BlockHash = deserialize(&row.blockhash).expect("invalid blockhash");
+            self.blockid_by_hash(&blockhash)
+        };
 
-                #[cfg(feature = "liquid")]
-                TxHistoryInfo::Issuing(_)
-                | TxHistoryInfo::Burning(_)
-                | TxHistoryInfo::Pegin(_)
-                | TxHistoryInfo::Pegout(_) => unreachable!(),
+        let first_seen = process_results(
+            self.store
+                .history_db
+                .iter_scan(&ActivityRow::filter(scripthash)),
+            |mut rows| rows.find_map(to_blockid),
+        )?;
+        let last_seen = process_results(
+            self.store.history_db.iter_scan_reverse(
+                &ActivityRow::filter(scripthash),
+                &ActivityRow::prefix_end(scripthash),
+            ),
+            |mut rows| rows.find_map(to_blockid),
+        )?;
+
+        Ok(first_seen
+            .zip(last_seen)
+            .map(|(first_seen, last_seen)| ScriptActivity {
+                first_seen,
+                last_seen,
+            }))
+    }
+
+    // The stats of the scripthash maintained at index time, or None when they aren't available:
+    // for databases indexed before they were introduced, and when they were last updated by a
+    // block that has since been orphaned.
+    fn indexed_stats(&self, scripthash: &[u8]) -> Result<Option<ScriptStats>> {
+        if !self.store.script_stats {
+            return Ok(None);
+        }
+        match self
+            .store
+            .history_db
+            .get(&ScriptStatsRow::key(scripthash))?
+        {
+            None => Ok(Some(ScriptStats::default())),
+            Some(value) => {
+                let row = ScriptStatsRow::from_value(full_hash(scripthash), &value)?;
+                Ok(self.height_by_hash(&row.blockhash()?).map(|_| row.stats))
             }
+        }
+    }
 
-            lastblock = Some(blockid.hash);
+    /// Returns whether each of the scripthashes was ever funded or spent from in the best chain.
+    pub fn scripthashes_used(&self, scripthashes: &[FullHash]) -> Result<Vec<bool>> {
+        let _timer = self.start_timer("scripthashes_used");
+        // only the scripthashes passing the existence filter are looked up
+        let maybe_used: Vec<bool> = scripthashes
+            .iter()
+            .map(|scripthash| self.may_be_used(&scripthash[..]))
+            .collect();
+        let mut used = self
+            .store
+            .history_db
+            .multi_get(
+                scripthashes
+                    .iter()
+                    .zip(&maybe_used)
+                    .filter(|(_, maybe_used)| **maybe_used)
+                    .map(|(scripthash, _)| UsedRow::key(scripthash)),
+            )
+            .into_iter()
+            .map(|res| res.map(|value| value.is_some()));
+        maybe_used
+            .into_iter()
+            .map(|maybe_used| -> Result<bool> { Ok(maybe_used && used.next().unwrap()?) })
+            .collect()
+    }
+
+    // The aggregates of the best chain up to `height`, connecting the stats rows of its blocks
+    // (skipping those of the orphaned blocks)
+    fn chain_stats(&self, height: usize) -> Result<ChainStats> {
+        let _timer = self.start_timer("chain_stats");
+        let mut chain_stats = ChainStats::new();
+        let headers = self.store.indexed_headers.read().unwrap();
+        let mut rows = self.store.history_db.iter_scan(&BlockStatsRow::filter());
+        let mut next_row = rows.next().transpose()?.map(BlockStatsRow::from_row);
+        for entry in headers.iter().take(height + 1) {
+            let blockhash = full_hash(&entry.hash()[..]);
+            let mut stats = None;
+            // skip rows of lower heights or of orphaned blocks
+            while let Some(row) = next_row.as_ref() {
+                if row.key.height as usize > entry.height() {
+                    break;
+                }
+                if row.key.height as usize == entry.height() && row.key.blockhash == blockhash {
+                    stats = Some(parse_block_stats(&row.value)?);
+                }
+                next_row = rows.next().transpose()?.map(BlockStatsRow::from_row);
+            }
+            // blocks indexed before the stats rows were introduced have no row
+            chain_stats.connect(&stats.unwrap_or_default());
+        }
+        Ok(chain_stats)
+    }
+
+    fn get_block_stats(&self, height: u32, blockhash: &BlockHash) -> Result<BlockStats> {
+        Ok(self
+            .lookup_block_stats(height, blockhash)?
+            .unwrap_or_default())
+    }
+
+    fn lookup_block_stats(&self, height: u32, blockhash: &BlockHash) -> Result<Option<BlockStats>> {
+        let key = BlockStatsKey {
+            code: b'G',
+            height,
+            blockhash: full_hash(&blockhash[..]),
+        };
+        self.store
+            .history_db
+            .get(&bincode::serialize_big(&key).unwrap())?
+            .map(|val| parse_block_stats(&val))
+            .transpose()
+    }
+
+    /// The aggregates of a block of the best chain, missing for the blocks indexed before they
+    /// were introduced
+    pub fn block_stats(&self, hash: &BlockHash) -> Result<Option<BlockStats>> {
+        match self.height_by_hash(hash) {
+            Some(height) => self.lookup_block_stats(height as u32, hash),
+            None => Ok(None),
         }
+    }
+
+    /// Value-weighted age distribution of the unspent outputs at the chain tip
+    pub fn utxo_age(&self) -> Result<Vec<UtxoAgeBucket>> {
+        let _timer = self.start_timer("utxo_age");
+        let (generation, tip) = self.best_hash_generation();
+        let cache_db = &self.store.cache_db;
+        self.aggregate_cache
+            .get_or_compute(cache_db, generation, &tip, "utxo_age", "", || {
+                let chain_stats = self.chain_stats(self.best_height())?;
+                let headers = self.store.indexed_headers.read().unwrap();
+                let tip_time = headers
+                    .header_by_blockhash(headers.tip())
+                    .map_or(0, |entry| entry.header().time);
+                Ok(utxo_age_buckets(
+                    chain_stats.unspent_by_height(),
+                    tip_time,
+                    |height| {
+                        headers
+                            .header_by_height(height)
+                            .map_or(tip_time, |entry| entry.header().time)
+                    },
+                ))
+            })
+    }
+
+    /// Multisig spends aggregated over the last `window` blocks
+    pub fn multisig_usage(&self, window: usize) -> Result<Vec<MultisigUsage>> {
+        let _timer = self.start_timer("multisig_usage");
+        let (generation, tip, blocks) = {
+            let headers = self.store.indexed_headers.read().unwrap();
+            let start = headers.len().saturating_sub(window);
+            let blocks = headers
+                .iter()
+                .skip(start)
+                .map(|entry| (entry.height() as u32, *entry.hash()))
+                .collect::<Vec<_>>();
+            (self.store.chain_generation(), *headers.tip(), blocks)
+        };
+        let cache_db = &self.store.cache_db;
+        let params = window.to_string();
+        self.aggregate_cache.get_or_compute(
+            cache_db,
+            generation,
+            &tip,
+            "multisig_usage",
+            &params,
+            || {
+                let stats = blocks
+                    .iter()
+                    .map(|(height, hash)| self.get_block_stats(*height, hash))
+                    .collect::<Result<Vec<_>>>()?;
+                Ok(multisig_usage(stats.iter()))
+            },
+        )
+    }
+
+    /// Cumulative issued and burned supply as of the given height (or the tip)
+    pub fn supply(&self, height: Option<usize>) -> Result<Option<(usize, Supply)>> {
+        let _timer = self.start_timer("supply");
+        let (generation, tip) = self.best_hash_generation();
+        let height = height.unwrap_or_else(|| self.best_height());
+        let cache_db = &self.store.cache_db;
+        let params = height.to_string();
+        let supply = self.aggregate_cache.get_or_compute(
+            cache_db,
+            generation,
+            &tip,
+            "supply",
+            &params,
+            || Ok(self.chain_stats(height)?.supply_at(height)),
+        )?;
+        Ok(supply.map(|supply| (height, supply)))
+    }
+
+    /// Burns confirmed since `from_height`, in chain order. Returns at least `limit` entries
+    /// (when available), but never splits the burns of a single block across pages.
+    pub fn burns(&self, from_height: usize, limit: usize) -> Result<Vec<Burn>> {
+        let _timer = self.start_timer("burns");
+        let (generation, tip) = self.best_hash_generation();
+        let cache_db = &self.store.cache_db;
+        let params = format!("{}:{}", from_height, limit);
+        self.aggregate_cache
+            .get_or_compute(cache_db, generation, &tip, "burns", &params, || {
+                self.scan_burns(from_height, limit)
+            })
+    }
 
-        (stats, lastblock)
+    fn scan_burns(&self, from_height: usize, limit: usize) -> Result<Vec<Burn>> {
+        let mut burns: Vec<Burn> = vec![];
+        let rows = self.store.history_db.iter_scan_from(
+            &BurnRow::filter(),
+            &BurnRow::prefix_height(from_height as u32),
+        );
+        for row in rows {
+            let row = BurnRow::from_row(row?);
+            let height = row.key.height as usize;
+            if burns.len() >= limit && burns.last().map_or(true, |b| b.status.height != height) {
+                break;
+            }
+            let txid: Txid = deserialize(&row.key.txid).expect("cannot parse Txid");
+            // drop burns that were confirmed in a re-orged block
+            if let Some(status) = self
+                .tx_confirming_block(&txid)?
+                .filter(|blockid| blockid.height == height)
+            {
+                burns.push(Burn {
+                    txid,
+                    vout: row.key.vout as u32,
+                    value: row.key.value,
+                    status,
+                });
+            }
+        }
+        Ok(burns)
     }
 
-    pub fn address_search(&self, prefix: &str, limit: usize) -> Vec<String> {
+    pub fn address_search(&self, prefix: &str, limit: usize) -> Result<Vec<String>> {
         let _timer_scan = self.start_timer("address_search");
         self.store
             .history_db
             .iter_scan(&addr_search_filter(prefix))
             .take(limit)
-            .map(|row| std::str::from_utf8(&row.key[1..]).unwrap().to_string())
+            .map_ok(|row| std::str::from_utf8(&row.key[1..]).unwrap().to_string())
             .collect()
     }
 
@@ -783,6 +2709,23 @@ impl ChainQuery {
             .cloned()
     }
 
+    pub fn header_by_time(&self, time: u32) -> Option<HeaderEntry> {
+        self.store
+            .indexed_headers
+            .read()
+            .unwrap()
+            .header_by_time(time)
+            .cloned()
+    }
+
+    pub fn height_by_time(&self, time: u32) -> usize {
+        self.store
+            .indexed_headers
+            .read()
+            .unwrap()
+            .height_by_time(time)
+    }
+
     pub fn hash_by_height(&self, height: usize) -> Option<BlockHash> {
         self.store
             .indexed_headers
@@ -819,6 +2762,12 @@ impl ChainQuery {
         *self.store.indexed_headers.read().unwrap().tip()
     }
 
+    // The chain generation and the best blockhash, read together
+    fn best_hash_generation(&self) -> (u64, BlockHash) {
+        let headers = self.store.indexed_headers.read().unwrap();
+        (self.store.chain_generation(), *headers.tip())
+    }
+
     pub fn best_header(&self) -> HeaderEntry {
         let headers = self.store.indexed_headers.read().unwrap();
         headers
@@ -828,47 +2777,82 @@ impl ChainQuery {
     }
 
     // TODO: can we pass txids as a "generic iterable"?
-    // TODO: should also use a custom ThreadPoolBuilder?
     pub fn lookup_txns(&self, txids: &[(Txid, BlockId)]) -> Result<Vec<Transaction>> {
         let _timer = self.start_timer("lookup_txns");
-        txids
-            .par_iter()
-            .map(|(txid, blockid)| {
-                self.lookup_txn(txid, Some(&blockid.hash))
-                    .chain_err(|| "missing tx")
-            })
-            .collect::<Result<Vec<Transaction>>>()
+        self.pool.install(|| {
+            txids
+                .par_iter()
+                .map(|(txid, blockid)| {
+                    self.lookup_txn(txid, Some(&blockid.hash))?
+                        .chain_err(|| "missing tx")
+                })
+                .collect::<Result<Vec<Transaction>>>()
+        })
+    }
+
+    /// The transactions of a block, or None if some of them are missing
+    pub fn lookup_block_txns(
+        &self,
+        blockhash: &BlockHash,
+        txids: &[Txid],
+    ) -> Result<Option<Vec<Transaction>>> {
+        let _timer = self.start_timer("lookup_block_txns");
+        self.pool.install(|| {
+            txids
+                .par_iter()
+                .map(|txid| self.lookup_txn(txid, Some(blockhash)))
+                .collect()
+        })
     }
 
-    pub fn lookup_txn(&self, txid: &Txid, blockhash: Option<&BlockHash>) -> Option<Transaction> {
+    pub fn lookup_txn(
+        &self,
+        txid: &Txid,
+        blockhash: Option<&BlockHash>,
+    ) -> Result<Option<Transaction>> {
         let _timer = self.start_timer("lookup_txn");
-        self.lookup_raw_txn(txid, blockhash).map(|rawtx| {
+        Ok(self.lookup_raw_txn(txid, blockhash)?.map(|rawtx| {
             let txn: Transaction = deserialize(&rawtx).expect("failed to parse Transaction");
             assert_eq!(*txid, txn.compute_txid());
             txn
-        })
+        }))
     }
 
-    pub fn lookup_raw_txn(&self, txid: &Txid, blockhash: Option<&BlockHash>) -> Option<Bytes> {
+    pub fn lookup_raw_txn(
+        &self,
+        txid: &Txid,
+        blockhash: Option<&BlockHash>,
+    ) -> Result<Option<Bytes>> {
         let _timer = self.start_timer("lookup_raw_txn");
 
         if self.light_mode {
-            let queried_blockhash =
-                blockhash.map_or_else(|| self.tx_confirming_block(txid).map(|b| b.hash), |_| None);
-            let blockhash = blockhash.or_else(|| queried_blockhash.as_ref())?;
+            let blockhash = match blockhash {
+                Some(blockhash) => *blockhash,
+                None => match self.tx_confirming_block(txid)? {
+                    Some(blockid) => blockid.hash,
+                    None => return Ok(None),
+                },
+            };
             // TODO fetch transaction as binary from REST API instead of as hex
-            let txval = self
-                .daemon
-                .gettransaction_raw(txid, blockhash, false)
-                .ok()?;
+            let txval = match self.daemon.gettransaction_raw(txid, &blockhash, false) {
+                Ok(txval) => txval,
+                Err(_) => return Ok(None),
+            };
             let txhex = txval.as_str().expect("valid tx from Flokicoin daemon");
-            Some(Bytes::from_hex(txhex).expect("valid tx from Flokicoin daemon"))
+            Ok(Some(
+                Bytes::from_hex(txhex).expect("valid tx from Flokicoin daemon"),
+            ))
         } else {
-            self.store.txstore_db.get(&TxRow::key(&txid[..]))
+            let key = TxRow::key(&txid[..]);
+            self.store
+                .txstore_db
+                .get(&key)?
+                .map(|val| self.store.checksums.verify("transaction", &key, val))
+                .transpose()
         }
     }
 
-    pub fn lookup_txo(&self, outpoint: &OutPoint) -> Option<TxOut> {
+    pub fn lookup_txo(&self, outpoint: &OutPoint) -> Result<Option<TxOut>> {
         let _timer = self.start_timer("lookup_txo");
         lookup_txo(&self.store.txstore_db, outpoint)
     }
@@ -878,36 +2862,55 @@ impl ChainQuery {
         lookup_txos(&self.store.txstore_db, outpoints)
     }
 
-    pub fn lookup_spend(&self, outpoint: &OutPoint) -> Option<SpendingInput> {
+    pub fn lookup_spend(&self, outpoint: &OutPoint) -> Result<Option<SpendingInput>> {
         let _timer = self.start_timer("lookup_spend");
-        self.store
+        for edge in self
+            .store
             .history_db
             .iter_scan(&TxEdgeRow::filter(&outpoint))
-            .map(TxEdgeRow::from_row)
-            .find_map(|edge| {
-                let txid: Txid = deserialize(&edge.key.spending_txid).unwrap();
-                self.tx_confirming_block(&txid).map(|b| SpendingInput {
+        {
+            let edge = TxEdgeRow::from_row(edge?);
+            let txid: Txid = deserialize(&edge.key.spending_txid).unwrap();
+            if let Some(blockid) = self.tx_confirming_block(&txid)? {
+                return Ok(Some(SpendingInput {
                     txid,
                     vin: edge.key.spending_vin as u32,
-                    confirmed: Some(b),
-                })
-            })
+                    confirmed: Some(blockid),
+                }));
+            }
+        }
+        Ok(None)
+    }
+
+    /// The txid of the confirmed segwit transaction with the given wtxid
+    #[cfg(not(feature = "liquid"))]
+    pub fn lookup_wtxid(&self, wtxid: &Wtxid) -> Result<Option<Txid>> {
+        let _timer = self.start_timer("lookup_wtxid");
+        Ok(self
+            .store
+            .txstore_db
+            .get(&WtxidRow::key(&wtxid[..]))?
+            .map(|txid| deserialize(&txid).expect("failed to parse Txid")))
     }
 
-    pub fn tx_confirming_block(&self, txid: &Txid) -> Option<BlockId> {
+    pub fn tx_confirming_block(&self, txid: &Txid) -> Result<Option<BlockId>> {
         let _timer = self.start_timer("tx_confirming_block");
         let headers = self.store.indexed_headers.read().unwrap();
-        self.store
+        let confs = self
+            .store
             .txstore_db
-            .iter_scan(&TxConfRow::filter(&txid[..]))
-            .map(TxConfRow::from_row)
-            // header_by_blockhash only returns blocks that are part of the best chain,
-            // or None for orphaned blocks.
-            .filter_map(|conf| {
-                headers.header_by_blockhash(&deserialize(&conf.key.blockhash).unwrap())
-            })
-            .next()
-            .map(BlockId::from)
+            .iter_scan(&TxConfRow::filter(&txid[..]));
+        process_results(confs, |confs| {
+            confs
+                .map(TxConfRow::from_row)
+                // header_by_blockhash only returns blocks that are part of the best chain,
+                // or None for orphaned blocks.
+                .filter_map(|conf| {
+                    headers.header_by_blockhash(&deserialize(&conf.key.blockhash).unwrap())
+                })
+                .next()
+                .map(BlockId::from)
+        })
     }
 
     pub fn get_block_status(&self, hash: &BlockHash) -> BlockStatus {
@@ -922,26 +2925,31 @@ impl ChainQuery {
             .header_by_blockhash(hash)
             .map_or_else(BlockStatus::orphaned, |header| {
                 BlockStatus::confirmed(
-                    header.height(),
+                    header,
                     headers
                         .header_by_height(header.height() + 1)
                         .map(|h| *h.hash()),
+                    headers.len() - 1,
                 )
             })
     }
 
     #[cfg(not(feature = "liquid"))]
-    pub fn get_merkleblock_proof(&self, txid: &Txid) -> Option<MerkleBlock> {
+    pub fn get_merkleblock_proof(&self, txid: &Txid) -> Result<Option<MerkleBlock>> {
         let _timer = self.start_timer("get_merkleblock_proof");
-        let blockid = self.tx_confirming_block(txid)?;
-        let headerentry = self.header_by_hash(&blockid.hash)?;
-        let block_txids = self.get_block_txids(&blockid.hash)?;
-
-        Some(MerkleBlock::from_header_txids_with_predicate(
-            headerentry.header(),
-            &block_txids,
-            |t| t == txid,
-        ))
+        let blockid = match self.tx_confirming_block(txid)? {
+            Some(blockid) => blockid,
+            None => return Ok(None),
+        };
+        let headerentry = match self.header_by_hash(&blockid.hash) {
+            Some(headerentry) => headerentry,
+            None => return Ok(None),
+        };
+        Ok(self.get_block_txids(&blockid.hash)?.map(|block_txids| {
+            MerkleBlock::from_header_txids_with_predicate(headerentry.header(), &block_txids, |t| {
+                t == txid
+            })
+        }))
     }
 
     #[cfg(feature = "liquid")]
@@ -950,30 +2958,108 @@ impl ChainQuery {
         asset_id: &AssetId,
         last_seen_txid: Option<&Txid>,
         limit: usize,
-    ) -> Vec<(Transaction, BlockId)> {
+    ) -> Result<Vec<(Transaction, BlockId)>> {
         self._history(b'I', &asset_id.into_inner()[..], last_seen_txid, limit)
     }
 
     #[cfg(feature = "liquid")]
-    pub fn asset_history_txids(&self, asset_id: &AssetId, limit: usize) -> Vec<(Txid, BlockId)> {
+    pub fn asset_history_txids(
+        &self,
+        asset_id: &AssetId,
+        limit: usize,
+    ) -> Result<Vec<(Txid, BlockId)>> {
         self._history_txids(b'I', &asset_id.into_inner()[..], limit)
     }
 }
 
-fn load_blockhashes(db: &DB, prefix: &[u8]) -> HashSet<BlockHash> {
+// Moves the databases of the legacy `<db-dir>/<network>/newindex/` layout up to
+// `<db-dir>/<network>/`
+fn migrate_legacy_layout(path: &Path) {
+    let legacy_path = path.join("newindex");
+    if !legacy_path.is_dir() {
+        // `--db-dir` pointing to the network directory of a legacy layout
+        if let Some(parent) = path.parent() {
+            if parent.join("newindex").is_dir() && !path.join("txstore").exists() {
+                panic!(
+                    "found a legacy index at {}, --db-dir should point to the parent directory",
+                    parent.display()
+                );
+            }
+        }
+        return;
+    }
+
+    for name in &["txstore", "history", "cache"] {
+        let (from, to) = (legacy_path.join(name), path.join(name));
+        if !from.exists() {
+            continue;
+        }
+        if to.exists() {
+            panic!(
+                "both {} and {} exist, remove the stale one",
+                from.display(),
+                to.display()
+            );
+        }
+        info!("migrating {} to {}", from.display(), to.display());
+        fs::rename(&from, &to).unwrap_or_else(|e| {
+            panic!(
+                "failed moving {} to {}: {}",
+                from.display(),
+                to.display(),
+                e
+            )
+        });
+    }
+    if let Err(e) = fs::remove_dir(&legacy_path) {
+        warn!("failed removing {}: {}", legacy_path.display(), e);
+    }
+}
+
+fn load_blockhashes(db: &DB, prefix: &[u8]) -> Result<HashSet<BlockHash>> {
     db.iter_scan(prefix)
-        .map(BlockRow::from_row)
-        .map(|r| deserialize(&r.key.hash).expect("failed to parse BlockHash"))
+        .map_ok(BlockRow::from_row)
+        .map_ok(|r| deserialize(&r.key.hash).expect("failed to parse BlockHash"))
         .collect()
 }
 
-fn load_blockheaders(db: &DB) -> HashMap<BlockHash, BlockHeader> {
+// The synced tip is only saved once the blocks up to it are added and indexed. When that isn't
+// the case (or headers are missing), the highest block of the tip's chain that is, if any.
+fn consistent_tip(
+    tip_hash: BlockHash,
+    headers_map: &HashMap<BlockHash, BlockHeader>,
+    added_blockhashes: &HashSet<BlockHash>,
+    indexed_blockhashes: &HashSet<BlockHash>,
+) -> Option<BlockHash> {
+    let mut chain = vec![];
+    let mut blockhash = tip_hash;
+    while blockhash != *DEFAULT_BLOCKHASH {
+        let header = headers_map.get(&blockhash)?;
+        chain.push(blockhash);
+        blockhash = header.prev_blockhash;
+    }
+    chain
+        .into_iter()
+        .rev()
+        .take_while(|hash| added_blockhashes.contains(hash) && indexed_blockhashes.contains(hash))
+        .last()
+}
+
+fn load_blockheaders(
+    db: &DB,
+    checksums: &ChecksumVerifier,
+) -> Result<HashMap<BlockHash, BlockHeader>> {
     db.iter_scan(&BlockRow::header_filter())
-        .map(BlockRow::from_row)
-        .map(|r| {
+        .map(|row| {
+            let row = row?;
+            let value = checksums.verify("block header", &row.key, row.value)?;
+            let r = BlockRow::from_row(DBRow {
+                key: row.key,
+                value,
+            });
             let key: BlockHash = deserialize(&r.key.hash).expect("failed to parse BlockHash");
             let value: BlockHeader = deserialize(&r.value).expect("failed to parse BlockHeader");
-            (key, value)
+            Ok((key, value))
         })
         .collect()
 }
@@ -998,11 +3084,13 @@ fn add_blocks(block_entries: &[BlockEntry], iconfig: &IndexerConfig) -> Vec<DBRo
             }
 
             if !iconfig.light_mode {
-                rows.push(BlockRow::new_txids(blockhash, &txids).into_row());
-                rows.push(BlockRow::new_meta(blockhash, &BlockMeta::from(b)).into_row());
+                let txids_row = BlockRow::new_txids(blockhash, &txids).into_row();
+                let meta_row = BlockRow::new_meta(blockhash, &BlockMeta::from(b)).into_row();
+                rows.push(checksummed(txids_row, iconfig));
+                rows.push(checksummed(meta_row, iconfig));
             }
 
-            rows.push(BlockRow::new_header(&b).into_row());
+            rows.push(checksummed(BlockRow::new_header(&b).into_row(), iconfig));
             rows.push(BlockRow::new_done(blockhash).into_row()); // mark block as "added"
             rows
         })
@@ -1010,6 +3098,14 @@ fn add_blocks(block_entries: &[BlockEntry], iconfig: &IndexerConfig) -> Vec<DBRo
         .collect()
 }
 
+// Appends a checksum to the value of a transaction or block row, if the txstore has them
+fn checksummed(mut row: DBRow, iconfig: &IndexerConfig) -> DBRow {
+    if iconfig.checksums {
+        row.value = append_checksum(row.value);
+    }
+    row
+}
+
 fn add_transaction(
     txid: Txid,
     tx: &Transaction,
@@ -1020,7 +3116,16 @@ fn add_transaction(
     rows.push(TxConfRow::new(txid, blockhash).into_row());
 
     if !iconfig.light_mode {
-        rows.push(TxRow::new(txid, tx).into_row());
+        rows.push(checksummed(TxRow::new(txid, tx).into_row(), iconfig));
+    }
+
+    #[cfg(not(feature = "liquid"))]
+    {
+        let wtxid = tx.compute_wtxid();
+        // only differs for transactions with witness data
+        if wtxid[..] != txid[..] {
+            rows.push(WtxidRow::new(&wtxid, &txid).into_row());
+        }
     }
 
     let txid = full_hash(&txid[..]);
@@ -1052,45 +3157,139 @@ fn lookup_txos(txstore_db: &DB, outpoints: BTreeSet<OutPoint>) -> Result<HashMap
         .zip(outpoints)
         .map(|(res, outpoint)| {
             let txo = res
-                .unwrap()
+                .chain_err(|| format!("failed reading txo {}", outpoint))?
                 .ok_or_else(|| format!("missing txo {}", outpoint))?;
             Ok((outpoint, deserialize(&txo).expect("failed to parse TxOut")))
         })
         .collect()
 }
 
-fn lookup_txo(txstore_db: &DB, outpoint: &OutPoint) -> Option<TxOut> {
-    txstore_db
-        .get(&TxOutRow::key(&outpoint))
-        .map(|val| deserialize(&val).expect("failed to parse TxOut"))
+fn lookup_txo(txstore_db: &DB, outpoint: &OutPoint) -> Result<Option<TxOut>> {
+    Ok(txstore_db
+        .get(&TxOutRow::key(&outpoint))?
+        .map(|val| deserialize(&val).expect("failed to parse TxOut")))
+}
+
+// The stats of the transactions of a block that touched each scripthash
+struct BlockScriptStats {
+    blockhash: FullHash,
+    stats: HashMap<FullHash, ScriptStats>,
 }
 
 fn index_blocks(
     block_entries: &[BlockEntry],
     previous_txos_map: &HashMap<OutPoint, TxOut>,
+    funding_heights: &HashMap<Txid, u32>,
     iconfig: &IndexerConfig,
-) -> Vec<DBRow> {
-    block_entries
+) -> (Vec<DBRow>, Vec<BlockScriptStats>) {
+    let (rows, script_stats): (Vec<Vec<DBRow>>, Vec<BlockScriptStats>) = block_entries
         .par_iter() // serialization is CPU-intensive
         .map(|b| {
             let mut rows = vec![];
+            let mut touched = HashMap::new();
+            let height = b.entry.height() as u32;
+            let blockhash = full_hash(&b.entry.hash()[..]);
             for tx in &b.block.txdata {
-                let height = b.entry.height() as u32;
-                index_transaction(tx, height, previous_txos_map, &mut rows, iconfig);
+                index_transaction(
+                    tx,
+                    height,
+                    previous_txos_map,
+                    &mut rows,
+                    &mut touched,
+                    iconfig,
+                );
             }
-            rows.push(BlockRow::new_done(full_hash(&b.entry.hash()[..])).into_row()); // mark block as "indexed"
-            rows
+            for scripthash in touched.keys() {
+                rows.push(ActivityRow::new(*scripthash, height, blockhash).into_row());
+                rows.push(UsedRow::new(*scripthash).into_row());
+            }
+            let stats = block_stats(b, previous_txos_map, funding_heights, iconfig);
+            rows.push(BlockStatsRow::new(height, blockhash, &stats).into_row());
+            rows.push(BlockRow::new_done(blockhash).into_row()); // mark block as "indexed"
+            let script_stats = BlockScriptStats {
+                blockhash,
+                stats: touched,
+            };
+            (rows, script_stats)
         })
-        .flatten()
-        .collect()
+        .unzip();
+    (rows.into_iter().flatten().collect(), script_stats)
+}
+
+// persist block aggregates:
+//      G{height}{blockhash} → {stats}
+fn block_stats(
+    b: &BlockEntry,
+    previous_txos_map: &HashMap<OutPoint, TxOut>,
+    funding_heights: &HashMap<Txid, u32>,
+    iconfig: &IndexerConfig,
+) -> BlockStats {
+    let height = b.entry.height() as u32;
+    let mut stats = BlockStats::default();
+    let mut spent_by_height = BTreeMap::<u32, u64>::new();
+    let mut multisig_spends = BTreeMap::<MultisigType, u32>::new();
+    let (mut outputs_value, mut inputs_value) = (0u64, 0u64);
+
+    for tx in &b.block.txdata {
+        stats.tx_vsize += tx_vsize(tx);
+        #[cfg(not(feature = "liquid"))]
+        {
+            stats.sigops +=
+                tx.total_sigop_cost(|outpoint| previous_txos_map.get(outpoint).cloned()) as u64;
+        }
+
+        for txo in &tx.output {
+            let value = explicit_value(txo).unwrap_or(0);
+            outputs_value += value;
+            if iconfig.is_burn(txo) {
+                stats.burned_value += value;
+            } else {
+                stats.created_value += value;
+            }
+        }
+        inputs_value += tx
+            .input
+            .iter()
+            .filter(|txi| has_prevout(txi))
+            .filter_map(|txi| previous_txos_map.get(&txi.previous_output))
+            .filter_map(explicit_value)
+            .sum::<u64>();
+
+        for txi in tx.input.iter().filter(|txi| has_prevout(txi)) {
+            if let Some(multisig_type) = previous_txos_map
+                .get(&txi.previous_output)
+                .and_then(|prevout| get_multisig_type(txi, prevout))
+            {
+                *multisig_spends.entry(multisig_type).or_default() += 1;
+            }
+        }
+
+        if iconfig.utxo_age_stats {
+            for txi in tx.input.iter().filter(|txi| has_prevout(txi)) {
+                let prevout = &txi.previous_output;
+                let value = previous_txos_map.get(prevout).and_then(explicit_value);
+                // outputs created and spent within the same block are missing from the map
+                let funding_height = funding_heights.get(&prevout.txid).unwrap_or(&height);
+                *spent_by_height.entry(*funding_height).or_default() += value.unwrap_or(0);
+            }
+        }
+    }
+
+    // the fees paid by the block transactions are collected by the coinbase, so whatever
+    // remains after subtracting the spent value is newly issued
+    stats.issued_value = outputs_value.saturating_sub(inputs_value);
+    stats.spent_by_height = spent_by_height.into_iter().collect();
+    stats.multisig_spends = multisig_spends.into_iter().collect();
+    stats
 }
 
 // TODO: return an iterator?
-fn index_transaction(
+pub(super) fn index_transaction(
     tx: &Transaction,
     confirmed_height: u32,
     previous_txos_map: &HashMap<OutPoint, TxOut>,
     rows: &mut Vec<DBRow>,
+    touched: &mut HashMap<FullHash, ScriptStats>,
     iconfig: &IndexerConfig,
 ) {
     // persist history index:
@@ -1098,8 +3297,21 @@ fn index_transaction(
     //      H{funding-scripthash}{spending-height}S{spending-txid:vin}{funding-txid:vout} → ""
     // persist "edges" for fast is-this-TXO-spent check
     //      S{funding-txid:vout}{spending-txid:vin} → ""
+    // sum up the txs and txos of each scripthash, for the per-block activity rows and stats:
+    //      L{scripthash}{height} → {blockhash}
+    // persist burns (provably unspendable outputs or outputs to burn addresses):
+    //      b{height}{txid:vout}{value} → ""
     let txid = full_hash(&tx.compute_txid()[..]);
+    let mut tx_touched = HashSet::new();
     for (txo_index, txo) in tx.output.iter().enumerate() {
+        if iconfig.is_burn(txo) {
+            // skip data carriers with no value attached
+            if let Some(value) = explicit_value(txo).filter(|value| *value > 0) {
+                let burn = BurnRow::new(confirmed_height, txid, txo_index as u16, value);
+                rows.push(burn.into_row());
+            }
+        }
+
         if is_spendable(txo) || iconfig.index_unspendables {
             let history = TxHistoryRow::new(
                 &txo.script_pubkey,
@@ -1110,6 +3322,11 @@ fn index_transaction(
                     value: txo.value.amount_value(),
                 }),
             );
+            tx_touched.insert(history.key.hash);
+            touched
+                .entry(history.key.hash)
+                .or_insert_with(ScriptStats::default)
+                .add_txo(&history.key.txinfo);
             rows.push(history.into_row());
 
             if iconfig.address_search {
@@ -1138,6 +3355,11 @@ fn index_transaction(
                 value: prev_txo.value.amount_value(),
             }),
         );
+        tx_touched.insert(history.key.hash);
+        touched
+            .entry(history.key.hash)
+            .or_insert_with(ScriptStats::default)
+            .add_txo(&history.key.txinfo);
         rows.push(history.into_row());
 
         let edge = TxEdgeRow::new(
@@ -1148,6 +3370,12 @@ fn index_transaction(
         );
         rows.push(edge.into_row());
     }
+    for scripthash in tx_touched {
+        touched
+            .entry(scripthash)
+            .or_insert_with(ScriptStats::default)
+            .tx_count += 1;
+    }
 
     // Index issued assets & native asset pegins/pegouts/burns
     #[cfg(feature = "liquid")]
@@ -1219,6 +3447,33 @@ impl TxRow {
     }
 }
 
+#[cfg(not(feature = "liquid"))]
+struct WtxidRow {
+    wtxid: FullHash,
+    txid: FullHash,
+}
+
+#[cfg(not(feature = "liquid"))]
+impl WtxidRow {
+    fn new(wtxid: &Wtxid, txid: &Txid) -> WtxidRow {
+        WtxidRow {
+            wtxid: full_hash(&wtxid[..]),
+            txid: full_hash(&txid[..]),
+        }
+    }
+
+    fn key(wtxid: &[u8]) -> Bytes {
+        [b"W", wtxid].concat()
+    }
+
+    fn into_row(self) -> DBRow {
+        DBRow {
+            key: WtxidRow::key(&self.wtxid),
+            value: self.txid.to_vec(),
+        }
+    }
+}
+
 #[derive(Serialize, Deserialize)]
 struct TxConfKey {
     code: u8,
@@ -1419,6 +3674,15 @@ impl TxHistoryInfo {
         }
         .expect("cannot parse Txid")
     }
+
+    /// The value moved into (when positive) or out of (when negative) the scripthash
+    #[cfg(not(feature = "liquid"))]
+    pub fn value_delta(&self) -> i64 {
+        match self {
+            TxHistoryInfo::Funding(info) => info.value as i64,
+            TxHistoryInfo::Spending(info) => -(info.value as i64),
+        }
+    }
 }
 
 #[derive(Serialize, Deserialize)]
@@ -1548,6 +3812,229 @@ impl TxEdgeRow {
     }
 }
 
+#[derive(Serialize, Deserialize)]
+struct ActivityKey {
+    code: u8,
+    scripthash: FullHash,
+    height: u32, // MUST be serialized as big-endian (for correct scans).
+}
+
+struct ActivityRow {
+    key: ActivityKey,
+    blockhash: FullHash,
+}
+
+impl ActivityRow {
+    fn new(scripthash: FullHash, height: u32, blockhash: FullHash) -> Self {
+        ActivityRow {
+            key: ActivityKey {
+                code: b'L',
+                scripthash,
+                height,
+            },
+            blockhash,
+        }
+    }
+
+    fn filter(scripthash: &[u8]) -> Bytes {
+        [b"L", scripthash].concat()
+    }
+
+    fn prefix_end(scripthash: &[u8]) -> Bytes {
+        bincode::serialize_big(&(b'L', full_hash(scripthash), std::u32::MAX)).unwrap()
+    }
+
+    fn into_row(self) -> DBRow {
+        DBRow {
+            key: bincode::serialize_big(&self.key).unwrap(),
+            value: self.blockhash.to_vec(),
+        }
+    }
+
+    fn from_row(row: DBRow) -> Self {
+        ActivityRow {
+            key: bincode::deserialize_big(&row.key).expect("failed to deserialize ActivityKey"),
+            blockhash: full_hash(&row.value),
+        }
+    }
+}
+
+// marks a scripthash as funded or spent from at least once, for cheap existence lookups
+struct UsedRow {
+    scripthash: FullHash,
+}
+
+impl UsedRow {
+    fn new(scripthash: FullHash) -> Self {
+        UsedRow { scripthash }
+    }
+
+    fn key(scripthash: &FullHash) -> Bytes {
+        [b"E", &scripthash[..]].concat()
+    }
+
+    fn filter() -> Bytes {
+        b"E".to_vec()
+    }
+
+    fn into_row(self) -> DBRow {
+        DBRow {
+            key: UsedRow::key(&self.scripthash),
+            value: vec![],
+        }
+    }
+}
+
+// the stats of a scripthash confirmed up to the last block that touched it
+struct ScriptStatsRow {
+    scripthash: FullHash,
+    stats: ScriptStats,
+    blockhash: FullHash,
+}
+
+impl ScriptStatsRow {
+    fn new(scripthash: FullHash, stats: ScriptStats, blockhash: FullHash) -> Self {
+        ScriptStatsRow {
+            scripthash,
+            stats,
+            blockhash,
+        }
+    }
+
+    fn key(scripthash: &[u8]) -> Bytes {
+        [b"c", scripthash].concat()
+    }
+
+    fn into_row(self) -> DBRow {
+        DBRow {
+            key: ScriptStatsRow::key(&self.scripthash),
+            value: bincode::serialize_little(&(self.stats, self.blockhash)).unwrap(),
+        }
+    }
+
+    fn from_value(scripthash: FullHash, value: &[u8]) -> Result<Self> {
+        let (stats, blockhash) = bincode::deserialize_little(value)
+            .chain_err(|| "failed to deserialize ScriptStatsRow")?;
+        Ok(ScriptStatsRow {
+            scripthash,
+            stats,
+            blockhash,
+        })
+    }
+
+    fn blockhash(&self) -> Result<BlockHash> {
+        deserialize(&self.blockhash).chain_err(|| "invalid blockhash in ScriptStatsRow")
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct BurnKey {
+    code: u8,
+    height: u32, // MUST be serialized as big-endian (for correct scans).
+    txid: FullHash,
+    vout: u16,
+    value: u64,
+}
+
+struct BurnRow {
+    key: BurnKey,
+}
+
+impl BurnRow {
+    fn new(height: u32, txid: FullHash, vout: u16, value: u64) -> Self {
+        BurnRow {
+            key: BurnKey {
+                code: b'b',
+                height,
+                txid,
+                vout,
+                value,
+            },
+        }
+    }
+
+    fn filter() -> Bytes {
+        b"b".to_vec()
+    }
+
+    fn prefix_height(height: u32) -> Bytes {
+        bincode::serialize_big(&(b'b', height)).unwrap()
+    }
+
+    fn into_row(self) -> DBRow {
+        DBRow {
+            key: bincode::serialize_big(&self.key).unwrap(),
+            value: vec![],
+        }
+    }
+
+    fn from_row(row: DBRow) -> Self {
+        BurnRow {
+            key: bincode::deserialize_big(&row.key).expect("failed to deserialize BurnKey"),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, ToSchema)]
+pub struct Burn {
+    #[schema(value_type = String)]
+    pub txid: Txid,
+    pub vout: u32,
+    pub value: u64,
+    pub status: BlockId,
+}
+
+#[derive(Serialize, Deserialize)]
+struct BlockStatsKey {
+    code: u8,
+    height: u32, // MUST be serialized as big-endian (for correct scans).
+    blockhash: FullHash,
+}
+
+struct BlockStatsRow {
+    key: BlockStatsKey,
+    value: Bytes,
+}
+
+fn parse_block_stats(value: &[u8]) -> Result<BlockStats> {
+    bincode::deserialize_little(value).chain_err(|| "failed to deserialize BlockStats")
+}
+
+impl BlockStatsRow {
+    fn new(height: u32, blockhash: FullHash, stats: &BlockStats) -> Self {
+        BlockStatsRow {
+            key: BlockStatsKey {
+                code: b'G',
+                height,
+                blockhash,
+            },
+            value: bincode::serialize_little(stats).unwrap(),
+        }
+    }
+
+    fn filter() -> Bytes {
+        b"G".to_vec()
+    }
+
+    fn prefix_height(height: u32) -> Bytes {
+        bincode::serialize_big(&(b'G', height)).unwrap()
+    }
+
+    fn into_row(self) -> DBRow {
+        DBRow {
+            key: bincode::serialize_big(&self.key).unwrap(),
+            value: self.value,
+        }
+    }
+
+    fn from_row(row: DBRow) -> Self {
+        BlockStatsRow {
+            key: bincode::deserialize_big(&row.key).expect("failed to deserialize BlockStatsKey"),
+            value: row.value,
+        }
+    }
+}
+
 #[derive(Serialize, Deserialize)]
 struct ScriptCacheKey {
     code: u8,
@@ -1582,33 +4069,96 @@ impl StatsCacheRow {
     }
 }
 
+#[cfg(not(feature = "liquid"))]
+#[derive(Serialize, Deserialize)]
+struct LedgerKey {
+    code: u8,
+    scripthash: FullHash,
+    seq: u64,
+}
+
+#[cfg(not(feature = "liquid"))]
+struct LedgerRow {
+    key: LedgerKey,
+    value: Bytes,
+}
+
+#[cfg(not(feature = "liquid"))]
+impl LedgerRow {
+    fn new(scripthash: &[u8], entry: &LedgerEntry) -> Self {
+        LedgerRow {
+            key: LedgerKey {
+                code: b'L',
+                scripthash: full_hash(scripthash),
+                seq: entry.seq,
+            },
+            value: bincode::serialize_little(entry).unwrap(),
+        }
+    }
+
+    fn filter(scripthash: &[u8]) -> Bytes {
+        [b"L", scripthash].concat()
+    }
+
+    fn key(scripthash: &[u8], seq: u64) -> Bytes {
+        bincode::serialize_big(&(b'L', full_hash(scripthash), seq)).unwrap()
+    }
+
+    fn entry(value: &[u8]) -> LedgerEntry {
+        bincode::deserialize_little(value).expect("failed to parse LedgerEntry")
+    }
+
+    fn into_row(self) -> DBRow {
+        DBRow {
+            key: bincode::serialize_big(&self.key).unwrap(),
+            value: self.value,
+        }
+    }
+}
+
 type CachedUtxoMap = HashMap<(Txid, u32), (u32, Value)>; // (txid,vout) => (block_height,output_value)
 
+// the utxo set of a scripthash as of the block at `height`, one row per cached block
 struct UtxoCacheRow {
-    key: ScriptCacheKey,
+    key: Bytes,
     value: Bytes,
 }
 
 impl UtxoCacheRow {
-    fn new(scripthash: &[u8], utxos: &UtxoMap, blockhash: &BlockHash) -> Self {
+    const KEY_LEN: usize = 1 + 32 + 4;
+
+    fn new(scripthash: &[u8], utxos: &UtxoMap, lastblock: &BlockId) -> Self {
         let utxos_cache = make_utxo_cache(utxos);
 
         UtxoCacheRow {
-            key: ScriptCacheKey {
-                code: b'U',
-                scripthash: full_hash(scripthash),
-            },
-            value: bincode::serialize_little(&(utxos_cache, blockhash)).unwrap(),
+            key: UtxoCacheRow::key(scripthash, lastblock.height as u32),
+            value: bincode::serialize_little(&(utxos_cache, lastblock.hash)).unwrap(),
         }
     }
 
-    pub fn key(scripthash: &[u8]) -> Bytes {
+    fn key(scripthash: &[u8], height: u32) -> Bytes {
+        [b"U", scripthash, &height.to_be_bytes()].concat()
+    }
+
+    pub fn filter(scripthash: &[u8]) -> Bytes {
         [b"U", scripthash].concat()
     }
 
+    fn prefix_end(scripthash: &[u8]) -> Bytes {
+        UtxoCacheRow::key(scripthash, u32::MAX)
+    }
+
+    // the height of a row key, unless of the former layout
+    fn height(key: &[u8]) -> Option<u32> {
+        let height = key.get(1 + 32..).filter(|height| height.len() == 4)?;
+        let mut bytes = [0u8; 4];
+        bytes.copy_from_slice(height);
+        Some(u32::from_be_bytes(bytes))
+    }
+
     fn into_row(self) -> DBRow {
         DBRow {
-            key: bincode::serialize_little(&self.key).unwrap(),
+            key: self.key,
             value: self.value,
         }
     }
@@ -1666,11 +4216,29 @@ impl GetAmountVal for confidential::Value {
 // This is needed to bench private functions
 #[cfg(feature = "bench")]
 pub mod bench {
-    use crate::new_index::schema::IndexerConfig;
-    use crate::new_index::BlockEntry;
-    use crate::new_index::DBRow;
-    use crate::util::HeaderEntry;
-    use bitcoin::Block;
+    use std::collections::HashMap;
+    use std::sync::Arc;
+
+    use bitcoin::absolute::LockTime;
+    use bitcoin::block::{Header, Version};
+    use bitcoin::hashes::Hash;
+    use bitcoin::opcodes::OP_TRUE;
+    use bitcoin::script::Builder;
+    use bitcoin::{
+        transaction, Amount, Block, BlockHash, CompactTarget, OutPoint, Script, ScriptBuf,
+        Sequence, Transaction, TxIn, TxMerkleNode, TxOut, Txid, Witness,
+    };
+
+    use crate::config::Config;
+    use crate::daemon::Daemon;
+    use crate::metrics::Metrics;
+    use crate::new_index::schema::{
+        compute_script_hash, ChainQuery, FullHash, Indexer, IndexerConfig, Store, Utxo,
+        UtxoCacheRow,
+    };
+    use crate::new_index::{BlockEntry, DBRow, FetchFrom};
+    use crate::util::pool::ThreadPools;
+    use crate::util::{BlockId, Bytes, HeaderEntry};
 
     pub struct Data {
         block_entry: BlockEntry,
@@ -1679,12 +4247,6 @@ pub mod bench {
 
     impl Data {
         pub fn new(block: Block) -> Data {
-            let iconfig = IndexerConfig {
-                light_mode: false,
-                address_search: false,
-                index_unspendables: false,
-                network: crate::chain::Network::Regtest,
-            };
             let height = 702861;
             let hash = block.block_hash();
             let header = block.header.clone();
@@ -1696,7 +4258,7 @@ pub mod bench {
 
             Data {
                 block_entry,
-                iconfig,
+                iconfig: indexer_config(),
             }
         }
     }
@@ -1704,4 +4266,258 @@ pub mod bench {
     pub fn add_blocks(data: &Data) -> Vec<DBRow> {
         super::add_blocks(&[data.block_entry.clone()], &data.iconfig)
     }
+
+    fn indexer_config() -> IndexerConfig {
+        IndexerConfig {
+            light_mode: false,
+            address_search: false,
+            index_unspendables: false,
+            utxo_age_stats: false,
+            burn_scripts: Default::default(),
+            reorg_check_depth: 6,
+            assume_valid_height: Some(0),
+            network: crate::chain::Network::Regtest,
+            checksums: true,
+        }
+    }
+
+    /// Synthetic blocks, along with the outputs their transactions spend
+    pub struct Chain {
+        pub block_entries: Vec<BlockEntry>,
+        pub txos: HashMap<OutPoint, TxOut>,
+        funding_heights: HashMap<Txid, u32>,
+        iconfig: IndexerConfig,
+    }
+
+    impl Chain {
+        /// `block_count` blocks from the genesis, of `txs_per_block` transactions paying twice to
+        /// `script`, each spending the first output of the one before. Every transaction adds a funding and a
+        /// spending row to the history of `script`, and an output to its utxo set.
+        pub fn new(block_count: usize, txs_per_block: usize, script: &Script) -> Chain {
+            const FEE: u64 = 1_000;
+            let tx_count = (block_count * txs_per_block) as u64;
+            let mut prevout = OutPoint::new(Txid::from_byte_array([1; 32]), 0);
+            let mut value = (tx_count + 1) * FEE * 2;
+            let mut chain = Chain {
+                block_entries: vec![],
+                txos: HashMap::new(),
+                funding_heights: HashMap::new(),
+                iconfig: indexer_config(),
+            };
+            chain.txos.insert(prevout, txout(value, script));
+            chain.funding_heights.insert(prevout.txid, 0);
+
+            let mut prev_blockhash = BlockHash::all_zeros();
+            for height in 0..block_count {
+                let mut txdata = vec![coinbase(height)];
+                for _ in 0..txs_per_block {
+                    value -= FEE * 2;
+                    let tx = Transaction {
+                        version: transaction::Version::TWO,
+                        lock_time: LockTime::ZERO,
+                        input: vec![txin(prevout, ScriptBuf::new())],
+                        output: vec![txout(value, script), txout(FEE, script)],
+                    };
+                    let txid = tx.compute_txid();
+                    for (vout, output) in tx.output.iter().enumerate() {
+                        chain
+                            .txos
+                            .insert(OutPoint::new(txid, vout as u32), output.clone());
+                    }
+                    chain.funding_heights.insert(txid, height as u32);
+                    prevout = OutPoint::new(txid, 0);
+                    txdata.push(tx);
+                }
+
+                let mut block = Block {
+                    header: Header {
+                        version: Version::from_consensus(0x2000_0000),
+                        prev_blockhash,
+                        merkle_root: TxMerkleNode::all_zeros(),
+                        time: 1_600_000_000 + height as u32 * 600,
+                        bits: CompactTarget::from_consensus(0x207f_ffff),
+                        nonce: 0,
+                    },
+                    txdata,
+                };
+                block.header.merkle_root = block.compute_merkle_root().unwrap();
+                let hash = block.block_hash();
+                prev_blockhash = hash;
+                chain.block_entries.push(BlockEntry {
+                    size: block.total_size() as u32,
+                    entry: HeaderEntry::new(height, hash, block.header.clone()),
+                    block,
+                });
+            }
+            chain
+        }
+
+        pub fn txs(&self) -> impl Iterator<Item = &Transaction> {
+            self.block_entries
+                .iter()
+                .flat_map(|entry| entry.block.txdata.iter())
+        }
+    }
+
+    fn coinbase(height: usize) -> Transaction {
+        let script_sig = Builder::new().push_int(height as i64).into_script();
+        let script_pubkey = Builder::new().push_opcode(OP_TRUE).into_script();
+        Transaction {
+            version: transaction::Version::ONE,
+            lock_time: LockTime::ZERO,
+            input: vec![txin(OutPoint::null(), script_sig)],
+            output: vec![txout(50 * 100_000_000, &script_pubkey)],
+        }
+    }
+
+    fn txin(previous_output: OutPoint, script_sig: ScriptBuf) -> TxIn {
+        TxIn {
+            previous_output,
+            script_sig,
+            sequence: Sequence::MAX,
+            witness: Witness::new(),
+        }
+    }
+
+    fn txout(value: u64, script: &Script) -> TxOut {
+        TxOut {
+            value: Amount::from_sat(value),
+            script_pubkey: script.to_owned(),
+        }
+    }
+
+    pub fn index_blocks(chain: &Chain) -> Vec<DBRow> {
+        super::index_blocks(
+            &chain.block_entries,
+            &chain.txos,
+            &chain.funding_heights,
+            &chain.iconfig,
+        )
+        .0
+    }
+
+    /// The index of a synthetic chain, written to a temporary database by the `Indexer` and
+    /// queried through the `ChainQuery`
+    pub struct History {
+        query: ChainQuery,
+        scripthash: FullHash,
+        _dir: tempfile::TempDir,
+    }
+
+    impl History {
+        pub fn new(chain: &Chain, script: &Script, config: &Config) -> History {
+            let dir = tempfile::tempdir().expect("failed creating the database directory");
+            let metrics = Metrics::new(([127, 0, 0, 1], 0).into());
+            let store = Arc::new(
+                Store::open(dir.path(), config, &metrics).expect("failed opening the database"),
+            );
+            let pools = ThreadPools::new(config, &metrics);
+            let indexer = Indexer::open(
+                Arc::clone(&store),
+                FetchFrom::Bitcoind,
+                config,
+                &pools,
+                &metrics,
+            );
+            indexer
+                .add(&chain.block_entries)
+                .expect("failed adding the blocks");
+            indexer
+                .index(&chain.block_entries)
+                .expect("failed indexing the blocks");
+            store.indexed_headers.write().unwrap().apply(
+                chain
+                    .block_entries
+                    .iter()
+                    .map(|block| block.entry.clone())
+                    .collect(),
+            );
+            // the queries only use the daemon in light mode
+            let daemon = Daemon::unreachable(config.network_type, &metrics)
+                .expect("failed creating the daemon");
+            History {
+                query: ChainQuery::new(store, Arc::new(daemon), config, &pools, &metrics),
+                scripthash: compute_script_hash(script),
+                _dir: dir,
+            }
+        }
+
+        /// Drops the cached utxo set of the script, for `utxos()` to assemble it from its
+        /// history again
+        pub fn clear_utxo_cache(&self) {
+            let cache_db = &self.query.store().cache_db;
+            let keys: Vec<Bytes> = cache_db
+                .iter_scan(&UtxoCacheRow::filter(&self.scripthash))
+                .map(|row| row.unwrap().key)
+                .collect();
+            cache_db.delete(&keys).unwrap();
+        }
+    }
+
+    /// The txids of the script's history, newest first
+    pub fn history_txids(history: &History) -> Vec<(Txid, BlockId)> {
+        history
+            .query
+            .history_txids(&history.scripthash, usize::MAX)
+            .unwrap()
+    }
+
+    /// The script's utxo set, assembled from its history unless cached
+    pub fn utxos(history: &History) -> Vec<Utxo> {
+        history.query.utxo(&history.scripthash, usize::MAX).unwrap()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // the (database, prefix) of the rows documented in doc/schema.md, with no database for the
+    // rows listed before the per-database sections
+    fn documented_prefixes() -> Vec<(Option<String>, u8)> {
+        let mut db = None;
+        let mut prefixes = vec![];
+        for line in include_str!("../../doc/schema.md").lines() {
+            if let Some(heading) = line.strip_prefix("### ") {
+                db = Some(heading.trim_matches('`').to_string());
+            } else if let Some(row) = line.trim_start().strip_prefix("* `\"") {
+                prefixes.push((db.clone(), row.as_bytes()[0]));
+            }
+        }
+        prefixes
+    }
+
+    #[test]
+    fn test_key_prefixes_documented() {
+        let documented = documented_prefixes();
+        assert!(!documented.is_empty());
+        for (db, prefix) in &documented {
+            assert!(
+                KEY_PREFIXES
+                    .iter()
+                    .any(|(d, p, _)| p == prefix && *d == db.as_deref()),
+                "{:?} prefix {} is documented but missing from KEY_PREFIXES",
+                db,
+                *prefix as char
+            );
+        }
+        for (db, prefix, _) in KEY_PREFIXES {
+            assert!(
+                documented
+                    .iter()
+                    .any(|(d, p)| p == prefix && d.as_deref() == *db),
+                "{:?} prefix {} is missing from doc/schema.md",
+                db,
+                *prefix as char
+            );
+        }
+    }
+
+    #[test]
+    fn test_describe_prefix() {
+        assert_eq!(describe_prefix("cache", b'g'), "chain generation");
+        assert_eq!(describe_prefix("cache", b'U'), "scripthash utxos");
+        assert_eq!(describe_prefix("history", b'V'), "database version");
+        assert_eq!(describe_prefix("txstore", b'g'), "unknown");
+    }
 }
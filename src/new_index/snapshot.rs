@@ -0,0 +1,196 @@
+//! Dumps of the utxo set as of a past height (`--run-mode dump-utxos`), reconstructed from the
+//! index: the spendable outputs created by the blocks up to the height, minus the ones spent by
+//! them. The dump is a text file with a line per utxo, in the order the utxos were created:
+//!
+//! ```text
+//! # utxo set at height 100000 (block 0000...)
+//! <txid>:<vout>,<value>,<scriptpubkey hex>
+//! ...
+//! # 1234 utxos, 50000000000 sats, sha256 <hex>
+//! ```
+//!
+//! The last line sums up the utxos, with the SHA256 of all the lines above it, so that two dumps
+//! of the same height can be compared (and checked for truncation) by their last line alone.
+//! The values of the blinded outputs (Elements only) are left empty.
+
+use std::fs::{self, File};
+use std::io::{BufWriter, Write};
+use std::path::Path;
+use std::time::Instant;
+
+use bitcoin::hex::DisplayHex;
+use crypto::digest::Digest;
+use crypto::sha2::Sha256;
+use rayon::prelude::*;
+
+use crate::chain::{BlockHash, OutPoint, TxOut};
+use crate::errors::*;
+use crate::new_index::ChainQuery;
+use crate::util::pool::ThreadPool;
+use crate::util::{explicit_value, is_spendable};
+
+// the number of blocks whose utxos are looked up at once
+const BLOCKS_PER_BATCH: usize = 100;
+
+pub struct UtxoSnapshot {
+    pub height: usize,
+    pub blockhash: BlockHash,
+    pub count: u64,
+    pub total: u64,
+    /// The SHA256 of the dump, without its last line
+    pub checksum: String,
+}
+
+/// Writes the utxo set as of the block at `height` of the best chain to `out`, replacing the
+/// file once complete
+pub fn dump_utxos(
+    chain: &ChainQuery,
+    pool: &ThreadPool,
+    height: usize,
+    out: &Path,
+) -> Result<UtxoSnapshot> {
+    let started = Instant::now();
+    let blockhash = *chain
+        .header_by_height(height)
+        .chain_err(|| format!("height {} is above the indexed tip", height))?
+        .hash();
+
+    let tmp_path = out.with_extension("tmp");
+    let file =
+        File::create(&tmp_path).chain_err(|| format!("failed creating {}", tmp_path.display()))?;
+    let mut writer = Writer {
+        file: BufWriter::new(file),
+        sha2: Sha256::new(),
+    };
+    writer.line(&format!(
+        "# utxo set at height {} (block {})",
+        height, blockhash
+    ))?;
+
+    let (mut count, mut total) = (0, 0);
+    for from in (0..=height).step_by(BLOCKS_PER_BATCH) {
+        let to = (from + BLOCKS_PER_BATCH - 1).min(height);
+        let blocks = pool.install(|| {
+            (from..=to)
+                .into_par_iter()
+                .map(|block_height| block_utxos(chain, block_height, height))
+                .collect::<Result<Vec<_>>>()
+        })?;
+        for (outpoint, txo) in blocks.iter().flatten() {
+            writer.line(&utxo_line(outpoint, txo))?;
+            count += 1;
+            total += explicit_value(txo).unwrap_or(0);
+        }
+        if to % 10_000 < BLOCKS_PER_BATCH {
+            info!("dumped the utxos created up to height {}", to);
+        }
+    }
+
+    let checksum = writer.sha2.result_str();
+    writeln!(
+        writer.file,
+        "# {} utxos, {} sats, sha256 {}",
+        count, total, checksum
+    )
+    .and_then(|()| writer.file.flush())
+    .chain_err(|| format!("failed writing {}", tmp_path.display()))?;
+    drop(writer);
+    fs::rename(&tmp_path, out).chain_err(|| format!("failed writing {}", out.display()))?;
+
+    info!(
+        "dumped {} utxos ({} sats) as of height {} to {} in {:?}, sha256 {}",
+        count,
+        total,
+        height,
+        out.display(),
+        started.elapsed(),
+        checksum
+    );
+    Ok(UtxoSnapshot {
+        height,
+        blockhash,
+        count,
+        total,
+        checksum,
+    })
+}
+
+// The outputs created by the block at `block_height` which are still unspent at `height`
+fn block_utxos(
+    chain: &ChainQuery,
+    block_height: usize,
+    height: usize,
+) -> Result<Vec<(OutPoint, TxOut)>> {
+    let header = chain
+        .header_by_height(block_height)
+        .chain_err(|| format!("missing header at height {}", block_height))?;
+    let blockhash = header.hash();
+    let txids = chain
+        .get_block_txids(blockhash)?
+        .chain_err(|| format!("missing txids of block {}", blockhash))?;
+
+    let mut utxos = vec![];
+    for txid in txids {
+        let tx = chain
+            .lookup_txn(&txid, Some(blockhash))?
+            .chain_err(|| format!("missing transaction {}", txid))?;
+        for (vout, txo) in tx.output.into_iter().enumerate() {
+            if !is_spendable(&txo) {
+                continue;
+            }
+            let outpoint = OutPoint::new(txid, vout as u32);
+            let spent = chain
+                .lookup_spend(&outpoint)?
+                .and_then(|spend| spend.confirmed)
+                .map_or(false, |blockid| blockid.height <= height);
+            if !spent {
+                utxos.push((outpoint, txo));
+            }
+        }
+    }
+    Ok(utxos)
+}
+
+fn utxo_line(outpoint: &OutPoint, txo: &TxOut) -> String {
+    format!(
+        "{},{},{}",
+        outpoint,
+        explicit_value(txo).map_or_else(String::new, |value| value.to_string()),
+        txo.script_pubkey.as_bytes().to_lower_hex_string()
+    )
+}
+
+// Writes the lines of the dump, hashing them on the way
+struct Writer {
+    file: BufWriter<File>,
+    sha2: Sha256,
+}
+
+impl Writer {
+    fn line(&mut self, line: &str) -> Result<()> {
+        self.sha2.input_str(line);
+        self.sha2.input_str("\n");
+        writeln!(self.file, "{}", line).chain_err(|| "failed writing the utxo dump")
+    }
+}
+
+#[cfg(test)]
+#[cfg(not(feature = "liquid"))]
+mod tests {
+    use super::*;
+    use crate::chain::{Script, Txid};
+    use bitcoin::hashes::Hash;
+
+    #[test]
+    fn test_utxo_line() {
+        let outpoint = OutPoint::new(Txid::all_zeros(), 3);
+        let txo = TxOut {
+            value: bitcoin::Amount::from_sat(1500),
+            script_pubkey: Script::from(vec![0x51]),
+        };
+        assert_eq!(
+            utxo_line(&outpoint, &txo),
+            format!("{}:3,1500,51", Txid::all_zeros())
+        );
+    }
+}
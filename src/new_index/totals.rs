@@ -0,0 +1,114 @@
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use crate::errors::*;
+use crate::metrics::{CounterVec, MetricOpts, Metrics};
+use crate::new_index::db::{DBFlush, DBRow, DB};
+use crate::util::pool::LoadMonitor;
+
+// how often the totals are persisted, besides on shutdown
+const SAVE_INTERVAL: Duration = Duration::from_secs(60);
+
+/// The events counted over the lifetime of the index
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Total {
+    BlocksIndexed,
+    Reorgs,
+    TxsBroadcast,
+}
+
+const TOTALS: [Total; 3] = [Total::BlocksIndexed, Total::Reorgs, Total::TxsBroadcast];
+
+impl Total {
+    fn name(&self) -> &'static str {
+        match self {
+            Total::BlocksIndexed => "blocks_indexed",
+            Total::Reorgs => "reorgs",
+            Total::TxsBroadcast => "txs_broadcast",
+        }
+    }
+
+    fn key(&self) -> Vec<u8> {
+        [b"T", self.name().as_bytes()].concat()
+    }
+}
+
+/// Counters kept across restarts, exported by the `lifetime_total{event}` metric. They are
+/// restored from the cache db (`T{event}` rows) on startup, and persisted every minute and on
+/// shutdown, so that the dashboards keep the totals since the index was created. The events
+/// counted since the last save are lost on a crash.
+pub struct LifetimeTotals {
+    counters: CounterVec,
+    last_saved: Mutex<Instant>,
+}
+
+impl LifetimeTotals {
+    pub fn load(db: &DB, metrics: &Metrics) -> Result<Self> {
+        let totals = LifetimeTotals {
+            counters: metrics.counter_vec(
+                MetricOpts::new(
+                    "lifetime_total",
+                    "# of events since the index was created, kept across restarts, by event",
+                ),
+                &["event"],
+            ),
+            last_saved: Mutex::new(Instant::now()),
+        };
+        for total in TOTALS {
+            if let Some(value) = db.get(&total.key())? {
+                totals.inc(total, decode(&value));
+            }
+        }
+        Ok(totals)
+    }
+
+    pub fn inc(&self, total: Total, count: u64) {
+        self.counters
+            .with_label_values(&[total.name()])
+            .inc_by(count);
+    }
+
+    pub fn get(&self, total: Total) -> u64 {
+        self.counters.with_label_values(&[total.name()]).get()
+    }
+
+    /// Persists the totals, unless they were persisted less than a minute ago (or longer, while
+    /// the queries are overloaded)
+    pub fn save_if_due(&self, db: &DB, load: &LoadMonitor) -> Result<()> {
+        let mut last_saved = self.last_saved.lock().unwrap();
+        if !load.is_due("lifetime_totals", last_saved.elapsed(), SAVE_INTERVAL) {
+            return Ok(());
+        }
+        *last_saved = Instant::now();
+        self.save(db)
+    }
+
+    pub fn save(&self, db: &DB) -> Result<()> {
+        let rows = TOTALS
+            .iter()
+            .map(|total| DBRow {
+                key: total.key(),
+                value: self.get(*total).to_be_bytes().to_vec(),
+            })
+            .collect();
+        db.write(rows, DBFlush::Enable)
+            .chain_err(|| "failed persisting the lifetime totals")
+    }
+}
+
+fn decode(value: &[u8]) -> u64 {
+    let mut bytes = [0u8; 8];
+    bytes.copy_from_slice(value);
+    u64::from_be_bytes(bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_keys() {
+        assert_eq!(Total::BlocksIndexed.key(), b"Tblocks_indexed".to_vec());
+        assert_eq!(decode(&42u64.to_be_bytes()), 42);
+    }
+}
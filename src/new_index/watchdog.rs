@@ -0,0 +1,84 @@
+use std::ffi::CString;
+use std::io;
+use std::mem::MaybeUninit;
+use std::os::unix::ffi::OsStrExt;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use crate::config::Config;
+use crate::metrics::{Gauge, MetricOpts, Metrics};
+use crate::new_index::Store;
+
+/// Watches the free space of the volume holding the databases, switching the store to read-only
+/// while it is below `--min-free-disk-mb` so that indexing stops cleanly instead of failing
+/// mid-write.
+pub struct DiskWatchdog {
+    store: Arc<Store>,
+    path: PathBuf,
+    min_free: u64,
+    free_space: Gauge,
+    read_only: Gauge,
+}
+
+impl DiskWatchdog {
+    pub fn new(store: Arc<Store>, config: &Config, metrics: &Metrics) -> Self {
+        DiskWatchdog {
+            store,
+            path: config.db_path.clone(),
+            min_free: config.min_free_disk_mb * 1024 * 1024,
+            free_space: metrics.gauge(MetricOpts::new(
+                "db_free_space_bytes",
+                "Free space on the volume holding the databases (in bytes)",
+            )),
+            read_only: metrics.gauge(MetricOpts::new(
+                "db_read_only",
+                "Whether indexing is paused because of low disk space",
+            )),
+        }
+    }
+
+    /// Checks the free space, returning whether there's enough of it to keep indexing
+    pub fn check(&self) -> bool {
+        if self.min_free == 0 {
+            return true;
+        }
+        let free = match free_space(&self.path) {
+            Ok(free) => free,
+            Err(e) => {
+                warn!("failed checking the free space of {:?}: {}", self.path, e);
+                return !self.store.is_read_only();
+            }
+        };
+        self.free_space.set(free as i64);
+
+        let low = free < self.min_free;
+        if low != self.store.is_read_only() {
+            if low {
+                error!(
+                    "only {} MB left on the database volume, pausing indexing and serving read-only",
+                    free / 1024 / 1024
+                );
+            } else {
+                info!("enough free disk space again, resuming indexing");
+            }
+            self.store.set_read_only(low);
+            self.read_only.set(low as i64);
+        }
+        !low
+    }
+}
+
+/// The space available to unprivileged users on the volume holding `path`
+pub fn free_space(path: &Path) -> io::Result<u64> {
+    let c_path = CString::new(path.as_os_str().as_bytes())
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+    let mut stat = MaybeUninit::<libc::statvfs>::uninit();
+    if unsafe { libc::statvfs(c_path.as_ptr(), stat.as_mut_ptr()) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    let stat = unsafe { stat.assume_init() };
+    // the width of the fields differs across platforms
+    #[allow(clippy::unnecessary_cast)]
+    let free = stat.f_bavail as u64 * stat.f_frsize as u64;
+    Ok(free)
+}
@@ -1,9 +1,45 @@
+use std::convert::TryInto;
+use std::sync::{Arc, Mutex};
+
 use bitcoin::{hashes::Hash, BlockHash};
 use crossbeam_channel::Sender;
 
-use crate::util::spawn_thread;
+use crate::new_index::BlockLatency;
+use crate::util::supervisor::spawn_supervised;
+
+/// The latest mempool sequence number published on lokid's ZMQ `sequence` topic (with
+/// `-zmqpubsequence`), so that the mempool isn't fetched again while it didn't change
+#[derive(Default)]
+pub struct MempoolSequenceFeed {
+    sequence: Mutex<Option<u64>>,
+}
+
+impl MempoolSequenceFeed {
+    pub fn sequence(&self) -> Option<u64> {
+        *self.sequence.lock().unwrap()
+    }
 
-pub fn start(url: &str, block_hash_notify: Sender<BlockHash>) {
+    // Records a `<hash>|<label>[|<sequence>]` message
+    fn record(&self, data: &[u8]) {
+        let sequence = match data.get(32) {
+            // a transaction was added to or removed from the mempool
+            Some(b'A') | Some(b'R') => data
+                .get(33..41)
+                .map(|seq| u64::from_le_bytes(seq.try_into().unwrap())),
+            // a block was (dis)connected, which changes the mempool without reporting its
+            // sequence, unknown until the next transaction
+            _ => None,
+        };
+        *self.sequence.lock().unwrap() = sequence;
+    }
+}
+
+pub fn start(
+    url: &str,
+    block_hash_notify: Sender<BlockHash>,
+    latency: Arc<BlockLatency>,
+    mempool_sequence: Arc<MempoolSequenceFeed>,
+) {
     log::debug!("Starting ZMQ thread");
     let ctx = zmq::Context::new();
     let subscriber: zmq::Socket = ctx.socket(zmq::SUB).expect("failed creating subscriber");
@@ -15,8 +51,11 @@ pub fn start(url: &str, block_hash_notify: Sender<BlockHash>) {
     subscriber
         .set_subscribe(b"hashblock")
         .expect("failed subscribing to hashblock");
+    subscriber
+        .set_subscribe(b"sequence")
+        .expect("failed subscribing to sequence");
 
-    spawn_thread("zmq", move || loop {
+    spawn_supervised("zmq", move || loop {
         match subscriber.recv_multipart(0) {
             Ok(data) => match (data.get(0), data.get(1)) {
                 (Some(topic), Some(data)) => {
@@ -28,8 +67,14 @@ pub fn start(url: &str, block_hash_notify: Sender<BlockHash>) {
                         reversed.reverse();
                         if let Ok(block_hash) = BlockHash::from_slice(&reversed[..]) {
                             log::debug!("New block from ZMQ: {block_hash}");
+                            // the chain's own hash type, with the liquid feature
+                            latency.announced(crate::chain::BlockHash::from_byte_array(
+                                block_hash.to_byte_array(),
+                            ));
                             let _ = block_hash_notify.send(block_hash);
                         }
+                    } else if &topic[..] == b"sequence" {
+                        mempool_sequence.record(data);
                     }
                 }
                 _ => (),
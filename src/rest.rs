@@ -1,52 +1,151 @@
 use crate::chain::{
-    address, BlockHash, Network, OutPoint, Script, Sequence, Transaction, TxIn, TxMerkleNode,
+    address, ActiveChain, BlockHash, ChainParams, Network, OutPoint, Script, Transaction, TxIn,
     TxOut, Txid,
 };
-use crate::config::{Config, VERSION_STRING};
+use crate::config::{Config, EndpointGroup, ELECTRS_VERSION, FEATURES, GIT_HASH, VERSION_STRING};
+use crate::daemon::PASSTHROUGH_METHODS;
 use crate::errors;
-use crate::new_index::{compute_script_hash, Query, SpendingInput, Utxo};
+use crate::metrics::{Metrics, SlowQueryLog};
+use crate::new_index::blockstats::{MultisigUsage, UtxoAgeBucket};
+use crate::new_index::db::{ReadStats, DB_VERSION};
+use crate::new_index::{
+    compute_script_hash, BacklogStats, HistoryFilter, Query, SpendingInput, TrackerStats, Utxo,
+};
+#[cfg(not(feature = "liquid"))]
+use crate::new_index::{HistoryDelta, ScriptActivity, ScriptStats};
+use crate::util::amount::{self, AmountFormat};
 #[cfg(not(feature = "liquid"))]
 use crate::util::floki_address;
+#[cfg(not(feature = "liquid"))]
+use crate::util::interpreter;
+use crate::util::locktime;
 #[cfg(feature = "liquid")]
 use crate::util::optional_value_for_newer_blocks;
+#[cfg(not(feature = "liquid"))]
+use crate::util::price::FiatValue;
+use crate::util::ratelimit::RateLimiter;
 use crate::util::{
-    create_socket, electrum_merkle, extract_tx_prevouts, get_innerscripts, get_tx_fee, has_prevout,
-    is_coinbase, BlockHeaderMeta, BlockId, FullHash, ScriptToAddr, ScriptToAsm, TransactionStatus,
-    DEFAULT_BLOCKHASH,
+    create_socket, electrum_merkle, explicit_value, extract_tx_prevouts, get_innerscripts,
+    get_tx_fee, has_prevout, is_coinbase, is_spendable, is_valid_request_id, new_request_id,
+    proxy_protocol, systemd, tx_vsize, BlockHeaderMeta, BlockId, FullHash, ScriptToAddr,
+    ScriptToAsm, TransactionStatus, DEFAULT_BLOCKHASH,
 };
 #[cfg(not(feature = "liquid"))]
 use bitcoin::consensus::encode;
 
 use bitcoin::hashes::FromSliceError as HashError;
 use bitcoin::hex::{self, DisplayHex, FromHex, HexToBytesIter};
+use hyper::body::HttpBody;
+use hyper::server::conn::{AddrStream, Http};
 use hyper::service::{make_service_fn, service_fn};
 use hyper::{Body, Method, Response, Server, StatusCode};
 use hyperlocal::UnixServerExt;
 use tokio::sync::oneshot;
 
+use self::access_log::{AccessLog, AccessLogEntry, AccessLogSource};
+use self::aliases::Aliases;
+use self::coalesce::Coalescer;
+use self::lanes::{Lane, Lanes};
+
 use std::fs;
+use std::net::SocketAddr;
 use std::str::FromStr;
+use std::time::{Duration, Instant};
 
 use electrs_macros::trace;
 
 #[cfg(feature = "liquid")]
 use {
     crate::elements::{ebcompact::*, peg::PegoutValue, AssetSorting, IssuanceValue},
-    elements::{encode, secp256k1_zkp as zkp, AssetId},
+    elements::{encode, AssetId},
+};
+
+// The response values are defined by the API crate, except for the elements flavour of them
+#[cfg(feature = "liquid")]
+use self::liquid::{
+    AddressInfo as AddressInfoValue, Block as BlockValue, Transaction as TransactionValue,
+    TxIn as TxInValue, TxOut as TxOutValue, Utxo as UtxoValue,
+};
+#[cfg(not(feature = "liquid"))]
+use flokicoin_electrs_api::{
+    AddressInfo as AddressInfoValue, Block as BlockValue, Transaction as TransactionValue,
+    TxIn as TxInValue, TxOut as TxOutValue, Utxo as UtxoValue,
 };
+use flokicoin_electrs_api::{ApiError, TxIoPaging};
 
 use serde::Serialize;
 use serde_json;
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::num::ParseIntError;
 use std::os::unix::fs::FileTypeExt;
 use std::sync::Arc;
 use std::thread;
 use url::form_urlencoded;
+use utoipa::ToSchema;
+
+mod access_log;
+mod aliases;
+mod coalesce;
+mod lanes;
+#[cfg(feature = "liquid")]
+mod liquid;
+mod openapi;
 
 const ADDRESS_SEARCH_LIMIT: usize = 10;
-#[allow(dead_code)]
+const BURNS_PER_PAGE: usize = 50;
+const MULTISIG_DEFAULT_WINDOW: usize = 144;
+const MULTISIG_MAX_WINDOW: usize = 2016;
 const MULTI_ADDRESS_LIMIT: usize = 300;
+// transactions with more inputs or outputs than this only include a page of them
+const TX_IO_PAGE_SIZE: usize = 500;
+
+const MAX_ADDRESS_LEN: usize = 128; // leaves room for the confidential addresses of elements
+const MAX_NUMBER_LEN: usize = 20; // the digits of u64::MAX
+const NUMERIC_QUERY_PARAMS: &[&str] = &[
+    "height",
+    "from_height",
+    "window",
+    "max_txs",
+    "start_index",
+    "limit",
+    "count",
+    "to_height",
+    "from",
+    "to",
+    "vin",
+    "vin_offset",
+    "vout_offset",
+    "after",
+    "min_conf_height",
+    "min_amount",
+    "max_amount",
+    "from_time",
+    "to_time",
+    "since",
+];
+
+lazy_static! {
+    static ref OPENAPI: utoipa::openapi::OpenApi = openapi::openapi();
+}
+
+const PROXY_HEADER_TIMEOUT: Duration = Duration::from_secs(10);
+
+const EXPORT_CHUNK_SIZE: usize = 64 * 1024;
+// the number of chunks produced ahead of the client reading them
+const EXPORT_BUFFERED_CHUNKS: usize = 4;
+
+#[cfg(not(feature = "liquid"))]
+const LEDGER_ENTRIES_DEFAULT: usize = 100;
+#[cfg(not(feature = "liquid"))]
+const LEDGER_ENTRIES_MAX: usize = 1_000;
+
+const EVICTED_TXS_DEFAULT: usize = 25;
+const EVICTED_TXS_MAX: usize = 1_000;
+
+const DEBUG_HEADERS_DEFAULT: usize = 10;
+const DEBUG_HEADERS_MAX: usize = 100;
+const DEBUG_CLIENTS_DEFAULT: usize = 100;
+const DEBUG_CLIENTS_MAX: usize = 10_000;
 
 #[cfg(feature = "liquid")]
 const ASSETS_PER_PAGE: usize = 25;
@@ -71,374 +170,498 @@ enum TxidLocation {
 }
 
 #[allow(dead_code)]
-fn find_txid(txid: &Txid, query: &Query) -> TxidLocation {
-    if query.mempool().lookup_txn(txid).is_some() {
+fn find_txid(txid: &Txid, query: &Query) -> Result<TxidLocation, HttpError> {
+    Ok(if query.mempool().lookup_txn(txid).is_some() {
         TxidLocation::Mempool
-    } else if let Some(block) = query.chain().tx_confirming_block(txid) {
+    } else if let Some(block) = query.chain().tx_confirming_block(txid)? {
         TxidLocation::Chain(block.height as u32)
     } else {
         TxidLocation::None
-    }
+    })
 }
 
-#[derive(Serialize, Deserialize)]
-struct BlockValue {
-    id: BlockHash,
-    height: u32,
-    version: u32,
-    timestamp: u32,
-    tx_count: u32,
-    size: u32,
-    weight: u64,
-    merkle_root: TxMerkleNode,
-    previousblockhash: Option<BlockHash>,
-    mediantime: u32,
+#[cfg_attr(feature = "liquid", allow(unused_variables))]
+fn block_value(blockhm: BlockHeaderMeta, _config: &Config) -> BlockValue {
+    let header = blockhm.header_entry.header();
+    BlockValue {
+        id: header.block_hash(),
+        height: blockhm.header_entry.height() as u32,
+        #[cfg(not(feature = "liquid"))]
+        version: header.version.to_consensus() as u32,
+        #[cfg(feature = "liquid")]
+        version: header.version,
+        timestamp: header.time,
+        tx_count: blockhm.meta.tx_count,
+        size: blockhm.meta.size,
+        weight: blockhm.meta.weight as u64,
+        merkle_root: header.merkle_root,
+        previousblockhash: if header.prev_blockhash != *DEFAULT_BLOCKHASH {
+            Some(header.prev_blockhash)
+        } else {
+            None
+        },
+        mediantime: blockhm.mtp,
 
-    #[cfg(not(feature = "liquid"))]
-    nonce: u32,
-    #[cfg(not(feature = "liquid"))]
-    bits: bitcoin::pow::CompactTarget,
-    #[cfg(not(feature = "liquid"))]
-    difficulty: f64,
+        #[cfg(not(feature = "liquid"))]
+        bits: header.bits.to_consensus(),
+        #[cfg(not(feature = "liquid"))]
+        nonce: header.nonce,
+        #[cfg(not(feature = "liquid"))]
+        difficulty: header.difficulty_float(),
 
-    #[cfg(feature = "liquid")]
-    #[serde(skip_serializing_if = "Option::is_none")]
-    ext: Option<elements::BlockExtData>,
+        #[cfg(feature = "liquid")]
+        ext: Some(header.ext.clone()),
+    }
 }
 
-impl BlockValue {
-    #[cfg_attr(feature = "liquid", allow(unused_variables))]
-    fn new(blockhm: BlockHeaderMeta, _config: &Config) -> Self {
-        let header = blockhm.header_entry.header();
-        BlockValue {
-            id: header.block_hash(),
-            height: blockhm.header_entry.height() as u32,
-            #[cfg(not(feature = "liquid"))]
-            version: header.version.to_consensus() as u32,
-            #[cfg(feature = "liquid")]
-            version: header.version,
-            timestamp: header.time,
-            tx_count: blockhm.meta.tx_count,
-            size: blockhm.meta.size,
-            weight: blockhm.meta.weight as u64,
-            merkle_root: header.merkle_root,
-            previousblockhash: if header.prev_blockhash != *DEFAULT_BLOCKHASH {
-                Some(header.prev_blockhash)
-            } else {
-                None
-            },
-            mediantime: blockhm.mtp,
+// The inputs and outputs of a transaction to include, for the very large ones
+#[derive(Clone, Copy, Default)]
+struct TxIoPage {
+    vin_offset: usize,
+    vout_offset: usize,
+}
 
-            #[cfg(not(feature = "liquid"))]
-            bits: header.bits,
-            #[cfg(not(feature = "liquid"))]
-            nonce: header.nonce,
-            #[cfg(not(feature = "liquid"))]
-            difficulty: header.difficulty_float(),
+// The paging of the inputs and outputs of `tx`, set when a `page` is given and the transaction
+// is large or the offsets are non-zero
+fn tx_io_paging(tx: &Transaction, page: Option<TxIoPage>) -> Option<TxIoPaging> {
+    page.filter(|page| {
+        page.vin_offset > 0
+            || page.vout_offset > 0
+            || tx.input.len() > TX_IO_PAGE_SIZE
+            || tx.output.len() > TX_IO_PAGE_SIZE
+    })
+    .map(|page| TxIoPaging {
+        vin_count: tx.input.len(),
+        vin_offset: page.vin_offset,
+        vout_count: tx.output.len(),
+        vout_offset: page.vout_offset,
+        page_size: TX_IO_PAGE_SIZE,
+        truncated: page.vin_offset + TX_IO_PAGE_SIZE < tx.input.len()
+            || page.vout_offset + TX_IO_PAGE_SIZE < tx.output.len(),
+    })
+}
 
-            #[cfg(feature = "liquid")]
-            ext: Some(header.ext.clone()),
+// The indexes of the inputs included with the `paging`
+fn paged_inputs(tx: &Transaction, paging: Option<&TxIoPaging>) -> std::ops::Range<usize> {
+    match paging {
+        Some(paging) => {
+            let start = paging.vin_offset.min(tx.input.len());
+            start..(start + paging.page_size).min(tx.input.len())
         }
+        None => 0..tx.input.len(),
     }
 }
 
-#[derive(Serialize)]
-struct TransactionValue {
-    txid: Txid,
-    version: u32,
-    locktime: u32,
-    vin: Vec<TxInValue>,
-    vout: Vec<TxOutValue>,
-    size: u32,
-    weight: u64,
-    fee: u64,
-    #[serde(skip_serializing_if = "Option::is_none")]
-    status: Option<TransactionStatus>,
-
-    #[cfg(feature = "liquid")]
-    #[serde(skip_serializing_if = "Option::is_none")]
-    discount_vsize: Option<usize>,
-
-    #[cfg(feature = "liquid")]
-    #[serde(skip_serializing_if = "Option::is_none")]
-    discount_weight: Option<usize>,
+// Whether any of the outputs, or of the outputs spent by the inputs, is of one of the types
+fn has_script_type(tx: &TransactionValue, script_types: &HashSet<String>) -> bool {
+    tx.vout
+        .iter()
+        .chain(tx.vin.iter().filter_map(|vin| vin.prevout.as_ref()))
+        .any(|txout| script_types.contains(&txout.scriptpubkey_type))
 }
 
-impl TransactionValue {
-    fn new(
-        tx: Transaction,
-        blockid: Option<BlockId>,
-        txos: &HashMap<OutPoint, TxOut>,
-        config: &Config,
-    ) -> Self {
-        let prevouts = extract_tx_prevouts(&tx, &txos, true);
-        let vins: Vec<TxInValue> = tx
-            .input
-            .iter()
-            .enumerate()
-            .map(|(index, txin)| {
-                TxInValue::new(txin, prevouts.get(&(index as u32)).cloned(), config)
-            })
-            .collect();
-        let vouts: Vec<TxOutValue> = tx
-            .output
-            .iter()
-            .map(|txout| TxOutValue::new(txout, config))
-            .collect();
-
-        let fee = get_tx_fee(&tx, &prevouts, config.network_type);
+// Includes all the inputs and outputs unless a `page` is given, which only applies to large
+// transactions or non-zero offsets. Without the `txos` the inputs are spending, the prevouts
+// and the fee are left out. The fee is left out too when only a page of the inputs is included,
+// as only their prevouts are looked up.
+fn tx_value(
+    tx: Transaction,
+    blockid: Option<BlockId>,
+    txos: Option<&HashMap<OutPoint, TxOut>>,
+    config: &Config,
+    page: Option<TxIoPage>,
+) -> TransactionValue {
+    #[cfg(not(feature = "liquid"))]
+    let version = tx.version.0 as u32;
+    #[cfg(feature = "liquid")]
+    let version = tx.version as u32;
+
+    let paging = tx_io_paging(&tx, page);
+    let inputs = paged_inputs(&tx, paging.as_ref());
+    let all_inputs = inputs.len() == tx.input.len();
+    let (vout_offset, page_size) = match paging {
+        Some(ref paging) => (paging.vout_offset, paging.page_size),
+        None => (0, usize::MAX),
+    };
 
-        let weight = tx.weight();
-        #[cfg(not(feature = "liquid"))] // rust-flokicoin has a wrapper Weight type
-        let weight = weight.to_wu();
+    let prevouts = txos
+        .map(|txos| extract_tx_prevouts(&tx, txos, true))
+        .unwrap_or_default();
+    let vins: Vec<TxInValue> = tx
+        .input
+        .iter()
+        .enumerate()
+        .skip(inputs.start)
+        .take(inputs.len())
+        .map(|(index, txin)| {
+            txin_value(
+                txin,
+                prevouts.get(&(index as u32)).cloned(),
+                version,
+                config,
+            )
+        })
+        .collect();
+    let vouts: Vec<TxOutValue> = tx
+        .output
+        .iter()
+        .skip(vout_offset)
+        .take(page_size)
+        .map(|txout| txout_value(txout, config))
+        .collect();
 
-        TransactionValue {
-            txid: tx.compute_txid(),
-            #[cfg(not(feature = "liquid"))]
-            version: tx.version.0 as u32,
-            #[cfg(feature = "liquid")]
-            version: tx.version as u32,
-            locktime: tx.lock_time.to_consensus_u32(),
-            vin: vins,
-            vout: vouts,
-            size: tx.total_size() as u32,
-            weight: weight as u64,
-            fee,
-            status: Some(TransactionStatus::from(blockid)),
+    let fee = txos
+        .filter(|_| all_inputs)
+        .map(|_| get_tx_fee(&tx, &prevouts, config.network_type));
+
+    let weight = tx.weight();
+    #[cfg(not(feature = "liquid"))] // rust-flokicoin has a wrapper Weight type
+    let weight = weight.to_wu();
+    let size = tx.total_size();
+
+    TransactionValue {
+        txid: tx.compute_txid(),
+        version,
+        locktime: tx.lock_time.to_consensus_u32(),
+        locktime_type: locktime::locktime_type(tx.lock_time.to_consensus_u32()),
+        locktime_active: locktime::is_locktime_active(&tx),
+        // depends on the chain tip, set by prepare_txs()
+        is_final: blockid.is_some(),
+        vin: vins,
+        vout: vouts,
+        size: size as u32,
+        weight: weight as u64,
+        vsize: tx_vsize(&tx),
+        // the witness data is counted once in the weight, the rest four times
+        stripped_size: ((weight as usize - size) / 3) as u32,
+        // requires the prevouts, set by prepare_txs()
+        #[cfg(not(feature = "liquid"))]
+        sigops: None,
+        fee,
+        // requires the price feed, set by prepare_txs()
+        #[cfg(not(feature = "liquid"))]
+        fiat: None,
+        status: Some(TransactionStatus::from(blockid).into()),
+        paging,
+        blocked: false,
 
-            #[cfg(feature = "liquid")]
-            discount_vsize: optional_value_for_newer_blocks(
-                blockid,
-                START_OF_LIQUID_DISCOUNT_CT_POLICY,
-                tx.discount_vsize(),
-            ),
+        #[cfg(feature = "liquid")]
+        discount_vsize: optional_value_for_newer_blocks(
+            blockid,
+            START_OF_LIQUID_DISCOUNT_CT_POLICY,
+            tx.discount_vsize(),
+        ),
 
-            #[cfg(feature = "liquid")]
-            discount_weight: optional_value_for_newer_blocks(
-                blockid,
-                START_OF_LIQUID_DISCOUNT_CT_POLICY,
-                tx.discount_weight(),
-            ),
-        }
+        #[cfg(feature = "liquid")]
+        discount_weight: optional_value_for_newer_blocks(
+            blockid,
+            START_OF_LIQUID_DISCOUNT_CT_POLICY,
+            tx.discount_weight(),
+        ),
     }
 }
 
-#[derive(Serialize, Clone)]
-struct TxInValue {
-    txid: Txid,
-    vout: u32,
-    prevout: Option<TxOutValue>,
-    scriptsig: Script,
-    scriptsig_asm: String,
-    #[serde(skip_serializing_if = "Option::is_none")]
-    witness: Option<Vec<String>>,
-    is_coinbase: bool,
-    sequence: Sequence,
-
-    #[serde(skip_serializing_if = "Option::is_none")]
-    inner_redeemscript_asm: Option<String>,
-    #[serde(skip_serializing_if = "Option::is_none")]
-    inner_witnessscript_asm: Option<String>,
-
+fn txin_value(txin: &TxIn, prevout: Option<&TxOut>, tx_version: u32, config: &Config) -> TxInValue {
+    let witness = &txin.witness;
     #[cfg(feature = "liquid")]
-    is_pegin: bool,
-    #[cfg(feature = "liquid")]
-    #[serde(skip_serializing_if = "Option::is_none")]
-    issuance: Option<IssuanceValue>,
-}
+    let witness = &witness.script_witness;
 
-impl TxInValue {
-    fn new(txin: &TxIn, prevout: Option<&TxOut>, config: &Config) -> Self {
-        let witness = &txin.witness;
-        #[cfg(feature = "liquid")]
-        let witness = &witness.script_witness;
+    let witness = if !witness.is_empty() {
+        Some(
+            witness
+                .iter()
+                .map(DisplayHex::to_lower_hex_string)
+                .collect(),
+        )
+    } else {
+        None
+    };
 
-        let witness = if !witness.is_empty() {
-            Some(
-                witness
-                    .iter()
-                    .map(DisplayHex::to_lower_hex_string)
-                    .collect(),
-            )
+    let is_coinbase = is_coinbase(&txin);
+
+    let innerscripts = prevout.map(|prevout| get_innerscripts(&txin, &prevout));
+
+    TxInValue {
+        txid: txin.previous_output.txid,
+        vout: txin.previous_output.vout,
+        prevout: prevout.map(|prevout| txout_value(prevout, config)),
+        scriptsig_asm: txin.script_sig.to_asm(),
+        witness,
+
+        inner_redeemscript_asm: innerscripts
+            .as_ref()
+            .and_then(|i| i.redeem_script.as_ref())
+            .map(ScriptToAsm::to_asm),
+        inner_witnessscript_asm: innerscripts
+            .as_ref()
+            .and_then(|i| i.witness_script.as_ref())
+            .map(ScriptToAsm::to_asm),
+
+        is_coinbase,
+        sequence: txin.sequence.to_consensus_u32(),
+        relative_timelock: if is_coinbase {
+            None
+        } else {
+            locktime::relative_timelock(tx_version, txin.sequence.to_consensus_u32())
+        },
+        #[cfg(feature = "liquid")]
+        is_pegin: txin.is_pegin,
+        #[cfg(feature = "liquid")]
+        issuance: if txin.has_issuance() {
+            Some(IssuanceValue::from(txin))
         } else {
             None
-        };
-
-        let is_coinbase = is_coinbase(&txin);
-
-        let innerscripts = prevout.map(|prevout| get_innerscripts(&txin, &prevout));
+        },
 
-        TxInValue {
-            txid: txin.previous_output.txid,
-            vout: txin.previous_output.vout,
-            prevout: prevout.map(|prevout| TxOutValue::new(prevout, config)),
-            scriptsig_asm: txin.script_sig.to_asm(),
-            witness,
+        scriptsig: txin.script_sig.clone(),
+    }
+}
 
-            inner_redeemscript_asm: innerscripts
-                .as_ref()
-                .and_then(|i| i.redeem_script.as_ref())
-                .map(ScriptToAsm::to_asm),
-            inner_witnessscript_asm: innerscripts
-                .as_ref()
-                .and_then(|i| i.witness_script.as_ref())
-                .map(ScriptToAsm::to_asm),
+fn txout_value(txout: &TxOut, config: &Config) -> TxOutValue {
+    #[cfg(not(feature = "liquid"))]
+    let value = txout.value.to_sat();
+    #[cfg(feature = "liquid")]
+    let value = txout.value.explicit();
 
-            is_coinbase,
-            sequence: txin.sequence,
-            #[cfg(feature = "liquid")]
-            is_pegin: txin.is_pegin,
-            #[cfg(feature = "liquid")]
-            issuance: if txin.has_issuance() {
-                Some(IssuanceValue::from(txin))
-            } else {
-                None
-            },
+    #[cfg(not(feature = "liquid"))]
+    let is_fee = false;
+    #[cfg(feature = "liquid")]
+    let is_fee = txout.is_fee();
+
+    let script = &txout.script_pubkey;
+    let script_asm = script.to_asm();
+    let script_addr = script.to_address_str(config.network_type);
+
+    // TODO should the following something to put inside rust-elements lib?
+    let script_type = if is_fee {
+        "fee"
+    } else if script.is_empty() {
+        "empty"
+    } else if script.is_op_return() {
+        "op_return"
+    } else if script.is_p2pk() {
+        "p2pk"
+    } else if script.is_p2pkh() {
+        "p2pkh"
+    } else if script.is_p2sh() {
+        "p2sh"
+    } else if script.is_p2wpkh() {
+        "v0_p2wpkh"
+    } else if script.is_p2wsh() {
+        "v0_p2wsh"
+    } else if script.is_p2tr() {
+        "v1_p2tr"
+    } else if script.is_op_return() {
+        "provably_unspendable"
+    } else {
+        "unknown"
+    };
 
-            scriptsig: txin.script_sig.clone(),
-        }
+    #[cfg(feature = "liquid")]
+    let pegout = PegoutValue::from_txout(txout, config.network_type, config.parent_network);
+
+    TxOutValue {
+        scriptpubkey: script.clone(),
+        scriptpubkey_asm: script_asm,
+        scriptpubkey_address: script_addr,
+        scriptpubkey_type: script_type.to_string(),
+        value,
+        #[cfg(feature = "liquid")]
+        valuecommitment: txout.value.commitment(),
+        #[cfg(feature = "liquid")]
+        asset: txout.asset.explicit(),
+        #[cfg(feature = "liquid")]
+        assetcommitment: txout.asset.commitment(),
+        #[cfg(feature = "liquid")]
+        pegout,
     }
 }
 
-#[derive(Serialize, Clone)]
-struct TxOutValue {
-    scriptpubkey: Script,
-    scriptpubkey_asm: String,
-    scriptpubkey_type: String,
-
-    #[serde(skip_serializing_if = "Option::is_none")]
-    scriptpubkey_address: Option<String>,
-
+/// A lightweight entry of the transaction list of a block, without the inputs and outputs
+#[derive(Serialize, ToSchema)]
+struct BlockTxSummary {
+    #[schema(value_type = String)]
+    txid: Txid,
+    #[serde(serialize_with = "crate::util::amount::serialize")]
+    fee: u64,
+    vsize: u64,
+    /// The total value of the outputs
     #[cfg(not(feature = "liquid"))]
+    #[serde(serialize_with = "crate::util::amount::serialize")]
     value: u64,
+}
 
-    #[cfg(feature = "liquid")]
-    #[serde(skip_serializing_if = "Option::is_none")]
-    value: Option<u64>,
-
-    #[cfg(feature = "liquid")]
-    #[serde(skip_serializing_if = "Option::is_none")]
-    valuecommitment: Option<zkp::PedersenCommitment>,
+// The summaries of the transactions of a block, which only need the values of their prevouts
+fn block_tx_summaries(
+    txs: &[Transaction],
+    query: &Query,
+    config: &Config,
+) -> Result<Vec<BlockTxSummary>, HttpError> {
+    let outpoints = txs
+        .iter()
+        .flat_map(|tx| {
+            tx.input
+                .iter()
+                .filter(|txin| has_prevout(txin))
+                .map(|txin| txin.previous_output)
+        })
+        .collect();
+    let txos = query.lookup_txos(outpoints)?;
 
-    #[cfg(feature = "liquid")]
-    #[serde(skip_serializing_if = "Option::is_none")]
-    asset: Option<AssetId>,
+    Ok(txs
+        .iter()
+        .map(|tx| BlockTxSummary {
+            txid: tx.compute_txid(),
+            fee: get_tx_fee(
+                tx,
+                &extract_tx_prevouts(tx, &txos, true),
+                config.network_type,
+            ),
+            vsize: tx_vsize(tx),
+            #[cfg(not(feature = "liquid"))]
+            value: tx.output.iter().map(|txout| txout.value.to_sat()).sum(),
+        })
+        .collect())
+}
 
-    #[cfg(feature = "liquid")]
-    #[serde(skip_serializing_if = "Option::is_none")]
-    assetcommitment: Option<zkp::Generator>,
+/// The totals of the transactions of a block
+#[derive(Serialize, ToSchema)]
+struct BlockStatsValue {
+    tx_count: u32,
+    size: u32,
+    weight: u32,
+    stripped_size: u32,
+    tx_vsize: u64,
+    #[cfg(not(feature = "liquid"))]
+    sigops: u64,
+}
 
-    #[cfg(feature = "liquid")]
-    #[serde(skip_serializing_if = "Option::is_none")]
-    pegout: Option<PegoutValue>,
+/// The merkle proof of a confirmed transaction, like `blockchain.transaction.get_merkle`
+#[derive(Serialize, ToSchema)]
+struct MerkleProofValue {
+    block_height: usize,
+    merkle: Vec<String>,
+    pos: usize,
 }
 
-impl TxOutValue {
-    fn new(txout: &TxOut, config: &Config) -> Self {
-        #[cfg(not(feature = "liquid"))]
-        let value = txout.value.to_sat();
-        #[cfg(feature = "liquid")]
-        let value = txout.value.explicit();
+#[derive(Serialize, ToSchema)]
+struct ServerInfo {
+    version: String,
+    git_commit: Option<String>,
+    features: Vec<String>,
+    db_version: u32,
+    network: String,
+    light_mode: bool,
+    address_search: bool,
+    index_unspendables: bool,
+    utxo_age_stats: bool,
+    tip_height: usize,
+    /// In seconds
+    uptime: u64,
+}
 
-        #[cfg(not(feature = "liquid"))]
-        let is_fee = false;
-        #[cfg(feature = "liquid")]
-        let is_fee = txout.is_fee();
-
-        let script = &txout.script_pubkey;
-        let script_asm = script.to_asm();
-        let script_addr = script.to_address_str(config.network_type);
-
-        // TODO should the following something to put inside rust-elements lib?
-        let script_type = if is_fee {
-            "fee"
-        } else if script.is_empty() {
-            "empty"
-        } else if script.is_op_return() {
-            "op_return"
-        } else if script.is_p2pk() {
-            "p2pk"
-        } else if script.is_p2pkh() {
-            "p2pkh"
-        } else if script.is_p2sh() {
-            "p2sh"
-        } else if script.is_p2wpkh() {
-            "v0_p2wpkh"
-        } else if script.is_p2wsh() {
-            "v0_p2wsh"
-        } else if script.is_p2tr() {
-            "v1_p2tr"
-        } else if script.is_op_return() {
-            "provably_unspendable"
-        } else {
-            "unknown"
-        };
+#[derive(Serialize, ToSchema)]
+struct SupplyValue {
+    height: usize,
+    #[serde(serialize_with = "crate::util::amount::serialize")]
+    total: u64,
+    #[serde(serialize_with = "crate::util::amount::serialize")]
+    burned: u64,
+    #[serde(serialize_with = "crate::util::amount::serialize")]
+    circulating: u64,
+}
 
-        #[cfg(feature = "liquid")]
-        let pegout = PegoutValue::from_txout(txout, config.network_type, config.parent_network);
-
-        TxOutValue {
-            scriptpubkey: script.clone(),
-            scriptpubkey_asm: script_asm,
-            scriptpubkey_address: script_addr,
-            scriptpubkey_type: script_type.to_string(),
-            value,
-            #[cfg(feature = "liquid")]
-            valuecommitment: txout.value.commitment(),
-            #[cfg(feature = "liquid")]
-            asset: txout.asset.explicit(),
-            #[cfg(feature = "liquid")]
-            assetcommitment: txout.asset.commitment(),
-            #[cfg(feature = "liquid")]
-            pegout,
-        }
-    }
+#[derive(Serialize, ToSchema)]
+struct BlockTimestampValue {
+    height: usize,
+    #[schema(value_type = String)]
+    hash: BlockHash,
+    timestamp: u32,
 }
 
-#[derive(Serialize)]
-struct UtxoValue {
-    txid: Txid,
-    vout: u32,
-    status: TransactionStatus,
+#[derive(Serialize, ToSchema)]
+struct MultisigStatsValue {
+    height: usize,
+    window: usize,
+    spends: Vec<MultisigUsage>,
+}
 
-    #[cfg(not(feature = "liquid"))]
-    value: u64,
+#[derive(Serialize, ToSchema)]
+struct UtxoAgeStatsValue {
+    height: usize,
+    buckets: Vec<UtxoAgeBucket>,
+}
 
-    #[cfg(feature = "liquid")]
-    #[serde(skip_serializing_if = "Option::is_none")]
-    value: Option<u64>,
+/// The feerate estimates (in sat/vB) by confirmation target (in blocks)
+#[derive(Serialize, ToSchema)]
+struct FeeEstimatesValue(HashMap<u16, f64>);
 
-    #[cfg(feature = "liquid")]
-    #[serde(skip_serializing_if = "Option::is_none")]
-    valuecommitment: Option<zkp::PedersenCommitment>,
+// the state of the index, for `/internal/debug/chain`
+#[derive(Serialize)]
+struct DebugChainValue {
+    tip_height: usize,
+    tip_hash: BlockHash,
+    done_initial_sync: bool,
+    read_only: bool,
+    headers: Vec<DebugHeaderValue>,
+}
 
-    #[cfg(feature = "liquid")]
-    #[serde(skip_serializing_if = "Option::is_none")]
-    asset: Option<AssetId>,
+#[derive(Serialize)]
+struct DebugHeaderValue {
+    height: usize,
+    hash: BlockHash,
+    time: u32,
+    #[cfg(not(feature = "liquid"))]
+    chainwork: String,
+}
 
-    #[cfg(feature = "liquid")]
-    #[serde(skip_serializing_if = "Option::is_none")]
-    assetcommitment: Option<zkp::Generator>,
+// the state of the mempool tracker, for `/internal/debug/mempool`
+#[derive(Serialize)]
+struct DebugMempoolValue<'a> {
+    tracker: TrackerStats,
+    backlog: &'a BacklogStats,
+}
 
-    // nonces are never explicit
-    #[cfg(feature = "liquid")]
-    #[serde(skip_serializing_if = "Option::is_none")]
-    noncecommitment: Option<zkp::PublicKey>,
+#[cfg(not(feature = "liquid"))]
+impl From<ScriptStats> for flokicoin_electrs_api::ScriptStats {
+    fn from(stats: ScriptStats) -> Self {
+        flokicoin_electrs_api::ScriptStats {
+            tx_count: stats.tx_count,
+            funded_txo_count: stats.funded_txo_count,
+            spent_txo_count: stats.spent_txo_count,
+            funded_txo_sum: stats.funded_txo_sum,
+            spent_txo_sum: stats.spent_txo_sum,
+        }
+    }
+}
 
-    #[cfg(feature = "liquid")]
-    #[serde(skip_serializing_if = "Option::is_none")]
-    surjection_proof: Option<zkp::SurjectionProof>,
+#[cfg(not(feature = "liquid"))]
+impl From<ScriptActivity> for flokicoin_electrs_api::ScriptActivity {
+    fn from(activity: ScriptActivity) -> Self {
+        flokicoin_electrs_api::ScriptActivity {
+            first_seen: activity.first_seen.into(),
+            last_seen: activity.last_seen.into(),
+        }
+    }
+}
 
-    #[cfg(feature = "liquid")]
-    #[serde(skip_serializing_if = "Option::is_none")]
-    range_proof: Option<zkp::RangeProof>,
+#[cfg(not(feature = "liquid"))]
+impl From<BlockId> for flokicoin_electrs_api::BlockId {
+    fn from(blockid: BlockId) -> Self {
+        flokicoin_electrs_api::BlockId {
+            height: blockid.height as u32,
+            hash: blockid.hash,
+            time: blockid.time,
+        }
+    }
 }
+
 impl From<Utxo> for UtxoValue {
     fn from(utxo: Utxo) -> Self {
         UtxoValue {
             txid: utxo.txid,
             vout: utxo.vout,
-            status: TransactionStatus::from(utxo.confirmed),
+            status: TransactionStatus::from(utxo.confirmed).into(),
 
             #[cfg(not(feature = "liquid"))]
             value: utxo.value,
@@ -458,46 +681,448 @@ impl From<Utxo> for UtxoValue {
             #[cfg(feature = "liquid")]
             range_proof: utxo.witness.rangeproof.map(|p| *p),
         }
-    }
+    }
+}
+
+#[derive(Serialize, ToSchema)]
+struct SpendingValue {
+    spent: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[schema(value_type = Option<String>)]
+    txid: Option<Txid>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    vin: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    status: Option<TransactionStatus>,
+}
+impl From<SpendingInput> for SpendingValue {
+    fn from(spend: SpendingInput) -> Self {
+        SpendingValue {
+            spent: true,
+            txid: Some(spend.txid),
+            vin: Some(spend.vin),
+            status: Some(TransactionStatus::from(spend.confirmed)),
+        }
+    }
+}
+impl Default for SpendingValue {
+    fn default() -> Self {
+        SpendingValue {
+            spent: false,
+            txid: None,
+            vin: None,
+            status: None,
+        }
+    }
+}
+
+fn ttl_by_depth(height: Option<usize>, query: &Query) -> u32 {
+    height.map_or(TTL_SHORT, |height| {
+        if query.chain().best_height() - height >= CONF_FINAL {
+            TTL_LONG
+        } else {
+            TTL_SHORT
+        }
+    })
+}
+
+// The fiat values follow the price feed, so they are only cached briefly even when the
+// transactions are buried
+#[cfg(not(feature = "liquid"))]
+fn ttl_with_fiat(ttl: u32, txs: &[TransactionValue]) -> u32 {
+    if txs.iter().any(|tx| tx.fiat.is_some()) {
+        ttl.min(TTL_SHORT)
+    } else {
+        ttl
+    }
+}
+
+#[cfg(feature = "liquid")]
+fn ttl_with_fiat(ttl: u32, _txs: &[TransactionValue]) -> u32 {
+    ttl
+}
+
+// With `?min_conf_height=`, fail unless the transaction is confirmed at that height or below, so
+// that a reorg moving it to a later block (or back to the mempool) is noticed instead of being
+// reported as a lower number of confirmations
+fn check_min_conf_height(
+    query_params: &HashMap<String, String>,
+    height: Option<usize>,
+) -> Result<(), HttpError> {
+    let min_conf_height = match query_params.get("min_conf_height") {
+        Some(min_conf_height) => min_conf_height.parse::<usize>()?,
+        None => return Ok(()),
+    };
+    match height {
+        Some(height) if height <= min_conf_height => Ok(()),
+        _ => Err(HttpError::tx_reorged(min_conf_height)),
+    }
+}
+
+#[cfg(not(feature = "liquid"))]
+#[derive(Clone, Copy)]
+enum ExportFormat {
+    Csv,
+    Jsonl,
+}
+
+// A line of the `jsonl` ledger export
+#[cfg(not(feature = "liquid"))]
+#[derive(Serialize)]
+struct ExportLine {
+    date: String,
+    txid: Txid,
+    height: usize,
+    direction: &'static str,
+    amount: serde_json::Value,
+    fee: serde_json::Value,
+    balance: serde_json::Value,
+}
+
+// Buffers the body of a streamed response into chunks, handed over to the connection as the
+// client reads them
+struct StreamWriter {
+    chunk: Vec<u8>,
+    sender: tokio::sync::mpsc::Sender<Result<Vec<u8>, String>>,
+}
+
+impl StreamWriter {
+    // Returns false once the client went away, for the producer to stop
+    fn write(&mut self, data: &[u8]) -> bool {
+        self.chunk.extend_from_slice(data);
+        self.chunk.len() < EXPORT_CHUNK_SIZE || self.flush()
+    }
+
+    fn flush(&mut self) -> bool {
+        self.chunk.is_empty()
+            || self
+                .sender
+                .blocking_send(Ok(std::mem::take(&mut self.chunk)))
+                .is_ok()
+    }
+}
+
+// Stream a response produced on the blocking thread pool, which is only as fast as the client
+// reads it. Errors met by the producer cut the response short, as the status was already sent.
+fn stream_response<F>(content_type: &str, filename: String, produce: F) -> Response<Body>
+where
+    F: FnOnce(&mut StreamWriter) -> Result<(), String> + Send + 'static,
+{
+    let (chunk_tx, mut chunk_rx) = tokio::sync::mpsc::channel(EXPORT_BUFFERED_CHUNKS);
+    tokio::task::spawn_blocking(move || {
+        let mut writer = StreamWriter {
+            chunk: vec![],
+            sender: chunk_tx,
+        };
+        match produce(&mut writer) {
+            Ok(()) => {
+                writer.flush();
+            }
+            Err(e) => {
+                writer.sender.blocking_send(Err(e)).ok();
+            }
+        }
+    });
+
+    let (mut sender, body) = Body::channel();
+    tokio::spawn(async move {
+        while let Some(chunk) = chunk_rx.recv().await {
+            match chunk {
+                Ok(chunk) => {
+                    if sender.send_data(chunk.into()).await.is_err() {
+                        break;
+                    }
+                }
+                Err(e) => {
+                    warn!("streamed response failed: {}", e);
+                    sender.abort();
+                    break;
+                }
+            }
+        }
+    });
+
+    Response::builder()
+        .header("Content-Type", content_type)
+        .header(
+            "Content-Disposition",
+            format!("attachment; filename=\"{}\"", filename),
+        )
+        .header("X-Powered-By", &**VERSION_STRING)
+        .body(body)
+        .unwrap()
+}
+
+// Stream the confirmed history of a scripthash, along with its running balance
+#[cfg(not(feature = "liquid"))]
+fn export_history(
+    query: Arc<Query>,
+    scripthash: FullHash,
+    name: &str,
+    (from_height, to_height): (usize, usize),
+    format: ExportFormat,
+    amounts: AmountFormat,
+) -> Response<Body> {
+    let (content_type, filename) = match format {
+        ExportFormat::Csv => ("text/csv", format!("{}.csv", name)),
+        ExportFormat::Jsonl => ("application/x-ndjson", format!("{}.jsonl", name)),
+    };
+    stream_response(content_type, filename, move |writer| {
+        if let ExportFormat::Csv = format {
+            writer.write(b"date,txid,height,direction,amount,fee,balance\n");
+        }
+        let mut balance = 0;
+        for entry in query.chain().history_deltas(&scripthash, 0, to_height) {
+            let entry = entry.map_err(|e| e.to_string())?;
+            balance += entry.delta;
+            if entry.blockid.height < from_height {
+                continue;
+            }
+            let line = export_line(&query, &entry, balance, format, &amounts)?;
+            if !writer.write(line.as_bytes()) {
+                break;
+            }
+        }
+        Ok(())
+    })
+}
+
+// Stream the transactions confirmed in the blocks at heights `from..=to` as JSON lines, keeping
+// those with an input or output of one of the `script_types` when given
+fn export_blocks(
+    query: Arc<Query>,
+    config: Arc<Config>,
+    (from, to): (usize, usize),
+    script_types: Option<HashSet<String>>,
+    amounts: AmountFormat,
+) -> Response<Body> {
+    let filename = format!("blocks-{}-{}.jsonl", from, to);
+    stream_response("application/x-ndjson", filename, move |writer| {
+        for height in from..=to {
+            let blockid = query
+                .chain()
+                .blockid_by_height(height)
+                .ok_or_else(|| format!("missing block at height {}", height))?;
+            let txids = query
+                .chain()
+                .get_block_txids(&blockid.hash)
+                .map_err(|e| e.to_string())?
+                .ok_or_else(|| format!("missing txids of block {}", blockid.hash))?;
+            for txids in txids.chunks(config.rest_default_chain_txs_per_page.max(1)) {
+                let txs = txids
+                    .iter()
+                    .map(|txid| {
+                        query
+                            .chain()
+                            .lookup_txn(txid, Some(&blockid.hash))
+                            .map_err(|e| e.to_string())?
+                            .map(|tx| (tx, Some(blockid)))
+                            .ok_or_else(|| format!("missing transaction {}", txid))
+                    })
+                    .collect::<Result<Vec<_>, _>>()?;
+                // exports include all the inputs and outputs
+                let txs =
+                    prepare_txs_paged(txs, &query, &config, None, true).map_err(|e| e.message)?;
+                for tx in txs {
+                    if let Some(ref script_types) = script_types {
+                        if !has_script_type(&tx, script_types) {
+                            continue;
+                        }
+                    }
+                    let line = amount::with_format(amounts, || serde_json::to_string(&tx))
+                        .map_err(|e| e.to_string())?;
+                    if !writer.write(format!("{}\n", line).as_bytes()) {
+                        return Ok(());
+                    }
+                }
+            }
+        }
+        Ok(())
+    })
 }
 
+/// A line of the `/v1/chainstate/diff` stream: an output created, or a previous output spent, by
+/// a transaction of the block at `height`
 #[derive(Serialize)]
-struct SpendingValue {
-    spent: bool,
-    #[serde(skip_serializing_if = "Option::is_none")]
-    txid: Option<Txid>,
-    #[serde(skip_serializing_if = "Option::is_none")]
-    vin: Option<u32>,
-    #[serde(skip_serializing_if = "Option::is_none")]
-    status: Option<TransactionStatus>,
+struct ChainstateChange {
+    height: usize,
+    #[serde(rename = "type")]
+    kind: &'static str,
+    txid: Txid,
+    vout: u32,
+    /// None for blinded outputs (elements only)
+    #[serde(serialize_with = "crate::util::amount::serialize_opt")]
+    value: Option<u64>,
+    scriptpubkey: String,
 }
-impl From<SpendingInput> for SpendingValue {
-    fn from(spend: SpendingInput) -> Self {
-        SpendingValue {
-            spent: true,
-            txid: Some(spend.txid),
-            vin: Some(spend.vin),
-            status: Some(TransactionStatus::from(spend.confirmed)),
+
+// Streams the outputs created and spent by the blocks above `from` up to `to`, in the order of
+// the blocks and transactions (each transaction's spends before its outputs), so that applying
+// them in order to the utxo set at `from` gives the utxo set at `to`
+fn chainstate_diff(
+    query: Arc<Query>,
+    config: Arc<Config>,
+    (from, to): (usize, usize),
+    amounts: AmountFormat,
+) -> Response<Body> {
+    let filename = format!("chainstate-{}-{}.jsonl", from, to);
+    stream_response("application/x-ndjson", filename, move |writer| {
+        let chain = query.chain();
+        for height in from + 1..=to {
+            let blockid = chain
+                .blockid_by_height(height)
+                .ok_or_else(|| format!("missing block at height {}", height))?;
+            let txids = chain
+                .get_block_txids(&blockid.hash)
+                .map_err(|e| e.to_string())?
+                .ok_or_else(|| format!("missing txids of block {}", blockid.hash))?;
+            for txids in txids.chunks(config.rest_default_chain_txs_per_page.max(1)) {
+                let txs = txids
+                    .iter()
+                    .map(|txid| {
+                        chain
+                            .lookup_txn(txid, Some(&blockid.hash))
+                            .map_err(|e| e.to_string())?
+                            .ok_or_else(|| format!("missing transaction {}", txid))
+                    })
+                    .collect::<Result<Vec<_>, _>>()?;
+                let outpoints = txs
+                    .iter()
+                    .flat_map(|tx| tx.input.iter())
+                    .filter(|txin| has_prevout(txin))
+                    .map(|txin| txin.previous_output)
+                    .collect();
+                let prevouts = chain.lookup_txos(outpoints).map_err(|e| e.to_string())?;
+
+                for tx in &txs {
+                    let txid = tx.compute_txid();
+                    let spent = tx
+                        .input
+                        .iter()
+                        .filter(|txin| has_prevout(txin))
+                        .map(|txin| {
+                            (
+                                "spent",
+                                txin.previous_output,
+                                &prevouts[&txin.previous_output],
+                            )
+                        });
+                    let created = tx
+                        .output
+                        .iter()
+                        .enumerate()
+                        .filter(|(_, txout)| is_spendable(txout))
+                        .map(|(vout, txout)| ("created", OutPoint::new(txid, vout as u32), txout));
+                    for (kind, outpoint, txout) in spent.chain(created) {
+                        let change = ChainstateChange {
+                            height,
+                            kind,
+                            txid: outpoint.txid,
+                            vout: outpoint.vout,
+                            value: explicit_value(txout),
+                            scriptpubkey: txout.script_pubkey.as_bytes().to_lower_hex_string(),
+                        };
+                        let line = amount::with_format(amounts, || serde_json::to_string(&change))
+                            .map_err(|e| e.to_string())?;
+                        if !writer.write(format!("{}\n", line).as_bytes()) {
+                            return Ok(());
+                        }
+                    }
+                }
+            }
         }
-    }
+        Ok(())
+    })
 }
-impl Default for SpendingValue {
-    fn default() -> Self {
-        SpendingValue {
-            spent: false,
-            txid: None,
-            vin: None,
-            status: None,
-        }
+
+// Whether to look up the prevouts of the transaction inputs, unless `?prevouts=false`
+fn prevouts_param(query_params: &HashMap<String, String>) -> Result<bool, HttpError> {
+    match query_params.get("prevouts").map(String::as_str) {
+        None | Some("true") => Ok(true),
+        Some("false") => Ok(false),
+        Some(_) => Err(HttpError::invalid_param(
+            "Invalid prevouts (expected true or false)".to_string(),
+        )),
     }
 }
 
-fn ttl_by_depth(height: Option<usize>, query: &Query) -> u32 {
-    height.map_or(TTL_SHORT, |height| {
-        if query.chain().best_height() - height >= CONF_FINAL {
-            TTL_LONG
-        } else {
-            TTL_SHORT
+// A block height of the query string
+fn height_param(query_params: &HashMap<String, String>, name: &str) -> Result<usize, HttpError> {
+    query_params
+        .get(name)
+        .ok_or_else(|| HttpError::invalid_param(format!("Missing {}", name)))?
+        .parse::<usize>()
+        .map_err(HttpError::from)
+}
+
+#[cfg(not(feature = "liquid"))]
+fn export_line(
+    query: &Query,
+    entry: &HistoryDelta,
+    balance: i64,
+    format: ExportFormat,
+    amounts: &AmountFormat,
+) -> Result<String, String> {
+    let chain = query.chain();
+    let tx = chain
+        .lookup_txn(&entry.txid, Some(&entry.blockid.hash))
+        .map_err(|e| e.to_string())?
+        .ok_or_else(|| format!("missing transaction {}", entry.txid))?;
+    let outpoints = tx
+        .input
+        .iter()
+        .filter(|txin| has_prevout(txin))
+        .map(|txin| txin.previous_output)
+        .collect();
+    let txos = chain.lookup_txos(outpoints).map_err(|e| e.to_string())?;
+    let fee = get_tx_fee(&tx, &extract_tx_prevouts(&tx, &txos, true), chain.network());
+
+    let date = time::OffsetDateTime::from_unix_timestamp(entry.blockid.time as i64)
+        .map_err(|e| e.to_string())?
+        .format(&time::format_description::well_known::Rfc3339)
+        .map_err(|e| e.to_string())?;
+    let direction = match entry.delta.signum() {
+        1 => "in",
+        -1 => "out",
+        _ => "self",
+    };
+    let (amount, fee, balance) = (
+        amounts.format(entry.delta),
+        amounts.format(fee as i64),
+        amounts.format(balance),
+    );
+
+    Ok(match format {
+        ExportFormat::Csv => {
+            let csv_amount = |value: serde_json::Value| match value {
+                serde_json::Value::String(amount) => amount,
+                amount => amount.to_string(),
+            };
+            format!(
+                "{},{},{},{},{},{},{}\n",
+                date,
+                entry.txid,
+                entry.blockid.height,
+                direction,
+                csv_amount(amount),
+                csv_amount(fee),
+                csv_amount(balance)
+            )
+        }
+        ExportFormat::Jsonl => {
+            let line = ExportLine {
+                date,
+                txid: entry.txid,
+                height: entry.blockid.height,
+                direction,
+                amount,
+                fee,
+                balance,
+            };
+            format!(
+                "{}\n",
+                serde_json::to_string(&line).map_err(|e| e.to_string())?
+            )
         }
     })
 }
@@ -506,59 +1131,227 @@ fn prepare_txs(
     txs: Vec<(Transaction, Option<BlockId>)>,
     query: &Query,
     config: &Config,
-) -> Vec<TransactionValue> {
+) -> Result<Vec<TransactionValue>, HttpError> {
+    prepare_txs_paged(txs, query, config, Some(TxIoPage::default()), true)
+}
+
+// Like prepare_txs(), including a `page` of the inputs and outputs of the large transactions, or
+// all of them if there's none. Only the prevouts of the included inputs are looked up, and none
+// without `with_prevouts`, leaving out the fields requiring them.
+fn prepare_txs_paged(
+    txs: Vec<(Transaction, Option<BlockId>)>,
+    query: &Query,
+    config: &Config,
+    page: Option<TxIoPage>,
+    with_prevouts: bool,
+) -> Result<Vec<TransactionValue>, HttpError> {
     let outpoints = txs
         .iter()
+        .filter(|_| with_prevouts)
         .flat_map(|(tx, _)| {
-            tx.input
+            let inputs = paged_inputs(tx, tx_io_paging(tx, page).as_ref());
+            tx.input[inputs]
                 .iter()
                 .filter(|txin| has_prevout(txin))
                 .map(|txin| txin.previous_output)
         })
         .collect();
 
-    let prevouts = query.lookup_txos(outpoints);
+    let prevouts = query.lookup_txos(outpoints)?;
+
+    // the unconfirmed transactions could be included in the block following the tip
+    let next_height = query.chain().best_height() + 1;
+    let tip_mtp = query.chain().get_mtp(next_height - 1);
 
-    txs.into_iter()
-        .map(|(tx, blockid)| TransactionValue::new(tx, blockid, &prevouts, config))
-        .collect()
+    Ok(txs
+        .into_iter()
+        .map(|(tx, blockid)| {
+            let blocked = query.is_tx_blocked(&tx, &prevouts);
+            let is_final =
+                blockid.is_some() || locktime::is_final(&tx, next_height as u32, tip_mtp);
+            // the sigops require the prevouts of all the inputs
+            #[cfg(not(feature = "liquid"))]
+            let all_prevouts = with_prevouts
+                && paged_inputs(&tx, tx_io_paging(&tx, page).as_ref()).len() == tx.input.len();
+            #[cfg(not(feature = "liquid"))]
+            let sigops = all_prevouts.then(|| query.sigop_cost(&tx, &prevouts, blockid.is_some()));
+            #[cfg(not(feature = "liquid"))]
+            let value_out = tx.output.iter().map(|txout| txout.value.to_sat()).sum();
+            let txos = with_prevouts.then_some(&prevouts);
+            let value = tx_value(tx, blockid, txos, config, page);
+            TransactionValue {
+                blocked,
+                is_final,
+                #[cfg(not(feature = "liquid"))]
+                sigops,
+                #[cfg(not(feature = "liquid"))]
+                fiat: query.fiat_value(value_out, value.fee),
+                ..value
+            }
+        })
+        .collect())
 }
 
 #[tokio::main]
-async fn run_server(config: Arc<Config>, query: Arc<Query>, rx: oneshot::Receiver<()>) {
+async fn run_server(
+    config: Arc<Config>,
+    query: Arc<Query>,
+    metrics: Metrics,
+    slow_query_log: SlowQueryLog,
+    aliases: Aliases,
+    lanes: Lanes,
+    coalescer: Coalescer,
+    access_log: Option<AccessLog>,
+    rx: oneshot::Receiver<()>,
+) {
     let addr = &config.http_addr;
+    let addr_v6 = &config.http_addr_v6;
+    let addr_internal = &config.http_internal_addr;
     let socket_file = &config.http_socket_file;
+    let proxy_protocol = config.rest_proxy_protocol;
+    #[cfg(feature = "tls")]
+    let tls_acceptor = match socket_file {
+        None => crate::tls::acceptor(&config)
+            .await
+            .expect("failed setting up TLS"),
+        Some(_) => None,
+    };
 
     let config = Arc::clone(&config);
     let query = Arc::clone(&query);
+    let rate_limiter = Arc::new(RateLimiter::default());
+    let aliases = Arc::new(aliases);
+    let lanes = Arc::new(lanes);
+    let coalescer = Arc::new(coalescer);
 
-    let make_service_fn_inn = || {
+    // `internal_listener` is whether the connection was accepted on the internal listener
+    let make_service_fn_inn = move |remote_addr: Option<SocketAddr>, internal_listener: bool| {
         let query = Arc::clone(&query);
         let config = Arc::clone(&config);
+        let metrics = metrics.clone();
+        let rate_limiter = Arc::clone(&rate_limiter);
+        let slow_query_log = slow_query_log.clone();
+        let aliases = Arc::clone(&aliases);
+        let lanes = Arc::clone(&lanes);
+        let coalescer = Arc::clone(&coalescer);
+        let access_log = access_log.clone();
 
         async move {
             Ok::<_, hyper::Error>(service_fn(move |req| {
                 let query = Arc::clone(&query);
                 let config = Arc::clone(&config);
+                let metrics = metrics.clone();
+                let rate_limiter = Arc::clone(&rate_limiter);
+                let slow_query_log = slow_query_log.clone();
+                let aliases = Arc::clone(&aliases);
+                let lanes = Arc::clone(&lanes);
+                let coalescer = Arc::clone(&coalescer);
+                let access_log = access_log.clone();
 
                 async move {
+                    let start_time = Instant::now();
                     let method = req.method().clone();
                     let uri = req.uri().clone();
+                    // the requests to the old paths of renamed endpoints are served by the new ones
+                    let alias = aliases.resolve(&uri);
+                    let route = alias.as_ref().map_or(&uri, |(route, _)| route).clone();
+                    let request_id = req
+                        .headers()
+                        .get("X-Request-Id")
+                        .and_then(|id| id.to_str().ok())
+                        .filter(|id| is_valid_request_id(id))
+                        .map_or_else(new_request_id, str::to_string);
+                    let user_agent = req
+                        .headers()
+                        .get(hyper::header::USER_AGENT)
+                        .and_then(|ua| ua.to_str().ok())
+                        .map(str::to_string);
+                    let authorized = is_authorized(
+                        route.path(),
+                        req.headers()
+                            .get(hyper::header::AUTHORIZATION)
+                            .and_then(|auth| auth.to_str().ok()),
+                        config.debug_token.as_deref(),
+                    );
+                    let lane = lanes.lane(
+                        internal_listener,
+                        req.headers()
+                            .get("X-Api-Key")
+                            .and_then(|key| key.to_str().ok()),
+                    );
                     let body = hyper::body::to_bytes(req.into_body()).await?;
-
-                    let mut resp = handle_request(method, uri, body, &query, &config)
-                        .unwrap_or_else(|err| {
-                            warn!("{:?}", err);
-                            Response::builder()
-                                .status(err.0)
-                                .header("Content-Type", "text/plain")
-                                .body(Body::from(err.1))
-                                .unwrap()
-                        });
-                    if let Some(ref origins) = config.cors {
+                    let request_size = body.len();
+
+                    let mut reads = ReadStats::default();
+                    let runtime = config.runtime();
+                    let (rate, burst) = (runtime.rate_limit, runtime.rate_limit_burst);
+                    let result = match remote_addr {
+                        Some(addr)
+                            if lane == Lane::Public
+                                && !rate_limiter.check(addr.ip(), rate, burst) =>
+                        {
+                            Err(HttpError::rate_limited())
+                        }
+                        _ if !authorized => Err(HttpError::unauthorized()),
+                        _ if lane == Lane::Public && is_internal_only(&method, route.path()) => {
+                            Err(HttpError::forbidden())
+                        }
+                        _ => {
+                            handle_formatted_request(
+                                method.clone(),
+                                route.clone(),
+                                body,
+                                &request_id,
+                                &query,
+                                &config,
+                                &metrics,
+                                &lanes,
+                                lane,
+                                &coalescer,
+                                &mut reads,
+                            )
+                            .await
+                        }
+                    };
+                    slow_query_log.observe(
+                        &endpoint_label(&method, route.path()),
+                        &request_id,
+                        start_time.elapsed(),
+                        reads,
+                        || format!("{} {}", method, uri),
+                    );
+                    let mut resp = result.unwrap_or_else(|err| {
+                        warn!("[{}] {:?}", request_id, err);
+                        err.into_response(&request_id)
+                    });
+                    if let Some(ref origins) = config.runtime().cors {
                         resp.headers_mut()
                             .insert("Access-Control-Allow-Origin", origins.parse().unwrap());
                     }
+                    resp.headers_mut()
+                        .insert("X-Request-Id", request_id.parse().unwrap());
+                    if let Some((route, alias)) = alias {
+                        alias.mark(&mut resp, route.path());
+                    }
+
+                    if let Some(ref access_log) = access_log {
+                        access_log.log(&AccessLogEntry {
+                            event: "http_request",
+                            request_id: &request_id,
+                            method: method.as_str(),
+                            path: uri.path(),
+                            query: uri.query(),
+                            status: resp.status().as_u16(),
+                            request_size,
+                            response_size: resp.body().size_hint().exact(),
+                            duration_micros: start_time.elapsed().as_micros(),
+                            source: remote_addr.map(|addr| AccessLogSource {
+                                ip: addr.ip().to_string(),
+                                port: addr.port(),
+                            }),
+                            user_agent: user_agent.as_deref(),
+                        });
+                    }
                     Ok::<_, hyper::Error>(resp)
                 }
             }))
@@ -567,14 +1360,97 @@ async fn run_server(config: Arc<Config>, query: Arc<Query>, rx: oneshot::Receive
 
     let server = match socket_file {
         None => {
-            info!("REST server running on {}", addr);
+            let listener = tcp_listener("rest", addr, false);
+            let listener_v6 = addr_v6.map(|addr_v6| tcp_listener("rest-v6", &addr_v6, true));
+            let listener_internal =
+                addr_internal.map(|addr| tcp_listener("rest-internal", &addr, false));
+            let extra_listeners = listener_v6.is_some() || listener_internal.is_some();
+
+            #[cfg(feature = "tls")]
+            let manual_accept = proxy_protocol || tls_acceptor.is_some() || extra_listeners;
+            #[cfg(not(feature = "tls"))]
+            let manual_accept = proxy_protocol || extra_listeners;
+
+            // hyper's own server can't read the PROXY header, do TLS handshakes or accept on
+            // several listeners, so accept and set up the connections ourselves
+            if manual_accept {
+                let to_tokio = |listener: std::net::TcpListener| {
+                    listener
+                        .set_nonblocking(true)
+                        .expect("setting non-blocking failed");
+                    tokio::net::TcpListener::from_std(listener)
+                        .expect("TcpListener::from_std failed")
+                };
+                let listener = to_tokio(listener);
+                let listener_v6 = listener_v6.map(to_tokio);
+                let listener_internal = listener_internal.map(to_tokio);
+                let mut rx = rx;
+                loop {
+                    let (accepted, internal_listener) = tokio::select! {
+                        _ = &mut rx => break,
+                        accepted = listener.accept() => (accepted, false),
+                        accepted = accept_optional(listener_v6.as_ref()) => (accepted, false),
+                        accepted = accept_optional(listener_internal.as_ref()) => (accepted, true),
+                    };
+                    let (mut stream, peer_addr) = match accepted {
+                        Ok(accepted) => accepted,
+                        Err(e) => {
+                            // most likely out of file descriptors, back off like hyper does
+                            warn!("REST accept error: {}", e);
+                            tokio::time::sleep(Duration::from_secs(1)).await;
+                            continue;
+                        }
+                    };
+                    let make_service = make_service_fn_inn.clone();
+                    #[cfg(feature = "tls")]
+                    let tls_acceptor = tls_acceptor.clone();
+                    tokio::spawn(async move {
+                        let remote_addr = if proxy_protocol {
+                            let header = tokio::time::timeout(
+                                PROXY_HEADER_TIMEOUT,
+                                proxy_protocol::read_header_async(&mut stream),
+                            );
+                            match header.await {
+                                Ok(Ok(client_addr)) => client_addr.unwrap_or(peer_addr),
+                                Ok(Err(e)) => {
+                                    return warn!("[{}] rejected connection: {}", peer_addr, e)
+                                }
+                                Err(_) => return debug!("[{}] PROXY header timeout", peer_addr),
+                            }
+                        } else {
+                            peer_addr
+                        };
+                        let service = make_service(Some(remote_addr), internal_listener)
+                            .await
+                            .expect("infallible");
+
+                        #[cfg(feature = "tls")]
+                        if let Some(acceptor) = tls_acceptor {
+                            let stream = match acceptor.accept(stream).await {
+                                Ok(stream) => stream,
+                                Err(e) => {
+                                    return debug!("[{}] TLS handshake failed: {}", remote_addr, e)
+                                }
+                            };
+                            if let Err(e) = Http::new().serve_connection(stream, service).await {
+                                debug!("[{}] REST connection failed: {}", remote_addr, e);
+                            }
+                            return;
+                        }
 
-            let socket = create_socket(&addr);
-            socket.listen(511).expect("setting backlog failed");
+                        if let Err(e) = Http::new().serve_connection(stream, service).await {
+                            debug!("[{}] REST connection failed: {}", remote_addr, e);
+                        }
+                    });
+                }
+                return;
+            }
 
-            Server::from_tcp(socket.into())
+            Server::from_tcp(listener)
                 .expect("Server::from_tcp failed")
-                .serve(make_service_fn(move |_| make_service_fn_inn()))
+                .serve(make_service_fn(move |conn: &AddrStream| {
+                    make_service_fn_inn(Some(conn.remote_addr()), false)
+                }))
                 .with_graceful_shutdown(async {
                     rx.await.ok();
                 })
@@ -592,7 +1468,7 @@ async fn run_server(config: Arc<Config>, query: Arc<Query>, rx: oneshot::Receive
 
             Server::bind_unix(path)
                 .expect("Server::bind_unix failed")
-                .serve(make_service_fn(move |_| make_service_fn_inn()))
+                .serve(make_service_fn(move |_| make_service_fn_inn(None, false)))
                 .with_graceful_shutdown(async {
                     rx.await.ok();
                 })
@@ -605,13 +1481,50 @@ async fn run_server(config: Arc<Config>, query: Arc<Query>, rx: oneshot::Receive
     }
 }
 
-pub fn start(config: Arc<Config>, query: Arc<Query>) -> Handle {
+// The listener passed by systemd as `name`, or a new one bound to `addr`
+fn tcp_listener(name: &str, addr: &SocketAddr, only_v6: bool) -> std::net::TcpListener {
+    let listener = systemd::take_listener(name).unwrap_or_else(|| {
+        let socket = create_socket(addr, only_v6);
+        socket.listen(511).expect("setting backlog failed");
+        socket.into()
+    });
+    info!("REST server running on {}", listener.local_addr().unwrap());
+    listener
+}
+
+// Accepts a connection on `listener`, or never resolves if there's none
+async fn accept_optional(
+    listener: Option<&tokio::net::TcpListener>,
+) -> std::io::Result<(tokio::net::TcpStream, SocketAddr)> {
+    match listener {
+        Some(listener) => listener.accept().await,
+        None => std::future::pending().await,
+    }
+}
+
+pub fn start(config: Arc<Config>, query: Arc<Query>, metrics: &Metrics) -> Handle {
     let (tx, rx) = oneshot::channel::<()>();
+    let slow_query_log = SlowQueryLog::new(metrics, "rest", config.slow_query_threshold);
+    let aliases = Aliases::new(metrics);
+    let lanes = Lanes::new(&config, metrics, query.chain().load().clone());
+    let coalescer = Coalescer::new(metrics);
+    let access_log = config.rest_access_log.then(AccessLog::start);
+    let metrics = metrics.clone();
 
     Handle {
         tx,
         thread: thread::spawn(move || {
-            run_server(config, query, rx);
+            run_server(
+                config,
+                query,
+                metrics,
+                slow_query_log,
+                aliases,
+                lanes,
+                coalescer,
+                access_log,
+                rx,
+            );
         }),
     }
 }
@@ -628,13 +1541,85 @@ impl Handle {
     }
 }
 
+#[trace(request_id = %request_id)]
+async fn handle_formatted_request(
+    method: Method,
+    uri: hyper::Uri,
+    body: hyper::body::Bytes,
+    request_id: &str,
+    query: &Arc<Query>,
+    config: &Arc<Config>,
+    metrics: &Metrics,
+    lanes: &Lanes,
+    lane: Lane,
+    coalescer: &Coalescer,
+    reads: &mut ReadStats,
+) -> Result<Response<Body>, HttpError> {
+    let format = amount_format(&uri, config)?;
+    let coalesce = method == Method::GET && body.is_empty();
+    let route = uri.clone();
+    let (query, config, metrics) = (Arc::clone(query), Arc::clone(config), metrics.clone());
+    // the reads of the requests coalesced into another one are left out
+    let handle = || async move {
+        let (resp, request_reads) = lanes
+            .run(lane, move || {
+                ReadStats::measure(|| {
+                    amount::with_format(format, || {
+                        handle_request(method, uri, body, &query, &config, &metrics)
+                    })
+                })
+            })
+            .await;
+        *reads = request_reads;
+        resp
+    };
+    if coalesce {
+        coalescer.run(lane, &route, handle).await
+    } else {
+        handle().await
+    }
+}
+
+// The route of a request path for use as a metric label, with the parameters replaced by `:`
+// (e.g. `GET /address/:/txs/chain`)
+fn endpoint_label(method: &Method, path: &str) -> String {
+    let mut label = format!("{} ", method);
+    let mut prev = "";
+    for segment in path.split('/').skip(1) {
+        let is_route = segment.len() <= 16
+            && segment.bytes().all(|b| b.is_ascii_lowercase() || b == b'-')
+            && prev != "address-prefix";
+        label.push('/');
+        label.push_str(if is_route { segment } else { ":" });
+        prev = segment;
+    }
+    label
+}
+
+// Get the amount format for the unit requested with ?unit= (or the configured default)
+fn amount_format(uri: &hyper::Uri, config: &Config) -> Result<AmountFormat, HttpError> {
+    let unit = uri.query().and_then(|query| {
+        form_urlencoded::parse(query.as_bytes())
+            .find(|(key, _)| key == "unit")
+            .map(|(_, unit)| unit.into_owned())
+    });
+    Ok(AmountFormat {
+        unit: match unit {
+            Some(unit) => unit.parse().map_err(HttpError::invalid_param)?,
+            None => config.rest_default_unit,
+        },
+        as_strings: config.json_amounts_as_strings,
+    })
+}
+
 #[trace]
 fn handle_request(
     method: Method,
     uri: hyper::Uri,
     body: hyper::body::Bytes,
-    query: &Query,
-    config: &Config,
+    query: &Arc<Query>,
+    config: &Arc<Config>,
+    metrics: &Metrics,
 ) -> Result<Response<Body>, HttpError> {
     // TODO it looks hyper does not have routing and query parsing :(
     let path: Vec<&str> = uri.path().split('/').skip(1).collect();
@@ -646,6 +1631,13 @@ fn handle_request(
     };
 
     info!("handle {:?} {:?}", method, uri);
+    validate_request(&path, &query_params)?;
+    match endpoint_group(&method, &path) {
+        Some(group) if config.rest_disabled_endpoints.contains(&group) => {
+            return Err(HttpError::endpoint_disabled(group));
+        }
+        _ => (),
+    }
     match (
         &method,
         path.get(0),
@@ -675,19 +1667,55 @@ fn handle_request(
             let header = query
                 .chain()
                 .header_by_height(height)
-                .ok_or_else(|| HttpError::not_found("Block not found".to_string()))?;
+                .ok_or_else(|| HttpError::block_not_found())?;
             let ttl = ttl_by_depth(Some(height), query);
             http_message(StatusCode::OK, header.hash().to_string(), ttl)
         }
+        (&Method::GET, Some(&"block-height"), Some(height), Some(&"timestamp"), None, None) => {
+            let height = height.parse::<usize>()?;
+            let header = query
+                .chain()
+                .header_by_height(height)
+                .ok_or_else(|| HttpError::block_not_found())?;
+            let ttl = ttl_by_depth(Some(height), query);
+            http_message(StatusCode::OK, header.header().time.to_string(), ttl)
+        }
         (&Method::GET, Some(&"block"), Some(hash), None, None, None) => {
             let hash = BlockHash::from_str(hash)?;
             let blockhm = query
                 .chain()
-                .get_block_with_meta(&hash)
-                .ok_or_else(|| HttpError::not_found("Block not found".to_string()))?;
-            let block_value = BlockValue::new(blockhm, config);
+                .get_block_with_meta(&hash)?
+                .ok_or_else(|| HttpError::block_not_found())?;
+            let block_value = block_value(blockhm, config);
             json_response(block_value, TTL_LONG)
         }
+        (&Method::GET, Some(&"block"), Some(hash), Some(&"stats"), None, None) => {
+            let hash = BlockHash::from_str(hash)?;
+            let blockhm = query
+                .chain()
+                .get_block_with_meta(&hash)?
+                .ok_or_else(|| HttpError::block_not_found())?;
+            let stats = query.chain().block_stats(&hash)?.ok_or_else(|| {
+                HttpError::new(
+                    StatusCode::NOT_FOUND,
+                    "BLOCK_STATS_NOT_FOUND",
+                    "Block stats not found (orphaned block, or indexed before they were introduced)"
+                        .to_string(),
+                )
+            })?;
+            let meta = &blockhm.meta;
+            let block_stats = BlockStatsValue {
+                tx_count: meta.tx_count,
+                size: meta.size,
+                weight: meta.weight,
+                stripped_size: (meta.weight - meta.size) / 3,
+                tx_vsize: stats.tx_vsize,
+                #[cfg(not(feature = "liquid"))]
+                sigops: stats.sigops,
+            };
+            let ttl = ttl_by_depth(Some(blockhm.header_entry.height()), query);
+            json_response(block_stats, ttl)
+        }
         (&Method::GET, Some(&"block"), Some(hash), Some(&"status"), None, None) => {
             let hash = BlockHash::from_str(hash)?;
             let status = query.chain().get_block_status(&hash);
@@ -698,8 +1726,8 @@ fn handle_request(
             let hash = BlockHash::from_str(hash)?;
             let txids = query
                 .chain()
-                .get_block_txids(&hash)
-                .ok_or_else(|| HttpError::not_found("Block not found".to_string()))?;
+                .get_block_txids(&hash)?
+                .ok_or_else(|| HttpError::block_not_found())?;
             json_response(txids, TTL_LONG)
         }
         (&Method::GET, Some(&INTERNAL_PREFIX), Some(&"block"), Some(hash), Some(&"txs"), None) => {
@@ -707,29 +1735,30 @@ fn handle_request(
             let block_id = query.chain().blockid_by_hash(&hash);
             let txids = query
                 .chain()
-                .get_block_txids(&hash)
-                .ok_or_else(|| HttpError::not_found("Block not found".to_string()))?;
+                .get_block_txids(&hash)?
+                .ok_or_else(|| HttpError::block_not_found())?;
 
-            let txs = txids
-                .into_iter()
-                .filter_map(|txid| {
-                    query
-                        .chain()
-                        .lookup_txn(&txid, Some(&hash))
-                        .or_else(|| query.lookup_txn(&txid))
-                        .map(|tx| (tx, block_id.clone()))
-                })
-                .collect::<Vec<_>>();
+            let mut txs = vec![];
+            for txid in txids {
+                let tx = match query.chain().lookup_txn(&txid, Some(&hash))? {
+                    Some(tx) => Some(tx),
+                    None => query.lookup_txn(&txid)?,
+                };
+                if let Some(tx) = tx {
+                    txs.push((tx, block_id.clone()));
+                }
+            }
 
-            let ttl = ttl_by_depth(block_id.map(|b| b.height), query);
-            json_response(prepare_txs(txs, query, config), ttl)
+            let txs = prepare_txs(txs, query, config)?;
+            let ttl = ttl_with_fiat(ttl_by_depth(block_id.map(|b| b.height), query), &txs);
+            json_response(txs, ttl)
         }
         (&Method::GET, Some(&"block"), Some(hash), Some(&"header"), None, None) => {
             let hash = BlockHash::from_str(hash)?;
             let header = query
                 .chain()
                 .get_block_header(&hash)
-                .ok_or_else(|| HttpError::not_found("Block not found".to_string()))?;
+                .ok_or_else(|| HttpError::block_not_found())?;
 
             let header_hex = encode::serialize_hex(&header);
             http_message(StatusCode::OK, header_hex, TTL_LONG)
@@ -738,8 +1767,8 @@ fn handle_request(
             let hash = BlockHash::from_str(hash)?;
             let raw = query
                 .chain()
-                .get_block_raw(&hash)
-                .ok_or_else(|| HttpError::not_found("Block not found".to_string()))?;
+                .get_block_raw(&hash)?
+                .ok_or_else(|| HttpError::block_not_found())?;
 
             Ok(Response::builder()
                 .status(StatusCode::OK)
@@ -753,25 +1782,42 @@ fn handle_request(
             let index: usize = index.parse()?;
             let txids = query
                 .chain()
-                .get_block_txids(&hash)
-                .ok_or_else(|| HttpError::not_found("Block not found".to_string()))?;
+                .get_block_txids(&hash)?
+                .ok_or_else(|| HttpError::block_not_found())?;
             if index >= txids.len() {
-                bail!(HttpError::not_found("tx index out of range".to_string()));
+                bail!(HttpError::out_of_range("tx index out of range".to_string()));
             }
             http_message(StatusCode::OK, txids[index].to_string(), TTL_LONG)
         }
+        (&Method::GET, Some(&"block"), Some(hash), Some(&"txs"), Some(&"summary"), None) => {
+            let hash = BlockHash::from_str(hash)?;
+            let txids = query
+                .chain()
+                .get_block_txids(&hash)?
+                .ok_or_else(|| HttpError::block_not_found())?;
+            let txs = query
+                .chain()
+                .lookup_block_txns(&hash, &txids)?
+                .ok_or_else(|| HttpError::missing_block_txs(&hash))?;
+
+            let confirmed_blockid = query.chain().blockid_by_hash(&hash);
+            let ttl = ttl_by_depth(confirmed_blockid.map(|b| b.height), query);
+            json_response(block_tx_summaries(&txs, query, config)?, ttl)
+        }
         (&Method::GET, Some(&"block"), Some(hash), Some(&"txs"), start_index, None) => {
             let hash = BlockHash::from_str(hash)?;
             let txids = query
                 .chain()
-                .get_block_txids(&hash)
-                .ok_or_else(|| HttpError::not_found("Block not found".to_string()))?;
+                .get_block_txids(&hash)?
+                .ok_or_else(|| HttpError::block_not_found())?;
 
             let start_index = start_index
                 .map_or(0u32, |el| el.parse().unwrap_or(0))
                 .max(0u32) as usize;
             if start_index >= txids.len() {
-                bail!(HttpError::not_found("start index out of range".to_string()));
+                bail!(HttpError::out_of_range(
+                    "start index out of range".to_string()
+                ));
             } else if start_index % config.rest_default_chain_txs_per_page != 0 {
                 bail!(HttpError::from(format!(
                     "start index must be a multipication of {}",
@@ -783,33 +1829,59 @@ fn handle_request(
             // or None for orphaned
             let confirmed_blockid = query.chain().blockid_by_hash(&hash);
 
-            let txs = txids
-                .iter()
-                .skip(start_index)
-                .take(config.rest_default_chain_txs_per_page)
-                .map(|txid| {
-                    query
-                        .lookup_txn(&txid)
-                        .map(|tx| (tx, confirmed_blockid.clone()))
-                        .ok_or_else(|| "missing tx".to_string())
-                })
-                .collect::<Result<Vec<(Transaction, Option<BlockId>)>, _>>()?;
+            let page = &txids[start_index..];
+            let page = &page[..page.len().min(config.rest_default_chain_txs_per_page)];
+            let txs: Vec<(Transaction, Option<BlockId>)> = query
+                .chain()
+                .lookup_block_txns(&hash, page)?
+                .ok_or_else(|| HttpError::missing_block_txs(&hash))?
+                .into_iter()
+                .map(|tx| (tx, confirmed_blockid.clone()))
+                .collect();
 
             // XXX orphraned blocks alway get TTL_SHORT
             let ttl = ttl_by_depth(confirmed_blockid.map(|b| b.height), query);
 
-            json_response(prepare_txs(txs, query, config), ttl)
+            let with_prevouts = prevouts_param(&query_params)?;
+            let txs =
+                prepare_txs_paged(txs, query, config, Some(TxIoPage::default()), with_prevouts)?;
+            json_response(&txs, ttl_with_fiat(ttl, &txs))
         }
         (&Method::GET, Some(script_type @ &"address"), Some(script_str), None, None, None)
-        | (&Method::GET, Some(script_type @ &"scripthash"), Some(script_str), None, None, None) => {
-            let script_hash = to_scripthash(script_type, script_str, config.network_type)?;
-            let stats = query.stats(&script_hash[..]);
+        | (&Method::GET, Some(script_type @ &"scripthash"), Some(script_str), None, None, None)
+        | (&Method::GET, Some(script_type @ &"pkh"), Some(script_str), None, None, None)
+        | (&Method::GET, Some(script_type @ &"sh"), Some(script_str), None, None, None)
+        | (&Method::GET, Some(script_type @ &"taproot-key"), Some(script_str), None, None, None) => {
+            let script_hash = to_scripthash(query, script_type, script_str, config.network_type)?;
+            let (chain_stats, activity) = query.chain().address_summary(&script_hash[..])?;
+            let stats = (chain_stats, query.mempool().stats(&script_hash[..]));
+            let looked_up_by = |kind: &str| {
+                if *script_type == kind {
+                    Some(script_str.to_string())
+                } else {
+                    None
+                }
+            };
+            #[cfg(not(feature = "liquid"))]
+            let fiat = {
+                let balance = (stats.0.funded_txo_sum + stats.1.funded_txo_sum)
+                    .saturating_sub(stats.0.spent_txo_sum + stats.1.spent_txo_sum);
+                query.fiat_value(balance, None)
+            };
             json_response(
-                json!({
-                    *script_type: script_str,
-                    "chain_stats": stats.0,
-                    "mempool_stats": stats.1,
-                }),
+                AddressInfoValue {
+                    address: looked_up_by("address"),
+                    scripthash: looked_up_by("scripthash"),
+                    pkh: looked_up_by("pkh"),
+                    sh: looked_up_by("sh"),
+                    taproot_key: looked_up_by("taproot-key"),
+                    tx_count: stats.0.tx_count + stats.1.tx_count,
+                    chain_stats: stats.0.into(),
+                    mempool_stats: stats.1.into(),
+                    chain_activity: activity.map(Into::into),
+                    #[cfg(not(feature = "liquid"))]
+                    fiat,
+                },
                 TTL_SHORT,
             )
         }
@@ -817,7 +1889,142 @@ fn handle_request(
             &Method::GET,
             Some(script_type @ &"address"),
             Some(script_str),
-            Some(&"txs"),
+            Some(&"txs"),
+            None,
+            None,
+        )
+        | (
+            &Method::GET,
+            Some(script_type @ &"scripthash"),
+            Some(script_str),
+            Some(&"txs"),
+            None,
+            None,
+        )
+        | (&Method::GET, Some(script_type @ &"pkh"), Some(script_str), Some(&"txs"), None, None)
+        | (&Method::GET, Some(script_type @ &"sh"), Some(script_str), Some(&"txs"), None, None)
+        | (
+            &Method::GET,
+            Some(script_type @ &"taproot-key"),
+            Some(script_str),
+            Some(&"txs"),
+            None,
+            None,
+        ) => {
+            let script_hash = to_scripthash(query, script_type, script_str, config.network_type)?;
+
+            let after_txid = query_params
+                .get("after_txid")
+                .and_then(|t| Txid::from_str(t).ok());
+
+            let filter = history_filter(&query_params, query)?;
+
+            let mut txs = vec![];
+
+            if after_txid.is_none() {
+                txs.extend(
+                    query
+                        .mempool()
+                        .history_filtered(
+                            &script_hash[..],
+                            config.rest_default_max_mempool_txs,
+                            &filter,
+                        )
+                        .into_iter()
+                        .map(|tx| (tx, None)),
+                );
+            }
+
+            txs.extend(
+                query
+                    .chain()
+                    .history_filtered(
+                        &script_hash[..],
+                        after_txid.as_ref(),
+                        config.rest_default_chain_txs_per_page,
+                        &filter,
+                    )?
+                    .into_iter()
+                    .map(|(tx, blockid)| (tx, Some(blockid))),
+            );
+
+            json_response(prepare_txs(txs, query, config)?, TTL_SHORT)
+        }
+
+        #[cfg(not(feature = "liquid"))]
+        (
+            &Method::GET,
+            Some(script_type @ &"address"),
+            Some(script_str),
+            Some(&"txs"),
+            Some(&"export"),
+            None,
+        )
+        | (
+            &Method::GET,
+            Some(script_type @ &"scripthash"),
+            Some(script_str),
+            Some(&"txs"),
+            Some(&"export"),
+            None,
+        )
+        | (
+            &Method::GET,
+            Some(script_type @ &"pkh"),
+            Some(script_str),
+            Some(&"txs"),
+            Some(&"export"),
+            None,
+        )
+        | (
+            &Method::GET,
+            Some(script_type @ &"sh"),
+            Some(script_str),
+            Some(&"txs"),
+            Some(&"export"),
+            None,
+        )
+        | (
+            &Method::GET,
+            Some(script_type @ &"taproot-key"),
+            Some(script_str),
+            Some(&"txs"),
+            Some(&"export"),
+            None,
+        ) => {
+            let script_hash = to_scripthash(query, script_type, script_str, config.network_type)?;
+            let format = match query_params.get("format").map(String::as_str) {
+                None | Some("csv") => ExportFormat::Csv,
+                Some("jsonl") => ExportFormat::Jsonl,
+                Some(_) => {
+                    return Err(HttpError::invalid_param(
+                        "Invalid format (expected csv or jsonl)".to_string(),
+                    ))
+                }
+            };
+            let height_param = |name: &str| {
+                query_params
+                    .get(name)
+                    .map(|height| height.parse::<usize>())
+                    .transpose()
+            };
+            let from_height = height_param("from_height")?.unwrap_or(0);
+            let to_height = height_param("to_height")?.unwrap_or(usize::MAX);
+            Ok(export_history(
+                Arc::clone(query),
+                script_hash,
+                script_str,
+                (from_height, to_height),
+                format,
+                amount_format(&uri, config)?,
+            ))
+        }
+        #[cfg(not(feature = "liquid"))]
+        (
+            &Method::GET,
+            Some(script_type @ &"address"),
+            Some(script_str),
+            Some(&"ledger"),
             None,
             None,
         )
@@ -825,41 +2032,44 @@ fn handle_request(
             &Method::GET,
             Some(script_type @ &"scripthash"),
             Some(script_str),
-            Some(&"txs"),
+            Some(&"ledger"),
+            None,
+            None,
+        )
+        | (
+            &Method::GET,
+            Some(script_type @ &"pkh"),
+            Some(script_str),
+            Some(&"ledger"),
+            None,
+            None,
+        )
+        | (
+            &Method::GET,
+            Some(script_type @ &"sh"),
+            Some(script_str),
+            Some(&"ledger"),
+            None,
+            None,
+        )
+        | (
+            &Method::GET,
+            Some(script_type @ &"taproot-key"),
+            Some(script_str),
+            Some(&"ledger"),
             None,
             None,
         ) => {
-            let script_hash = to_scripthash(script_type, script_str, config.network_type)?;
-
-            let after_txid = query_params.get("after_txid").and_then(|t| Txid::from_str(t).ok());
-
-            let mut txs = vec![];
-
-            if after_txid.is_none() {
-                txs.extend(
-                    query
-                        .mempool()
-                        .history(&script_hash[..], config.rest_default_max_mempool_txs)
-                        .into_iter()
-                        .map(|tx| (tx, None)),
-                );
-            }
-
-            txs.extend(
-                query
-                    .chain()
-                    .history(
-                        &script_hash[..],
-                        after_txid.as_ref(),
-                        config.rest_default_chain_txs_per_page,
-                    )
-                    .into_iter()
-                    .map(|(tx, blockid)| (tx, Some(blockid))),
-            );
-
-            json_response(prepare_txs(txs, query, config), TTL_SHORT)
+            let script_hash = to_scripthash(query, script_type, script_str, config.network_type)?;
+            let after = query_params
+                .get("after")
+                .map_or(Ok(0), |after| after.parse::<u64>())?;
+            let limit = query_params
+                .get("limit")
+                .map_or(Ok(LEDGER_ENTRIES_DEFAULT), |limit| limit.parse::<usize>())?
+                .min(LEDGER_ENTRIES_MAX);
+            json_response(query.chain().ledger(&script_hash, after, limit)?, TTL_SHORT)
         }
-
         (
             &Method::GET,
             Some(script_type @ &"address"),
@@ -875,22 +2085,48 @@ fn handle_request(
             Some(&"txs"),
             Some(&"chain"),
             last_seen_txid,
+        )
+        | (
+            &Method::GET,
+            Some(script_type @ &"pkh"),
+            Some(script_str),
+            Some(&"txs"),
+            Some(&"chain"),
+            last_seen_txid,
+        )
+        | (
+            &Method::GET,
+            Some(script_type @ &"sh"),
+            Some(script_str),
+            Some(&"txs"),
+            Some(&"chain"),
+            last_seen_txid,
+        )
+        | (
+            &Method::GET,
+            Some(script_type @ &"taproot-key"),
+            Some(script_str),
+            Some(&"txs"),
+            Some(&"chain"),
+            last_seen_txid,
         ) => {
-            let script_hash = to_scripthash(script_type, script_str, config.network_type)?;
+            let script_hash = to_scripthash(query, script_type, script_str, config.network_type)?;
             let last_seen_txid = last_seen_txid.and_then(|txid| Txid::from_str(txid).ok());
+            let filter = history_filter(&query_params, query)?;
 
             let txs = query
                 .chain()
-                .history(
+                .history_filtered(
                     &script_hash[..],
                     last_seen_txid.as_ref(),
                     config.rest_default_chain_txs_per_page,
-                )
+                    &filter,
+                )?
                 .into_iter()
                 .map(|(tx, blockid)| (tx, Some(blockid)))
                 .collect();
 
-            json_response(prepare_txs(txs, query, config), TTL_SHORT)
+            json_response(prepare_txs(txs, query, config)?, TTL_SHORT)
         }
         (
             &Method::GET,
@@ -907,8 +2143,32 @@ fn handle_request(
             Some(&"txs"),
             Some(&"mempool"),
             None,
+        )
+        | (
+            &Method::GET,
+            Some(script_type @ &"pkh"),
+            Some(script_str),
+            Some(&"txs"),
+            Some(&"mempool"),
+            None,
+        )
+        | (
+            &Method::GET,
+            Some(script_type @ &"sh"),
+            Some(script_str),
+            Some(&"txs"),
+            Some(&"mempool"),
+            None,
+        )
+        | (
+            &Method::GET,
+            Some(script_type @ &"taproot-key"),
+            Some(script_str),
+            Some(&"txs"),
+            Some(&"mempool"),
+            None,
         ) => {
-            let script_hash = to_scripthash(script_type, script_str, config.network_type)?;
+            let script_hash = to_scripthash(query, script_type, script_str, config.network_type)?;
 
             let txs = query
                 .mempool()
@@ -917,7 +2177,54 @@ fn handle_request(
                 .map(|tx| (tx, None))
                 .collect();
 
-            json_response(prepare_txs(txs, query, config), TTL_SHORT)
+            json_response(prepare_txs(txs, query, config)?, TTL_SHORT)
+        }
+        (
+            &Method::GET,
+            Some(script_type @ &"address"),
+            Some(script_str),
+            Some(&"events"),
+            None,
+            None,
+        )
+        | (
+            &Method::GET,
+            Some(script_type @ &"scripthash"),
+            Some(script_str),
+            Some(&"events"),
+            None,
+            None,
+        )
+        | (
+            &Method::GET,
+            Some(script_type @ &"pkh"),
+            Some(script_str),
+            Some(&"events"),
+            None,
+            None,
+        )
+        | (
+            &Method::GET,
+            Some(script_type @ &"sh"),
+            Some(script_str),
+            Some(&"events"),
+            None,
+            None,
+        )
+        | (
+            &Method::GET,
+            Some(script_type @ &"taproot-key"),
+            Some(script_str),
+            Some(&"events"),
+            None,
+            None,
+        ) => {
+            let script_hash = to_scripthash(query, script_type, script_str, config.network_type)?;
+            let since = query_params
+                .get("since")
+                .ok_or_else(|| HttpError::invalid_param("Missing since".to_string()))?
+                .parse::<u64>()?;
+            json_response(query.mempool().events(&script_hash[..], since), TTL_SHORT)
         }
 
         (
@@ -935,8 +2242,18 @@ fn handle_request(
             Some(&"utxo"),
             None,
             None,
+        )
+        | (&Method::GET, Some(script_type @ &"pkh"), Some(script_str), Some(&"utxo"), None, None)
+        | (&Method::GET, Some(script_type @ &"sh"), Some(script_str), Some(&"utxo"), None, None)
+        | (
+            &Method::GET,
+            Some(script_type @ &"taproot-key"),
+            Some(script_str),
+            Some(&"utxo"),
+            None,
+            None,
         ) => {
-            let script_hash = to_scripthash(script_type, script_str, config.network_type)?;
+            let script_hash = to_scripthash(query, script_type, script_str, config.network_type)?;
             let utxos: Vec<UtxoValue> = query
                 .utxo(&script_hash[..])?
                 .into_iter()
@@ -947,36 +2264,80 @@ fn handle_request(
         }
         (&Method::GET, Some(&"address-prefix"), Some(prefix), None, None, None) => {
             if !config.address_search {
-                return Err(HttpError::from("address search disabled".to_string()));
+                return Err(HttpError::disabled("address search disabled".to_string()));
+            }
+            json_response(address_search(prefix, query, config)?, TTL_SHORT)
+        }
+        (&Method::GET, Some(&"search"), Some(search_query), None, None, None) => {
+            json_response(search(search_query, query, config)?, TTL_SHORT)
+        }
+        (&Method::POST, Some(&"addresses"), Some(&"used"), None, None, None) => {
+            let scripthashes: Vec<String> = serde_json::from_slice(&body)?;
+            if scripthashes.len() > MULTI_ADDRESS_LIMIT {
+                bail!(HttpError::invalid_param(format!(
+                    "Exceeded maximum of {} scripthashes",
+                    MULTI_ADDRESS_LIMIT
+                )));
             }
-            let results = query.chain().address_search(prefix, ADDRESS_SEARCH_LIMIT);
-            json_response(results, TTL_SHORT)
+            let scripthashes = scripthashes
+                .iter()
+                .map(|scripthash| {
+                    to_scripthash(query, "scripthash", scripthash, config.network_type)
+                })
+                .collect::<Result<Vec<_>, _>>()?;
+            json_response(query.scripthashes_used(&scripthashes)?, TTL_SHORT)
         }
         (&Method::GET, Some(&"tx"), Some(hash), None, None, None) => {
-            let hash = Txid::from_str(hash)?;
-            let tx = query
-                .lookup_txn(&hash)
-                .ok_or_else(|| HttpError::not_found("Transaction not found".to_string()))?;
-            let blockid = query.chain().tx_confirming_block(&hash);
+            let hash = query.resolve_txid(Txid::from_str(hash)?)?;
+            let (tx, evicted) = match query.lookup_txn(&hash)? {
+                Some(tx) => (tx, false),
+                None => query
+                    .lookup_evicted_txn(&hash)
+                    .map(|tx| (tx, true))
+                    .ok_or_else(|| HttpError::tx_not_found())?,
+            };
+            let blockid = query.chain().tx_confirming_block(&hash)?;
+            check_min_conf_height(&query_params, blockid.as_ref().map(|b| b.height))?;
             let ttl = ttl_by_depth(blockid.as_ref().map(|b| b.height), query);
 
-            let tx = prepare_txs(vec![(tx, blockid)], query, config).remove(0);
+            let parse_offset = |name: &str| {
+                query_params
+                    .get(name)
+                    .map_or(Ok(0), |offset| offset.parse::<usize>())
+            };
+            let page = TxIoPage {
+                vin_offset: parse_offset("vin_offset")?,
+                vout_offset: parse_offset("vout_offset")?,
+            };
+            let with_prevouts = prevouts_param(&query_params)?;
+            let mut tx = prepare_txs_paged(
+                vec![(tx, blockid)],
+                query,
+                config,
+                Some(page),
+                with_prevouts,
+            )?
+            .remove(0);
+            if evicted {
+                tx.status = Some(query.get_tx_status(&hash)?.into());
+            }
 
+            let ttl = ttl_with_fiat(ttl, std::slice::from_ref(&tx));
             json_response(tx, ttl)
         }
         (&Method::GET, Some(&"tx"), Some(hash), Some(out_type @ &"hex"), None, None)
         | (&Method::GET, Some(&"tx"), Some(hash), Some(out_type @ &"raw"), None, None) => {
-            let hash = Txid::from_str(hash)?;
+            let hash = query.resolve_txid(Txid::from_str(hash)?)?;
             let rawtx = query
-                .lookup_raw_txn(&hash)
-                .ok_or_else(|| HttpError::not_found("Transaction not found".to_string()))?;
+                .lookup_raw_txn(&hash)?
+                .ok_or_else(|| HttpError::tx_not_found())?;
 
             let (content_type, body) = match *out_type {
                 "raw" => ("application/octet-stream", Body::from(rawtx)),
                 "hex" => ("text/plain", Body::from(rawtx.to_lower_hex_string())),
                 _ => unreachable!(),
             };
-            let ttl = ttl_by_depth(query.get_tx_status(&hash).block_height, query);
+            let ttl = ttl_by_depth(query.get_tx_status(&hash)?.block_height, query);
 
             Ok(Response::builder()
                 .status(StatusCode::OK)
@@ -986,14 +2347,14 @@ fn handle_request(
                 .unwrap())
         }
         (&Method::GET, Some(&"tx"), Some(hash), Some(&"status"), None, None) => {
-            let hash = Txid::from_str(hash)?;
-            let status = query.get_tx_status(&hash);
+            let hash = query.resolve_txid(Txid::from_str(hash)?)?;
+            let status = query.get_tx_status(&hash)?;
+            check_min_conf_height(&query_params, status.block_height)?;
             let ttl = ttl_by_depth(status.block_height, query);
             json_response(status, ttl)
         }
         (&Method::POST, Some(&INTERNAL_PREFIX), Some(&"txs"), None, None, None) => {
-            let txid_strings: Vec<String> =
-                serde_json::from_slice(&body).map_err(|err| HttpError::from(err.to_string()))?;
+            let txid_strings: Vec<String> = serde_json::from_slice(&body)?;
 
             match txid_strings
                 .into_iter()
@@ -1001,40 +2362,58 @@ fn handle_request(
                 .collect::<Result<Vec<Txid>, _>>()
             {
                 Ok(txids) => {
-                    let txs: Vec<(Transaction, Option<BlockId>)> = txids
-                        .iter()
-                        .filter_map(|txid| {
-                            query
-                                .lookup_txn(txid)
-                                .map(|tx| (tx, query.chain().tx_confirming_block(txid)))
-                        })
-                        .collect();
-                    json_response(prepare_txs(txs, query, config), 0)
+                    let mut txs: Vec<(Transaction, Option<BlockId>)> = vec![];
+                    for txid in &txids {
+                        if let Some(tx) = query.lookup_txn(txid)? {
+                            txs.push((tx, query.chain().tx_confirming_block(txid)?));
+                        }
+                    }
+                    json_response(prepare_txs(txs, query, config)?, 0)
                 }
-                Err(err) => http_message(StatusCode::BAD_REQUEST, err.to_string(), 0),
+                Err(err) => Err(err.into()),
             }
         }
 
+        (&Method::GET, Some(&"tx"), Some(hash), Some(&"conflicts"), None, None) => {
+            let hash = query.resolve_txid(Txid::from_str(hash)?)?;
+            let tx = query
+                .lookup_txn(&hash)?
+                .or_else(|| query.lookup_evicted_txn(&hash))
+                .ok_or_else(|| HttpError::tx_not_found())?;
+            json_response(query.tx_conflicts(&tx)?, TTL_SHORT)
+        }
         (&Method::GET, Some(&"tx"), Some(hash), Some(&"merkle-proof"), None, None) => {
-            let hash = Txid::from_str(hash)?;
-            let blockid = query.chain().tx_confirming_block(&hash).ok_or_else(|| {
-                HttpError::not_found("Transaction not found or is unconfirmed".to_string())
+            let hash = query.resolve_txid(Txid::from_str(hash)?)?;
+            let blockid = query.chain().tx_confirming_block(&hash)?.ok_or_else(|| {
+                HttpError::new(
+                    StatusCode::NOT_FOUND,
+                    "TX_NOT_CONFIRMED",
+                    "Transaction not found or is unconfirmed".to_string(),
+                )
             })?;
             let (merkle, pos) =
                 electrum_merkle::get_tx_merkle_proof(query.chain(), &hash, &blockid.hash)?;
             let merkle: Vec<String> = merkle.into_iter().map(|txid| txid.to_string()).collect();
             let ttl = ttl_by_depth(Some(blockid.height), query);
             json_response(
-                json!({ "block_height": blockid.height, "merkle": merkle, "pos": pos }),
+                MerkleProofValue {
+                    block_height: blockid.height,
+                    merkle,
+                    pos,
+                },
                 ttl,
             )
         }
         #[cfg(not(feature = "liquid"))]
         (&Method::GET, Some(&"tx"), Some(hash), Some(&"merkleblock-proof"), None, None) => {
-            let hash = Txid::from_str(hash)?;
+            let hash = query.resolve_txid(Txid::from_str(hash)?)?;
 
-            let merkleblock = query.chain().get_merkleblock_proof(&hash).ok_or_else(|| {
-                HttpError::not_found("Transaction not found or is unconfirmed".to_string())
+            let merkleblock = query.chain().get_merkleblock_proof(&hash)?.ok_or_else(|| {
+                HttpError::new(
+                    StatusCode::NOT_FOUND,
+                    "TX_NOT_CONFIRMED",
+                    "Transaction not found or is unconfirmed".to_string(),
+                )
             })?;
 
             let height = query
@@ -1048,13 +2427,13 @@ fn handle_request(
             )
         }
         (&Method::GET, Some(&"tx"), Some(hash), Some(&"outspend"), Some(index), None) => {
-            let hash = Txid::from_str(hash)?;
+            let hash = query.resolve_txid(Txid::from_str(hash)?)?;
             let outpoint = OutPoint {
                 txid: hash,
                 vout: index.parse::<u32>()?,
             };
             let spend = query
-                .lookup_spend(&outpoint)
+                .lookup_spend(&outpoint)?
                 .map_or_else(SpendingValue::default, SpendingValue::from);
             let ttl = ttl_by_depth(
                 spend
@@ -1066,12 +2445,12 @@ fn handle_request(
             json_response(spend, ttl)
         }
         (&Method::GET, Some(&"tx"), Some(hash), Some(&"outspends"), None, None) => {
-            let hash = Txid::from_str(hash)?;
+            let hash = query.resolve_txid(Txid::from_str(hash)?)?;
             let tx = query
-                .lookup_txn(&hash)
-                .ok_or_else(|| HttpError::not_found("Transaction not found".to_string()))?;
+                .lookup_txn(&hash)?
+                .ok_or_else(|| HttpError::tx_not_found())?;
             let spends: Vec<SpendingValue> = query
-                .lookup_tx_spends(tx)
+                .lookup_tx_spends(tx)?
                 .into_iter()
                 .map(|spend| spend.map_or_else(SpendingValue::default, SpendingValue::from))
                 .collect();
@@ -1086,26 +2465,27 @@ fn handle_request(
             Some(&"by-txid"),
             None,
         ) => {
-            let txid_strings: Vec<String> =
-                serde_json::from_slice(&body).map_err(|err| HttpError::from(err.to_string()))?;
+            let txid_strings: Vec<String> = serde_json::from_slice(&body)?;
 
             let spends: Vec<Vec<SpendingValue>> = txid_strings
                 .into_iter()
-                .map(|txid_str| {
-                    Txid::from_str(&txid_str)
-                        .ok()
-                        .and_then(|txid| query.lookup_txn(&txid))
-                        .map_or_else(Vec::new, |tx| {
-                            query
-                                .lookup_tx_spends(tx)
-                                .into_iter()
-                                .map(|spend| {
-                                    spend.map_or_else(SpendingValue::default, SpendingValue::from)
-                                })
-                                .collect()
-                        })
+                .map(|txid_str| -> Result<_, HttpError> {
+                    let tx = match Txid::from_str(&txid_str) {
+                        Ok(txid) => query.lookup_txn(&txid)?,
+                        Err(_) => None,
+                    };
+                    Ok(match tx {
+                        Some(tx) => query
+                            .lookup_tx_spends(tx)?
+                            .into_iter()
+                            .map(|spend| {
+                                spend.map_or_else(SpendingValue::default, SpendingValue::from)
+                            })
+                            .collect(),
+                        None => vec![],
+                    })
                 })
-                .collect();
+                .collect::<Result<_, _>>()?;
 
             json_response(spends, TTL_SHORT)
         }
@@ -1117,12 +2497,11 @@ fn handle_request(
             Some(&"by-outpoint"),
             None,
         ) => {
-            let outpoint_strings: Vec<String> =
-                serde_json::from_slice(&body).map_err(|err| HttpError::from(err.to_string()))?;
+            let outpoint_strings: Vec<String> = serde_json::from_slice(&body)?;
 
             let spends: Vec<SpendingValue> = outpoint_strings
                 .into_iter()
-                .map(|outpoint_str| {
+                .map(|outpoint_str| -> Result<_, HttpError> {
                     let mut parts = outpoint_str.split(':');
                     let hash_part = parts.next();
                     let index_part = parts.next();
@@ -1130,14 +2509,14 @@ fn handle_request(
                     if let (Some(hash), Some(index)) = (hash_part, index_part) {
                         if let (Ok(txid), Ok(vout)) = (Txid::from_str(hash), index.parse::<u32>()) {
                             let outpoint = OutPoint { txid, vout };
-                            return query
-                                .lookup_spend(&outpoint)
-                                .map_or_else(SpendingValue::default, SpendingValue::from);
+                            return Ok(query
+                                .lookup_spend(&outpoint)?
+                                .map_or_else(SpendingValue::default, SpendingValue::from));
                         }
                     }
-                    SpendingValue::default()
+                    Ok(SpendingValue::default())
                 })
-                .collect();
+                .collect::<Result<_, _>>()?;
 
             json_response(spends, TTL_SHORT)
         }
@@ -1151,11 +2530,42 @@ fn handle_request(
                     .get("tx")
                     .cloned()
                     .ok_or_else(|| HttpError::from("Missing tx".to_string()))?,
-                _ => return http_message(StatusCode::METHOD_NOT_ALLOWED, "Invalid method", 0),
+                _ => {
+                    bail!(HttpError::new(
+                        StatusCode::METHOD_NOT_ALLOWED,
+                        "METHOD_NOT_ALLOWED",
+                        "Invalid method".to_string(),
+                    ))
+                }
             };
             let txid = query.broadcast_raw(&txhex)?;
             http_message(StatusCode::OK, txid.to_string(), 0)
         }
+        #[cfg(not(feature = "liquid"))]
+        (&Method::POST, Some(&"tx"), Some(&"debug-input"), None, None, None) => {
+            let txhex = String::from_utf8(body.to_vec())?;
+            let tx: Transaction = encode::deserialize(&Vec::<u8>::from_hex(txhex.trim())?)?;
+            let vin = query_params
+                .get("vin")
+                .map_or(Ok(0), |vin| vin.parse::<usize>())?;
+            let txin = tx.input.get(vin).ok_or_else(|| {
+                HttpError::out_of_range(format!("Transaction has no input {}", vin))
+            })?;
+            if is_coinbase(txin) {
+                bail!(HttpError::invalid_param(
+                    "Coinbase inputs have no scripts to execute".to_string()
+                ));
+            }
+            let prevout = query.lookup_txo(&txin.previous_output)?.ok_or_else(|| {
+                HttpError::new(
+                    StatusCode::NOT_FOUND,
+                    "PREVOUT_NOT_FOUND",
+                    format!("Spent output {} not found", txin.previous_output),
+                )
+            })?;
+
+            json_response(interpreter::trace_input(&tx, vin, &prevout), TTL_SHORT)
+        }
         (&Method::POST, Some(&"txs"), Some(&"test"), None, None, None) => {
             let txhexes: Vec<String> =
                 serde_json::from_str(String::from_utf8(body.to_vec())?.as_str())?;
@@ -1170,7 +2580,7 @@ fn handle_request(
                 .get("maxfeerate")
                 .map(|s| {
                     s.parse::<f64>()
-                        .map_err(|_| HttpError::from("Invalid maxfeerate".to_string()))
+                        .map_err(|_| HttpError::invalid_param("Invalid maxfeerate".to_string()))
                 })
                 .transpose()?;
 
@@ -1208,7 +2618,7 @@ fn handle_request(
                 .get("maxfeerate")
                 .map(|s| {
                     s.parse::<f64>()
-                        .map_err(|_| HttpError::from("Invalid maxfeerate".to_string()))
+                        .map_err(|_| HttpError::invalid_param("Invalid maxfeerate".to_string()))
                 })
                 .transpose()?;
 
@@ -1216,7 +2626,7 @@ fn handle_request(
                 .get("maxburnamount")
                 .map(|s| {
                     s.parse::<f64>()
-                        .map_err(|_| HttpError::from("Invalid maxburnamount".to_string()))
+                        .map_err(|_| HttpError::invalid_param("Invalid maxburnamount".to_string()))
                 })
                 .transpose()?;
 
@@ -1263,7 +2673,9 @@ fn handle_request(
             let max_txs = query_params
                 .get("max_txs")
                 .and_then(|s| s.parse::<usize>().ok())
-                .unwrap_or(config.rest_max_mempool_txid_page_size);
+                .map_or(config.rest_max_mempool_txid_page_size, |max_txs| {
+                    max_txs.min(config.rest_max_mempool_txid_page_size)
+                });
             let page = {
                 let mempool = query.mempool();
                 mempool
@@ -1290,11 +2702,14 @@ fn handle_request(
                     .map(|tx| (tx, None))
                     .collect::<Vec<_>>()
             };
-            json_response(prepare_txs(txs, query, config), TTL_SHORT)
+            json_response(prepare_txs(txs, query, config)?, TTL_SHORT)
+        }
+        (&Method::POST, Some(&INTERNAL_PREFIX), Some(&"reload"), None, None, None) => {
+            config.reload_runtime()?;
+            http_message(StatusCode::OK, "reloaded", 0)
         }
         (&Method::POST, Some(&INTERNAL_PREFIX), Some(&"mempool"), Some(&"txs"), None, None) => {
-            let txid_strings: Vec<String> =
-                serde_json::from_slice(&body).map_err(|err| HttpError::from(err.to_string()))?;
+            let txid_strings: Vec<String> = serde_json::from_slice(&body)?;
 
             match txid_strings
                 .into_iter()
@@ -1309,43 +2724,334 @@ fn handle_request(
                             .filter_map(|txid| mempool.lookup_txn(txid).map(|tx| (tx, None)))
                             .collect::<Vec<_>>()
                     };
-                    json_response(prepare_txs(txs, query, config), 0)
+                    json_response(prepare_txs(txs, query, config)?, 0)
                 }
-                Err(err) => http_message(StatusCode::BAD_REQUEST, err.to_string(), 0),
+                Err(err) => Err(err.into()),
+            }
+        }
+        (
+            &Method::GET,
+            Some(&INTERNAL_PREFIX),
+            Some(&"mempool"),
+            Some(&"txs"),
+            last_seen_txid,
+            None,
+        ) => {
+            let last_seen_txid = last_seen_txid.and_then(|txid| Txid::from_str(txid).ok());
+            let max_txs = query_params
+                .get("max_txs")
+                .and_then(|s| s.parse::<usize>().ok())
+                .map_or(config.rest_max_mempool_page_size, |max_txs| {
+                    max_txs.min(config.rest_max_mempool_page_size)
+                });
+            let txs = {
+                let mempool = query.mempool();
+                mempool
+                    .txs_page(max_txs, last_seen_txid)
+                    .into_iter()
+                    .map(|tx| (tx, None))
+                    .collect::<Vec<_>>()
+            };
+
+            json_response(prepare_txs(txs, query, config)?, TTL_SHORT)
+        }
+        (&Method::GET, Some(&"mempool"), Some(&"recent"), None, None, None) => {
+            let mempool = query.mempool();
+            let recent = mempool.recent_txs_overview();
+            json_response(recent, TTL_MEMPOOL_RECENT)
+        }
+        (&Method::GET, Some(&"mempool"), Some(&"evicted"), None, None, None) => {
+            let limit = query_params
+                .get("limit")
+                .map(|l| l.parse::<usize>())
+                .transpose()?
+                .unwrap_or(EVICTED_TXS_DEFAULT)
+                .min(EVICTED_TXS_MAX);
+            let mempool = query.mempool();
+            let evicted: Vec<_> = mempool.evicted_txs().take(limit).collect();
+            json_response(evicted, TTL_MEMPOOL_RECENT)
+        }
+
+        (&Method::GET, Some(&"daemon"), Some(method), None, None, None) => {
+            if !config.daemon_passthrough {
+                return Err(HttpError::disabled(
+                    "Daemon passthrough is disabled, set --daemon-passthrough to enable it"
+                        .to_string(),
+                ));
+            }
+            if !PASSTHROUGH_METHODS.contains(method) {
+                return Err(HttpError::new(
+                    StatusCode::NOT_FOUND,
+                    "UNKNOWN_METHOD",
+                    format!(
+                        "Unknown daemon method, available: {}",
+                        PASSTHROUGH_METHODS.join(", ")
+                    ),
+                ));
+            }
+            json_response(query.daemon_passthrough(method)?, TTL_SHORT)
+        }
+        (&Method::GET, Some(&"fee-estimates"), None, None, None, None) => {
+            json_response(FeeEstimatesValue(query.estimate_fee_map()), TTL_SHORT)
+        }
+
+        (_, Some(&INTERNAL_PREFIX), Some(&"debug"), ..) if config.debug_token.is_none() => {
+            Err(HttpError::disabled(
+                "Debug endpoints are disabled, set --debug-token to enable them".to_string(),
+            ))
+        }
+        (&Method::GET, Some(&INTERNAL_PREFIX), Some(&"debug"), Some(&"chain"), None, None) => {
+            let count = query_params
+                .get("count")
+                .map(|c| c.parse::<usize>())
+                .transpose()?
+                .unwrap_or(DEBUG_HEADERS_DEFAULT)
+                .min(DEBUG_HEADERS_MAX);
+            let chain = query.chain();
+            let best_height = chain.best_height();
+            let headers = (0..count)
+                .map_while(|depth| best_height.checked_sub(depth))
+                .filter_map(|height| chain.header_by_height(height))
+                .map(|entry| DebugHeaderValue {
+                    height: entry.height(),
+                    hash: *entry.hash(),
+                    time: entry.header().time,
+                    #[cfg(not(feature = "liquid"))]
+                    chainwork: entry.chainwork().to_be_bytes().to_lower_hex_string(),
+                })
+                .collect();
+            json_response(
+                DebugChainValue {
+                    tip_height: best_height,
+                    tip_hash: chain.best_hash(),
+                    done_initial_sync: chain.store().done_initial_sync()?,
+                    read_only: chain.store().is_read_only(),
+                    headers,
+                },
+                0,
+            )
+        }
+        (&Method::GET, Some(&INTERNAL_PREFIX), Some(&"debug"), Some(&"mempool"), None, None) => {
+            let mempool = query.mempool();
+            json_response(
+                DebugMempoolValue {
+                    tracker: mempool.tracker_stats(),
+                    backlog: mempool.backlog_stats(),
+                },
+                0,
+            )
+        }
+        (
+            &Method::GET,
+            Some(&INTERNAL_PREFIX),
+            Some(&"debug"),
+            Some(&"electrum-clients"),
+            None,
+            None,
+        ) => {
+            let limit = query_params
+                .get("limit")
+                .map(|l| l.parse::<usize>())
+                .transpose()?
+                .unwrap_or(DEBUG_CLIENTS_DEFAULT)
+                .min(DEBUG_CLIENTS_MAX);
+            json_response(query.clients().top_clients(limit), 0)
+        }
+        (&Method::GET, Some(&INTERNAL_PREFIX), Some(&"debug"), Some(&"metrics"), None, None) => {
+            let prefix = query_params.get("prefix").map_or("", String::as_str);
+            json_response(metrics.snapshot(prefix), 0)
+        }
+
+        (&Method::GET, Some(&"readyz"), None, None, None, None) => {
+            if query.chain().store().is_read_only() {
+                return Err(HttpError::new(
+                    StatusCode::SERVICE_UNAVAILABLE,
+                    "READ_ONLY",
+                    "low disk space, indexing is paused".to_string(),
+                ));
+            }
+            http_message(StatusCode::OK, "ready", 0)
+        }
+
+        (&Method::GET, Some(&"openapi.json"), None, None, None, None) => {
+            json_response(&*OPENAPI, TTL_SHORT)
+        }
+
+        (&Method::GET, Some(&"v1"), Some(&"export"), Some(&"blocks"), None, None) => {
+            if query_params
+                .get("format")
+                .map_or(false, |format| format != "jsonl")
+            {
+                bail!(HttpError::invalid_param(
+                    "Invalid format (expected jsonl)".to_string()
+                ));
+            }
+            let (from, to) = (
+                height_param(&query_params, "from")?,
+                height_param(&query_params, "to")?,
+            );
+            if from > to || to > query.chain().best_height() {
+                bail!(HttpError::out_of_range(format!(
+                    "Invalid height range {}..={}",
+                    from, to
+                )));
             }
+            if to - from >= config.rest_max_export_blocks {
+                bail!(HttpError::invalid_param(format!(
+                    "Too many blocks, at most {} can be exported at once",
+                    config.rest_max_export_blocks
+                )));
+            }
+            let script_types = query_params
+                .get("script_types")
+                .map(|types| types.split(',').map(str::to_string).collect());
+            Ok(export_blocks(
+                Arc::clone(query),
+                Arc::clone(config),
+                (from, to),
+                script_types,
+                amount_format(&uri, config)?,
+            ))
+        }
+
+        (&Method::GET, Some(&"v1"), Some(&"chainstate"), Some(&"diff"), None, None) => {
+            let (from, to) = (
+                height_param(&query_params, "from")?,
+                height_param(&query_params, "to")?,
+            );
+            if from > to || to > query.chain().best_height() {
+                bail!(HttpError::out_of_range(format!(
+                    "Invalid height range {}..{}",
+                    from, to
+                )));
+            }
+            if to - from > config.rest_max_export_blocks {
+                bail!(HttpError::invalid_param(format!(
+                    "Too many blocks, the diff can span at most {}",
+                    config.rest_max_export_blocks
+                )));
+            }
+            Ok(chainstate_diff(
+                Arc::clone(query),
+                Arc::clone(config),
+                (from, to),
+                amount_format(&uri, config)?,
+            ))
+        }
+
+        (&Method::GET, Some(&"v1"), Some(&"server-info"), None, None, None) => {
+            let features = FEATURES
+                .iter()
+                .filter(|(_, enabled)| *enabled)
+                .map(|(name, _)| name.to_string())
+                .collect();
+            json_response(
+                ServerInfo {
+                    version: ELECTRS_VERSION.to_string(),
+                    git_commit: GIT_HASH.map(str::to_string),
+                    features,
+                    db_version: DB_VERSION,
+                    network: config.network_name.clone(),
+                    light_mode: config.light_mode,
+                    address_search: config.address_search,
+                    index_unspendables: config.index_unspendables,
+                    utxo_age_stats: config.utxo_age_stats,
+                    tip_height: query.chain().best_height(),
+                    uptime: query.uptime().as_secs(),
+                },
+                TTL_SHORT,
+            )
+        }
+
+        (&Method::GET, Some(&"v1"), Some(&"supply"), None, None, None) => {
+            let height = query_params
+                .get("height")
+                .map(|h| h.parse::<usize>())
+                .transpose()?;
+            let (height, supply) = query
+                .chain()
+                .supply(height)?
+                .ok_or_else(|| HttpError::block_not_found())?;
+            json_response(
+                SupplyValue {
+                    height,
+                    total: supply.total,
+                    burned: supply.burned,
+                    circulating: supply.circulating(),
+                },
+                ttl_by_depth(Some(height), query),
+            )
         }
+
         (
             &Method::GET,
-            Some(&INTERNAL_PREFIX),
-            Some(&"mempool"),
-            Some(&"txs"),
-            last_seen_txid,
-            None,
+            Some(&"v1"),
+            Some(&"mining"),
+            Some(&"blocks"),
+            Some(&"timestamp"),
+            Some(timestamp),
         ) => {
-            let last_seen_txid = last_seen_txid.and_then(|txid| Txid::from_str(txid).ok());
-            let max_txs = query_params
-                .get("max_txs")
-                .and_then(|s| s.parse::<usize>().ok())
-                .unwrap_or(config.rest_max_mempool_page_size);
-            let txs = {
-                let mempool = query.mempool();
-                mempool
-                    .txs_page(max_txs, last_seen_txid)
-                    .into_iter()
-                    .map(|tx| (tx, None))
-                    .collect::<Vec<_>>()
-            };
+            let timestamp = timestamp.parse::<u32>()?;
+            let header = query
+                .chain()
+                .header_by_time(timestamp)
+                .ok_or_else(|| HttpError::block_not_found())?;
+            json_response(
+                BlockTimestampValue {
+                    height: header.height(),
+                    hash: *header.hash(),
+                    timestamp: header.header().time,
+                },
+                ttl_by_depth(Some(header.height()), query),
+            )
+        }
+
+        #[cfg(not(feature = "liquid"))]
+        (&Method::GET, Some(&"v1"), Some(&"mining"), Some(&"next-block"), None, None) => {
+            let next_block = query.next_block().ok_or_else(|| {
+                HttpError::new(
+                    StatusCode::SERVICE_UNAVAILABLE,
+                    "TEMPLATE_UNAVAILABLE",
+                    "No block template available from lokid".to_string(),
+                )
+            })?;
+            json_response(next_block, TTL_SHORT)
+        }
 
-            json_response(prepare_txs(txs, query, config), TTL_SHORT)
+        (&Method::GET, Some(&"burns"), None, None, None, None) => {
+            let from_height = query_params
+                .get("from_height")
+                .map_or(Ok(0), |h| h.parse::<usize>())?;
+            let burns = query.chain().burns(from_height, BURNS_PER_PAGE)?;
+            json_response(burns, TTL_SHORT)
         }
-        (&Method::GET, Some(&"mempool"), Some(&"recent"), None, None, None) => {
-            let mempool = query.mempool();
-            let recent = mempool.recent_txs_overview();
-            json_response(recent, TTL_MEMPOOL_RECENT)
+
+        (&Method::GET, Some(&"stats"), Some(&"multisig"), None, None, None) => {
+            let window = query_params
+                .get("window")
+                .map_or(Ok(MULTISIG_DEFAULT_WINDOW), |w| w.parse::<usize>())?
+                .min(MULTISIG_MAX_WINDOW);
+            json_response(
+                MultisigStatsValue {
+                    height: query.chain().best_height(),
+                    window,
+                    spends: query.chain().multisig_usage(window)?,
+                },
+                TTL_SHORT,
+            )
         }
 
-        (&Method::GET, Some(&"fee-estimates"), None, None, None, None) => {
-            json_response(query.estimate_fee_map(), TTL_SHORT)
+        (&Method::GET, Some(&"stats"), Some(&"utxo-age"), None, None, None) => {
+            if !config.utxo_age_stats {
+                return Err(HttpError::disabled("utxo age stats disabled".to_string()));
+            }
+            json_response(
+                UtxoAgeStatsValue {
+                    height: query.chain().best_height(),
+                    buckets: query.chain().utxo_age()?,
+                },
+                TTL_SHORT,
+            )
         }
 
         #[cfg(feature = "liquid")]
@@ -1377,9 +3083,13 @@ fn handle_request(
         #[cfg(feature = "liquid")]
         (&Method::GET, Some(&"asset"), Some(asset_str), None, None, None) => {
             let asset_id = AssetId::from_str(asset_str)?;
-            let asset_entry = query
-                .lookup_asset(&asset_id)?
-                .ok_or_else(|| HttpError::not_found("Asset id not found".to_string()))?;
+            let asset_entry = query.lookup_asset(&asset_id)?.ok_or_else(|| {
+                HttpError::new(
+                    StatusCode::NOT_FOUND,
+                    "ASSET_NOT_FOUND",
+                    "Asset id not found".to_string(),
+                )
+            })?;
 
             json_response(asset_entry, TTL_SHORT)
         }
@@ -1401,12 +3111,12 @@ fn handle_request(
             txs.extend(
                 query
                     .chain()
-                    .asset_history(&asset_id, None, config.rest_default_chain_txs_per_page)
+                    .asset_history(&asset_id, None, config.rest_default_chain_txs_per_page)?
                     .into_iter()
                     .map(|(tx, blockid)| (tx, Some(blockid))),
             );
 
-            json_response(prepare_txs(txs, query, config), TTL_SHORT)
+            json_response(prepare_txs(txs, query, config)?, TTL_SHORT)
         }
 
         #[cfg(feature = "liquid")]
@@ -1427,12 +3137,12 @@ fn handle_request(
                     &asset_id,
                     last_seen_txid.as_ref(),
                     config.rest_default_chain_txs_per_page,
-                )
+                )?
                 .into_iter()
                 .map(|(tx, blockid)| (tx, Some(blockid)))
                 .collect();
 
-            json_response(prepare_txs(txs, query, config), TTL_SHORT)
+            json_response(prepare_txs(txs, query, config)?, TTL_SHORT)
         }
 
         #[cfg(feature = "liquid")]
@@ -1446,15 +3156,19 @@ fn handle_request(
                 .map(|tx| (tx, None))
                 .collect();
 
-            json_response(prepare_txs(txs, query, config), TTL_SHORT)
+            json_response(prepare_txs(txs, query, config)?, TTL_SHORT)
         }
 
         #[cfg(feature = "liquid")]
         (&Method::GET, Some(&"asset"), Some(asset_str), Some(&"supply"), param, None) => {
             let asset_id = AssetId::from_str(asset_str)?;
-            let asset_entry = query
-                .lookup_asset(&asset_id)?
-                .ok_or_else(|| HttpError::not_found("Asset id not found".to_string()))?;
+            let asset_entry = query.lookup_asset(&asset_id)?.ok_or_else(|| {
+                HttpError::new(
+                    StatusCode::NOT_FOUND,
+                    "ASSET_NOT_FOUND",
+                    "Asset id not found".to_string(),
+                )
+            })?;
 
             let supply = asset_entry
                 .supply()
@@ -1469,10 +3183,21 @@ fn handle_request(
             }
         }
 
-        _ => Err(HttpError::not_found(format!(
-            "endpoint does not exist {:?}",
-            uri.path()
-        ))),
+        (&Method::GET, Some(&"asset"), ..) | (&Method::GET, Some(&"assets"), ..)
+            if !<ActiveChain as ChainParams>::HAS_ASSET_ENDPOINTS =>
+        {
+            Err(HttpError::new(
+                StatusCode::NOT_FOUND,
+                "ENDPOINT_NOT_FOUND",
+                "asset endpoints are not available on this chain".to_string(),
+            ))
+        }
+
+        _ => Err(HttpError::new(
+            StatusCode::NOT_FOUND,
+            "ENDPOINT_NOT_FOUND",
+            format!("endpoint does not exist {:?}", uri.path()),
+        )),
     }
 }
 
@@ -1510,7 +3235,7 @@ fn blocks(
         Some(height) => *query
             .chain()
             .header_by_height(height)
-            .ok_or_else(|| HttpError::not_found("Block not found".to_string()))?
+            .ok_or_else(|| HttpError::block_not_found())?
             .hash(),
         None => query.chain().best_hash(),
     };
@@ -1519,12 +3244,12 @@ fn blocks(
     for _ in 0..config.rest_default_block_limit {
         let blockhm = query
             .chain()
-            .get_block_with_meta(&current_hash)
-            .ok_or_else(|| HttpError::not_found("Block not found".to_string()))?;
+            .get_block_with_meta(&current_hash)?
+            .ok_or_else(|| HttpError::block_not_found())?;
         current_hash = blockhm.header_entry.header().prev_blockhash;
 
         #[allow(unused_mut)]
-        let mut value = BlockValue::new(blockhm, config);
+        let mut value = block_value(blockhm, config);
 
         #[cfg(feature = "liquid")]
         {
@@ -1540,16 +3265,135 @@ fn blocks(
     json_response(values, TTL_SHORT)
 }
 
+#[derive(Serialize, ToSchema)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum SearchResult {
+    Block {
+        height: usize,
+        #[schema(value_type = String)]
+        hash: BlockHash,
+    },
+    Tx {
+        #[schema(value_type = String)]
+        txid: Txid,
+        confirmed: bool,
+    },
+    Address {
+        address: String,
+    },
+    // an indexed address starting with the query
+    AddressPrefix {
+        address: String,
+    },
+}
+
+// Everything the query could refer to: a block height, a block hash or txid (or wtxid), an
+// address, or else the start of indexed addresses
+fn search(
+    search_query: &str,
+    query: &Query,
+    config: &Config,
+) -> Result<Vec<SearchResult>, HttpError> {
+    let mut results = vec![];
+
+    if let Ok(height) = search_query.parse::<usize>() {
+        if let Some(header) = query.chain().header_by_height(height) {
+            results.push(SearchResult::Block {
+                height,
+                hash: *header.hash(),
+            });
+        }
+    }
+
+    if search_query.len() == 64 {
+        if let Ok(hash) = BlockHash::from_str(search_query) {
+            if let Some(height) = query.chain().height_by_hash(&hash) {
+                results.push(SearchResult::Block { height, hash });
+            }
+        }
+        if let Ok(txid) = Txid::from_str(search_query) {
+            let txid = query.resolve_txid(txid)?;
+            if query.chain().tx_confirming_block(&txid)?.is_some() {
+                results.push(SearchResult::Tx {
+                    txid,
+                    confirmed: true,
+                });
+            } else if query.mempool().lookup_txn(&txid).is_some() {
+                results.push(SearchResult::Tx {
+                    txid,
+                    confirmed: false,
+                });
+            }
+        }
+    }
+
+    let scripthash = address_to_scripthash(search_query, config.network_type);
+    if scripthash.map_or(false, |scripthash| !query.is_blocked(&scripthash)) {
+        results.push(SearchResult::Address {
+            address: search_query.to_string(),
+        });
+    }
+
+    if results.is_empty() && config.address_search {
+        results.extend(
+            address_search(search_query, query, config)?
+                .into_iter()
+                .map(|address| SearchResult::AddressPrefix { address }),
+        );
+    }
+
+    Ok(results)
+}
+
+// The addresses starting with the prefix, leaving out the ones of the `--blocklist`
+fn address_search(prefix: &str, query: &Query, config: &Config) -> Result<Vec<String>, HttpError> {
+    let mut addresses = query.chain().address_search(prefix, ADDRESS_SEARCH_LIMIT)?;
+    addresses.retain(|address| {
+        address_to_scripthash(address, config.network_type)
+            .map_or(true, |scripthash| !query.is_blocked(&scripthash))
+    });
+    Ok(addresses)
+}
+
+// Get the scripthash of the script, refusing the ones of the `--blocklist`
 fn to_scripthash(
+    query: &Query,
     script_type: &str,
     script_str: &str,
     network: Network,
 ) -> Result<FullHash, HttpError> {
-    match script_type {
+    let scripthash = match script_type {
         "address" => address_to_scripthash(script_str, network),
         "scripthash" => parse_scripthash(script_str),
+        "pkh" | "sh" => hash160_to_scripthash(script_type, script_str),
+        "taproot-key" => taproot_key_to_scripthash(script_str),
         _ => bail!("Invalid script type".to_string()),
-    }
+    }?;
+    query.check_scripthash(&scripthash)?;
+    Ok(scripthash)
+}
+
+// Get the scripthash of the P2PKH or P2SH script paying to the hash160
+fn hash160_to_scripthash(script_type: &str, hash160: &str) -> Result<FullHash, HttpError> {
+    let hash160 = <[u8; 20]>::from_hex(hash160)
+        .map_err(|_| HttpError::invalid_param("Invalid hash160".to_string()))?;
+    let script = match script_type {
+        // OP_DUP OP_HASH160 <hash160> OP_EQUALVERIFY OP_CHECKSIG
+        "pkh" => [&[0x76, 0xa9, 0x14][..], &hash160, &[0x88, 0xac]].concat(),
+        // OP_HASH160 <hash160> OP_EQUAL
+        _ => [&[0xa9, 0x14][..], &hash160, &[0x87]].concat(),
+    };
+    Ok(compute_script_hash(&Script::from(script)))
+}
+
+// Get the scripthash of the P2TR script with the x-only output key, which is all there is to it,
+// so that no index is needed to find the outputs of a key
+fn taproot_key_to_scripthash(output_key: &str) -> Result<FullHash, HttpError> {
+    let output_key = <[u8; 32]>::from_hex(output_key)
+        .map_err(|_| HttpError::invalid_param("Invalid x-only key".to_string()))?;
+    // OP_1 <output key>
+    let script = [&[0x51, 0x20][..], &output_key].concat();
+    Ok(compute_script_hash(&Script::from(script)))
 }
 
 fn address_to_scripthash(addr: &str, network: Network) -> Result<FullHash, HttpError> {
@@ -1564,60 +3408,405 @@ fn address_to_scripthash(addr: &str, network: Network) -> Result<FullHash, HttpE
     {
         let addr = address::Address::parse_with_params(addr, network.address_params())?;
         if addr.params != network.address_params() {
-            bail!(HttpError::from("Address on invalid network".to_string()))
+            bail!(HttpError::new(
+                StatusCode::BAD_REQUEST,
+                "INVALID_ADDRESS",
+                "Address on invalid network".to_string(),
+            ))
         }
         Ok(compute_script_hash(&addr.script_pubkey()))
     }
 }
 
 fn parse_scripthash(scripthash: &str) -> Result<FullHash, HttpError> {
-    FullHash::from_hex(scripthash).map_err(|_| HttpError::from("Invalid scripthash".to_string()))
+    FullHash::from_hex(scripthash)
+        .map_err(|_| HttpError::invalid_param("Invalid scripthash".to_string()))
+}
+
+// The debug endpoints expose internal state, so they require `Authorization: Bearer <token>`
+// matching --debug-token. Everything else is left to the upstream proxy.
+fn is_authorized(path: &str, authorization: Option<&str>, debug_token: Option<&str>) -> bool {
+    let debug_path = format!("/{}/debug", INTERNAL_PREFIX);
+    if path != debug_path && !path.starts_with(&format!("{}/", debug_path)) {
+        return true;
+    }
+    match (debug_token, authorization) {
+        (Some(token), Some(authorization)) => authorization
+            .strip_prefix("Bearer ")
+            .map_or(false, |given| {
+                constant_time_eq(given.as_bytes(), token.as_bytes())
+            }),
+        // the routes themselves report the endpoints as disabled
+        (None, _) => true,
+        (Some(_), None) => false,
+    }
+}
+
+// The routes changing the state of the server, only served on the internal lane (the internal
+// listener or the internal api key)
+fn is_internal_only(method: &Method, path: &str) -> bool {
+    method == Method::POST && path == format!("/{}/reload", INTERNAL_PREFIX)
+}
+
+// Compare without short-circuiting on the first mismatch, to avoid leaking the token by timing
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+// The filter of an address history, from the `from_height`, `to_height`, `from_time`, `to_time`
+// (confirmed within the range, inclusive), `direction`, `min_amount` and `max_amount` (in
+// satoshis) query parameters. The timestamps are resolved to heights with the headers.
+fn history_filter(
+    query_params: &HashMap<String, String>,
+    query: &Query,
+) -> Result<HistoryFilter, HttpError> {
+    let number = |name: &str| {
+        query_params
+            .get(name)
+            .map(|number| number.parse::<u64>())
+            .transpose()
+    };
+    let height_by_time = |time: u64| {
+        query
+            .chain()
+            .height_by_time(time.min(std::u32::MAX as u64) as u32)
+    };
+    // the blocks timestamped at or after `from_time`, and before `to_time + 1`
+    let from_heights = [
+        number("from_height")?.map(|height| height as usize),
+        number("from_time")?.map(height_by_time),
+    ];
+    let to_heights = [
+        number("to_height")?.map(|height| height as usize),
+        number("to_time")?.map(|time| height_by_time(time.saturating_add(1)).saturating_sub(1)),
+    ];
+    Ok(HistoryFilter {
+        from_height: from_heights.iter().flatten().copied().max(),
+        to_height: to_heights.iter().flatten().copied().min(),
+        #[cfg(not(feature = "liquid"))]
+        direction: query_params
+            .get("direction")
+            .map(|direction| direction.parse())
+            .transpose()
+            .map_err(HttpError::invalid_param)?,
+        #[cfg(not(feature = "liquid"))]
+        min_amount: number("min_amount")?,
+        #[cfg(not(feature = "liquid"))]
+        max_amount: number("max_amount")?,
+    })
+}
+
+// The group of expensive endpoints the request belongs to, if any, for --rest-disable-endpoints
+fn endpoint_group(method: &Method, path: &[&str]) -> Option<EndpointGroup> {
+    Some(match (method, path) {
+        (
+            &Method::GET,
+            ["address" | "scripthash" | "pkh" | "sh" | "taproot-key", _, "txs", "export"],
+        )
+        | (&Method::GET, ["v1", "export" | "chainstate", ..]) => EndpointGroup::Export,
+        (&Method::POST, ["addresses", "used"])
+        | (&Method::POST, ["txs", "outspends", ..])
+        | (&Method::POST, [INTERNAL_PREFIX, "mempool", "txs"])
+        | (&Method::GET, [INTERNAL_PREFIX, "mempool", "txs", "all"]) => EndpointGroup::Bulk,
+        (
+            &Method::GET,
+            ["address" | "scripthash" | "pkh" | "sh" | "taproot-key", _, "ledger", ..],
+        ) => EndpointGroup::Ledger,
+        (&Method::GET, ["stats", ..]) | (&Method::GET, ["v1", "supply" | "mining", ..]) => {
+            EndpointGroup::Stats
+        }
+        (&Method::GET, ["search" | "address-prefix", _]) => EndpointGroup::Search,
+        _ => return None,
+    })
+}
+
+// Reject malformed ids, addresses and numbers in the request before it gets routed, so that
+// the handlers (and the database) only ever see well-formed parameters
+fn validate_request(
+    path: &[&str],
+    query_params: &HashMap<String, String>,
+) -> Result<(), HttpError> {
+    match path {
+        ["block", hash, rest @ ..] | [INTERNAL_PREFIX, "block", hash, rest @ ..] => {
+            check_hex("block hash", hash, 32)?;
+            match rest {
+                ["txs", "summary"] => (),
+                ["txid", index] | ["txs", index] => check_number("index", index)?,
+                _ => (),
+            }
+        }
+        // the literal route under /tx/, which would otherwise be checked as a txid
+        ["tx", "debug-input"] => (),
+        ["tx", txid, rest @ ..] => {
+            check_hex("txid", txid, 32)?;
+            if let ["outspend", vout] = rest {
+                check_number("vout", vout)?;
+            }
+        }
+        ["address" | "scripthash" | "pkh" | "sh" | "taproot-key", script_str, rest @ ..] => {
+            match path[0] {
+                "address" => check_len("address", script_str, MAX_ADDRESS_LEN)?,
+                "scripthash" => check_hex("scripthash", script_str, 32)?,
+                "taproot-key" => check_hex("x-only key", script_str, 32)?,
+                _ => check_hex("hash160", script_str, 20)?,
+            }
+            if let ["txs", "chain", last_seen_txid] = rest {
+                check_hex("txid", last_seen_txid, 32)?;
+            }
+        }
+        ["address-prefix", prefix] => check_len("address prefix", prefix, MAX_ADDRESS_LEN)?,
+        ["search", search_query] => check_len("search query", search_query, MAX_ADDRESS_LEN)?,
+        #[cfg(feature = "liquid")]
+        ["asset", asset_id, ..] => check_hex("asset id", asset_id, 32)?,
+        ["block-height", height, ..] => check_number("height", height)?,
+        ["blocks", start_height] if !start_height.is_empty() && *start_height != "tip" => {
+            check_number("height", start_height)?
+        }
+        ["v1", "mining", "blocks", "timestamp", timestamp] => check_number("timestamp", timestamp)?,
+        ["mempool", "txids", "page", last_seen_txid]
+        | [INTERNAL_PREFIX, "mempool", "txs", last_seen_txid] => {
+            check_hex("txid", last_seen_txid, 32)?
+        }
+        _ => (),
+    }
+
+    for name in NUMERIC_QUERY_PARAMS {
+        if let Some(value) = query_params.get(*name) {
+            check_number(name, value)?;
+        }
+    }
+    if let Some(after_txid) = query_params.get("after_txid") {
+        check_hex("after_txid", after_txid, 32)?;
+    }
+    Ok(())
+}
+
+fn check_hex(name: &str, value: &str, bytes: usize) -> Result<(), HttpError> {
+    if value.len() != bytes * 2 || !value.bytes().all(|b| b.is_ascii_hexdigit()) {
+        bail!(HttpError::invalid_param(format!(
+            "Invalid {}: expected {} hex characters",
+            name,
+            bytes * 2
+        )));
+    }
+    Ok(())
+}
+
+fn check_number(name: &str, value: &str) -> Result<(), HttpError> {
+    if value.is_empty()
+        || value.len() > MAX_NUMBER_LEN
+        || !value.bytes().all(|b| b.is_ascii_digit())
+    {
+        bail!(HttpError::invalid_param(format!(
+            "Invalid {}: expected a number",
+            name
+        )));
+    }
+    Ok(())
+}
+
+fn check_len(name: &str, value: &str, max_len: usize) -> Result<(), HttpError> {
+    if value.is_empty() || value.len() > max_len {
+        bail!(HttpError::invalid_param(format!(
+            "Invalid {}: expected 1 to {} characters",
+            name, max_len
+        )));
+    }
+    Ok(())
 }
 
-#[derive(Debug)]
-struct HttpError(StatusCode, String);
+/// An error returned by the REST API, rendered as
+/// `{ "code": "TX_NOT_FOUND", "message": "...", "details": ... }`
+#[derive(Debug, Clone)]
+struct HttpError {
+    status: StatusCode,
+    // stable machine-readable error code, for clients to branch on
+    code: &'static str,
+    message: String,
+    details: Option<serde_json::Value>,
+}
 
 impl HttpError {
-    fn not_found(msg: String) -> Self {
-        HttpError(StatusCode::NOT_FOUND, msg)
+    fn new(status: StatusCode, code: &'static str, message: String) -> Self {
+        HttpError {
+            status,
+            code,
+            message,
+            details: None,
+        }
+    }
+
+    fn with_details(mut self, details: serde_json::Value) -> Self {
+        self.details = Some(details);
+        self
+    }
+
+    fn block_not_found() -> Self {
+        HttpError::new(
+            StatusCode::NOT_FOUND,
+            "BLOCK_NOT_FOUND",
+            "Block not found".to_string(),
+        )
+    }
+
+    fn tx_not_found() -> Self {
+        HttpError::new(
+            StatusCode::NOT_FOUND,
+            "TX_NOT_FOUND",
+            "Transaction not found".to_string(),
+        )
+    }
+
+    // the transactions of a block known to the index are expected to be stored along with it
+    fn missing_block_txs(hash: &BlockHash) -> Self {
+        HttpError::new(
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "INTERNAL_ERROR",
+            format!("Missing transactions of block {}", hash),
+        )
+    }
+
+    fn tx_reorged(min_conf_height: usize) -> Self {
+        HttpError::new(
+            StatusCode::CONFLICT,
+            "TX_REORGED",
+            format!(
+                "Transaction not confirmed at height {} or below",
+                min_conf_height
+            ),
+        )
+    }
+
+    fn out_of_range(msg: String) -> Self {
+        HttpError::new(StatusCode::NOT_FOUND, "OUT_OF_RANGE", msg)
+    }
+
+    fn invalid_param(msg: String) -> Self {
+        HttpError::new(StatusCode::BAD_REQUEST, "INVALID_PARAMETER", msg)
+    }
+
+    fn disabled(msg: String) -> Self {
+        HttpError::new(StatusCode::BAD_REQUEST, "FEATURE_DISABLED", msg)
+    }
+
+    fn endpoint_disabled(group: EndpointGroup) -> Self {
+        HttpError::new(
+            StatusCode::FORBIDDEN,
+            "ENDPOINT_DISABLED",
+            format!("The {} endpoints are disabled on this server", group.name()),
+        )
+    }
+
+    fn unauthorized() -> Self {
+        HttpError::new(
+            StatusCode::UNAUTHORIZED,
+            "UNAUTHORIZED",
+            "Missing or invalid debug token".to_string(),
+        )
+    }
+
+    fn forbidden() -> Self {
+        HttpError::new(
+            StatusCode::FORBIDDEN,
+            "FORBIDDEN",
+            "Only served on the internal listener or with the internal api key".to_string(),
+        )
+    }
+
+    fn rate_limited() -> Self {
+        HttpError::new(
+            StatusCode::TOO_MANY_REQUESTS,
+            "RATE_LIMITED",
+            "Rate limit exceeded".to_string(),
+        )
+    }
+
+    fn into_response(self, request_id: &str) -> Response<Body> {
+        let body = serde_json::to_string(&ApiError {
+            code: self.code.to_string(),
+            message: self.message,
+            details: self.details,
+            request_id: Some(request_id.to_string()),
+        })
+        .unwrap();
+        Response::builder()
+            .status(self.status)
+            .header("Content-Type", "application/json")
+            .header("X-Powered-By", &**VERSION_STRING)
+            .body(Body::from(body))
+            .unwrap()
     }
 }
 
 impl From<String> for HttpError {
     fn from(msg: String) -> Self {
-        HttpError(StatusCode::BAD_REQUEST, msg)
+        HttpError::new(StatusCode::BAD_REQUEST, "BAD_REQUEST", msg)
     }
 }
 impl From<ParseIntError> for HttpError {
     fn from(_e: ParseIntError) -> Self {
-        //HttpError::from(e.description().to_string())
-        HttpError::from("Invalid number".to_string())
+        HttpError::new(
+            StatusCode::BAD_REQUEST,
+            "INVALID_NUMBER",
+            "Invalid number".to_string(),
+        )
     }
 }
 impl From<HashError> for HttpError {
     fn from(_e: HashError) -> Self {
-        //HttpError::from(e.description().to_string())
-        HttpError::from("Invalid hash string".to_string())
+        HttpError::new(
+            StatusCode::BAD_REQUEST,
+            "INVALID_HASH",
+            "Invalid hash string".to_string(),
+        )
     }
 }
 impl From<hex::HexToBytesError> for HttpError {
     fn from(_e: hex::HexToBytesError) -> Self {
-        //HttpError::from(e.description().to_string())
-        HttpError::from("Invalid hex string".to_string())
+        HttpError::new(
+            StatusCode::BAD_REQUEST,
+            "INVALID_HEX",
+            "Invalid hex string".to_string(),
+        )
     }
 }
 impl From<hex::HexToArrayError> for HttpError {
     fn from(_e: hex::HexToArrayError) -> Self {
-        //HttpError::from(e.description().to_string())
-        HttpError::from("Invalid hex string".to_string())
+        HttpError::new(
+            StatusCode::BAD_REQUEST,
+            "INVALID_HEX",
+            "Invalid hex string".to_string(),
+        )
     }
 }
 impl From<errors::Error> for HttpError {
     fn from(e: errors::Error) -> Self {
         warn!("errors::Error: {:?}", e);
-        match e.description().to_string().as_ref() {
-            "getblock RPC error: {\"code\":-5,\"message\":\"Block not found\"}" => {
-                HttpError::not_found("Block not found".to_string())
+        match e.kind() {
+            errors::ErrorKind::RpcError(code, _, method) if *code == -5 && method == "getblock" => {
+                HttpError::block_not_found()
+            }
+            errors::ErrorKind::RpcError(code, msg, _) => {
+                HttpError::new(StatusCode::BAD_REQUEST, "RPC_ERROR", msg.clone())
+                    .with_details(json!({ "rpc_code": code }))
+            }
+            errors::ErrorKind::Blocked => HttpError::new(
+                StatusCode::UNAVAILABLE_FOR_LEGAL_REASONS,
+                "BLOCKED",
+                e.to_string(),
+            ),
+            errors::ErrorKind::TooPopular => HttpError::new(
+                StatusCode::BAD_REQUEST,
+                "TOO_MANY_HISTORY_ENTRIES",
+                e.to_string(),
+            ),
+            errors::ErrorKind::Connection(_) => HttpError::new(
+                StatusCode::SERVICE_UNAVAILABLE,
+                "DAEMON_UNAVAILABLE",
+                e.to_string(),
+            ),
+            errors::ErrorKind::ReadOnly => {
+                HttpError::new(StatusCode::SERVICE_UNAVAILABLE, "READ_ONLY", e.to_string())
             }
             _ => HttpError::from(e.to_string()),
         }
@@ -1625,37 +3814,70 @@ impl From<errors::Error> for HttpError {
 }
 impl From<serde_json::Error> for HttpError {
     fn from(e: serde_json::Error) -> Self {
-        HttpError::from(e.to_string())
+        HttpError::new(StatusCode::BAD_REQUEST, "INVALID_JSON", e.to_string())
     }
 }
 impl From<encode::Error> for HttpError {
     fn from(e: encode::Error) -> Self {
-        HttpError::from(e.to_string())
+        HttpError::new(StatusCode::BAD_REQUEST, "INVALID_ENCODING", e.to_string())
     }
 }
 impl From<std::string::FromUtf8Error> for HttpError {
     fn from(e: std::string::FromUtf8Error) -> Self {
-        HttpError::from(e.to_string())
+        HttpError::new(StatusCode::BAD_REQUEST, "INVALID_ENCODING", e.to_string())
     }
 }
 
 #[cfg(not(feature = "liquid"))]
 impl From<address::ParseError> for HttpError {
     fn from(e: address::ParseError) -> Self {
-        HttpError::from(e.to_string())
+        HttpError::new(StatusCode::BAD_REQUEST, "INVALID_ADDRESS", e.to_string())
     }
 }
 
 #[cfg(feature = "liquid")]
 impl From<address::AddressError> for HttpError {
     fn from(e: address::AddressError) -> Self {
-        HttpError::from(e.to_string())
+        HttpError::new(StatusCode::BAD_REQUEST, "INVALID_ADDRESS", e.to_string())
+    }
+}
+
+// This is needed to bench private types
+#[cfg(feature = "bench")]
+pub mod bench {
+    use std::collections::HashMap;
+
+    use crate::chain::{OutPoint, Transaction, TxOut};
+    use crate::config::Config;
+
+    use super::{tx_value, TransactionValue};
+
+    /// Serializes the transactions to JSON, like the `/block/:hash/txs` endpoint
+    pub fn txs_json(
+        txs: &[Transaction],
+        txos: &HashMap<OutPoint, TxOut>,
+        config: &Config,
+    ) -> Vec<u8> {
+        let values: Vec<TransactionValue> = txs
+            .iter()
+            .map(|tx| tx_value(tx.clone(), None, Some(txos), config, None))
+            .collect();
+        serde_json::to_vec(&values).unwrap()
     }
 }
 
 #[cfg(test)]
 mod tests {
-    use crate::rest::HttpError;
+    use crate::chain::Script;
+    use crate::config::EndpointGroup;
+    use crate::errors;
+    use crate::new_index::{compute_script_hash, ScriptStats};
+    use crate::rest::{
+        endpoint_group, endpoint_label, is_authorized, is_internal_only, taproot_key_to_scripthash,
+        validate_request, AddressInfoValue, HttpError, SupplyValue,
+    };
+    use bitcoin::hex::FromHex;
+    use hyper::{Method, StatusCode};
     use serde_json::Value;
     use std::collections::HashMap;
 
@@ -1720,4 +3942,338 @@ mod tests {
 
         assert!(err.is_err());
     }
+
+    #[test]
+    fn test_http_error_codes() {
+        let err = HttpError::from(errors::Error::from(errors::ErrorKind::RpcError(
+            -5,
+            "Block not found".to_string(),
+            "getblock".to_string(),
+        )));
+        assert_eq!(err.status, StatusCode::NOT_FOUND);
+        assert_eq!(err.code, "BLOCK_NOT_FOUND");
+
+        let err = HttpError::from(errors::Error::from(errors::ErrorKind::RpcError(
+            -26,
+            "bad-txns-inputs-missingorspent".to_string(),
+            "sendrawtransaction".to_string(),
+        )));
+        assert_eq!(err.status, StatusCode::BAD_REQUEST);
+        assert_eq!(err.code, "RPC_ERROR");
+        assert_eq!(err.details, Some(json!({ "rpc_code": -26 })));
+
+        let err = HttpError::from("aaa".parse::<u32>().unwrap_err());
+        assert_eq!(err.status, StatusCode::BAD_REQUEST);
+        assert_eq!(err.code, "INVALID_NUMBER");
+    }
+
+    #[test]
+    fn test_endpoint_label() {
+        let label = |method, path| endpoint_label(&method, path);
+        assert_eq!(
+            label(
+                Method::GET,
+                "/address/Fk8SuyHqLyLpfXizmfBTGqZ1AJvfkY3mSK/txs/chain"
+            ),
+            "GET /address/:/txs/chain"
+        );
+        assert_eq!(
+            label(Method::GET, "/block-height/123"),
+            "GET /block-height/:"
+        );
+        assert_eq!(
+            label(Method::GET, "/address-prefix/fc"),
+            "GET /address-prefix/:"
+        );
+        assert_eq!(label(Method::POST, "/txs/test"), "POST /txs/test");
+    }
+
+    #[test]
+    fn test_is_authorized() {
+        let token = Some("s3cret");
+        assert!(is_authorized("/blocks/tip/height", None, token));
+        assert!(is_authorized("/internal/debugger", None, token));
+        assert!(is_authorized(
+            "/internal/debug/chain",
+            Some("Bearer s3cret"),
+            token
+        ));
+        assert!(!is_authorized("/internal/debug/chain", None, token));
+        assert!(!is_authorized("/internal/debug", Some("s3cret"), token));
+        assert!(!is_authorized(
+            "/internal/debug/metrics",
+            Some("Bearer s3cre"),
+            token
+        ));
+        // left for the routes to report as disabled
+        assert!(is_authorized("/internal/debug/chain", None, None));
+    }
+
+    #[test]
+    fn test_is_internal_only() {
+        assert!(is_internal_only(&Method::POST, "/internal/reload"));
+        assert!(!is_internal_only(&Method::GET, "/internal/reload"));
+        assert!(!is_internal_only(&Method::POST, "/tx"));
+    }
+
+    #[test]
+    fn test_endpoint_group() {
+        let group = |method: Method, path: &str| {
+            let path: Vec<&str> = path.split('/').skip(1).collect();
+            endpoint_group(&method, &path)
+        };
+        assert_eq!(
+            group(Method::GET, "/address/fc1q/txs/export"),
+            Some(EndpointGroup::Export)
+        );
+        assert_eq!(
+            group(Method::GET, "/v1/export/blocks"),
+            Some(EndpointGroup::Export)
+        );
+        assert_eq!(
+            group(Method::GET, "/v1/chainstate/diff"),
+            Some(EndpointGroup::Export)
+        );
+        assert_eq!(
+            group(Method::POST, "/txs/outspends/by-txid"),
+            Some(EndpointGroup::Bulk)
+        );
+        assert_eq!(
+            group(Method::GET, "/internal/mempool/txs/all"),
+            Some(EndpointGroup::Bulk)
+        );
+        assert_eq!(
+            group(Method::GET, "/scripthash/ab/ledger"),
+            Some(EndpointGroup::Ledger)
+        );
+        assert_eq!(group(Method::GET, "/v1/supply"), Some(EndpointGroup::Stats));
+        assert_eq!(
+            group(Method::GET, "/address-prefix/fc1q"),
+            Some(EndpointGroup::Search)
+        );
+        assert_eq!(group(Method::GET, "/address/fc1q/txs"), None);
+        assert_eq!(group(Method::GET, "/internal/mempool/txs"), None);
+        assert_eq!(group(Method::GET, "/blocks/tip/height"), None);
+    }
+
+    #[test]
+    fn test_taproot_key_to_scripthash() {
+        let key = "ab".repeat(32);
+        let script = Script::from(Vec::from_hex(&format!("5120{}", key)).unwrap());
+        assert_eq!(
+            taproot_key_to_scripthash(&key).unwrap(),
+            compute_script_hash(&script)
+        );
+        assert!(taproot_key_to_scripthash("abcd").is_err());
+    }
+
+    #[test]
+    fn test_validate_request() {
+        let validate = |path: &str, query: &[(&str, &str)]| {
+            let path: Vec<&str> = path.split('/').skip(1).collect();
+            let query_params = query
+                .iter()
+                .map(|(k, v)| (k.to_string(), v.to_string()))
+                .collect::<HashMap<_, _>>();
+            validate_request(&path, &query_params).map_err(|e| e.code)
+        };
+        let txid = "1".repeat(64);
+
+        assert!(validate(&format!("/tx/{}/outspend/0", txid), &[]).is_ok());
+        assert!(validate(&format!("/block/{}/txs/25", txid), &[]).is_ok());
+        assert!(validate(&format!("/block/{}/txs/summary", txid), &[]).is_ok());
+        assert!(validate(&format!("/address/fc1q/txs/chain/{}", txid), &[]).is_ok());
+        assert!(validate(&format!("/pkh/{}", "ab".repeat(20)), &[]).is_ok());
+        assert!(validate(&format!("/taproot-key/{}/utxo", "ab".repeat(32)), &[]).is_ok());
+        assert!(validate("/blocks/tip/height", &[]).is_ok());
+        assert!(validate("/blocks/", &[]).is_ok());
+        assert!(validate("/mempool/txids/page", &[("max_txs", "10")]).is_ok());
+
+        let invalid = |path: &str, query: &[(&str, &str)]| {
+            assert_eq!(validate(path, query), Err("INVALID_PARAMETER"), "{}", path);
+        };
+        invalid("/tx/abc", &[]);
+        invalid(&format!("/tx/{}", "g".repeat(64)), &[]);
+        invalid(&format!("/tx/{}/outspend/-1", txid), &[]);
+        invalid(
+            &format!("/block/{}/txid/99999999999999999999999", txid),
+            &[],
+        );
+        invalid(&format!("/address/{}", "q".repeat(200)), &[]);
+        invalid("/address/fc1q/txs/chain/zz", &[]);
+        invalid(&format!("/scripthash/{}", "ab".repeat(20)), &[]);
+        invalid(&format!("/taproot-key/{}", "ab".repeat(20)), &[]);
+        invalid("/block-height/1e3", &[]);
+        invalid("/blocks/-1", &[]);
+        invalid("/mempool/txids/page", &[("max_txs", "ten")]);
+        invalid("/address/fc1q/txs", &[("after_txid", "00")]);
+        invalid("/address/fc1q/txs", &[("min_amount", "1.5")]);
+    }
+
+    #[test]
+    fn test_validate_debug_input() {
+        let validate = |path: &str| {
+            let path: Vec<&str> = path.split('/').skip(1).collect();
+            validate_request(&path, &HashMap::new()).map_err(|e| e.code)
+        };
+        assert!(validate("/tx/debug-input").is_ok());
+        // only the route itself is exempt from the txid check
+        assert_eq!(validate("/tx/debug-input/status"), Err("INVALID_PARAMETER"));
+    }
+
+    #[test]
+    fn test_response_field_order() {
+        // the fields are serialized in their declaration order, which clients may rely on
+        let supply = SupplyValue {
+            height: 10,
+            total: 500,
+            burned: 20,
+            circulating: 480,
+        };
+        assert_eq!(
+            serde_json::to_string(&supply).unwrap(),
+            r#"{"height":10,"total":500,"burned":20,"circulating":480}"#
+        );
+
+        // only the kind of id the script was looked up by is included
+        let stats = serde_json::to_string(&AddressInfoValue {
+            address: None,
+            scripthash: Some("ab".to_string()),
+            pkh: None,
+            sh: None,
+            taproot_key: None,
+            tx_count: 0,
+            chain_stats: ScriptStats::default().into(),
+            mempool_stats: ScriptStats::default().into(),
+            chain_activity: None,
+            #[cfg(not(feature = "liquid"))]
+            fiat: None,
+        })
+        .unwrap();
+        assert!(
+            stats.starts_with(r#"{"scripthash":"ab","tx_count":0,"chain_stats":{"tx_count":0,"#)
+        );
+        assert!(stats.ends_with(r#"},"chain_activity":null}"#));
+        assert!(!stats.contains("address"));
+    }
+
+    // the responses the server doesn't build from the API crate types still parse as them
+    #[cfg(not(feature = "liquid"))]
+    #[test]
+    fn test_api_types_parse_responses() {
+        use crate::new_index::BacklogStats;
+        use crate::rest::SpendingValue;
+        use crate::util::{BlockStatus, Eviction, EvictionReason, TransactionStatus};
+        use bitcoin::hashes::Hash;
+        use bitcoin::{BlockHash, Txid};
+        use flokicoin_electrs_api as api;
+
+        fn reparse<T: serde::de::DeserializeOwned>(value: &impl serde::Serialize) -> T {
+            serde_json::from_str(&serde_json::to_string(value).unwrap()).unwrap()
+        }
+
+        let hash = BlockHash::from_byte_array([1; 32]);
+        let confirmed = TransactionStatus {
+            confirmed: true,
+            block_height: Some(10),
+            block_hash: Some(hash),
+            block_time: Some(1600000000),
+            evicted: None,
+        };
+        let status: api::TxStatus = reparse(&confirmed);
+        assert_eq!(status.block_height, Some(10));
+        assert_eq!(status.block_hash, Some(hash));
+
+        let evicted = TransactionStatus {
+            confirmed: false,
+            block_height: None,
+            block_hash: None,
+            block_time: None,
+            evicted: Some(Eviction {
+                reason: EvictionReason::Replaced,
+                replaced_by: Some(Txid::from_byte_array([2; 32])),
+                time: 1600000000,
+            }),
+        };
+        let status: api::TxStatus = reparse(&evicted);
+        assert_eq!(
+            status.evicted.unwrap().reason,
+            api::EvictionReason::Replaced
+        );
+
+        let outspend: api::Outspend = reparse(&SpendingValue {
+            spent: true,
+            txid: Some(Txid::from_byte_array([2; 32])),
+            vin: Some(1),
+            status: Some(confirmed),
+        });
+        assert_eq!(outspend.vin, Some(1));
+        let outspend: api::Outspend = reparse(&SpendingValue::default());
+        assert!(!outspend.spent);
+
+        let block_status: api::BlockStatus = reparse(&BlockStatus {
+            in_best_chain: true,
+            height: Some(10),
+            next_best: None,
+            confirmations: Some(3),
+            block_hash: Some(hash),
+            chainwork: Some("0a".to_string()),
+        });
+        assert_eq!(block_status.confirmations, Some(3));
+
+        let mempool: api::MempoolStats = reparse(&BacklogStats {
+            count: 2,
+            vsize: 300,
+            total_fee: 450,
+            fee_histogram: vec![(1.5, 300)],
+        });
+        assert_eq!(mempool.total_fee, 450);
+        assert_eq!(mempool.fee_histogram, vec![(1.5, 300)]);
+    }
+
+    #[cfg(not(feature = "liquid"))]
+    #[test]
+    fn test_tx_io_paging() {
+        use crate::rest::{paged_inputs, tx_io_paging, TxIoPage};
+        use bitcoin::absolute::LockTime;
+        use bitcoin::transaction::Version;
+        use bitcoin::{Amount, ScriptBuf, Transaction, TxIn, TxOut};
+
+        let tx = Transaction {
+            version: Version::TWO,
+            lock_time: LockTime::ZERO,
+            input: vec![TxIn::default(); 600],
+            output: vec![
+                TxOut {
+                    value: Amount::from_sat(1),
+                    script_pubkey: ScriptBuf::new(),
+                };
+                10
+            ],
+        };
+        let page = |vin_offset| {
+            Some(TxIoPage {
+                vin_offset,
+                vout_offset: 0,
+            })
+        };
+
+        // small transactions are included whole
+        let small = Transaction {
+            input: vec![TxIn::default(); 2],
+            ..tx.clone()
+        };
+        assert!(tx_io_paging(&small, page(0)).is_none());
+        assert!(tx_io_paging(&tx, None).is_none());
+        assert_eq!(paged_inputs(&tx, None), 0..600);
+
+        let first = tx_io_paging(&tx, page(0)).unwrap();
+        assert!(first.truncated);
+        assert_eq!(paged_inputs(&tx, Some(&first)), 0..500);
+        let last = tx_io_paging(&tx, page(500)).unwrap();
+        assert!(!last.truncated);
+        assert_eq!(paged_inputs(&tx, Some(&last)), 500..600);
+        let past = tx_io_paging(&tx, page(1000)).unwrap();
+        assert_eq!(paged_inputs(&tx, Some(&past)), 600..600);
+    }
 }
@@ -0,0 +1,100 @@
+//! The `--rest-access-log` lines are handed over to a dedicated thread, which writes them to
+//! stdout through a buffer that is flushed once no more lines are pending. The request handlers
+//! never block on stdout: when the writer falls behind, the lines that don't fit in the queue are
+//! dropped and counted.
+
+use std::io::{self, BufWriter, Write};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use crossbeam_channel::{bounded, Receiver, RecvTimeoutError, Sender, TrySendError};
+use serde::Serialize;
+
+use crate::util::spawn_thread;
+
+// the lines waiting to be written before new ones get dropped
+const QUEUE_SIZE: usize = 16_384;
+// how often the dropped lines are reported
+const REPORT_INTERVAL: Duration = Duration::from_secs(10);
+
+/// A line of the access log, written as a JSON document
+#[derive(Serialize)]
+pub struct AccessLogEntry<'a> {
+    pub event: &'static str,
+    pub request_id: &'a str,
+    pub method: &'a str,
+    pub path: &'a str,
+    pub query: Option<&'a str>,
+    pub status: u16,
+    pub request_size: usize,
+    pub response_size: Option<u64>,
+    pub duration_micros: u128,
+    pub source: Option<AccessLogSource>,
+    pub user_agent: Option<&'a str>,
+}
+
+#[derive(Serialize)]
+pub struct AccessLogSource {
+    pub ip: String,
+    pub port: u16,
+}
+
+#[derive(Clone)]
+pub struct AccessLog {
+    sender: Sender<String>,
+    dropped: Arc<AtomicUsize>,
+}
+
+impl AccessLog {
+    pub fn start() -> Self {
+        let (sender, receiver) = bounded(QUEUE_SIZE);
+        let dropped = Arc::new(AtomicUsize::new(0));
+        let writer_dropped = Arc::clone(&dropped);
+        spawn_thread("access-log", move || write_lines(receiver, &writer_dropped));
+        AccessLog { sender, dropped }
+    }
+
+    pub fn log(&self, entry: &AccessLogEntry) {
+        let line = match serde_json::to_string(entry) {
+            Ok(line) => line,
+            Err(e) => {
+                warn!("failed serializing an access log line: {}", e);
+                return;
+            }
+        };
+        match self.sender.try_send(line) {
+            Ok(()) | Err(TrySendError::Disconnected(_)) => (),
+            Err(TrySendError::Full(_)) => {
+                self.dropped.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+    }
+}
+
+fn write_lines(receiver: Receiver<String>, dropped: &AtomicUsize) {
+    // stdout isn't kept locked, the electrum rpc log shares it
+    let mut out = BufWriter::new(io::stdout());
+    loop {
+        match receiver.recv_timeout(REPORT_INTERVAL) {
+            Ok(line) => {
+                if let Err(e) = writeln!(out, "{}", line) {
+                    warn!("failed writing the access log: {}", e);
+                }
+                if !receiver.is_empty() {
+                    continue;
+                }
+            }
+            Err(RecvTimeoutError::Timeout) => (),
+            Err(RecvTimeoutError::Disconnected) => break,
+        }
+        if let Err(e) = out.flush() {
+            warn!("failed writing the access log: {}", e);
+        }
+        let dropped = dropped.swap(0, Ordering::Relaxed);
+        if dropped > 0 {
+            warn!("dropped {} access log lines, stdout is too slow", dropped);
+        }
+    }
+    let _ = out.flush();
+}
@@ -0,0 +1,182 @@
+//! The old paths of the renamed REST endpoints, kept working while their clients move to the new
+//! paths. A request to an old path is served by the route of the new one, with a `Deprecation`
+//! header and a `Link` to the new path in the response, and counted by the
+//! `rest_deprecated_requests` metric. Only the new paths are routed by `handle_request()` and
+//! listed in the OpenAPI document.
+
+use std::collections::HashMap;
+
+use hyper::header::{HeaderValue, LINK};
+use hyper::{Body, Response, Uri};
+
+use crate::metrics::{CounterVec, MetricOpts, Metrics};
+
+/// An old path of an endpoint. Its `{...}` segments match any value, passed on to the same
+/// parameters of the new path, and the segments following it are appended to the new path (so
+/// that moving `/foo/{id}` also moves `/foo/{id}/bar`).
+pub struct Alias {
+    pub old: &'static str,
+    pub new: &'static str,
+    /// When the old path stops working, as an HTTP date for the `Sunset` header, if planned
+    pub sunset: Option<&'static str>,
+}
+
+// The aliases of the renamed endpoints, like
+// `Alias { old: "/tx/{txid}/merkle-proof", new: "/v1/tx/{txid}/merkle-proof", sunset: None }`
+const ALIASES: &[Alias] = &[];
+
+pub struct Aliases {
+    aliases: &'static [Alias],
+    hits: CounterVec,
+}
+
+impl Aliases {
+    pub fn new(metrics: &Metrics) -> Self {
+        Aliases::with_aliases(ALIASES, metrics)
+    }
+
+    fn with_aliases(aliases: &'static [Alias], metrics: &Metrics) -> Self {
+        Aliases {
+            aliases,
+            hits: metrics.counter_vec(
+                MetricOpts::new(
+                    "rest_deprecated_requests",
+                    "# of REST requests made to the old path of a renamed endpoint, by old path",
+                ),
+                &["path"],
+            ),
+        }
+    }
+
+    /// The request `uri` with its old path replaced by the new one, if it uses an alias
+    pub fn resolve(&self, uri: &Uri) -> Option<(Uri, &'static Alias)> {
+        let (alias, path) = self
+            .aliases
+            .iter()
+            .find_map(|alias| Some((alias, rewrite(alias, uri.path())?)))?;
+        let uri = match uri.query() {
+            Some(query) => format!("{}?{}", path, query),
+            None => path,
+        };
+        self.hits.with_label_values(&[alias.old]).inc();
+        Some((uri.parse().ok()?, alias))
+    }
+}
+
+impl Alias {
+    /// Marks the response to a request made to the old path as deprecated, pointing to `new_path`
+    pub fn mark(&self, resp: &mut Response<Body>, new_path: &str) {
+        let headers = resp.headers_mut();
+        headers.insert("Deprecation", HeaderValue::from_static("true"));
+        if let Ok(link) = format!("<{}>; rel=\"successor-version\"", new_path).parse() {
+            headers.insert(LINK, link);
+        }
+        if let Some(sunset) = self.sunset {
+            headers.insert("Sunset", HeaderValue::from_static(sunset));
+        }
+    }
+}
+
+fn is_param(segment: &str) -> bool {
+    segment.starts_with('{') && segment.ends_with('}')
+}
+
+// `path` with the old path of `alias` replaced by its new one, if it starts with it
+fn rewrite(alias: &Alias, path: &str) -> Option<String> {
+    let mut segments = path.split('/').skip(1);
+    let mut params = HashMap::new();
+    for pattern in alias.old.split('/').skip(1) {
+        let segment = segments.next()?;
+        if is_param(pattern) {
+            params.insert(pattern, segment);
+        } else if pattern != segment {
+            return None;
+        }
+    }
+
+    let mut new_path = String::new();
+    for pattern in alias.new.split('/').skip(1) {
+        new_path.push('/');
+        new_path.push_str(if is_param(pattern) {
+            params.get(pattern)?
+        } else {
+            pattern
+        });
+    }
+    for segment in segments {
+        new_path.push('/');
+        new_path.push_str(segment);
+    }
+    Some(new_path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TEST_ALIASES: &[Alias] = &[
+        Alias {
+            old: "/tx/{txid}/merkle-proof",
+            new: "/v1/tx/{txid}/merkle-proof",
+            sunset: Some("Sat, 01 Jan 2028 00:00:00 GMT"),
+        },
+        Alias {
+            old: "/scripthash/{hash}",
+            new: "/v1/script/{hash}",
+            sunset: None,
+        },
+    ];
+
+    #[test]
+    fn test_aliases() {
+        // the new paths only use the parameters of the old ones
+        for alias in ALIASES.iter().chain(TEST_ALIASES) {
+            for param in alias.new.split('/').filter(|s| is_param(s)) {
+                assert!(alias.old.split('/').any(|s| s == param), "{}", alias.new);
+            }
+        }
+    }
+
+    #[test]
+    fn test_resolve() {
+        let metrics = Metrics::new("127.0.0.1:0".parse().unwrap());
+        let aliases = Aliases::with_aliases(TEST_ALIASES, &metrics);
+        let resolve = |uri: &str| {
+            aliases
+                .resolve(&uri.parse().unwrap())
+                .map(|(uri, alias)| (uri.to_string(), alias.old))
+        };
+
+        assert_eq!(
+            resolve("/tx/ab/merkle-proof?unit=sat"),
+            Some((
+                "/v1/tx/ab/merkle-proof?unit=sat".to_string(),
+                "/tx/{txid}/merkle-proof"
+            ))
+        );
+        assert_eq!(
+            resolve("/scripthash/cd/txs/chain"),
+            Some(("/v1/script/cd/txs/chain".to_string(), "/scripthash/{hash}"))
+        );
+        assert_eq!(resolve("/tx/ab"), None);
+        assert_eq!(resolve("/tx/ab/merkleblock-proof"), None);
+        assert_eq!(resolve("/v1/tx/ab/merkle-proof"), None);
+        assert_eq!(
+            aliases
+                .hits
+                .with_label_values(&["/scripthash/{hash}"])
+                .get(),
+            1
+        );
+
+        let mut resp = Response::new(Body::empty());
+        TEST_ALIASES[0].mark(&mut resp, "/v1/tx/ab/merkle-proof");
+        let headers = resp.headers();
+        assert_eq!(headers["Deprecation"], "true");
+        assert_eq!(
+            headers[LINK],
+            "</v1/tx/ab/merkle-proof>; rel=\"successor-version\""
+        );
+        assert_eq!(headers["Sunset"], "Sat, 01 Jan 2028 00:00:00 GMT");
+    }
+}
@@ -0,0 +1,209 @@
+//! Coalescing of the identical REST requests handled at the same time (single-flight). When many
+//! clients ask for the same thing at once, like the new block or a popular address right after a
+//! block, the first request is handled and its response is shared with the ones that arrived while
+//! it was, instead of running the same queries once per client.
+//!
+//! Only the GET requests are coalesced, by lane and URI, which are all their responses depend on
+//! once they are authorized. The streamed responses (like the exports) can't be shared, the
+//! requests waiting on them are handled on their own once they are sent.
+
+use std::collections::HashMap;
+use std::future::Future;
+use std::sync::{Arc, Mutex};
+
+use hyper::body::{Bytes, HttpBody};
+use hyper::header::HeaderMap;
+use hyper::{Body, Response, StatusCode};
+use tokio::sync::watch;
+
+use super::lanes::Lane;
+use super::HttpError;
+use crate::metrics::{Counter, MetricOpts, Metrics};
+
+// The response of a request, to share with the ones coalesced into it, or None if it can't be
+type Outcome = Option<Arc<Result<SharedResponse, HttpError>>>;
+
+struct SharedResponse {
+    status: StatusCode,
+    headers: HeaderMap,
+    body: Bytes,
+}
+
+impl SharedResponse {
+    fn to_response(&self) -> Response<Body> {
+        let mut resp = Response::new(Body::from(self.body.clone()));
+        *resp.status_mut() = self.status;
+        *resp.headers_mut() = self.headers.clone();
+        resp
+    }
+}
+
+pub struct Coalescer {
+    // the requests being handled, by key, with the channel their outcome is sent to
+    inflight: Mutex<HashMap<String, watch::Receiver<Option<Outcome>>>>,
+    coalesced: Counter,
+}
+
+impl Coalescer {
+    pub fn new(metrics: &Metrics) -> Self {
+        Coalescer {
+            inflight: Mutex::new(HashMap::new()),
+            coalesced: metrics.counter(MetricOpts::new(
+                "rest_coalesced_requests",
+                "# of REST requests served with the response of an identical request handled at the same time",
+            )),
+        }
+    }
+
+    /// Handles the request for `uri` with `handle`, unless an identical one is being handled, whose
+    /// response is then shared
+    pub async fn run<F, Fut>(
+        &self,
+        lane: Lane,
+        uri: &hyper::Uri,
+        handle: F,
+    ) -> Result<Response<Body>, HttpError>
+    where
+        F: FnOnce() -> Fut,
+        Fut: Future<Output = Result<Response<Body>, HttpError>>,
+    {
+        let key = format!("{:?} {}", lane, uri);
+        let leader = {
+            let mut inflight = self.inflight.lock().unwrap();
+            match inflight.get(&key) {
+                Some(rx) => Err(rx.clone()),
+                None => {
+                    let (tx, rx) = watch::channel(None);
+                    inflight.insert(key.clone(), rx);
+                    Ok(tx)
+                }
+            }
+        };
+        let tx = match leader {
+            Ok(tx) => tx,
+            Err(mut rx) => {
+                // the request being handled may go away without an outcome, like when its client
+                // disconnects, leaving this one to be handled on its own
+                if rx.changed().await.is_ok() {
+                    let outcome = rx.borrow().clone().flatten();
+                    if let Some(outcome) = outcome {
+                        self.coalesced.inc();
+                        return match &*outcome {
+                            Ok(shared) => Ok(shared.to_response()),
+                            Err(e) => Err(e.clone()),
+                        };
+                    }
+                }
+                return handle().await;
+            }
+        };
+
+        let _inflight = Inflight {
+            coalescer: self,
+            key,
+        };
+        let (result, outcome) = share(handle().await).await;
+        tx.send_replace(Some(outcome));
+        result
+    }
+}
+
+// Removes a request from the ones in flight once handled, or cancelled
+struct Inflight<'a> {
+    coalescer: &'a Coalescer,
+    key: String,
+}
+
+impl Drop for Inflight<'_> {
+    fn drop(&mut self) {
+        self.coalescer.inflight.lock().unwrap().remove(&self.key);
+    }
+}
+
+// Buffers the response to share it, unless it's streamed
+async fn share(
+    result: Result<Response<Body>, HttpError>,
+) -> (Result<Response<Body>, HttpError>, Outcome) {
+    let resp = match result {
+        Ok(resp) => resp,
+        Err(e) => {
+            let outcome = Arc::new(Err(e.clone()));
+            return (Err(e), Some(outcome));
+        }
+    };
+    if resp.body().size_hint().exact().is_none() {
+        return (Ok(resp), None);
+    }
+    let (parts, body) = resp.into_parts();
+    match hyper::body::to_bytes(body).await {
+        Ok(body) => {
+            let shared = SharedResponse {
+                status: parts.status,
+                headers: parts.headers,
+                body,
+            };
+            (Ok(shared.to_response()), Some(Arc::new(Ok(shared))))
+        }
+        Err(e) => (Err(HttpError::from(e.to_string())), None),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::time::Duration;
+
+    #[tokio::test]
+    async fn test_coalesce() {
+        let metrics = Metrics::new("127.0.0.1:0".parse().unwrap());
+        let coalescer = Coalescer::new(&metrics);
+        let uri: hyper::Uri = "/block/abc".parse().unwrap();
+        let calls = AtomicUsize::new(0);
+        let handle = || async {
+            calls.fetch_add(1, Ordering::SeqCst);
+            tokio::time::sleep(Duration::from_millis(100)).await;
+            Ok(Response::new(Body::from("block")))
+        };
+
+        let (first, second, internal) = tokio::join!(
+            coalescer.run(Lane::Public, &uri, handle),
+            coalescer.run(Lane::Public, &uri, handle),
+            coalescer.run(Lane::Internal, &uri, handle),
+        );
+        // the internal lane doesn't wait on the public one
+        assert_eq!(calls.load(Ordering::SeqCst), 2);
+        assert_eq!(coalescer.coalesced.get(), 1);
+        for resp in [first, second, internal] {
+            let body = hyper::body::to_bytes(resp.unwrap().into_body()).await;
+            assert_eq!(body.unwrap(), "block");
+        }
+
+        // handled again once done
+        coalescer.run(Lane::Public, &uri, handle).await.unwrap();
+        assert_eq!(calls.load(Ordering::SeqCst), 3);
+        assert!(coalescer.inflight.lock().unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_coalesce_streamed() {
+        let metrics = Metrics::new("127.0.0.1:0".parse().unwrap());
+        let coalescer = Coalescer::new(&metrics);
+        let uri: hyper::Uri = "/v1/export/blocks".parse().unwrap();
+        let calls = AtomicUsize::new(0);
+        let handle = || async {
+            calls.fetch_add(1, Ordering::SeqCst);
+            tokio::time::sleep(Duration::from_millis(100)).await;
+            let (_sender, body) = Body::channel();
+            Ok(Response::new(body))
+        };
+
+        let (first, second) = tokio::join!(
+            coalescer.run(Lane::Public, &uri, handle),
+            coalescer.run(Lane::Public, &uri, handle),
+        );
+        assert!(first.is_ok() && second.is_ok());
+        assert_eq!(calls.load(Ordering::SeqCst), 2);
+        assert_eq!(coalescer.coalesced.get(), 0);
+    }
+}
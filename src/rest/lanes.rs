@@ -0,0 +1,135 @@
+//! Two scheduling lanes for the REST requests. The requests arriving on the `--http-internal-addr`
+//! listener, or with the `--internal-api-key` in their `X-Api-Key` header, take the internal lane
+//! and are handled right away. The others take the public lane, which handles at most
+//! `--rest-public-concurrency` of them at once, so that a busy public explorer never delays the
+//! internal services sharing the index.
+
+use std::sync::Arc;
+
+use tokio::sync::Semaphore;
+
+use super::constant_time_eq;
+use crate::config::Config;
+use crate::metrics::{GaugeVec, MetricOpts, Metrics};
+use crate::util::pool::LoadMonitor;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Lane {
+    Public,
+    Internal,
+}
+
+impl Lane {
+    fn name(&self) -> &'static str {
+        match self {
+            Lane::Public => "public",
+            Lane::Internal => "internal",
+        }
+    }
+}
+
+pub struct Lanes {
+    public: Arc<Semaphore>,
+    api_key: Option<String>,
+    requests: GaugeVec,
+    // the waiting public requests put off the background work
+    load: LoadMonitor,
+}
+
+impl Lanes {
+    pub fn new(config: &Config, metrics: &Metrics, load: LoadMonitor) -> Self {
+        Lanes {
+            public: Arc::new(Semaphore::new(config.rest_public_concurrency)),
+            api_key: config.internal_api_key.clone(),
+            requests: metrics.gauge_vec(
+                MetricOpts::new(
+                    "rest_lane_requests",
+                    "# of REST requests waiting or running, by lane and state",
+                ),
+                &["lane", "state"],
+            ),
+            load,
+        }
+    }
+
+    /// The lane of a request received on the internal listener or not, with the given API key
+    pub fn lane(&self, internal_listener: bool, api_key: Option<&str>) -> Lane {
+        let has_key = match (&self.api_key, api_key) {
+            (Some(expected), Some(given)) => {
+                constant_time_eq(given.as_bytes(), expected.as_bytes())
+            }
+            _ => false,
+        };
+        if internal_listener || has_key {
+            Lane::Internal
+        } else {
+            Lane::Public
+        }
+    }
+
+    /// Runs `handle` on the blocking thread pool once the `lane` has room for it
+    pub async fn run<T, F>(&self, lane: Lane, handle: F) -> T
+    where
+        T: Send + 'static,
+        F: FnOnce() -> T + Send + 'static,
+    {
+        let _permit = match lane {
+            Lane::Public => {
+                let waiting = self.requests.with_label_values(&[lane.name(), "waiting"]);
+                waiting.inc();
+                let load = self.load.rest_waiting();
+                let permit = Arc::clone(&self.public).acquire_owned().await;
+                drop(load);
+                waiting.dec();
+                Some(permit.expect("public lane closed"))
+            }
+            Lane::Internal => None,
+        };
+        let running = self.requests.with_label_values(&[lane.name(), "running"]);
+        running.inc();
+        let result = tokio::task::spawn_blocking(handle).await;
+        running.dec();
+        result.unwrap_or_else(|e| std::panic::resume_unwind(e.into_panic()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_lane() {
+        let metrics = Metrics::new("127.0.0.1:0".parse().unwrap());
+        let lanes = Lanes {
+            public: Arc::new(Semaphore::new(1)),
+            api_key: Some("s3cret".to_string()),
+            requests: metrics.gauge_vec(MetricOpts::new("lanes", "lanes"), &["lane", "state"]),
+            load: LoadMonitor::for_tests(&metrics),
+        };
+        assert_eq!(lanes.lane(false, None), Lane::Public);
+        assert_eq!(lanes.lane(false, Some("s3cre")), Lane::Public);
+        assert_eq!(lanes.lane(false, Some("s3cret")), Lane::Internal);
+        assert_eq!(lanes.lane(true, None), Lane::Internal);
+
+        let lanes = Lanes {
+            api_key: None,
+            ..lanes
+        };
+        assert_eq!(lanes.lane(false, Some("")), Lane::Public);
+    }
+
+    #[tokio::test]
+    async fn test_run() {
+        let metrics = Metrics::new("127.0.0.1:0".parse().unwrap());
+        let lanes = Lanes {
+            public: Arc::new(Semaphore::new(1)),
+            api_key: None,
+            requests: metrics.gauge_vec(MetricOpts::new("lanes", "lanes"), &["lane", "state"]),
+            load: LoadMonitor::for_tests(&metrics),
+        };
+        // the internal requests are handled while the public lane is full
+        let _busy = Arc::clone(&lanes.public).acquire_owned().await.unwrap();
+        assert_eq!(lanes.run(Lane::Internal, || 1).await, 1);
+        assert_eq!(lanes.public.available_permits(), 0);
+    }
+}
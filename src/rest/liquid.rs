@@ -0,0 +1,161 @@
+//! The elements flavour of the response values, which are otherwise defined by the
+//! `flokicoin-electrs-api` crate. They are named the same for the OpenAPI schemas to match.
+
+use crate::chain::{BlockHash, Script, TxMerkleNode, Txid};
+use crate::elements::{peg::PegoutValue, IssuanceValue};
+use crate::new_index::{ScriptActivity, ScriptStats};
+use crate::util::TransactionStatus;
+use elements::{secp256k1_zkp as zkp, AssetId};
+use flokicoin_electrs_api::{LocktimeType, RelativeTimelock, TxIoPaging};
+use serde::Serialize;
+use utoipa::ToSchema;
+
+#[derive(Serialize, ToSchema)]
+pub struct Block {
+    #[schema(value_type = String)]
+    pub id: BlockHash,
+    pub height: u32,
+    pub version: u32,
+    pub timestamp: u32,
+    pub tx_count: u32,
+    pub size: u32,
+    pub weight: u64,
+    #[schema(value_type = String)]
+    pub merkle_root: TxMerkleNode,
+    #[schema(value_type = Option<String>)]
+    pub previousblockhash: Option<BlockHash>,
+    pub mediantime: u32,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[schema(value_type = Option<Object>)]
+    pub ext: Option<elements::BlockExtData>,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct Transaction {
+    #[schema(value_type = String)]
+    pub txid: Txid,
+    pub version: u32,
+    pub locktime: u32,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub locktime_type: Option<LocktimeType>,
+    pub locktime_active: bool,
+    pub is_final: bool,
+    pub vin: Vec<TxIn>,
+    pub vout: Vec<TxOut>,
+    pub size: u32,
+    pub weight: u64,
+    pub vsize: u64,
+    pub stripped_size: u32,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[serde(serialize_with = "crate::util::amount::serialize_opt")]
+    pub fee: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub status: Option<TransactionStatus>,
+    #[serde(flatten)]
+    pub paging: Option<TxIoPaging>,
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    pub blocked: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub discount_vsize: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub discount_weight: Option<usize>,
+}
+
+#[derive(Serialize, Clone, ToSchema)]
+pub struct TxIn {
+    #[schema(value_type = String)]
+    pub txid: Txid,
+    pub vout: u32,
+    pub prevout: Option<TxOut>,
+    #[schema(value_type = String)]
+    pub scriptsig: Script,
+    pub scriptsig_asm: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub witness: Option<Vec<String>>,
+    pub is_coinbase: bool,
+    pub sequence: u32,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub relative_timelock: Option<RelativeTimelock>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub inner_redeemscript_asm: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub inner_witnessscript_asm: Option<String>,
+    pub is_pegin: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[schema(value_type = Option<Object>)]
+    pub issuance: Option<IssuanceValue>,
+}
+
+#[derive(Serialize, Clone, ToSchema)]
+pub struct TxOut {
+    #[schema(value_type = String)]
+    pub scriptpubkey: Script,
+    pub scriptpubkey_asm: String,
+    pub scriptpubkey_type: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub scriptpubkey_address: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[serde(serialize_with = "crate::util::amount::serialize_opt")]
+    pub value: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[schema(value_type = Option<String>)]
+    pub valuecommitment: Option<zkp::PedersenCommitment>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[schema(value_type = Option<String>)]
+    pub asset: Option<AssetId>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[schema(value_type = Option<String>)]
+    pub assetcommitment: Option<zkp::Generator>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[schema(value_type = Option<Object>)]
+    pub pegout: Option<PegoutValue>,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct Utxo {
+    #[schema(value_type = String)]
+    pub txid: Txid,
+    pub vout: u32,
+    pub status: TransactionStatus,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[serde(serialize_with = "crate::util::amount::serialize_opt")]
+    pub value: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[schema(value_type = Option<String>)]
+    pub valuecommitment: Option<zkp::PedersenCommitment>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[schema(value_type = Option<String>)]
+    pub asset: Option<AssetId>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[schema(value_type = Option<String>)]
+    pub assetcommitment: Option<zkp::Generator>,
+    // nonces are never explicit
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[schema(value_type = Option<String>)]
+    pub noncecommitment: Option<zkp::PublicKey>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[schema(value_type = Option<String>)]
+    pub surjection_proof: Option<zkp::SurjectionProof>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[schema(value_type = Option<String>)]
+    pub range_proof: Option<zkp::RangeProof>,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct AddressInfo {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub address: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub scripthash: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub pkh: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub sh: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub taproot_key: Option<String>,
+    pub tx_count: usize,
+    // without the sums, which are unknown for blinded outputs
+    pub chain_stats: ScriptStats,
+    pub mempool_stats: ScriptStats,
+    pub chain_activity: Option<ScriptActivity>,
+}
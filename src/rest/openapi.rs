@@ -0,0 +1,757 @@
+//! The OpenAPI 3 document of the REST API, served at `/openapi.json`. The response schemas are
+//! derived from the types the handlers serialize. The routes and the schema each of them responds
+//! with are listed by hand in `ROUTES` though, and have to be kept in sync with the routes of
+//! `handle_request()`: nothing checks that every route is documented. The golden tests check the
+//! responses of the routes they cover against the schema listed here.
+
+use utoipa::openapi::path::{OperationBuilder, ParameterBuilder, ParameterIn, PathItemType};
+use utoipa::openapi::{
+    ArrayBuilder, ComponentsBuilder, ContentBuilder, InfoBuilder, KnownFormat, ObjectBuilder,
+    OpenApi, OpenApiBuilder, PathItem, Paths, Ref, RefOr, Required, Response, ResponseBuilder,
+    Schema, SchemaFormat, SchemaType,
+};
+use utoipa::ToSchema;
+
+use super::{
+    AddressInfoValue, BlockStatsValue, BlockTimestampValue, BlockTxSummary, BlockValue,
+    FeeEstimatesValue, MerkleProofValue, MultisigStatsValue, SearchResult, ServerInfo,
+    SpendingValue, SupplyValue, TransactionValue, TxInValue, TxIoPaging, TxOutValue,
+    UtxoAgeStatsValue, UtxoValue, NUMERIC_QUERY_PARAMS,
+};
+use crate::daemon::{
+    MempoolAcceptResult, MempoolFees, MempoolFeesSubmitPackage, SubmitPackageResult, TxResult,
+};
+use crate::new_index::blockstats::{MultisigUsage, UtxoAgeBucket};
+use crate::new_index::{
+    BacklogStats, Burn, ConflictingTx, EvictedTx, MempoolEvent, MempoolEventKind, MempoolEvents,
+    ScriptActivity, ScriptStats, TxConflicts, TxOverview, TxState,
+};
+#[cfg(not(feature = "liquid"))]
+use crate::new_index::{LedgerEntry, LedgerEntryKind, NextBlock, ProjectionStats, TemplateStats};
+#[cfg(not(feature = "liquid"))]
+use crate::util::interpreter::{ScriptKind, Step, Trace};
+use crate::util::locktime::{LocktimeType, RelativeTimelock};
+#[cfg(not(feature = "liquid"))]
+use crate::util::price::FiatValue;
+use crate::util::{
+    BlockId, BlockStatus, Eviction, EvictionReason, MultisigKind, TransactionStatus,
+};
+use flokicoin_electrs_api::ApiError;
+#[cfg(not(feature = "liquid"))]
+use flokicoin_electrs_api::TxStatus;
+
+// the path parameters that are numbers, the others are strings
+const NUMERIC_PATH_PARAMS: &[&str] = &[
+    "height",
+    "start_height",
+    "index",
+    "start_index",
+    "vout",
+    "timestamp",
+];
+
+enum Body {
+    /// A JSON document of one of the component schemas
+    Json(&'static str),
+    /// A JSON array of one of the component schemas
+    JsonList(&'static str),
+    /// A JSON array of numbers, strings or booleans
+    List(SchemaType),
+    /// A JSON document without a component schema, only for the documents defined elsewhere
+    /// (lokid's RPC results, the asset registry and this document)
+    Any,
+    Text,
+    Binary,
+}
+
+struct Route {
+    method: PathItemType,
+    path: &'static str,
+    summary: &'static str,
+    query: &'static [&'static str],
+    body: Body,
+}
+
+const fn get(
+    path: &'static str,
+    summary: &'static str,
+    query: &'static [&'static str],
+    body: Body,
+) -> Route {
+    Route {
+        method: PathItemType::Get,
+        path,
+        summary,
+        query,
+        body,
+    }
+}
+
+const fn post(
+    path: &'static str,
+    summary: &'static str,
+    query: &'static [&'static str],
+    body: Body,
+) -> Route {
+    Route {
+        method: PathItemType::Post,
+        path,
+        summary,
+        query,
+        body,
+    }
+}
+
+const TX_LIST: Body = Body::JsonList("Transaction");
+
+const ROUTES: &[Route] = &[
+    get(
+        "/blocks/tip/hash",
+        "The hash of the best block",
+        &[],
+        Body::Text,
+    ),
+    get(
+        "/blocks/tip/height",
+        "The height of the best block",
+        &[],
+        Body::Text,
+    ),
+    get(
+        "/blocks",
+        "The 10 most recent blocks",
+        &[],
+        Body::JsonList("Block"),
+    ),
+    get(
+        "/blocks/{start_height}",
+        "The 10 blocks at and below a height",
+        &[],
+        Body::JsonList("Block"),
+    ),
+    get(
+        "/block-height/{height}",
+        "The hash of the block at a height",
+        &[],
+        Body::Text,
+    ),
+    get(
+        "/block-height/{height}/timestamp",
+        "The timestamp of the block at a height",
+        &[],
+        Body::Text,
+    ),
+    get("/block/{hash}", "A block", &[], Body::Json("Block")),
+    get(
+        "/block/{hash}/stats",
+        "The totals of the block transactions",
+        &[],
+        Body::Json("BlockStatsValue"),
+    ),
+    get(
+        "/block/{hash}/status",
+        "The confirmation status of a block",
+        &[],
+        Body::Json("BlockStatus"),
+    ),
+    get(
+        "/block/{hash}/txids",
+        "The txids of a block",
+        &[],
+        Body::List(SchemaType::String),
+    ),
+    get(
+        "/block/{hash}/header",
+        "The hex-encoded block header",
+        &[],
+        Body::Text,
+    ),
+    get("/block/{hash}/raw", "The raw block", &[], Body::Binary),
+    get(
+        "/block/{hash}/txid/{index}",
+        "The txid at a position of a block",
+        &[],
+        Body::Text,
+    ),
+    get(
+        "/block/{hash}/txs",
+        "The first transactions of a block",
+        &["prevouts"],
+        TX_LIST,
+    ),
+    get(
+        "/block/{hash}/txs/summary",
+        "The txid, fee, virtual size and output value of all the transactions of a block",
+        &[],
+        Body::JsonList("BlockTxSummary"),
+    ),
+    get(
+        "/block/{hash}/txs/{start_index}",
+        "The transactions of a block from a position",
+        &["prevouts"],
+        TX_LIST,
+    ),
+    get(
+        "/address/{address}",
+        "The confirmed and unconfirmed stats of an address",
+        &[],
+        Body::Json("AddressInfo"),
+    ),
+    get(
+        "/address/{address}/txs",
+        "The unconfirmed and the most recent confirmed transactions of an address, optionally \
+         filtered by confirmation height or time, direction (sent or received) and net amount",
+        &[
+            "after_txid",
+            "from_height",
+            "to_height",
+            "from_time",
+            "to_time",
+            "direction",
+            "min_amount",
+            "max_amount",
+        ],
+        TX_LIST,
+    ),
+    get(
+        "/address/{address}/txs/chain",
+        "The confirmed transactions of an address, newest first",
+        &[
+            "from_height",
+            "to_height",
+            "from_time",
+            "to_time",
+            "direction",
+            "min_amount",
+            "max_amount",
+        ],
+        TX_LIST,
+    ),
+    get(
+        "/address/{address}/txs/chain/{last_seen_txid}",
+        "The confirmed transactions of an address following a txid",
+        &[
+            "from_height",
+            "to_height",
+            "from_time",
+            "to_time",
+            "direction",
+            "min_amount",
+            "max_amount",
+        ],
+        TX_LIST,
+    ),
+    get(
+        "/address/{address}/txs/mempool",
+        "The unconfirmed transactions of an address",
+        &[],
+        TX_LIST,
+    ),
+    get(
+        "/address/{address}/events",
+        "The transactions of an address added to or removed from the mempool since a unix time",
+        &["since"],
+        Body::Json("MempoolEvents"),
+    ),
+    #[cfg(not(feature = "liquid"))]
+    get(
+        "/address/{address}/txs/export",
+        "The confirmed history of an address as CSV or JSON lines",
+        &["format", "from_height", "to_height", "unit"],
+        Body::Text,
+    ),
+    #[cfg(not(feature = "liquid"))]
+    get(
+        "/address/{address}/ledger",
+        "The append-only balance ledger of an address",
+        &["after", "limit"],
+        Body::JsonList("LedgerEntry"),
+    ),
+    get(
+        "/address/{address}/utxo",
+        "The unspent outputs of an address",
+        &[],
+        Body::JsonList("Utxo"),
+    ),
+    get(
+        "/address-prefix/{prefix}",
+        "The indexed addresses starting with a prefix",
+        &[],
+        Body::List(SchemaType::String),
+    ),
+    get(
+        "/search/{query}",
+        "The blocks, transactions and addresses matching a query",
+        &[],
+        Body::JsonList("SearchResult"),
+    ),
+    post(
+        "/addresses/used",
+        "Whether each of the scripthashes in the body was ever used",
+        &[],
+        Body::List(SchemaType::Boolean),
+    ),
+    get(
+        "/tx/{txid}",
+        "A transaction (by txid or wtxid)",
+        &["vin_offset", "vout_offset", "min_conf_height", "prevouts"],
+        Body::Json("Transaction"),
+    ),
+    get(
+        "/tx/{txid}/hex",
+        "The hex-encoded raw transaction",
+        &[],
+        Body::Text,
+    ),
+    get("/tx/{txid}/raw", "The raw transaction", &[], Body::Binary),
+    get(
+        "/tx/{txid}/status",
+        "The confirmation status of a transaction",
+        &["min_conf_height"],
+        Body::Json("TransactionStatus"),
+    ),
+    get(
+        "/tx/{txid}/conflicts",
+        "The transactions spending the same outputs as a transaction",
+        &[],
+        Body::Json("TxConflicts"),
+    ),
+    get(
+        "/tx/{txid}/merkle-proof",
+        "The merkle proof of a transaction",
+        &[],
+        Body::Json("MerkleProofValue"),
+    ),
+    get(
+        "/tx/{txid}/merkleblock-proof",
+        "The hex-encoded merkle block proof of a transaction",
+        &[],
+        Body::Text,
+    ),
+    get(
+        "/tx/{txid}/outspend/{vout}",
+        "The spending status of an output",
+        &[],
+        Body::Json("SpendingValue"),
+    ),
+    get(
+        "/tx/{txid}/outspends",
+        "The spending status of all the outputs of a transaction",
+        &[],
+        Body::JsonList("SpendingValue"),
+    ),
+    post(
+        "/tx",
+        "Broadcast the hex-encoded raw transaction in the body, returning its txid",
+        &[],
+        Body::Text,
+    ),
+    #[cfg(not(feature = "liquid"))]
+    post(
+        "/tx/debug-input",
+        "Execute the scripts of an input of the raw transaction in the body",
+        &["vin"],
+        Body::Json("Trace"),
+    ),
+    post(
+        "/txs/test",
+        "Test the acceptance of the raw transactions in the body",
+        &[],
+        Body::JsonList("MempoolAcceptResult"),
+    ),
+    post(
+        "/txs/package",
+        "Submit the raw transactions in the body as a package",
+        &[],
+        Body::Json("SubmitPackageResult"),
+    ),
+    get(
+        "/mempool",
+        "The mempool backlog stats",
+        &[],
+        Body::Json("BacklogStats"),
+    ),
+    get(
+        "/mempool/txids",
+        "The txids of the mempool",
+        &[],
+        Body::List(SchemaType::String),
+    ),
+    get(
+        "/mempool/txids/page",
+        "The first page of the mempool txids",
+        &["max_txs"],
+        Body::List(SchemaType::String),
+    ),
+    get(
+        "/mempool/txids/page/{last_seen_txid}",
+        "The page of the mempool txids following a txid",
+        &["max_txs"],
+        Body::List(SchemaType::String),
+    ),
+    get(
+        "/mempool/recent",
+        "The most recent transactions to enter the mempool",
+        &[],
+        Body::JsonList("TxOverview"),
+    ),
+    get(
+        "/mempool/evicted",
+        "The most recent transactions to leave the mempool unconfirmed",
+        &["limit"],
+        Body::JsonList("EvictedTx"),
+    ),
+    get(
+        "/fee-estimates",
+        "The feerate estimates by confirmation target",
+        &[],
+        Body::Json("FeeEstimatesValue"),
+    ),
+    get(
+        "/daemon/{method}",
+        "The result of a read-only lokid RPC",
+        &[],
+        Body::Any,
+    ),
+    get(
+        "/readyz",
+        "Whether the indexing isn't paused by low disk space",
+        &[],
+        Body::Text,
+    ),
+    get(
+        "/v1/export/blocks",
+        "The transactions of a range of blocks as JSON lines",
+        &["from", "to", "format", "script_types", "unit"],
+        Body::Text,
+    ),
+    get(
+        "/v1/chainstate/diff",
+        "The outputs created and spent by the blocks above a height up to another, as JSON lines",
+        &["from", "to", "unit"],
+        Body::Text,
+    ),
+    get(
+        "/v1/server-info",
+        "The version, build features, index options and uptime of the server",
+        &[],
+        Body::Json("ServerInfo"),
+    ),
+    get(
+        "/v1/supply",
+        "The coin supply at a height",
+        &["height"],
+        Body::Json("SupplyValue"),
+    ),
+    get(
+        "/v1/mining/blocks/timestamp/{timestamp}",
+        "The last block mined before a timestamp",
+        &[],
+        Body::Json("BlockTimestampValue"),
+    ),
+    #[cfg(not(feature = "liquid"))]
+    get(
+        "/v1/mining/next-block",
+        "lokid's block template compared with the mempool projection",
+        &[],
+        Body::Json("NextBlock"),
+    ),
+    get(
+        "/burns",
+        "The outputs burning coins",
+        &["from_height"],
+        Body::JsonList("Burn"),
+    ),
+    get(
+        "/stats/multisig",
+        "The usage of the multisig script types",
+        &["window"],
+        Body::Json("MultisigStatsValue"),
+    ),
+    get(
+        "/stats/utxo-age",
+        "The distribution of the utxo ages",
+        &[],
+        Body::Json("UtxoAgeStatsValue"),
+    ),
+    #[cfg(feature = "liquid")]
+    get(
+        "/assets/registry",
+        "The registered assets",
+        &["start_index", "limit", "sort_field", "sort_dir"],
+        Body::Any,
+    ),
+    #[cfg(feature = "liquid")]
+    get("/asset/{asset_id}", "An asset", &[], Body::Any),
+    #[cfg(feature = "liquid")]
+    get(
+        "/asset/{asset_id}/txs",
+        "The transactions of an asset",
+        &[],
+        TX_LIST,
+    ),
+    #[cfg(feature = "liquid")]
+    get(
+        "/asset/{asset_id}/txs/chain",
+        "The confirmed transactions of an asset",
+        &[],
+        TX_LIST,
+    ),
+    #[cfg(feature = "liquid")]
+    get(
+        "/asset/{asset_id}/txs/mempool",
+        "The unconfirmed transactions of an asset",
+        &[],
+        TX_LIST,
+    ),
+    #[cfg(feature = "liquid")]
+    get(
+        "/asset/{asset_id}/supply",
+        "The supply of an asset",
+        &[],
+        Body::Text,
+    ),
+    get("/openapi.json", "This document", &[], Body::Any),
+];
+
+pub fn openapi() -> OpenApi {
+    let mut paths = Paths::new();
+    for route in ROUTES {
+        let operation = OperationBuilder::new()
+            .summary(Some(route.summary))
+            .parameters(Some(parameters(route)))
+            .response("200", response(&route.body))
+            .response("default", error_response())
+            .build();
+        match paths.paths.get_mut(route.path) {
+            Some(item) => {
+                item.operations.insert(route.method.clone(), operation);
+            }
+            None => {
+                let item = PathItem::new(route.method.clone(), operation);
+                paths.paths.insert(route.path.to_string(), item);
+            }
+        }
+    }
+
+    // the error body is named after what it is in the document
+    let mut components = ComponentsBuilder::new().schema("Error", ApiError::schema().1);
+    components = add_schema::<TransactionValue>(components);
+    components = add_schema::<TxInValue>(components);
+    components = add_schema::<TxOutValue>(components);
+    components = add_schema::<TxIoPaging>(components);
+    components = add_schema::<TransactionStatus>(components);
+    components = add_schema::<Eviction>(components);
+    components = add_schema::<EvictionReason>(components);
+    components = add_schema::<LocktimeType>(components);
+    components = add_schema::<RelativeTimelock>(components);
+    components = add_schema::<SpendingValue>(components);
+    components = add_schema::<UtxoValue>(components);
+    components = add_schema::<BlockValue>(components);
+    components = add_schema::<BlockTxSummary>(components);
+    components = add_schema::<BlockStatus>(components);
+    components = add_schema::<ScriptStats>(components);
+    components = add_schema::<AddressInfoValue>(components);
+    components = add_schema::<BlockStatsValue>(components);
+    components = add_schema::<MerkleProofValue>(components);
+    components = add_schema::<ServerInfo>(components);
+    components = add_schema::<SupplyValue>(components);
+    components = add_schema::<BlockTimestampValue>(components);
+    components = add_schema::<MultisigStatsValue>(components);
+    components = add_schema::<UtxoAgeStatsValue>(components);
+    components = add_schema::<BacklogStats>(components);
+    components = add_schema::<MempoolEvents>(components);
+    components = add_schema::<MempoolEvent>(components);
+    components = add_schema::<MempoolEventKind>(components);
+    components = add_schema::<ScriptActivity>(components);
+    components = add_schema::<BlockId>(components);
+    components = add_schema::<MultisigUsage>(components);
+    components = add_schema::<MultisigKind>(components);
+    components = add_schema::<UtxoAgeBucket>(components);
+    components = add_schema::<FeeEstimatesValue>(components);
+    components = add_schema::<SearchResult>(components);
+    components = add_schema::<TxConflicts>(components);
+    components = add_schema::<ConflictingTx>(components);
+    components = add_schema::<TxState>(components);
+    components = add_schema::<TxOverview>(components);
+    components = add_schema::<EvictedTx>(components);
+    components = add_schema::<Burn>(components);
+    components = add_schema::<MempoolAcceptResult>(components);
+    components = add_schema::<MempoolFees>(components);
+    components = add_schema::<SubmitPackageResult>(components);
+    components = add_schema::<TxResult>(components);
+    components = add_schema::<MempoolFeesSubmitPackage>(components);
+    #[cfg(not(feature = "liquid"))]
+    {
+        components = add_schema::<LedgerEntry>(components);
+        components = add_schema::<LedgerEntryKind>(components);
+        components = add_schema::<FiatValue>(components);
+        components = add_schema::<NextBlock>(components);
+        components = add_schema::<TemplateStats>(components);
+        components = add_schema::<ProjectionStats>(components);
+        components = add_schema::<Trace>(components);
+        components = add_schema::<Step>(components);
+        components = add_schema::<ScriptKind>(components);
+        // the status embedded in the transactions and utxos of the API crate
+        components = add_schema::<TxStatus>(components);
+    }
+
+    OpenApiBuilder::new()
+        .info(
+            InfoBuilder::new()
+                .title("flokicoin-electrs REST API")
+                .version(env!("CARGO_PKG_VERSION"))
+                .description(Some(
+                    "The `/address/{address}` routes are also served for `/scripthash/{hash}`, \
+                     `/pkh/{hash}`, `/sh/{hash}` and `/taproot-key/{xonly}`. The amounts of the JSON responses are \
+                     converted to the unit given with `?unit=`.",
+                ))
+                .build(),
+        )
+        .paths(paths)
+        .components(Some(components.build()))
+        .build()
+}
+
+fn add_schema<'s, T: ToSchema<'s>>(components: ComponentsBuilder) -> ComponentsBuilder {
+    let (name, schema) = T::schema();
+    components.schema(name, schema)
+}
+
+fn parameters(route: &Route) -> Vec<utoipa::openapi::path::Parameter> {
+    let path_params = route
+        .path
+        .split('/')
+        .filter_map(|part| part.strip_prefix('{')?.strip_suffix('}'))
+        .map(|name| {
+            ParameterBuilder::new()
+                .name(name)
+                .parameter_in(ParameterIn::Path)
+                .required(Required::True)
+                .schema(Some(param_schema(NUMERIC_PATH_PARAMS.contains(&name))))
+                .build()
+        });
+    let query_params = route.query.iter().map(|name| {
+        ParameterBuilder::new()
+            .name(*name)
+            .parameter_in(ParameterIn::Query)
+            .required(Required::False)
+            .schema(Some(param_schema(NUMERIC_QUERY_PARAMS.contains(name))))
+            .build()
+    });
+    path_params.chain(query_params).collect()
+}
+
+fn param_schema(numeric: bool) -> Schema {
+    let schema_type = if numeric {
+        SchemaType::Integer
+    } else {
+        SchemaType::String
+    };
+    ObjectBuilder::new().schema_type(schema_type).into()
+}
+
+fn response(body: &Body) -> Response {
+    let (content_type, schema): (_, RefOr<Schema>) = match body {
+        Body::Json(name) => ("application/json", Ref::from_schema_name(*name).into()),
+        Body::JsonList(name) => (
+            "application/json",
+            ArrayBuilder::new()
+                .items(Ref::from_schema_name(*name))
+                .into(),
+        ),
+        Body::List(item) => (
+            "application/json",
+            ArrayBuilder::new()
+                .items(ObjectBuilder::new().schema_type(item.clone()))
+                .into(),
+        ),
+        Body::Any => (
+            "application/json",
+            ObjectBuilder::new().schema_type(SchemaType::Value).into(),
+        ),
+        Body::Text => (
+            "text/plain",
+            ObjectBuilder::new().schema_type(SchemaType::String).into(),
+        ),
+        Body::Binary => (
+            "application/octet-stream",
+            ObjectBuilder::new()
+                .schema_type(SchemaType::String)
+                .format(Some(SchemaFormat::KnownFormat(KnownFormat::Binary)))
+                .into(),
+        ),
+    };
+    ResponseBuilder::new()
+        .description("Success")
+        .content(content_type, ContentBuilder::new().schema(schema).build())
+        .build()
+}
+
+fn error_response() -> Response {
+    ResponseBuilder::new()
+        .description("Error")
+        .content(
+            "application/json",
+            ContentBuilder::new()
+                .schema(Ref::from_schema_name("Error"))
+                .build(),
+        )
+        .build()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_response_schemas() {
+        // the JSON responses refer to the registered component schemas
+        let doc = serde_json::to_value(openapi()).unwrap();
+        let schemas = &doc["components"]["schemas"];
+        for route in ROUTES {
+            if let Body::Json(name) | Body::JsonList(name) = route.body {
+                assert!(schemas[name].is_object(), "{} {}", route.path, name);
+            }
+        }
+        // the bodies defined elsewhere are the only ones without a schema
+        let mut untyped: Vec<_> = ROUTES
+            .iter()
+            .filter(|route| matches!(route.body, Body::Any))
+            .map(|route| route.path)
+            .collect();
+        let mut expected = vec!["/daemon/{method}", "/openapi.json"];
+        if cfg!(feature = "liquid") {
+            expected.extend(&["/assets/registry", "/asset/{asset_id}"]);
+        }
+        expected.sort_unstable();
+        untyped.sort_unstable();
+        assert_eq!(untyped, expected);
+
+        // every schema the document refers to is registered
+        fn check_refs(value: &serde_json::Value, schemas: &serde_json::Value) {
+            match value {
+                serde_json::Value::Object(object) => {
+                    if let Some(serde_json::Value::String(reference)) = object.get("$ref") {
+                        let name = reference.trim_start_matches("#/components/schemas/");
+                        assert!(schemas[name].is_object(), "unregistered {}", reference);
+                    }
+                    object.values().for_each(|value| check_refs(value, schemas));
+                }
+                serde_json::Value::Array(array) => {
+                    array.iter().for_each(|value| check_refs(value, schemas))
+                }
+                _ => (),
+            }
+        }
+        check_refs(&doc, schemas);
+
+        let server_info = &schemas["ServerInfo"];
+        assert!(server_info["properties"]["tip_height"].is_object());
+        assert!(!server_info["required"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .any(|field| field == "git_commit"));
+    }
+}
@@ -1,9 +1,11 @@
 use bitcoin::BlockHash;
 use crossbeam_channel::{self as channel, after, select};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
 use std::thread;
 use std::time::{Duration, Instant};
 
-use signal_hook::consts::{SIGINT, SIGTERM, SIGUSR1};
+use signal_hook::consts::{SIGHUP, SIGINT, SIGTERM, SIGUSR1};
 
 use crate::errors::*;
 
@@ -11,6 +13,7 @@ use crate::errors::*;
 pub struct Waiter {
     receiver: channel::Receiver<i32>,
     zmq_receiver: channel::Receiver<BlockHash>,
+    reload_requested: Arc<AtomicBool>,
 }
 
 fn notify(signals: &[i32]) -> channel::Receiver<i32> {
@@ -32,11 +35,18 @@ impl Waiter {
             receiver: notify(&[
                 SIGINT, SIGTERM,
                 SIGUSR1, // allow external triggering (e.g. via lokid `blocknotify`)
+                SIGHUP,  // reload the runtime configuration
             ]),
             zmq_receiver: block_hash_receive,
+            reload_requested: Arc::new(AtomicBool::new(false)),
         }
     }
 
+    /// Whether a configuration reload was requested via SIGHUP since the last call
+    pub fn take_reload_request(&self) -> bool {
+        self.reload_requested.swap(false, Ordering::SeqCst)
+    }
+
     pub fn wait(&self, duration: Duration, accept_block_notification: bool) -> Result<()> {
         let start = Instant::now();
         select! {
@@ -51,6 +61,16 @@ impl Waiter {
                             self.wait(wait_more, accept_block_notification)
                         }
                     }
+                    Ok(sig) if sig == SIGHUP => {
+                        trace!("notified via SIGHUP");
+                        self.reload_requested.store(true, Ordering::SeqCst);
+                        if accept_block_notification {
+                            Ok(())
+                        } else {
+                            let wait_more = duration.saturating_sub(start.elapsed());
+                            self.wait(wait_more, accept_block_notification)
+                        }
+                    }
                     Ok(sig) => bail!(ErrorKind::Interrupt(sig)),
                     Err(_) => bail!("signal hook channel disconnected"),
                 }
@@ -0,0 +1,554 @@
+//! A harness for deterministic end-to-end tests against a regtest node (or an elementsd node
+//! with the `liquid` feature), available with the `testing` feature.
+//!
+//! `TestRunner` starts the node, indexes it and drives it: mining blocks, funding addresses and
+//! reorging the chain. Each of these returns once the index caught up with the node, so the
+//! servers started with `start_rest()` and `start_electrum()` can be queried right away.
+//!
+//! ```no_run
+//! # fn example() -> electrs::errors::Result<()> {
+//! let mut tester = electrs::testing::TestRunner::new()?;
+//! let _rest = tester.start_rest();
+//! let addr = tester.newaddress()?;
+//! let txid = tester.send(&addr, "1 BTC".parse().unwrap())?;
+//! tester.mine()?;
+//! tester.reorg(1)?;
+//! assert!(tester.query().chain().tx_confirming_block(&txid)?.is_some());
+//! # Ok(())
+//! # }
+//! ```
+
+use std::str::FromStr;
+use std::sync::{Arc, Once, RwLock};
+use std::time::Duration;
+use std::{env, net};
+
+use log::LevelFilter;
+use stderrlog::StdErrLog;
+use tempfile::TempDir;
+
+use serde_json::Value;
+
+#[cfg(not(feature = "liquid"))]
+use bitcoind::{self as noded, BitcoinD as NodeD};
+#[cfg(feature = "liquid")]
+use elementsd::{self as noded, ElementsD as NodeD};
+
+use noded::bitcoincore_rpc::{self, RpcApi};
+
+#[cfg(not(feature = "liquid"))]
+use bitcoin::consensus::encode::{deserialize_hex, serialize_hex};
+
+use crate::chain::{Address, BlockHash, Network, Txid};
+use crate::config::{Config, RpcLogging, RunMode};
+use crate::daemon::Daemon;
+use crate::electrum::RPC as ElectrumRPC;
+use crate::errors::*;
+use crate::metrics::Metrics;
+use crate::new_index::checksum::ChecksumMode;
+use crate::new_index::db::WalMode;
+use crate::new_index::{ChainQuery, FetchFrom, HistoryAuditor, Indexer, Mempool, Query, Store};
+use crate::rest;
+use crate::signal::Waiter;
+use crate::util::pool::ThreadPools;
+
+#[cfg(not(feature = "liquid"))]
+pub mod fixture;
+#[cfg(not(feature = "liquid"))]
+use self::fixture::Fixture;
+
+pub struct TestRunner {
+    config: Arc<Config>,
+    /// Wrapper for the Lokid daemon or an elementsd::ElementsD in liquid mode
+    node: NodeD,
+    _electrsdb: TempDir, // rm'd when dropped
+    indexer: Indexer,
+    chain: Arc<ChainQuery>,
+    query: Arc<Query>,
+    daemon: Arc<Daemon>,
+    mempool: Arc<RwLock<Mempool>>,
+    metrics: Metrics,
+    pools: ThreadPools,
+    salt_rwlock: Arc<RwLock<String>>,
+}
+
+impl TestRunner {
+    /// Start a regtest node with 101 blocks and index it with the default test configuration
+    pub fn new() -> Result<TestRunner> {
+        TestRunner::with_config(|_| ())
+    }
+
+    /// Like `new()`, with a callback for adjusting the configuration before indexing
+    pub fn with_config(customize: impl FnOnce(&mut Config)) -> Result<TestRunner> {
+        TestRunner::start(customize, |client| {
+            generate(client, 101).chain_err(|| "failed initializing blocks")?;
+            Ok(())
+        })
+    }
+
+    /// Start a regtest node with the blocks of the deterministic `Fixture` (instead of blocks
+    /// mined by the node's wallet) and index it
+    #[cfg(not(feature = "liquid"))]
+    pub fn with_fixture() -> Result<(TestRunner, Fixture)> {
+        let mut fixture = None;
+        let tester = TestRunner::start(
+            |_| (),
+            |client| {
+                let genesis_hash = client
+                    .call::<BlockHash>("getblockhash", &[0.into()])
+                    .chain_err(|| "failed getting the genesis hash")?;
+                let genesis: String = client
+                    .call(
+                        "getblockheader",
+                        &[genesis_hash.to_string().into(), false.into()],
+                    )
+                    .chain_err(|| "failed getting the genesis header")?;
+                let genesis = deserialize_hex(&genesis).chain_err(|| "invalid genesis header")?;
+
+                let blocks = Fixture::new(&genesis);
+                for block in &blocks.blocks {
+                    let result = client
+                        .call::<Value>("submitblock", &[serialize_hex(block).into()])
+                        .chain_err(|| "failed submitting a fixture block")?;
+                    if !result.is_null() {
+                        bail!("fixture block {} rejected: {}", block.block_hash(), result);
+                    }
+                }
+                fixture = Some(blocks);
+                Ok(())
+            },
+        )?;
+        Ok((tester, fixture.unwrap()))
+    }
+
+    fn start(
+        customize: impl FnOnce(&mut Config),
+        init_chain: impl FnOnce(&bitcoincore_rpc::Client) -> Result<()>,
+    ) -> Result<TestRunner> {
+        let log = init_log();
+
+        // Setup the lokid/elementsd config
+        let mut node_conf = noded::Conf::default();
+        {
+            #[cfg(not(feature = "liquid"))]
+            let node_conf = &mut node_conf;
+            #[cfg(feature = "liquid")]
+            let node_conf = &mut node_conf.0;
+
+            #[cfg(feature = "liquid")]
+            node_conf.args.push("-anyonecanspendaremine=1");
+            // consider the node synced with the old timestamps of the fixture blocks
+            #[cfg(not(feature = "liquid"))]
+            node_conf.args.push("-maxtipage=2147483647");
+
+            node_conf.view_stdout = std::env::var_os("RUST_LOG").is_some();
+        }
+
+        // Setup node
+        let exe_path = noded::exe_path().chain_err(|| "node executable not found")?;
+        let node =
+            NodeD::with_conf(exe_path, &node_conf).chain_err(|| "failed starting the node")?;
+
+        #[cfg(not(feature = "liquid"))]
+        let (node_client, params) = (&node.client, &node.params);
+        #[cfg(feature = "liquid")]
+        let (node_client, params) = (node.client(), &node.params());
+
+        log::info!("node params: {:?}", params);
+
+        init_chain(node_client)?;
+
+        // Needed to claim the initialfreecoins as our own
+        // See https://github.com/ElementsProject/elements/issues/956
+        #[cfg(feature = "liquid")]
+        node_client
+            .call::<Value>("rescanblockchain", &[])
+            .chain_err(|| "failed rescanning the node wallet")?;
+
+        #[cfg(not(feature = "liquid"))]
+        let (network_type, network_name) = (Network::Regtest, "regtest");
+        #[cfg(feature = "liquid")]
+        let (network_type, network_name) = (Network::LiquidRegtest, "liquidregtest");
+
+        let mut daemon_subdir = params.cookie_file.clone();
+        // drop `.cookie` filename, leaving just the network subdirectory
+        daemon_subdir.pop();
+
+        let electrsdb =
+            tempfile::tempdir().chain_err(|| "failed creating the database directory")?;
+
+        let mut config = Config {
+            log,
+            network_type,
+            network_name: network_name.to_string(),
+            db_path: electrsdb.path().to_path_buf(),
+            daemon_dir: daemon_subdir.clone(),
+            daemon_parallelism: 3,
+            daemon_rpc_batch_size: 50,
+            blocks_dir: daemon_subdir.join("blocks"),
+            daemon_rpc_addr: params.rpc_socket.into(),
+            cookie: None,
+            electrum_rpc_addr: rand_available_addr(),
+            electrum_rpc_addr_v6: None,
+            http_addr: rand_available_addr(),
+            http_addr_v6: None,
+            http_internal_addr: None,
+            http_socket_file: None, // XXX test with socket file or tcp?
+            monitoring_addr: rand_available_addr(),
+            jsonrpc_import: false,
+            light_mode: false,
+            address_search: true,
+            index_unspendables: false,
+            utxo_age_stats: false,
+            reorg_check_depth: 6,
+            index_dedup_window: 100_000,
+            history_audit_rows: 0,
+            mempool_event_retention: Duration::from_secs(300),
+            assume_valid_height: Some(0),
+            aggregate_cache_mb: 32,
+            ledger_cache_mb: 256,
+            existence_filter_mb: 1,
+            burn_addresses: vec![],
+            cors: None,
+            precache_scripts: None,
+            utxos_limit: 100,
+            electrum_txs_limit: 100,
+            electrum_utxos_limit: 100,
+            electrum_history_page_size: 10,
+            electrum_max_block_size: 4_000_000,
+            electrum_banner: "".into(),
+            electrum_proxy_protocol: false,
+            electrum_idle_timeout: None,
+            electrum_keepalive: None,
+            electrum_max_lifetime: None,
+            rate_limit: 0.0,
+            rate_limit_burst: 50,
+            electrum_ban_threshold: 0,
+            electrum_ban_window: Duration::from_secs(60),
+            electrum_ban_duration: Duration::from_secs(3600),
+            electrum_client_stats: None,
+            slow_query_threshold: None,
+            debug_token: None,
+            internal_api_key: None,
+            rest_public_concurrency: 4,
+            query_threads: 2,
+            index_threads: 2,
+            notify_threads: 2,
+            daemon_passthrough: true,
+            price_source: None,
+            price_currency: "USD".to_string(),
+            price_refresh: Duration::from_secs(300),
+            socks_proxy: None,
+            blocklist: None,
+            rpc_logging: RpcLogging::default(),
+            zmq_addr: None,
+
+            #[cfg(feature = "liquid")]
+            asset_db_path: None, // XXX
+            #[cfg(feature = "liquid")]
+            parent_network: bitcoin::Network::Regtest,
+            initial_sync_compaction: false,
+            db_block_cache_mb: 8,
+            db_parallelism: 2,
+            db_write_buffer_size_mb: 256,
+            memory_budget_mb: None,
+            min_free_disk_mb: 0,
+            txstore_checksums: ChecksumMode::Fail,
+            wal_mode: WalMode::TipOnly,
+            run_mode: RunMode::Serve,
+            //#[cfg(feature = "electrum-discovery")]
+            //electrum_public_hosts: Option<crate::electrum::ServerHosts>,
+            //#[cfg(feature = "electrum-discovery")]
+            //electrum_onion_host: Option<(String, u16)>,
+            //#[cfg(feature = "electrum-discovery")]
+            //electrum_announce: bool,
+            //#[cfg(feature = "electrum-discovery")]
+            //electrum_seed_peers: Vec<String>,
+            //#[cfg(feature = "electrum-discovery")]
+            //tor_proxy: Option<std::net::SocketAddr>,
+        };
+        customize(&mut config);
+        let config = Arc::new(config);
+
+        let signal = Waiter::start(crossbeam_channel::never());
+        let metrics = Metrics::new(rand_available_addr());
+        metrics.start();
+
+        let daemon = Arc::new(Daemon::new(
+            &config.daemon_dir,
+            &config.blocks_dir,
+            config.daemon_rpc_addr.clone(),
+            config.daemon_parallelism,
+            config.daemon_rpc_batch_size,
+            config.cookie_getter(),
+            config.network_type,
+            signal.clone(),
+            &metrics,
+        )?);
+
+        let store = Arc::new(Store::open(&config.db_path, &config, &metrics)?);
+
+        let fetch_from = if !env::var("JSONRPC_IMPORT").is_ok() && !cfg!(feature = "liquid") {
+            // run the initial indexing from the blk files then switch to using the jsonrpc,
+            // similarly to how electrs is typically used.
+            FetchFrom::BlkFiles
+        } else {
+            // when JSONRPC_IMPORT is set, use the jsonrpc for the initial indexing too.
+            // this runs faster on small regtest chains and can be useful for quicker local development iteration.
+            // this is also used on liquid regtest, which currently fails to parse the BlkFiles due to the magic bytes
+            FetchFrom::Bitcoind
+        };
+
+        let pools = ThreadPools::new(&config, &metrics);
+        let mut indexer = Indexer::open(Arc::clone(&store), fetch_from, &config, &pools, &metrics);
+        let tip = indexer.update(&daemon)?;
+        indexer.fetch_from(FetchFrom::Bitcoind);
+
+        let chain = Arc::new(ChainQuery::new(
+            Arc::clone(&store),
+            Arc::clone(&daemon),
+            &config,
+            &pools,
+            &metrics,
+        ));
+
+        let mempool = Arc::new(RwLock::new(Mempool::new(
+            Arc::clone(&chain),
+            &metrics,
+            Arc::clone(&config),
+        )));
+        assert!(Mempool::update(&mempool, &daemon, &tip)?);
+
+        let query = Arc::new(Query::new(
+            Arc::clone(&chain),
+            Arc::clone(&mempool),
+            Arc::clone(&daemon),
+            Arc::clone(&config),
+            &metrics,
+            #[cfg(feature = "liquid")]
+            None, // TODO
+        )?);
+        // refreshed in the background by the main loop
+        #[cfg(not(feature = "liquid"))]
+        query.update_block_template();
+
+        let salt_rwlock = Arc::new(RwLock::new(String::from("foobar")));
+
+        Ok(TestRunner {
+            config,
+            node,
+            _electrsdb: electrsdb,
+            indexer,
+            chain,
+            query,
+            daemon,
+            mempool,
+            metrics,
+            pools,
+            salt_rwlock,
+        })
+    }
+
+    pub fn config(&self) -> &Arc<Config> {
+        &self.config
+    }
+
+    pub fn query(&self) -> &Arc<Query> {
+        &self.query
+    }
+
+    /// A history auditor of the index, for running the audits on demand
+    pub fn history_auditor(&self) -> HistoryAuditor {
+        HistoryAuditor::new(Arc::clone(&self.chain), &self.config, &self.metrics)
+    }
+
+    /// Start a REST server serving the index, on `config().http_addr`
+    pub fn start_rest(&self) -> rest::Handle {
+        let server = rest::start(
+            Arc::clone(&self.config),
+            Arc::clone(&self.query),
+            &self.metrics,
+        );
+        log::info!("REST server running on {}", self.config.http_addr);
+        server
+    }
+
+    /// Start an Electrum server serving the index, on `config().electrum_rpc_addr`. Its
+    /// subscribers are only notified of the changes once `ElectrumRPC::notify()` is called.
+    pub fn start_electrum(&self) -> ElectrumRPC {
+        let server = ElectrumRPC::start(
+            Arc::clone(&self.config),
+            Arc::clone(&self.query),
+            &self.pools,
+            &self.metrics,
+            Arc::clone(&self.salt_rwlock),
+        );
+        log::info!(
+            "Electrum server running on {}",
+            self.config.electrum_rpc_addr
+        );
+        server
+    }
+
+    pub fn node_client(&self) -> &bitcoincore_rpc::Client {
+        #[cfg(not(feature = "liquid"))]
+        return &self.node.client;
+        #[cfg(feature = "liquid")]
+        return &self.node.client();
+    }
+
+    /// Index the node's chain and mempool, returning once the index caught up with them
+    pub fn sync(&mut self) -> Result<()> {
+        let tip = self.indexer.update(&self.daemon)?;
+        assert!(Mempool::update(&self.mempool, &self.daemon, &tip)?);
+        // force an update for the mempool stats, which are normally cached
+        self.mempool.write().unwrap().update_backlog_stats();
+        #[cfg(not(feature = "liquid"))]
+        self.query.update_block_template();
+        Ok(())
+    }
+
+    pub fn mine(&mut self) -> Result<BlockHash> {
+        Ok(self.generate(1)?.remove(0))
+    }
+
+    /// Mine blocks including the node's mempool and sync to them
+    pub fn generate(&mut self, num_blocks: u32) -> Result<Vec<BlockHash>> {
+        let generated =
+            generate(self.node_client(), num_blocks).chain_err(|| "failed generating blocks")?;
+        self.sync()?;
+        Ok(generated)
+    }
+
+    /// Invalidate a block and sync to the resulting shorter chain, which puts the transactions of
+    /// the block back in the mempool
+    pub fn invalidate(&mut self, hash: &BlockHash) -> Result<()> {
+        self.node_client()
+            .call::<()>("invalidateblock", &[hash.to_string().into()])
+            .chain_err(|| "failed invalidating the block")?;
+        self.sync()
+    }
+
+    /// Replace the `depth` most recent blocks with `depth + 1` new ones (confirming the
+    /// transactions of the replaced blocks again), returning the hashes of the new blocks
+    pub fn reorg(&mut self, depth: u32) -> Result<Vec<BlockHash>> {
+        let client = self.node_client();
+        let tip_height = client
+            .call::<u32>("getblockcount", &[])
+            .chain_err(|| "failed getting the tip height")?;
+        assert!(depth >= 1 && depth <= tip_height, "invalid reorg depth");
+        let hash = client
+            .call::<BlockHash>("getblockhash", &[(tip_height - depth + 1).into()])
+            .chain_err(|| "failed getting the block hash")?;
+        self.invalidate(&hash)?;
+        self.generate(depth + 1)
+    }
+
+    pub fn send(&mut self, addr: &Address, amount: bitcoin::Amount) -> Result<Txid> {
+        // Must use raw call() because send_to_address() expects a Flokicoin address and not an elements::Address
+        let txid = self
+            .node_client()
+            .call(
+                "sendtoaddress",
+                &[addr.to_string().into(), json!(amount.to_btc())],
+            )
+            .chain_err(|| "failed sending to the address")?;
+        self.sync()?;
+        Ok(txid)
+    }
+
+    #[cfg(feature = "liquid")]
+    pub fn send_asset(
+        &mut self,
+        addr: &Address,
+        amount: bitcoin::Amount,
+        assetid: elements::AssetId,
+    ) -> Result<Txid> {
+        let txid = self
+            .node_client()
+            .call(
+                "sendtoaddress",
+                &[
+                    addr.to_string().into(),
+                    json!(amount.to_btc()),
+                    Value::Null,
+                    Value::Null,
+                    Value::Null,
+                    Value::Null,
+                    Value::Null,
+                    Value::Null,
+                    Value::Null,
+                    json!(assetid),
+                ],
+            )
+            .chain_err(|| "failed sending to the address")?;
+        self.sync()?;
+        Ok(txid)
+    }
+
+    /// Generate and return a new address.
+    /// Returns the unconfidential address in Liquid mode, to make it interchangeable with Flokicoin addresses in tests.
+    pub fn newaddress(&self) -> Result<Address> {
+        #[cfg(not(feature = "liquid"))]
+        return raw_new_address(self.node_client()).chain_err(|| "failed generating an address");
+
+        #[cfg(feature = "liquid")]
+        return Ok(self.ct_newaddress()?.1);
+    }
+    /// Generate a new address, returning both the confidential and non-confidential versions
+    #[cfg(feature = "liquid")]
+    pub fn ct_newaddress(&self) -> Result<(Address, Address)> {
+        let client = self.node_client();
+        let c_addr = raw_new_address(client).chain_err(|| "failed generating an address")?;
+        let mut info = client
+            .call::<Value>("getaddressinfo", &[c_addr.to_string().into()])
+            .chain_err(|| "failed getting the address info")?;
+        let uc_addr = serde_json::from_value(info["unconfidential"].take())
+            .chain_err(|| "invalid unconfidential address")?;
+        Ok((c_addr, uc_addr))
+    }
+}
+
+#[cfg(not(feature = "liquid"))]
+fn raw_new_address(
+    client: &bitcoincore_rpc::Client,
+) -> bitcoincore_rpc::Result<Address<bitcoin::address::NetworkChecked>> {
+    Ok(client.get_new_address(None, None)?.assume_checked())
+}
+
+// Returns the confidential address
+#[cfg(feature = "liquid")]
+fn raw_new_address(client: &bitcoincore_rpc::Client) -> bitcoincore_rpc::Result<Address> {
+    // Must use raw call() because get_new_address() returns a Flokicoin address and not an elements::Address
+    Ok(client.call::<Address>("getnewaddress", &[])?)
+}
+
+fn generate(
+    client: &bitcoincore_rpc::Client,
+    num_blocks: u32,
+) -> bitcoincore_rpc::Result<Vec<BlockHash>> {
+    let addr = raw_new_address(client)?;
+    client.call(
+        "generatetoaddress",
+        &[num_blocks.into(), addr.to_string().into()],
+    )
+}
+
+fn init_log() -> StdErrLog {
+    static ONCE: Once = Once::new();
+    let mut log = stderrlog::new();
+    match std::env::var("RUST_LOG") {
+        Ok(e) => log.verbosity(LevelFilter::from_str(&e).unwrap_or(LevelFilter::Off)),
+        Err(_) => log.verbosity(0),
+    };
+
+    // log.timestamp(stderrlog::Timestamp::Millisecond        );
+    ONCE.call_once(|| log.init().expect("logging initialization failed"));
+    log
+}
+
+fn rand_available_addr() -> net::SocketAddr {
+    // note this has a potential but unlikely race condition, if the port is grabbed before the caller binds it
+    let socket = net::UdpSocket::bind("127.0.0.1:0").unwrap();
+    socket.local_addr().unwrap()
+}
@@ -0,0 +1,200 @@
+//! A deterministic regtest chain, for tests comparing query responses across versions.
+//!
+//! The blocks pay to P2SH scripts that anyone can spend (`<index> OP_DROP OP_TRUE`), so spending
+//! them takes no keys, and their timestamps follow the genesis block's, so the same blocks (and
+//! the same hashes) are built on every run.
+
+use bitcoin::absolute::LockTime;
+use bitcoin::block::{Header, Version};
+use bitcoin::blockdata::constants::COINBASE_MATURITY;
+use bitcoin::hashes::Hash;
+use bitcoin::opcodes::all::OP_DROP;
+use bitcoin::opcodes::{OP_0, OP_TRUE};
+use bitcoin::script::{Builder, PushBytes};
+use bitcoin::{
+    transaction, Address, Amount, Block, BlockHash, Network, OutPoint, ScriptBuf, Sequence,
+    Transaction, TxIn, TxMerkleNode, TxOut, Txid, Witness,
+};
+
+/// The number of addresses the fixture pays to
+pub const ADDRESS_COUNT: usize = 3;
+/// The value of the coinbase outputs, kept below the subsidy
+pub const COINBASE_VALUE: u64 = 100_000_000;
+const FEE: u64 = 10_000;
+const BLOCK_INTERVAL: u32 = 600;
+
+pub struct Fixture {
+    pub blocks: Vec<Block>,
+    /// The non-coinbase transactions, in the order they were confirmed
+    pub txids: Vec<Txid>,
+}
+
+impl Fixture {
+    /// Build the chain on top of the genesis block: matured coinbases paying to the fixture
+    /// addresses in turn, then a few blocks of transactions spending them.
+    pub fn new(genesis: &Header) -> Fixture {
+        let mut fixture = Fixture {
+            blocks: vec![],
+            txids: vec![],
+        };
+        let (mut prev_hash, mut time) = (genesis.block_hash(), genesis.time);
+
+        let maturity = COINBASE_MATURITY as usize;
+        for height in 1..=maturity + 1 {
+            time += BLOCK_INTERVAL;
+            let block = mine(height, prev_hash, time, genesis, vec![]);
+            prev_hash = block.block_hash();
+            fixture.blocks.push(block);
+        }
+
+        // a payment with change, spending the coinbase of block 1
+        let payment = spend(
+            &[(coinbase_outpoint(&fixture.blocks[0]), 0)],
+            &[(1, 30_000_000), (0, COINBASE_VALUE - 30_000_000 - FEE)],
+            None,
+        );
+        // a consolidation, spending the change above and the coinbase of block 2
+        let consolidated = COINBASE_VALUE * 2 - 30_000_000 - FEE * 2;
+        let consolidation = spend(
+            &[
+                (OutPoint::new(payment.compute_txid(), 1), 0),
+                (coinbase_outpoint(&fixture.blocks[1]), 1),
+            ],
+            &[(2, consolidated)],
+            None,
+        );
+        // a transaction with an OP_RETURN output, confirmed in the same block as its parent
+        let data = spend(
+            &[(OutPoint::new(consolidation.compute_txid(), 0), 2)],
+            &[(1, consolidated - FEE)],
+            Some(&b"flokicoin-electrs fixture"[..]),
+        );
+
+        for txs in [vec![payment], vec![consolidation, data]] {
+            let height = fixture.blocks.len() + 1;
+            time += BLOCK_INTERVAL;
+            fixture
+                .txids
+                .extend(txs.iter().map(Transaction::compute_txid));
+            let block = mine(height, prev_hash, time, genesis, txs);
+            prev_hash = block.block_hash();
+            fixture.blocks.push(block);
+        }
+
+        fixture
+    }
+
+    pub fn tip_height(&self) -> usize {
+        self.blocks.len()
+    }
+
+    /// The block at a height (above the genesis block)
+    pub fn block(&self, height: usize) -> &Block {
+        &self.blocks[height - 1]
+    }
+}
+
+/// The `index`-th address the fixture pays to
+pub fn address(index: usize) -> Address {
+    Address::p2sh(&redeem_script(index), Network::Regtest).expect("valid redeem script")
+}
+
+fn redeem_script(index: usize) -> ScriptBuf {
+    Builder::new()
+        .push_int(index as i64)
+        .push_opcode(OP_DROP)
+        .push_opcode(OP_TRUE)
+        .into_script()
+}
+
+fn coinbase_outpoint(block: &Block) -> OutPoint {
+    OutPoint::new(block.txdata[0].compute_txid(), 0)
+}
+
+// Spend `(outpoint, address index)` outputs of the fixture addresses, paying to
+// `(address index, value)` outputs
+fn spend(
+    prevouts: &[(OutPoint, usize)],
+    outputs: &[(usize, u64)],
+    data: Option<&[u8]>,
+) -> Transaction {
+    let input = prevouts
+        .iter()
+        .map(|(prevout, index)| TxIn {
+            previous_output: *prevout,
+            script_sig: unlock_script(*index),
+            sequence: Sequence::MAX,
+            witness: Witness::new(),
+        })
+        .collect();
+    let mut output: Vec<TxOut> = outputs
+        .iter()
+        .map(|(index, value)| TxOut {
+            value: Amount::from_sat(*value),
+            script_pubkey: address(*index).script_pubkey(),
+        })
+        .collect();
+    if let Some(data) = data {
+        let data: &PushBytes = data.try_into().expect("short data");
+        output.push(TxOut {
+            value: Amount::ZERO,
+            script_pubkey: ScriptBuf::new_op_return(data),
+        });
+    }
+    Transaction {
+        version: transaction::Version::TWO,
+        lock_time: LockTime::ZERO,
+        input,
+        output,
+    }
+}
+
+// The scriptSig spending from the `index`-th address
+fn unlock_script(index: usize) -> ScriptBuf {
+    let redeem_script = redeem_script(index);
+    let redeem_script: &PushBytes = redeem_script.as_bytes().try_into().unwrap();
+    Builder::new().push_slice(redeem_script).into_script()
+}
+
+fn mine(
+    height: usize,
+    prev_blockhash: BlockHash,
+    time: u32,
+    genesis: &Header,
+    txs: Vec<Transaction>,
+) -> Block {
+    let coinbase = Transaction {
+        version: transaction::Version::ONE,
+        lock_time: LockTime::ZERO,
+        input: vec![TxIn {
+            previous_output: OutPoint::null(),
+            // the BIP34 height, padded to the minimum coinbase script size
+            script_sig: Builder::new()
+                .push_int(height as i64)
+                .push_opcode(OP_0)
+                .into_script(),
+            sequence: Sequence::MAX,
+            witness: Witness::new(),
+        }],
+        output: vec![TxOut {
+            value: Amount::from_sat(COINBASE_VALUE),
+            script_pubkey: address((height - 1) % ADDRESS_COUNT).script_pubkey(),
+        }],
+    };
+    let mut block = Block {
+        header: Header {
+            version: Version::from_consensus(0x2000_0000),
+            prev_blockhash,
+            merkle_root: TxMerkleNode::all_zeros(),
+            time,
+            bits: genesis.bits,
+            nonce: 0,
+        },
+        txdata: std::iter::once(coinbase).chain(txs).collect(),
+    };
+    block.header.merkle_root = block.compute_merkle_root().expect("non-empty block");
+    while block.header.validate_pow(block.header.target()).is_err() {
+        block.header.nonce += 1;
+    }
+    block
+}
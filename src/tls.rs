@@ -0,0 +1,480 @@
+//! TLS for the REST server, using either the certificate given with `--rest-tls-cert` and
+//! `--rest-tls-key`, or one provisioned (and renewed) from an ACME server like Let's Encrypt,
+//! answering its HTTP-01 challenges.
+
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::fs;
+use std::io::Write;
+use std::net::SocketAddr;
+use std::os::unix::fs::OpenOptionsExt;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+
+use error_chain::ChainedError;
+use hyper::service::{make_service_fn, service_fn};
+use hyper::{Body, Request, Response, Server, StatusCode};
+use instant_acme::{
+    Account, AccountCredentials, AuthorizationStatus, ChallengeType, Identifier, NewAccount,
+    NewOrder, Order, OrderStatus,
+};
+use rcgen::{Certificate, CertificateParams, DistinguishedName};
+use tokio::sync::oneshot;
+use tokio_rustls::rustls::server::{ClientHello, ResolvesServerCert};
+use tokio_rustls::rustls::{self, sign::CertifiedKey};
+use tokio_rustls::TlsAcceptor;
+
+use crate::config::Config;
+use crate::errors::*;
+use crate::util::unix_now;
+
+// Let's Encrypt certificates are valid for 90 days, and recommended to be renewed 30 days before
+// they expire
+const RENEW_BEFORE: Duration = Duration::from_secs(30 * 24 * 3600);
+const RENEW_CHECK_INTERVAL: Duration = Duration::from_secs(12 * 3600);
+const RETRY_INTERVAL: Duration = Duration::from_secs(10 * 60);
+const POLL_ATTEMPTS: usize = 8;
+
+const CHALLENGE_PATH: &str = "/.well-known/acme-challenge/";
+
+type Challenges = Arc<RwLock<HashMap<String, String>>>; // token -> key authorization
+
+/// Sets up the TLS acceptor for the REST server, or returns `None` when TLS is not enabled.
+/// When using ACME, the certificate is provisioned in the background, and handshakes fail
+/// until it is available. The HTTP-01 challenges are only answered while a certificate is being
+/// ordered.
+pub async fn acceptor(config: &Config) -> Result<Option<TlsAcceptor>> {
+    let resolver = Arc::new(CertResolver::default());
+    match (&config.rest_tls_cert, &config.rest_tls_key) {
+        (Some(cert_path), Some(key_path)) => {
+            resolver.set(load_certified_key(cert_path, key_path)?);
+        }
+        (None, None) if !config.rest_acme_domains.is_empty() => {
+            let acme = Acme {
+                domains: config.rest_acme_domains.clone(),
+                email: config.rest_acme_email.clone(),
+                directory_url: config.rest_acme_directory.clone(),
+                dir: config.rest_acme_dir.clone(),
+                challenge_addr: config.rest_acme_challenge_addr,
+                challenges: Default::default(),
+            };
+            fs::create_dir_all(&acme.dir)
+                .chain_err(|| format!("failed creating {}", acme.dir.display()))?;
+            tokio::spawn(acme.run(Arc::clone(&resolver)));
+        }
+        (None, None) => return Ok(None),
+        _ => bail!("both --rest-tls-cert and --rest-tls-key are required"),
+    }
+
+    let mut server_config = rustls::ServerConfig::builder()
+        .with_safe_defaults()
+        .with_no_client_auth()
+        .with_cert_resolver(resolver);
+    server_config.alpn_protocols = vec![b"http/1.1".to_vec()];
+    Ok(Some(TlsAcceptor::from(Arc::new(server_config))))
+}
+
+/// Serves the current certificate, which gets swapped on renewals
+#[derive(Default)]
+struct CertResolver(RwLock<Option<Arc<CertifiedKey>>>);
+
+impl CertResolver {
+    fn set(&self, key: CertifiedKey) {
+        *self.0.write().unwrap() = Some(Arc::new(key));
+    }
+
+    fn is_empty(&self) -> bool {
+        self.0.read().unwrap().is_none()
+    }
+}
+
+impl ResolvesServerCert for CertResolver {
+    fn resolve(&self, _client_hello: ClientHello) -> Option<Arc<CertifiedKey>> {
+        self.0.read().unwrap().clone()
+    }
+}
+
+fn load_certified_key(cert_path: &Path, key_path: &Path) -> Result<CertifiedKey> {
+    let read =
+        |path: &Path| fs::read(path).chain_err(|| format!("failed reading {}", path.display()));
+    let (cert_pem, key_pem) = (read(cert_path)?, read(key_path)?);
+
+    let certs = rustls_pemfile::certs(&mut &cert_pem[..])
+        .chain_err(|| format!("invalid certificate {}", cert_path.display()))?
+        .into_iter()
+        .map(rustls::Certificate)
+        .collect::<Vec<_>>();
+    if certs.is_empty() {
+        bail!("no certificate found in {}", cert_path.display());
+    }
+    let key = rustls_pemfile::read_all(&mut &key_pem[..])
+        .chain_err(|| format!("invalid private key {}", key_path.display()))?
+        .into_iter()
+        .find_map(|item| match item {
+            rustls_pemfile::Item::RSAKey(key)
+            | rustls_pemfile::Item::PKCS8Key(key)
+            | rustls_pemfile::Item::ECKey(key) => Some(rustls::PrivateKey(key)),
+            _ => None,
+        })
+        .chain_err(|| format!("no private key found in {}", key_path.display()))?;
+    let key = rustls::sign::any_supported_type(&key)
+        .map_err(|_| format!("unsupported private key type in {}", key_path.display()))?;
+
+    Ok(CertifiedKey::new(certs, key))
+}
+
+struct Acme {
+    domains: Vec<String>,
+    email: Option<String>,
+    directory_url: String,
+    // holds the account credentials, the certificate and its private key
+    dir: PathBuf,
+    challenge_addr: SocketAddr,
+    challenges: Challenges,
+}
+
+impl Acme {
+    async fn run(self, resolver: Arc<CertResolver>) {
+        loop {
+            let delay = match self.renew_if_needed(&resolver).await {
+                Ok(()) => RENEW_CHECK_INTERVAL,
+                Err(e) => {
+                    warn!(
+                        "failed provisioning the TLS certificate: {}",
+                        e.display_chain()
+                    );
+                    RETRY_INTERVAL
+                }
+            };
+            tokio::time::sleep(delay).await;
+        }
+    }
+
+    async fn renew_if_needed(&self, resolver: &CertResolver) -> Result<()> {
+        let cert_path = self.dir.join("cert.pem");
+        let key_path = self.dir.join("key.pem");
+
+        let expires_at = fs::read(&cert_path)
+            .ok()
+            .and_then(|cert_pem| cert_expiry(&cert_pem));
+        let renew_at = expires_at.map(|expires_at| expires_at - RENEW_BEFORE.as_secs() as i64);
+        if renew_at.map_or(false, |renew_at| (unix_now() as i64) < renew_at) {
+            if resolver.is_empty() {
+                resolver.set(load_certified_key(&cert_path, &key_path)?);
+            }
+            return Ok(());
+        }
+
+        info!(
+            "requesting a TLS certificate for {} from {}",
+            self.domains.join(", "),
+            self.directory_url
+        );
+        let (cert_pem, key_pem) = self.order_certificate().await?;
+        // both files are complete before replacing the previous ones
+        let key_tmp_path = write_private_tmp(&key_path, key_pem.as_bytes())?;
+        let cert_tmp_path = write_private_tmp(&cert_path, cert_pem.as_bytes())?;
+        install(&key_tmp_path, &key_path)?;
+        install(&cert_tmp_path, &cert_path)?;
+        resolver.set(load_certified_key(&cert_path, &key_path)?);
+        info!("installed a new TLS certificate");
+        Ok(())
+    }
+
+    async fn account(&self) -> Result<Account> {
+        let path = self.dir.join("account.json");
+        if let Ok(contents) = fs::read(&path) {
+            let credentials: AccountCredentials = serde_json::from_slice(&contents)
+                .chain_err(|| format!("invalid ACME account {}", path.display()))?;
+            return Account::from_credentials(credentials)
+                .chain_err(|| "failed loading the ACME account");
+        }
+
+        let contact = self
+            .email
+            .iter()
+            .map(|email| format!("mailto:{}", email))
+            .collect::<Vec<_>>();
+        let contact = contact.iter().map(String::as_str).collect::<Vec<_>>();
+        let (account, credentials) = Account::create(
+            &NewAccount {
+                contact: &contact,
+                terms_of_service_agreed: true,
+                only_return_existing: false,
+            },
+            &self.directory_url,
+            None,
+        )
+        .await
+        .chain_err(|| "failed creating the ACME account")?;
+        let credentials =
+            serde_json::to_vec(&credentials).chain_err(|| "invalid ACME account credentials")?;
+        write_private(&path, &credentials)?;
+        Ok(account)
+    }
+
+    // Returns the PEM-encoded certificate chain and private key
+    async fn order_certificate(&self) -> Result<(String, String)> {
+        let account = self.account().await?;
+        let identifiers = self
+            .domains
+            .iter()
+            .map(|domain| Identifier::Dns(domain.clone()))
+            .collect::<Vec<_>>();
+        let mut order = account
+            .new_order(&NewOrder {
+                identifiers: &identifiers,
+            })
+            .await
+            .chain_err(|| "failed creating the ACME order")?;
+        // stops answering the challenges when dropped
+        let _challenges_server =
+            serve_challenges(self.challenge_addr, Arc::clone(&self.challenges))?;
+
+        let authorizations = order
+            .authorizations()
+            .await
+            .chain_err(|| "failed fetching the ACME authorizations")?;
+        let mut tokens = vec![];
+        for authz in &authorizations {
+            if let AuthorizationStatus::Valid = authz.status {
+                continue;
+            }
+            let challenge = authz
+                .challenges
+                .iter()
+                .find(|challenge| challenge.r#type == ChallengeType::Http01)
+                .chain_err(|| format!("no HTTP-01 challenge for {:?}", authz.identifier))?;
+            let key_authorization = order.key_authorization(challenge).as_str().to_string();
+            self.challenges
+                .write()
+                .unwrap()
+                .insert(challenge.token.clone(), key_authorization);
+            tokens.push(challenge.token.clone());
+            order
+                .set_challenge_ready(&challenge.url)
+                .await
+                .chain_err(|| "failed notifying the ACME server")?;
+        }
+
+        let result = self.finalize(&mut order).await;
+        let mut challenges = self.challenges.write().unwrap();
+        for token in tokens {
+            challenges.remove(&token);
+        }
+        result
+    }
+
+    async fn finalize(&self, order: &mut Order) -> Result<(String, String)> {
+        let mut delay = Duration::from_secs(1);
+        for attempt in 0.. {
+            if attempt == POLL_ATTEMPTS {
+                bail!("timed out waiting for the ACME challenges validation");
+            }
+            tokio::time::sleep(delay).await;
+            let state = order
+                .refresh()
+                .await
+                .chain_err(|| "failed polling the ACME order")?;
+            match state.status {
+                OrderStatus::Ready | OrderStatus::Valid => break,
+                OrderStatus::Invalid => bail!("the ACME order is invalid: {:?}", state),
+                OrderStatus::Pending | OrderStatus::Processing => delay *= 2,
+            }
+        }
+
+        let mut params = CertificateParams::new(self.domains.clone());
+        params.distinguished_name = DistinguishedName::new();
+        let cert = Certificate::from_params(params).chain_err(|| "failed generating a key")?;
+        let csr = cert
+            .serialize_request_der()
+            .chain_err(|| "failed generating the CSR")?;
+        order
+            .finalize(&csr)
+            .await
+            .chain_err(|| "failed finalizing the ACME order")?;
+
+        let mut delay = Duration::from_secs(1);
+        for _ in 0..POLL_ATTEMPTS {
+            let cert_pem = order
+                .certificate()
+                .await
+                .chain_err(|| "failed downloading the certificate")?;
+            if let Some(cert_pem) = cert_pem {
+                return Ok((cert_pem, cert.serialize_private_key_pem()));
+            }
+            tokio::time::sleep(delay).await;
+            delay *= 2;
+        }
+        bail!("timed out waiting for the certificate to be issued")
+    }
+}
+
+// Serves the key authorizations of the pending HTTP-01 challenges, until the returned sender
+// is dropped
+fn serve_challenges(addr: SocketAddr, challenges: Challenges) -> Result<oneshot::Sender<()>> {
+    let make_service = make_service_fn(move |_| {
+        let challenges = Arc::clone(&challenges);
+        async move {
+            Ok::<_, hyper::Error>(service_fn(move |req: Request<Body>| {
+                let key_authorization = req
+                    .uri()
+                    .path()
+                    .strip_prefix(CHALLENGE_PATH)
+                    .and_then(|token| challenges.read().unwrap().get(token).cloned());
+                async move {
+                    Ok::<_, hyper::Error>(match key_authorization {
+                        Some(key_authorization) => Response::new(Body::from(key_authorization)),
+                        None => Response::builder()
+                            .status(StatusCode::NOT_FOUND)
+                            .body(Body::empty())
+                            .unwrap(),
+                    })
+                }
+            }))
+        }
+    });
+
+    let server = Server::try_bind(&addr)
+        .chain_err(|| format!("failed binding the ACME challenges server to {}", addr))?;
+    let (stop, stopped) = oneshot::channel();
+    let server = server
+        .serve(make_service)
+        .with_graceful_shutdown(async move {
+            stopped.await.ok();
+        });
+    debug!("serving ACME challenges on {}", addr);
+    tokio::spawn(async move {
+        if let Err(e) = server.await {
+            error!("ACME challenges server error: {}", e);
+        }
+    });
+    Ok(stop)
+}
+
+// The expiry of the first certificate of the PEM-encoded chain, as a unix timestamp
+fn cert_expiry(cert_pem: &[u8]) -> Option<i64> {
+    let certs = rustls_pemfile::certs(&mut &cert_pem[..]).ok()?;
+    not_after(certs.first()?)
+}
+
+// The notAfter of the DER-encoded certificate, as a unix timestamp:
+// Certificate ::= SEQUENCE { tbsCertificate SEQUENCE { [0] version OPTIONAL, serialNumber,
+//   signature, issuer, validity SEQUENCE { notBefore, notAfter }, ... }, ... }
+fn not_after(der: &[u8]) -> Option<i64> {
+    let (_, cert, _) = der_element(der)?;
+    let (_, mut tbs, _) = der_element(cert)?;
+    if let (0xa0, _, rest) = der_element(tbs)? {
+        tbs = rest;
+    }
+    for _ in 0..3 {
+        tbs = der_element(tbs)?.2;
+    }
+    let (_, validity, _) = der_element(tbs)?;
+    let (_, _, validity) = der_element(validity)?;
+    let (tag, value, _) = der_element(validity)?;
+    parse_der_time(tag, value)
+}
+
+// Splits the first DER element into its tag, its contents and the elements following it
+fn der_element(der: &[u8]) -> Option<(u8, &[u8], &[u8])> {
+    let (&tag, der) = der.split_first()?;
+    let (&len, mut der) = der.split_first()?;
+    let len = if len < 0x80 {
+        len as usize
+    } else {
+        let len_bytes = (len & 0x7f) as usize;
+        if len_bytes == 0 || len_bytes > 4 || der.len() < len_bytes {
+            return None;
+        }
+        let (len, rest) = der.split_at(len_bytes);
+        der = rest;
+        len.iter().fold(0, |len, &byte| len << 8 | byte as usize)
+    };
+    if der.len() < len {
+        return None;
+    }
+    let (contents, rest) = der.split_at(len);
+    Some((tag, contents, rest))
+}
+
+// Parses a UTCTime (YYMMDDHHMMSSZ) or a GeneralizedTime (YYYYMMDDHHMMSSZ) as a unix timestamp
+fn parse_der_time(tag: u8, value: &[u8]) -> Option<i64> {
+    let value = std::str::from_utf8(value).ok()?.strip_suffix('Z')?;
+    let (year, rest) = match tag {
+        0x17 => {
+            let year: i32 = value.get(..2)?.parse().ok()?;
+            let year = if year < 50 { 2000 + year } else { 1900 + year };
+            (year, value.get(2..)?)
+        }
+        0x18 => (value.get(..4)?.parse().ok()?, value.get(4..)?),
+        _ => return None,
+    };
+    if rest.len() != 10 {
+        return None;
+    }
+    let field = |at: usize| -> Option<u8> { rest.get(at..at + 2)?.parse().ok() };
+    let month = time::Month::try_from(field(0)?).ok()?;
+    let datetime = time::Date::from_calendar_date(year, month, field(2)?)
+        .ok()?
+        .with_hms(field(4)?, field(6)?, field(8)?)
+        .ok()?;
+    Some(datetime.assume_utc().unix_timestamp())
+}
+
+// Writes a file only readable by the current user, replacing it at once
+fn write_private(path: &Path, contents: &[u8]) -> Result<()> {
+    install(&write_private_tmp(path, contents)?, path)
+}
+
+// Writes a file only readable by the current user next to `path`, returning its path to
+// `install` it once complete
+fn write_private_tmp(path: &Path, contents: &[u8]) -> Result<PathBuf> {
+    let tmp_path = path.with_extension("tmp");
+    fs::OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .mode(0o600)
+        .open(&tmp_path)
+        .and_then(|mut file| {
+            file.write_all(contents)?;
+            file.sync_all()
+        })
+        .chain_err(|| format!("failed writing {}", tmp_path.display()))?;
+    Ok(tmp_path)
+}
+
+fn install(tmp_path: &Path, path: &Path) -> Result<()> {
+    fs::rename(tmp_path, path)
+        .chain_err(|| format!("failed moving {} to {}", tmp_path.display(), path.display()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_cert_expiry() {
+        let not_after = time::OffsetDateTime::from_unix_timestamp(1941873015).unwrap();
+        let mut params = CertificateParams::new(vec!["example.com".to_string()]);
+        params.not_after = not_after;
+        let cert = Certificate::from_params(params).unwrap();
+        let cert_pem = cert.serialize_pem().unwrap();
+        assert_eq!(
+            cert_expiry(cert_pem.as_bytes()),
+            Some(not_after.unix_timestamp())
+        );
+        assert_eq!(cert_expiry(b"not a certificate"), None);
+    }
+
+    #[test]
+    fn test_parse_der_time() {
+        assert_eq!(parse_der_time(0x17, b"490101000000Z"), Some(2493072000));
+        assert_eq!(parse_der_time(0x17, b"700101000001Z"), Some(1));
+        assert_eq!(parse_der_time(0x18, b"20500101000000Z"), Some(2524608000));
+        assert_eq!(parse_der_time(0x17, b"701301000000Z"), None);
+        assert_eq!(parse_der_time(0x04, b"700101000000Z"), None);
+    }
+}
@@ -0,0 +1,88 @@
+//! An alternative global allocator, behind the `jemalloc` or `mimalloc` features. Both return
+//! freed memory to the OS more readily than glibc's malloc, whose fragmentation makes the
+//! resident memory of long-running instances grow well above what they allocate. When both
+//! features are enabled (like with `--all-features`), jemalloc is used.
+
+use crate::errors::*;
+
+#[cfg(feature = "jemalloc")]
+#[global_allocator]
+static GLOBAL: tikv_jemallocator::Jemalloc = tikv_jemallocator::Jemalloc;
+
+#[cfg(all(feature = "mimalloc", not(feature = "jemalloc")))]
+#[global_allocator]
+static GLOBAL: mimalloc::MiMalloc = mimalloc::MiMalloc;
+
+/// The allocator in use, unless it is the system's
+pub const NAME: Option<&str> = if cfg!(feature = "jemalloc") {
+    Some("jemalloc")
+} else if cfg!(feature = "mimalloc") {
+    Some("mimalloc")
+} else {
+    None
+};
+
+/// The memory usage reported by the allocator, in bytes
+#[derive(Debug, Clone, Copy)]
+pub struct AllocatorStats {
+    /// Allocated by the application (not reported by mimalloc)
+    pub allocated: Option<u64>,
+    /// In the pages holding allocations (committed, for mimalloc)
+    pub active: u64,
+    /// Physically resident
+    pub resident: u64,
+}
+
+impl AllocatorStats {
+    /// The share of the resident memory that isn't allocated, between 0 and 1
+    pub fn fragmentation(&self) -> Option<f64> {
+        let allocated = self.allocated?;
+        (self.resident > 0).then(|| 1.0 - (allocated as f64 / self.resident as f64).min(1.0))
+    }
+}
+
+#[cfg(feature = "jemalloc")]
+pub fn stats() -> Result<AllocatorStats> {
+    use tikv_jemalloc_ctl::{epoch, stats};
+
+    let read = |name: &str, value: tikv_jemalloc_ctl::Result<usize>| -> Result<u64> {
+        value
+            .map(|value| value as u64)
+            .map_err(|e| format!("failed reading jemalloc {}: {}", name, e).into())
+    };
+    // the statistics are refreshed when the epoch advances
+    epoch::advance().map_err(|e| format!("failed advancing the jemalloc epoch: {}", e))?;
+    Ok(AllocatorStats {
+        allocated: Some(read("allocated", stats::allocated::read())?),
+        active: read("active", stats::active::read())?,
+        resident: read("resident", stats::resident::read())?,
+    })
+}
+
+#[cfg(all(feature = "mimalloc", not(feature = "jemalloc")))]
+pub fn stats() -> Result<AllocatorStats> {
+    let (mut elapsed, mut user, mut system, mut faults) = (0, 0, 0, 0);
+    let (mut rss, mut peak_rss, mut commit, mut peak_commit) = (0, 0, 0, 0);
+    unsafe {
+        libmimalloc_sys::mi_process_info(
+            &mut elapsed,
+            &mut user,
+            &mut system,
+            &mut rss,
+            &mut peak_rss,
+            &mut commit,
+            &mut peak_commit,
+            &mut faults,
+        )
+    };
+    Ok(AllocatorStats {
+        allocated: None,
+        active: commit as u64,
+        resident: rss as u64,
+    })
+}
+
+#[cfg(not(any(feature = "jemalloc", feature = "mimalloc")))]
+pub fn stats() -> Result<AllocatorStats> {
+    bail!("the system allocator reports no statistics")
+}
@@ -0,0 +1,4 @@
+//! The amount formats of the REST responses, shared with the API crate which serializes them
+//! with the same `serialize_with` helpers.
+
+pub use flokicoin_electrs_api::amount::*;
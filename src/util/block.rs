@@ -1,13 +1,21 @@
-use crate::chain::{BlockHash, BlockHeader};
+use crate::chain::{BlockHash, BlockHeader, Network};
 use crate::errors::*;
 use crate::new_index::BlockEntry;
 
+#[cfg(not(feature = "liquid"))]
+use bitcoin::hex::DisplayHex;
+#[cfg(not(feature = "liquid"))]
+use bitcoin::{params::Params, CompactTarget, Work};
+#[cfg(not(feature = "liquid"))]
+use rayon::prelude::*;
+
 use std::collections::HashMap;
 use std::fmt;
 use std::iter::FromIterator;
 use std::slice;
 use time::format_description::well_known::Rfc3339;
 use time::OffsetDateTime as DateTime;
+use utoipa::ToSchema;
 
 use electrs_macros::trace;
 
@@ -20,9 +28,10 @@ lazy_static! {
             .unwrap();
 }
 
-#[derive(Debug, Serialize, Deserialize, Clone, Copy)]
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, ToSchema)]
 pub struct BlockId {
     pub height: usize,
+    #[schema(value_type = String)]
     pub hash: BlockHash,
     pub time: u32,
 }
@@ -42,6 +51,9 @@ pub struct HeaderEntry {
     height: usize,
     hash: BlockHash,
     header: BlockHeader,
+    // the cumulative work of the chain up to this block
+    #[cfg(not(feature = "liquid"))]
+    chainwork: Work,
 }
 
 impl HeaderEntry {
@@ -50,6 +62,8 @@ impl HeaderEntry {
         Self {
             height,
             hash,
+            #[cfg(not(feature = "liquid"))]
+            chainwork: header.work(),
             header,
         }
     }
@@ -64,6 +78,11 @@ impl HeaderEntry {
     pub fn height(&self) -> usize {
         self.height
     }
+
+    #[cfg(not(feature = "liquid"))]
+    pub fn chainwork(&self) -> Work {
+        self.chainwork
+    }
 }
 
 impl fmt::Debug for HeaderEntry {
@@ -154,26 +173,151 @@ impl HeaderList {
             Some(h) => h.header.prev_blockhash,
             None => return vec![], // hashed_headers is empty
         };
-        let new_height: usize = if prev_blockhash == *DEFAULT_BLOCKHASH {
-            0
+        let prev = if prev_blockhash == *DEFAULT_BLOCKHASH {
+            None
         } else {
-            self.header_by_blockhash(&prev_blockhash)
-                .unwrap_or_else(|| panic!("{} is not part of the blockchain", prev_blockhash))
-                .height()
-                + 1
+            Some(
+                self.header_by_blockhash(&prev_blockhash)
+                    .unwrap_or_else(|| panic!("{} is not part of the blockchain", prev_blockhash)),
+            )
         };
+        let new_height: usize = prev.map_or(0, |prev| prev.height() + 1);
+        #[cfg(not(feature = "liquid"))]
+        let mut chainwork = prev.map(HeaderEntry::chainwork);
         (new_height..)
             .zip(hashed_headers.into_iter())
-            .map(|(height, hashed_header)| HeaderEntry {
-                height,
-                hash: hashed_header.blockhash,
-                header: hashed_header.header,
+            .map(|(height, hashed_header)| {
+                #[cfg(not(feature = "liquid"))]
+                {
+                    let work = hashed_header.header.work();
+                    chainwork = Some(chainwork.map_or(work, |chainwork| chainwork + work));
+                }
+                HeaderEntry {
+                    height,
+                    hash: hashed_header.blockhash,
+                    header: hashed_header.header,
+                    #[cfg(not(feature = "liquid"))]
+                    chainwork: chainwork.unwrap(),
+                }
             })
             .collect()
     }
 
+    /// Validates new headers (as returned by `order()`) against the current chain before they
+    /// are applied: the proof-of-work and its difficulty, the timestamp against the median time
+    /// past, and that reorgs increase the chain work. Headers below `assume_valid_height` are
+    /// trusted as-is.
+    #[cfg(not(feature = "liquid"))]
     #[trace]
-    pub fn apply(&mut self, new_headers: Vec<HeaderEntry>) {
+    pub fn validate(
+        &self,
+        new_headers: &[HeaderEntry],
+        network: Network,
+        assume_valid_height: usize,
+    ) -> Result<()> {
+        let params = Params::new(network.into());
+        self.validate_with(new_headers, &params, assume_valid_height)
+    }
+
+    #[cfg(not(feature = "liquid"))]
+    fn validate_with(
+        &self,
+        new_headers: &[HeaderEntry],
+        params: &Params,
+        assume_valid_height: usize,
+    ) -> Result<()> {
+        let first_height = match new_headers.first() {
+            Some(entry) => entry.height(),
+            None => return Ok(()),
+        };
+        let header_at = |height: usize| match height.checked_sub(first_height) {
+            Some(i) => new_headers[i].header(),
+            None => self.headers[height].header(),
+        };
+
+        if first_height < self.len() {
+            let work = |entries: &[HeaderEntry]| {
+                entries
+                    .iter()
+                    .map(|entry| entry.header().work())
+                    .reduce(|a, b| a + b)
+            };
+            if work(new_headers) <= work(&self.headers[first_height..]) {
+                bail!(
+                    "reorg to {:?} does not increase the chain work",
+                    new_headers.last().unwrap()
+                );
+            }
+        }
+
+        let interval = params.difficulty_adjustment_interval() as usize;
+        let validated = new_headers
+            .iter()
+            .filter(|entry| entry.height() > 0 && entry.height() >= assume_valid_height);
+        for entry in validated {
+            let height = entry.height();
+            let header = entry.header();
+            if header.target() > params.max_attainable_target {
+                bail!(
+                    "target of block {:?} is above the proof-of-work limit",
+                    entry
+                );
+            }
+
+            let prev = header_at(height - 1);
+            let expected_bits = if params.no_pow_retargeting {
+                Some(prev.bits)
+            } else if height % interval == 0 {
+                let epoch_start = *header_at(height - interval);
+                Some(CompactTarget::from_header_difficulty_adjustment(
+                    epoch_start,
+                    *prev,
+                    params,
+                ))
+            } else if params.allow_min_difficulty_blocks {
+                None // testnets allow minimum difficulty blocks in between retargets
+            } else {
+                Some(prev.bits)
+            };
+            if expected_bits.map_or(false, |bits| bits != header.bits) {
+                bail!(
+                    "unexpected difficulty bits {:#x} for block {:?}",
+                    header.bits.to_consensus(),
+                    entry
+                );
+            }
+
+            let mtp = median_time(
+                (height.saturating_sub(MTP_SPAN)..height)
+                    .map(|p_height| header_at(p_height).time)
+                    .collect(),
+            );
+            if header.time <= mtp {
+                bail!(
+                    "timestamp of block {:?} is not after the median time past ({})",
+                    entry,
+                    mtp
+                );
+            }
+        }
+
+        check_headers_pow(new_headers, assume_valid_height)
+    }
+
+    // Elements blocks are signed by the federation rather than mined
+    #[cfg(feature = "liquid")]
+    pub fn validate(
+        &self,
+        _new_headers: &[HeaderEntry],
+        _network: Network,
+        _assume_valid_height: usize,
+    ) -> Result<()> {
+        Ok(())
+    }
+
+    /// Applies the new headers on top of the current chain, returning the orphaned entries
+    #[trace]
+    pub fn apply(&mut self, new_headers: Vec<HeaderEntry>) -> Vec<HeaderEntry> {
         // new_headers[i] -> new_headers[i - 1] (i.e. new_headers.last() is the tip)
         for i in 1..new_headers.len() {
             assert_eq!(new_headers[i - 1].height() + 1, new_headers[i].height());
@@ -193,14 +337,14 @@ impl HeaderList {
                 assert_eq!(entry.header().prev_blockhash, expected_prev_blockhash);
                 height
             }
-            None => return,
+            None => return vec![],
         };
         debug!(
             "applying {} new headers from height {}",
             new_headers.len(),
             new_height
         );
-        let _removed = self.headers.split_off(new_height); // keep [0..new_height) entries
+        let removed = self.headers.split_off(new_height); // keep [0..new_height) entries
         for new_header in new_headers {
             let height = new_header.height();
             assert_eq!(height, self.headers.len());
@@ -208,6 +352,7 @@ impl HeaderList {
             self.headers.push(new_header);
             self.heights.insert(self.tip, height);
         }
+        removed
     }
 
     #[trace]
@@ -229,6 +374,32 @@ impl HeaderList {
         })
     }
 
+    /// Get the header with the timestamp closest to `time`. Block timestamps are not strictly
+    /// increasing, so this is found by a binary search that may land next to an out-of-order
+    /// block rather than on the closest one overall.
+    pub fn header_by_time(&self, time: u32) -> Option<&HeaderEntry> {
+        let next = self
+            .headers
+            .partition_point(|entry| entry.header.time <= time);
+        let prev = next
+            .checked_sub(1)
+            .and_then(|height| self.headers.get(height));
+        match (prev, self.headers.get(next)) {
+            (Some(prev), Some(next)) if next.header.time - time < time - prev.header.time => {
+                Some(next)
+            }
+            (Some(prev), _) => Some(prev),
+            (None, next) => next,
+        }
+    }
+
+    /// Get the height of the first block timestamped at or after `time` (or the height following
+    /// the tip if there's none), found by a binary search like `header_by_time()`
+    pub fn height_by_time(&self, time: u32) -> usize {
+        self.headers
+            .partition_point(|entry| entry.header.time < time)
+    }
+
     pub fn equals(&self, other: &HeaderList) -> bool {
         self.headers.last() == other.headers.last()
     }
@@ -265,28 +436,65 @@ impl HeaderList {
         } else if height > self.len() - 1 {
             0
         } else {
-            let mut timestamps = (height.saturating_sub(MTP_SPAN - 1)..=height)
-                .map(|p_height| self.headers.get(p_height).unwrap().header.time)
-                .collect::<Vec<_>>();
-            timestamps.sort_unstable();
-            timestamps[timestamps.len() / 2]
+            median_time(
+                (height.saturating_sub(MTP_SPAN - 1)..=height)
+                    .map(|p_height| self.headers.get(p_height).unwrap().header.time)
+                    .collect(),
+            )
         }
     }
 }
 
-#[derive(Serialize, Deserialize)]
+// Checks the proof-of-work of the headers against the target encoded in their `bits`
+#[cfg(not(feature = "liquid"))]
+fn check_headers_pow(entries: &[HeaderEntry], assume_valid_height: usize) -> Result<()> {
+    entries
+        .par_iter()
+        .filter(|entry| entry.height() >= assume_valid_height)
+        .try_for_each(|entry| {
+            let header = entry.header();
+            header
+                .validate_pow(header.target())
+                .map(|_| ())
+                .map_err(|e| format!("invalid proof-of-work for block {:?}: {}", entry, e).into())
+        })
+}
+
+fn median_time(mut timestamps: Vec<u32>) -> u32 {
+    timestamps.sort_unstable();
+    timestamps[timestamps.len() / 2]
+}
+
+#[derive(Serialize, Deserialize, ToSchema)]
 pub struct BlockStatus {
     pub in_best_chain: bool,
     pub height: Option<usize>,
+    #[schema(value_type = Option<String>)]
     pub next_best: Option<BlockHash>,
+    pub confirmations: Option<usize>,
+    /// The block the confirmations are counted from, for telling them apart across reorgs
+    #[schema(value_type = Option<String>)]
+    pub block_hash: Option<BlockHash>,
+    /// The cumulative work of the chain up to the block, as a hex string
+    pub chainwork: Option<String>,
 }
 
 impl BlockStatus {
-    pub fn confirmed(height: usize, next_best: Option<BlockHash>) -> BlockStatus {
+    pub fn confirmed(
+        entry: &HeaderEntry,
+        next_best: Option<BlockHash>,
+        best_height: usize,
+    ) -> BlockStatus {
         BlockStatus {
             in_best_chain: true,
-            height: Some(height),
+            height: Some(entry.height()),
             next_best,
+            confirmations: Some(best_height + 1 - entry.height()),
+            block_hash: Some(*entry.hash()),
+            #[cfg(not(feature = "liquid"))]
+            chainwork: Some(entry.chainwork().to_be_bytes().to_lower_hex_string()),
+            #[cfg(feature = "liquid")]
+            chainwork: None,
         }
     }
 
@@ -295,6 +503,9 @@ impl BlockStatus {
             in_best_chain: false,
             height: None,
             next_best: None,
+            confirmations: None,
+            block_hash: None,
+            chainwork: None,
         }
     }
 }
@@ -351,3 +562,150 @@ impl BlockMeta {
         })
     }
 }
+
+#[cfg(all(test, not(feature = "liquid")))]
+mod tests {
+    use super::*;
+    use bitcoin::block::Version;
+    use bitcoin::hashes::Hash;
+    use bitcoin::TxMerkleNode;
+
+    const GENESIS_TIME: u32 = 1_600_000_000;
+
+    // regtest's proof-of-work limit, with a difficulty retarget every 4 blocks
+    fn params() -> Params {
+        let mut params = Params::new(Network::Regtest.into());
+        params.no_pow_retargeting = false;
+        params.allow_min_difficulty_blocks = false;
+        params.pow_target_timespan = 4 * params.pow_target_spacing;
+        params
+    }
+
+    fn mine(prev: Option<&BlockHeader>, time: u32, bits: CompactTarget) -> BlockHeader {
+        let mut header = BlockHeader {
+            version: Version::from_consensus(0x2000_0000),
+            prev_blockhash: prev.map_or(*DEFAULT_BLOCKHASH, BlockHeader::block_hash),
+            merkle_root: TxMerkleNode::all_zeros(),
+            time,
+            bits,
+            nonce: 0,
+        };
+        while header.validate_pow(header.target()).is_err() {
+            header.nonce += 1;
+        }
+        header
+    }
+
+    // Extends the chain with `count` valid headers, mined a second apart so that each retarget
+    // raises the difficulty
+    fn extend(headers: &mut Vec<BlockHeader>, count: usize) {
+        let params = params();
+        let interval = params.difficulty_adjustment_interval() as usize;
+        for _ in 0..count {
+            let height = headers.len();
+            let bits = match headers.last() {
+                None => params.max_attainable_target.to_compact_lossy(),
+                Some(prev) if height % interval == 0 => {
+                    CompactTarget::from_header_difficulty_adjustment(
+                        headers[height - interval],
+                        *prev,
+                        &params,
+                    )
+                }
+                Some(prev) => prev.bits,
+            };
+            let header = mine(headers.last(), GENESIS_TIME + height as u32, bits);
+            headers.push(header);
+        }
+    }
+
+    fn validate(list: &HeaderList, headers: &[BlockHeader]) -> Result<()> {
+        list.validate_with(&list.order(headers.to_vec()), &params(), 0)
+    }
+
+    #[test]
+    fn test_validate_retarget() {
+        let mut headers = vec![];
+        extend(&mut headers, 10);
+        // the difficulty went up at both retarget boundaries
+        assert!(headers[4].target() < headers[3].target());
+        assert!(headers[8].target() < headers[7].target());
+
+        let mut list = HeaderList::empty();
+        validate(&list, &headers).unwrap();
+
+        // the headers below the new ones are taken from the chain, like the start of the epoch
+        list.apply(list.order(headers[..6].to_vec()));
+        validate(&list, &headers[6..]).unwrap();
+    }
+
+    #[test]
+    fn test_validate_unexpected_bits() {
+        let list = HeaderList::empty();
+
+        // missing retarget
+        let mut headers = vec![];
+        extend(&mut headers, 4);
+        let time = headers[3].time + 1;
+        headers.push(mine(headers.last(), time, headers[3].bits));
+        let err = validate(&list, &headers).unwrap_err();
+        assert!(err.to_string().contains("unexpected difficulty bits"));
+        // unless below the assumed valid height
+        let entries = list.order(headers.clone());
+        list.validate_with(&entries, &params(), 5).unwrap();
+
+        // difficulty change in between retargets
+        headers.truncate(4);
+        extend(&mut headers, 1);
+        let time = headers[4].time + 1;
+        headers.push(mine(headers.last(), time, headers[3].bits));
+        let err = validate(&list, &headers).unwrap_err();
+        assert!(err.to_string().contains("unexpected difficulty bits"));
+    }
+
+    #[test]
+    fn test_validate_timestamp() {
+        let list = HeaderList::empty();
+        let mut headers = vec![];
+        extend(&mut headers, 3);
+        // the median of the 3 previous timestamps
+        let time = headers[1].time;
+        headers.push(mine(headers.last(), time, headers[2].bits));
+        let err = validate(&list, &headers).unwrap_err();
+        assert!(err.to_string().contains("median time past"));
+    }
+
+    #[test]
+    fn test_validate_pow() {
+        let list = HeaderList::empty();
+        let mut headers = vec![];
+        extend(&mut headers, 3);
+        let mut header = headers.pop().unwrap();
+        while header.validate_pow(header.target()).is_ok() {
+            header.nonce += 1;
+        }
+        headers.push(header);
+        let err = validate(&list, &headers).unwrap_err();
+        assert!(err.to_string().contains("invalid proof-of-work"));
+    }
+
+    #[test]
+    fn test_validate_reorg_work() {
+        let mut headers = vec![];
+        extend(&mut headers, 6);
+        let mut list = HeaderList::empty();
+        list.apply(list.order(headers.clone()));
+
+        // a shorter branch from height 3, at the same difficulty
+        let mut branch = headers[..3].to_vec();
+        let time = headers[3].time + 1;
+        branch.push(mine(branch.last(), time, headers[3].bits));
+        extend(&mut branch, 1);
+        let err = validate(&list, &branch[3..]).unwrap_err();
+        assert!(err.to_string().contains("does not increase the chain work"));
+
+        // a longer one
+        extend(&mut branch, 2);
+        validate(&list, &branch[3..]).unwrap();
+    }
+}
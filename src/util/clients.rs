@@ -0,0 +1,228 @@
+//! Per-client counters of the Electrum RPC requests, used to temporarily ban the clients that keep
+//! sending invalid requests. The counters and the bans can be persisted to a file, so that they
+//! survive restarts.
+
+use std::collections::HashMap;
+use std::fs;
+use std::net::IpAddr;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use crate::errors::*;
+use crate::util::pool::LoadMonitor;
+use crate::util::ratelimit::client_key;
+use crate::util::unix_now;
+
+// clients that aren't banned are dropped once there are this many clients tracked
+const MAX_TRACKED_CLIENTS: usize = 100_000;
+// how often the counters are written to the persistence file
+const SAVE_INTERVAL: Duration = Duration::from_secs(60);
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ClientStats {
+    /// The IPv4 address, IPv6 /64 or Tor circuit of the client (as for the rate limiter)
+    pub client: IpAddr,
+    pub requests: u64,
+    pub errors: u64,
+    pub subscriptions: u64,
+    pub bans: u64,
+    /// The unix timestamp the last ban of the client expires at
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub banned_until: Option<u64>,
+    // the start of the window invalid requests are counted in, and their count
+    #[serde(skip)]
+    window: Option<(Instant, u32)>,
+}
+
+impl ClientStats {
+    fn new(client: IpAddr) -> Self {
+        ClientStats {
+            client,
+            requests: 0,
+            errors: 0,
+            subscriptions: 0,
+            bans: 0,
+            banned_until: None,
+            window: None,
+        }
+    }
+
+    fn is_banned(&self, now: u64) -> bool {
+        self.banned_until.map_or(false, |until| until > now)
+    }
+}
+
+pub struct ClientTracker {
+    path: Option<PathBuf>,
+    ban_threshold: u32,
+    ban_window: Duration,
+    ban_duration: Duration,
+    clients: Mutex<HashMap<IpAddr, ClientStats>>,
+    last_saved: Mutex<Instant>,
+}
+
+impl ClientTracker {
+    /// Loads the counters persisted at `path`, if any. Clients are banned for `ban_duration` once
+    /// they made `ban_threshold` invalid requests within `ban_window`, a threshold of 0 disables
+    /// banning.
+    pub fn open(
+        path: Option<&Path>,
+        ban_threshold: u32,
+        ban_window: Duration,
+        ban_duration: Duration,
+    ) -> Result<Self> {
+        let clients = match path {
+            Some(path) if path.exists() => {
+                let contents = fs::read_to_string(path)
+                    .chain_err(|| format!("failed reading {}", path.display()))?;
+                let clients: Vec<ClientStats> = serde_json::from_str(&contents)
+                    .chain_err(|| format!("invalid client stats file {}", path.display()))?;
+                info!("loaded the stats of {} electrum clients", clients.len());
+                clients
+                    .into_iter()
+                    .map(|stats| (stats.client, stats))
+                    .collect()
+            }
+            _ => HashMap::new(),
+        };
+        Ok(ClientTracker {
+            path: path.map(Path::to_path_buf),
+            ban_threshold,
+            ban_window,
+            ban_duration,
+            clients: Mutex::new(clients),
+            last_saved: Mutex::new(Instant::now()),
+        })
+    }
+
+    pub fn is_banned(&self, ip: IpAddr) -> bool {
+        let now = unix_now();
+        self.clients
+            .lock()
+            .unwrap()
+            .get(&client_key(ip))
+            .map_or(false, |stats| stats.is_banned(now))
+    }
+
+    pub fn record_request(&self, ip: IpAddr) {
+        self.update(ip, |stats| stats.requests += 1)
+    }
+
+    pub fn record_subscription(&self, ip: IpAddr) {
+        self.update(ip, |stats| stats.subscriptions += 1)
+    }
+
+    /// Counts an invalid request of the client at `ip`, returning true if it got banned for it
+    pub fn record_error(&self, ip: IpAddr) -> bool {
+        let (threshold, window, duration) =
+            (self.ban_threshold, self.ban_window, self.ban_duration);
+        self.update(ip, |stats| {
+            stats.errors += 1;
+            if threshold == 0 {
+                return false;
+            }
+            let (start, errors) = match stats.window {
+                Some((start, errors)) if start.elapsed() < window => (start, errors + 1),
+                _ => (Instant::now(), 1),
+            };
+            if errors < threshold {
+                stats.window = Some((start, errors));
+                return false;
+            }
+            stats.window = None;
+            stats.bans += 1;
+            stats.banned_until = Some(unix_now() + duration.as_secs());
+            true
+        })
+    }
+
+    fn update<T>(&self, ip: IpAddr, f: impl FnOnce(&mut ClientStats) -> T) -> T {
+        let key = client_key(ip);
+        let mut clients = self.clients.lock().unwrap();
+        if clients.len() >= MAX_TRACKED_CLIENTS && !clients.contains_key(&key) {
+            let now = unix_now();
+            clients.retain(|_, stats| stats.is_banned(now));
+        }
+        f(clients.entry(key).or_insert_with(|| ClientStats::new(key)))
+    }
+
+    /// The `limit` clients that made the most requests
+    pub fn top_clients(&self, limit: usize) -> Vec<ClientStats> {
+        let mut clients: Vec<_> = self.clients.lock().unwrap().values().cloned().collect();
+        clients.sort_unstable_by(|a, b| b.requests.cmp(&a.requests));
+        clients.truncate(limit);
+        clients
+    }
+
+    /// Persists the counters, unless they were persisted less than a minute ago (or longer, while
+    /// the queries are overloaded)
+    pub fn save_if_due(&self, load: &LoadMonitor) -> Result<()> {
+        let mut last_saved = self.last_saved.lock().unwrap();
+        if !load.is_due("client_stats", last_saved.elapsed(), SAVE_INTERVAL) {
+            return Ok(());
+        }
+        *last_saved = Instant::now();
+        self.save()
+    }
+
+    pub fn save(&self) -> Result<()> {
+        let path = match self.path {
+            Some(ref path) => path,
+            None => return Ok(()),
+        };
+        let contents = {
+            let clients = self.clients.lock().unwrap();
+            serde_json::to_string(&clients.values().collect::<Vec<_>>())
+                .chain_err(|| "failed serializing the client stats")?
+        };
+        // replace the file at once, so that a crash never leaves a truncated one behind
+        let tmp_path = path.with_extension("tmp");
+        fs::write(&tmp_path, contents)
+            .chain_err(|| format!("failed writing {}", tmp_path.display()))?;
+        fs::rename(&tmp_path, path).chain_err(|| format!("failed writing {}", path.display()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_ban() {
+        let tracker =
+            ClientTracker::open(None, 3, Duration::from_secs(60), Duration::from_secs(3600))
+                .unwrap();
+        let (a, b) = ("192.0.2.1".parse().unwrap(), "192.0.2.2".parse().unwrap());
+        tracker.record_request(a);
+        assert!(!tracker.record_error(a));
+        assert!(!tracker.record_error(a));
+        assert!(!tracker.record_error(b));
+        assert!(!tracker.is_banned(a));
+        assert!(tracker.record_error(a));
+        assert!(tracker.is_banned(a));
+        assert!(!tracker.is_banned(b));
+
+        let top = tracker.top_clients(1);
+        assert_eq!(top.len(), 1);
+        assert_eq!((top[0].client, top[0].errors, top[0].bans), (a, 3, 1));
+
+        let disabled =
+            ClientTracker::open(None, 0, Duration::from_secs(60), Duration::from_secs(60)).unwrap();
+        assert!((0..10).all(|_| !disabled.record_error(a)));
+        assert!(!disabled.is_banned(a));
+    }
+
+    #[test]
+    fn test_stats_json() {
+        let mut stats = ClientStats::new("2001:db8::".parse().unwrap());
+        stats.requests = 5;
+        let json = serde_json::to_string(&stats).unwrap();
+        assert_eq!(
+            json,
+            r#"{"client":"2001:db8::","requests":5,"errors":0,"subscriptions":0,"bans":0}"#
+        );
+        let parsed: ClientStats = serde_json::from_str(&json).unwrap();
+        assert_eq!((parsed.client, parsed.requests), (stats.client, 5));
+    }
+}
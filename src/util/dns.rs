@@ -0,0 +1,240 @@
+//! A minimal DNS client for the SRV lookups (RFC 2782) of `--daemon-rpc-addr srv:<name>`, which
+//! the standard library can't do. The query is sent over UDP to the first nameserver of
+//! /etc/resolv.conf, and truncated responses (which would have to be retried over TCP) are
+//! rejected, which is fine for the handful of records listing the daemons of a deployment.
+
+use std::cmp::Reverse;
+use std::fs;
+use std::net::{IpAddr, SocketAddr, UdpSocket};
+use std::time::Duration;
+
+use crate::errors::*;
+
+const RESOLV_CONF: &str = "/etc/resolv.conf";
+const QUERY_TIMEOUT: Duration = Duration::from_secs(5);
+const TYPE_SRV: u16 = 33;
+const CLASS_IN: u16 = 1;
+const FLAG_RESPONSE: u16 = 0x8000;
+const FLAG_TRUNCATED: u16 = 0x0200;
+const FLAG_RECURSION_DESIRED: u16 = 0x0100;
+// guards against the compression pointer loops of malformed responses
+const MAX_NAME_JUMPS: usize = 64;
+
+/// A host listed by an SRV record
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SrvTarget {
+    pub priority: u16,
+    pub weight: u16,
+    pub port: u16,
+    pub host: String,
+}
+
+/// Looks up the SRV records of `name`, by order of preference: the lowest priority first, then
+/// the highest weight
+pub fn lookup_srv(name: &str) -> Result<Vec<SrvTarget>> {
+    let nameserver = nameserver()?;
+    let bind_addr: SocketAddr = if nameserver.is_ipv4() {
+        ([0, 0, 0, 0], 0).into()
+    } else {
+        ([0u16; 8], 0).into()
+    };
+    let socket = UdpSocket::bind(bind_addr).chain_err(|| "failed binding the DNS socket")?;
+    socket
+        .set_read_timeout(Some(QUERY_TIMEOUT))
+        .chain_err(|| "failed setting the DNS timeout")?;
+
+    let id = rand::random::<u16>();
+    socket
+        .send_to(&query(id, name)?, nameserver)
+        .chain_err(|| format!("failed querying {}", nameserver))?;
+    let mut buf = [0u8; 4096];
+    let len = socket
+        .recv(&mut buf)
+        .chain_err(|| format!("no DNS response from {}", nameserver))?;
+
+    let mut targets = parse_srv_response(id, &buf[..len])
+        .chain_err(|| format!("invalid DNS response from {}", nameserver))?;
+    // a "." target means that the service isn't available at this domain
+    targets.retain(|target| !target.host.is_empty());
+    targets.sort_by_key(|target| (target.priority, Reverse(target.weight)));
+    Ok(targets)
+}
+
+fn nameserver() -> Result<SocketAddr> {
+    let conf =
+        fs::read_to_string(RESOLV_CONF).chain_err(|| format!("failed reading {}", RESOLV_CONF))?;
+    conf.lines()
+        .filter_map(|line| line.strip_prefix("nameserver"))
+        .filter_map(|addr| addr.trim().parse::<IpAddr>().ok())
+        .map(|ip| SocketAddr::new(ip, 53))
+        .next()
+        .chain_err(|| format!("no nameserver in {}", RESOLV_CONF))
+}
+
+fn query(id: u16, name: &str) -> Result<Vec<u8>> {
+    let mut msg = vec![];
+    msg.extend_from_slice(&id.to_be_bytes());
+    msg.extend_from_slice(&FLAG_RECURSION_DESIRED.to_be_bytes());
+    // one question, no answer, authority or additional records
+    msg.extend_from_slice(&[0, 1, 0, 0, 0, 0, 0, 0]);
+    for label in name.trim_end_matches('.').split('.') {
+        if label.is_empty() || label.len() > 63 {
+            bail!("invalid DNS name {:?}", name);
+        }
+        msg.push(label.len() as u8);
+        msg.extend_from_slice(label.as_bytes());
+    }
+    msg.push(0);
+    msg.extend_from_slice(&TYPE_SRV.to_be_bytes());
+    msg.extend_from_slice(&CLASS_IN.to_be_bytes());
+    Ok(msg)
+}
+
+fn parse_srv_response(id: u16, msg: &[u8]) -> Result<Vec<SrvTarget>> {
+    if read_u16(msg, 0)? != id {
+        bail!("mismatching query id");
+    }
+    let flags = read_u16(msg, 2)?;
+    if flags & FLAG_RESPONSE == 0 {
+        bail!("not a response");
+    }
+    if flags & FLAG_TRUNCATED != 0 {
+        bail!("truncated response");
+    }
+    match flags & 0xf {
+        0 => (),
+        3 => bail!("no such domain"),
+        rcode => bail!("query failed with rcode {}", rcode),
+    }
+    let questions = read_u16(msg, 4)?;
+    let answers = read_u16(msg, 6)?;
+
+    let mut pos = 12;
+    for _ in 0..questions {
+        pos = read_name(msg, pos)?.1 + 4; // skip the type and class
+    }
+    let mut targets = vec![];
+    for _ in 0..answers {
+        pos = read_name(msg, pos)?.1;
+        let rtype = read_u16(msg, pos)?;
+        let rdata = pos + 10; // after the type, class, ttl and length
+        let rdata_len = read_u16(msg, pos + 8)? as usize;
+        // answers may include the CNAME records the name was resolved through
+        if rtype == TYPE_SRV {
+            targets.push(SrvTarget {
+                priority: read_u16(msg, rdata)?,
+                weight: read_u16(msg, rdata + 2)?,
+                port: read_u16(msg, rdata + 4)?,
+                host: read_name(msg, rdata + 6)?.0,
+            });
+        }
+        pos = rdata + rdata_len;
+    }
+    Ok(targets)
+}
+
+// Reads the (possibly compressed) name at `pos`, returning it with the position following it
+fn read_name(msg: &[u8], mut pos: usize) -> Result<(String, usize)> {
+    let mut labels = vec![];
+    let mut end = None;
+    for _ in 0..MAX_NAME_JUMPS {
+        loop {
+            let len = *msg.get(pos).chain_err(|| "truncated name")? as usize;
+            if len == 0 {
+                return Ok((labels.join("."), end.unwrap_or(pos + 1)));
+            }
+            if len & 0xc0 == 0xc0 {
+                let offset = ((len & 0x3f) << 8)
+                    | *msg.get(pos + 1).chain_err(|| "truncated name")? as usize;
+                end.get_or_insert(pos + 2);
+                pos = offset;
+                break;
+            }
+            if len > 63 {
+                bail!("invalid label length {}", len);
+            }
+            let label = msg
+                .get(pos + 1..pos + 1 + len)
+                .chain_err(|| "truncated name")?;
+            labels.push(String::from_utf8_lossy(label).into_owned());
+            pos += 1 + len;
+        }
+    }
+    bail!("too many compression pointers")
+}
+
+fn read_u16(msg: &[u8], pos: usize) -> Result<u16> {
+    let bytes = msg.get(pos..pos + 2).chain_err(|| "truncated message")?;
+    Ok(u16::from_be_bytes([bytes[0], bytes[1]]))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn srv_answer(priority: u16, weight: u16, port: u16, target: &[u8]) -> Vec<u8> {
+        // the name points to the question, at offset 12
+        let mut answer = vec![0xc0, 12];
+        answer.extend_from_slice(&TYPE_SRV.to_be_bytes());
+        answer.extend_from_slice(&CLASS_IN.to_be_bytes());
+        answer.extend_from_slice(&300u32.to_be_bytes());
+        answer.extend_from_slice(&((6 + target.len()) as u16).to_be_bytes());
+        answer.extend_from_slice(&priority.to_be_bytes());
+        answer.extend_from_slice(&weight.to_be_bytes());
+        answer.extend_from_slice(&port.to_be_bytes());
+        answer.extend_from_slice(target);
+        answer
+    }
+
+    #[test]
+    fn test_query() {
+        let msg = query(0x1234, "_rpc._tcp.example.com.").unwrap();
+        let mut expected = vec![0x12, 0x34, 1, 0, 0, 1, 0, 0, 0, 0, 0, 0];
+        expected.extend_from_slice(b"\x04_rpc\x04_tcp\x07example\x03com\x00");
+        expected.extend_from_slice(&[0, 33, 0, 1]);
+        assert_eq!(msg, expected);
+
+        assert!(query(1, "a..b").is_err());
+        assert!(query(1, &"a".repeat(64)).is_err());
+    }
+
+    #[test]
+    fn test_parse_srv_response() {
+        let mut msg = query(7, "_rpc._tcp.example.com").unwrap();
+        msg[2] |= 0x80; // response
+        msg[7] = 2; // two answers
+                    // the second target ends with a pointer to "example.com" in the question
+        msg.extend(srv_answer(10, 5, 8332, b"\x05node1\x07example\x03com\x00"));
+        msg.extend(srv_answer(20, 0, 18332, b"\x05node2\xc0\x16"));
+
+        let targets = parse_srv_response(7, &msg).unwrap();
+        assert_eq!(
+            targets,
+            vec![
+                SrvTarget {
+                    priority: 10,
+                    weight: 5,
+                    port: 8332,
+                    host: "node1.example.com".to_string(),
+                },
+                SrvTarget {
+                    priority: 20,
+                    weight: 0,
+                    port: 18332,
+                    host: "node2.example.com".to_string(),
+                },
+            ]
+        );
+
+        assert!(parse_srv_response(8, &msg).is_err());
+        msg[3] = 3; // NXDOMAIN
+        assert!(parse_srv_response(7, &msg).is_err());
+    }
+
+    #[test]
+    fn test_read_name_loop() {
+        let msg = [0xc0, 0];
+        assert!(read_name(&msg, 0).is_err());
+        assert!(read_name(&[3, b'a'], 0).is_err());
+    }
+}
@@ -1,9 +1,11 @@
 use crate::chain::{Network, Transaction, TxOut};
-use std::collections::HashMap;
+use std::collections::{HashMap, VecDeque};
+use std::time::{Duration, Instant};
 
 use electrs_macros::trace;
 
 const VSIZE_BIN_WIDTH: u64 = 50_000; // in vbytes
+const FEE_FLOOR_WINDOW: Duration = Duration::from_secs(10 * 60);
 
 pub struct TxFeeInfo {
     pub fee: u64,           // in satoshis
@@ -69,3 +71,33 @@ pub fn make_fee_histogram(mut entries: Vec<&TxFeeInfo>) -> Vec<(f64, u64)> {
     }
     histogram
 }
+
+/// Lokid's mempool min fee (the feerate under which transactions are evicted, or not accepted at
+/// all, while its mempool is full) sampled over the last `FEE_FLOOR_WINDOW`. The highest sample
+/// is used, so that the floor doesn't drop as soon as a block briefly relieves the congestion.
+#[derive(Default)]
+pub struct FeeFloor {
+    samples: VecDeque<(Instant, f64)>, // in sat/vb
+}
+
+impl FeeFloor {
+    pub fn record(&mut self, fee_rate: f64) {
+        while self
+            .samples
+            .front()
+            .map_or(false, |(time, _)| time.elapsed() > FEE_FLOOR_WINDOW)
+        {
+            self.samples.pop_front();
+        }
+        self.samples.push_back((Instant::now(), fee_rate));
+    }
+
+    /// The feerate estimates should be kept above, in sat/vb
+    pub fn get(&self) -> f64 {
+        self.samples
+            .iter()
+            .filter(|(time, _)| time.elapsed() <= FEE_FLOOR_WINDOW)
+            .map(|(_, fee_rate)| *fee_rate)
+            .fold(0.0, f64::max)
+    }
+}
@@ -64,3 +64,80 @@ fn expected_hrp(network: Network) -> &'static str {
         _ => "fc",
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::rngs::StdRng;
+    use rand::{RngCore, SeedableRng};
+
+    const REGTEST_ADDR: &str = "fcrt1qqqqsyqcyq5rqwzqfpg9scrgwpugpzysnmt5y76";
+    const MAINNET_ADDR: &str = "fc1qqqqsyqcyq5rqwzqfpg9scrgwpugpzysn0gat44";
+    const BECH32_CHARS: &[u8] =
+        b"qpzry9x8gf2tvdw0s3jn54khce6mua7lQPZRY9X8GF2TVDW0S3JN54KHCE6MUA7L1";
+
+    const NETWORKS: [Network; 5] = [
+        Network::Bitcoin,
+        Network::Testnet,
+        Network::Testnet4,
+        Network::Regtest,
+        Network::Signet,
+    ];
+
+    #[test]
+    fn test_flokicoin_bech32() {
+        let mut expected = vec![0x00, 0x14];
+        expected.extend(0..20u8);
+        let script = script_pubkey_from_address(REGTEST_ADDR, Network::Regtest).unwrap();
+        assert_eq!(script.as_bytes(), &expected[..]);
+        let script = script_pubkey_from_address(MAINNET_ADDR, Network::Bitcoin).unwrap();
+        assert_eq!(script.as_bytes(), &expected[..]);
+
+        assert!(script_pubkey_from_address(REGTEST_ADDR, Network::Bitcoin).is_err());
+        assert!(script_pubkey_from_address(MAINNET_ADDR, Network::Testnet).is_err());
+    }
+
+    // Mutations of valid addresses and random strings must be rejected, never panic
+    #[test]
+    fn test_fuzz_script_pubkey_from_address() {
+        let mut rng = StdRng::seed_from_u64(0);
+        let mut inputs: Vec<Vec<u8>> = vec![];
+        for addr in &[REGTEST_ADDR, MAINNET_ADDR] {
+            let addr = addr.as_bytes();
+            for i in 0..addr.len() {
+                let mut mutated = addr.to_vec();
+                mutated[i] = BECH32_CHARS[rng.next_u32() as usize % BECH32_CHARS.len()];
+                inputs.push(mutated);
+                inputs.push([&addr[..i], &addr[i + 1..]].concat());
+                inputs.push(addr[..i].to_vec());
+            }
+        }
+        for _ in 0..2000 {
+            let len = rng.next_u32() as usize % 128;
+            let mut input = if rng.next_u32() % 2 == 0 {
+                b"fcrt1".to_vec()
+            } else {
+                vec![]
+            };
+            input.extend((0..len).map(|_| match rng.next_u32() % 8 {
+                0 => rng.next_u32() as u8,
+                _ => BECH32_CHARS[rng.next_u32() as usize % BECH32_CHARS.len()],
+            }));
+            inputs.push(input);
+        }
+
+        for input in inputs {
+            let input = String::from_utf8_lossy(&input);
+            for network in &NETWORKS {
+                if let Ok(script) = script_pubkey_from_address(&input, *network) {
+                    assert!(
+                        script.is_witness_program(),
+                        "{} parsed as {:?}",
+                        input,
+                        script
+                    );
+                }
+            }
+        }
+    }
+}
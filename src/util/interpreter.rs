@@ -0,0 +1,984 @@
+//! A script interpreter that records the stacks after each executed opcode, for debugging why an
+//! input fails to validate. It follows the consensus rules of legacy, P2SH and segwit v0 spends,
+//! without the standardness policy checks of lokid. Taproot spends aren't traced, and the legacy
+//! `FindAndDelete` of signatures from the signed script code isn't replicated.
+
+use bitcoin::hashes::{hash160, ripemd160, sha1, sha256, sha256d, Hash};
+use bitcoin::hex::DisplayHex;
+use bitcoin::opcodes::all::*;
+use bitcoin::opcodes::Opcode;
+use bitcoin::script::{Builder, Instruction, PushBytes};
+use bitcoin::secp256k1::{ecdsa::Signature, Message, PublicKey, Secp256k1, VerifyOnly};
+use bitcoin::sighash::{EcdsaSighashType, SighashCache};
+use bitcoin::{Script, ScriptBuf, Transaction, TxOut};
+use utoipa::ToSchema;
+
+use crate::util::locktime::{
+    LOCKTIME_THRESHOLD, SEQUENCE_FINAL, SEQUENCE_LOCKTIME_DISABLE_FLAG, SEQUENCE_LOCKTIME_MASK,
+    SEQUENCE_LOCKTIME_TYPE_FLAG,
+};
+
+const MAX_SCRIPT_SIZE: usize = 10_000;
+const MAX_SCRIPT_ELEMENT_SIZE: usize = 520;
+const MAX_OPS_PER_SCRIPT: usize = 201;
+const MAX_STACK_SIZE: usize = 1000;
+const MAX_PUBKEYS_PER_MULTISIG: i64 = 20;
+// the bounds of the recorded trace, as every step dumps the whole stacks
+const MAX_TRACE_STEPS: usize = 1_000;
+const MAX_TRACE_BYTES: usize = 4_000_000;
+
+type Stack = Vec<Vec<u8>>;
+
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum ScriptKind {
+    ScriptSig,
+    ScriptPubkey,
+    RedeemScript,
+    WitnessScript,
+}
+
+#[derive(Serialize, Debug, ToSchema)]
+pub struct Step {
+    pub script: ScriptKind,
+    // the byte offset of the opcode within its script
+    pub pc: usize,
+    pub opcode: String,
+    pub stack: Vec<String>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub altstack: Vec<String>,
+}
+
+#[derive(Serialize, Debug, ToSchema)]
+pub struct Trace {
+    pub success: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    pub steps: Vec<Step>,
+    /// Set when the steps stop short of the executed opcodes, to bound the trace size
+    pub truncated: bool,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum SigVersion {
+    Base,
+    WitnessV0,
+}
+
+struct Interpreter<'a> {
+    tx: &'a Transaction,
+    index: usize,
+    prevout: &'a TxOut,
+    secp: Secp256k1<VerifyOnly>,
+    steps: Vec<Step>,
+    // the hex characters of the stacks recorded so far
+    trace_bytes: usize,
+    truncated: bool,
+}
+
+/// Executes the scripts of the `index`-th input of `tx`, which spends `prevout`
+pub fn trace_input(tx: &Transaction, index: usize, prevout: &TxOut) -> Trace {
+    let mut interpreter = Interpreter {
+        tx,
+        index,
+        prevout,
+        secp: Secp256k1::verification_only(),
+        steps: vec![],
+        trace_bytes: 0,
+        truncated: false,
+    };
+    let result = interpreter.verify();
+    Trace {
+        success: result.is_ok(),
+        error: result.err(),
+        steps: interpreter.steps,
+        truncated: interpreter.truncated,
+    }
+}
+
+impl Interpreter<'_> {
+    // The equivalent of VerifyScript()
+    fn verify(&mut self) -> Result<(), String> {
+        let (tx, prevout) = (self.tx, self.prevout);
+        let txin = &tx.input[self.index];
+        let script_pubkey = &prevout.script_pubkey;
+
+        let mut stack = Stack::new();
+        self.execute(
+            &txin.script_sig,
+            ScriptKind::ScriptSig,
+            &mut stack,
+            SigVersion::Base,
+        )?;
+        let p2sh_stack = stack.clone();
+        self.execute(
+            script_pubkey,
+            ScriptKind::ScriptPubkey,
+            &mut stack,
+            SigVersion::Base,
+        )?;
+        check_success(&stack)?;
+
+        if script_pubkey.is_witness_program() {
+            if !txin.script_sig.is_empty() {
+                return Err("the scriptSig of native segwit spends must be empty".to_string());
+            }
+            return self.verify_witness(script_pubkey);
+        }
+
+        if script_pubkey.is_p2sh() {
+            if !txin.script_sig.is_push_only() {
+                return Err("the scriptSig of P2SH spends must only push data".to_string());
+            }
+            let mut stack = p2sh_stack;
+            let redeem_script = ScriptBuf::from(stack.pop().expect("checked by the scriptPubKey"));
+            self.execute(
+                &redeem_script,
+                ScriptKind::RedeemScript,
+                &mut stack,
+                SigVersion::Base,
+            )?;
+            check_success(&stack)?;
+
+            if redeem_script.is_witness_program() {
+                if txin.script_sig.instructions().count() != 1 {
+                    return Err(
+                        "the scriptSig of nested segwit spends must only push the redeem script"
+                            .to_string(),
+                    );
+                }
+                return self.verify_witness(&redeem_script);
+            }
+        }
+
+        if !txin.witness.is_empty() {
+            return Err("witness data given for a non-segwit spend".to_string());
+        }
+        Ok(())
+    }
+
+    fn verify_witness(&mut self, program_script: &Script) -> Result<(), String> {
+        let version = program_script.as_bytes()[0];
+        let program = &program_script.as_bytes()[2..];
+        let mut stack: Stack = self.tx.input[self.index]
+            .witness
+            .iter()
+            .map(|item| item.to_vec())
+            .collect();
+
+        let witness_script = match (version, program.len()) {
+            (0, 20) => {
+                if stack.len() != 2 {
+                    return Err("P2WPKH spends take a signature and a public key".to_string());
+                }
+                Builder::new()
+                    .push_opcode(OP_DUP)
+                    .push_opcode(OP_HASH160)
+                    .push_slice(<&PushBytes>::try_from(program).unwrap())
+                    .push_opcode(OP_EQUALVERIFY)
+                    .push_opcode(OP_CHECKSIG)
+                    .into_script()
+            }
+            (0, 32) => {
+                let witness_script = ScriptBuf::from(
+                    stack
+                        .pop()
+                        .ok_or_else(|| "P2WSH spends need a witness script".to_string())?,
+                );
+                if sha256::Hash::hash(witness_script.as_bytes()).as_byte_array()[..] != *program {
+                    return Err("the witness script doesn't match the P2WSH program".to_string());
+                }
+                witness_script
+            }
+            (0, _) => return Err("invalid segwit v0 program length".to_string()),
+            // OP_1 followed by a 32 bytes program
+            (0x51, 32) => return Err("taproot spends are not traced".to_string()),
+            // unknown witness versions are reserved for future soft forks and always succeed
+            _ => return Ok(()),
+        };
+
+        if stack
+            .iter()
+            .any(|item| item.len() > MAX_SCRIPT_ELEMENT_SIZE)
+        {
+            return Err("a witness item exceeds the maximum element size".to_string());
+        }
+        self.execute(
+            &witness_script,
+            ScriptKind::WitnessScript,
+            &mut stack,
+            SigVersion::WitnessV0,
+        )?;
+        if stack.len() != 1 {
+            return Err("segwit scripts must leave exactly one item on the stack".to_string());
+        }
+        check_success(&stack)
+    }
+
+    // The equivalent of EvalScript()
+    fn execute(
+        &mut self,
+        script: &Script,
+        kind: ScriptKind,
+        stack: &mut Stack,
+        sigversion: SigVersion,
+    ) -> Result<(), String> {
+        if script.len() > MAX_SCRIPT_SIZE {
+            return Err(format!("the {:?} exceeds the maximum script size", kind));
+        }
+
+        let mut altstack = Stack::new();
+        // the evaluated conditions of the enclosing IF/NOTIF branches
+        let mut exec: Vec<bool> = vec![];
+        let mut op_count = 0;
+        // the script code signed by the following signatures starts after the last OP_CODESEPARATOR
+        let mut codesep = 0;
+
+        for instruction in script.instruction_indices() {
+            let (pc, instruction) =
+                instruction.map_err(|e| format!("failed parsing the {:?}: {}", kind, e))?;
+            let executing = exec.iter().all(|cond| *cond);
+
+            let (opcode, result) = match instruction {
+                Instruction::PushBytes(data) => {
+                    let result = if data.len() > MAX_SCRIPT_ELEMENT_SIZE {
+                        Err("the pushed data exceeds the maximum element size".to_string())
+                    } else {
+                        if executing {
+                            stack.push(data.as_bytes().to_vec());
+                        }
+                        Ok(())
+                    };
+                    (push_name(script.as_bytes()[pc], data.as_bytes()), result)
+                }
+                Instruction::Op(op) => {
+                    let script_code = &script.as_bytes()[codesep..];
+                    let result = self.execute_op(
+                        op,
+                        executing,
+                        stack,
+                        &mut altstack,
+                        &mut exec,
+                        &mut op_count,
+                        Script::from_bytes(script_code),
+                        sigversion,
+                    );
+                    if executing && op == OP_CODESEPARATOR {
+                        codesep = pc + 1;
+                    }
+                    (op.to_string(), result)
+                }
+            };
+
+            let result = result.and_then(|()| {
+                if stack.len() + altstack.len() > MAX_STACK_SIZE {
+                    Err("the stack exceeds the maximum size".to_string())
+                } else {
+                    Ok(())
+                }
+            });
+            if executing || result.is_err() {
+                self.record(kind, pc, &opcode, stack, &altstack);
+            }
+            result
+                .map_err(|e| format!("{} at position {} of the {:?}: {}", opcode, pc, kind, e))?;
+        }
+
+        if !exec.is_empty() {
+            return Err(format!("unbalanced conditional in the {:?}", kind));
+        }
+        Ok(())
+    }
+
+    // Records the stacks after an opcode, until the trace reaches its bounds
+    fn record(
+        &mut self,
+        kind: ScriptKind,
+        pc: usize,
+        opcode: &str,
+        stack: &Stack,
+        altstack: &Stack,
+    ) {
+        if self.truncated {
+            return;
+        }
+        let bytes: usize = stack
+            .iter()
+            .chain(altstack)
+            .map(|item| item.len() * 2)
+            .sum();
+        if self.steps.len() >= MAX_TRACE_STEPS || self.trace_bytes + bytes > MAX_TRACE_BYTES {
+            self.truncated = true;
+            return;
+        }
+        self.trace_bytes += bytes;
+        self.steps.push(Step {
+            script: kind,
+            pc,
+            opcode: opcode.to_string(),
+            stack: stack
+                .iter()
+                .map(|item| item.to_lower_hex_string())
+                .collect(),
+            altstack: altstack
+                .iter()
+                .map(|item| item.to_lower_hex_string())
+                .collect(),
+        });
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    fn execute_op(
+        &self,
+        op: Opcode,
+        executing: bool,
+        stack: &mut Stack,
+        altstack: &mut Stack,
+        exec: &mut Vec<bool>,
+        op_count: &mut usize,
+        script_code: &Script,
+        sigversion: SigVersion,
+    ) -> Result<(), String> {
+        if op.to_u8() > OP_PUSHNUM_16.to_u8() {
+            *op_count += 1;
+            if *op_count > MAX_OPS_PER_SCRIPT {
+                return Err("the script exceeds the maximum opcodes count".to_string());
+            }
+        }
+
+        // these fail the script even within an unexecuted branch
+        match op {
+            OP_CAT | OP_SUBSTR | OP_LEFT | OP_RIGHT | OP_INVERT | OP_AND | OP_OR | OP_XOR
+            | OP_2MUL | OP_2DIV | OP_MUL | OP_DIV | OP_MOD | OP_LSHIFT | OP_RSHIFT => {
+                return Err("disabled opcode".to_string())
+            }
+            OP_VERIF | OP_VERNOTIF => return Err("invalid opcode".to_string()),
+            _ => (),
+        }
+
+        if !executing && !matches!(op, OP_IF | OP_NOTIF | OP_ELSE | OP_ENDIF) {
+            return Ok(());
+        }
+
+        if (OP_PUSHNUM_1.to_u8()..=OP_PUSHNUM_16.to_u8()).contains(&op.to_u8()) {
+            let n = op.to_u8() - OP_PUSHNUM_1.to_u8() + 1;
+            stack.push(encode_num(n as i64));
+            return Ok(());
+        }
+
+        match op {
+            OP_PUSHNUM_NEG1 => stack.push(encode_num(-1)),
+            OP_NOP | OP_NOP1 | OP_NOP4 | OP_NOP5 | OP_NOP6 | OP_NOP7 | OP_NOP8 | OP_NOP9
+            | OP_NOP10 => (),
+
+            OP_IF | OP_NOTIF => {
+                let cond = executing && (cast_to_bool(&pop(stack)?) == (op == OP_IF));
+                exec.push(cond);
+            }
+            OP_ELSE => {
+                let cond = exec
+                    .last_mut()
+                    .ok_or_else(|| "OP_ELSE without OP_IF".to_string())?;
+                *cond = !*cond;
+            }
+            OP_ENDIF => {
+                exec.pop()
+                    .ok_or_else(|| "OP_ENDIF without OP_IF".to_string())?;
+            }
+            OP_VERIFY => verify(&pop(stack)?)?,
+            OP_RETURN => return Err("OP_RETURN always fails".to_string()),
+
+            OP_CLTV => {
+                let locktime = decode_num(top(stack, 1)?, 5)?;
+                if locktime < 0 {
+                    return Err("negative locktime".to_string());
+                }
+                self.check_locktime(locktime)?;
+            }
+            OP_CSV => {
+                let sequence = decode_num(top(stack, 1)?, 5)?;
+                if sequence < 0 {
+                    return Err("negative sequence".to_string());
+                }
+                // behaves as a NOP when the disable flag is set
+                if sequence & SEQUENCE_LOCKTIME_DISABLE_FLAG as i64 == 0 {
+                    self.check_sequence(sequence)?;
+                }
+            }
+
+            OP_TOALTSTACK => altstack.push(pop(stack)?),
+            OP_FROMALTSTACK => stack.push(
+                altstack
+                    .pop()
+                    .ok_or_else(|| "the altstack is empty".to_string())?,
+            ),
+            OP_2DROP => {
+                require(stack, 2)?;
+                stack.truncate(stack.len() - 2);
+            }
+            OP_2DUP => {
+                require(stack, 2)?;
+                stack.extend_from_within(stack.len() - 2..);
+            }
+            OP_3DUP => {
+                require(stack, 3)?;
+                stack.extend_from_within(stack.len() - 3..);
+            }
+            OP_2OVER => {
+                require(stack, 4)?;
+                stack.extend_from_within(stack.len() - 4..stack.len() - 2);
+            }
+            OP_2ROT => {
+                require(stack, 6)?;
+                let items = stack
+                    .drain(stack.len() - 6..stack.len() - 4)
+                    .collect::<Vec<_>>();
+                stack.extend(items);
+            }
+            OP_2SWAP => {
+                require(stack, 4)?;
+                let len = stack.len();
+                stack.swap(len - 4, len - 2);
+                stack.swap(len - 3, len - 1);
+            }
+            OP_IFDUP => {
+                let item = top(stack, 1)?.clone();
+                if cast_to_bool(&item) {
+                    stack.push(item);
+                }
+            }
+            OP_DEPTH => stack.push(encode_num(stack.len() as i64)),
+            OP_DROP => {
+                pop(stack)?;
+            }
+            OP_DUP => stack.push(top(stack, 1)?.clone()),
+            OP_NIP => {
+                require(stack, 2)?;
+                stack.remove(stack.len() - 2);
+            }
+            OP_OVER => stack.push(top(stack, 2)?.clone()),
+            OP_PICK | OP_ROLL => {
+                let n = decode_num(&pop(stack)?, 4)?;
+                if n < 0 || n as usize >= stack.len() {
+                    return Err("the stack has too few items".to_string());
+                }
+                let pos = stack.len() - 1 - n as usize;
+                let item = if op == OP_PICK {
+                    stack[pos].clone()
+                } else {
+                    stack.remove(pos)
+                };
+                stack.push(item);
+            }
+            OP_ROT => {
+                require(stack, 3)?;
+                let item = stack.remove(stack.len() - 3);
+                stack.push(item);
+            }
+            OP_SWAP => {
+                require(stack, 2)?;
+                let len = stack.len();
+                stack.swap(len - 2, len - 1);
+            }
+            OP_TUCK => {
+                let item = top(stack, 1)?.clone();
+                require(stack, 2)?;
+                stack.insert(stack.len() - 2, item);
+            }
+
+            OP_SIZE => stack.push(encode_num(top(stack, 1)?.len() as i64)),
+            OP_EQUAL | OP_EQUALVERIFY => {
+                let (b, a) = (pop(stack)?, pop(stack)?);
+                stack.push(encode_bool(a == b));
+                if op == OP_EQUALVERIFY {
+                    verify(&pop(stack)?)?;
+                }
+            }
+
+            OP_1ADD | OP_1SUB | OP_NEGATE | OP_ABS | OP_NOT | OP_0NOTEQUAL => {
+                let n = decode_num(&pop(stack)?, 4)?;
+                stack.push(match op {
+                    OP_1ADD => encode_num(n + 1),
+                    OP_1SUB => encode_num(n - 1),
+                    OP_NEGATE => encode_num(-n),
+                    OP_ABS => encode_num(n.abs()),
+                    OP_NOT => encode_bool(n == 0),
+                    _ => encode_bool(n != 0),
+                });
+            }
+            OP_ADD
+            | OP_SUB
+            | OP_BOOLAND
+            | OP_BOOLOR
+            | OP_NUMEQUAL
+            | OP_NUMEQUALVERIFY
+            | OP_NUMNOTEQUAL
+            | OP_LESSTHAN
+            | OP_GREATERTHAN
+            | OP_LESSTHANOREQUAL
+            | OP_GREATERTHANOREQUAL
+            | OP_MIN
+            | OP_MAX => {
+                let b = decode_num(&pop(stack)?, 4)?;
+                let a = decode_num(&pop(stack)?, 4)?;
+                stack.push(match op {
+                    OP_ADD => encode_num(a + b),
+                    OP_SUB => encode_num(a - b),
+                    OP_BOOLAND => encode_bool(a != 0 && b != 0),
+                    OP_BOOLOR => encode_bool(a != 0 || b != 0),
+                    OP_NUMEQUAL | OP_NUMEQUALVERIFY => encode_bool(a == b),
+                    OP_NUMNOTEQUAL => encode_bool(a != b),
+                    OP_LESSTHAN => encode_bool(a < b),
+                    OP_GREATERTHAN => encode_bool(a > b),
+                    OP_LESSTHANOREQUAL => encode_bool(a <= b),
+                    OP_GREATERTHANOREQUAL => encode_bool(a >= b),
+                    OP_MIN => encode_num(a.min(b)),
+                    _ => encode_num(a.max(b)),
+                });
+                if op == OP_NUMEQUALVERIFY {
+                    verify(&pop(stack)?)?;
+                }
+            }
+            OP_WITHIN => {
+                let max = decode_num(&pop(stack)?, 4)?;
+                let min = decode_num(&pop(stack)?, 4)?;
+                let n = decode_num(&pop(stack)?, 4)?;
+                stack.push(encode_bool(min <= n && n < max));
+            }
+
+            OP_RIPEMD160 => {
+                stack.push(ripemd160::Hash::hash(&pop(stack)?).to_byte_array().to_vec())
+            }
+            OP_SHA1 => stack.push(sha1::Hash::hash(&pop(stack)?).to_byte_array().to_vec()),
+            OP_SHA256 => stack.push(sha256::Hash::hash(&pop(stack)?).to_byte_array().to_vec()),
+            OP_HASH160 => stack.push(hash160::Hash::hash(&pop(stack)?).to_byte_array().to_vec()),
+            OP_HASH256 => stack.push(sha256d::Hash::hash(&pop(stack)?).to_byte_array().to_vec()),
+            OP_CODESEPARATOR => (),
+
+            OP_CHECKSIG | OP_CHECKSIGVERIFY => {
+                let (pubkey, sig) = (pop(stack)?, pop(stack)?);
+                stack.push(encode_bool(self.check_sig(
+                    &sig,
+                    &pubkey,
+                    script_code,
+                    sigversion,
+                )));
+                if op == OP_CHECKSIGVERIFY {
+                    verify(&pop(stack)?)?;
+                }
+            }
+            OP_CHECKMULTISIG | OP_CHECKMULTISIGVERIFY => {
+                let n = decode_num(&pop(stack)?, 4)?;
+                if !(0..=MAX_PUBKEYS_PER_MULTISIG).contains(&n) {
+                    return Err("invalid public keys count".to_string());
+                }
+                *op_count += n as usize;
+                if *op_count > MAX_OPS_PER_SCRIPT {
+                    return Err("the script exceeds the maximum opcodes count".to_string());
+                }
+                require(stack, n as usize)?;
+                let pubkeys = stack.split_off(stack.len() - n as usize);
+
+                let m = decode_num(&pop(stack)?, 4)?;
+                if !(0..=n).contains(&m) {
+                    return Err("invalid signatures count".to_string());
+                }
+                require(stack, m as usize)?;
+                let sigs = stack.split_off(stack.len() - m as usize);
+
+                // the extra item consumed because of an off-by-one bug must be empty (BIP147)
+                if !pop(stack)?.is_empty() {
+                    return Err("the dummy item must be empty".to_string());
+                }
+
+                // the signatures must match the public keys in the same order
+                let mut pubkeys = pubkeys.iter().rev();
+                let success = sigs.iter().rev().all(|sig| {
+                    pubkeys.any(|pubkey| self.check_sig(sig, pubkey, script_code, sigversion))
+                });
+                stack.push(encode_bool(success));
+                if op == OP_CHECKMULTISIGVERIFY {
+                    verify(&pop(stack)?)?;
+                }
+            }
+
+            _ => return Err("invalid opcode".to_string()),
+        }
+        Ok(())
+    }
+
+    fn check_sig(
+        &self,
+        sig: &[u8],
+        pubkey: &[u8],
+        script_code: &Script,
+        sigversion: SigVersion,
+    ) -> bool {
+        let (hash_type, der) = match sig.split_last() {
+            Some((hash_type, der)) => (*hash_type as u32, der),
+            None => return false,
+        };
+        let (mut signature, pubkey) =
+            match (Signature::from_der(der), PublicKey::from_slice(pubkey)) {
+                (Ok(signature), Ok(pubkey)) => (signature, pubkey),
+                _ => return false,
+            };
+        // high-S signatures are non-standard, but valid
+        signature.normalize_s();
+
+        let mut cache = SighashCache::new(self.tx);
+        let sighash = match sigversion {
+            SigVersion::Base => cache
+                .legacy_signature_hash(self.index, script_code, hash_type)
+                .map(|sighash| sighash.to_byte_array())
+                .ok(),
+            SigVersion::WitnessV0 => cache
+                .p2wsh_signature_hash(
+                    self.index,
+                    script_code,
+                    self.prevout.value,
+                    EcdsaSighashType::from_consensus(hash_type),
+                )
+                .map(|sighash| sighash.to_byte_array())
+                .ok(),
+        };
+        sighash.map_or(false, |sighash| {
+            let msg = Message::from_digest(sighash);
+            self.secp.verify_ecdsa(&msg, &signature, &pubkey).is_ok()
+        })
+    }
+
+    // The equivalent of CheckLockTime(), for OP_CHECKLOCKTIMEVERIFY
+    fn check_locktime(&self, locktime: i64) -> Result<(), String> {
+        let tx_locktime = self.tx.lock_time.to_consensus_u32() as i64;
+        let threshold = LOCKTIME_THRESHOLD as i64;
+        if (locktime < threshold) != (tx_locktime < threshold) {
+            return Err("the locktime and the transaction locktime differ in type".to_string());
+        }
+        if locktime > tx_locktime {
+            return Err(format!(
+                "the transaction locktime {} is lower than {}",
+                tx_locktime, locktime
+            ));
+        }
+        if self.tx.input[self.index].sequence.to_consensus_u32() == SEQUENCE_FINAL {
+            return Err("the input sequence is final, which disables the locktime".to_string());
+        }
+        Ok(())
+    }
+
+    // The equivalent of CheckSequence(), for OP_CHECKSEQUENCEVERIFY
+    fn check_sequence(&self, sequence: i64) -> Result<(), String> {
+        if self.tx.version.0 < 2 {
+            return Err("relative timelocks need a transaction version of 2 or above".to_string());
+        }
+        let tx_sequence = self.tx.input[self.index].sequence.to_consensus_u32() as i64;
+        if tx_sequence & SEQUENCE_LOCKTIME_DISABLE_FLAG as i64 != 0 {
+            return Err("the input sequence disables relative timelocks".to_string());
+        }
+        let mask = (SEQUENCE_LOCKTIME_TYPE_FLAG | SEQUENCE_LOCKTIME_MASK) as i64;
+        let (sequence, tx_sequence) = (sequence & mask, tx_sequence & mask);
+        let type_flag = SEQUENCE_LOCKTIME_TYPE_FLAG as i64;
+        if (sequence & type_flag) != (tx_sequence & type_flag) {
+            return Err("the sequence and the input sequence differ in type".to_string());
+        }
+        if sequence > tx_sequence {
+            return Err(format!(
+                "the input sequence {} is lower than {}",
+                tx_sequence, sequence
+            ));
+        }
+        Ok(())
+    }
+}
+
+fn check_success(stack: &Stack) -> Result<(), String> {
+    match stack.last() {
+        Some(item) if cast_to_bool(item) => Ok(()),
+        Some(_) => Err("the script ended with a false item on the stack".to_string()),
+        None => Err("the script ended with an empty stack".to_string()),
+    }
+}
+
+fn verify(item: &[u8]) -> Result<(), String> {
+    if cast_to_bool(item) {
+        Ok(())
+    } else {
+        Err("the verified item is false".to_string())
+    }
+}
+
+fn require(stack: &Stack, count: usize) -> Result<(), String> {
+    if stack.len() < count {
+        Err("the stack has too few items".to_string())
+    } else {
+        Ok(())
+    }
+}
+
+fn pop(stack: &mut Stack) -> Result<Vec<u8>, String> {
+    stack
+        .pop()
+        .ok_or_else(|| "the stack has too few items".to_string())
+}
+
+// The item at `depth` from the top of the stack, starting at 1
+fn top(stack: &Stack, depth: usize) -> Result<&Vec<u8>, String> {
+    require(stack, depth)?;
+    Ok(&stack[stack.len() - depth])
+}
+
+// Any non-zero value is true, except for negative zero
+fn cast_to_bool(item: &[u8]) -> bool {
+    match item.split_last() {
+        Some((last, rest)) => rest.iter().any(|b| *b != 0) || (*last != 0 && *last != 0x80),
+        None => false,
+    }
+}
+
+fn encode_bool(value: bool) -> Vec<u8> {
+    if value {
+        vec![1]
+    } else {
+        vec![]
+    }
+}
+
+// Script numbers are little-endian, with the sign in the most significant bit
+fn decode_num(item: &[u8], max_len: usize) -> Result<i64, String> {
+    if item.len() > max_len {
+        return Err(format!("numeric operands are limited to {} bytes", max_len));
+    }
+    let last = match item.last() {
+        Some(last) => *last,
+        None => return Ok(0),
+    };
+    let abs = item
+        .iter()
+        .enumerate()
+        .fold(0i64, |n, (i, b)| n | ((*b as i64) << (8 * i)))
+        & !(0x80i64 << (8 * (item.len() - 1)));
+    Ok(if last & 0x80 != 0 { -abs } else { abs })
+}
+
+fn encode_num(n: i64) -> Vec<u8> {
+    let mut abs = n.unsigned_abs();
+    let mut item = vec![];
+    while abs > 0 {
+        item.push((abs & 0xff) as u8);
+        abs >>= 8;
+    }
+    let sign = if n < 0 { 0x80 } else { 0 };
+    if item.last().map_or(false, |last| last & 0x80 != 0) {
+        item.push(sign);
+    } else if let Some(last) = item.last_mut() {
+        *last |= sign;
+    }
+    item
+}
+
+// Formats data pushes like the ASM of scripts
+fn push_name(opcode: u8, data: &[u8]) -> String {
+    match opcode {
+        0 => "OP_0".to_string(),
+        0x4c => format!("OP_PUSHDATA1 {}", data.to_lower_hex_string()),
+        0x4d => format!("OP_PUSHDATA2 {}", data.to_lower_hex_string()),
+        0x4e => format!("OP_PUSHDATA4 {}", data.to_lower_hex_string()),
+        n => format!("OP_PUSHBYTES_{} {}", n, data.to_lower_hex_string()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bitcoin::absolute::LockTime;
+    use bitcoin::secp256k1::SecretKey;
+    use bitcoin::{transaction, Amount, OutPoint, Sequence, TxIn, Witness};
+
+    // A transaction spending `prevout` with the given scriptSig and witness
+    fn spend(script_sig: ScriptBuf, witness: &[Vec<u8>]) -> Transaction {
+        Transaction {
+            version: transaction::Version::TWO,
+            lock_time: LockTime::ZERO,
+            input: vec![TxIn {
+                previous_output: OutPoint::null(),
+                script_sig,
+                sequence: Sequence::MAX,
+                witness: Witness::from_slice(witness),
+            }],
+            output: vec![TxOut {
+                value: Amount::from_sat(90_000),
+                script_pubkey: ScriptBuf::new(),
+            }],
+        }
+    }
+
+    fn prevout(script_pubkey: ScriptBuf) -> TxOut {
+        TxOut {
+            value: Amount::from_sat(100_000),
+            script_pubkey,
+        }
+    }
+
+    fn push(data: &[u8]) -> &PushBytes {
+        <&PushBytes>::try_from(data).unwrap()
+    }
+
+    #[test]
+    fn test_p2pkh() {
+        let secp = Secp256k1::new();
+        let secret_key = SecretKey::from_slice(&[0x42; 32]).unwrap();
+        let pubkey = PublicKey::from_secret_key(&secp, &secret_key).serialize();
+        let prevout = prevout(
+            Builder::new()
+                .push_opcode(OP_DUP)
+                .push_opcode(OP_HASH160)
+                .push_slice(push(hash160::Hash::hash(&pubkey).as_byte_array()))
+                .push_opcode(OP_EQUALVERIFY)
+                .push_opcode(OP_CHECKSIG)
+                .into_script(),
+        );
+
+        let mut tx = spend(ScriptBuf::new(), &[]);
+        let sighash = SighashCache::new(&tx)
+            .legacy_signature_hash(0, &prevout.script_pubkey, EcdsaSighashType::All.to_u32())
+            .unwrap();
+        let signature =
+            secp.sign_ecdsa(&Message::from_digest(sighash.to_byte_array()), &secret_key);
+        let mut sig = signature.serialize_der().to_vec();
+        sig.push(EcdsaSighashType::All.to_u32() as u8);
+        tx.input[0].script_sig = Builder::new()
+            .push_slice(push(&sig))
+            .push_slice(push(&pubkey))
+            .into_script();
+
+        let trace = trace_input(&tx, 0, &prevout);
+        assert!(trace.success, "{:?}", trace.error);
+        assert!(!trace.truncated);
+        let opcodes: Vec<&str> = trace.steps.iter().map(|step| &step.opcode[..]).collect();
+        assert_eq!(opcodes[2..4], ["OP_DUP", "OP_HASH160"]);
+        assert!(opcodes[4].starts_with("OP_PUSHBYTES_20 "));
+        assert_eq!(opcodes[5..], ["OP_EQUALVERIFY", "OP_CHECKSIG"]);
+        assert_eq!(trace.steps.last().unwrap().stack, ["01"]);
+
+        // a signature of another transaction fails the final OP_CHECKSIG
+        tx.output[0].value = Amount::from_sat(80_000);
+        let trace = trace_input(&tx, 0, &prevout);
+        assert!(!trace.success);
+        assert_eq!(trace.steps.last().unwrap().opcode, "OP_CHECKSIG");
+        assert_eq!(trace.steps.last().unwrap().stack, [""]);
+    }
+
+    #[test]
+    fn test_p2sh() {
+        // <a> <b> | OP_ADD OP_5 OP_EQUAL
+        let redeem_script = Builder::new()
+            .push_opcode(OP_ADD)
+            .push_int(5)
+            .push_opcode(OP_EQUAL)
+            .into_script();
+        let prevout = prevout(ScriptBuf::new_p2sh(&redeem_script.script_hash()));
+        let unlock = |a: i64, b: i64| {
+            Builder::new()
+                .push_int(a)
+                .push_int(b)
+                .push_slice(push(redeem_script.as_bytes()))
+                .into_script()
+        };
+
+        let trace = trace_input(&spend(unlock(2, 3), &[]), 0, &prevout);
+        assert!(trace.success, "{:?}", trace.error);
+        let redeem_steps: Vec<&Step> = trace
+            .steps
+            .iter()
+            .filter(|step| step.script == ScriptKind::RedeemScript)
+            .collect();
+        assert_eq!(redeem_steps.len(), 3);
+        assert_eq!(redeem_steps[0].stack, ["05"]);
+
+        let trace = trace_input(&spend(unlock(2, 2), &[]), 0, &prevout);
+        assert!(!trace.success);
+        assert_eq!(trace.steps.last().unwrap().script, ScriptKind::RedeemScript);
+        assert_eq!(trace.steps.last().unwrap().stack, [""]);
+    }
+
+    #[test]
+    fn test_p2wsh() {
+        // OP_SHA256 <hash> OP_EQUAL, a hashlock
+        let preimage = b"flokicoin".to_vec();
+        let witness_script = Builder::new()
+            .push_opcode(OP_SHA256)
+            .push_slice(push(sha256::Hash::hash(&preimage).as_byte_array()))
+            .push_opcode(OP_EQUAL)
+            .into_script();
+        let prevout = prevout(ScriptBuf::new_p2wsh(&witness_script.wscript_hash()));
+
+        let witness = [preimage, witness_script.to_bytes()];
+        let trace = trace_input(&spend(ScriptBuf::new(), &witness), 0, &prevout);
+        assert!(trace.success, "{:?}", trace.error);
+        let witness_steps = |trace: &Trace| {
+            trace
+                .steps
+                .iter()
+                .filter(|step| step.script == ScriptKind::WitnessScript)
+                .count()
+        };
+        assert_eq!(witness_steps(&trace), 3);
+        assert_eq!(trace.steps.last().unwrap().stack, ["01"]);
+
+        let witness = [b"dogecoin".to_vec(), witness_script.to_bytes()];
+        let trace = trace_input(&spend(ScriptBuf::new(), &witness), 0, &prevout);
+        assert!(!trace.success);
+
+        // a witness script not matching the program fails before executing it
+        let witness = [b"flokicoin".to_vec(), ScriptBuf::new().to_bytes()];
+        let trace = trace_input(&spend(ScriptBuf::new(), &witness), 0, &prevout);
+        assert!(!trace.success);
+        assert_eq!(witness_steps(&trace), 0);
+    }
+
+    #[test]
+    fn test_trace_bounds() {
+        // 900 pushes followed by 150 drops execute more steps than the trace keeps
+        let mut script_sig = Builder::new();
+        for _ in 0..900 {
+            script_sig = script_sig.push_opcode(OP_PUSHNUM_1);
+        }
+        let mut script_pubkey = Builder::new();
+        for _ in 0..150 {
+            script_pubkey = script_pubkey.push_opcode(OP_DROP);
+        }
+        let tx = spend(script_sig.into_script(), &[]);
+        let trace = trace_input(&tx, 0, &prevout(script_pubkey.into_script()));
+        assert!(trace.success, "{:?}", trace.error);
+        assert!(trace.truncated);
+        assert_eq!(trace.steps.len(), MAX_TRACE_STEPS);
+    }
+
+    #[test]
+    fn test_script_num() {
+        for n in [
+            0,
+            1,
+            -1,
+            127,
+            128,
+            -128,
+            255,
+            256,
+            -32768,
+            2147483647,
+            -2147483647,
+        ] {
+            assert_eq!(decode_num(&encode_num(n), 4).unwrap(), n);
+        }
+        assert_eq!(encode_num(0), Vec::<u8>::new());
+        assert_eq!(encode_num(128), vec![0x80, 0x00]);
+        assert_eq!(encode_num(-1), vec![0x81]);
+        assert!(decode_num(&[1, 2, 3, 4, 5], 4).is_err());
+
+        assert!(!cast_to_bool(&[]));
+        assert!(!cast_to_bool(&[0, 0x80]));
+        assert!(cast_to_bool(&[0, 1]));
+    }
+}
@@ -0,0 +1,91 @@
+//! Interpretation of the absolute (nLockTime) and relative (BIP68 nSequence) timelocks of a
+//! transaction, for explaining why it can't be confirmed yet.
+
+use crate::chain::Transaction;
+
+pub use flokicoin_electrs_api::{LocktimeType, RelativeTimelock};
+
+// nLockTime values below are block heights, values above are unix timestamps
+pub const LOCKTIME_THRESHOLD: u32 = 500_000_000;
+
+pub const SEQUENCE_FINAL: u32 = 0xffff_ffff;
+pub const SEQUENCE_LOCKTIME_DISABLE_FLAG: u32 = 1 << 31;
+pub const SEQUENCE_LOCKTIME_TYPE_FLAG: u32 = 1 << 22;
+pub const SEQUENCE_LOCKTIME_MASK: u32 = 0x0000_ffff;
+// time-based relative locks are in units of 512 seconds
+const SEQUENCE_LOCKTIME_GRANULARITY: u32 = 9;
+
+/// `None` for a zero nLockTime, which doesn't lock anything
+pub fn locktime_type(locktime: u32) -> Option<LocktimeType> {
+    match locktime {
+        0 => None,
+        l if l < LOCKTIME_THRESHOLD => Some(LocktimeType::Height),
+        _ => Some(LocktimeType::Time),
+    }
+}
+
+/// The lock set by the nSequence of an input, which only applies to transactions of version 2
+/// and above
+pub fn relative_timelock(tx_version: u32, sequence: u32) -> Option<RelativeTimelock> {
+    if tx_version < 2 || sequence & SEQUENCE_LOCKTIME_DISABLE_FLAG != 0 {
+        return None;
+    }
+    let value = sequence & SEQUENCE_LOCKTIME_MASK;
+    Some(if sequence & SEQUENCE_LOCKTIME_TYPE_FLAG != 0 {
+        RelativeTimelock::Time(value << SEQUENCE_LOCKTIME_GRANULARITY)
+    } else {
+        RelativeTimelock::Blocks(value)
+    })
+}
+
+fn sequences(tx: &Transaction) -> impl Iterator<Item = u32> + '_ {
+    tx.input.iter().map(|txin| txin.sequence.to_consensus_u32())
+}
+
+/// Whether the nLockTime is enforced, which takes an input with a non-final nSequence
+pub fn is_locktime_active(tx: &Transaction) -> bool {
+    tx.lock_time.to_consensus_u32() != 0 && sequences(tx).any(|sequence| sequence != SEQUENCE_FINAL)
+}
+
+/// Whether the nLockTime allows including the transaction in a block at `height`, whose
+/// median time past is `mtp` (as checked by `IsFinalTx`). Relative timelocks are not checked.
+pub fn is_final(tx: &Transaction, height: u32, mtp: u32) -> bool {
+    let locktime = tx.lock_time.to_consensus_u32();
+    let reached = match locktime_type(locktime) {
+        None => return true,
+        Some(LocktimeType::Height) => locktime < height,
+        Some(LocktimeType::Time) => locktime < mtp,
+    };
+    reached || sequences(tx).all(|sequence| sequence == SEQUENCE_FINAL)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_locktime_type() {
+        assert_eq!(locktime_type(0), None);
+        assert_eq!(locktime_type(840_000), Some(LocktimeType::Height));
+        assert_eq!(locktime_type(1_700_000_000), Some(LocktimeType::Time));
+    }
+
+    #[test]
+    fn test_relative_timelock() {
+        assert_eq!(relative_timelock(1, 10), None);
+        assert_eq!(relative_timelock(2, SEQUENCE_FINAL), None);
+        assert_eq!(relative_timelock(2, 0xffff_fffd), None);
+        assert_eq!(
+            relative_timelock(2, 144),
+            Some(RelativeTimelock::Blocks(144))
+        );
+        assert_eq!(
+            relative_timelock(2, SEQUENCE_LOCKTIME_TYPE_FLAG | 2),
+            Some(RelativeTimelock::Time(1024))
+        );
+        assert_eq!(
+            serde_json::to_value(RelativeTimelock::Blocks(6)).unwrap(),
+            serde_json::json!({ "type": "blocks", "value": 6 })
+        );
+    }
+}
@@ -0,0 +1,140 @@
+use std::str::FromStr;
+
+// the RocksDB databases opened with the configured cache and buffer sizes (txstore, history, cache)
+pub const DB_COUNT: usize = 3;
+// the number of write buffers RocksDB keeps per database (its default `max_write_buffer_number`)
+pub const WRITE_BUFFERS_PER_DB: usize = 2;
+
+/// The shares of a memory budget given to the RocksDB block caches and write buffers, parsed from
+/// `<block-cache>:<write-buffers>` weights (like `60:40`, which don't need to add up to 100).
+///
+/// The in-memory application caches (the existence filter) are taken out of the budget first. The
+/// aggregate and ledger caches are stored in the cache database, and only take memory through its
+/// block cache.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct MemoryRatios {
+    block_cache: f64,
+    write_buffers: f64,
+}
+
+impl Default for MemoryRatios {
+    fn default() -> Self {
+        MemoryRatios {
+            block_cache: 60.0,
+            write_buffers: 40.0,
+        }
+    }
+}
+
+impl FromStr for MemoryRatios {
+    type Err = String;
+
+    fn from_str(ratios: &str) -> Result<Self, Self::Err> {
+        let invalid = || {
+            format!(
+                "Invalid memory ratios {:?} (expected <block-cache>:<write-buffers>, like 60:40)",
+                ratios
+            )
+        };
+        let weights = ratios
+            .split(':')
+            .map(|weight| weight.trim().parse::<f64>())
+            .collect::<Result<Vec<f64>, _>>()
+            .map_err(|_| invalid())?;
+        match weights[..] {
+            [block_cache, write_buffers]
+                if weights.iter().all(|w| w.is_finite() && *w >= 0.0)
+                    && weights.iter().sum::<f64>() > 0.0 =>
+            {
+                Ok(MemoryRatios {
+                    block_cache,
+                    write_buffers,
+                })
+            }
+            _ => Err(invalid()),
+        }
+    }
+}
+
+/// The sizes, in MB, of the options a memory budget sets
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MemoryAllocation {
+    /// The in-memory application caches
+    pub app_caches_mb: usize,
+    /// The block cache of each database
+    pub db_block_cache_mb: usize,
+    /// Each write buffer of each database
+    pub db_write_buffer_size_mb: usize,
+}
+
+impl MemoryAllocation {
+    /// Split what `budget_mb` leaves after the `app_caches_mb` between the caches and buffers of
+    /// the databases, according to the `ratios`
+    pub fn new(budget_mb: usize, app_caches_mb: usize, ratios: &MemoryRatios) -> Self {
+        let total = ratios.block_cache + ratios.write_buffers;
+        let db_budget_mb = budget_mb.saturating_sub(app_caches_mb);
+        let share = |weight: f64| (db_budget_mb as f64 * weight / total) as usize;
+        MemoryAllocation {
+            app_caches_mb,
+            db_block_cache_mb: share(ratios.block_cache) / DB_COUNT,
+            // RocksDB needs at least some room to write into
+            db_write_buffer_size_mb: (share(ratios.write_buffers)
+                / (DB_COUNT * WRITE_BUFFERS_PER_DB))
+                .max(1),
+        }
+    }
+
+    /// The memory used at most by the caches and buffers, in MB
+    pub fn total_mb(&self) -> usize {
+        self.app_caches_mb
+            + self.db_block_cache_mb * DB_COUNT
+            + self.db_write_buffer_size_mb * DB_COUNT * WRITE_BUFFERS_PER_DB
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_ratios() {
+        assert_eq!("60:40".parse(), Ok(MemoryRatios::default()));
+        assert_eq!(
+            " 1 : 0 ".parse(),
+            Ok(MemoryRatios {
+                block_cache: 1.0,
+                write_buffers: 0.0,
+            })
+        );
+        for invalid in &["", "50", "50:40:10", "a:b", "-1:1", "0:0", "inf:1"] {
+            assert!(invalid.parse::<MemoryRatios>().is_err(), "{}", invalid);
+        }
+    }
+
+    #[test]
+    fn test_allocation() {
+        let allocation = MemoryAllocation::new(6000, 0, &MemoryRatios::default());
+        assert_eq!(
+            allocation,
+            MemoryAllocation {
+                app_caches_mb: 0,
+                db_block_cache_mb: 1200,
+                db_write_buffer_size_mb: 400,
+            }
+        );
+        assert_eq!(allocation.total_mb(), 6000);
+
+        // the application caches are taken out first
+        let allocation = MemoryAllocation::new(6100, 100, &MemoryRatios::default());
+        assert_eq!(allocation.db_block_cache_mb, 1200);
+        assert_eq!(allocation.db_write_buffer_size_mb, 400);
+        assert_eq!(allocation.total_mb(), 6100);
+
+        // the allocation never exceeds the budget, except for the minimal write buffers
+        let ratios = "1:1".parse().unwrap();
+        let allocation = MemoryAllocation::new(1000, 10, &ratios);
+        assert!(allocation.total_mb() <= 1000);
+        let allocation = MemoryAllocation::new(0, 0, &ratios);
+        assert_eq!(allocation.db_write_buffer_size_mb, 1);
+    }
+}
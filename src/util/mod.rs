@@ -4,18 +4,38 @@ pub mod floki_address;
 mod script;
 mod transaction;
 
+pub mod allocator;
+pub mod amount;
 pub mod bincode;
+pub mod clients;
+pub mod dns;
 pub mod electrum_merkle;
 pub mod fees;
+#[cfg(not(feature = "liquid"))]
+pub mod interpreter;
+pub mod locktime;
+pub mod memory;
+pub mod pool;
+#[cfg(not(feature = "liquid"))]
+pub mod price;
+pub mod proxy_protocol;
+pub mod ratelimit;
+pub mod socks;
+pub mod supervisor;
+pub mod systemd;
 
 pub use self::block::{
     BlockHeaderMeta, BlockId, BlockMeta, BlockStatus, HeaderEntry, HeaderList, DEFAULT_BLOCKHASH,
 };
 pub use self::fees::get_tx_fee;
-pub use self::script::{get_innerscripts, ScriptToAddr, ScriptToAsm};
+pub use self::script::{
+    address_to_script, get_innerscripts, get_multisig_type, multisig_threshold, MultisigKind,
+    MultisigType, ScriptToAddr, ScriptToAsm,
+};
 pub use self::transaction::{
-    extract_tx_prevouts, get_prev_outpoints, has_prevout, is_coinbase, is_spendable,
-    serialize_outpoint, TransactionStatus, TxInput,
+    explicit_value, extract_tx_prevouts, get_prev_outpoints, has_prevout, is_coinbase,
+    is_spendable, serialize_outpoint, tx_vsize, Eviction, EvictionReason, TransactionStatus,
+    TxInput,
 };
 
 #[cfg(feature = "liquid")]
@@ -24,6 +44,7 @@ pub use self::transaction::optional_value_for_newer_blocks;
 use std::collections::HashMap;
 use std::sync::mpsc::{channel, sync_channel, Receiver, Sender, SyncSender};
 use std::thread;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
 use crate::chain::BlockHeader;
 use bitcoin::hashes::sha256d::Hash as Sha256dHash;
@@ -102,6 +123,18 @@ where
         .unwrap()
 }
 
+/// The time elapsed since the unix epoch, zero when the clock is set before it
+pub fn unix_time() -> Duration {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+}
+
+/// The current unix timestamp, in seconds
+pub fn unix_now() -> u64 {
+    unix_time().as_secs()
+}
+
 // Similar to https://doc.rust-lang.org/std/primitive.bool.html#method.then (nightly only),
 // but with a function that returns an `Option<T>` instead of `T`. Adding something like
 // this to std is being discussed: https://github.com/rust-lang/rust/issues/64260
@@ -120,7 +153,23 @@ impl BoolThen for bool {
     }
 }
 
-pub fn create_socket(addr: &SocketAddr) -> Socket {
+/// Generates a random id for tracking a request across the logs
+pub fn new_request_id() -> String {
+    format!("{:016x}", rand::random::<u64>())
+}
+
+/// Checks whether a client-provided request id (e.g. via `X-Request-Id`) is safe to log and echo
+pub fn is_valid_request_id(id: &str) -> bool {
+    !id.is_empty()
+        && id.len() <= 64
+        && id
+            .bytes()
+            .all(|b| b.is_ascii_alphanumeric() || b == b'-' || b == b'_' || b == b'.')
+}
+
+/// Creates a socket bound to `addr`. IPv6 sockets follow the system default for accepting IPv4
+/// connections too, unless `only_v6` is set (to leave the port free for a separate IPv4 socket).
+pub fn create_socket(addr: &SocketAddr, only_v6: bool) -> Socket {
     let domain = match &addr {
         SocketAddr::V4(_) => Domain::IPV4,
         SocketAddr::V6(_) => Domain::IPV6,
@@ -128,6 +177,10 @@ pub fn create_socket(addr: &SocketAddr) -> Socket {
     let socket =
         Socket::new(domain, Type::STREAM, Some(Protocol::TCP)).expect("creating socket failed");
 
+    if only_v6 && addr.is_ipv6() {
+        socket.set_only_v6(true).expect("cannot set IPV6_V6ONLY");
+    }
+
     #[cfg(unix)]
     socket
         .set_reuse_port(true)
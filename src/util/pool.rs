@@ -0,0 +1,353 @@
+//! The named thread pools sharing the CPU-bound work of a network, each with a fixed number of
+//! threads so that a load spike queues up work instead of starting more threads:
+//!
+//! - `query` runs the parallel lookups serving the REST and Electrum requests,
+//! - `index` checks the proof-of-work of the new headers, then parses and indexes their blocks,
+//! - `notify` refreshes the Electrum subscriptions of the clients once the chain or the mempool
+//!   changed, which would otherwise hit the database from all the client threads at once.
+//!
+//! Keeping them apart means that indexing a burst of blocks doesn't starve the queries, nor the
+//! other way around. The non-critical background work also makes way for the queries when they
+//! pile up, see `LoadMonitor`.
+//!
+//! The Electrum connections, which block on their socket for their whole lifetime, are served by
+//! threads of their own instead, bounded by `--electrum-max-connections`.
+
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::config::Config;
+use crate::metrics::{CounterVec, GaugeVec, MetricOpts, Metrics};
+
+// how many times its interval a background task is put off for at most, while overloaded
+const BACKGROUND_SLOWDOWN: u32 = 4;
+
+#[derive(Clone)]
+pub struct ThreadPools {
+    pub query: ThreadPool,
+    pub index: ThreadPool,
+    pub notify: ThreadPool,
+    pub load: LoadMonitor,
+}
+
+impl ThreadPools {
+    pub fn new(config: &Config, metrics: &Metrics) -> Self {
+        let pool_metrics = PoolMetrics {
+            jobs: metrics.gauge_vec(
+                MetricOpts::new(
+                    "thread_pool_jobs",
+                    "# of jobs waiting for or running on a thread pool, by pool and state",
+                ),
+                &["pool", "state"],
+            ),
+            threads: metrics.gauge_vec(
+                MetricOpts::new("thread_pool_threads", "# of threads of a thread pool"),
+                &["pool"],
+            ),
+            utilization: metrics.gauge_vec(
+                MetricOpts::new(
+                    "thread_pool_utilization",
+                    "Share of the threads of a thread pool running jobs, between 0 and 1",
+                ),
+                &["pool"],
+            ),
+        };
+        let query = ThreadPool::new("query", config.query_threads, &pool_metrics);
+        ThreadPools {
+            index: ThreadPool::new("index", config.index_threads, &pool_metrics),
+            notify: ThreadPool::new("notify", config.notify_threads, &pool_metrics),
+            load: LoadMonitor::new(query.clone(), metrics),
+            query,
+        }
+    }
+}
+
+#[derive(Clone)]
+struct PoolMetrics {
+    jobs: GaugeVec,
+    threads: GaugeVec,
+    utilization: GaugeVec,
+}
+
+/// A fixed-size pool of threads named after it. Running a job on it blocks the caller until one
+/// of its threads is free, and the parallel iterators used by the job run on the pool's threads.
+#[derive(Clone)]
+pub struct ThreadPool {
+    name: &'static str,
+    size: usize,
+    pool: Arc<rayon::ThreadPool>,
+    // the jobs waiting for a thread, also reported by the metrics
+    queued: Arc<AtomicUsize>,
+    metrics: PoolMetrics,
+}
+
+impl ThreadPool {
+    fn new(name: &'static str, size: usize, metrics: &PoolMetrics) -> Self {
+        let size = size.max(1);
+        metrics.threads.with_label_values(&[name]).set(size as f64);
+        ThreadPool {
+            name,
+            size,
+            pool: Arc::new(
+                rayon::ThreadPoolBuilder::new()
+                    .num_threads(size)
+                    .thread_name(move |i| format!("{}-{}", name, i))
+                    .build()
+                    .unwrap(),
+            ),
+            queued: Arc::new(AtomicUsize::new(0)),
+            metrics: metrics.clone(),
+        }
+    }
+
+    /// The number of jobs waiting for a thread of the pool
+    pub fn queued(&self) -> usize {
+        self.queued.load(Ordering::Relaxed)
+    }
+
+    /// Runs `job` on the pool, waiting for it to complete
+    pub fn install<T, F>(&self, job: F) -> T
+    where
+        T: Send,
+        F: FnOnce() -> T + Send,
+    {
+        if self.pool.current_thread_index().is_some() {
+            // already running on the pool, like a job calling another one
+            return job();
+        }
+        let queued = self.metrics.jobs.with_label_values(&[self.name, "queued"]);
+        queued.inc();
+        self.queued.fetch_add(1, Ordering::Relaxed);
+        self.pool.install(|| {
+            queued.dec();
+            self.queued.fetch_sub(1, Ordering::Relaxed);
+            let _running = Running::start(self);
+            job()
+        })
+    }
+}
+
+/// Tells the non-critical background work (like refreshing the mempool backlog stats or the block
+/// template) to slow down while the queries are overloaded, which is when jobs are waiting for a
+/// thread of the query pool, public REST requests are waiting for their lane, or more Electrum
+/// requests are being handled than there are query threads. The work is put off for up to
+/// `BACKGROUND_SLOWDOWN` times its usual interval, so that it still gets done during long surges.
+#[derive(Clone)]
+pub struct LoadMonitor {
+    query: ThreadPool,
+    rest_waiting: Arc<AtomicUsize>,
+    electrum_requests: Arc<AtomicUsize>,
+    deferred: CounterVec,
+}
+
+/// Counts towards the load until dropped
+pub struct LoadGuard(Arc<AtomicUsize>);
+
+impl LoadGuard {
+    fn new(counter: &Arc<AtomicUsize>) -> Self {
+        counter.fetch_add(1, Ordering::Relaxed);
+        LoadGuard(Arc::clone(counter))
+    }
+}
+
+impl Drop for LoadGuard {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+impl LoadMonitor {
+    fn new(query: ThreadPool, metrics: &Metrics) -> Self {
+        LoadMonitor {
+            query,
+            rest_waiting: Arc::new(AtomicUsize::new(0)),
+            electrum_requests: Arc::new(AtomicUsize::new(0)),
+            deferred: metrics.counter_vec(
+                MetricOpts::new(
+                    "background_deferrals",
+                    "# of times a background task was put off because of the query load, by task",
+                ),
+                &["task"],
+            ),
+        }
+    }
+
+    /// The monitor of a single-threaded query pool, for the tests of its users
+    #[cfg(test)]
+    pub fn for_tests(metrics: &Metrics) -> Self {
+        let pool_metrics = PoolMetrics {
+            jobs: metrics.gauge_vec(MetricOpts::new("jobs", "jobs"), &["pool", "state"]),
+            threads: metrics.gauge_vec(MetricOpts::new("threads", "threads"), &["pool"]),
+            utilization: metrics.gauge_vec(MetricOpts::new("util", "util"), &["pool"]),
+        };
+        LoadMonitor::new(ThreadPool::new("query", 1, &pool_metrics), metrics)
+    }
+
+    /// A public REST request waiting for its lane
+    pub fn rest_waiting(&self) -> LoadGuard {
+        LoadGuard::new(&self.rest_waiting)
+    }
+
+    /// An Electrum request being handled
+    pub fn electrum_request(&self) -> LoadGuard {
+        LoadGuard::new(&self.electrum_requests)
+    }
+
+    pub fn overloaded(&self) -> bool {
+        self.query.queued() > 0
+            || self.rest_waiting.load(Ordering::Relaxed) > 0
+            || self.electrum_requests.load(Ordering::Relaxed) > self.query.size
+    }
+
+    /// Whether the background `task`, run every `interval` and last run `elapsed` ago, is due
+    pub fn is_due(&self, task: &str, elapsed: Duration, interval: Duration) -> bool {
+        elapsed >= interval && self.may_run(task, elapsed, interval * BACKGROUND_SLOWDOWN)
+    }
+
+    /// Whether the background `task`, pending for `waiting`, may run now rather than be put off
+    /// for up to `max_delay`
+    pub fn may_run(&self, task: &str, waiting: Duration, max_delay: Duration) -> bool {
+        if waiting < max_delay && self.overloaded() {
+            self.deferred.with_label_values(&[task]).inc();
+            return false;
+        }
+        true
+    }
+}
+
+// Counts a job as running until dropped, even when the job panics
+struct Running<'a>(&'a ThreadPool);
+
+impl<'a> Running<'a> {
+    fn start(pool: &'a ThreadPool) -> Self {
+        pool.metrics
+            .jobs
+            .with_label_values(&[pool.name, "running"])
+            .inc();
+        let running = Running(pool);
+        running.update_utilization();
+        running
+    }
+
+    fn update_utilization(&self) {
+        let pool = self.0;
+        let running = pool
+            .metrics
+            .jobs
+            .with_label_values(&[pool.name, "running"])
+            .get();
+        pool.metrics
+            .utilization
+            .with_label_values(&[pool.name])
+            .set((running / pool.size as f64).min(1.0));
+    }
+}
+
+impl Drop for Running<'_> {
+    fn drop(&mut self) {
+        let pool = self.0;
+        pool.metrics
+            .jobs
+            .with_label_values(&[pool.name, "running"])
+            .dec();
+        self.update_utilization();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rayon::prelude::*;
+
+    fn pool(size: usize) -> ThreadPool {
+        let metrics = Metrics::new("127.0.0.1:0".parse().unwrap());
+        let metrics = PoolMetrics {
+            jobs: metrics.gauge_vec(MetricOpts::new("jobs", "jobs"), &["pool", "state"]),
+            threads: metrics.gauge_vec(MetricOpts::new("threads", "threads"), &["pool"]),
+            utilization: metrics.gauge_vec(MetricOpts::new("util", "util"), &["pool"]),
+        };
+        ThreadPool::new("test", size, &metrics)
+    }
+
+    #[test]
+    fn test_install() {
+        let pool = pool(2);
+        let names = pool.install(|| {
+            assert_eq!(
+                pool.metrics
+                    .jobs
+                    .with_label_values(&["test", "running"])
+                    .get(),
+                1.0
+            );
+            assert_eq!(
+                pool.metrics.utilization.with_label_values(&["test"]).get(),
+                0.5
+            );
+            // the nested jobs and parallel iterators stay on the pool
+            pool.install(|| {
+                (0..4)
+                    .into_par_iter()
+                    .map(|_| std::thread::current().name().unwrap().to_string())
+                    .collect::<Vec<_>>()
+            })
+        });
+        assert!(names.iter().all(|name| name.starts_with("test-")));
+        assert_eq!(
+            pool.metrics
+                .jobs
+                .with_label_values(&["test", "running"])
+                .get(),
+            0.0
+        );
+        assert_eq!(
+            pool.metrics
+                .jobs
+                .with_label_values(&["test", "queued"])
+                .get(),
+            0.0
+        );
+        assert_eq!(
+            pool.metrics.utilization.with_label_values(&["test"]).get(),
+            0.0
+        );
+        assert_eq!(pool.metrics.threads.with_label_values(&["test"]).get(), 2.0);
+    }
+
+    #[test]
+    fn test_load_monitor() {
+        let metrics = Metrics::new("127.0.0.1:0".parse().unwrap());
+        let load = LoadMonitor::new(pool(1), &metrics);
+        let secs = Duration::from_secs;
+        assert!(!load.is_due("task", secs(5), secs(10)));
+        assert!(load.is_due("task", secs(10), secs(10)));
+
+        // a job waiting for the only thread of the pool
+        load.query.queued.fetch_add(1, Ordering::Relaxed);
+        assert!(load.overloaded());
+        assert!(!load.is_due("task", secs(10), secs(10)));
+        assert!(!load.is_due("task", secs(39), secs(10)));
+        assert!(load.is_due("task", secs(40), secs(10)));
+        assert_eq!(load.deferred.with_label_values(&["task"]).get(), 2);
+        assert!(!load.may_run("other", secs(0), secs(60)));
+        assert!(load.may_run("other", secs(60), secs(60)));
+        load.query.queued.fetch_sub(1, Ordering::Relaxed);
+        assert!(load.is_due("task", secs(10), secs(10)));
+        assert!(load.may_run("other", secs(0), secs(60)));
+
+        // a public REST request waiting for its lane
+        let waiting = load.rest_waiting();
+        assert!(load.overloaded());
+        drop(waiting);
+        assert!(!load.overloaded());
+
+        // more Electrum requests than query threads
+        let first = load.electrum_request();
+        assert!(!load.overloaded());
+        let second = load.electrum_request();
+        assert!(load.overloaded());
+        drop((first, second));
+        assert!(!load.overloaded());
+    }
+}
@@ -0,0 +1,191 @@
+//! The fiat price of FLC, fetched in the background from the `--price-source` endpoint to add
+//! fiat values to the transaction and address responses of the REST API.
+//!
+//! The endpoint is an HTTP URL template, whose `{currency}` is replaced with the
+//! `--price-currency`, answering with the price of 1 FLC either as a JSON number or as the
+//! `price` field of a JSON object. Only plain HTTP is supported, which is meant for a price
+//! service (or a proxy to one) running next to the server, or for an onion service reached
+//! through the `--socks-proxy`.
+
+use std::io::{Read, Write};
+use std::net::SocketAddr;
+use std::sync::{Arc, RwLock};
+use std::thread;
+use std::time::Duration;
+
+use serde_json::Value;
+use url::Url;
+
+use crate::config::Config;
+use crate::errors::*;
+use crate::metrics::{CounterVec, MetricOpts, Metrics};
+use crate::util::socks;
+use crate::util::supervisor::spawn_supervised;
+use crate::util::unix_now;
+
+pub use flokicoin_electrs_api::FiatValue;
+
+const SATS_PER_COIN: f64 = 100_000_000.0;
+const FETCH_TIMEOUT: Duration = Duration::from_secs(10);
+// a price quote is tiny, anything larger than this is not one
+const MAX_RESPONSE_SIZE: u64 = 64 * 1024;
+// the price stops being served once it missed this many refreshes
+const STALE_REFRESHES: u32 = 3;
+
+pub struct PriceFeed {
+    currency: String,
+    proxy: Option<SocketAddr>,
+    refresh: Duration,
+    price: RwLock<Option<(f64, u64)>>, // (price, unix time it was fetched at)
+    updates: CounterVec,
+}
+
+impl PriceFeed {
+    /// Starts refreshing the price every `--price-refresh-secs`, unless no `--price-source` is set
+    pub fn start(config: &Config, metrics: &Metrics) -> Option<Arc<PriceFeed>> {
+        let url = config
+            .price_source
+            .as_ref()?
+            .replace("{currency}", &config.price_currency);
+        let feed = Arc::new(PriceFeed {
+            currency: config.price_currency.clone(),
+            proxy: config.socks_proxy,
+            refresh: config.price_refresh,
+            price: RwLock::new(None),
+            updates: metrics.counter_vec(
+                MetricOpts::new(
+                    "price_updates",
+                    "# of attempts to fetch the fiat price from the --price-source, by result",
+                ),
+                &["result"],
+            ),
+        });
+        let updater = Arc::clone(&feed);
+        spawn_supervised("price", move || loop {
+            updater.update(&url);
+            thread::sleep(updater.refresh);
+        });
+        Some(feed)
+    }
+
+    fn update(&self, url: &str) {
+        match fetch_price(url, self.proxy) {
+            Ok(price) => {
+                *self.price.write().unwrap() = Some((price, unix_now()));
+                self.updates.with_label_values(&["ok"]).inc();
+            }
+            Err(e) => {
+                warn!(
+                    "failed fetching the price from {}: {}",
+                    url,
+                    e.display_chain()
+                );
+                self.updates.with_label_values(&["error"]).inc();
+            }
+        }
+    }
+
+    /// The fiat value of `sats` (and of a `fee`), unless the price is unknown or stale
+    pub fn value(&self, sats: u64, fee: Option<u64>) -> Option<FiatValue> {
+        let (price, fetched_at) = (*self.price.read().unwrap())?;
+        if unix_now().saturating_sub(fetched_at) > (self.refresh * STALE_REFRESHES).as_secs() {
+            return None;
+        }
+        Some(FiatValue {
+            currency: self.currency.clone(),
+            rate: price,
+            rate_time: fetched_at,
+            value: sats as f64 / SATS_PER_COIN * price,
+            fee: fee.map(|fee| fee as f64 / SATS_PER_COIN * price),
+        })
+    }
+}
+
+fn fetch_price(url: &str, proxy: Option<SocketAddr>) -> Result<f64> {
+    let url = Url::parse(url).chain_err(|| "invalid --price-source")?;
+    if url.scheme() != "http" {
+        bail!("only http:// price sources are supported");
+    }
+    let host = url.host_str().chain_err(|| "missing host")?;
+    let port = url.port_or_known_default().unwrap_or(80);
+    let mut conn = socks::connect(proxy, host, port, FETCH_TIMEOUT)?;
+    let path = match url.query() {
+        Some(query) => format!("{}?{}", url.path(), query),
+        None => url.path().to_string(),
+    };
+    // HTTP/1.0, so that the response is neither chunked nor kept alive
+    write!(
+        conn,
+        "GET {} HTTP/1.0\r\nHost: {}\r\nAccept: application/json\r\n\r\n",
+        path, host
+    )
+    .chain_err(|| "failed sending the request")?;
+    let mut response = String::new();
+    conn.take(MAX_RESPONSE_SIZE + 1)
+        .read_to_string(&mut response)
+        .chain_err(|| "failed reading the response")?;
+    if response.len() as u64 > MAX_RESPONSE_SIZE {
+        bail!("response exceeds {} bytes", MAX_RESPONSE_SIZE);
+    }
+
+    let (head, body) = response
+        .split_once("\r\n\r\n")
+        .chain_err(|| "invalid HTTP response")?;
+    let status = head.lines().next().unwrap_or_default();
+    if status.split_whitespace().nth(1) != Some("200") {
+        bail!("request failed: {}", status);
+    }
+    parse_price(body)
+}
+
+fn parse_price(body: &str) -> Result<f64> {
+    let value: Value = serde_json::from_str(body).chain_err(|| "invalid JSON response")?;
+    let price = match value {
+        Value::Object(ref fields) => fields.get("price").and_then(Value::as_f64),
+        ref number => number.as_f64(),
+    };
+    match price {
+        Some(price) if price.is_finite() && price >= 0.0 => Ok(price),
+        _ => bail!("no price in the response {}", body),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_price() {
+        assert_eq!(parse_price("0.00125").unwrap(), 0.00125);
+        assert_eq!(
+            parse_price(r#"{"price": 2.5, "currency": "USD"}"#).unwrap(),
+            2.5
+        );
+        assert!(parse_price(r#"{"usd": 2.5}"#).is_err());
+        assert!(parse_price(r#""2.5""#).is_err());
+        assert!(parse_price("-1").is_err());
+    }
+
+    #[test]
+    fn test_value() {
+        let metrics = Metrics::new("127.0.0.1:0".parse().unwrap());
+        let feed = PriceFeed {
+            currency: "EUR".to_string(),
+            proxy: None,
+            refresh: Duration::from_secs(60),
+            price: RwLock::new(None),
+            updates: metrics.counter_vec(MetricOpts::new("updates", "updates"), &["result"]),
+        };
+        assert_eq!(feed.value(100_000_000, None), None);
+
+        *feed.price.write().unwrap() = Some((2.0, unix_now()));
+        let value = feed.value(150_000_000, Some(50_000_000)).unwrap();
+        assert_eq!(value.currency, "EUR");
+        assert_eq!(value.value, 3.0);
+        assert_eq!(value.fee, Some(1.0));
+
+        // not served once stale
+        *feed.price.write().unwrap() = Some((2.0, unix_now() - 600));
+        assert_eq!(feed.value(100_000_000, None), None);
+    }
+}
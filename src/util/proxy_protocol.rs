@@ -0,0 +1,179 @@
+//! Parsing of the HAProxy PROXY protocol header (versions 1 and 2), sent by load balancers and
+//! by Tor (with `HiddenServiceExportCircuitID haproxy`) ahead of the proxied connection data to
+//! pass along the original client address.
+//!
+//! See https://www.haproxy.org/download/2.8/doc/proxy-protocol.txt
+
+use std::convert::TryInto;
+use std::io::Read;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+
+use tokio::io::{AsyncRead, AsyncReadExt};
+
+use crate::errors::*;
+
+const V1_PREFIX: &[u8] = b"PROXY ";
+const V1_MAX_LEN: usize = 107;
+const V2_SIGNATURE: &[u8] = b"\r\n\r\n\0\r\nQUIT\n";
+const V2_HEADER_LEN: usize = 16;
+
+// v1 headers are at least 15 bytes long ("PROXY UNKNOWN\r\n"), so reading the length of the v2
+// signature upfront never consumes any of the proxied data
+const PREFIX_LEN: usize = 12;
+
+/// Reads the PROXY header from a connection, returning the client address it holds or `None`
+/// for connections made by the proxy itself (like health checks).
+pub fn read_header<R: Read>(stream: &mut R) -> Result<Option<SocketAddr>> {
+    let mut read_exact = |buf: &mut [u8]| {
+        stream
+            .read_exact(buf)
+            .chain_err(|| "failed reading the PROXY header")
+    };
+    let mut header = vec![0; PREFIX_LEN];
+    read_exact(&mut header)?;
+    if header == V2_SIGNATURE {
+        header.resize(V2_HEADER_LEN, 0);
+        read_exact(&mut header[PREFIX_LEN..])?;
+        header.resize(V2_HEADER_LEN + v2_addresses_len(&header), 0);
+        read_exact(&mut header[V2_HEADER_LEN..])?;
+        parse_v2(&header)
+    } else if header.starts_with(V1_PREFIX) {
+        while !header.ends_with(b"\r\n") {
+            ensure!(header.len() < V1_MAX_LEN, "PROXY header too long");
+            let mut byte = [0];
+            read_exact(&mut byte)?;
+            header.push(byte[0]);
+        }
+        parse_v1(&header)
+    } else {
+        bail!("missing PROXY header")
+    }
+}
+
+/// Like `read_header`, for async connections
+pub async fn read_header_async<R: AsyncRead + Unpin>(stream: &mut R) -> Result<Option<SocketAddr>> {
+    let mut header = vec![0; PREFIX_LEN];
+    stream
+        .read_exact(&mut header)
+        .await
+        .chain_err(|| "failed reading the PROXY header")?;
+    if header == V2_SIGNATURE {
+        header.resize(V2_HEADER_LEN, 0);
+        stream
+            .read_exact(&mut header[PREFIX_LEN..])
+            .await
+            .chain_err(|| "failed reading the PROXY header")?;
+        header.resize(V2_HEADER_LEN + v2_addresses_len(&header), 0);
+        stream
+            .read_exact(&mut header[V2_HEADER_LEN..])
+            .await
+            .chain_err(|| "failed reading the PROXY header")?;
+        parse_v2(&header)
+    } else if header.starts_with(V1_PREFIX) {
+        while !header.ends_with(b"\r\n") {
+            ensure!(header.len() < V1_MAX_LEN, "PROXY header too long");
+            let byte = stream
+                .read_u8()
+                .await
+                .chain_err(|| "failed reading the PROXY header")?;
+            header.push(byte);
+        }
+        parse_v1(&header)
+    } else {
+        bail!("missing PROXY header")
+    }
+}
+
+// "PROXY TCP4 {src-ip} {dst-ip} {src-port} {dst-port}\r\n", or "PROXY UNKNOWN ...\r\n"
+fn parse_v1(header: &[u8]) -> Result<Option<SocketAddr>> {
+    let line =
+        std::str::from_utf8(&header[..header.len() - 2]).chain_err(|| "invalid PROXY header")?;
+    let fields = line.split(' ').collect::<Vec<_>>();
+    match fields[..] {
+        ["PROXY", "UNKNOWN", ..] => Ok(None),
+        ["PROXY", "TCP4", src_ip, _, src_port, _] | ["PROXY", "TCP6", src_ip, _, src_port, _] => {
+            let ip: IpAddr = src_ip.parse().chain_err(|| "invalid PROXY source ip")?;
+            let port: u16 = src_port.parse().chain_err(|| "invalid PROXY source port")?;
+            Ok(Some(SocketAddr::new(ip, port)))
+        }
+        _ => bail!("invalid PROXY header: {:?}", line),
+    }
+}
+
+fn v2_addresses_len(header: &[u8]) -> usize {
+    u16::from_be_bytes([header[14], header[15]]) as usize
+}
+
+fn parse_v2(header: &[u8]) -> Result<Option<SocketAddr>> {
+    let (version_command, family) = (header[12], header[13]);
+    let addresses = &header[V2_HEADER_LEN..];
+    ensure!(
+        version_command >> 4 == 2,
+        "unsupported PROXY protocol version"
+    );
+    match (version_command & 0xf, family >> 4) {
+        // LOCAL command, made by the proxy itself
+        (0, _) => Ok(None),
+        // PROXY command over IPv4: src(4) dst(4) src_port(2) dst_port(2)
+        (1, 1) => {
+            ensure!(addresses.len() >= 12, "truncated PROXY header");
+            let ip: [u8; 4] = addresses[..4].try_into().unwrap();
+            let port = u16::from_be_bytes([addresses[8], addresses[9]]);
+            Ok(Some(SocketAddr::new(Ipv4Addr::from(ip).into(), port)))
+        }
+        // PROXY command over IPv6: src(16) dst(16) src_port(2) dst_port(2)
+        (1, 2) => {
+            ensure!(addresses.len() >= 36, "truncated PROXY header");
+            let ip: [u8; 16] = addresses[..16].try_into().unwrap();
+            let port = u16::from_be_bytes([addresses[32], addresses[33]]);
+            Ok(Some(SocketAddr::new(Ipv6Addr::from(ip).into(), port)))
+        }
+        // unspecified or unix socket addresses, there is no client ip to use
+        (1, _) => Ok(None),
+        _ => bail!("unsupported PROXY command"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_read_v1_header() {
+        let mut stream = &b"PROXY TCP4 192.0.2.1 198.51.100.1 56324 50001\r\n{\"id\":1}"[..];
+        let addr = read_header(&mut stream).unwrap();
+        assert_eq!(addr, Some("192.0.2.1:56324".parse().unwrap()));
+        // the proxied data is left untouched
+        assert_eq!(stream, b"{\"id\":1}");
+
+        let mut stream = &b"PROXY TCP6 fc00:dead:beef:4dad::c:1 ::1 65535 50001\r\n"[..];
+        let addr = read_header(&mut stream).unwrap();
+        assert_eq!(
+            addr,
+            Some("[fc00:dead:beef:4dad::c:1]:65535".parse().unwrap())
+        );
+
+        let mut stream = &b"PROXY UNKNOWN\r\n"[..];
+        assert_eq!(read_header(&mut stream).unwrap(), None);
+
+        let mut stream = &b"GET / HTTP/1.1\r\n"[..];
+        assert!(read_header(&mut stream).is_err());
+    }
+
+    #[test]
+    fn test_read_v2_header() {
+        let mut header = V2_SIGNATURE.to_vec();
+        header.extend(&[0x21, 0x11, 0, 12]);
+        header.extend(&[192, 0, 2, 1, 198, 51, 100, 1, 0xdc, 0x04, 0xc3, 0x51]);
+        header.extend(b"GET");
+        let mut stream = &header[..];
+        let addr = read_header(&mut stream).unwrap();
+        assert_eq!(addr, Some("192.0.2.1:56324".parse().unwrap()));
+        assert_eq!(stream, b"GET");
+
+        let mut header = V2_SIGNATURE.to_vec();
+        header.extend(&[0x20, 0x00, 0, 0]);
+        let mut stream = &header[..];
+        assert_eq!(read_header(&mut stream).unwrap(), None);
+    }
+}
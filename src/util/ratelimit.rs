@@ -0,0 +1,119 @@
+use std::collections::HashMap;
+use std::net::{IpAddr, Ipv6Addr};
+use std::sync::Mutex;
+use std::time::Instant;
+
+// full buckets are dropped once there are this many clients tracked
+const MAX_TRACKED_CLIENTS: usize = 100_000;
+
+// the /96 prefix Tor uses to export the circuit id of onion service connections as the client
+// address of PROXY headers (with `HiddenServiceExportCircuitID haproxy`)
+const TOR_CIRCUIT_PREFIX: [u16; 6] = [0xfc00, 0xdead, 0xbeef, 0x4dad, 0, 0];
+
+/// Per-client token bucket rate limiter, allowing bursts of `burst` requests refilled at `rate`
+/// requests per second. The limits are given to each check, so that they can be reloaded.
+#[derive(Default)]
+pub struct RateLimiter {
+    buckets: Mutex<HashMap<IpAddr, Bucket>>,
+}
+
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+impl Bucket {
+    fn refill(&mut self, now: Instant, rate: f64, burst: f64) {
+        let elapsed = now.saturating_duration_since(self.updated).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * rate).min(burst);
+        self.updated = now;
+    }
+}
+
+impl RateLimiter {
+    /// Takes a request from the bucket of the client at `ip`, returning false if it is empty. A
+    /// `rate` of 0 disables rate limiting.
+    pub fn check(&self, ip: IpAddr, rate: f64, burst: u32) -> bool {
+        if rate <= 0.0 {
+            return true;
+        }
+        let now = Instant::now();
+        let burst = burst.max(1) as f64;
+        let mut buckets = self.buckets.lock().unwrap();
+        if buckets.len() >= MAX_TRACKED_CLIENTS {
+            buckets.retain(|_, bucket| {
+                bucket.refill(now, rate, burst);
+                bucket.tokens < burst
+            });
+        }
+        let bucket = buckets.entry(client_key(ip)).or_insert(Bucket {
+            tokens: burst,
+            updated: now,
+        });
+        bucket.refill(now, rate, burst);
+        if bucket.tokens >= 1.0 {
+            bucket.tokens -= 1.0;
+            true
+        } else {
+            false
+        }
+    }
+}
+
+/// Whether `ip` is a Tor onion service circuit id passed via the PROXY protocol
+pub fn is_tor_circuit(ip: &IpAddr) -> bool {
+    match ip {
+        IpAddr::V6(ip) => ip.segments()[..6] == TOR_CIRCUIT_PREFIX,
+        IpAddr::V4(_) => false,
+    }
+}
+
+// IPv6 clients are limited by their /64, as they typically get a whole one assigned. Tor
+// circuits are the exception: they all share the same prefix, but each is its own client.
+pub fn client_key(ip: IpAddr) -> IpAddr {
+    match ip {
+        IpAddr::V4(_) => ip,
+        IpAddr::V6(v6) => {
+            if let Some(v4) = v6.to_ipv4_mapped() {
+                return v4.into();
+            }
+            if is_tor_circuit(&ip) {
+                return ip;
+            }
+            let s = v6.segments();
+            Ipv6Addr::new(s[0], s[1], s[2], s[3], 0, 0, 0, 0).into()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_client_key() {
+        let key = |ip: &str| client_key(ip.parse().unwrap());
+        assert_eq!(key("192.0.2.1"), key("::ffff:192.0.2.1"));
+        assert_eq!(key("2001:db8::1"), key("2001:db8::2"));
+        assert_ne!(key("2001:db8::1"), key("2001:db8:0:1::1"));
+        assert_ne!(
+            key("fc00:dead:beef:4dad::c:1"),
+            key("fc00:dead:beef:4dad::c:2")
+        );
+    }
+
+    #[test]
+    fn test_rate_limiter() {
+        let limiter = RateLimiter::default();
+        let check = |ip| limiter.check(ip, 0.001, 3);
+        let (a, b) = ("192.0.2.1".parse().unwrap(), "192.0.2.2".parse().unwrap());
+        assert!((0..3).all(|_| check(a)));
+        assert!(!check(a));
+        assert!(check(b));
+
+        // the reloaded limits apply to the existing buckets
+        assert!(limiter.check(a, 0.0, 1));
+        assert!(limiter.check(b, 0.001, 1));
+        assert!(!limiter.check(b, 0.001, 1));
+    }
+}
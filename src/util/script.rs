@@ -9,6 +9,7 @@ use bitcoin::address::AddressData;
 #[cfg(not(feature = "liquid"))]
 use bitcoin::bech32;
 use script::Instruction::PushBytes;
+use utoipa::ToSchema;
 
 pub struct InnerScripts {
     pub redeem_script: Option<Script>,
@@ -60,6 +61,108 @@ impl ScriptToAddr for elements::Script {
     }
 }
 
+#[derive(
+    Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, ToSchema,
+)]
+#[serde(rename_all = "snake_case")]
+pub enum MultisigKind {
+    Bare,
+    P2sh,
+    P2wsh,
+    P2shP2wsh,
+    TaprootScriptPath,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct MultisigType {
+    pub kind: MultisigKind,
+    // (m, n) for CHECKMULTISIG scripts, None for taproot script trees
+    pub threshold: Option<(u8, u8)>,
+}
+
+const OP_PUSHNUM_1: u8 = 0x51;
+const OP_PUSHNUM_16: u8 = 0x60;
+const OP_CHECKMULTISIG: u8 = 0xae;
+
+// Parse a standard `<m> <pubkey>... <n> OP_CHECKMULTISIG` script, returning (m, n)
+pub fn multisig_threshold(script: &Script) -> Option<(u8, u8)> {
+    let bytes = script.as_bytes();
+    let pushnum = |op: u8| {
+        (OP_PUSHNUM_1..=OP_PUSHNUM_16)
+            .contains(&op)
+            .then(|| op - 0x50)
+    };
+
+    if bytes.len() < 3 || bytes[bytes.len() - 1] != OP_CHECKMULTISIG {
+        return None;
+    }
+    let m = pushnum(bytes[0])?;
+    let n = pushnum(bytes[bytes.len() - 2])?;
+
+    let mut keys = 0;
+    let mut pos = 1;
+    while pos < bytes.len() - 2 {
+        let len = bytes[pos] as usize;
+        if len != 33 && len != 65 {
+            return None;
+        }
+        pos += 1 + len;
+        keys += 1;
+    }
+    (pos == bytes.len() - 2 && keys == n && m <= n).then(|| (m, n))
+}
+
+// Classify the multisig pattern used by an input spending `prevout`, if any
+pub fn get_multisig_type(txin: &TxIn, prevout: &TxOut) -> Option<MultisigType> {
+    let spk = &prevout.script_pubkey;
+    let multisig = |kind, script: &Script| {
+        multisig_threshold(script).map(|threshold| MultisigType {
+            kind,
+            threshold: Some(threshold),
+        })
+    };
+
+    if spk.is_p2tr() {
+        let witness = &txin.witness;
+        #[cfg(feature = "liquid")]
+        let witness = &witness.script_witness;
+
+        let mut items = witness.iter().collect::<Vec<_>>();
+        // drop the annex, if present
+        if items.len() >= 2
+            && items
+                .last()
+                .map_or(false, |item| item.first() == Some(&0x50))
+        {
+            items.pop();
+        }
+        // key path spends have a single signature item, script path spends have at least the
+        // leaf script and the control block
+        return (items.len() >= 2).then(|| MultisigType {
+            kind: MultisigKind::TaprootScriptPath,
+            threshold: None,
+        });
+    }
+
+    let inner = get_innerscripts(txin, prevout);
+    match (&inner.redeem_script, &inner.witness_script) {
+        (Some(_), Some(witness_script)) => multisig(MultisigKind::P2shP2wsh, witness_script),
+        (Some(redeem_script), None) => multisig(MultisigKind::P2sh, redeem_script),
+        (None, Some(witness_script)) => multisig(MultisigKind::P2wsh, witness_script),
+        (None, None) => multisig(MultisigKind::Bare, spk),
+    }
+}
+
+pub fn address_to_script(addr: &str, network: Network) -> Result<Script, String> {
+    #[cfg(not(feature = "liquid"))]
+    return crate::util::floki_address::script_pubkey_from_address(addr, network);
+
+    #[cfg(feature = "liquid")]
+    return elements_address::Address::parse_with_params(addr, network.address_params())
+        .map(|a| a.script_pubkey())
+        .map_err(|e| e.to_string());
+}
+
 // Returns the witnessScript in the case of p2wsh, or the redeemScript in the case of p2sh.
 pub fn get_innerscripts(txin: &TxIn, prevout: &TxOut) -> InnerScripts {
     // Wrapped redeemScript for P2SH spends
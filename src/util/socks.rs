@@ -0,0 +1,165 @@
+//! A minimal SOCKS5 client (RFC 1928, without authentication), routing the outbound connections
+//! of the server through the `--socks-proxy`, like the SOCKS port of a Tor daemon. The hostnames
+//! are resolved by the proxy, so that they don't leak through the local DNS resolver.
+
+use std::io::{Read, Write};
+use std::net::{SocketAddr, TcpStream, ToSocketAddrs};
+use std::time::Duration;
+
+use crate::errors::*;
+
+const VERSION: u8 = 5;
+const NO_AUTH: u8 = 0;
+const CONNECT: u8 = 1;
+const ATYP_IPV4: u8 = 1;
+const ATYP_DOMAIN: u8 = 3;
+const ATYP_IPV6: u8 = 4;
+
+/// Connects to `host:port`, through the SOCKS5 `proxy` if any, with `timeout` for the connection
+/// and the reads and writes on it
+pub fn connect(
+    proxy: Option<SocketAddr>,
+    host: &str,
+    port: u16,
+    timeout: Duration,
+) -> Result<TcpStream> {
+    let addr = match proxy {
+        Some(proxy) => proxy,
+        None => (host, port)
+            .to_socket_addrs()
+            .chain_err(|| format!("failed resolving {}", host))?
+            .next()
+            .chain_err(|| format!("no address for {}", host))?,
+    };
+    let mut conn = TcpStream::connect_timeout(&addr, timeout)
+        .chain_err(|| format!("failed connecting to {}", addr))?;
+    conn.set_read_timeout(Some(timeout))
+        .and_then(|()| conn.set_write_timeout(Some(timeout)))
+        .chain_err(|| "failed setting the timeouts")?;
+    if proxy.is_some() {
+        handshake(&mut conn, host, port)
+            .chain_err(|| format!("failed connecting to {}:{} through {}", host, port, addr))?;
+    }
+    Ok(conn)
+}
+
+fn handshake<S: Read + Write>(conn: &mut S, host: &str, port: u16) -> Result<()> {
+    if host.is_empty() || host.len() > 255 {
+        bail!("invalid hostname {:?}", host);
+    }
+    conn.write_all(&[VERSION, 1, NO_AUTH])
+        .chain_err(|| "failed sending the greeting")?;
+    let mut reply = [0u8; 2];
+    conn.read_exact(&mut reply)
+        .chain_err(|| "failed reading the greeting")?;
+    if reply != [VERSION, NO_AUTH] {
+        bail!("the proxy requires an authentication method, which isn't supported");
+    }
+
+    let mut request = vec![VERSION, CONNECT, 0, ATYP_DOMAIN, host.len() as u8];
+    request.extend_from_slice(host.as_bytes());
+    request.extend_from_slice(&port.to_be_bytes());
+    conn.write_all(&request)
+        .chain_err(|| "failed sending the request")?;
+
+    let mut reply = [0u8; 4];
+    conn.read_exact(&mut reply)
+        .chain_err(|| "failed reading the reply")?;
+    if reply[1] != 0 {
+        bail!(
+            "the proxy refused the connection: {}",
+            reply_message(reply[1])
+        );
+    }
+    // skip the address bound by the proxy
+    let addr_len = match reply[3] {
+        ATYP_IPV4 => 4,
+        ATYP_IPV6 => 16,
+        ATYP_DOMAIN => {
+            let mut len = [0u8; 1];
+            conn.read_exact(&mut len)
+                .chain_err(|| "failed reading the reply")?;
+            len[0] as usize
+        }
+        atyp => bail!("invalid address type {} in the reply", atyp),
+    };
+    let mut bound = vec![0u8; addr_len + 2];
+    conn.read_exact(&mut bound)
+        .chain_err(|| "failed reading the reply")
+}
+
+fn reply_message(code: u8) -> &'static str {
+    match code {
+        1 => "general failure",
+        2 => "connection not allowed by ruleset",
+        3 => "network unreachable",
+        4 => "host unreachable",
+        5 => "connection refused",
+        6 => "TTL expired",
+        7 => "command not supported",
+        8 => "address type not supported",
+        _ => "unknown error",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    // the replies of a proxy, and the requests written to it
+    struct Proxy {
+        replies: Cursor<Vec<u8>>,
+        requests: Vec<u8>,
+    }
+
+    impl Read for Proxy {
+        fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+            self.replies.read(buf)
+        }
+    }
+
+    impl Write for Proxy {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.requests.write(buf)
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    fn proxy(replies: &[u8]) -> Proxy {
+        Proxy {
+            replies: Cursor::new(replies.to_vec()),
+            requests: vec![],
+        }
+    }
+
+    #[test]
+    fn test_handshake() {
+        let mut conn = proxy(&[5, 0, 5, 0, 0, 1, 127, 0, 0, 1, 0x1f, 0x90]);
+        handshake(&mut conn, "example.onion", 80).unwrap();
+        let mut expected = vec![5, 1, 0, 5, 1, 0, 3, 13];
+        expected.extend_from_slice(b"example.onion");
+        expected.extend_from_slice(&[0, 80]);
+        assert_eq!(conn.requests, expected);
+        assert_eq!(conn.replies.position(), 12);
+
+        // the domain name bound by the proxy is skipped too
+        let mut conn = proxy(&[5, 0, 5, 0, 0, 3, 2, b'a', b'b', 0, 80, 42]);
+        handshake(&mut conn, "example.com", 80).unwrap();
+        assert_eq!(conn.replies.position(), 11);
+    }
+
+    #[test]
+    fn test_handshake_refused() {
+        let mut conn = proxy(&[5, 0, 5, 4, 0, 1, 0, 0, 0, 0, 0, 0]);
+        let err = handshake(&mut conn, "example.com", 80).unwrap_err();
+        assert!(err.to_string().contains("host unreachable"));
+
+        let mut conn = proxy(&[5, 0xff]);
+        assert!(handshake(&mut conn, "example.com", 80).is_err());
+        assert!(handshake(&mut proxy(&[]), &"a".repeat(256), 80).is_err());
+    }
+}
@@ -0,0 +1,123 @@
+//! Supervision of the worker threads: a panicking worker is logged and counted, then either
+//! restarted with backoff (for the long-running loops that hold no state across iterations) or
+//! brings the process down (for the workers that can't be resumed, like the block fetchers),
+//! rather than dying silently and wedging the sync.
+
+use std::any::Any;
+use std::panic::{self, AssertUnwindSafe};
+use std::process;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::metrics::{CounterVec, MetricOpts};
+use crate::util::{spawn_thread, systemd};
+
+const INITIAL_BACKOFF: Duration = Duration::from_secs(1);
+const MAX_BACKOFF: Duration = Duration::from_secs(60);
+
+lazy_static! {
+    /// Registered into the metrics registry by `Metrics::start()`
+    pub static ref THREAD_PANICS: CounterVec = CounterVec::new(
+        MetricOpts::new("electrs_thread_panics", "# of panics by worker thread"),
+        &["thread"],
+    )
+    .unwrap();
+}
+
+/// Spawns a worker that is restarted when it panics, waiting for an exponentially increasing
+/// delay between restarts (reset once the worker stays up for a while).
+pub fn spawn_supervised<F>(name: &str, mut f: F) -> thread::JoinHandle<()>
+where
+    F: FnMut(),
+    F: Send + 'static,
+{
+    let thread_name = name.to_owned();
+    spawn_thread(name, move || {
+        let mut backoff = INITIAL_BACKOFF;
+        loop {
+            let started = Instant::now();
+            match panic::catch_unwind(AssertUnwindSafe(&mut f)) {
+                Ok(()) => break,
+                Err(payload) => {
+                    report_panic(&thread_name, &*payload);
+                    if started.elapsed() > MAX_BACKOFF {
+                        backoff = INITIAL_BACKOFF;
+                    }
+                    warn!("restarting the {} thread in {:?}", thread_name, backoff);
+                    thread::sleep(backoff);
+                    backoff = (backoff * 2).min(MAX_BACKOFF);
+                }
+            }
+        }
+    })
+}
+
+/// Spawns a worker that can't be restarted, exiting the process when it panics so that the
+/// service manager can start over from a clean state.
+pub fn spawn_critical<F, T>(name: &str, f: F) -> thread::JoinHandle<T>
+where
+    F: FnOnce() -> T,
+    F: Send + 'static,
+    T: Send + 'static,
+{
+    let thread_name = name.to_owned();
+    spawn_thread(name, move || {
+        match panic::catch_unwind(AssertUnwindSafe(f)) {
+            Ok(result) => result,
+            Err(payload) => {
+                report_panic(&thread_name, &*payload);
+                error!("the {} thread is critical, shutting down", thread_name);
+                systemd::notify("STOPPING=1");
+                process::exit(1);
+            }
+        }
+    })
+}
+
+fn report_panic(name: &str, payload: &(dyn Any + Send)) {
+    error!("{} thread panicked: {}", name, panic_message(payload));
+    THREAD_PANICS.with_label_values(&[name]).inc();
+}
+
+fn panic_message(payload: &(dyn Any + Send)) -> &str {
+    if let Some(msg) = payload.downcast_ref::<&str>() {
+        msg
+    } else if let Some(msg) = payload.downcast_ref::<String>() {
+        msg
+    } else {
+        "unknown panic payload"
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+
+    #[test]
+    fn test_spawn_supervised() {
+        let runs = Arc::new(AtomicUsize::new(0));
+        let runs_ = Arc::clone(&runs);
+        spawn_supervised("test_supervised", move || {
+            if runs_.fetch_add(1, Ordering::SeqCst) == 0 {
+                panic!("first run fails");
+            }
+        })
+        .join()
+        .unwrap();
+        assert_eq!(runs.load(Ordering::SeqCst), 2);
+        assert_eq!(
+            THREAD_PANICS.with_label_values(&["test_supervised"]).get(),
+            1
+        );
+    }
+
+    #[test]
+    fn test_panic_message() {
+        let payload = panic::catch_unwind(|| panic!("failed at {}", 1)).unwrap_err();
+        assert_eq!(panic_message(&*payload), "failed at 1");
+        let payload = panic::catch_unwind(|| panic!("static")).unwrap_err();
+        assert_eq!(panic_message(&*payload), "static");
+    }
+}
@@ -0,0 +1,83 @@
+//! Integration with systemd socket activation and readiness notifications.
+//!
+//! See `sd_listen_fds(3)` and `sd_notify(3)`. Both are no-ops when not running under systemd.
+
+use std::collections::HashMap;
+use std::env;
+use std::io;
+use std::net::TcpListener;
+use std::os::unix::io::{FromRawFd, RawFd};
+use std::os::unix::net::{SocketAddr as UnixSocketAddr, UnixDatagram};
+use std::process;
+use std::sync::Mutex;
+
+const LISTEN_FDS_START: RawFd = 3;
+
+lazy_static! {
+    // the listening sockets passed by systemd, keyed by their `FileDescriptorName=`
+    static ref LISTEN_FDS: Mutex<HashMap<String, RawFd>> = Mutex::new(listen_fds());
+}
+
+fn listen_fds() -> HashMap<String, RawFd> {
+    let pid = env::var("LISTEN_PID")
+        .ok()
+        .and_then(|pid| pid.parse::<u32>().ok());
+    if pid != Some(process::id()) {
+        return HashMap::new();
+    }
+    let count = env::var("LISTEN_FDS")
+        .ok()
+        .and_then(|count| count.parse::<RawFd>().ok())
+        .unwrap_or(0);
+    let names = env::var("LISTEN_FDNAMES").unwrap_or_default();
+    let mut names = names.split(':');
+
+    (0..count)
+        .map(|i| {
+            let name = names.next().unwrap_or("unknown").to_string();
+            (name, LISTEN_FDS_START + i)
+        })
+        .collect()
+}
+
+/// Takes the listening socket passed via socket activation with the given name (set with
+/// `FileDescriptorName=` in the `.socket` unit), if any.
+pub fn take_listener(name: &str) -> Option<TcpListener> {
+    let fd = LISTEN_FDS.lock().unwrap().remove(name)?;
+    info!("using the {} socket passed by systemd (fd {})", name, fd);
+    // safety: the fd was passed to us by systemd and is only taken once
+    Some(unsafe { TcpListener::from_raw_fd(fd) })
+}
+
+/// Sends a state update (e.g. `READY=1` or `STATUS=...`) to the systemd notification socket
+pub fn notify(state: &str) {
+    let path = match env::var_os("NOTIFY_SOCKET") {
+        Some(path) => path,
+        None => return,
+    };
+    let result = UnixDatagram::unbound().and_then(|socket| {
+        let path = path.to_string_lossy();
+        let addr = match path.strip_prefix('@') {
+            Some(name) => abstract_addr(name)?,
+            None => UnixSocketAddr::from_pathname(&*path)?,
+        };
+        socket.send_to_addr(state.as_bytes(), &addr)
+    });
+    if let Err(e) = result {
+        warn!("failed notifying systemd with {:?}: {}", state, e);
+    }
+}
+
+#[cfg(target_os = "linux")]
+fn abstract_addr(name: &str) -> io::Result<UnixSocketAddr> {
+    use std::os::linux::net::SocketAddrExt;
+    UnixSocketAddr::from_abstract_name(name)
+}
+
+#[cfg(not(target_os = "linux"))]
+fn abstract_addr(_name: &str) -> io::Result<UnixSocketAddr> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "abstract socket addresses are only supported on linux",
+    ))
+}
@@ -2,6 +2,7 @@ use crate::chain::{BlockHash, OutPoint, Transaction, TxIn, TxOut, Txid};
 use crate::util::BlockId;
 
 use std::collections::{BTreeSet, HashMap};
+use utoipa::ToSchema;
 
 #[cfg(feature = "liquid")]
 lazy_static! {
@@ -15,15 +16,32 @@ lazy_static! {
             .unwrap();
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, ToSchema)]
 pub struct TransactionStatus {
     pub confirmed: bool,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub block_height: Option<usize>,
     #[serde(skip_serializing_if = "Option::is_none")]
+    #[schema(value_type = Option<String>)]
     pub block_hash: Option<BlockHash>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub block_time: Option<u32>,
+    /// Set for the unconfirmed transactions that left the mempool
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub evicted: Option<Eviction>,
+}
+
+#[cfg(not(feature = "liquid"))]
+impl From<TransactionStatus> for flokicoin_electrs_api::TxStatus {
+    fn from(status: TransactionStatus) -> Self {
+        flokicoin_electrs_api::TxStatus {
+            confirmed: status.confirmed,
+            block_height: status.block_height.map(|height| height as u32),
+            block_hash: status.block_hash,
+            block_time: status.block_time,
+            evicted: status.evicted,
+        }
+    }
 }
 
 impl From<Option<BlockId>> for TransactionStatus {
@@ -34,17 +52,36 @@ impl From<Option<BlockId>> for TransactionStatus {
                 block_height: Some(b.height as usize),
                 block_hash: Some(b.hash),
                 block_time: Some(b.time),
+                evicted: None,
             },
             None => TransactionStatus {
                 confirmed: false,
                 block_height: None,
                 block_hash: None,
                 block_time: None,
+                evicted: None,
             },
         }
     }
 }
 
+// the evictions of the API crate hold flokicoin txids, elements ones for the liquid feature
+#[cfg(not(feature = "liquid"))]
+pub use flokicoin_electrs_api::Eviction;
+pub use flokicoin_electrs_api::EvictionReason;
+
+#[cfg(feature = "liquid")]
+#[derive(Serialize, Deserialize, Debug, Clone, ToSchema)]
+pub struct Eviction {
+    pub reason: EvictionReason,
+    /// The transaction spending the same input, for replaced and conflicted transactions
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    #[schema(value_type = Option<String>)]
+    pub replaced_by: Option<Txid>,
+    /// The unix timestamp the transaction left the mempool at
+    pub time: u64,
+}
+
 #[cfg(feature = "liquid")]
 pub fn optional_value_for_newer_blocks(
     block_id: Option<BlockId>,
@@ -91,6 +128,22 @@ pub fn is_spendable(txout: &TxOut) -> bool {
     return !txout.is_fee() && !txout.script_pubkey.is_provably_unspendable();
 }
 
+// Returns the output value in satoshis, or None if it is blinded (elements only)
+pub fn explicit_value(txout: &TxOut) -> Option<u64> {
+    #[cfg(not(feature = "liquid"))]
+    return Some(txout.value.to_sat());
+    #[cfg(feature = "liquid")]
+    return txout.value.explicit();
+}
+
+// The size of the transaction in virtual bytes (its weight / 4, rounded up)
+pub fn tx_vsize(tx: &Transaction) -> u64 {
+    let weight = tx.weight();
+    #[cfg(not(feature = "liquid"))] // rust-flokicoin has a wrapper Weight type
+    let weight = weight.to_wu();
+    (weight as u64).div_ceil(4)
+}
+
 pub fn extract_tx_prevouts<'a>(
     tx: &Transaction,
     txos: &'a HashMap<OutPoint, TxOut>,
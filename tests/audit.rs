@@ -0,0 +1,40 @@
+//! The background audit of the history rows, against a row corrupted in the index.
+//! This only runs on Flokicoin (non-Liquid) mode.
+#![cfg(not(feature = "liquid"))]
+
+use electrs::new_index::{compute_script_hash, Audit, TxHistoryInfo, TxHistoryRow};
+
+pub mod common;
+
+use common::{Result, TestRunner};
+
+#[test]
+fn test_history_audit() -> Result<()> {
+    let mut tester = TestRunner::new()?;
+    let addr = tester.newaddress()?;
+    tester.send(&addr, "0.5 BTC".parse().unwrap())?;
+    tester.mine()?;
+
+    let scripthash = compute_script_hash(&addr.script_pubkey());
+    let auditor = tester.history_auditor();
+    assert_eq!(auditor.audit_from(&scripthash)?, Some(Audit::Ok));
+
+    // corrupt the funding row of the address, as if indexed with a wrong value
+    let history_db = tester.query().chain().store().history_db();
+    let row = history_db
+        .iter_scan(&[&b"H"[..], &scripthash[..]].concat())
+        .next()
+        .expect("missing history row")?;
+    let key = row.key.clone();
+    let mut history = TxHistoryRow::from_row(row);
+    match history.key.txinfo {
+        TxHistoryInfo::Funding(ref mut info) => info.value += 1,
+        _ => panic!("expected a funding row"),
+    }
+    history_db.delete(&[key])?;
+    let corrupted = history.into_row();
+    history_db.put(&corrupted.key, &corrupted.value)?;
+
+    assert_eq!(auditor.audit_from(&scripthash)?, Some(Audit::Mismatch));
+    Ok(())
+}
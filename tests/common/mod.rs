@@ -0,0 +1,97 @@
+use std::net;
+use std::time::{Duration, Instant};
+
+pub use electrs::testing::TestRunner;
+use electrs::{electrum::RPC as ElectrumRPC, rest};
+
+pub fn init_rest_tester() -> Result<(rest::Handle, net::SocketAddr, TestRunner)> {
+    let tester = TestRunner::new()?;
+    let rest_server = tester.start_rest();
+    Ok((rest_server, tester.config().http_addr, tester))
+}
+pub fn init_electrum_tester() -> Result<(ElectrumRPC, net::SocketAddr, TestRunner)> {
+    let tester = TestRunner::new()?;
+    let electrum_server = tester.start_electrum();
+    Ok((electrum_server, tester.config().electrum_rpc_addr, tester))
+}
+
+/// Connect to the Electrum server, waiting for it to start listening
+pub fn connect_electrum(addr: net::SocketAddr) -> Result<net::TcpStream> {
+    let started = Instant::now();
+    loop {
+        match net::TcpStream::connect(addr) {
+            Ok(stream) => return Ok(stream),
+            Err(_) if started.elapsed() < Duration::from_secs(5) => {
+                std::thread::sleep(Duration::from_millis(100))
+            }
+            Err(e) => return Err(e.into()),
+        }
+    }
+}
+
+error_chain::error_chain! {
+    types {
+        Error, ErrorKind, ResultExt, Result;
+    }
+
+    errors {
+        Electrs(e: electrs::errors::Error) {
+            description("Electrs error")
+            display("Electrs error: {:?}", e)
+        }
+
+        BitcoindRpc(e: bitcoind::bitcoincore_rpc::Error) {
+            description("Lokid RPC error")
+            display("Lokid RPC error: {:?}", e)
+        }
+
+        ElectrumD(e: electrumd::Error) {
+            description("Electrum wallet RPC error")
+            display("Electrum wallet RPC error: {:?}", e)
+        }
+
+        Io(e: std::io::Error) {
+            description("IO error")
+            display("IO error: {:?}", e)
+        }
+        Ureq(e: ureq::Error) {
+            description("ureq error")
+            display("ureq error: {:?}", e)
+        }
+        Json(e: serde_json::Error) {
+            description("JSON error")
+            display("JSON error: {:?}", e)
+        }
+    }
+}
+
+impl From<electrs::errors::Error> for Error {
+    fn from(e: electrs::errors::Error) -> Self {
+        Error::from(ErrorKind::Electrs(e))
+    }
+}
+impl From<bitcoind::bitcoincore_rpc::Error> for Error {
+    fn from(e: bitcoind::bitcoincore_rpc::Error) -> Self {
+        Error::from(ErrorKind::BitcoindRpc(e))
+    }
+}
+impl From<electrumd::Error> for Error {
+    fn from(e: electrumd::Error) -> Self {
+        Error::from(ErrorKind::ElectrumD(e))
+    }
+}
+impl From<std::io::Error> for Error {
+    fn from(e: std::io::Error) -> Self {
+        Error::from(ErrorKind::Io(e))
+    }
+}
+impl From<ureq::Error> for Error {
+    fn from(e: ureq::Error) -> Self {
+        Error::from(ErrorKind::Ureq(e))
+    }
+}
+impl From<serde_json::Error> for Error {
+    fn from(e: serde_json::Error) -> Self {
+        Error::from(ErrorKind::Json(e))
+    }
+}
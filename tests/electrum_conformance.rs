@@ -0,0 +1,464 @@
+//! Conformance tests of the Electrum RPC server, driving every implemented method over a raw
+//! connection the way wallets do, including the subscription notifications across a reorg.
+//! These run in their own test binary, so the server doesn't conflict with the one of
+//! `tests/electrum.rs`. This only runs on Flokicoin (non-Liquid) mode.
+#![cfg(not(feature = "liquid"))]
+
+pub mod common;
+
+use std::collections::VecDeque;
+use std::io::{BufRead, BufReader, Write};
+use std::net::{SocketAddr, TcpStream};
+use std::time::Duration;
+
+use bitcoin::hex::DisplayHex;
+use serde_json::{json, Value};
+
+use common::Result;
+use electrs::chain::Script;
+use electrs::new_index::compute_script_hash;
+
+use bitcoind::bitcoincore_rpc::RpcApi;
+
+const HEADER_HEX_LEN: usize = 160;
+
+#[test]
+fn test_electrum_conformance() -> Result<()> {
+    let (electrum_server, electrum_addr, mut tester) = common::init_electrum_tester()?;
+    let mut client = Client::connect(electrum_addr)?;
+
+    let notify = || electrum_server.notify();
+
+    // server.*
+    let version = client.call("server.version", json!(["conformance", "1.4"]))?;
+    assert!(version[0].as_str().unwrap().starts_with("electrs-esplora "));
+    assert_eq!(version[1].as_str(), Some("1.4"));
+    assert!(client.call("server.banner", json!([]))?.is_string());
+    assert!(client.call("server.donation_address", json!([]))?.is_null());
+    assert_eq!(client.call("server.peers.subscribe", json!([]))?, json!([]));
+    assert!(client.call("server.ping", json!([]))?.is_null());
+
+    // blockchain.block.* and blockchain.headers.subscribe
+    let tip_height = tester.node_client().get_block_count()?;
+    let tip = client.call("blockchain.headers.subscribe", json!([]))?;
+    assert_eq!(tip["height"].as_u64(), Some(tip_height));
+    assert_eq!(
+        tip["hex"],
+        client.call("blockchain.block.header", json!([tip_height]))?
+    );
+    assert_eq!(
+        tip["hex"].as_str().unwrap(),
+        node_header_hex(&tester, tip_height)?
+    );
+
+    let proof = client.call("blockchain.block.header", json!([10, tip_height]))?;
+    assert_eq!(
+        proof["header"].as_str().unwrap(),
+        node_header_hex(&tester, 10)?
+    );
+    assert!(proof["root"].is_string());
+    assert!(!proof["branch"].as_array().unwrap().is_empty());
+
+    let headers = client.call("blockchain.block.headers", json!([0, 5]))?;
+    assert_eq!(headers["count"].as_u64(), Some(5));
+    assert_eq!(headers["hex"].as_str().unwrap().len(), 5 * HEADER_HEX_LEN);
+    assert!(headers["max"].as_u64().unwrap() >= 5);
+    let headers = client.call("blockchain.block.headers", json!([tip_height, 5]))?;
+    assert_eq!(headers["count"].as_u64(), Some(1));
+
+    client.call_err("blockchain.block.header", json!([tip_height + 1]))?;
+
+    // the raw blocks, by height or hash
+    let tip_hash = tester.node_client().get_block_hash(tip_height)?;
+    let block_hex: String = tester
+        .node_client()
+        .call("getblock", &[tip_hash.to_string().into(), 0.into()])?;
+    assert_eq!(
+        client.call("blockchain.block.get", json!([tip_height]))?,
+        json!(block_hex)
+    );
+    assert_eq!(
+        client.call("blockchain.block.get", json!([tip_hash]))?,
+        json!(block_hex)
+    );
+    client.call_err("blockchain.block.get", json!([tip_height + 1]))?;
+
+    // fees
+    assert!(
+        client
+            .call("blockchain.estimatefee", json!([6]))?
+            .as_f64()
+            .unwrap()
+            > 0.0
+    );
+    assert!(
+        client
+            .call("blockchain.relayfee", json!([]))?
+            .as_f64()
+            .unwrap()
+            > 0.0
+    );
+    assert!(client
+        .call("mempool.get_fee_histogram", json!([]))?
+        .is_array());
+
+    // blockchain.scripthash.* and blockchain.script.* of an unused address
+    let addr = tester.newaddress()?;
+    let script = addr.script_pubkey();
+    let script_hex = script.as_bytes().to_lower_hex_string();
+    let scripthash = electrum_scripthash(&script);
+
+    assert!(client
+        .call("blockchain.scripthash.subscribe", json!([scripthash]))?
+        .is_null());
+    assert!(client
+        .call("blockchain.script.subscribe", json!([script_hex]))?
+        .is_null());
+    assert_eq!(
+        client.call("blockchain.scripthash.get_history", json!([scripthash]))?,
+        json!([])
+    );
+    assert_eq!(
+        client.call("blockchain.scripthash.listunspent", json!([scripthash]))?,
+        json!([])
+    );
+    assert_eq!(
+        client.call("blockchain.scripthash.get_balance", json!([scripthash]))?,
+        json!({ "confirmed": 0, "unconfirmed": 0 })
+    );
+    client.call_err("blockchain.scripthash.subscribe", json!(["not-a-hash"]))?;
+    client.call_err("blockchain.script.subscribe", json!(["6a"]))?; // unspendables aren't indexed
+
+    // a payment to the address enters the mempool
+    let txid = tester.send(&addr, "1 BTC".parse().unwrap())?;
+    notify();
+    let mempool_status = client.scripthash_notification(&scripthash)?;
+    assert!(mempool_status.is_string());
+    let params = client.notification("blockchain.script.subscribe")?;
+    assert_eq!(params, json!([script_hex, mempool_status]));
+
+    let history = client.call("blockchain.scripthash.get_history", json!([scripthash]))?;
+    assert_eq!(
+        history[0]["tx_hash"].as_str(),
+        Some(txid.to_string().as_str())
+    );
+    assert_eq!(history[0]["height"].as_i64(), Some(0));
+    assert!(history[0]["fee"].as_u64().unwrap() > 0);
+    let page = client.call(
+        "blockchain.scripthash.get_history_page",
+        json!([scripthash]),
+    )?;
+    assert_eq!(page, json!({ "history": history, "next": null }));
+    let page = client.call(
+        "blockchain.scripthash.get_history_page",
+        json!([scripthash, txid]),
+    )?;
+    assert_eq!(page, json!({ "history": [], "next": null }));
+    let page = client.call(
+        "blockchain.scripthash.get_history_paginated",
+        json!([scripthash, 0, 10]),
+    )?;
+    assert_eq!(
+        page,
+        json!({ "history": history, "next_from_height": null, "next_after": null })
+    );
+    client.call_err(
+        "blockchain.scripthash.get_history_paginated",
+        json!([scripthash, 0, 0]),
+    )?;
+    assert_eq!(
+        client.call("blockchain.scripthash.get_balance", json!([scripthash]))?,
+        json!({ "confirmed": 0, "unconfirmed": 100_000_000 })
+    );
+    let utxos = client.call("blockchain.scripthash.listunspent", json!([scripthash]))?;
+    assert_eq!(utxos[0]["height"].as_u64(), Some(0));
+    assert_eq!(utxos[0]["value"].as_u64(), Some(100_000_000));
+    let tx_pos = utxos[0]["tx_pos"].as_u64().unwrap();
+
+    let tx_hex: String = tester
+        .node_client()
+        .call("getrawtransaction", &[txid.to_string().into()])?;
+    assert_eq!(
+        client.call("blockchain.transaction.get", json!([txid]))?,
+        json!(tx_hex)
+    );
+    let verbose = client.call("blockchain.transaction.get", json!([txid, true]))?;
+    assert_eq!(verbose["txid"].as_str(), Some(txid.to_string().as_str()));
+    assert_eq!(verbose["hex"].as_str(), Some(tx_hex.as_str()));
+    assert_eq!(
+        verbose["vout"][tx_pos as usize]["scriptPubKey"]["hex"].as_str(),
+        Some(script_hex.as_str())
+    );
+    client.call_err("blockchain.transaction.get_merkle", json!([txid, 0]))?;
+
+    // the payment is confirmed
+    let block_hash = tester.mine()?;
+    let height = tip_height + 1;
+    notify();
+    let header = client.notification("blockchain.headers.subscribe")?;
+    assert_eq!(header[0]["height"].as_u64(), Some(height));
+    assert_eq!(
+        header[0]["hex"].as_str().unwrap(),
+        node_header_hex(&tester, height)?
+    );
+    let confirmed_status = client.scripthash_notification(&scripthash)?;
+    assert_ne!(confirmed_status, mempool_status);
+    client.notification("blockchain.script.subscribe")?;
+
+    assert_confirmed(&mut client, &scripthash, &txid.to_string(), height)?;
+
+    // a reorg puts the payment back in the mempool
+    tester.invalidate(&block_hash)?;
+    notify();
+    let header = client.notification("blockchain.headers.subscribe")?;
+    assert_eq!(header[0]["height"].as_u64(), Some(tip_height));
+    assert_eq!(client.scripthash_notification(&scripthash)?, mempool_status);
+    client.notification("blockchain.script.subscribe")?;
+
+    let history = client.call("blockchain.scripthash.get_history", json!([scripthash]))?;
+    assert_eq!(history[0]["height"].as_i64(), Some(0));
+    assert_eq!(
+        client.call("blockchain.scripthash.get_balance", json!([scripthash]))?,
+        json!({ "confirmed": 0, "unconfirmed": 100_000_000 })
+    );
+    client.call_err("blockchain.transaction.get_merkle", json!([txid, height]))?;
+    client.call_err("blockchain.transaction.id_from_pos", json!([height, 0]))?;
+
+    // and it is confirmed again by the new chain
+    let new_block_hash = tester.mine()?;
+    assert_ne!(new_block_hash, block_hash);
+    notify();
+    let header = client.notification("blockchain.headers.subscribe")?;
+    assert_eq!(header[0]["height"].as_u64(), Some(height));
+    assert_eq!(
+        header[0]["hex"].as_str().unwrap(),
+        node_header_hex(&tester, height)?
+    );
+    assert_eq!(
+        client.scripthash_notification(&scripthash)?,
+        confirmed_status
+    );
+    client.notification("blockchain.script.subscribe")?;
+
+    assert_confirmed(&mut client, &scripthash, &txid.to_string(), height)?;
+
+    // blockchain.transaction.broadcast
+    let node = tester.node_client();
+    let raw_tx: String = node.call(
+        "createrawtransaction",
+        &[json!([]), json!({ addr.to_string(): 0.5 })],
+    )?;
+    let funded: Value = node.call("fundrawtransaction", &[raw_tx.into()])?;
+    let signed: Value = node.call("signrawtransactionwithwallet", &[funded["hex"].clone()])?;
+    let broadcast_txid = client.call("blockchain.transaction.broadcast", json!([signed["hex"]]))?;
+    let decoded: Value = node.call("decoderawtransaction", &[signed["hex"].clone()])?;
+    assert_eq!(broadcast_txid, decoded["txid"]);
+    client.call_err("blockchain.transaction.broadcast", json!(["00"]))?;
+
+    tester.sync()?;
+    notify();
+    assert_ne!(
+        client.scripthash_notification(&scripthash)?,
+        confirmed_status
+    );
+    client.notification("blockchain.script.subscribe")?;
+    let history = client.call("blockchain.scripthash.get_history", json!([scripthash]))?;
+    assert_eq!(history.as_array().unwrap().len(), 2);
+    assert_eq!(history[1]["tx_hash"], broadcast_txid);
+
+    // unsubscribing
+    assert_eq!(
+        client.call("blockchain.scripthash.unsubscribe", json!([scripthash]))?,
+        json!(true)
+    );
+    assert_eq!(
+        client.call("blockchain.scripthash.unsubscribe", json!([scripthash]))?,
+        json!(false)
+    );
+    assert_eq!(
+        client.call("blockchain.script.unsubscribe", json!([script_hex]))?,
+        json!(true)
+    );
+    assert_eq!(
+        client.call("blockchain.script.unsubscribe", json!([script_hex]))?,
+        json!(false)
+    );
+
+    // batches and unknown methods
+    let responses = client.batch(&[
+        ("server.ping", json!([])),
+        ("blockchain.block.header", json!([height])),
+        ("no.such.method", json!([])),
+    ])?;
+    assert!(responses[0]["result"].is_null() && responses[0]["error"].is_null());
+    assert_eq!(
+        responses[1]["result"].as_str().unwrap(),
+        node_header_hex(&tester, height)?
+    );
+    assert!(responses[2]["error"].is_string());
+
+    // nothing was left unread
+    tester.mine()?;
+    notify();
+    client.notification("blockchain.headers.subscribe")?;
+    assert!(client.notifications.is_empty());
+
+    Ok(())
+}
+
+fn assert_confirmed(client: &mut Client, scripthash: &str, txid: &str, height: u64) -> Result<()> {
+    let history = client.call("blockchain.scripthash.get_history", json!([scripthash]))?;
+    assert_eq!(history[0]["tx_hash"].as_str(), Some(txid));
+    assert_eq!(history[0]["height"].as_u64(), Some(height));
+    assert!(history[0].get("fee").is_none());
+    assert_eq!(
+        client.call("blockchain.scripthash.get_balance", json!([scripthash]))?,
+        json!({ "confirmed": 100_000_000, "unconfirmed": 0 })
+    );
+    let utxos = client.call("blockchain.scripthash.listunspent", json!([scripthash]))?;
+    assert_eq!(utxos[0]["height"].as_u64(), Some(height));
+
+    let proof = client.call("blockchain.transaction.get_merkle", json!([txid, height]))?;
+    assert_eq!(proof["block_height"].as_u64(), Some(height));
+    let pos = proof["pos"].as_u64().unwrap();
+    assert_eq!(
+        client.call("blockchain.transaction.id_from_pos", json!([height, pos]))?,
+        json!(txid)
+    );
+    let with_merkle = client.call(
+        "blockchain.transaction.id_from_pos",
+        json!([height, pos, true]),
+    )?;
+    assert_eq!(with_merkle["tx_hash"].as_str(), Some(txid));
+    assert_eq!(with_merkle["merkle"], proof["merkle"]);
+    client.call_err(
+        "blockchain.transaction.get_merkle",
+        json!([txid, height - 1]),
+    )?;
+    Ok(())
+}
+
+// The hex-encoded header at a height, as returned by the node
+fn node_header_hex(tester: &common::TestRunner, height: u64) -> Result<String> {
+    let node = tester.node_client();
+    let hash: String = node.call("getblockhash", &[height.into()])?;
+    Ok(node.call("getblockheader", &[hash.into(), false.into()])?)
+}
+
+// The reversed sha256 of the script, as used by the Electrum protocol
+fn electrum_scripthash(script: &Script) -> String {
+    let mut hash = compute_script_hash(script);
+    hash.reverse();
+    hash.to_lower_hex_string()
+}
+
+/// A minimal Electrum client, keeping the notifications received while waiting for responses
+struct Client {
+    reader: BufReader<TcpStream>,
+    writer: TcpStream,
+    next_id: u64,
+    notifications: VecDeque<Value>,
+}
+
+impl Client {
+    fn connect(addr: SocketAddr) -> Result<Self> {
+        let stream = common::connect_electrum(addr)?;
+        stream.set_read_timeout(Some(Duration::from_secs(10)))?;
+        Ok(Client {
+            reader: BufReader::new(stream.try_clone()?),
+            writer: stream,
+            next_id: 0,
+            notifications: VecDeque::new(),
+        })
+    }
+
+    /// The result of a method, failing on error responses
+    fn call(&mut self, method: &str, params: Value) -> Result<Value> {
+        let mut response = self.request(method, params)?;
+        if !response["error"].is_null() {
+            return Err(format!("{} failed: {}", method, response["error"]).into());
+        }
+        Ok(response["result"].take())
+    }
+
+    /// The error of a method, failing on successful responses
+    fn call_err(&mut self, method: &str, params: Value) -> Result<Value> {
+        let mut response = self.request(method, params.clone())?;
+        if response["error"].is_null() {
+            return Err(format!("{} {} unexpectedly succeeded", method, params).into());
+        }
+        Ok(response["error"].take())
+    }
+
+    fn request(&mut self, method: &str, params: Value) -> Result<Value> {
+        self.next_id += 1;
+        let id = self.next_id;
+        let request = json!({ "jsonrpc": "2.0", "id": id, "method": method, "params": params });
+        self.send_line(serde_json::to_string(&request)?.as_bytes())?;
+        loop {
+            let message = self.read_message()?;
+            if message["id"] == json!(id) {
+                return Ok(message);
+            }
+            self.notifications.push_back(message);
+        }
+    }
+
+    /// The responses of a batch request, in the order of the requests
+    fn batch(&mut self, requests: &[(&str, Value)]) -> Result<Vec<Value>> {
+        let requests: Vec<Value> = requests
+            .iter()
+            .map(|(method, params)| {
+                self.next_id += 1;
+                json!({ "jsonrpc": "2.0", "id": self.next_id, "method": method, "params": params })
+            })
+            .collect();
+        self.send_line(serde_json::to_string(&requests)?.as_bytes())?;
+        loop {
+            match self.read_message()? {
+                Value::Array(responses) => return Ok(responses),
+                notification => self.notifications.push_back(notification),
+            }
+        }
+    }
+
+    /// The params of the next notification of a method
+    fn notification(&mut self, method: &str) -> Result<Value> {
+        let pos = self
+            .notifications
+            .iter()
+            .position(|notification| notification["method"] == method);
+        if let Some(pos) = pos {
+            return Ok(self.notifications.remove(pos).unwrap()["params"].take());
+        }
+        loop {
+            let mut message = self.read_message()?;
+            if message["method"] == method {
+                return Ok(message["params"].take());
+            }
+            self.notifications.push_back(message);
+        }
+    }
+
+    /// The status hash of the next notification of a scripthash subscription
+    fn scripthash_notification(&mut self, scripthash: &str) -> Result<Value> {
+        let mut params = self.notification("blockchain.scripthash.subscribe")?;
+        assert_eq!(params[0].as_str(), Some(scripthash));
+        Ok(params[1].take())
+    }
+
+    fn send_line(&mut self, line: &[u8]) -> Result<()> {
+        self.writer.write_all(line)?;
+        self.writer.write_all(b"\n")?;
+        self.writer.flush()?;
+        Ok(())
+    }
+
+    fn read_message(&mut self) -> Result<Value> {
+        let mut line = String::new();
+        if self.reader.read_line(&mut line)? == 0 {
+            return Err("connection closed by the server".into());
+        }
+        Ok(serde_json::from_str(&line)?)
+    }
+}
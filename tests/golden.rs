@@ -0,0 +1,427 @@
+//! Golden-file tests of the REST and Electrum responses, served from the deterministic index of
+//! `electrs::testing::fixture`. The responses are compared byte for byte with the files under
+//! `tests/golden/`, so that any change to the serialization (renamed, reordered or reformatted
+//! fields) shows up as a diff. Missing files fail the test like mismatching ones: running with
+//! `UPDATE_GOLDEN=1` (re)writes all of them, to be committed after an intended change. Without a
+//! `tests/golden/rest` or `tests/golden/electrum` directory at all, the first run writes its files
+//! instead, to be reviewed and committed. The JSON
+//! REST responses are also checked against the schema of their route in `/openapi.json`, so that
+//! the document can't drift from what is served.
+//! This only runs on Flokicoin (non-Liquid) mode.
+#![cfg(not(feature = "liquid"))]
+
+pub mod common;
+
+use std::io::{BufRead, BufReader, Write};
+use std::net::TcpStream;
+use std::path::PathBuf;
+use std::{env, fs};
+
+use serde_json::{json, Map, Value};
+
+use common::Result;
+use electrs::new_index::compute_script_hash;
+use electrs::testing::fixture::{self, ADDRESS_COUNT};
+use electrs::testing::TestRunner;
+
+use bitcoin::hex::DisplayHex;
+
+#[test]
+fn test_golden() -> Result<()> {
+    let (tester, fixture) = TestRunner::with_fixture()?;
+    let _rest_server = tester.start_rest();
+    let _electrum_server = tester.start_electrum();
+    let mut golden = Golden::new();
+
+    let tip_height = fixture.tip_height();
+    let tip_hash = fixture.block(tip_height).block_hash();
+    let txids = &fixture.txids;
+    let tx_heights = [tip_height - 1, tip_height, tip_height];
+
+    // REST, with the JSON responses also checked against the schema of their route
+    let rest_addr = tester.config().http_addr;
+    let openapi: Value = ureq::get(&format!("http://{}/openapi.json", rest_addr))
+        .call()?
+        .into_json()?;
+    let mut schema_errors = vec![];
+    let mut rest = |name: &str, route: &str, path: &str| -> Result<()> {
+        let body = ureq::get(&format!("http://{}{}", rest_addr, path))
+            .call()?
+            .into_string()?;
+        golden.check("rest", name, &body);
+        let schema = &openapi["paths"][route]["get"]["responses"]["200"]["content"]
+            ["application/json"]["schema"];
+        if !schema.is_null() {
+            if let Err(error) = check_schema(&openapi, schema, &serde_json::from_str(&body)?, path)
+            {
+                schema_errors.push(error);
+            }
+        }
+        Ok(())
+    };
+    rest("tip-height", "/blocks/tip/height", "/blocks/tip/height")?;
+    rest("tip-hash", "/blocks/tip/hash", "/blocks/tip/hash")?;
+    rest(
+        "blocks",
+        "/blocks/{start_height}",
+        &format!("/blocks/{}", tip_height),
+    )?;
+    rest(
+        "block-height",
+        "/block-height/{height}",
+        &format!("/block-height/{}", tip_height),
+    )?;
+    rest("block", "/block/{hash}", &format!("/block/{}", tip_hash))?;
+    rest(
+        "block-status",
+        "/block/{hash}/status",
+        &format!("/block/{}/status", tip_hash),
+    )?;
+    rest(
+        "block-stats",
+        "/block/{hash}/stats",
+        &format!("/block/{}/stats", tip_hash),
+    )?;
+    rest(
+        "block-txids",
+        "/block/{hash}/txids",
+        &format!("/block/{}/txids", tip_hash),
+    )?;
+    rest(
+        "block-txs",
+        "/block/{hash}/txs",
+        &format!("/block/{}/txs", tip_hash),
+    )?;
+    rest(
+        "block-header",
+        "/block/{hash}/header",
+        &format!("/block/{}/header", tip_hash),
+    )?;
+    for (i, txid) in txids.iter().enumerate() {
+        rest(&format!("tx-{}", i), "/tx/{txid}", &format!("/tx/{}", txid))?;
+        rest(
+            &format!("tx-{}-hex", i),
+            "/tx/{txid}/hex",
+            &format!("/tx/{}/hex", txid),
+        )?;
+        rest(
+            &format!("tx-{}-status", i),
+            "/tx/{txid}/status",
+            &format!("/tx/{}/status", txid),
+        )?;
+        rest(
+            &format!("tx-{}-outspends", i),
+            "/tx/{txid}/outspends",
+            &format!("/tx/{}/outspends", txid),
+        )?;
+        rest(
+            &format!("tx-{}-merkle-proof", i),
+            "/tx/{txid}/merkle-proof",
+            &format!("/tx/{}/merkle-proof", txid),
+        )?;
+    }
+    for index in 0..ADDRESS_COUNT {
+        let address = fixture::address(index);
+        rest(
+            &format!("address-{}", index),
+            "/address/{address}",
+            &format!("/address/{}", address),
+        )?;
+        rest(
+            &format!("address-{}-txs", index),
+            "/address/{address}/txs",
+            &format!("/address/{}/txs", address),
+        )?;
+        rest(
+            &format!("address-{}-utxo", index),
+            "/address/{address}/utxo",
+            &format!("/address/{}/utxo", address),
+        )?;
+        rest(
+            &format!("address-{}-ledger", index),
+            "/address/{address}/ledger",
+            &format!("/address/{}/ledger", address),
+        )?;
+    }
+    rest("mempool", "/mempool", "/mempool")?;
+
+    // Electrum
+    let mut electrum = Electrum::connect(&tester)?;
+    let mut call = |name: &str, method: &str, params: Value| -> Result<()> {
+        let response = electrum.call(method, params)?;
+        golden.check("electrum", name, &response);
+        Ok(())
+    };
+    call(
+        "block-header",
+        "blockchain.block.header",
+        json!([tip_height]),
+    )?;
+    call(
+        "block-header-proof",
+        "blockchain.block.header",
+        json!([1, tip_height]),
+    )?;
+    call("block-headers", "blockchain.block.headers", json!([0, 3]))?;
+    call(
+        "block-headers-proof",
+        "blockchain.block.headers",
+        json!([0, 3, tip_height]),
+    )?;
+    call("relayfee", "blockchain.relayfee", json!([]))?;
+    call("fee-histogram", "mempool.get_fee_histogram", json!([]))?;
+    for (i, (txid, height)) in txids.iter().zip(tx_heights).enumerate() {
+        call(
+            &format!("tx-{}", i),
+            "blockchain.transaction.get",
+            json!([txid]),
+        )?;
+        call(
+            &format!("tx-{}-verbose", i),
+            "blockchain.transaction.get",
+            json!([txid, true]),
+        )?;
+        call(
+            &format!("tx-{}-merkle", i),
+            "blockchain.transaction.get_merkle",
+            json!([txid, height]),
+        )?;
+    }
+    call(
+        "id-from-pos",
+        "blockchain.transaction.id_from_pos",
+        json!([tip_height, 1, true]),
+    )?;
+    for index in 0..ADDRESS_COUNT {
+        let script = fixture::address(index).script_pubkey();
+        let mut scripthash = compute_script_hash(&script);
+        scripthash.reverse();
+        let scripthash = scripthash.to_lower_hex_string();
+        for method in &["subscribe", "get_history", "get_balance", "listunspent"] {
+            call(
+                &format!("scripthash-{}-{}", index, method),
+                &format!("blockchain.scripthash.{}", method),
+                json!([scripthash]),
+            )?;
+        }
+    }
+
+    golden.finish();
+    assert!(
+        schema_errors.is_empty(),
+        "{} responses don't match the OpenAPI document:\n{}",
+        schema_errors.len(),
+        schema_errors.join("\n")
+    );
+    Ok(())
+}
+
+/// Checks a JSON value against a schema of the OpenAPI document: the types of the values, and the
+/// required fields of the objects along with the absence of undocumented ones
+fn check_schema(
+    doc: &Value,
+    schema: &Value,
+    value: &Value,
+    at: &str,
+) -> std::result::Result<(), String> {
+    if value.is_null() && schema["nullable"] == true {
+        return Ok(());
+    }
+    if let Some(Value::String(reference)) = schema.get("$ref") {
+        let name = reference.trim_start_matches("#/components/schemas/");
+        return check_schema(doc, &doc["components"]["schemas"][name], value, at);
+    }
+    if let Some(Value::Array(variants)) = schema.get("oneOf").or_else(|| schema.get("anyOf")) {
+        if variants
+            .iter()
+            .any(|variant| check_schema(doc, variant, value, at).is_ok())
+        {
+            return Ok(());
+        }
+        return Err(format!("{}: {} matches none of the variants", at, value));
+    }
+    if let (Some(Value::Array(parts)), false) = (schema.get("allOf"), value.is_object()) {
+        return parts
+            .iter()
+            .try_for_each(|part| check_schema(doc, part, value, at));
+    }
+    match (schema["type"].as_str(), value) {
+        (Some("string"), Value::String(_)) => match schema.get("enum") {
+            Some(Value::Array(variants)) if !variants.contains(value) => {
+                Err(format!("{}: unknown variant {}", at, value))
+            }
+            _ => Ok(()),
+        },
+        (Some("integer"), Value::Number(number)) if !number.is_f64() => Ok(()),
+        (Some("number"), Value::Number(_)) | (Some("boolean"), Value::Bool(_)) => Ok(()),
+        (Some("array"), Value::Array(items)) => {
+            items.iter().enumerate().try_for_each(|(index, item)| {
+                check_schema(doc, &schema["items"], item, &format!("{}[{}]", at, index))
+            })
+        }
+        (Some("object") | None, Value::Object(object)) => check_object(doc, schema, object, at),
+        (Some(kind @ ("string" | "integer" | "number" | "boolean" | "array" | "object")), _) => {
+            Err(format!("{}: {} is not a {}", at, value, kind))
+        }
+        // the schemas of any value
+        _ => Ok(()),
+    }
+}
+
+fn check_object(
+    doc: &Value,
+    schema: &Value,
+    object: &Map<String, Value>,
+    at: &str,
+) -> std::result::Result<(), String> {
+    let mut fields = Fields::default();
+    fields.collect(doc, schema, object);
+    if let Some(field) = fields
+        .required
+        .iter()
+        .find(|field| !object.contains_key(*field))
+    {
+        return Err(format!("{}: missing {}", at, field));
+    }
+    for (key, value) in object {
+        let at = format!("{}.{}", at, key);
+        match fields.properties.get(key) {
+            Some(schema) => check_schema(doc, schema, value, &at)?,
+            None if fields.open => (),
+            None => return Err(format!("{}: undocumented field", at)),
+        }
+    }
+    Ok(())
+}
+
+/// The fields of an object schema, merged across the parts of an `allOf` (the flattened fields)
+#[derive(Default)]
+struct Fields {
+    properties: Map<String, Value>,
+    required: Vec<String>,
+    // whether fields other than the properties are allowed, like the keys of a map
+    open: bool,
+}
+
+impl Fields {
+    fn collect(&mut self, doc: &Value, schema: &Value, object: &Map<String, Value>) {
+        if let Some(Value::String(reference)) = schema.get("$ref") {
+            let name = reference.trim_start_matches("#/components/schemas/");
+            return self.collect(doc, &doc["components"]["schemas"][name], object);
+        }
+        if let Some(Value::Array(parts)) = schema.get("allOf") {
+            for part in parts {
+                // an unset flattened option (like the paging of the transactions) leaves none of
+                // its fields
+                let mut fields = Fields::default();
+                fields.collect(doc, part, object);
+                if fields.open || fields.properties.keys().any(|key| object.contains_key(key)) {
+                    self.merge(fields);
+                }
+            }
+            return;
+        }
+        match schema.get("properties") {
+            Some(Value::Object(properties)) => self.properties.extend(properties.clone()),
+            _ => self.open = true,
+        }
+        if schema
+            .get("additionalProperties")
+            .map_or(false, |more| more != false)
+        {
+            self.open = true;
+        }
+        if let Some(Value::Array(required)) = schema.get("required") {
+            let required = required.iter().filter_map(Value::as_str).map(String::from);
+            self.required.extend(required);
+        }
+    }
+
+    fn merge(&mut self, other: Fields) {
+        self.properties.extend(other.properties);
+        self.required.extend(other.required);
+        self.open |= other.open;
+    }
+}
+
+struct Golden {
+    dir: PathBuf,
+    update: bool,
+    // the kinds of responses without any golden file yet, which are written instead of compared
+    bootstrap: Vec<&'static str>,
+    mismatches: Vec<String>,
+}
+
+impl Golden {
+    fn new() -> Self {
+        let dir = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests/golden");
+        let bootstrap = ["rest", "electrum"]
+            .iter()
+            .copied()
+            .filter(|kind| !dir.join(kind).is_dir())
+            .collect();
+        Golden {
+            dir,
+            update: env::var("UPDATE_GOLDEN").map_or(false, |value| value == "1"),
+            bootstrap,
+            mismatches: vec![],
+        }
+    }
+
+    fn check(&mut self, kind: &str, name: &str, actual: &str) {
+        let path = self.dir.join(kind).join(format!("{}.json", name));
+        let actual = format!("{}\n", actual.trim_end());
+        match fs::read_to_string(&path) {
+            Ok(expected) if expected == actual => (),
+            Ok(expected) if !self.update => self.mismatches.push(format!(
+                "{}:\n  expected: {}  actual:   {}",
+                path.display(),
+                expected,
+                actual
+            )),
+            Err(_) if !self.update && !self.bootstrap.iter().any(|new| *new == kind) => self
+                .mismatches
+                .push(format!("{}: missing, actual: {}", path.display(), actual)),
+            _ => {
+                log::warn!("writing {}", path.display());
+                fs::create_dir_all(path.parent().unwrap()).unwrap();
+                fs::write(&path, actual).unwrap();
+            }
+        }
+    }
+
+    fn finish(self) {
+        assert!(
+            self.mismatches.is_empty(),
+            "{} responses differ from or have no golden files (rerun with UPDATE_GOLDEN=1 if \
+             the change is intended):\n{}",
+            self.mismatches.len(),
+            self.mismatches.join("\n")
+        );
+    }
+}
+
+/// An Electrum connection returning the raw response lines, with the field order kept as sent
+struct Electrum {
+    reader: BufReader<TcpStream>,
+    writer: TcpStream,
+}
+
+impl Electrum {
+    fn connect(tester: &TestRunner) -> Result<Self> {
+        let stream = common::connect_electrum(tester.config().electrum_rpc_addr)?;
+        Ok(Electrum {
+            reader: BufReader::new(stream.try_clone()?),
+            writer: stream,
+        })
+    }
+
+    fn call(&mut self, method: &str, params: Value) -> Result<String> {
+        // a constant id keeps the responses identical across runs
+        let request = json!({ "jsonrpc": "2.0", "id": 0, "method": method, "params": params });
+        self.writer.write_all(format!("{}\n", request).as_bytes())?;
+        let mut line = String::new();
+        self.reader.read_line(&mut line)?;
+        Ok(line)
+    }
+}
@@ -46,6 +46,25 @@ fn test_rest() -> Result<()> {
         assert_eq!(res["discount_weight"].as_u64().unwrap(), 912);
     }
 
+    // the wallet sets the locktime to the current height, against fee sniping
+    assert_eq!(res["locktime_type"].as_str(), Some("height"));
+    assert_eq!(res["is_final"].as_bool(), Some(true));
+    let res = get_json(&format!("/tx/{}", txid2_mempool))?;
+    assert_eq!(res["is_final"].as_bool(), Some(true));
+
+    // without the lookups of the prevouts
+    let res = get_json(&format!("/tx/{}?prevouts=false", txid1_confirmed))?;
+    assert!(res["vin"][0]["prevout"].is_null());
+    assert!(res.get("fee").is_none());
+    assert_eq!(res["vout"].as_array().unwrap().len(), outs.len());
+    let err = ureq::get(&format!(
+        "http://{}/tx/{}?prevouts=no",
+        rest_addr, txid1_confirmed
+    ))
+    .call()
+    .unwrap_err();
+    assert!(matches!(err, ureq::Error::Status(400, _)));
+
     // Test GET /tx/:txid/status
     let res = get_json(&format!("/tx/{}/status", txid1_confirmed))?;
     assert_eq!(res["confirmed"].as_bool(), Some(true));
@@ -54,6 +73,54 @@ fn test_rest() -> Result<()> {
     let res = get_json(&format!("/tx/{}/status", txid2_mempool))?;
     assert_eq!(res["confirmed"].as_bool(), Some(false));
     assert_eq!(res["block_height"].as_u64(), None);
+    assert!(res["evicted"].is_null());
+
+    // Test that the responses match the types of the API crate
+    #[cfg(not(feature = "liquid"))]
+    {
+        use flokicoin_electrs_api as api;
+
+        let tx: api::Transaction =
+            serde_json::from_value(get_json(&format!("/tx/{}", txid1_confirmed))?)?;
+        assert_eq!(tx.txid, txid1_confirmed);
+        let status = tx.status.unwrap();
+        assert_eq!(status.block_height, Some(102));
+
+        let block: api::Block =
+            serde_json::from_value(get_json(&format!("/block/{}", status.block_hash.unwrap()))?)?;
+        assert_eq!(block.height, 102);
+
+        let info: api::AddressInfo =
+            serde_json::from_value(get_json(&format!("/address/{}", addr1))?)?;
+        assert_eq!(info.chain_stats.funded_txo_sum, 119123000);
+        let utxos: Vec<api::Utxo> =
+            serde_json::from_value(get_json(&format!("/address/{}/utxo", addr1))?)?;
+        assert_eq!(utxos.len(), 1);
+    }
+
+    // Test the ?min_conf_height= guard
+    let res = get_json(&format!(
+        "/tx/{}/status?min_conf_height=102",
+        txid1_confirmed
+    ))?;
+    assert_eq!(res["block_height"].as_u64(), Some(102));
+    for (txid, min_conf_height) in [(txid1_confirmed, 101), (txid2_mempool, 102)] {
+        let res = ureq::get(&format!(
+            "http://{}/tx/{}/status?min_conf_height={}",
+            rest_addr, txid, min_conf_height
+        ))
+        .call();
+        assert_eq!(res.unwrap_err().into_response().unwrap().status(), 409);
+    }
+
+    // Test GET /tx/:txid/conflicts
+    let res = get_json(&format!("/tx/{}/conflicts", txid2_mempool))?;
+    assert_eq!(res["state"].as_str(), Some("mempool"));
+    assert_eq!(res["conflicts"].as_array().map(Vec::len), Some(0));
+    assert_eq!(
+        res["highest_feerate_txid"].as_str(),
+        Some(txid2_mempool.to_string().as_str())
+    );
 
     // Test GET /address/:address
     let res = get_json(&format!("/address/{}", addr1))?;
@@ -69,6 +136,8 @@ fn test_rest() -> Result<()> {
         res["mempool_stats"]["funded_txo_sum"].as_u64(),
         Some(71130000)
     );
+    assert_eq!(res["chain_stats"]["tx_count"].as_u64(), Some(1));
+    assert_eq!(res["mempool_stats"]["tx_count"].as_u64(), Some(1));
 
     // Test GET /address/:address/txs
     let res = get_json(&format!("/address/{}/txs", addr1))?;
@@ -81,6 +150,112 @@ fn test_rest() -> Result<()> {
     assert!(txids.remove(&txid2_mempool));
     assert!(txids.is_empty());
 
+    // Test GET /address/:address/txs/export (only the confirmed transaction)
+    #[cfg(not(feature = "liquid"))]
+    {
+        let res = get_plain(&format!("/address/{}/txs/export", addr1))?;
+        let lines = res.lines().collect::<Vec<_>>();
+        assert_eq!(lines[0], "date,txid,height,direction,amount,fee,balance");
+        assert_eq!(lines.len(), 2);
+        let fields = lines[1].split(',').collect::<Vec<_>>();
+        assert_eq!(fields[1], txid1_confirmed.to_string());
+        assert_eq!(fields[2..5], ["102", "in", "119123000"]);
+        assert_eq!(fields[6], "119123000");
+
+        let res = get_plain(&format!(
+            "/address/{}/txs/export?format=jsonl&unit=flc",
+            addr1
+        ))?;
+        let line: Value = serde_json::from_str(res.trim_end())?;
+        assert_eq!(line["amount"].as_str(), Some("1.19123000"));
+        assert_eq!(line["balance"].as_str(), Some("1.19123000"));
+
+        let res = get_plain(&format!("/address/{}/txs/export?from_height=103", addr1))?;
+        assert_eq!(res.lines().count(), 1);
+    }
+
+    // Test GET /address/:address/ledger (only the confirmed transaction)
+    #[cfg(not(feature = "liquid"))]
+    {
+        let res = get_json(&format!("/address/{}/ledger", addr1))?;
+        let entries = res.as_array().unwrap();
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0]["seq"].as_u64(), Some(1));
+        assert_eq!(entries[0]["kind"].as_str(), Some("funding"));
+        assert_eq!(
+            entries[0]["txid"].as_str(),
+            Some(txid1_confirmed.to_string().as_str())
+        );
+        assert_eq!(entries[0]["amount"].as_i64(), Some(119123000));
+        assert_eq!(entries[0]["balance"].as_i64(), Some(119123000));
+        assert!(entries[0]["reverses"].is_null());
+
+        // the entries are stable across requests
+        let res = get_json(&format!("/address/{}/ledger", addr1))?;
+        assert_eq!(res.as_array().unwrap().len(), 1);
+        let res = get_json(&format!("/address/{}/ledger?after=1", addr1))?;
+        assert!(res.as_array().unwrap().is_empty());
+    }
+
+    // Test GET /address/:address/events (only the mempool transaction)
+    let res = get_json(&format!("/address/{}/events?since=0", addr1))?;
+    // nothing is known before the server started
+    assert_eq!(res["complete"].as_bool(), Some(false));
+    let events = res["events"].as_array().unwrap();
+    assert_eq!(events.len(), 1);
+    assert_eq!(events[0]["type"].as_str(), Some("added"));
+    assert_eq!(
+        events[0]["txid"].as_str(),
+        Some(txid2_mempool.to_string().as_str())
+    );
+    let since = events[0]["time"].as_u64().unwrap();
+    let res = get_json(&format!("/address/{}/events?since={}", addr1, since))?;
+    assert_eq!(res["complete"].as_bool(), Some(true));
+    assert_eq!(res["events"].as_array().unwrap().len(), 1);
+    let err = ureq::get(&format!("http://{}/address/{}/events", rest_addr, addr1))
+        .call()
+        .unwrap_err();
+    assert!(matches!(err, ureq::Error::Status(400, _)));
+
+    // Test GET /v1/export/blocks (the coinbase and the confirmed transaction of block 102)
+    let res = get_plain("/v1/export/blocks?from=102&to=102&format=jsonl")?;
+    let txs = res
+        .lines()
+        .map(serde_json::from_str)
+        .collect::<serde_json::Result<Vec<Value>>>()?;
+    assert_eq!(txs.len(), 2);
+    assert_eq!(
+        txs[1]["txid"].as_str(),
+        Some(txid1_confirmed.to_string().as_str())
+    );
+    assert_eq!(txs[1]["status"]["block_height"].as_u64(), Some(102));
+
+    let res = get_plain("/v1/export/blocks?from=101&to=102&script_types=p2pk")?;
+    assert_eq!(res, "");
+
+    let err = ureq::get(&format!(
+        "http://{}/v1/export/blocks?from=102&to=103",
+        rest_addr
+    ))
+    .call()
+    .unwrap_err();
+    assert!(matches!(err, ureq::Error::Status(404, _)));
+
+    // Test GET /v1/chainstate/diff (the outputs spent and created by block 102)
+    let res = get_plain("/v1/chainstate/diff?from=101&to=102")?;
+    let changes = res
+        .lines()
+        .map(serde_json::from_str)
+        .collect::<serde_json::Result<Vec<Value>>>()?;
+    assert!(changes.iter().all(|change| change["height"] == 102));
+    assert!(changes.iter().any(|change| change["type"] == "spent"));
+    assert!(changes.iter().any(|change| {
+        change["type"] == "created"
+            && change["txid"].as_str() == Some(txid1_confirmed.to_string().as_str())
+            && change["value"].as_u64() == Some(119123000)
+    }));
+    assert_eq!(get_plain("/v1/chainstate/diff?from=102&to=102")?, "");
+
     // Test GET /address-prefix/:prefix
     let addr1_prefix = &addr1.to_string()[0..8];
     let res = get_json(&format!("/address-prefix/{}", addr1_prefix))?;
@@ -88,11 +263,31 @@ fn test_rest() -> Result<()> {
     assert_eq!(found.len(), 1);
     assert_eq!(found[0].as_str(), Some(addr1.to_string().as_str()));
 
+    // Test GET /search/:query
+    let res = get_json(&format!("/search/{}", addr1_prefix))?;
+    assert_eq!(res[0]["type"].as_str(), Some("address_prefix"));
+    assert_eq!(res[0]["address"].as_str(), Some(addr1.to_string().as_str()));
+    let res = get_json(&format!("/search/{}", txid1_confirmed))?;
+    assert_eq!(res[0]["type"].as_str(), Some("tx"));
+    assert_eq!(res[0]["confirmed"].as_bool(), Some(true));
+    let res = get_json("/search/102")?;
+    assert_eq!(res[0]["type"].as_str(), Some("block"));
+    assert_eq!(res[0]["height"].as_u64(), Some(102));
+
     // Test GET /blocks/tip/hash
     let bestblockhash = tester.node_client().get_best_block_hash()?;
     let res = get_plain("/blocks/tip/hash")?;
     assert_eq!(res, bestblockhash.to_string());
 
+    // Test GET /daemon/:method
+    let res = get_json("/daemon/getblockchaininfo")?;
+    assert_eq!(
+        res["bestblockhash"].as_str(),
+        Some(bestblockhash.to_string().as_str())
+    );
+    let res = ureq::get(&format!("http://{}/daemon/stop", rest_addr)).call();
+    assert_eq!(res.unwrap_err().into_response().unwrap().status(), 404);
+
     let bestblockhash = tester.mine()?;
     let res = get_plain("/blocks/tip/hash")?;
     assert_eq!(res, bestblockhash.to_string());
@@ -109,6 +304,112 @@ fn test_rest() -> Result<()> {
     let res = get_plain(&format!("/block-height/{}", bestblockheight))?;
     assert_eq!(res, bestblockhash.to_string());
 
+    // Test GET /block-height/:height/timestamp
+    let res = get_plain(&format!("/block-height/{}/timestamp", bestblockheight))?;
+    let timestamp = res.parse::<u64>().expect("block timestamp as an int");
+
+    // Test GET /v1/mining/blocks/timestamp/:timestamp
+    let res = get_json(&format!("/v1/mining/blocks/timestamp/{}", timestamp))?;
+    assert_eq!(res["timestamp"].as_u64(), Some(timestamp));
+    let res = get_json("/v1/mining/blocks/timestamp/0")?;
+    assert_eq!(res["height"].as_u64(), Some(0));
+
+    // Test GET /v1/mining/next-block
+    #[cfg(not(feature = "liquid"))]
+    {
+        let res = get_json("/v1/mining/next-block")?;
+        assert_eq!(
+            res["template"]["height"].as_u64(),
+            Some(bestblockheight + 1)
+        );
+        assert_eq!(
+            res["template"]["previous_block_hash"].as_str(),
+            Some(bestblockhash.to_string().as_str())
+        );
+        assert_eq!(
+            res["template"]["tx_count"].as_u64(),
+            Some(
+                res["shared_tx_count"].as_u64().unwrap()
+                    + res["template_only_tx_count"].as_u64().unwrap()
+            )
+        );
+    }
+
+    // Test GET /v1/server-info
+    let res = get_json("/v1/server-info")?;
+    assert_eq!(res["version"].as_str(), Some(env!("CARGO_PKG_VERSION")));
+    assert_eq!(res["db_version"].as_u64(), Some(1));
+    assert_eq!(res["tip_height"].as_u64(), Some(bestblockheight));
+    assert_eq!(
+        res["features"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .any(|feature| feature == "liquid"),
+        cfg!(feature = "liquid")
+    );
+
+    // Test GET /openapi.json
+    let res = get_json("/openapi.json")?;
+    assert!(res["openapi"].as_str().unwrap().starts_with("3."));
+    assert!(res["paths"]["/tx/{txid}"]["get"].is_object());
+    assert!(res["paths"]["/tx"]["post"].is_object());
+    assert_eq!(
+        res["paths"]["/block-height/{height}"]["get"]["parameters"][0]["schema"]["type"].as_str(),
+        Some("integer")
+    );
+    let tx_schema = &res["components"]["schemas"]["Transaction"];
+    assert!(tx_schema["properties"]["txid"].is_object());
+    assert!(tx_schema["properties"]["vin"].is_object());
+
+    // the responses of every route have the shape of their schemas, and the errors the shape
+    // of the error schema, so that changing them changes the document
+    let openapi = res.clone();
+    let schemas = &openapi["components"]["schemas"];
+    let path_params: &[(&str, String)] = &[
+        ("{hash}", bestblockhash.to_string()),
+        ("{height}", bestblockheight.to_string()),
+        ("{start_height}", bestblockheight.to_string()),
+        ("{index}", "0".to_string()),
+        ("{start_index}", "0".to_string()),
+        ("{vout}", "0".to_string()),
+        ("{timestamp}", "0".to_string()),
+        ("{address}", addr1.to_string()),
+        ("{prefix}", addr1.to_string()[..6].to_string()),
+        ("{txid}", txid1_confirmed.to_string()),
+        ("{last_seen_txid}", txid1_confirmed.to_string()),
+        ("{query}", txid1_confirmed.to_string()),
+        ("{method}", "getblockchaininfo".to_string()),
+    ];
+    for route in openapi["paths"].as_object().unwrap().keys() {
+        let schema = response_schema(&openapi, "get", route);
+        if schema.is_null() || route.contains("{asset_id}") {
+            continue; // not a JSON document
+        }
+        let path = path_params
+            .iter()
+            .fold(route.clone(), |path, (param, value)| {
+                path.replace(param, value)
+            });
+        let (body, schema) = match ureq::get(&format!("http://{}{}", rest_addr, path)).call() {
+            Ok(resp) => (resp.into_json::<Value>()?, schema),
+            Err(ureq::Error::Status(_, resp)) => (resp.into_json::<Value>()?, &schemas["Error"]),
+            Err(e) => return Err(e.into()),
+        };
+        if let Err(e) = check_schema(&body, schema, schemas) {
+            panic!("GET {}: {}\n{}", path, e, body);
+        }
+    }
+    let res: Value = ureq::post(&format!("http://{}/addresses/used", rest_addr))
+        .send_string(&format!(r#"["{}"]"#, "00".repeat(32)))?
+        .into_json()?;
+    check_schema(
+        &res,
+        response_schema(&openapi, "post", "/addresses/used"),
+        schemas,
+    )
+    .unwrap();
+
     // Test GET /blocks
     let res = get_json("/blocks")?;
     let last_blocks = res.as_array().unwrap();
@@ -138,6 +439,24 @@ fn test_rest() -> Result<()> {
     );
     assert_eq!(res["tx_count"].as_u64(), Some(2));
 
+    // Test GET /block/:hash/stats
+    let res = get_json(&format!("/block/{}/stats", blockhash))?;
+    assert_eq!(res["tx_count"].as_u64(), Some(2));
+    let tx = get_json(&format!("/tx/{}", txid))?;
+    let weight = tx["weight"].as_u64().unwrap();
+    assert_eq!(tx["vsize"].as_u64(), Some((weight + 3) / 4));
+    assert!(res["tx_vsize"].as_u64().unwrap() > tx["vsize"].as_u64().unwrap());
+    #[cfg(not(feature = "liquid"))]
+    assert!(res["sigops"].as_u64().unwrap() >= tx["sigops"].as_u64().unwrap());
+
+    // Test GET /block/:hash/status
+    let res = get_json(&format!("/block/{}/status", blockhash))?;
+    assert_eq!(res["in_best_chain"].as_bool(), Some(true));
+    assert_eq!(res["confirmations"].as_u64(), Some(1));
+    assert!(res["next_best"].is_null());
+    #[cfg(not(feature = "liquid"))]
+    assert_eq!(res["chainwork"].as_str().map(str::len), Some(64));
+
     // Test GET /block/:hash/txs
     let res = get_json(&format!("/block/{}/txs", blockhash))?;
     let block_txs = res.as_array().expect("list of txs");
@@ -186,6 +505,42 @@ fn test_rest() -> Result<()> {
     let broadcast2_resp = broadcast2_res.unwrap_err().into_response().unwrap();
     assert_eq!(broadcast2_resp.status(), 400);
 
+    // Test POST /tx/debug-input
+    #[cfg(not(feature = "liquid"))]
+    {
+        let res: Value = ureq::post(&format!("http://{}/tx/debug-input?vin=0", rest_addr))
+            .send_string(&tx_hex)?
+            .into_json()?;
+        assert_eq!(res["success"].as_bool(), Some(true), "{}", res);
+        check_schema(
+            &res,
+            response_schema(&openapi, "post", "/tx/debug-input"),
+            schemas,
+        )
+        .unwrap();
+        let steps = res["steps"].as_array().expect("list of steps");
+        assert_eq!(
+            steps.last().unwrap()["opcode"].as_str(),
+            Some("OP_CHECKSIG")
+        );
+
+        let debug_res =
+            ureq::post(&format!("http://{}/tx/debug-input?vin=99", rest_addr)).send_string(&tx_hex);
+        assert_eq!(
+            debug_res.unwrap_err().into_response().unwrap().status(),
+            404
+        );
+    }
+
+    // Test GET /tx/:wtxid
+    #[cfg(not(feature = "liquid"))]
+    {
+        let tx: bitcoin::Transaction =
+            bitcoin::consensus::encode::deserialize_hex(&tx_hex).unwrap();
+        let res = get_json(&format!("/tx/{}", tx.compute_wtxid()))?;
+        assert_eq!(res["txid"].as_str(), Some(txid.to_string().as_str()));
+    }
+
     // Test POST /txs/package - simple validation test
     // Test with invalid JSON first to verify the endpoint exists
     let invalid_package_result = ureq::post(&format!("http://{}/txs/package", rest_addr))
@@ -335,6 +690,12 @@ fn test_rest() -> Result<()> {
         let package_result = package_resp.into_json::<Value>()?;
 
         // Verify the response structure
+        check_schema(
+            &package_result,
+            response_schema(&openapi, "post", "/txs/package"),
+            schemas,
+        )
+        .unwrap();
         assert!(package_result["tx-results"].is_object());
         assert!(package_result["package_msg"].is_string());
 
@@ -508,3 +869,113 @@ fn test_rest() -> Result<()> {
     rest_handle.stop();
     Ok(())
 }
+
+fn response_schema<'a>(openapi: &'a Value, method: &str, route: &str) -> &'a Value {
+    &openapi["paths"][route][method]["responses"]["200"]["content"]["application/json"]["schema"]
+}
+
+// Checks that `value` has the shape of `schema`: the objects only have the properties of their
+// schema (merged with the ones it flattens) and have the required ones, recursively
+fn check_schema(value: &Value, schema: &Value, schemas: &Value) -> std::result::Result<(), String> {
+    if let Some(reference) = schema["$ref"].as_str() {
+        let name = reference.trim_start_matches("#/components/schemas/");
+        return check_schema(value, &schemas[name], schemas);
+    }
+    if value.is_null() && schema["nullable"] == true {
+        return Ok(());
+    }
+    if let Some(alternatives) = schema["oneOf"].as_array() {
+        if alternatives
+            .iter()
+            .any(|alternative| check_schema(value, alternative, schemas).is_ok())
+        {
+            return Ok(());
+        }
+        return Err(format!("{} matches none of {}", value, schema));
+    }
+    let matches = match schema["type"].as_str() {
+        Some("string") => value.is_string(),
+        Some("integer") => value.is_i64() || value.is_u64(),
+        Some("number") => value.is_number(),
+        Some("boolean") => value.is_boolean(),
+        Some("array") => {
+            let items = value
+                .as_array()
+                .ok_or_else(|| format!("{} isn't an array", value))?;
+            for item in items {
+                check_schema(item, &schema["items"], schemas)?;
+            }
+            true
+        }
+        Some("object") | None if value.is_object() => {
+            return check_object(value, schema, schemas);
+        }
+        Some("object") => false,
+        // anything, or an object made of flattened schemas
+        _ => !schema["allOf"].is_array(),
+    };
+    if !matches {
+        return Err(format!("{} doesn't match {}", value, schema));
+    }
+    Ok(())
+}
+
+fn check_object(value: &Value, schema: &Value, schemas: &Value) -> std::result::Result<(), String> {
+    let fields = value.as_object().unwrap();
+    if schema["additionalProperties"].is_object() {
+        for field in fields.values() {
+            check_schema(field, &schema["additionalProperties"], schemas)?;
+        }
+        return Ok(());
+    }
+    let mut properties = serde_json::Map::new();
+    let mut required = HashSet::new();
+    collect_properties(schema, schemas, true, &mut properties, &mut required);
+    if properties.is_empty() {
+        return Ok(()); // a free-form object
+    }
+    for (name, field) in fields {
+        let property = properties
+            .get(name)
+            .ok_or_else(|| format!("unexpected field {} in {}", name, value))?;
+        if !(field.is_null() && !required.contains(name.as_str())) {
+            check_schema(field, property, schemas).map_err(|e| format!("{}: {}", name, e))?;
+        }
+    }
+    for name in required {
+        if !fields.contains_key(name) {
+            return Err(format!("missing field {} in {}", name, value));
+        }
+    }
+    Ok(())
+}
+
+// the properties of an object schema and of the schemas it flattens, which only require their
+// fields when they are inlined (a flattened `Option` may be missing altogether)
+fn collect_properties<'a>(
+    schema: &'a Value,
+    schemas: &'a Value,
+    inline: bool,
+    properties: &mut serde_json::Map<String, Value>,
+    required: &mut HashSet<&'a str>,
+) {
+    if let Some(reference) = schema["$ref"].as_str() {
+        let name = reference.trim_start_matches("#/components/schemas/");
+        return collect_properties(&schemas[name], schemas, false, properties, required);
+    }
+    if let Some(own) = schema["properties"].as_object() {
+        properties.extend(own.clone());
+    }
+    if inline {
+        required.extend(
+            schema["required"]
+                .as_array()
+                .into_iter()
+                .flatten()
+                .filter_map(Value::as_str),
+        );
+    }
+    for part in schema["allOf"].as_array().into_iter().flatten() {
+        collect_properties(part, schemas, inline, properties, required);
+    }
+}